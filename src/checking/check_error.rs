@@ -0,0 +1,161 @@
+
+use std::fmt;
+
+use crate::parsing::ast::{Type, Meta};
+use crate::parsing::printer::print_type;
+
+#[derive(Debug)]
+pub enum CheckError {
+    UnknownTrait(String),
+    WrongTraitArity { trait_name : String, expected : usize, found : usize },
+    MissingTraitItem { trait_name : String, impl_type : Type, item : String },
+    ItemNotInTrait { trait_name : String, impl_type : Type, item : String },
+    ItemKindMismatch { trait_name : String, item : String, expected_own : bool },
+    SignatureMismatch { trait_name : String, item : String, reason : String, meta : Meta },
+    UnsatisfiedConstraint { target : Type, trait_name : String },
+    OverlappingImpls { trait_name : String, first : Type, second : Type },
+    DuplicateMethod { impl_type : Type, name : String, meta : Meta },
+    NoMethod { receiver : Type, name : String, meta : Meta },
+    AmbiguousMethod { receiver : Type, name : String, traits : Vec<String>, meta : Meta },
+    NoAssociatedType { target : Type, trait_name : String, item : String },
+    /// A variable used in `function`, in what `meta` spans, after its value was moved.
+    UseAfterMove { name : String, function : String, meta : Meta },
+    UnknownCase { name : String, meta : Meta },
+    CasePatternMismatch { name : String, meta : Meta },
+    /// A match whose arms, spanned by `meta`, miss the patterns in `missing`.
+    NonExhaustiveMatch { missing : Vec<String>, meta : Meta },
+    UnreachableArm { arm : usize, meta : Meta },
+    /// An alternative of an or pattern in an arm, counting every alternative
+    /// in the order they are written, which can never match.
+    RedundantAlternative { arm : usize, alternative : usize, meta : Meta },
+    AssignToImmutable { name : String, declared : Meta, write : Meta },
+    MutatingCallOnImmutable { name : String, method : String, declared : Meta, write : Meta },
+    /// A binding which is not `mut` passed to a `mut` parameter or bound again
+    /// with `let mut`, through which what it holds could be changed.
+    MutableAliasOfImmutable { name : String, alias : String, declared : Meta, write : Meta },
+    InvalidAssignTarget { write : Meta },
+    /// `?` outside of a `try` in a function which does not return a result.
+    PropagateOutsideResult { function : String, return_type : Type, meta : Meta },
+    /// `?` on a result whose error the function cannot return.
+    PropagateMismatch { function : String, expected : Box<Type>, found : Box<Type>, meta : Meta },
+    PropagateNonResult { function : String, found : Type, meta : Meta },
+    /// A `foreach` over a value which is neither a builtin collection nor an `Iterator`.
+    NotIterable { found : Type, meta : Meta },
+    /// `yield` somewhere other than a statement of a generator.
+    MisplacedYield { function : String, meta : Meta },
+    YieldInClosure { function : String, meta : Meta },
+    /// A `use std::...` naming nothing in the standard library.
+    UnknownImport { path : String },
+    /// The program defines something a standard library module it imports does too.
+    DefinedByStd { name : String, module : String },
+    /// An extern function taking or returning a type which native code cannot.
+    NotFfiSafe { function : String, found : Type },
+    /// An extern function with more arguments than can be passed in registers.
+    TooManyExternParams { function : String },
+    /// A constant, or the body of a `const fun`, using what is only known when the program runs.
+    NotConstant { name : String, expr : String },
+    /// Constants whose values depend on each other.
+    ConstCycle { names : Vec<String> },
+    /// Evaluating a constant failed, like a division by zero.
+    ConstFailed { name : String, message : String },
+    /// A constant named in a pattern whose value cannot be matched, like a list.
+    NotAPattern { name : String, meta : Meta },
+    /// The length of an array type which is not a non-negative `Int` constant.
+    InvalidArrayLength { length : String },
+    ArrayLengthMismatch { name : String, expected : i64, found : usize },
+}
+
+impl CheckError {
+    /// Where in the source the error is, for the errors which know.
+    pub fn at(&self) -> Option<Meta> {
+        match self {
+            CheckError::AssignToImmutable { write, .. } | CheckError::MutatingCallOnImmutable { write, .. }
+            | CheckError::MutableAliasOfImmutable { write, .. } | CheckError::InvalidAssignTarget { write } => Some(*write),
+            CheckError::NonExhaustiveMatch { meta, .. } | CheckError::UnreachableArm { meta, .. }
+            | CheckError::RedundantAlternative { meta, .. } | CheckError::NotIterable { meta, .. }
+            | CheckError::SignatureMismatch { meta, .. } | CheckError::DuplicateMethod { meta, .. }
+            | CheckError::NoMethod { meta, .. } | CheckError::AmbiguousMethod { meta, .. }
+            | CheckError::UseAfterMove { meta, .. } | CheckError::UnknownCase { meta, .. }
+            | CheckError::CasePatternMismatch { meta, .. } | CheckError::PropagateOutsideResult { meta, .. }
+            | CheckError::PropagateMismatch { meta, .. } | CheckError::PropagateNonResult { meta, .. }
+            | CheckError::MisplacedYield { meta, .. } | CheckError::YieldInClosure { meta, .. }
+            | CheckError::NotAPattern { meta, .. } => Some(*meta),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckError::UnknownTrait(name) => write!(f, "unknown trait {}", name),
+            CheckError::WrongTraitArity { trait_name, expected, found } =>
+                write!(f, "trait {} takes {} type arguments but was given {}", trait_name, expected, found),
+            CheckError::MissingTraitItem { trait_name, impl_type, item } =>
+                write!(f, "impl {} for {} is missing {}", trait_name, print_type(impl_type), item),
+            CheckError::ItemNotInTrait { trait_name, impl_type, item } =>
+                write!(f, "impl {} for {} defines {}, which is not in the trait", trait_name, print_type(impl_type), item),
+            CheckError::ItemKindMismatch { trait_name, item, expected_own: true } =>
+                write!(f, "{} in trait {} is an own type but the impl gives a plain type", item, trait_name),
+            CheckError::ItemKindMismatch { trait_name, item, expected_own: false } =>
+                write!(f, "{} in trait {} is a plain type but the impl gives an own type", item, trait_name),
+            CheckError::SignatureMismatch { trait_name, item, reason, .. } =>
+                write!(f, "{} does not match its declaration in trait {}: {}", item, trait_name, reason),
+            CheckError::UnsatisfiedConstraint { target, trait_name } =>
+                write!(f, "{} does not implement {}", print_type(target), trait_name),
+            CheckError::OverlappingImpls { trait_name, first, second } =>
+                write!(f, "impls of {} for {} and {} overlap", trait_name, print_type(first), print_type(second)),
+            CheckError::DuplicateMethod { impl_type, name, .. } => write!(f, "{} defines method {} more than once", print_type(impl_type), name),
+            CheckError::NoMethod { receiver, name, .. } => write!(f, "{} has no method {}", print_type(receiver), name),
+            CheckError::AmbiguousMethod { receiver, name, traits, .. } =>
+                write!(f, "method {} of {} could come from any of {}", name, print_type(receiver), traits.join(", ")),
+            CheckError::NoAssociatedType { target, trait_name, item } =>
+                write!(f, "{} has no associated type {} from {}", print_type(target), item, trait_name),
+            CheckError::UseAfterMove { name, function, .. } => write!(f, "{} is used in {} after it was moved", name, function),
+            CheckError::UnknownCase { name, .. } => write!(f, "unknown enum case {}", name),
+            CheckError::CasePatternMismatch { name, .. } => write!(f, "pattern does not match the fields of {}", name),
+            CheckError::NonExhaustiveMatch { missing, .. } => write!(f, "match does not cover {}", missing.join(", ")),
+            CheckError::UnreachableArm { arm, .. } => write!(f, "arm {} of match can never be reached", arm + 1),
+            CheckError::RedundantAlternative { arm, alternative, .. } =>
+                write!(f, "alternative {} of arm {} of match can never be reached", alternative + 1, arm + 1),
+            CheckError::AssignToImmutable { name, .. } => write!(f, "cannot assign to {}, which is not declared mut", name),
+            CheckError::MutatingCallOnImmutable { name, method, .. } =>
+                write!(f, "cannot call {} on {}, which is not declared mut", method, name),
+            CheckError::MutableAliasOfImmutable { name, alias, .. } =>
+                write!(f, "cannot give {} to mut {}, which could change it, as {} is not declared mut", name, alias, name),
+            CheckError::InvalidAssignTarget { .. } => write!(f, "invalid assignment target"),
+            CheckError::PropagateOutsideResult { function, return_type, .. } =>
+                write!(f, "? in {}, which returns {} rather than a result", function, print_type(return_type)),
+            CheckError::PropagateMismatch { function, expected, found, .. } =>
+                write!(f, "? in {} on {}, which cannot be returned as {}", function, print_type(found), print_type(expected)),
+            CheckError::PropagateNonResult { function, found, .. } => write!(f, "? in {} on {}, which is not a result", function, print_type(found)),
+            CheckError::NotIterable { found, .. } => write!(f, "foreach over {}, which does not implement Iterator", print_type(found)),
+            CheckError::MisplacedYield { function, .. } => write!(f, "yield in {} is not a statement of a generator", function),
+            CheckError::YieldInClosure { function, .. } => write!(f, "yield inside a closure in {}", function),
+            CheckError::UnknownImport { path } => write!(f, "{} is not in the standard library", path),
+            CheckError::DefinedByStd { name, module } => write!(f, "{} is already defined by {}", name, module),
+            CheckError::NotFfiSafe { function, found } => write!(f, "extern function {} cannot pass {}", function, print_type(found)),
+            CheckError::TooManyExternParams { function } => write!(f, "extern function {} has too many parameters", function),
+            CheckError::NotConstant { name, expr } => write!(f, "constant {} uses {}, which is not constant", name, expr),
+            CheckError::ConstCycle { names } => write!(f, "constants {} depend on each other", names.join(", ")),
+            CheckError::ConstFailed { name, message } => write!(f, "evaluating constant {} failed: {}", name, message),
+            CheckError::NotAPattern { name, .. } => write!(f, "constant {} cannot be matched", name),
+            CheckError::InvalidArrayLength { length } => write!(f, "array length {} is not a non-negative Int constant", length),
+            CheckError::ArrayLengthMismatch { name, expected, found } =>
+                write!(f, "{} should have {} elements but has {}", name, expected, found),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CheckWarning {
+    UnusedMut { name : String, declared : Meta },
+}
+
+impl fmt::Display for CheckWarning {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckWarning::UnusedMut { name, .. } => write!(f, "{} is declared mut but never changed", name),
+        }
+    }
+}
@@ -0,0 +1,383 @@
+
+use std::collections::HashMap;
+
+use crate::parsing::ast::*;
+use crate::evaluating::ops::has_native_method;
+use crate::evaluating::ffi::Signature;
+use crate::evaluating::consts::evaluate;
+use super::check_error::{CheckError, CheckWarning};
+use super::trait_solver::{TraitEnv, substitute};
+use super::ownership::check_ownership;
+use super::mutability::{check_mutability, infer_type};
+use super::exhaustiveness::check_matches;
+use super::propagation::check_propagation;
+use super::generators::check_generators;
+use super::constants::{check_constants, const_error};
+
+pub fn check( module : &Module ) -> Result<(), Vec<CheckError>> {
+    let (errors, _) = check_with_warnings(module);
+
+    if errors.is_empty() {
+        Ok(())
+    }
+    else {
+        Err(errors)
+    }
+}
+
+pub fn check_with_warnings( module : &Module ) -> (Vec<CheckError>, Vec<CheckWarning>) {
+    let env = TraitEnv::new(module);
+
+    let mut errors = env.check();
+
+    errors.append(&mut check_ownership(&env, module));
+
+    let (mut mutability_errors, warnings) = check_mutability(&env, module);
+    errors.append(&mut mutability_errors);
+
+    let (consts, const_errors) = evaluate(module);
+    errors.extend(const_errors.into_iter().map(const_error));
+    errors.append(&mut check_constants(module, &consts));
+
+    errors.append(&mut check_matches(module, &consts));
+
+    errors.append(&mut check_propagation(module));
+
+    errors.append(&mut check_generators(module));
+
+    for extern_def in &module.extern_defs {
+        check_extern(extern_def, &mut errors);
+    }
+
+    for fun_def in &module.fun_defs {
+        check_fun(&env, module, fun_def, &[], None, &mut errors);
+    }
+
+    for impl_def in &module.impl_defs {
+        for item in &impl_def.items {
+            if let ImplItem::Fun(fun_def) = item {
+                check_fun(&env, module, fun_def, &impl_def.type_params, Some(&impl_def.impl_type), &mut errors);
+            }
+        }
+    }
+
+    (errors, warnings)
+}
+
+fn check_extern(extern_def : &ExternDef, errors : &mut Vec<CheckError>) {
+    let function = extern_def.sig.name.clone();
+    match Signature::of(extern_def) {
+        Err(found) => errors.push(CheckError::NotFfiSafe { function, found }),
+        Ok(signature) if !signature.fits_in_registers() => errors.push(CheckError::TooManyExternParams { function }),
+        Ok(_) => {},
+    }
+}
+
+fn check_fun<'a>(env : &TraitEnv<'a>, module : &'a Module, fun_def : &FunDef, outer_params : &[TypeParam], self_type : Option<&Type>, errors : &mut Vec<CheckError>) {
+    let mut self_binding = HashMap::new();
+    if let Some(t) = self_type {
+        self_binding.insert("Self".to_string(), t.clone());
+    }
+
+    let mut scope = outer_params.iter()
+                                .map(|tp| TypeParam { name: tp.name.clone(), constraints: tp.constraints.clone() })
+                                .collect::<Vec<_>>();
+    scope.extend(fun_def.sig.type_params.iter().map(|tp| TypeParam { name: tp.name.clone(), constraints: tp.constraints.clone() }));
+
+    let mut locals = vec![];
+    for p in &fun_def.sig.params {
+        locals.push((p.name.clone(), substitute(&p.param_type, &self_binding)));
+    }
+
+    check_expr(env, module, &fun_def.body, &scope, &mut locals, errors);
+}
+
+fn check_expr<'a>(env : &TraitEnv<'a>, module : &'a Module, expr : &Expr, scope : &[TypeParam], locals : &mut Vec<(String, Type)>, errors : &mut Vec<CheckError>) {
+    match expr {
+        Expr::Number(_) | Expr::DString(_) | Expr::Unit | Expr::Variable(_) | Expr::Namespace(_, _) => (),
+        Expr::Tuple(exprs) => {
+            for e in exprs {
+                check_expr(env, module, e, scope, locals, errors);
+            }
+        },
+        Expr::Block(exprs) => {
+            let depth = locals.len();
+            for e in exprs {
+                check_expr(env, module, e, scope, locals, errors);
+            }
+            locals.truncate(depth);
+        },
+        Expr::Call { fun, args, .. } => {
+            check_expr(env, module, fun, scope, locals, errors);
+            for e in args {
+                check_expr(env, module, e, scope, locals, errors);
+            }
+        },
+        Expr::Dot { expr, .. } => check_expr(env, module, expr, scope, locals, errors),
+        Expr::Assign { target, value, .. } => {
+            check_expr(env, module, target, scope, locals, errors);
+            check_expr(env, module, value, scope, locals, errors);
+        },
+        Expr::MethodCall { receiver, name, args, meta } => {
+            check_expr(env, module, receiver, scope, locals, errors);
+            for e in args {
+                check_expr(env, module, e, scope, locals, errors);
+            }
+
+            if let Expr::Variable(var) = &**receiver {
+                let receiver_type = locals.iter().rev().find(|(n, _)| n == var).map(|(_, t)| t.clone());
+                match receiver_type {
+                    Some(Type::Infer) | None => (),
+                    Some(t) => match env.resolve_method(&t, name, scope, *meta) {
+                        Err(CheckError::NoMethod { .. }) if is_native_method(&t, name) => (),
+                        Err(e) => errors.push(e),
+                        Ok(_) => (),
+                    },
+                }
+            }
+        },
+        Expr::Let { name, let_type, value, .. } => {
+            check_expr(env, module, value, scope, locals, errors);
+            let binding_type = match let_type {
+                Type::Infer => infer_type(env, module, locals, value),
+                t => t.clone(),
+            };
+            locals.push((name.clone(), binding_type));
+        },
+        Expr::Return(e) | Expr::Yield(e) | Expr::Try(e) | Expr::Propagate(e) | Expr::Panic { message: e, .. } => check_expr(env, module, e, scope, locals, errors),
+        Expr::Assert { condition, message, .. } => {
+            check_expr(env, module, condition, scope, locals, errors);
+            if let Some(message) = message {
+                check_expr(env, module, message, scope, locals, errors);
+            }
+        },
+        Expr::Bool(_) | Expr::Break | Expr::Continue => (),
+        Expr::List(exprs) => {
+            for e in exprs {
+                check_expr(env, module, e, scope, locals, errors);
+            }
+        },
+        Expr::Dict(pairs) => {
+            for (k, v) in pairs {
+                check_expr(env, module, k, scope, locals, errors);
+                check_expr(env, module, v, scope, locals, errors);
+            }
+        },
+        Expr::Struct { fields, .. } => {
+            for (_, e) in fields {
+                check_expr(env, module, e, scope, locals, errors);
+            }
+        },
+        Expr::Lambda { params, body } => {
+            let depth = locals.len();
+            for p in params {
+                locals.push((p.name.clone(), p.param_type.clone()));
+            }
+            check_expr(env, module, body, scope, locals, errors);
+            locals.truncate(depth);
+        },
+        Expr::Index { expr, index } => {
+            check_expr(env, module, expr, scope, locals, errors);
+            check_expr(env, module, index, scope, locals, errors);
+        },
+        Expr::Range { start, end, .. } => {
+            for e in start.iter().chain(end) {
+                check_expr(env, module, e, scope, locals, errors);
+            }
+        },
+        Expr::Unary { expr, .. } => check_expr(env, module, expr, scope, locals, errors),
+        Expr::Binary { left, right, .. } => {
+            check_expr(env, module, left, scope, locals, errors);
+            check_expr(env, module, right, scope, locals, errors);
+        },
+        Expr::If { condition, then, otherwise } => {
+            check_expr(env, module, condition, scope, locals, errors);
+            check_expr(env, module, then, scope, locals, errors);
+            check_expr(env, module, otherwise, scope, locals, errors);
+        },
+        Expr::While { condition, body } => {
+            check_expr(env, module, condition, scope, locals, errors);
+            check_expr(env, module, body, scope, locals, errors);
+        },
+        Expr::Foreach { name, iterable, body, meta } => {
+            check_expr(env, module, iterable, scope, locals, errors);
+            let found = infer_type(env, module, locals, iterable);
+            if !is_iterable(env, module, &found, scope) {
+                errors.push(CheckError::NotIterable { found, meta: *meta });
+            }
+            locals.push((name.clone(), Type::Infer));
+            check_expr(env, module, body, scope, locals, errors);
+            locals.pop();
+        },
+        Expr::Match { expr, arms } => {
+            check_expr(env, module, expr, scope, locals, errors);
+            for arm in arms {
+                let depth = locals.len();
+                for name in pattern_variables(&arm.pattern) {
+                    locals.push((name, Type::Infer));
+                }
+                check_expr(env, module, &arm.body, scope, locals, errors);
+                locals.truncate(depth);
+            }
+        },
+    }
+}
+
+/// Whether `foreach` can step through a value of type `t`, which it can
+/// through the builtin collections, ranges, generators and anything
+/// implementing `Iterator`.  Types which are not known are given the benefit
+/// of the doubt.
+fn is_iterable(env : &TraitEnv, module : &Module, t : &Type, scope : &[TypeParam]) -> bool {
+    match t {
+        Type::Simple(name) | Type::Indexed(name, _) if ["Int", "Float", "Bool"].contains(&name.as_str()) => false,
+        Type::Simple(name) | Type::Indexed(name, _) if module.struct_defs.iter().any(|s| &s.name == name)
+                                                     || module.enum_defs.iter().any(|e| &e.name == name) =>
+            env.implements(t, "Iterator", scope),
+        Type::Tuple(_) => false,
+        _ => true,
+    }
+}
+
+fn is_native_method(receiver : &Type, name : &str) -> bool {
+    match receiver {
+        Type::Simple(n) | Type::Indexed(n, _) => has_native_method(n, name),
+        Type::Tuple(_) => has_native_method("Tuple", name),
+        Type::Array { .. } => has_native_method("List", name),
+        _ => false,
+    }
+}
+
+pub fn pattern_variables(pattern : &Pattern) -> Vec<String> {
+    fn collect(pattern : &Pattern, names : &mut Vec<String>) {
+        match pattern {
+            Pattern::Wildcard | Pattern::Number(_) | Pattern::DString(_) | Pattern::Bool(_) | Pattern::Range { .. } => (),
+            Pattern::Variable(n) => names.push(n.clone()),
+            Pattern::Tuple(ps) => {
+                for p in ps {
+                    collect(p, names);
+                }
+            },
+            Pattern::Or(ps) => {
+                if let Some(p) = ps.first() {
+                    collect(p, names);
+                }
+            },
+            Pattern::Case { contents, .. } => match contents {
+                CasePattern::Empty => (),
+                CasePattern::Tuple(ps) => {
+                    for p in ps {
+                        collect(p, names);
+                    }
+                },
+                CasePattern::Struct { fields, .. } => {
+                    for (_, p) in fields {
+                        collect(p, names);
+                    }
+                },
+            },
+        }
+    }
+
+    let mut names = vec![];
+    collect(pattern, &mut names);
+    names
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+
+    #[test]
+    fn should_check_method_calls_in_bodies() {
+        let m = parse(r#"
+trait Area { fun area(self : Self) -> Int; }
+struct Circle { r : Int }
+impl Area for Circle { fun area(self : Self) -> Int { self.r } }
+
+fun total<T : Area>(c : Circle, t : T, s : Square) -> Int {
+    c.area();
+    t.area();
+    let x : Circle = c;
+    x.perimeter();
+    s.area()
+}
+"#).unwrap();
+
+        let errors = check(&m).unwrap_err();
+        assert_eq!( errors.len(), 2, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::NoMethod { name, .. } if name == "perimeter" ) );
+        assert!( matches!( &errors[1], CheckError::NoMethod { receiver: Type::Simple(r), .. } if r == "Square" ) );
+    }
+
+    #[test]
+    fn should_allow_native_methods_of_builtin_types() {
+        let m = parse(r#"
+fun f(mut xs : List<Int>, d : Dict<String, Int>, s : String, t : (Int, Int)) -> Int {
+    xs.push(1);
+    d.keys();
+    s.to_string();
+    xs.len() + t.len() + s.len();
+    xs.shuffle()
+}
+"#).unwrap();
+
+        let errors = check(&m).unwrap_err();
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::NoMethod { name, .. } if name == "shuffle" ) );
+    }
+
+    #[test]
+    fn should_only_step_through_iterators_and_builtin_collections() {
+        let m = parse(r#"
+enum Option<T> { Some(T), None }
+trait Iterator { type Item; fun next(mut self : Self) -> Option<Self::Item>; }
+struct Countdown { n : Int }
+impl Iterator for Countdown {
+    type Item = Int;
+    fun next(mut self : Self) -> Option<Int> { Option::None }
+}
+struct Point { x : Int }
+
+fun naturals() { yield 1; }
+
+fun f(p : Point, xs : List<Int>) {
+    foreach x in Countdown { n : 3 } { }
+    foreach x in xs { }
+    foreach x in naturals() { }
+    foreach x in 0..3 { }
+    let c = Countdown { n : 1 };
+    foreach x in c { }
+    foreach x in p { }
+    let q = Point { x : 1 };
+    foreach x in q { }
+    foreach x in 5 { }
+}
+"#).unwrap();
+
+        let errors = check(&m).unwrap_err();
+        assert_eq!( errors.len(), 3, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::NotIterable { found: Type::Simple(t), .. } if t == "Point" ) );
+        assert!( matches!( &errors[1], CheckError::NotIterable { found: Type::Simple(t), .. } if t == "Point" ) );
+        assert!( matches!( &errors[2], CheckError::NotIterable { found: Type::Simple(t), .. } if t == "Int" ) );
+    }
+
+    #[test]
+    fn should_only_let_ffi_safe_types_cross_into_extern_functions() {
+        let m = parse(r#"
+extern "libc.so.6" fun abs(n : Int) -> Int;
+extern "libc.so.6" fun puts(s : String);
+extern "libc.so.6" fun sum(xs : List<Int>) -> Int;
+extern "libc.so.6" fun pair() -> (Int, Int);
+extern "libc.so.6" fun id<T>(x : T) -> T;
+extern "libc.so.6" fun wide(a : Int, b : Int, c : Int, d : Int, e : Int, f : Int, g : Bool) -> Int;
+"#).unwrap();
+
+        let errors = check(&m).unwrap_err();
+        assert_eq!( errors.len(), 4, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::NotFfiSafe { function, found: Type::Indexed(..) } if function == "sum" ) );
+        assert!( matches!( &errors[1], CheckError::NotFfiSafe { function, found: Type::Tuple(..) } if function == "pair" ) );
+        assert!( matches!( &errors[2], CheckError::NotFfiSafe { function, found: Type::Simple(t) } if function == "id" && t == "T" ) );
+        assert!( matches!( &errors[3], CheckError::TooManyExternParams { function } if function == "wide" ) );
+    }
+}
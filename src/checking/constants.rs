@@ -0,0 +1,212 @@
+
+use crate::parsing::ast::*;
+use crate::parsing::printer::print_expr;
+use crate::evaluating::consts::{Consts, ConstError, non_constant};
+use super::check_error::CheckError;
+use super::generators::children;
+
+/* Constants are evaluated before the checks which need their values, like
+   those of patterns naming them.  Here the errors of evaluating them are
+   reported, along with the bodies of `const fun`s which could never be
+   evaluated and the lengths of array types.  The length of `[T; N]` has to be
+   a number or an `Int` constant which is not negative, and a list literal or
+   a list constant given for an array has to have exactly that many items.
+*/
+
+pub fn const_error(error : ConstError) -> CheckError {
+    match error {
+        ConstError::NotConstant { name, expr } => CheckError::NotConstant { name, expr },
+        ConstError::Cycle { names } => CheckError::ConstCycle { names },
+        ConstError::Failed { name, error } => CheckError::ConstFailed { name, message: error.to_string() },
+    }
+}
+
+pub fn check_constants( module : &Module, consts : &Consts ) -> Vec<CheckError> {
+    let mut errors = vec![];
+
+    for fun_def in module.fun_defs.iter().filter(|f| f.constant) {
+        if let Some(expr) = non_constant(module, fun_def) {
+            errors.push(CheckError::NotConstant { name: fun_def.sig.name.clone(), expr: print_expr(expr, 0) });
+        }
+    }
+
+    let mut types = vec![];
+    types.extend(module.const_defs.iter().map(|c| &c.const_type));
+    for s in &module.struct_defs {
+        types.extend(s.fields.iter().map(|f| &f.field_type));
+    }
+    for e in &module.enum_defs {
+        for case in &e.cases {
+            match case {
+                EnumCase::EmptyCase { .. } => (),
+                EnumCase::StructCase { fields, .. } => types.extend(fields.iter().map(|f| &f.field_type)),
+                EnumCase::TypeCase { types: ts, .. } => types.extend(ts),
+            }
+        }
+    }
+    let trait_sigs = module.trait_defs.iter().flat_map(|t| &t.items).filter_map(|item| match item {
+        TraitItem::Fun(sig) => Some(sig),
+        _ => None,
+    });
+    let impl_funs = module.impl_defs.iter().flat_map(|i| &i.items).filter_map(|item| match item {
+        ImplItem::Fun(fun_def) => Some(fun_def),
+        _ => None,
+    }).collect::<Vec<_>>();
+    let funs = module.fun_defs.iter().chain(impl_funs.iter().copied());
+    for sig in trait_sigs.chain(module.extern_defs.iter().map(|e| &e.sig)).chain(funs.clone().map(|f| &f.sig)) {
+        types.extend(sig.params.iter().map(|p| &p.param_type));
+        types.push(&sig.return_type);
+    }
+    for t in types {
+        check_type(module, consts, t, &mut errors);
+    }
+    for fun_def in funs {
+        check_expr(module, consts, &fun_def.body, &mut errors);
+    }
+
+    for const_def in &module.const_defs {
+        let found = match consts.expr(&const_def.name).map(|e| &**e) {
+            Some(Expr::List(items)) => items.len(),
+            _ => continue,
+        };
+        check_length(consts, &const_def.name, &const_def.const_type, found, &mut errors);
+    }
+
+    errors
+}
+
+fn check_expr(module : &Module, consts : &Consts, expr : &Expr, errors : &mut Vec<CheckError>) {
+    match expr {
+        Expr::Let { name, let_type, value, .. } => {
+            check_type(module, consts, let_type, errors);
+            if let Expr::List(items) = &**value {
+                check_length(consts, name, let_type, items.len(), errors);
+            }
+        },
+        Expr::Lambda { params, .. } => {
+            for p in params {
+                check_type(module, consts, &p.param_type, errors);
+            }
+        },
+        _ => (),
+    }
+    for child in children(expr) {
+        check_expr(module, consts, child, errors);
+    }
+}
+
+fn check_type(module : &Module, consts : &Consts, t : &Type, errors : &mut Vec<CheckError>) {
+    match t {
+        Type::Unit | Type::Simple(_) | Type::Infer => (),
+        Type::Indexed(_, ts) | Type::Tuple(ts) => {
+            for t in ts {
+                check_type(module, consts, t, errors);
+            }
+        },
+        Type::Arrow { input, output } => {
+            check_type(module, consts, input, errors);
+            check_type(module, consts, output, errors);
+        },
+        Type::Namespace(_, t) => check_type(module, consts, t, errors),
+        Type::Array { element, length } => {
+            check_type(module, consts, element, errors);
+            // a constant which could not be evaluated has been reported already
+            let unevaluated = module.const_defs.iter().any(|c| &c.name == length) && !consts.contains(length);
+            if !unevaluated && array_length(consts, length).is_none() {
+                errors.push(CheckError::InvalidArrayLength { length: length.clone() });
+            }
+        },
+    }
+}
+
+/// The number of items of an array whose length is written `length`.
+fn array_length(consts : &Consts, length : &str) -> Option<i64> {
+    let n = if length.starts_with(|c : char| c.is_ascii_digit() || c == '-') { length.parse().ok() } else { consts.int(length) };
+    n.filter(|n| *n >= 0)
+}
+
+fn check_length(consts : &Consts, name : &str, t : &Type, found : usize, errors : &mut Vec<CheckError>) {
+    if let Type::Array { length, .. } = t {
+        match array_length(consts, length) {
+            Some(expected) if expected as usize != found => {
+                errors.push(CheckError::ArrayLengthMismatch { name: name.to_string(), expected, found });
+            },
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parsing::parser::parse;
+    use crate::checking::checker::check;
+    use crate::checking::check_error::CheckError;
+
+    #[test]
+    fn should_report_constants_which_cannot_be_evaluated() {
+        let m = parse(r#"
+fun now_ish() -> Int { 1 }
+const fun twice(x : Int) -> Int { x * 2 }
+const fun noisy(x : Int) -> Int { println(x); x }
+const A : Int = twice(B);
+const B : Int = 4;
+const C : Int = now_ish();
+const D : Int = E + 1;
+const E : Int = D;
+const F : Int = 1 / (B - 4);
+const G : Int = [1, 2, 3].len() + || 1;
+"#).unwrap();
+
+        let errors = check(&m).unwrap_err();
+        assert_eq!( errors.len(), 5, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::NotConstant { name, expr } if name == "C" && expr == "now_ish()" ) );
+        assert!( matches!( &errors[1], CheckError::ConstCycle { names } if names == &["D", "E"] ) );
+        assert!( matches!( &errors[2], CheckError::ConstFailed { name, message } if name == "F" && message == "division by zero" ) );
+        assert!( matches!( &errors[3], CheckError::NotConstant { name, .. } if name == "G" ) );
+        assert!( matches!( &errors[4], CheckError::NotConstant { name, expr } if name == "noisy" && expr == "println(x)" ) );
+    }
+
+    #[test]
+    fn should_check_the_lengths_of_arrays() {
+        let m = parse(r#"
+const N : Int = 3;
+const NAME : String = "three";
+const XS : [Int; N] = [1, 2, 3];
+const YS : [Int; 2] = [1, 2, 3];
+struct Grid { cells : [[Bool; N]; N], names : [String; NAME] }
+fun f(xs : [Int; -1]) -> [Int; M] {
+    let ok : [Int; N] = [1, 2, 3];
+    let short : [Int; N] = [1, 2];
+    xs
+}
+"#).unwrap();
+
+        let errors = check(&m).unwrap_err();
+        assert_eq!( errors.len(), 5, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::InvalidArrayLength { length } if length == "NAME" ) );
+        assert!( matches!( &errors[1], CheckError::InvalidArrayLength { length } if length == "-1" ) );
+        assert!( matches!( &errors[2], CheckError::InvalidArrayLength { length } if length == "M" ) );
+        assert!( matches!( &errors[3], CheckError::ArrayLengthMismatch { name, expected: 3, found: 2 } if name == "short" ) );
+        assert!( matches!( &errors[4], CheckError::ArrayLengthMismatch { name, expected: 2, found: 3 } if name == "YS" ) );
+    }
+
+    #[test]
+    fn should_only_match_constants_which_are_patterns() {
+        let m = parse(r#"
+enum Answer { Yes, No }
+const YES : Answer = Answer::Yes;
+const NO : Answer = Answer::No;
+const PRIMES : List<Int> = [2, 3, 5];
+fun f(a : Answer, xs : List<Int>) -> Int {
+    match a { YES => 1, NO => 2 };
+    match a { YES => 1 };
+    match xs { PRIMES => 1, _ => 2 }
+}
+"#).unwrap();
+
+        let errors = check(&m).unwrap_err();
+        assert_eq!( errors.len(), 2, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::NonExhaustiveMatch { missing, .. } if missing == &["Answer::No"] ) );
+        assert!( matches!( &errors[1], CheckError::NotAPattern { name, .. } if name == "PRIMES" ) );
+    }
+}
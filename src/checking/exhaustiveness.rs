@@ -0,0 +1,917 @@
+
+use crate::parsing::ast::*;
+use crate::parsing::printer::print_pattern;
+use crate::evaluating::consts::Consts;
+use super::check_error::CheckError;
+
+/* Match checking uses the pattern matrix usefulness algorithm (Maranget,
+   "Warnings for pattern matching").  Each arm is a row of the matrix.  An arm
+   is unreachable when it is not useful with respect to the arms above it, and
+   the match is exhaustive when a row of wildcards is not useful with respect
+   to every arm.  While checking exhaustiveness we also build the missing
+   patterns so that they can be reported.
+
+   Integer literals and ranges are intervals of `Int`.  Intervals overlap, so
+   before the rows are specialized to one, it is split at every bound of the
+   intervals heading the rows, which leaves pieces each either inside or
+   outside of every one of them.  The pieces of all of `Int` are its
+   constructors, covered when every piece is.
+*/
+
+const MAX_WITNESSES : usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ctor {
+    Case { enum_name : String, name : String },
+    Struct { name : String },
+    Tuple(usize),
+    Bool(bool),
+    /// The integers from `lo` to `hi`, both included.
+    Int { lo : i64, hi : i64 },
+    Literal(String),
+}
+
+#[derive(Debug, Clone)]
+enum Pat {
+    Wild,
+    Ctor(Ctor, Vec<Pat>),
+    Or(Vec<Pat>),
+}
+
+pub fn check_matches( module : &Module, consts : &Consts ) -> Vec<CheckError> {
+    let mut errors = vec![];
+
+    for fun_def in &module.fun_defs {
+        check_expr(module, consts, &fun_def.body, &mut errors);
+    }
+
+    for impl_def in &module.impl_defs {
+        for item in &impl_def.items {
+            if let ImplItem::Fun(fun_def) = item {
+                check_expr(module, consts, &fun_def.body, &mut errors);
+            }
+        }
+    }
+
+    errors
+}
+
+pub fn check_match( module : &Module, consts : &Consts, arms : &[MatchArm] ) -> Vec<CheckError> {
+    let mut errors = vec![];
+
+    let mut rows : Vec<Vec<Pat>> = vec![];
+    for (index, arm) in arms.iter().enumerate() {
+        let pat = match lower(module, consts, &arm.pattern, arm.meta) {
+            Ok(p) => p,
+            Err(e) => {
+                errors.push(e);
+                return errors;
+            },
+        };
+
+        if !useful(module, &rows, std::slice::from_ref(&pat)) {
+            errors.push(CheckError::UnreachableArm { arm: index, meta: arm.meta });
+        }
+        else {
+            let mut redundant = vec![];
+            redundant_alternatives(module, &rows, &pat, &pat, &mut vec![], &mut 0, &mut redundant);
+            for alternative in redundant {
+                errors.push(CheckError::RedundantAlternative { arm: index, alternative, meta: arm.meta });
+            }
+        }
+
+        rows.push(vec![pat]);
+    }
+
+    let missing = missing(module, &rows, 1);
+    if !missing.is_empty() {
+        let missing = missing.iter().map(|w| display(module, &w[0])).collect();
+        let meta = match (arms.first(), arms.last()) {
+            (Some(first), Some(last)) => Meta { start: first.meta.start, end: last.meta.end },
+            _ => Meta { start: 0, end: 0 },
+        };
+        errors.push(CheckError::NonExhaustiveMatch { missing, meta });
+    }
+
+    errors
+}
+
+/* An alternative of an or pattern, at any depth, is redundant when the arm
+   with the alternatives around it chosen and it in place of its or pattern
+   matches nothing which the arms above or the alternatives before it do not.
+   Alternatives are numbered in the order they are written, counting those
+   inside other alternatives.
+*/
+
+fn redundant_alternatives(module : &Module, rows : &[Vec<Pat>], root : &Pat, pat : &Pat, path : &mut Vec<usize>, count : &mut usize, found : &mut Vec<usize>) {
+    match pat {
+        Pat::Wild => (),
+        Pat::Ctor(_, args) => {
+            for (i, arg) in args.iter().enumerate() {
+                path.push(i);
+                redundant_alternatives(module, rows, root, arg, path, count, found);
+                path.pop();
+            }
+        },
+        Pat::Or(alternatives) => {
+            for (i, alternative) in alternatives.iter().enumerate() {
+                let mut previous = rows.to_vec();
+                if i > 0 {
+                    previous.push(vec![focus(root, path, &Pat::Or(alternatives[..i].to_vec()))]);
+                }
+
+                let index = *count;
+                *count += 1;
+                if useful(module, &previous, &[focus(root, path, alternative)]) {
+                    path.push(i);
+                    redundant_alternatives(module, rows, root, alternative, path, count, found);
+                    path.pop();
+                }
+                else {
+                    found.push(index);
+                    *count += alternative_count(alternative);
+                }
+            }
+        },
+    }
+}
+
+/// `pat` with what is at `path` replaced by `leaf`, and each or pattern on
+/// the way by the alternative the path goes through.
+fn focus(pat : &Pat, path : &[usize], leaf : &Pat) -> Pat {
+    match (pat, path.split_first()) {
+        (_, None) => leaf.clone(),
+        (Pat::Or(alternatives), Some((i, rest))) => focus(&alternatives[*i], rest, leaf),
+        (Pat::Ctor(c, args), Some((i, rest))) => {
+            let mut args = args.clone();
+            args[*i] = focus(&args[*i], rest, leaf);
+            Pat::Ctor(c.clone(), args)
+        },
+        (Pat::Wild, Some(_)) => unreachable!("paths only lead through or patterns and constructors"),
+    }
+}
+
+fn alternative_count(pat : &Pat) -> usize {
+    match pat {
+        Pat::Wild => 0,
+        Pat::Ctor(_, args) => args.iter().map(alternative_count).sum(),
+        Pat::Or(alternatives) => alternatives.len() + alternatives.iter().map(alternative_count).sum::<usize>(),
+    }
+}
+
+fn check_expr(module : &Module, consts : &Consts, expr : &Expr, errors : &mut Vec<CheckError>) {
+    match expr {
+        Expr::Number(_) | Expr::DString(_) | Expr::Unit | Expr::Variable(_) | Expr::Namespace(_, _) => (),
+        Expr::Tuple(exprs) | Expr::Block(exprs) => {
+            for e in exprs {
+                check_expr(module, consts, e, errors);
+            }
+        },
+        Expr::Call { fun, args, .. } => {
+            check_expr(module, consts, fun, errors);
+            for e in args {
+                check_expr(module, consts, e, errors);
+            }
+        },
+        Expr::Dot { expr, .. } => check_expr(module, consts, expr, errors),
+        Expr::MethodCall { receiver, args, .. } => {
+            check_expr(module, consts, receiver, errors);
+            for e in args {
+                check_expr(module, consts, e, errors);
+            }
+        },
+        Expr::Let { value, .. } => check_expr(module, consts, value, errors),
+        Expr::Assign { target, value, .. } => {
+            check_expr(module, consts, target, errors);
+            check_expr(module, consts, value, errors);
+        },
+        Expr::Range { start, end, .. } => {
+            for e in start.iter().chain(end) {
+                check_expr(module, consts, e, errors);
+            }
+        },
+        Expr::Return(e) | Expr::Yield(e) | Expr::Try(e) | Expr::Propagate(e) | Expr::Panic { message: e, .. } => check_expr(module, consts, e, errors),
+        Expr::Assert { condition, message, .. } => {
+            check_expr(module, consts, condition, errors);
+            if let Some(message) = message {
+                check_expr(module, consts, message, errors);
+            }
+        },
+        Expr::Bool(_) | Expr::Break | Expr::Continue => (),
+        Expr::List(exprs) => {
+            for e in exprs {
+                check_expr(module, consts, e, errors);
+            }
+        },
+        Expr::Dict(pairs) => {
+            for (k, v) in pairs {
+                check_expr(module, consts, k, errors);
+                check_expr(module, consts, v, errors);
+            }
+        },
+        Expr::Struct { fields, .. } => {
+            for (_, e) in fields {
+                check_expr(module, consts, e, errors);
+            }
+        },
+        Expr::Lambda { body, .. } => check_expr(module, consts, body, errors),
+        Expr::Unary { expr, .. } => check_expr(module, consts, expr, errors),
+        Expr::Index { expr: left, index: right } | Expr::Binary { left, right, .. } | Expr::While { condition: left, body: right }
+            | Expr::Foreach { iterable: left, body: right, .. } => {
+            check_expr(module, consts, left, errors);
+            check_expr(module, consts, right, errors);
+        },
+        Expr::If { condition, then, otherwise } => {
+            check_expr(module, consts, condition, errors);
+            check_expr(module, consts, then, errors);
+            check_expr(module, consts, otherwise, errors);
+        },
+        Expr::Match { expr, arms } => {
+            check_expr(module, consts, expr, errors);
+            for arm in arms {
+                check_expr(module, consts, &arm.body, errors);
+            }
+            errors.append(&mut check_match(module, consts, arms));
+        },
+    }
+}
+
+fn find_enum<'a>(module : &'a Module, namespace : &[String], name : &str) -> Option<&'a EnumDef> {
+    match namespace.last() {
+        Some(enum_name) => module.enum_defs.iter().find(|e| &e.name == enum_name),
+        None => module.enum_defs.iter().find(|e| e.cases.iter().any(|c| case_name(c) == name)),
+    }
+}
+
+fn find_case<'a>(module : &'a Module, enum_name : &str, name : &str) -> Option<&'a EnumCase> {
+    module.enum_defs.iter()
+                    .find(|e| e.name == enum_name)
+                    .and_then(|e| e.cases.iter().find(|c| case_name(c) == name))
+}
+
+fn case_name(case : &EnumCase) -> &str {
+    match case {
+        EnumCase::EmptyCase { name } => name,
+        EnumCase::StructCase { name, .. } => name,
+        EnumCase::TypeCase { name, .. } => name,
+    }
+}
+
+/// The pattern `pattern` of the arm `meta` spans, lowered.
+fn lower(module : &Module, consts : &Consts, pattern : &Pattern, meta : Meta) -> Result<Pat, CheckError> {
+    match pattern {
+        Pattern::Variable(name) if consts.contains(name) => match consts.pattern(name) {
+            Some(p) => lower(module, consts, p, meta),
+            None => Err(CheckError::NotAPattern { name: name.clone(), meta }),
+        },
+        Pattern::Wildcard | Pattern::Variable(_) => Ok(Pat::Wild),
+        Pattern::Number(n) => match n.parse::<i64>() {
+            Ok(n) => Ok(Pat::Ctor(Ctor::Int { lo: n, hi: n }, vec![])),
+            Err(_) => Ok(Pat::Ctor(Ctor::Literal(n.clone()), vec![])),
+        },
+        Pattern::Bool(b) => Ok(Pat::Ctor(Ctor::Bool(*b), vec![])),
+        Pattern::DString(s) => Ok(Pat::Ctor(Ctor::Literal(format!("{:?}", s)), vec![])),
+        Pattern::Range { start, end, inclusive } => match int_range(start.as_deref(), end.as_deref(), *inclusive) {
+            Some((lo, hi)) => Ok(Pat::Ctor(Ctor::Int { lo, hi }, vec![])),
+            // like a literal, a range of floats or an empty one never covers every number
+            None => Ok(Pat::Ctor(Ctor::Literal(print_pattern(pattern)), vec![])),
+        },
+        Pattern::Tuple(ps) => {
+            let ps = ps.iter().map(|p| lower(module, consts, p, meta)).collect::<Result<Vec<_>, _>>()?;
+            Ok(Pat::Ctor(Ctor::Tuple(ps.len()), ps))
+        },
+        Pattern::Or(ps) => {
+            let ps = ps.iter().map(|p| lower(module, consts, p, meta)).collect::<Result<Vec<_>, _>>()?;
+            Ok(Pat::Or(ps))
+        },
+        Pattern::Case { namespace, name, contents } if namespace.is_empty() && find_enum(module, namespace, name).is_none()
+                                                      && module.struct_defs.iter().any(|s| &s.name == name) => {
+            let struct_def = module.struct_defs.iter().find(|s| &s.name == name).unwrap();
+            let mismatch = || CheckError::CasePatternMismatch { name: name.clone(), meta };
+
+            let field_patterns = match contents {
+                CasePattern::Struct { fields, .. } => fields,
+                _ => return Err(mismatch()),
+            };
+
+            for (field_name, _) in field_patterns {
+                if !struct_def.fields.iter().any(|f| &f.name == field_name) {
+                    return Err(mismatch());
+                }
+            }
+
+            let mut ps = vec![];
+            for field in &struct_def.fields {
+                match field_patterns.iter().find(|(n, _)| n == &field.name) {
+                    Some((_, p)) => ps.push(lower(module, consts, p, meta)?),
+                    None => ps.push(Pat::Wild),
+                }
+            }
+            Ok(Pat::Ctor(Ctor::Struct { name: name.clone() }, ps))
+        },
+        Pattern::Case { namespace, name, contents } => {
+            let unknown = || CheckError::UnknownCase { name: name.clone(), meta };
+
+            let enum_def = find_enum(module, namespace, name).ok_or_else(unknown)?;
+            let case = enum_def.cases.iter().find(|c| case_name(c) == name).ok_or_else(unknown)?;
+            let ctor = Ctor::Case { enum_name: enum_def.name.clone(), name: name.clone() };
+
+            let mismatch = || CheckError::CasePatternMismatch { name: name.clone(), meta };
+
+            match (case, contents) {
+                (EnumCase::EmptyCase { .. }, CasePattern::Empty) => Ok(Pat::Ctor(ctor, vec![])),
+                (EnumCase::TypeCase { types, .. }, CasePattern::Tuple(ps)) if types.len() == ps.len() => {
+                    let ps = ps.iter().map(|p| lower(module, consts, p, meta)).collect::<Result<Vec<_>, _>>()?;
+                    Ok(Pat::Ctor(ctor, ps))
+                },
+                (EnumCase::StructCase { fields, .. }, CasePattern::Struct { fields: field_patterns, .. }) => {
+                    for (field_name, _) in field_patterns {
+                        if !fields.iter().any(|f| &f.name == field_name) {
+                            return Err(mismatch());
+                        }
+                    }
+
+                    let mut ps = vec![];
+                    for field in fields {
+                        match field_patterns.iter().find(|(n, _)| n == &field.name) {
+                            Some((_, p)) => ps.push(lower(module, consts, p, meta)?),
+                            None => ps.push(Pat::Wild),
+                        }
+                    }
+                    Ok(Pat::Ctor(ctor, ps))
+                },
+                _ => Err(mismatch()),
+            }
+        },
+    }
+}
+
+/// The interval of a range of integers, when it is one and is not empty.
+fn int_range(start : Option<&str>, end : Option<&str>, inclusive : bool) -> Option<(i64, i64)> {
+    let lo = match start {
+        Some(start) => start.parse::<i64>().ok()?,
+        None => i64::MIN,
+    };
+    let hi = match end {
+        Some(end) if inclusive => end.parse::<i64>().ok()?,
+        Some(end) => end.parse::<i64>().ok()?.checked_sub(1)?,
+        None => i64::MAX,
+    };
+    if lo <= hi { Some((lo, hi)) } else { None }
+}
+
+/// The pieces of the interval from `lo` to `hi` cut at the bounds of the
+/// integer constructors in `heads`.
+fn split(lo : i64, hi : i64, heads : &[Ctor]) -> Vec<Ctor> {
+    let mut cuts = vec![lo];
+    for c in heads {
+        if let Ctor::Int { lo: a, hi: b } = c {
+            if *a > lo && *a <= hi {
+                cuts.push(*a);
+            }
+            if *b >= lo && *b < hi {
+                cuts.push(b + 1);
+            }
+        }
+    }
+    cuts.sort_unstable();
+    cuts.dedup();
+
+    cuts.iter()
+        .enumerate()
+        .map(|(i, start)| Ctor::Int { lo: *start, hi: cuts.get(i + 1).map_or(hi, |next| next - 1) })
+        .collect()
+}
+
+/// Whether a row headed by `c` matches everything `ctor` does.
+fn covers(c : &Ctor, ctor : &Ctor) -> bool {
+    match (c, ctor) {
+        (Ctor::Int { lo: a, hi: b }, Ctor::Int { lo, hi }) => a <= lo && hi <= b,
+        _ => c == ctor,
+    }
+}
+
+fn arity(module : &Module, ctor : &Ctor) -> usize {
+    match ctor {
+        Ctor::Tuple(n) => *n,
+        Ctor::Struct { name } => module.struct_defs.iter().find(|s| &s.name == name).map_or(0, |s| s.fields.len()),
+        Ctor::Bool(_) | Ctor::Int { .. } | Ctor::Literal(_) => 0,
+        Ctor::Case { enum_name, name } => match find_case(module, enum_name, name) {
+            Some(EnumCase::TypeCase { types, .. }) => types.len(),
+            Some(EnumCase::StructCase { fields, .. }) => fields.len(),
+            _ => 0,
+        },
+    }
+}
+
+fn head_ctors(rows : &[Vec<Pat>]) -> Vec<Ctor> {
+    fn collect(p : &Pat, ctors : &mut Vec<Ctor>) {
+        match p {
+            Pat::Wild => (),
+            Pat::Ctor(c, _) => {
+                if !ctors.contains(c) {
+                    ctors.push(c.clone());
+                }
+            },
+            Pat::Or(ps) => {
+                for p in ps {
+                    collect(p, ctors);
+                }
+            },
+        }
+    }
+
+    let mut ctors = vec![];
+    for row in rows {
+        collect(&row[0], &mut ctors);
+    }
+    ctors
+}
+
+// Returns every constructor of the type when the head constructors cover it,
+// otherwise returns the constructors which are missing.
+fn signature(module : &Module, heads : &[Ctor]) -> Result<Vec<Ctor>, Vec<Ctor>> {
+    if heads.iter().any(|c| matches!( c, Ctor::Int { .. } )) {
+        // floats can be matched by integer patterns too, so with a float among them nothing is covered
+        if heads.iter().any(|c| !matches!( c, Ctor::Int { .. } )) {
+            return Err(vec![]);
+        }
+
+        let pieces = split(i64::MIN, i64::MAX, heads);
+        let mut missing : Vec<Ctor> = vec![];
+        for piece in pieces.iter().filter(|p| !heads.iter().any(|c| covers(c, p))) {
+            match (missing.last_mut(), piece) {
+                (Some(Ctor::Int { hi, .. }), Ctor::Int { lo, hi: next }) if *hi + 1 == *lo => *hi = *next,
+                _ => missing.push(piece.clone()),
+            }
+        }
+        return if missing.is_empty() { Ok(pieces) } else { Err(missing) };
+    }
+
+    match heads.first() {
+        None => Err(vec![]),
+        Some(Ctor::Tuple(n)) => Ok(vec![Ctor::Tuple(*n)]),
+        Some(c @ Ctor::Struct { .. }) => Ok(vec![c.clone()]),
+        Some(Ctor::Bool(_)) => {
+            let all = vec![Ctor::Bool(true), Ctor::Bool(false)];
+            let missing = all.iter().filter(|c| !heads.contains(c)).cloned().collect::<Vec<_>>();
+            if missing.is_empty() {
+                Ok(all)
+            }
+            else {
+                Err(missing)
+            }
+        },
+        Some(Ctor::Int { .. }) | Some(Ctor::Literal(_)) => Err(vec![]),
+        Some(Ctor::Case { enum_name, .. }) => {
+            let all = module.enum_defs.iter()
+                                      .find(|e| &e.name == enum_name)
+                                      .map(|e| e.cases.iter()
+                                                      .map(|c| Ctor::Case { enum_name: enum_name.clone(), name: case_name(c).to_string() })
+                                                      .collect::<Vec<_>>())
+                                      .unwrap_or_default();
+
+            let missing = all.iter().filter(|c| !heads.contains(c)).cloned().collect::<Vec<_>>();
+
+            if missing.is_empty() {
+                Ok(all)
+            }
+            else {
+                Err(missing)
+            }
+        },
+    }
+}
+
+fn expand_or(rows : &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    let mut result = vec![];
+    for row in rows {
+        match &row[0] {
+            Pat::Or(ps) => {
+                let expanded = ps.iter().map(|p| {
+                    let mut r = vec![p.clone()];
+                    r.extend(row[1..].iter().cloned());
+                    r
+                }).collect::<Vec<_>>();
+                result.extend(expand_or(&expanded));
+            },
+            _ => result.push(row.clone()),
+        }
+    }
+    result
+}
+
+fn specialize(module : &Module, rows : &[Vec<Pat>], ctor : &Ctor) -> Vec<Vec<Pat>> {
+    let n = arity(module, ctor);
+    let mut result = vec![];
+    for row in expand_or(rows) {
+        match &row[0] {
+            Pat::Ctor(c, args) if covers(c, ctor) => {
+                let mut r = args.clone();
+                r.extend(row[1..].iter().cloned());
+                result.push(r);
+            },
+            Pat::Wild => {
+                let mut r = vec![Pat::Wild; n];
+                r.extend(row[1..].iter().cloned());
+                result.push(r);
+            },
+            _ => (),
+        }
+    }
+    result
+}
+
+fn default_rows(rows : &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    expand_or(rows).into_iter()
+                   .filter(|row| matches!( row[0], Pat::Wild ))
+                   .map(|row| row[1..].to_vec())
+                   .collect()
+}
+
+fn useful(module : &Module, rows : &[Vec<Pat>], v : &[Pat]) -> bool {
+    if v.is_empty() {
+        return rows.is_empty();
+    }
+
+    match &v[0] {
+        Pat::Or(ps) => ps.iter().any(|p| {
+            let mut r = vec![p.clone()];
+            r.extend(v[1..].iter().cloned());
+            useful(module, rows, &r)
+        }),
+        Pat::Ctor(Ctor::Int { lo, hi }, _) => split(*lo, *hi, &head_ctors(rows)).iter().any(|piece| {
+            useful(module, &specialize(module, rows, piece), &v[1..])
+        }),
+        Pat::Ctor(c, args) => {
+            let mut r = args.clone();
+            r.extend(v[1..].iter().cloned());
+            useful(module, &specialize(module, rows, c), &r)
+        },
+        Pat::Wild => {
+            match signature(module, &head_ctors(rows)) {
+                Ok(all) => all.iter().any(|c| {
+                    let mut r = vec![Pat::Wild; arity(module, c)];
+                    r.extend(v[1..].iter().cloned());
+                    useful(module, &specialize(module, rows, c), &r)
+                }),
+                Err(_) => useful(module, &default_rows(rows), &v[1..]),
+            }
+        },
+    }
+}
+
+fn missing(module : &Module, rows : &[Vec<Pat>], n : usize) -> Vec<Vec<Pat>> {
+    if n == 0 {
+        return if rows.is_empty() { vec![vec![]] } else { vec![] };
+    }
+
+    let mut witnesses = vec![];
+
+    match signature(module, &head_ctors(rows)) {
+        Ok(all) => {
+            for c in all {
+                let a = arity(module, &c);
+                for w in missing(module, &specialize(module, rows, &c), a + n - 1) {
+                    let mut r = vec![Pat::Ctor(c.clone(), w[..a].to_vec())];
+                    r.extend(w[a..].iter().cloned());
+                    witnesses.push(r);
+                }
+                if witnesses.len() >= MAX_WITNESSES {
+                    break;
+                }
+            }
+        },
+        Err(missing_ctors) => {
+            let rest = missing(module, &default_rows(rows), n - 1);
+            let heads = if missing_ctors.is_empty() {
+                vec![Pat::Wild]
+            }
+            else {
+                missing_ctors.iter().map(|c| Pat::Ctor(c.clone(), vec![Pat::Wild; arity(module, c)])).collect()
+            };
+
+            for w in rest.iter().take(MAX_WITNESSES) {
+                for h in &heads {
+                    let mut r = vec![h.clone()];
+                    r.extend(w.iter().cloned());
+                    witnesses.push(r);
+                }
+            }
+        },
+    }
+
+    witnesses.truncate(MAX_WITNESSES);
+    witnesses
+}
+
+fn display(module : &Module, pat : &Pat) -> String {
+    match pat {
+        Pat::Wild => "_".to_string(),
+        Pat::Or(ps) => ps.iter().map(|p| display(module, p)).collect::<Vec<_>>().join(" | "),
+        Pat::Ctor(Ctor::Literal(l), _) => l.clone(),
+        Pat::Ctor(Ctor::Int { lo, hi }, _) => match (*lo, *hi) {
+            (lo, hi) if lo == hi => lo.to_string(),
+            (i64::MIN, i64::MAX) => "_".to_string(),
+            (i64::MIN, hi) => format!("..={}", hi),
+            (lo, i64::MAX) => format!("{}..", lo),
+            (lo, hi) => format!("{}..={}", lo, hi),
+        },
+        Pat::Ctor(Ctor::Bool(b), _) => b.to_string(),
+        Pat::Ctor(Ctor::Struct { name }, _) => format!("{} {{ .. }}", name),
+        Pat::Ctor(Ctor::Tuple(_), ps) => format!("({})", ps.iter().map(|p| display(module, p)).collect::<Vec<_>>().join(", ")),
+        Pat::Ctor(Ctor::Case { enum_name, name }, ps) => {
+            match find_case(module, enum_name, name) {
+                Some(EnumCase::StructCase { fields, .. }) => {
+                    let shown = fields.iter()
+                                      .zip(ps)
+                                      .filter(|(_, p)| !matches!( p, Pat::Wild ))
+                                      .map(|(f, p)| format!("{} : {}", f.name, display(module, p)))
+                                      .collect::<Vec<_>>();
+                    if shown.is_empty() {
+                        format!("{}::{} {{ .. }}", enum_name, name)
+                    }
+                    else {
+                        format!("{}::{} {{ {}, .. }}", enum_name, name, shown.join(", "))
+                    }
+                },
+                Some(EnumCase::TypeCase { .. }) =>
+                    format!("{}::{}({})", enum_name, name, ps.iter().map(|p| display(module, p)).collect::<Vec<_>>().join(", ")),
+                _ => format!("{}::{}", enum_name, name),
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+
+    const ENUMS : &str = r#"
+enum Shape {
+    Circle(Int),
+    Square { side : Int, color : Int },
+    Empty,
+}
+
+enum Option<T> {
+    Some(T),
+    None,
+}
+"#;
+
+    fn errors_for(body : &str) -> Vec<CheckError> {
+        let module = parse(&format!("{} fun f() {{ {} }}", ENUMS, body)).unwrap();
+        check_matches(&module, &Consts::default())
+    }
+
+    fn missing_of(errors : &[CheckError]) -> Vec<String> {
+        match errors {
+            [CheckError::NonExhaustiveMatch { missing, .. }] => missing.clone(),
+            x => panic!( "Expected single NonExhaustiveMatch but found {:?}", x ),
+        }
+    }
+
+    #[test]
+    fn should_accept_exhaustive_match() {
+        let errors = errors_for(r#"
+match s {
+    Shape::Circle(r) => r,
+    Shape::Square { side, .. } => side,
+    Shape::Empty => 0,
+}"#);
+        assert!( errors.is_empty(), "{:?}", errors );
+    }
+
+    #[test]
+    fn should_report_missing_struct_case() {
+        let errors = errors_for(r#"
+match s {
+    Shape::Circle(r) => r,
+    Shape::Empty => 0,
+}"#);
+        assert_eq!( missing_of(&errors), vec!["Shape::Square { .. }".to_string()] );
+    }
+
+    #[test]
+    fn should_report_missing_nested_case() {
+        let errors = errors_for(r#"
+match o {
+    Option::Some(Shape::Circle(_)) => 1,
+    Option::Some(Shape::Empty) => 2,
+    Option::None => 3,
+}"#);
+        assert_eq!( missing_of(&errors), vec!["Option::Some(Shape::Square { .. })".to_string()] );
+    }
+
+    #[test]
+    fn should_report_missing_tuple_combination() {
+        let errors = errors_for(r#"
+match pair {
+    (Option::Some(_), Option::Some(_)) => 1,
+    (Option::None, _) => 2,
+}"#);
+        assert_eq!( missing_of(&errors), vec!["(Option::Some(_), Option::None)".to_string()] );
+    }
+
+    #[test]
+    fn should_require_wildcard_for_literals() {
+        let errors = errors_for(r#"
+match n {
+    1 => "one",
+    2 => "two",
+}"#);
+        assert_eq!( missing_of(&errors), vec!["..=0".to_string(), "3..".to_string()] );
+
+        let errors = errors_for(r#"
+match n {
+    1 => "one",
+    _ => "other",
+}"#);
+        assert!( errors.is_empty(), "{:?}", errors );
+    }
+
+    #[test]
+    fn should_report_field_literal_in_witness() {
+        let errors = errors_for(r#"
+match s {
+    Shape::Square { side : 1, .. } => 1,
+    Shape::Circle(_) | Shape::Empty => 2,
+}"#);
+        assert_eq!( missing_of(&errors), vec!["Shape::Square { side : ..=0, .. }".to_string(), "Shape::Square { side : 2.., .. }".to_string()] );
+    }
+
+    #[test]
+    fn should_report_unreachable_arm() {
+        let errors = errors_for(r#"
+match o {
+    Option::Some(x) => 1,
+    _ => 2,
+    Option::None => 3,
+}"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( errors[0], CheckError::UnreachableArm { arm: 2, .. } ) );
+    }
+
+    #[test]
+    fn should_report_unreachable_literal_arm() {
+        let errors = errors_for(r#"
+match n {
+    "a" => 1,
+    "a" => 2,
+    _ => 3,
+}"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( errors[0], CheckError::UnreachableArm { arm: 1, .. } ) );
+    }
+
+    #[test]
+    fn should_report_redundant_or_alternative() {
+        let errors = errors_for(r#"
+match s {
+    Shape::Circle(_) => 1,
+    Shape::Empty | Shape::Circle(_) | Shape::Square { .. } => 2,
+}"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( errors[0], CheckError::RedundantAlternative { arm: 1, alternative: 1, .. } ) );
+    }
+
+    #[test]
+    fn should_report_redundant_nested_or_alternative() {
+        let errors = errors_for(r#"
+match o {
+    Option::Some(1 | 1) => 1,
+    Option::None | Option::Some((2 | 3) | 2) => 2,
+    _ => 3,
+}"#);
+        assert_eq!( errors.len(), 2, "{:?}", errors );
+        assert!( matches!( errors[0], CheckError::RedundantAlternative { arm: 0, alternative: 1, .. } ) );
+        assert!( matches!( errors[1], CheckError::RedundantAlternative { arm: 1, alternative: 5, .. } ) );
+    }
+
+    #[test]
+    fn should_not_report_alternatives_needed_in_some_combination() {
+        let errors = errors_for(r#"
+match p {
+    (1, 3) => 1,
+    (1 | 2, 3 | 4) => 2,
+    _ => 3,
+}"#);
+        assert!( errors.is_empty(), "{:?}", errors );
+    }
+
+    #[test]
+    fn should_report_where_the_match_or_arm_is() {
+        let source = format!("{} fun f() {{ {} }}", ENUMS, "match o { Option::None => 1, Option::None => 2 }");
+        let module = parse(&source).unwrap();
+        let errors = check_matches(&module, &Consts::default());
+        assert_eq!( errors.len(), 2, "{:?}", errors );
+        match &errors[0] {
+            CheckError::UnreachableArm { arm: 1, meta } => assert_eq!( &source[meta.start..meta.end], "Option::None" ),
+            x => panic!( "Expected UnreachableArm but found {:?}", x ),
+        }
+        match &errors[1] {
+            CheckError::NonExhaustiveMatch { meta, .. } => assert_eq!( &source[meta.start..meta.end], "Option::None => 1, Option::None" ),
+            x => panic!( "Expected NonExhaustiveMatch but found {:?}", x ),
+        }
+    }
+
+    #[test]
+    fn should_report_integers_inside_earlier_ranges() {
+        let errors = errors_for(r#"
+match n {
+    0..=9 => 1,
+    5 => 2,
+    2..=4 => 3,
+    5..=15 => 4,
+    10..=20 => 5,
+    7..=18 => 6,
+    _ => 7,
+}"#);
+        assert_eq!( errors.len(), 3, "{:?}", errors );
+        assert!( matches!( errors[0], CheckError::UnreachableArm { arm: 1, .. } ) );
+        assert!( matches!( errors[1], CheckError::UnreachableArm { arm: 2, .. } ) );
+        assert!( matches!( errors[2], CheckError::UnreachableArm { arm: 5, .. } ) );
+
+        let errors = errors_for(r#"
+match n {
+    1 | 0..=3 => 1,
+    _ => 2,
+}"#);
+        assert!( errors.is_empty(), "{:?}", errors );
+
+        let errors = errors_for(r#"
+match n {
+    0..=3 | 2 => 1,
+    _ => 2,
+}"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( errors[0], CheckError::RedundantAlternative { arm: 0, alternative: 1, .. } ) );
+    }
+
+    #[test]
+    fn should_accept_ranges_covering_every_integer() {
+        let errors = errors_for(r#"
+match n {
+    0..=9 => 1,
+    10..=9223372036854775807 => 2,
+    -9223372036854775808..=-1 => 3,
+}"#);
+        assert!( errors.is_empty(), "{:?}", errors );
+
+        let errors = errors_for(r#"
+match n {
+    ..0 => 1,
+    0 => 2,
+    1.. => 3,
+}"#);
+        assert!( errors.is_empty(), "{:?}", errors );
+
+        let errors = errors_for(r#"
+match n {
+    ..0 => 1,
+    1..=9 | 20.. => 2,
+}"#);
+        assert_eq!( missing_of(&errors), vec!["0".to_string(), "10..=19".to_string()] );
+
+        let errors = errors_for(r#"
+match n {
+    ..0 => 1,
+    0.. => 2,
+    1.5 => 3,
+}"#);
+        assert_eq!( missing_of(&errors), vec!["_".to_string()] );
+    }
+
+    #[test]
+    fn should_report_unknown_case() {
+        let errors = errors_for(r#"
+match s {
+    Shape::Triangle => 1,
+}"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::UnknownCase { name, .. } if name == "Triangle" ) );
+    }
+
+    #[test]
+    fn should_report_case_pattern_with_wrong_shape() {
+        let errors = errors_for(r#"
+match s {
+    Shape::Circle(a, b) => 1,
+    _ => 2,
+}"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::CasePatternMismatch { name, .. } if name == "Circle" ) );
+    }
+
+    #[test]
+    fn should_check_nested_matches() {
+        let errors = errors_for(r#"
+match o {
+    Option::Some(x) => match x { 1 => 1 },
+    Option::None => 2,
+}"#);
+        assert_eq!( missing_of(&errors), vec!["..=0".to_string(), "2..".to_string()] );
+    }
+}
@@ -0,0 +1,150 @@
+
+use crate::parsing::ast::*;
+use super::check_error::CheckError;
+
+/* A function whose body contains `yield` is a generator:  calling it gives an
+   iterator and each `yield` hands the next item to whoever is stepping it.
+   The interpreter resumes a generator by walking back down to where it
+   stopped, so a `yield` has to be a statement:  an item of a block, a branch
+   of an `if` or `match`, or the body of a loop, all the way up from the
+   function body.  A closure cannot be a generator.
+*/
+
+pub fn check_generators( module : &Module ) -> Vec<CheckError> {
+    let mut errors = vec![];
+
+    let impl_funs = module.impl_defs.iter().flat_map(|i| &i.items).filter_map(|item| match item {
+        ImplItem::Fun(fun_def) => Some(fun_def),
+        _ => None,
+    });
+    for fun_def in module.fun_defs.iter().chain(impl_funs) {
+        check_expr(&fun_def.sig.name, &fun_def.body, true, fun_def.meta, &mut errors);
+    }
+
+    errors
+}
+
+/// Whether `expr` yields, not counting the closures in it.
+pub fn contains_yield(expr : &Expr) -> bool {
+    match expr {
+        Expr::Yield(_) => true,
+        Expr::Lambda { .. } => false,
+        e => children(e).into_iter().any(contains_yield),
+    }
+}
+
+/// Checks `expr` inside of what `at` spans, the innermost expression around it
+/// which knows where it is.
+fn check_expr(function : &str, expr : &Expr, statement : bool, at : Meta, errors : &mut Vec<CheckError>) {
+    let at = expr.meta().unwrap_or(at);
+    match expr {
+        Expr::Yield(e) => {
+            if !statement {
+                errors.push(CheckError::MisplacedYield { function: function.to_string(), meta: at });
+            }
+            check_expr(function, e, false, at, errors);
+        },
+        Expr::Lambda { body, .. } => check_closure(function, body, at, errors),
+        Expr::Block(exprs) => {
+            for e in exprs {
+                check_expr(function, e, statement, at, errors);
+            }
+        },
+        Expr::If { condition, then, otherwise } => {
+            check_expr(function, condition, false, at, errors);
+            check_expr(function, then, statement, at, errors);
+            check_expr(function, otherwise, statement, at, errors);
+        },
+        Expr::While { condition, body } | Expr::Foreach { iterable: condition, body, .. } => {
+            check_expr(function, condition, false, at, errors);
+            check_expr(function, body, statement, at, errors);
+        },
+        Expr::Match { expr, arms } => {
+            check_expr(function, expr, false, at, errors);
+            for arm in arms {
+                check_expr(function, &arm.body, statement, at, errors);
+            }
+        },
+        e => {
+            for child in children(e) {
+                check_expr(function, child, false, at, errors);
+            }
+        },
+    }
+}
+
+fn check_closure(function : &str, expr : &Expr, at : Meta, errors : &mut Vec<CheckError>) {
+    let at = expr.meta().unwrap_or(at);
+    if let Expr::Yield(_) = expr {
+        errors.push(CheckError::YieldInClosure { function: function.to_string(), meta: at });
+    }
+    for child in children(expr) {
+        check_closure(function, child, at, errors);
+    }
+}
+
+/// The expressions directly inside of `expr`, including the body of a closure.
+pub fn children(expr : &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Number(_) | Expr::DString(_) | Expr::Unit | Expr::Variable(_) | Expr::Namespace(_, _) => vec![],
+        Expr::Bool(_) | Expr::Break | Expr::Continue => vec![],
+        Expr::Tuple(exprs) | Expr::Block(exprs) | Expr::List(exprs) => exprs.iter().collect(),
+        Expr::Call { fun, args, .. } => std::iter::once(&**fun).chain(args).collect(),
+        Expr::MethodCall { receiver, args, .. } => std::iter::once(&**receiver).chain(args).collect(),
+        Expr::Dot { expr, .. } | Expr::Return(expr) | Expr::Yield(expr) | Expr::Unary { expr, .. } => vec![expr],
+        Expr::Try(expr) | Expr::Propagate(expr) | Expr::Panic { message: expr, .. } | Expr::Let { value: expr, .. } => vec![expr],
+        Expr::Assert { condition, message, .. } => std::iter::once(&**condition).chain(message.as_deref()).collect(),
+        Expr::Range { start, end, .. } => start.as_deref().into_iter().chain(end.as_deref()).collect(),
+        Expr::Assign { target: a, value: b, .. } | Expr::Index { expr: a, index: b } | Expr::Binary { left: a, right: b, .. }
+            | Expr::While { condition: a, body: b } | Expr::Foreach { iterable: a, body: b, .. } => vec![a, b],
+        Expr::If { condition, then, otherwise } => vec![condition, then, otherwise],
+        Expr::Match { expr, arms } => std::iter::once(&**expr).chain(arms.iter().map(|a| &a.body)).collect(),
+        Expr::Dict(pairs) => pairs.iter().flat_map(|(k, v)| vec![k, v]).collect(),
+        Expr::Struct { fields, .. } => fields.iter().map(|(_, e)| e).collect(),
+        Expr::Lambda { body, .. } => vec![body],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+
+    #[test]
+    fn should_allow_yield_as_a_statement_of_a_function() {
+        let m = parse(r#"
+fun evens(xs : List<Int>) {
+    foreach x in xs {
+        if x % 2 == 0 { yield x } else { }
+    }
+    let mut i = 0;
+    while true {
+        match i { 0 => yield 0, _ => { yield i; } }
+        i = i + 1;
+    }
+}
+fun plain() -> Int { 1 }
+"#).unwrap();
+
+        assert!( check_generators(&m).is_empty() );
+        assert!( contains_yield(&m.fun_defs[0].body) );
+        assert!( !contains_yield(&m.fun_defs[1].body) );
+    }
+
+    #[test]
+    fn should_reject_yield_inside_expressions_and_closures() {
+        let m = parse(r#"
+fun f() {
+    let x = yield 1;
+    println(yield 2);
+    let g = || { yield 3 };
+}
+"#).unwrap();
+
+        let errors = check_generators(&m);
+        assert_eq!( errors.len(), 3, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::MisplacedYield { function, .. } if function == "f" ) );
+        assert!( matches!( &errors[1], CheckError::MisplacedYield { .. } ) );
+        assert!( matches!( &errors[2], CheckError::YieldInClosure { .. } ) );
+    }
+}
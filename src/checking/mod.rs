@@ -0,0 +1,10 @@
+
+pub mod check_error;
+pub mod trait_solver;
+pub mod checker;
+pub mod ownership;
+pub mod exhaustiveness;
+pub mod mutability;
+pub mod propagation;
+pub mod generators;
+pub mod constants;
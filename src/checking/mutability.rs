@@ -0,0 +1,725 @@
+
+use std::collections::HashMap;
+
+use crate::parsing::ast::*;
+use crate::evaluating::ops::{has_native_method, native_method_mutates, NATIVE_TYPES};
+use super::check_error::{CheckError, CheckWarning};
+use super::trait_solver::{TraitEnv, MethodTarget, substitute};
+use super::checker::pattern_variables;
+use super::generators::contains_yield;
+
+struct Binding {
+    name : String,
+    binding_type : Type,
+    mutable : bool,
+    mutated : bool,
+    declared : Meta,
+}
+
+/* A call of a method which takes `mut self` changes its receiver, like the
+   native methods which change a list or a dict do, so the receiver has to be
+   a mutable binding.  Lists, dicts, structs and enums are shared rather than
+   copied, so passing a binding to a `mut` parameter or binding it again with
+   `let mut` lets what it holds be changed too, and counts as a write to it.  Which method a call finds depends on the type of the
+   receiver.  A binding declared without a type takes the type of what it is
+   initialized with, as far as that is plain from the expression; when it is
+   not, a call mutates when every method of that name does.
+*/
+
+struct Mutability<'a, 'b> {
+    module : &'a Module,
+    env : &'b TraitEnv<'a>,
+    scope : Vec<TypeParam>,
+    bindings : Vec<Binding>,
+    errors : Vec<CheckError>,
+    warnings : Vec<CheckWarning>,
+}
+
+pub fn check_mutability<'a>( env : &TraitEnv<'a>, module : &'a Module ) -> (Vec<CheckError>, Vec<CheckWarning>) {
+    let mut m = Mutability { module, env, scope: vec![], bindings: vec![], errors: vec![], warnings: vec![] };
+
+    for fun_def in &module.fun_defs {
+        m.check_fun(fun_def, &[], None);
+    }
+
+    for impl_def in &module.impl_defs {
+        for item in &impl_def.items {
+            if let ImplItem::Fun(fun_def) = item {
+                m.check_fun(fun_def, &impl_def.type_params, Some(&impl_def.impl_type));
+            }
+        }
+    }
+
+    (m.errors, m.warnings)
+}
+
+/// The type of `expr` with `bindings` in scope, as far as it is plain from the
+/// expression, and `Type::Infer` when it is not.
+pub fn infer_type<'a>( env : &TraitEnv<'a>, module : &'a Module, bindings : &[(String, Type)], expr : &Expr ) -> Type {
+    let bindings = bindings.iter()
+                           .map(|(name, t)| Binding { name: name.clone(), binding_type: t.clone(), mutable: false, mutated: false, declared: Meta { start: 0, end: 0 } })
+                           .collect();
+    let m = Mutability { module, env, scope: vec![], bindings, errors: vec![], warnings: vec![] };
+    m.infer(expr)
+}
+
+fn root_variable(expr : &Expr) -> Option<&String> {
+    match expr {
+        Expr::Variable(name) => Some(name),
+        Expr::Dot { expr, .. } | Expr::Index { expr, .. } => root_variable(expr),
+        _ => None,
+    }
+}
+
+/// Whether values of a type are shared rather than copied, so that changing
+/// one through another binding changes the first too.  A type which is not
+/// known, or is a type parameter, is taken not to be.
+fn is_shared(t : &Type, scope : &[TypeParam]) -> bool {
+    match t {
+        Type::Simple(name) => !["Int", "Float", "Bool", "String"].contains(&name.as_str()) && !scope.iter().any(|tp| &tp.name == name),
+        Type::Unit | Type::Tuple(_) | Type::Arrow { .. } | Type::Infer => false,
+        _ => true,
+    }
+}
+
+/// The name of a builtin type with native methods.
+fn native_type(t : &Type) -> Option<&str> {
+    match t {
+        Type::Simple(name) | Type::Indexed(name, _) => NATIVE_TYPES.iter().find(|n| *n == name).copied(),
+        Type::Array { .. } => Some("List"),
+        Type::Tuple(_) => Some("Tuple"),
+        _ => None,
+    }
+}
+
+/// The type of the items of a list or the values of a dict.
+fn element_type(t : &Type) -> Type {
+    match t {
+        Type::Indexed(name, ts) if name == "List" => ts.first().cloned().unwrap_or(Type::Infer),
+        Type::Indexed(name, ts) if name == "Dict" => ts.get(1).cloned().unwrap_or(Type::Infer),
+        Type::Array { element, .. } => (**element).clone(),
+        _ => Type::Infer,
+    }
+}
+
+impl<'a, 'b> Mutability<'a, 'b> {
+    fn check_fun(&mut self, fun_def : &FunDef, outer_params : &[TypeParam], self_type : Option<&Type>) {
+        let mut self_binding = HashMap::new();
+        if let Some(t) = self_type {
+            self_binding.insert("Self".to_string(), t.clone());
+        }
+
+        self.scope = outer_params.iter()
+                                 .chain(fun_def.sig.type_params.iter())
+                                 .map(|tp| TypeParam { name: tp.name.clone(), constraints: tp.constraints.clone() })
+                                 .collect();
+
+        for p in &fun_def.sig.params {
+            self.bindings.push(Binding { name: p.name.clone()
+                                       , binding_type: substitute(&p.param_type, &self_binding)
+                                       , mutable: p.mutable
+                                       , mutated: false
+                                       , declared: p.meta
+                                       });
+        }
+
+        self.check_expr(&fun_def.body);
+        self.pop_bindings(0);
+    }
+
+    fn pop_bindings(&mut self, depth : usize) {
+        for binding in self.bindings.drain(depth..) {
+            if binding.mutable && !binding.mutated {
+                self.warnings.push(CheckWarning::UnusedMut { name: binding.name, declared: binding.declared });
+            }
+        }
+    }
+
+    fn write(&mut self, name : &str, write : Meta, method : Option<&str>) {
+        let binding = match self.bindings.iter_mut().rev().find(|b| b.name == name) {
+            Some(b) => b,
+            None => return,
+        };
+
+        if binding.mutable {
+            binding.mutated = true;
+            return;
+        }
+
+        let declared = binding.declared;
+        match method {
+            Some(method) => self.errors.push(CheckError::MutatingCallOnImmutable { name: name.to_string()
+                                                                                 , method: method.to_string()
+                                                                                 , declared
+                                                                                 , write
+                                                                                 }),
+            None => self.errors.push(CheckError::AssignToImmutable { name: name.to_string(), declared, write }),
+        }
+    }
+
+    /// Gives the place `expr` to the mutable binding `alias`, which can change
+    /// what it holds when it is shared.
+    fn alias(&mut self, expr : &Expr, alias : &str, write : Meta) {
+        let name = match root_variable(expr) {
+            Some(name) => name,
+            None => return,
+        };
+        if !is_shared(&self.type_of(expr), &self.scope) {
+            return;
+        }
+        let binding = match self.bindings.iter_mut().rev().find(|b| &b.name == name) {
+            Some(b) => b,
+            None => return,
+        };
+
+        if binding.mutable {
+            binding.mutated = true;
+        }
+        else {
+            let declared = binding.declared;
+            self.errors.push(CheckError::MutableAliasOfImmutable { name: name.clone(), alias: alias.to_string(), declared, write });
+        }
+    }
+
+    /// The parameters of what a call calls, when it is a function of the module.
+    fn called_params(&self, fun : &Expr) -> Option<&'a [Param]> {
+        let module = self.module;
+        match fun {
+            Expr::Variable(f) if !self.bindings.iter().any(|b| &b.name == f) =>
+                module.fun_defs.iter().find(|d| &d.sig.name == f).map(|d| &d.sig.params[..]),
+            Expr::Namespace(path, name) => {
+                let owner_type = Type::Simple(path.last()?.clone());
+                module.impl_defs.iter()
+                                .filter(|i| i.impl_type == owner_type)
+                                .flat_map(|i| &i.items)
+                                .find_map(|item| match item {
+                                    ImplItem::Fun(f) if &f.sig.name == name => Some(&f.sig.params[..]),
+                                    _ => None,
+                                })
+            },
+            _ => None,
+        }
+    }
+
+    /// The parameters after `self` of the method a call finds.
+    fn method_params(&self, receiver : &Expr, name : &str, meta : Meta) -> Vec<Param> {
+        let params = match self.env.resolve_method(&self.type_of(receiver), name, &self.scope, meta) {
+            Ok(MethodTarget::Impl { fun_def, .. }) => fun_def.sig.params.clone(),
+            Ok(MethodTarget::Bound { sig, .. }) => sig.params.clone(),
+            Err(_) => vec![],
+        };
+        params.into_iter().skip(1).collect()
+    }
+
+    fn method_takes_mut_self(&self, receiver : &Expr, name : &str, meta : Meta) -> bool {
+        let receiver_type = self.type_of(receiver);
+        if matches!( receiver_type, Type::Infer ) {
+            return self.every_method_takes_mut_self(name);
+        }
+
+        match self.env.resolve_method(&receiver_type, name, &self.scope, meta) {
+            Ok(MethodTarget::Impl { fun_def, .. }) => fun_def.sig.params.first().is_some_and(|p| p.mutable),
+            Ok(MethodTarget::Bound { sig, .. }) => sig.params.first().is_some_and(|p| p.mutable),
+            Err(_) => native_type(&receiver_type).is_some_and(|t| native_method_mutates(t, name)),
+        }
+    }
+
+    /// Whether a method called `name` takes `mut self` whatever it is called on.
+    fn every_method_takes_mut_self(&self, name : &str) -> bool {
+        let methods = self.module.impl_defs.iter().flat_map(|i| &i.items).filter_map(|item| match item {
+            ImplItem::Fun(fun_def) if fun_def.sig.name == name => Some(fun_def.sig.params.first().is_some_and(|p| p.mutable)),
+            _ => None,
+        });
+        let natives = NATIVE_TYPES.iter().filter(|t| has_native_method(t, name)).map(|t| native_method_mutates(t, name));
+        let mutates = methods.chain(natives).collect::<Vec<_>>();
+        !mutates.is_empty() && mutates.iter().all(|m| *m)
+    }
+
+    /// The type of a place, as far as the bindings tell.
+    fn type_of(&self, expr : &Expr) -> Type {
+        match expr {
+            Expr::Variable(var) => match self.bindings.iter().rev().find(|b| &b.name == var) {
+                Some(b) => b.binding_type.clone(),
+                None => Type::Infer,
+            },
+            Expr::Dot { expr, name } => {
+                let struct_def = match self.type_of(expr) {
+                    Type::Simple(s) | Type::Indexed(s, _) => self.module.struct_defs.iter().find(|d| d.name == s),
+                    _ => None,
+                };
+                struct_def.and_then(|d| d.fields.iter().find(|f| &f.name == name)).map_or(Type::Infer, |f| f.field_type.clone())
+            },
+            Expr::Index { expr, .. } => element_type(&self.type_of(expr)),
+            _ => Type::Infer,
+        }
+    }
+
+    /// The type of what a binding declared without one is initialized with,
+    /// as far as that is plain from the expression.
+    fn infer(&self, expr : &Expr) -> Type {
+        let simple = |name : &str| self.named_type(name);
+        match expr {
+            Expr::Number(n) if n.contains(['.', 'e', 'E']) => simple("Float"),
+            Expr::Number(_) => simple("Int"),
+            Expr::DString(_) => simple("String"),
+            Expr::Bool(_) => simple("Bool"),
+            Expr::List(exprs) => Type::Indexed("List".to_string(), vec![exprs.first().map_or(Type::Infer, |e| self.infer(e))]),
+            Expr::Tuple(exprs) => Type::Tuple(exprs.iter().map(|e| self.infer(e)).collect()),
+            Expr::Dict(_) => Type::Indexed("Dict".to_string(), vec![Type::Infer, Type::Infer]),
+            Expr::Struct { namespace, name, .. } => simple(namespace.last().unwrap_or(name)),
+            Expr::Variable(_) | Expr::Dot { .. } | Expr::Index { .. } => self.type_of(expr),
+            Expr::Call { fun, .. } => match &**fun {
+                Expr::Variable(f) if !self.bindings.iter().any(|b| &b.name == f) => match self.module.fun_defs.iter().find(|d| &d.sig.name == f) {
+                    // a generator gives an iterator whatever it returns
+                    Some(d) if contains_yield(&d.body) => Type::Infer,
+                    Some(d) => d.sig.return_type.clone(),
+                    None => Type::Infer,
+                },
+                Expr::Namespace(path, name) => match path.last() {
+                    Some(owner) if self.module.enum_defs.iter().any(|e| &e.name == owner) => simple(owner),
+                    Some(owner) => self.associated_fun_type(owner, name),
+                    None => Type::Infer,
+                },
+                _ => Type::Infer,
+            },
+            Expr::Namespace(path, _) if path.last().is_some_and(|owner| self.module.enum_defs.iter().any(|e| &e.name == owner)) =>
+                simple(path.last().unwrap()),
+            _ => Type::Infer,
+        }
+    }
+
+    /// The type called `name`, with its type arguments, if it has any, not known.
+    fn named_type(&self, name : &str) -> Type {
+        let type_params = self.module.struct_defs.iter().find(|d| d.name == name).map(|d| d.type_params.len())
+                              .or_else(|| self.module.enum_defs.iter().find(|d| d.name == name).map(|d| d.type_params.len()))
+                              .unwrap_or(0);
+        match type_params {
+            0 => Type::Simple(name.to_string()),
+            n => Type::Indexed(name.to_string(), vec![Type::Infer; n]),
+        }
+    }
+
+    /// What `owner::name(..)` gives, when `owner` has one inherent function of that name.
+    fn associated_fun_type(&self, owner : &str, name : &str) -> Type {
+        let owner_type = Type::Simple(owner.to_string());
+        let fun_def = self.module.impl_defs.iter()
+                                           .filter(|i| i.trait_type.is_none() && i.impl_type == owner_type)
+                                           .flat_map(|i| &i.items)
+                                           .find_map(|item| match item {
+                                               ImplItem::Fun(f) if f.sig.name == name => Some(f),
+                                               _ => None,
+                                           });
+        let mut self_binding = HashMap::new();
+        self_binding.insert("Self".to_string(), owner_type.clone());
+        fun_def.map_or(Type::Infer, |f| substitute(&f.sig.return_type, &self_binding))
+    }
+
+    fn check_expr(&mut self, expr : &Expr) {
+        match expr {
+            Expr::Number(_) | Expr::DString(_) | Expr::Unit | Expr::Variable(_) | Expr::Namespace(_, _) => (),
+            Expr::Tuple(exprs) => {
+                for e in exprs {
+                    self.check_expr(e);
+                }
+            },
+            Expr::Block(exprs) => {
+                let depth = self.bindings.len();
+                for e in exprs {
+                    self.check_expr(e);
+                }
+                self.pop_bindings(depth);
+            },
+            Expr::Call { fun, args, meta } => {
+                self.check_expr(fun);
+                for e in args {
+                    self.check_expr(e);
+                }
+                for (arg, param) in args.iter().zip(self.called_params(fun).unwrap_or(&[])) {
+                    if param.mutable {
+                        self.alias(arg, &param.name, *meta);
+                    }
+                }
+            },
+            Expr::Dot { expr, .. } => self.check_expr(expr),
+            Expr::MethodCall { receiver, name, args, meta } => {
+                self.check_expr(receiver);
+                for e in args {
+                    self.check_expr(e);
+                }
+
+                if self.method_takes_mut_self(receiver, name, *meta) {
+                    if let Some(var) = root_variable(receiver) {
+                        self.write(var, *meta, Some(name));
+                    }
+                }
+                for (arg, param) in args.iter().zip(self.method_params(receiver, name, *meta)) {
+                    if param.mutable {
+                        self.alias(arg, &param.name, *meta);
+                    }
+                }
+            },
+            Expr::Let { name, mutable, let_type, value, meta } => {
+                self.check_expr(value);
+                if *mutable {
+                    self.alias(value, name, *meta);
+                }
+                let binding_type = match let_type {
+                    Type::Infer => self.infer(value),
+                    t => t.clone(),
+                };
+                self.bindings.push(Binding { name: name.clone()
+                                           , binding_type
+                                           , mutable: *mutable
+                                           , mutated: false
+                                           , declared: *meta
+                                           });
+            },
+            Expr::Assign { target, value, meta } => {
+                self.check_expr(value);
+                match &**target {
+                    Expr::Variable(_) | Expr::Dot { .. } | Expr::Index { .. } => {
+                        self.check_expr(target);
+                        if let Some(var) = root_variable(target) {
+                            self.write(var, *meta, None);
+                        }
+                    },
+                    _ => self.errors.push(CheckError::InvalidAssignTarget { write: *meta }),
+                }
+            },
+            Expr::Return(e) | Expr::Yield(e) | Expr::Try(e) | Expr::Propagate(e) | Expr::Panic { message: e, .. } => self.check_expr(e),
+            Expr::Assert { condition, message, .. } => {
+                self.check_expr(condition);
+                if let Some(message) = message {
+                    self.check_expr(message);
+                }
+            },
+            Expr::Bool(_) | Expr::Break | Expr::Continue => (),
+            Expr::List(exprs) => {
+                for e in exprs {
+                    self.check_expr(e);
+                }
+            },
+            Expr::Dict(pairs) => {
+                for (k, v) in pairs {
+                    self.check_expr(k);
+                    self.check_expr(v);
+                }
+            },
+            Expr::Struct { fields, .. } => {
+                for (_, e) in fields {
+                    self.check_expr(e);
+                }
+            },
+            Expr::Lambda { params, body } => {
+                let depth = self.bindings.len();
+                for p in params {
+                    self.bindings.push(Binding { name: p.name.clone()
+                                               , binding_type: p.param_type.clone()
+                                               , mutable: p.mutable
+                                               , mutated: false
+                                               , declared: p.meta
+                                               });
+                }
+                self.check_expr(body);
+                self.pop_bindings(depth);
+            },
+            Expr::Index { expr, index } => {
+                self.check_expr(expr);
+                self.check_expr(index);
+            },
+            Expr::Range { start, end, .. } => {
+                for e in start.iter().chain(end) {
+                    self.check_expr(e);
+                }
+            },
+            Expr::Unary { expr, .. } => self.check_expr(expr),
+            Expr::Binary { left, right, .. } => {
+                self.check_expr(left);
+                self.check_expr(right);
+            },
+            Expr::If { condition, then, otherwise } => {
+                self.check_expr(condition);
+                self.check_expr(then);
+                self.check_expr(otherwise);
+            },
+            Expr::While { condition, body } => {
+                self.check_expr(condition);
+                self.check_expr(body);
+            },
+            Expr::Foreach { name, iterable, body, meta } => {
+                self.check_expr(iterable);
+                let depth = self.bindings.len();
+                self.bindings.push(Binding { name: name.clone()
+                                           , binding_type: element_type(&self.infer(iterable))
+                                           , mutable: false
+                                           , mutated: false
+                                           , declared: *meta
+                                           });
+                self.check_expr(body);
+                self.pop_bindings(depth);
+            },
+            Expr::Match { expr, arms } => {
+                self.check_expr(expr);
+                for arm in arms {
+                    let depth = self.bindings.len();
+                    for name in pattern_variables(&arm.pattern) {
+                        self.bindings.push(Binding { name
+                                                   , binding_type: Type::Infer
+                                                   , mutable: false
+                                                   , mutated: false
+                                                   , declared: arm.meta
+                                                   });
+                    }
+                    self.check_expr(&arm.body);
+                    self.pop_bindings(depth);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+
+    fn check_src(src : &str) -> (Vec<CheckError>, Vec<CheckWarning>) {
+        let module = parse(src).unwrap();
+        let env = TraitEnv::new(&module);
+        check_mutability(&env, &module)
+    }
+
+    #[test]
+    fn should_reject_assignment_to_immutable_param() {
+        let src = "fun f(x : Int) { x = 5; }";
+        let (errors, warnings) = check_src(src);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( warnings.is_empty() );
+        match &errors[0] {
+            CheckError::AssignToImmutable { name, declared, write } => {
+                assert_eq!( name, "x" );
+                assert_eq!( &src[declared.start..declared.end], "x : Int" );
+                assert_eq!( &src[write.start..write.end], "x = 5" );
+            },
+            x => panic!( "Expected AssignToImmutable but found {:?}", x ),
+        }
+    }
+
+    #[test]
+    fn should_reject_assignment_to_immutable_let() {
+        let src = "fun f() { let x = 1; x.field = 5; }";
+        let (errors, _) = check_src(src);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        match &errors[0] {
+            CheckError::AssignToImmutable { name, declared, write } => {
+                assert_eq!( name, "x" );
+                assert_eq!( &src[declared.start..declared.end], "let x = 1" );
+                assert_eq!( &src[write.start..write.end], "x.field = 5" );
+            },
+            x => panic!( "Expected AssignToImmutable but found {:?}", x ),
+        }
+    }
+
+    #[test]
+    fn should_allow_assignment_to_mut_bindings() {
+        let (errors, warnings) = check_src("fun f(mut x : Int) { let mut y = 1; x = 2; y = x; }");
+        assert!( errors.is_empty(), "{:?}", errors );
+        assert!( warnings.is_empty(), "{:?}", warnings );
+    }
+
+    #[test]
+    fn should_reject_assignment_to_match_binding() {
+        let src = "fun f(o : Int) { match o { n => { n = 1 } } }";
+        let (errors, _) = check_src(src);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        match &errors[0] {
+            CheckError::AssignToImmutable { name, declared, .. } => {
+                assert_eq!( name, "n" );
+                assert_eq!( &src[declared.start..declared.end], "n" );
+            },
+            x => panic!( "Expected AssignToImmutable but found {:?}", x ),
+        }
+    }
+
+    #[test]
+    fn should_reject_mutating_method_call_through_immutable_binding() {
+        let src = r#"
+struct Counter { n : Int }
+impl Counter {
+    fun bump(mut self : Self) { self.n = self.n; }
+    fun get(self : Self) -> Int { self.n }
+}
+fun f(c : Counter, mut d : Counter) {
+    c.get();
+    c.bump();
+    d.bump();
+}
+"#;
+        let (errors, warnings) = check_src(src);
+        assert!( warnings.is_empty(), "{:?}", warnings );
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        match &errors[0] {
+            CheckError::MutatingCallOnImmutable { name, method, declared, write } => {
+                assert_eq!( name, "c" );
+                assert_eq!( method, "bump" );
+                assert_eq!( &src[declared.start..declared.end], "c : Counter" );
+                assert_eq!( &src[write.start..write.end], "c.bump()" );
+            },
+            x => panic!( "Expected MutatingCallOnImmutable but found {:?}", x ),
+        }
+    }
+
+    #[test]
+    fn should_reject_mutating_trait_method_through_immutable_generic_binding() {
+        let (errors, _) = check_src(r#"
+trait Push { fun push(mut self : Self, x : Int); }
+fun f<T : Push>(t : T) { t.push(1); }
+"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::MutatingCallOnImmutable { name, .. } if name == "t" ) );
+    }
+
+    #[test]
+    fn should_infer_the_type_of_a_binding_from_its_value() {
+        let src = r#"
+struct P { x : Int }
+impl P {
+    fun new() -> Self { P { x : 0 } }
+    fun bump(mut self : Self) { self.x = self.x + 1; }
+}
+fun f() {
+    let p = P { x : 1 };
+    p.bump();
+    let q = P::new();
+    q.bump();
+    let mut r = p;
+    r.bump();
+}
+"#;
+        let (errors, warnings) = check_src(src);
+        assert!( warnings.is_empty(), "{:?}", warnings );
+        assert_eq!( errors.len(), 3, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::MutatingCallOnImmutable { name, method, .. } if name == "p" && method == "bump" ) );
+        assert!( matches!( &errors[1], CheckError::MutatingCallOnImmutable { name, .. } if name == "q" ) );
+        assert!( matches!( &errors[2], CheckError::MutableAliasOfImmutable { name, alias, .. } if name == "p" && alias == "r" ) );
+    }
+
+    #[test]
+    fn should_reject_passing_immutable_bindings_to_mut_params() {
+        let src = r#"
+struct P { x : Int }
+impl P {
+    fun bump(mut self : Self) { self.x = self.x + 1; }
+    fun reset(mut p : P) { p.x = 0; }
+    fun take(mut self : Self, mut other : P) { other.bump(); self.bump(); }
+}
+fun bump(mut p : P) { p.bump(); }
+fun inc(mut n : Int) { n = n + 1; }
+fun f(p : P, mut q : P, n : Int) {
+    bump(p);
+    P::reset(p);
+    q.take(p);
+    bump(q);
+    inc(n);
+}
+"#;
+        let (errors, warnings) = check_src(src);
+        assert!( warnings.is_empty(), "{:?}", warnings );
+        assert_eq!( errors.len(), 3, "{:?}", errors );
+        match &errors[0] {
+            CheckError::MutableAliasOfImmutable { name, alias, declared, write } => {
+                assert_eq!( (name.as_str(), alias.as_str()), ("p", "p") );
+                assert_eq!( &src[declared.start..declared.end], "p : P" );
+                assert_eq!( &src[write.start..write.end], "bump(p)" );
+            },
+            x => panic!( "Expected MutableAliasOfImmutable but found {:?}", x ),
+        }
+        assert!( matches!( &errors[1], CheckError::MutableAliasOfImmutable { write, .. } if &src[write.start..write.end] == "P::reset(p)" ) );
+        assert!( matches!( &errors[2], CheckError::MutableAliasOfImmutable { alias, .. } if alias == "other" ) );
+    }
+
+    #[test]
+    fn should_reject_binding_immutable_bindings_again_as_mut() {
+        let src = r#"
+struct P { x : Int }
+impl P { fun bump(mut self : Self) { self.x = self.x + 1; } }
+fun f(p : P) {
+    let q = p;
+    let mut r = q;
+    r.bump();
+    let n = 1;
+    let mut m = n;
+    m = 2;
+    let mut s = P { x : 0 };
+    let mut t = s;
+    t.bump();
+}
+"#;
+        let (errors, warnings) = check_src(src);
+        assert!( warnings.is_empty(), "{:?}", warnings );
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        match &errors[0] {
+            CheckError::MutableAliasOfImmutable { name, alias, write, .. } => {
+                assert_eq!( (name.as_str(), alias.as_str()), ("q", "r") );
+                assert_eq!( &src[write.start..write.end], "let mut r = q" );
+            },
+            x => panic!( "Expected MutableAliasOfImmutable but found {:?}", x ),
+        }
+    }
+
+    #[test]
+    fn should_reject_mutating_native_methods_through_immutable_bindings() {
+        let (errors, _) = check_src(r#"
+struct Bag { items : List<Int> }
+fun f(bag : Bag, d : Dict<String, Int>) {
+    let xs = [1];
+    xs.push(2);
+    xs.len();
+    d.insert("a", 1);
+    d.keys();
+    bag.items.sort();
+}
+"#);
+        assert_eq!( errors.len(), 3, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::MutatingCallOnImmutable { name, method, .. } if name == "xs" && method == "push" ) );
+        assert!( matches!( &errors[1], CheckError::MutatingCallOnImmutable { name, method, .. } if name == "d" && method == "insert" ) );
+        assert!( matches!( &errors[2], CheckError::MutatingCallOnImmutable { name, method, .. } if name == "bag" && method == "sort" ) );
+    }
+
+    #[test]
+    fn should_count_mutating_native_methods_as_using_mut() {
+        let (errors, warnings) = check_src("fun f() { let mut xs = [1]; xs.push(2); let mut ys = []; foreach y in ys { } }");
+        assert!( errors.is_empty(), "{:?}", errors );
+        assert_eq!( warnings.len(), 1, "{:?}", warnings );
+        assert!( matches!( &warnings[0], CheckWarning::UnusedMut { name, .. } if name == "ys" ) );
+    }
+
+    #[test]
+    fn should_warn_about_unused_mut() {
+        let src = "fun f(mut x : Int) { let mut y = x; let mut z = 1; z = 2; }";
+        let (errors, warnings) = check_src(src);
+        assert!( errors.is_empty(), "{:?}", errors );
+        assert_eq!( warnings.len(), 2, "{:?}", warnings );
+
+        let names = warnings.iter().map(|w| match w {
+            CheckWarning::UnusedMut { name, declared } => (name.clone(), src[declared.start..declared.end].to_string()),
+        }).collect::<Vec<_>>();
+
+        assert!( names.contains(&("x".to_string(), "mut x : Int".to_string())) );
+        assert!( names.contains(&("y".to_string(), "let mut y = x".to_string())) );
+    }
+
+    #[test]
+    fn should_check_shadowed_bindings_separately() {
+        let (errors, warnings) = check_src("fun f() { let mut x = 1; x = 2; let x = 3; x = 4; }");
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( warnings.is_empty(), "{:?}", warnings );
+    }
+
+    #[test]
+    fn should_reject_assignment_to_non_place() {
+        let (errors, _) = check_src("fun f() { g() = 4; }");
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( errors[0], CheckError::InvalidAssignTarget { .. } ) );
+    }
+}
@@ -0,0 +1,705 @@
+
+use std::cell::Cell;
+use std::collections::HashMap;
+
+use crate::parsing::ast::*;
+use super::check_error::CheckError;
+use super::trait_solver::{TraitEnv, MethodTarget, declares_own, substitute, trait_name};
+use super::checker::pattern_variables;
+
+/* An `own` item in a trait declares an owned associated type.  Every impl of
+   the trait has to supply it with `own Name = Type;`.  Values whose declared
+   type is an owned projection (`Self::Name` inside of an impl of the trait,
+   `T::Name` where `T` is constrained by the trait, or `Type::Name` where `Type`
+   implements the trait) have move semantics:  passing them to a function or
+   method, binding them with let, returning them, matching on them, or putting
+   them in a tuple moves the value and any later use of the moved variable is
+   an error.  Reading a field does not move the value and assigning a new value
+   to the variable makes it usable again.
+
+   After an `if` or a `match` a value is moved when any branch which carries on
+   past it moved it; a branch which returns, breaks, continues or panics does
+   not.  The body of a loop is checked as it runs the first time and then
+   again as it runs after that, with what the first time moved, so a value
+   moved in one iteration cannot be used in the next.
+*/
+
+struct Local {
+    name : String,
+    local_type : Type,
+    owned : bool,
+    moved : bool,
+}
+
+struct Context<'a> {
+    function : &'a str,
+    scope : Vec<TypeParam>,
+    self_trait : Option<&'a TraitDef>,
+    /// The innermost expression being checked which knows where it is, or the function.
+    at : Cell<Meta>,
+}
+
+pub fn check_ownership( env : &TraitEnv, module : &Module ) -> Vec<CheckError> {
+    let mut errors = vec![];
+
+    for fun_def in &module.fun_defs {
+        let context = Context { function: &fun_def.sig.name, scope: copy_params(&fun_def.sig.type_params), self_trait: None, at: Cell::new(fun_def.meta) };
+        check_fun(env, module, &context, fun_def, None, &mut errors);
+    }
+
+    for impl_def in &module.impl_defs {
+        let self_trait = trait_name(impl_def).and_then(|n| env.trait_def(&n));
+        for item in &impl_def.items {
+            if let ImplItem::Fun(fun_def) = item {
+                let mut scope = copy_params(&impl_def.type_params);
+                scope.extend(copy_params(&fun_def.sig.type_params));
+                let context = Context { function: &fun_def.sig.name, scope, self_trait, at: Cell::new(fun_def.meta) };
+                check_fun(env, module, &context, fun_def, Some(&impl_def.impl_type), &mut errors);
+            }
+        }
+    }
+
+    errors
+}
+
+fn copy_params(params : &[TypeParam]) -> Vec<TypeParam> {
+    params.iter()
+          .map(|tp| TypeParam { name: tp.name.clone(), constraints: tp.constraints.clone() })
+          .collect()
+}
+
+fn is_owned_type(env : &TraitEnv, t : &Type, scope : &[TypeParam], self_trait : Option<&TraitDef>) -> bool {
+    let (owner, item) = match t {
+        Type::Namespace(names, item) if names.len() == 1 => match &**item {
+            Type::Simple(item) => (&names[0], item),
+            _ => return false,
+        },
+        _ => return false,
+    };
+
+    if owner == "Self" {
+        return self_trait.is_some_and(|t| declares_own(t, item));
+    }
+
+    if scope.iter().any(|tp| &tp.name == owner) {
+        return scope.iter()
+                    .filter(|tp| &tp.name == owner)
+                    .flat_map(|tp| tp.constraints.iter())
+                    .filter_map(|c| env.trait_def(c))
+                    .any(|t| declares_own(t, item));
+    }
+
+    env.traits_declaring_own(item)
+       .iter()
+       .any(|t| env.implements(&Type::Simple(owner.clone()), &t.name, scope))
+}
+
+fn check_fun(env : &TraitEnv, module : &Module, context : &Context, fun_def : &FunDef, self_type : Option<&Type>, errors : &mut Vec<CheckError>) {
+    let mut self_binding = HashMap::new();
+    if let Some(t) = self_type {
+        self_binding.insert("Self".to_string(), t.clone());
+    }
+
+    let mut locals = vec![];
+    for p in &fun_def.sig.params {
+        let owned = is_owned_type(env, &p.param_type, &context.scope, context.self_trait);
+        locals.push(Local { name: p.name.clone()
+                          , local_type: substitute(&p.param_type, &self_binding)
+                          , owned
+                          , moved: false
+                          });
+    }
+
+    check_expr(env, module, context, &fun_def.body, true, &mut locals, errors);
+}
+
+fn check_expr(env : &TraitEnv, module : &Module, context : &Context, expr : &Expr, moving : bool, locals : &mut Vec<Local>, errors : &mut Vec<CheckError>) -> bool {
+    let outer = context.at.get();
+    if let Some(meta) = expr.meta() {
+        context.at.set(meta);
+    }
+    let owned = check_kind(env, module, context, expr, moving, locals, errors);
+    context.at.set(outer);
+    owned
+}
+
+fn check_kind(env : &TraitEnv, module : &Module, context : &Context, expr : &Expr, moving : bool, locals : &mut Vec<Local>, errors : &mut Vec<CheckError>) -> bool {
+    match expr {
+        Expr::Number(_) | Expr::DString(_) | Expr::Unit | Expr::Namespace(_, _) => false,
+        Expr::Variable(name) => {
+            match locals.iter_mut().rev().find(|l| &l.name == name) {
+                Some(local) if local.moved => {
+                    errors.push(CheckError::UseAfterMove { name: name.clone(), function: context.function.to_string(), meta: context.at.get() });
+                    false
+                },
+                Some(local) => {
+                    if moving && local.owned {
+                        local.moved = true;
+                    }
+                    local.owned
+                },
+                None => false,
+            }
+        },
+        Expr::Tuple(exprs) => {
+            let mut owned = false;
+            for e in exprs {
+                owned |= check_expr(env, module, context, e, true, locals, errors);
+            }
+            owned
+        },
+        Expr::Block(exprs) => {
+            let depth = locals.len();
+            let mut owned = false;
+            for e in exprs {
+                owned = check_expr(env, module, context, e, moving, locals, errors);
+            }
+            locals.truncate(depth);
+            owned
+        },
+        Expr::Call { fun, args, .. } => {
+            check_expr(env, module, context, fun, false, locals, errors);
+            for e in args {
+                check_expr(env, module, context, e, true, locals, errors);
+            }
+
+            match &**fun {
+                Expr::Variable(name) => {
+                    match module.fun_defs.iter().find(|f| &f.sig.name == name) {
+                        Some(f) => is_owned_type(env, &f.sig.return_type, &f.sig.type_params, None),
+                        None => false,
+                    }
+                },
+                _ => false,
+            }
+        },
+        Expr::Dot { expr, .. } => {
+            check_expr(env, module, context, expr, false, locals, errors);
+            false
+        },
+        Expr::Assign { target, value, .. } => {
+            check_expr(env, module, context, value, true, locals, errors);
+            match &**target {
+                Expr::Variable(name) => {
+                    if let Some(local) = locals.iter_mut().rev().find(|l| &l.name == name) {
+                        local.moved = false;
+                    }
+                },
+                _ => {
+                    check_expr(env, module, context, target, false, locals, errors);
+                },
+            }
+            false
+        },
+        Expr::MethodCall { receiver, name, args, meta } => {
+            let receiver_type = match &**receiver {
+                Expr::Variable(var) => locals.iter().rev().find(|l| &l.name == var).map(|l| l.local_type.clone()),
+                _ => None,
+            };
+
+            check_expr(env, module, context, receiver, true, locals, errors);
+            for e in args {
+                check_expr(env, module, context, e, true, locals, errors);
+            }
+
+            match receiver_type {
+                Some(Type::Infer) | None => false,
+                Some(t) => match env.resolve_method(&t, name, &context.scope, *meta) {
+                    Ok(MethodTarget::Impl { impl_def, fun_def, .. }) => {
+                        let mut scope = copy_params(&impl_def.type_params);
+                        scope.extend(copy_params(&fun_def.sig.type_params));
+                        let self_trait = trait_name(impl_def).and_then(|n| env.trait_def(&n));
+                        is_owned_type(env, &fun_def.sig.return_type, &scope, self_trait)
+                    },
+                    Ok(MethodTarget::Bound { trait_def, sig }) =>
+                        is_owned_type(env, &sig.return_type, &sig.type_params, Some(trait_def)),
+                    Err(_) => false,
+                },
+            }
+        },
+        Expr::Let { name, let_type, value, .. } => {
+            let owned = check_expr(env, module, context, value, true, locals, errors)
+                     || is_owned_type(env, let_type, &context.scope, context.self_trait);
+            locals.push(Local { name: name.clone(), local_type: let_type.clone(), owned, moved: false });
+            false
+        },
+        Expr::Return(e) | Expr::Yield(e) => {
+            check_expr(env, module, context, e, true, locals, errors);
+            false
+        },
+        Expr::Try(e) | Expr::Propagate(e) => check_expr(env, module, context, e, moving, locals, errors),
+        Expr::Assert { condition, message, .. } => {
+            check_expr(env, module, context, condition, false, locals, errors);
+            if let Some(message) = message {
+                check_expr(env, module, context, message, false, locals, errors);
+            }
+            false
+        },
+        Expr::Panic { message, .. } => {
+            check_expr(env, module, context, message, false, locals, errors);
+            false
+        },
+        Expr::Bool(_) | Expr::Break | Expr::Continue => false,
+        Expr::List(exprs) => {
+            let mut owned = false;
+            for e in exprs {
+                owned |= check_expr(env, module, context, e, true, locals, errors);
+            }
+            owned
+        },
+        Expr::Dict(pairs) => {
+            let mut owned = false;
+            for (k, v) in pairs {
+                owned |= check_expr(env, module, context, k, true, locals, errors);
+                owned |= check_expr(env, module, context, v, true, locals, errors);
+            }
+            owned
+        },
+        Expr::Struct { fields, .. } => {
+            let mut owned = false;
+            for (_, e) in fields {
+                owned |= check_expr(env, module, context, e, true, locals, errors);
+            }
+            owned
+        },
+        Expr::Lambda { params, body } => {
+            // closures capture by value, so owned variables used in the body move into the closure
+            let depth = locals.len();
+            for p in params {
+                let owned = is_owned_type(env, &p.param_type, &context.scope, context.self_trait);
+                locals.push(Local { name: p.name.clone(), local_type: p.param_type.clone(), owned, moved: false });
+            }
+            check_expr(env, module, context, body, true, locals, errors);
+            locals.truncate(depth);
+            false
+        },
+        Expr::Index { expr, index } => {
+            check_expr(env, module, context, expr, false, locals, errors);
+            check_expr(env, module, context, index, false, locals, errors);
+            false
+        },
+        Expr::Unary { expr, .. } => {
+            check_expr(env, module, context, expr, false, locals, errors);
+            false
+        },
+        Expr::Binary { left, right, .. } => {
+            check_expr(env, module, context, left, false, locals, errors);
+            check_expr(env, module, context, right, false, locals, errors);
+            false
+        },
+        Expr::Range { start, end, .. } => {
+            for e in start.iter().chain(end) {
+                check_expr(env, module, context, e, false, locals, errors);
+            }
+            false
+        },
+        Expr::If { condition, then, otherwise } => {
+            check_expr(env, module, context, condition, false, locals, errors);
+
+            let before = locals.iter().map(|l| l.moved).collect::<Vec<_>>();
+            let mut after = vec![false; before.len()];
+            let mut owned = false;
+            for branch in [then, otherwise].iter().copied() {
+                for (local, moved) in locals.iter_mut().zip(&before) {
+                    local.moved = *moved;
+                }
+                owned |= check_expr(env, module, context, branch, moving, locals, errors);
+                if !diverges(branch) {
+                    for (moved, local) in after.iter_mut().zip(locals.iter()) {
+                        *moved |= local.moved;
+                    }
+                }
+            }
+
+            for (local, moved) in locals.iter_mut().zip(after) {
+                local.moved = moved;
+            }
+
+            owned
+        },
+        Expr::While { condition, body } => {
+            check_loop(env, module, context, Some(condition), body, locals, errors);
+            false
+        },
+        Expr::Foreach { name, iterable, body, .. } => {
+            check_expr(env, module, context, iterable, false, locals, errors);
+            let depth = locals.len();
+            locals.push(Local { name: name.clone(), local_type: Type::Infer, owned: false, moved: false });
+            check_loop(env, module, context, None, body, locals, errors);
+            locals.truncate(depth);
+            false
+        },
+        Expr::Match { expr, arms } => {
+            check_expr(env, module, context, expr, true, locals, errors);
+
+            let before = locals.iter().map(|l| l.moved).collect::<Vec<_>>();
+            let mut after = vec![false; before.len()];
+            let mut owned = false;
+
+            for arm in arms {
+                for (local, moved) in locals.iter_mut().zip(&before) {
+                    local.moved = *moved;
+                }
+
+                let depth = locals.len();
+                for name in pattern_variables(&arm.pattern) {
+                    locals.push(Local { name, local_type: Type::Infer, owned: false, moved: false });
+                }
+                owned |= check_expr(env, module, context, &arm.body, moving, locals, errors);
+                locals.truncate(depth);
+
+                if !diverges(&arm.body) {
+                    for (moved, local) in after.iter_mut().zip(locals.iter()) {
+                        *moved |= local.moved;
+                    }
+                }
+            }
+
+            for (local, moved) in locals.iter_mut().zip(after) {
+                local.moved = moved;
+            }
+
+            owned
+        },
+    }
+}
+
+/// Checks the body of a loop running the first time and then again with what
+/// the first time moved, reporting each use after a move once.  Afterwards a
+/// value is moved when it was before the loop or any run of the body moved it.
+fn check_loop(env : &TraitEnv, module : &Module, context : &Context, condition : Option<&Expr>, body : &Expr, locals : &mut Vec<Local>, errors : &mut Vec<CheckError>) {
+    let before = locals.iter().map(|l| l.moved).collect::<Vec<_>>();
+    let mut found = vec![];
+    for _ in 0..2 {
+        if let Some(condition) = condition {
+            check_expr(env, module, context, condition, false, locals, &mut found);
+        }
+        check_expr(env, module, context, body, true, locals, &mut found);
+    }
+
+    let mut reported = vec![];
+    for error in found {
+        if let CheckError::UseAfterMove { name, .. } = &error {
+            if reported.contains(name) {
+                continue;
+            }
+            reported.push(name.clone());
+        }
+        errors.push(error);
+    }
+    for (local, moved) in locals.iter_mut().zip(before) {
+        local.moved |= moved;
+    }
+}
+
+/// Whether nothing after `expr` runs once it has, because it always returns,
+/// breaks, continues or panics.
+fn diverges(expr : &Expr) -> bool {
+    match expr {
+        Expr::Return(_) | Expr::Break | Expr::Continue | Expr::Panic { .. } => true,
+        Expr::Block(exprs) => exprs.iter().any(diverges),
+        Expr::If { condition, then, otherwise } => diverges(condition) || (diverges(then) && diverges(otherwise)),
+        Expr::Match { expr, arms } => diverges(expr) || (!arms.is_empty() && arms.iter().all(|arm| diverges(&arm.body))),
+        Expr::Let { value, .. } => diverges(value),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+
+    const STORE : &str = r#"
+trait Store {
+    own Buf : Sized;
+    fun take(self : Self) -> Self::Buf;
+    fun give(self : Self, buf : Self::Buf);
+    fun peek(self : Self, buf : Self::Buf) -> Int;
+}
+
+trait Sized { }
+
+struct Bytes { len : Int }
+struct Disk { }
+
+impl Sized for Bytes { }
+
+impl Store for Disk {
+    own Buf = Bytes;
+    fun take(self : Self) -> Self::Buf { self.buf }
+    fun give(self : Self, buf : Self::Buf) { }
+    fun peek(self : Self, buf : Self::Buf) -> Int { buf.len }
+}
+"#;
+
+    fn errors_for(src : &str) -> Vec<CheckError> {
+        let module = parse(&format!("{}{}", STORE, src)).unwrap();
+        let env = TraitEnv::new(&module);
+        let mut errors = env.check();
+        errors.append(&mut check_ownership(&env, &module));
+        errors
+    }
+
+    #[test]
+    fn should_accept_impl_supplying_own_item() {
+        let errors = errors_for("");
+        assert!( errors.is_empty(), "{:?}", errors );
+    }
+
+    #[test]
+    fn should_report_missing_own_item() {
+        let errors = errors_for(r#"
+struct Tape { }
+impl Store for Tape {
+    fun take(self : Self) -> Self::Buf { self }
+    fun give(self : Self, buf : Self::Buf) { }
+    fun peek(self : Self, buf : Self::Buf) -> Int { 0 }
+}
+"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::MissingTraitItem { item, .. } if item == "Buf" ) );
+    }
+
+    #[test]
+    fn should_report_type_item_supplied_for_own_item() {
+        let errors = errors_for(r#"
+struct Tape { }
+impl Store for Tape {
+    type Buf = Bytes;
+    fun take(self : Self) -> Bytes { self }
+    fun give(self : Self, buf : Bytes) { }
+    fun peek(self : Self, buf : Bytes) -> Int { 0 }
+}
+"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::ItemKindMismatch { item, expected_own: true, .. } if item == "Buf" ) );
+    }
+
+    #[test]
+    fn should_report_unsatisfied_own_constraint() {
+        let errors = errors_for(r#"
+struct Tape { }
+impl Store for Tape {
+    own Buf = Int;
+    fun take(self : Self) -> Int { self }
+    fun give(self : Self, buf : Int) { }
+    fun peek(self : Self, buf : Int) -> Int { 0 }
+}
+"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::UnsatisfiedConstraint { trait_name, .. } if trait_name == "Sized" ) );
+    }
+
+    #[test]
+    fn should_report_use_after_passing_to_trait_method() {
+        let errors = errors_for(r#"
+fun twice<S : Store>(s : S, b : S::Buf) {
+    s.give(b);
+    s.give(b);
+}
+"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::UseAfterMove { name, .. } if name == "b" ) );
+    }
+
+    #[test]
+    fn should_report_use_after_let_move() {
+        let errors = errors_for(r#"
+fun rebind<S : Store>(s : S, b : S::Buf) -> S::Buf {
+    let c = b;
+    b
+}
+"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::UseAfterMove { name, .. } if name == "b" ) );
+    }
+
+    #[test]
+    fn should_report_where_and_in_which_function_a_moved_value_is_used() {
+        let src = r#"
+fun twice<S : Store>(s : S, b : S::Buf) {
+    s.give(b);
+    s.give(b);
+}
+fun rebind<S : Store>(s : S, b : S::Buf) -> S::Buf {
+    let c = b;
+    b
+}
+"#;
+        let errors = errors_for(src);
+        let text = format!("{}{}", STORE, src);
+        assert_eq!( errors.len(), 2, "{:?}", errors );
+        match &errors[0] {
+            CheckError::UseAfterMove { name, function, meta } => {
+                assert_eq!( (name.as_str(), function.as_str()), ("b", "twice") );
+                assert_eq!( &text[meta.start..meta.end], "s.give(b)" );
+                assert_eq!( meta.start, text.rfind("s.give(b)").unwrap() );
+            },
+            x => panic!( "Expected UseAfterMove but found {:?}", x ),
+        }
+        match &errors[1] {
+            CheckError::UseAfterMove { function, meta, .. } => {
+                assert_eq!( function, "rebind" );
+                assert_eq!( meta.start, text.find("fun rebind").unwrap() );
+            },
+            x => panic!( "Expected UseAfterMove but found {:?}", x ),
+        }
+        assert_eq!( errors[0].to_string(), "b is used in twice after it was moved" );
+    }
+
+    #[test]
+    fn should_track_owned_values_returned_from_trait_methods() {
+        let errors = errors_for(r#"
+fun swap(d : Disk, e : Disk) {
+    let b = d.take();
+    e.give(b);
+    d.give(b);
+}
+"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::UseAfterMove { name, .. } if name == "b" ) );
+    }
+
+    #[test]
+    fn should_allow_field_reads_before_move_but_not_after() {
+        let errors = errors_for(r#"
+fun read<S : Store>(s : S, b : S::Buf) {
+    b.len;
+    b.len;
+    s.give(b);
+    b.len;
+}
+"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::UseAfterMove { name, .. } if name == "b" ) );
+    }
+
+    #[test]
+    fn should_report_use_after_move_inside_impl() {
+        let errors = errors_for(r#"
+struct Cache { }
+impl Store for Cache {
+    own Buf = Bytes;
+    fun take(self : Self) -> Self::Buf { self.buf }
+    fun give(self : Self, buf : Self::Buf) { let kept = (buf, buf); }
+    fun peek(self : Self, buf : Self::Buf) -> Int { buf.len }
+}
+"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::UseAfterMove { name, .. } if name == "buf" ) );
+    }
+
+    #[test]
+    fn should_report_use_after_move_in_any_match_arm() {
+        let errors = errors_for(r#"
+fun branch<S : Store>(s : S, b : S::Buf, n : Int) {
+    match n {
+        1 => s.give(b),
+        _ => 0,
+    }
+    s.give(b);
+}
+"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::UseAfterMove { name, .. } if name == "b" ) );
+    }
+
+    #[test]
+    fn should_report_move_in_one_loop_iteration_used_in_the_next() {
+        let errors = errors_for(r#"
+fun drain<S : Store>(s : S, b : S::Buf, n : Int) {
+    let mut i = 0;
+    while i < n {
+        s.give(b);
+        i = i + 1;
+    }
+}
+"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::UseAfterMove { name, .. } if name == "b" ) );
+
+        let errors = errors_for(r#"
+fun drain<S : Store>(s : S, b : S::Buf, xs : List<Int>) {
+    foreach x in xs {
+        s.give(b);
+    }
+}
+"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::UseAfterMove { name, .. } if name == "b" ) );
+
+        let errors = errors_for(r#"
+fun cycle(d : Disk, mut b : Disk::Buf, n : Int) {
+    let mut i = 0;
+    while i < n {
+        d.give(b);
+        b = d.take();
+        i = i + 1;
+    }
+    d.give(b);
+}
+"#);
+        assert!( errors.is_empty(), "{:?}", errors );
+    }
+
+    #[test]
+    fn should_ignore_moves_in_branches_that_return() {
+        let errors = errors_for(r#"
+fun once<S : Store>(s : S, b : S::Buf, n : Int) -> Int {
+    if n > 0 {
+        s.give(b);
+        return 0;
+    }
+    s.give(b);
+    1
+}
+"#);
+        assert!( errors.is_empty(), "{:?}", errors );
+
+        let errors = errors_for(r#"
+fun once<S : Store>(s : S, b : S::Buf, n : Int) -> Int {
+    match n {
+        1 => { s.give(b); return 0; },
+        _ => 0,
+    }
+    s.give(b);
+    1
+}
+"#);
+        assert!( errors.is_empty(), "{:?}", errors );
+    }
+
+    #[test]
+    fn should_allow_use_after_reassignment() {
+        let errors = errors_for(r#"
+fun refill(d : Disk, mut b : Disk::Buf) {
+    d.give(b);
+    b = d.take();
+    d.give(b);
+}
+"#);
+        assert!( errors.is_empty(), "{:?}", errors );
+
+        let errors = errors_for(r#"
+fun refill(d : Disk, b : Disk::Buf) {
+    d.give(b);
+    d.give(b);
+}
+"#);
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+    }
+
+    #[test]
+    fn should_not_move_values_of_ordinary_types() {
+        let errors = errors_for(r#"
+fun copy(x : Int, d : Disk) {
+    let y = x;
+    let z = (x, x);
+    d.peek(d.take());
+    x
+}
+"#);
+        assert!( errors.is_empty(), "{:?}", errors );
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+
+use crate::parsing::ast::*;
+use super::check_error::CheckError;
+use super::trait_solver::substitute;
+
+/* `e?` takes an `Ok` apart and sends an `Err` on unchanged, to the nearest
+   `try` block or else out of the function.  An enum is a result when it has
+   an `Ok` and an `Err` case with one field each.
+
+   Outside of a `try` the function has to return a result, and when the type
+   of `e` is known it has to be the same enum with the same error type.  Types
+   are only known from annotations, so anything else is let through.  The
+   return type of a lambda is never known.
+*/
+
+const BUILTIN_TYPES : [&str; 6] = ["Int", "Float", "Bool", "String", "List", "Dict"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Shape {
+    /// A result enum, with its error type when the type arguments give it.
+    Result { enum_name : String, error : Option<Type> },
+    NotResult,
+    Unknown,
+}
+
+struct Propagation<'a> {
+    module : &'a Module,
+    function : &'a str,
+    return_type : &'a Type,
+    returns : Shape,
+    scope : Vec<&'a str>,
+    locals : Vec<(String, Type)>,
+    tries : usize,
+    lambdas : usize,
+    /// The innermost expression being checked which knows where it is, or the function.
+    at : Meta,
+    errors : Vec<CheckError>,
+}
+
+pub fn check_propagation( module : &Module ) -> Vec<CheckError> {
+    let mut errors = vec![];
+
+    for fun_def in &module.fun_defs {
+        errors.append(&mut check_fun(module, fun_def, &[]));
+    }
+
+    for impl_def in &module.impl_defs {
+        for item in &impl_def.items {
+            if let ImplItem::Fun(fun_def) = item {
+                errors.append(&mut check_fun(module, fun_def, &impl_def.type_params));
+            }
+        }
+    }
+
+    errors
+}
+
+fn check_fun(module : &Module, fun_def : &FunDef, outer_params : &[TypeParam]) -> Vec<CheckError> {
+    let scope = outer_params.iter().chain(&fun_def.sig.type_params).map(|tp| tp.name.as_str()).collect::<Vec<_>>();
+    let mut p = Propagation { module
+                            , function: &fun_def.sig.name
+                            , return_type: &fun_def.sig.return_type
+                            , returns: Shape::Unknown
+                            , scope
+                            , locals: fun_def.sig.params.iter().map(|p| (p.name.clone(), p.param_type.clone())).collect()
+                            , tries: 0
+                            , lambdas: 0
+                            , at: fun_def.meta
+                            , errors: vec![]
+                            };
+    p.returns = p.shape(&fun_def.sig.return_type);
+    p.check_expr(&fun_def.body);
+    p.errors
+}
+
+impl<'a> Propagation<'a> {
+    fn shape(&self, t : &Type) -> Shape {
+        let (name, args) = match t {
+            Type::Simple(name) => (name, &[][..]),
+            Type::Indexed(name, args) => (name, &args[..]),
+            Type::Namespace(_, t) => return self.shape(t),
+            Type::Unit | Type::Tuple(_) | Type::Arrow { .. } | Type::Array { .. } => return Shape::NotResult,
+            Type::Infer => return Shape::Unknown,
+        };
+        if self.scope.contains(&name.as_str()) {
+            return Shape::Unknown;
+        }
+
+        if let Some(enum_def) = self.module.enum_defs.iter().find(|e| &e.name == name) {
+            let field = |case : &str| enum_def.cases.iter().find_map(|c| match c {
+                EnumCase::TypeCase { name, types } if name == case && types.len() == 1 => Some(&types[0]),
+                _ => None,
+            });
+            return match (field("Ok"), field("Err")) {
+                (Some(_), Some(error)) => {
+                    let error = match (enum_def.type_params.len(), args.len()) {
+                        (0, _) => Some(error.clone()),
+                        (expected, found) if expected == found => {
+                            let bindings = enum_def.type_params.iter().map(|tp| tp.name.clone()).zip(args.iter().cloned()).collect::<HashMap<_, _>>();
+                            Some(substitute(error, &bindings))
+                        },
+                        _ => None,
+                    };
+                    Shape::Result { enum_name: enum_def.name.clone(), error }
+                },
+                _ => Shape::NotResult,
+            };
+        }
+
+        if BUILTIN_TYPES.contains(&name.as_str()) || self.module.struct_defs.iter().any(|s| &s.name == name) {
+            Shape::NotResult
+        }
+        else {
+            Shape::Unknown
+        }
+    }
+
+    /// The type of an operand of `?` when an annotation gives it.
+    fn operand_type(&self, expr : &Expr) -> Option<Type> {
+        match expr {
+            Expr::Variable(name) => self.locals.iter().rev().find(|(n, _)| n == name).map(|(_, t)| t.clone()),
+            Expr::Call { fun, .. } => match &**fun {
+                Expr::Variable(name) if !self.locals.iter().any(|(n, _)| n == name) => {
+                    self.module.fun_defs.iter()
+                                        .find(|f| &f.sig.name == name && f.sig.type_params.is_empty())
+                                        .map(|f| f.sig.return_type.clone())
+                },
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn check_propagate(&mut self, operand : &Expr) {
+        let found = self.operand_type(operand).unwrap_or(Type::Infer);
+        let operand_shape = self.shape(&found);
+        if operand_shape == Shape::NotResult {
+            self.errors.push(CheckError::PropagateNonResult { function: self.function.to_string(), found, meta: self.at });
+            return;
+        }
+        if self.tries > 0 || self.lambdas > 0 {
+            return;
+        }
+
+        match (&self.returns, &operand_shape) {
+            (Shape::NotResult, _) => {
+                let return_type = self.return_type.clone();
+                self.errors.push(CheckError::PropagateOutsideResult { function: self.function.to_string(), return_type, meta: self.at });
+            },
+            (Shape::Result { enum_name: expected_enum, error: expected_error }, Shape::Result { enum_name, error }) => {
+                let errors_differ = matches!( (expected_error, error), (Some(a), Some(b)) if a != b && self.shape(a) != Shape::Unknown && self.shape(b) != Shape::Unknown );
+                if expected_enum != enum_name || errors_differ {
+                    let expected = self.return_type.clone();
+                    self.errors.push(CheckError::PropagateMismatch { function: self.function.to_string(), expected: Box::new(expected), found: Box::new(found), meta: self.at });
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn check_expr(&mut self, expr : &Expr) {
+        let outer = self.at;
+        if let Some(meta) = expr.meta() {
+            self.at = meta;
+        }
+        self.check_kind(expr);
+        self.at = outer;
+    }
+
+    fn check_kind(&mut self, expr : &Expr) {
+        match expr {
+            Expr::Number(_) | Expr::DString(_) | Expr::Unit | Expr::Variable(_) | Expr::Namespace(_, _) => (),
+            Expr::Bool(_) | Expr::Break | Expr::Continue => (),
+            Expr::Tuple(exprs) | Expr::List(exprs) => {
+                for e in exprs {
+                    self.check_expr(e);
+                }
+            },
+            Expr::Block(exprs) => {
+                let depth = self.locals.len();
+                for e in exprs {
+                    self.check_expr(e);
+                }
+                self.locals.truncate(depth);
+            },
+            Expr::Call { fun, args, .. } => {
+                self.check_expr(fun);
+                for e in args {
+                    self.check_expr(e);
+                }
+            },
+            Expr::MethodCall { receiver, args, .. } => {
+                self.check_expr(receiver);
+                for e in args {
+                    self.check_expr(e);
+                }
+            },
+            Expr::Dot { expr, .. } | Expr::Return(expr) | Expr::Yield(expr) | Expr::Unary { expr, .. } | Expr::Panic { message: expr, .. } => self.check_expr(expr),
+            Expr::Assert { condition, message, .. } => {
+                self.check_expr(condition);
+                if let Some(message) = message {
+                    self.check_expr(message);
+                }
+            },
+            Expr::Range { start, end, .. } => {
+                for e in start.iter().chain(end) {
+                    self.check_expr(e);
+                }
+            },
+            Expr::Let { name, let_type, value, .. } => {
+                self.check_expr(value);
+                self.locals.push((name.clone(), let_type.clone()));
+            },
+            Expr::Assign { target, value, .. } | Expr::Index { expr: target, index: value }
+                | Expr::Binary { left: target, right: value, .. } | Expr::While { condition: target, body: value } => {
+                self.check_expr(target);
+                self.check_expr(value);
+            },
+            Expr::Dict(pairs) => {
+                for (k, v) in pairs {
+                    self.check_expr(k);
+                    self.check_expr(v);
+                }
+            },
+            Expr::Struct { fields, .. } => {
+                for (_, e) in fields {
+                    self.check_expr(e);
+                }
+            },
+            Expr::Foreach { name, iterable, body, .. } => {
+                self.check_expr(iterable);
+                self.locals.push((name.clone(), Type::Infer));
+                self.check_expr(body);
+                self.locals.pop();
+            },
+            Expr::Lambda { params, body } => {
+                let depth = self.locals.len();
+                self.locals.extend(params.iter().map(|p| (p.name.clone(), p.param_type.clone())));
+                self.lambdas += 1;
+                self.check_expr(body);
+                self.lambdas -= 1;
+                self.locals.truncate(depth);
+            },
+            Expr::If { condition, then, otherwise } => {
+                self.check_expr(condition);
+                self.check_expr(then);
+                self.check_expr(otherwise);
+            },
+            Expr::Match { expr, arms } => {
+                self.check_expr(expr);
+                for arm in arms {
+                    let depth = self.locals.len();
+                    self.locals.extend(super::checker::pattern_variables(&arm.pattern).into_iter().map(|n| (n, Type::Infer)));
+                    self.check_expr(&arm.body);
+                    self.locals.truncate(depth);
+                }
+            },
+            Expr::Try(body) => {
+                self.tries += 1;
+                self.check_expr(body);
+                self.tries -= 1;
+            },
+            Expr::Propagate(e) => {
+                self.check_expr(e);
+                self.check_propagate(e);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+
+    const RESULT : &str = "enum Result<T, E> { Ok(T), Err(E) }\nenum Either<L, R> { Ok(L), Err(R) }\n";
+
+    fn check_src(src : &str) -> Vec<CheckError> {
+        check_propagation(&parse(&format!("{}{}", RESULT, src)).unwrap())
+    }
+
+    #[test]
+    fn should_allow_propagation_to_compatible_results() {
+        let errors = check_src(r#"
+fun f(x : Result<Int, String>) -> Result<Bool, String> { let y = x?; Result::Ok(y == 1) }
+fun g<E>(x : Result<Int, E>) -> Result<Int, E> { Result::Ok(x? + f(Result::Ok(1))?.len()) }
+fun h(x : Result<Int, Int>) { let r = try { x? }; let l = |y| y?; }
+"#);
+        assert!( errors.is_empty(), "{:?}", errors );
+    }
+
+    #[test]
+    fn should_reject_propagation_out_of_functions_which_do_not_return_results() {
+        let errors = check_src("fun main() { let x : Result<Int, String> = Result::Ok(1); x?; }");
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        match &errors[0] {
+            CheckError::PropagateOutsideResult { function, return_type, .. } => {
+                assert_eq!( function, "main" );
+                assert_eq!( return_type, &Type::Unit );
+            },
+            x => panic!( "Expected PropagateOutsideResult but found {:?}", x ),
+        }
+    }
+
+    #[test]
+    fn should_reject_propagation_of_other_errors() {
+        let errors = check_src(r#"
+fun f(x : Result<Int, String>) -> Result<Int, Int> { x? }
+fun g(x : Either<Int, String>) -> Result<Int, String> { x? }
+fun h() -> Result<Int, Int> { Result::Ok(f(Result::Ok(1))? + g(Result::Ok(1))?) }
+"#);
+        assert_eq!( errors.len(), 3, "{:?}", errors );
+        assert!( errors.iter().all(|e| matches!( e, CheckError::PropagateMismatch { .. } )), "{:?}", errors );
+        match &errors[2] {
+            CheckError::PropagateMismatch { function, expected, found, .. } => {
+                assert_eq!( function, "h" );
+                assert_eq!( **expected, Type::Indexed("Result".to_string(), vec![Type::Simple("Int".to_string()), Type::Simple("Int".to_string())]) );
+                assert_eq!( **found, Type::Indexed("Result".to_string(), vec![Type::Simple("Int".to_string()), Type::Simple("String".to_string())]) );
+            },
+            x => panic!( "Expected PropagateMismatch but found {:?}", x ),
+        }
+    }
+
+    #[test]
+    fn should_reject_propagation_of_values_which_are_not_results() {
+        let errors = check_src("fun f(x : Int, y : List<Int>) -> Result<Int, Int> { try { x? }; y? }");
+        assert_eq!( errors.len(), 2, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::PropagateNonResult { found: Type::Simple(t), .. } if t == "Int" ), "{:?}", errors );
+        assert!( matches!( &errors[1], CheckError::PropagateNonResult { found: Type::Indexed(t, _), .. } if t == "List" ), "{:?}", errors );
+    }
+}
@@ -0,0 +1,741 @@
+
+use std::collections::HashMap;
+
+use crate::parsing::ast::*;
+use crate::parsing::printer::print_type;
+use super::check_error::CheckError;
+
+const MAX_SOLVE_DEPTH : usize = 32;
+
+pub struct TraitEnv<'a> {
+    traits : HashMap<&'a str, &'a TraitDef>,
+    impls : Vec<&'a ImplDef>,
+}
+
+#[derive(Debug)]
+pub struct ImplMatch<'a> {
+    pub impl_def : &'a ImplDef,
+    pub bindings : HashMap<String, Type>,
+}
+
+#[derive(Debug)]
+pub enum MethodTarget<'a> {
+    Impl { impl_def : &'a ImplDef, fun_def : &'a FunDef, bindings : HashMap<String, Type> },
+    Bound { trait_def : &'a TraitDef, sig : &'a FunSig },
+}
+
+impl<'a> TraitEnv<'a> {
+    pub fn new(module : &'a Module) -> TraitEnv<'a> {
+        let traits = module.trait_defs.iter().map(|t| (&t.name[..], t)).collect();
+        let impls = module.impl_defs.iter().collect();
+        TraitEnv { traits, impls }
+    }
+
+    pub fn trait_def(&self, name : &str) -> Option<&'a TraitDef> {
+        self.traits.get(name).copied()
+    }
+
+    pub fn traits_declaring_own(&self, item : &str) -> Vec<&'a TraitDef> {
+        self.traits.values().filter(|t| declares_own(t, item)).copied().collect()
+    }
+
+    pub fn check(&self) -> Vec<CheckError> {
+        let mut errors = vec![];
+
+        for impl_def in &self.impls {
+            for tp in &impl_def.type_params {
+                for c in &tp.constraints {
+                    if !self.traits.contains_key(&c[..]) {
+                        errors.push(CheckError::UnknownTrait(c.clone()));
+                    }
+                }
+            }
+
+            match &impl_def.trait_type {
+                Some(trait_type) => self.check_trait_impl(impl_def, trait_type, &mut errors),
+                None => check_duplicate_methods(impl_def, &mut errors),
+            }
+        }
+
+        self.check_overlap(&mut errors);
+
+        errors
+    }
+
+    pub fn implements(&self, target : &Type, trait_name : &str, scope : &[TypeParam]) -> bool {
+        self.implements_at_depth(target, trait_name, scope, 0)
+    }
+
+    pub fn find_impl(&self, target : &Type, trait_name : &str, scope : &[TypeParam]) -> Option<ImplMatch<'a>> {
+        self.find_impl_at_depth(target, trait_name, scope, 0)
+    }
+
+    pub fn resolve_associated_type(&self, target : &Type, trait_name : &str, item : &str, scope : &[TypeParam]) -> Result<Type, CheckError> {
+        let no_item = || CheckError::NoAssociatedType { target: target.clone()
+                                                      , trait_name: trait_name.to_string()
+                                                      , item: item.to_string()
+                                                      };
+
+        let m = self.find_impl(target, trait_name, scope).ok_or_else(no_item)?;
+
+        for impl_item in &m.impl_def.items {
+            match impl_item {
+                ImplItem::Type { name, item_type } | ImplItem::Own { name, item_type } if name == item => return Ok(substitute(item_type, &m.bindings)),
+                _ => (),
+            }
+        }
+
+        Err(no_item())
+    }
+
+    /// The method `name` of `receiver`, or an error reported at `meta`, the call.
+    pub fn resolve_method(&self, receiver : &Type, name : &str, scope : &[TypeParam], meta : Meta) -> Result<MethodTarget<'a>, CheckError> {
+        for impl_def in self.impls.iter().filter(|i| i.trait_type.is_none()) {
+            if let Some(m) = self.match_impl(impl_def, receiver, scope, 0) {
+                if let Some(fun_def) = impl_fun(impl_def, name) {
+                    return Ok(MethodTarget::Impl { impl_def: m.impl_def, fun_def, bindings: m.bindings });
+                }
+            }
+        }
+
+        let mut candidates = vec![];
+        for impl_def in self.impls.iter().filter(|i| i.trait_type.is_some()) {
+            if let Some(fun_def) = impl_fun(impl_def, name) {
+                if let Some(m) = self.match_impl(impl_def, receiver, scope, 0) {
+                    candidates.push((m, fun_def));
+                }
+            }
+        }
+
+        match candidates.len() {
+            0 => (),
+            1 => {
+                let (m, fun_def) = candidates.remove(0);
+                return Ok(MethodTarget::Impl { impl_def: m.impl_def, fun_def, bindings: m.bindings });
+            },
+            _ => {
+                let traits = candidates.iter()
+                                       .map(|(m, _)| trait_name(m.impl_def).unwrap_or_default())
+                                       .collect();
+                return Err(CheckError::AmbiguousMethod { receiver: receiver.clone(), name: name.to_string(), traits, meta });
+            },
+        }
+
+        if let Type::Simple(param_name) = receiver {
+            let mut bounds = vec![];
+            for tp in scope.iter().filter(|tp| &tp.name == param_name) {
+                for c in &tp.constraints {
+                    if let Some(trait_def) = self.trait_def(c) {
+                        if let Some(sig) = trait_fun(trait_def, name) {
+                            bounds.push(MethodTarget::Bound { trait_def, sig });
+                        }
+                    }
+                }
+            }
+
+            match bounds.len() {
+                0 => (),
+                1 => return Ok(bounds.remove(0)),
+                _ => {
+                    let traits = bounds.iter().filter_map(|b| match b {
+                        MethodTarget::Bound { trait_def, .. } => Some(trait_def.name.clone()),
+                        _ => None,
+                    }).collect();
+                    return Err(CheckError::AmbiguousMethod { receiver: receiver.clone(), name: name.to_string(), traits, meta });
+                },
+            }
+        }
+
+        Err(CheckError::NoMethod { receiver: receiver.clone(), name: name.to_string(), meta })
+    }
+
+    fn implements_at_depth(&self, target : &Type, trait_name : &str, scope : &[TypeParam], depth : usize) -> bool {
+        if let Type::Simple(name) = target {
+            if scope.iter().any(|tp| &tp.name == name && tp.constraints.iter().any(|c| c == trait_name)) {
+                return true;
+            }
+        }
+
+        self.find_impl_at_depth(target, trait_name, scope, depth).is_some()
+    }
+
+    fn find_impl_at_depth(&self, target : &Type, trait_name : &str, scope : &[TypeParam], depth : usize) -> Option<ImplMatch<'a>> {
+        if depth > MAX_SOLVE_DEPTH {
+            return None;
+        }
+
+        self.impls.iter()
+                  .filter(|i| self::trait_name(i).as_deref() == Some(trait_name))
+                  .find_map(|i| self.match_impl(i, target, scope, depth))
+    }
+
+    fn match_impl(&self, impl_def : &'a ImplDef, target : &Type, scope : &[TypeParam], depth : usize) -> Option<ImplMatch<'a>> {
+        let mut bindings = HashMap::new();
+
+        if !match_type(&impl_def.impl_type, target, &impl_def.type_params, &mut bindings) {
+            return None;
+        }
+
+        for tp in &impl_def.type_params {
+            if let Some(t) = bindings.get(&tp.name) {
+                for c in &tp.constraints {
+                    if !self.implements_at_depth(t, c, scope, depth + 1) {
+                        return None;
+                    }
+                }
+            }
+        }
+
+        Some(ImplMatch { impl_def, bindings })
+    }
+
+    fn check_trait_impl(&self, impl_def : &ImplDef, trait_type : &Type, errors : &mut Vec<CheckError>) {
+        let (name, args) = match trait_name_and_args(trait_type) {
+            Some(x) => x,
+            None => {
+                errors.push(CheckError::UnknownTrait(print_type(trait_type)));
+                return;
+            },
+        };
+
+        let trait_def = match self.traits.get(name) {
+            Some(t) => t,
+            None => {
+                errors.push(CheckError::UnknownTrait(name.to_string()));
+                return;
+            },
+        };
+
+        if trait_def.type_params.len() != args.len() {
+            errors.push(CheckError::WrongTraitArity { trait_name: name.to_string()
+                                                    , expected: trait_def.type_params.len()
+                                                    , found: args.len()
+                                                    });
+            return;
+        }
+
+        let mut bindings = HashMap::new();
+        bindings.insert("Self".to_string(), impl_def.impl_type.clone());
+        for (tp, arg) in trait_def.type_params.iter().zip(args) {
+            bindings.insert(tp.name.clone(), arg.clone());
+        }
+
+        let mut associated = HashMap::new();
+        let mut owned = HashMap::new();
+        for item in &impl_def.items {
+            match item {
+                ImplItem::Type { name, item_type } => {
+                    associated.insert(name.clone(), item_type.clone());
+                    owned.insert(name.clone(), false);
+                },
+                ImplItem::Own { name, item_type } => {
+                    associated.insert(name.clone(), item_type.clone());
+                    owned.insert(name.clone(), true);
+                },
+                ImplItem::Fun(_) => (),
+            }
+        }
+
+        let missing = |item : &str| CheckError::MissingTraitItem { trait_name: name.to_string()
+                                                                 , impl_type: impl_def.impl_type.clone()
+                                                                 , item: item.to_string()
+                                                                 };
+
+        for trait_item in &trait_def.items {
+            match trait_item {
+                TraitItem::Type { name: item_name, constraints } | TraitItem::Own { name: item_name, constraints } => {
+                    let expect_owned = matches!( trait_item, TraitItem::Own { .. } );
+                    match associated.get(item_name) {
+                        Some(_) if owned[item_name] != expect_owned => {
+                            errors.push(CheckError::ItemKindMismatch { trait_name: name.to_string()
+                                                                     , item: item_name.clone()
+                                                                     , expected_own: expect_owned
+                                                                     });
+                        },
+                        Some(t) => {
+                            for c in constraints {
+                                if !self.implements(t, c, &impl_def.type_params) {
+                                    errors.push(CheckError::UnsatisfiedConstraint { target: t.clone(), trait_name: c.clone() });
+                                }
+                            }
+                        },
+                        None => errors.push(missing(item_name)),
+                    }
+                },
+                TraitItem::Fun(sig) => {
+                    match impl_fun(impl_def, &sig.name) {
+                        Some(fun_def) => {
+                            if let Err(reason) = compare_sigs(sig, &fun_def.sig, &bindings, &associated) {
+                                errors.push(CheckError::SignatureMismatch { trait_name: name.to_string()
+                                                                          , item: sig.name.clone()
+                                                                          , reason
+                                                                          , meta: fun_def.meta
+                                                                          });
+                            }
+                        },
+                        None => errors.push(missing(&sig.name)),
+                    }
+                },
+            }
+        }
+
+        for item in &impl_def.items {
+            let (item_name, declared) = match item {
+                ImplItem::Type { name: item_name, .. } | ImplItem::Own { name: item_name, .. } =>
+                    (item_name, trait_def.items.iter().any(|i| matches!(i, TraitItem::Type { name, .. } | TraitItem::Own { name, .. } if name == item_name))),
+                ImplItem::Fun(f) =>
+                    (&f.sig.name, trait_fun(trait_def, &f.sig.name).is_some()),
+            };
+
+            if !declared {
+                errors.push(CheckError::ItemNotInTrait { trait_name: name.to_string()
+                                                       , impl_type: impl_def.impl_type.clone()
+                                                       , item: item_name.clone()
+                                                       });
+            }
+        }
+    }
+
+    fn check_overlap(&self, errors : &mut Vec<CheckError>) {
+        for (index, a) in self.impls.iter().enumerate() {
+            let a_name = match trait_name(a) {
+                Some(n) => n,
+                None => continue,
+            };
+
+            for b in self.impls.iter().skip(index + 1) {
+                if trait_name(b).as_ref() != Some(&a_name) {
+                    continue;
+                }
+
+                let renamed = b.type_params.iter()
+                                           .map(|tp| (tp.name.clone(), Type::Simple(format!("{}'", tp.name))))
+                                           .collect::<HashMap<_, _>>();
+
+                let mut vars = a.type_params.iter().map(|tp| tp.name.clone()).collect::<Vec<_>>();
+                vars.extend(renamed.values().filter_map(|t| match t { Type::Simple(n) => Some(n.clone()), _ => None }));
+
+                let a_header = header(a);
+                let b_header = substitute(&header(b), &renamed);
+
+                let mut bindings = HashMap::new();
+                if unify(&a_header, &b_header, &vars, &mut bindings) {
+                    errors.push(CheckError::OverlappingImpls { trait_name: a_name.clone()
+                                                             , first: a.impl_type.clone()
+                                                             , second: b.impl_type.clone()
+                                                             });
+                }
+            }
+        }
+    }
+}
+
+fn check_duplicate_methods(impl_def : &ImplDef, errors : &mut Vec<CheckError>) {
+    let mut seen = vec![];
+    for item in &impl_def.items {
+        if let ImplItem::Fun(f) = item {
+            if seen.contains(&&f.sig.name) {
+                errors.push(CheckError::DuplicateMethod { impl_type: impl_def.impl_type.clone(), name: f.sig.name.clone(), meta: f.meta });
+            }
+            seen.push(&f.sig.name);
+        }
+    }
+}
+
+fn compare_sigs(trait_sig : &FunSig, impl_sig : &FunSig, bindings : &HashMap<String, Type>, associated : &HashMap<String, Type>) -> Result<(), String> {
+    if trait_sig.type_params.len() != impl_sig.type_params.len() {
+        return Err(format!("expected {} type parameters but found {}", trait_sig.type_params.len(), impl_sig.type_params.len()));
+    }
+
+    if trait_sig.params.len() != impl_sig.params.len() {
+        return Err(format!("expected {} parameters but found {}", trait_sig.params.len(), impl_sig.params.len()));
+    }
+
+    let mut bindings = bindings.clone();
+    for (t, i) in trait_sig.type_params.iter().zip(&impl_sig.type_params) {
+        bindings.insert(t.name.clone(), Type::Simple(i.name.clone()));
+    }
+
+    let mut self_binding = HashMap::new();
+    self_binding.insert("Self".to_string(), bindings["Self"].clone());
+
+    let expected = |t : &Type| substitute(&resolve_self_items(t, associated), &bindings);
+    let found = |t : &Type| substitute(&resolve_self_items(t, associated), &self_binding);
+
+    for (t, i) in trait_sig.params.iter().zip(&impl_sig.params) {
+        let (e, f) = (expected(&t.param_type), found(&i.param_type));
+        if e != f {
+            return Err(format!("parameter {} expected type {} but found {}", i.name, print_type(&e), print_type(&f)));
+        }
+    }
+
+    let (e, f) = (expected(&trait_sig.return_type), found(&impl_sig.return_type));
+    if e != f {
+        return Err(format!("expected return type {} but found {}", print_type(&e), print_type(&f)));
+    }
+
+    Ok(())
+}
+
+fn resolve_self_items(t : &Type, associated : &HashMap<String, Type>) -> Type {
+    match t {
+        Type::Namespace(names, item) if names.len() == 1 && names[0] == "Self" => {
+            match &**item {
+                Type::Simple(n) if associated.contains_key(n) => associated[n].clone(),
+                _ => t.clone(),
+            }
+        },
+        Type::Indexed(n, ts) => Type::Indexed(n.clone(), ts.iter().map(|t| resolve_self_items(t, associated)).collect()),
+        Type::Arrow { input, output } => Type::Arrow { input: Box::new(resolve_self_items(input, associated))
+                                                     , output: Box::new(resolve_self_items(output, associated))
+                                                     },
+        Type::Tuple(ts) => Type::Tuple(ts.iter().map(|t| resolve_self_items(t, associated)).collect()),
+        Type::Array { element, length } => Type::Array { element: Box::new(resolve_self_items(element, associated)), length: length.clone() },
+        _ => t.clone(),
+    }
+}
+
+fn header(impl_def : &ImplDef) -> Type {
+    match &impl_def.trait_type {
+        Some(t) => Type::Tuple(vec![t.clone(), impl_def.impl_type.clone()]),
+        None => impl_def.impl_type.clone(),
+    }
+}
+
+fn impl_fun<'a>(impl_def : &'a ImplDef, name : &str) -> Option<&'a FunDef> {
+    impl_def.items.iter().find_map(|item| match item {
+        ImplItem::Fun(f) if f.sig.name == name => Some(f),
+        _ => None,
+    })
+}
+
+fn trait_fun<'a>(trait_def : &'a TraitDef, name : &str) -> Option<&'a FunSig> {
+    trait_def.items.iter().find_map(|item| match item {
+        TraitItem::Fun(sig) if sig.name == name => Some(sig),
+        _ => None,
+    })
+}
+
+pub fn declares_own(trait_def : &TraitDef, item : &str) -> bool {
+    trait_def.items.iter().any(|i| matches!(i, TraitItem::Own { name, .. } if name == item))
+}
+
+pub fn trait_name(impl_def : &ImplDef) -> Option<String> {
+    impl_def.trait_type.as_ref()
+                       .and_then(trait_name_and_args)
+                       .map(|(name, _)| name.to_string())
+}
+
+pub fn trait_name_and_args(t : &Type) -> Option<(&str, &[Type])> {
+    match t {
+        Type::Simple(name) => Some((name, &[])),
+        Type::Indexed(name, args) => Some((name, args)),
+        _ => None,
+    }
+}
+
+pub fn substitute(t : &Type, bindings : &HashMap<String, Type>) -> Type {
+    match t {
+        Type::Simple(n) => match bindings.get(n) {
+            Some(b) => b.clone(),
+            None => t.clone(),
+        },
+        Type::Indexed(n, ts) => Type::Indexed(n.clone(), ts.iter().map(|t| substitute(t, bindings)).collect()),
+        Type::Arrow { input, output } => Type::Arrow { input: Box::new(substitute(input, bindings))
+                                                     , output: Box::new(substitute(output, bindings))
+                                                     },
+        Type::Tuple(ts) => Type::Tuple(ts.iter().map(|t| substitute(t, bindings)).collect()),
+        Type::Array { element, length } => Type::Array { element: Box::new(substitute(element, bindings)), length: length.clone() },
+        Type::Namespace(names, item) if names.len() == 1 && bindings.contains_key(&names[0]) => {
+            match &bindings[&names[0]] {
+                Type::Simple(n) => Type::Namespace(vec![n.clone()], item.clone()),
+                _ => t.clone(),
+            }
+        },
+        Type::Unit | Type::Namespace(_, _) | Type::Infer => t.clone(),
+    }
+}
+
+pub fn match_type(pattern : &Type, target : &Type, vars : &[TypeParam], bindings : &mut HashMap<String, Type>) -> bool {
+    match (pattern, target) {
+        (Type::Simple(n), _) if vars.iter().any(|v| &v.name == n) => {
+            match bindings.get(n) {
+                Some(t) => t == target,
+                None => {
+                    bindings.insert(n.clone(), target.clone());
+                    true
+                },
+            }
+        },
+        (Type::Infer, _) | (_, Type::Infer) => true,
+        (Type::Unit, Type::Unit) => true,
+        (Type::Simple(a), Type::Simple(b)) => a == b,
+        (Type::Indexed(a, xs), Type::Indexed(b, ys)) =>
+            a == b && xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| match_type(x, y, vars, bindings)),
+        (Type::Tuple(xs), Type::Tuple(ys)) =>
+            xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| match_type(x, y, vars, bindings)),
+        (Type::Arrow { input: i1, output: o1 }, Type::Arrow { input: i2, output: o2 }) =>
+            match_type(i1, i2, vars, bindings) && match_type(o1, o2, vars, bindings),
+        (Type::Array { element: e1, length: l1 }, Type::Array { element: e2, length: l2 }) => l1 == l2 && match_type(e1, e2, vars, bindings),
+        (Type::Namespace(n1, t1), Type::Namespace(n2, t2)) => n1 == n2 && match_type(t1, t2, vars, bindings),
+        _ => false,
+    }
+}
+
+fn unify(a : &Type, b : &Type, vars : &[String], bindings : &mut HashMap<String, Type>) -> bool {
+    let a = substitute(a, bindings);
+    let b = substitute(b, bindings);
+
+    match (&a, &b) {
+        _ if a == b => true,
+        (Type::Simple(n), _) if vars.contains(n) => {
+            bindings.insert(n.clone(), b.clone());
+            true
+        },
+        (_, Type::Simple(n)) if vars.contains(n) => {
+            bindings.insert(n.clone(), a.clone());
+            true
+        },
+        (Type::Indexed(x, xs), Type::Indexed(y, ys)) =>
+            x == y && xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| unify(x, y, vars, bindings)),
+        (Type::Tuple(xs), Type::Tuple(ys)) =>
+            xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| unify(x, y, vars, bindings)),
+        (Type::Arrow { input: i1, output: o1 }, Type::Arrow { input: i2, output: o2 }) =>
+            unify(i1, i2, vars, bindings) && unify(o1, o2, vars, bindings),
+        (Type::Array { element: e1, length: l1 }, Type::Array { element: e2, length: l2 }) => l1 == l2 && unify(e1, e2, vars, bindings),
+        (Type::Namespace(n1, t1), Type::Namespace(n2, t2)) => n1 == n2 && unify(t1, t2, vars, bindings),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+
+    const NOWHERE : Meta = Meta { start: 0, end: 0 };
+
+    const SHOW : &str = r#"
+trait Show {
+    type Out : Show;
+    fun show(self : Self) -> Self::Out;
+}
+
+trait Area {
+    fun area(self : Self) -> Int;
+}
+
+struct Circle { r : Int }
+struct Square { side : Int }
+struct List<T> { items : T }
+
+impl Show for Int {
+    type Out = Int;
+    fun show(self : Self) -> Int { self }
+}
+
+impl<T : Show> Show for List<T> {
+    type Out = T;
+    fun show(self : Self) -> T { self.items }
+}
+
+impl Area for Circle {
+    fun area(self : Circle) -> Int { self.r }
+}
+
+impl Circle {
+    fun radius(self : Self) -> Int { self.r }
+}
+"#;
+
+    #[test]
+    fn should_accept_complete_impls() {
+        let m = parse(SHOW).unwrap();
+        let env = TraitEnv::new(&m);
+        let errors = env.check();
+        assert!( errors.is_empty(), "{:?}", errors );
+    }
+
+    #[test]
+    fn should_report_missing_items() {
+        let m = parse(r#"
+trait Show {
+    type Out;
+    fun show(self : Self) -> Self::Out;
+}
+impl Show for Int { }
+"#).unwrap();
+        let env = TraitEnv::new(&m);
+        let errors = env.check();
+        assert_eq!( errors.len(), 2 );
+        assert!( matches!( &errors[0], CheckError::MissingTraitItem { item, .. } if item == "Out" ) );
+        assert!( matches!( &errors[1], CheckError::MissingTraitItem { item, .. } if item == "show" ) );
+    }
+
+    #[test]
+    fn should_report_signature_mismatch() {
+        let m = parse(r#"
+trait Show {
+    type Out;
+    fun show(self : Self) -> Self::Out;
+}
+impl Show for Int {
+    type Out = String;
+    fun show(self : Self, extra : Int) -> String { self }
+}
+impl Show for Float {
+    type Out = String;
+    fun show(self : Self) -> Int { self }
+}
+"#).unwrap();
+        let env = TraitEnv::new(&m);
+        let errors = env.check();
+        assert_eq!( errors.len(), 2, "{:?}", errors );
+        assert!( errors.iter().all(|e| matches!( e, CheckError::SignatureMismatch { item, .. } if item == "show" )) );
+        assert_eq!( errors[1].to_string(), "show does not match its declaration in trait Show: expected return type String but found Int" );
+    }
+
+    #[test]
+    fn should_report_unknown_trait_and_extra_items() {
+        let m = parse(r#"
+trait Show { }
+impl Blarg for Int { }
+impl Show for Int { fun extra() { } }
+"#).unwrap();
+        let env = TraitEnv::new(&m);
+        let errors = env.check();
+        assert_eq!( errors.len(), 2, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::UnknownTrait(n) if n == "Blarg" ) );
+        assert!( matches!( &errors[1], CheckError::ItemNotInTrait { item, .. } if item == "extra" ) );
+    }
+
+    #[test]
+    fn should_report_unsatisfied_associated_type_constraint() {
+        let m = parse(r#"
+trait Show { }
+trait Container { type Item : Show; }
+impl Container for Box { type Item = Float; }
+"#).unwrap();
+        let env = TraitEnv::new(&m);
+        let errors = env.check();
+        assert_eq!( errors.len(), 1, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::UnsatisfiedConstraint { trait_name, .. } if trait_name == "Show" ) );
+    }
+
+    #[test]
+    fn should_report_overlapping_impls() {
+        let m = parse(r#"
+trait Show { }
+impl<T> Show for List<T> { }
+impl Show for List<Int> { }
+impl Show for List<Float> { }
+"#).unwrap();
+        let env = TraitEnv::new(&m);
+        let errors = env.check();
+        assert_eq!( errors.len(), 2, "{:?}", errors );
+        assert!( errors.iter().all(|e| matches!( e, CheckError::OverlappingImpls { .. } )) );
+    }
+
+    #[test]
+    fn should_not_report_impls_of_different_trait_args_as_overlapping() {
+        let m = parse(r#"
+trait Into<T> { }
+impl Into<Int> for Float { }
+impl Into<String> for Float { }
+"#).unwrap();
+        let env = TraitEnv::new(&m);
+        let errors = env.check();
+        assert!( errors.is_empty(), "{:?}", errors );
+    }
+
+    #[test]
+    fn should_solve_generic_impl_with_constraints() {
+        let m = parse(SHOW).unwrap();
+        let env = TraitEnv::new(&m);
+
+        let int = Type::Simple("Int".to_string());
+        let float = Type::Simple("Float".to_string());
+        let list_int = Type::Indexed("List".to_string(), vec![int.clone()]);
+        let list_list_int = Type::Indexed("List".to_string(), vec![list_int.clone()]);
+        let list_float = Type::Indexed("List".to_string(), vec![float.clone()]);
+
+        assert!( env.implements(&int, "Show", &[]) );
+        assert!( env.implements(&list_int, "Show", &[]) );
+        assert!( env.implements(&list_list_int, "Show", &[]) );
+        assert!( !env.implements(&float, "Show", &[]) );
+        assert!( !env.implements(&list_float, "Show", &[]) );
+
+        let scope = vec![TypeParam { name: "X".to_string(), constraints: vec!["Show".to_string()] }];
+        let list_x = Type::Indexed("List".to_string(), vec![Type::Simple("X".to_string())]);
+        assert!( env.implements(&list_x, "Show", &scope) );
+    }
+
+    #[test]
+    fn should_resolve_associated_type() -> Result<(), CheckError> {
+        let m = parse(SHOW).unwrap();
+        let env = TraitEnv::new(&m);
+
+        let int = Type::Simple("Int".to_string());
+        let list_list_int = Type::Indexed("List".to_string(), vec![Type::Indexed("List".to_string(), vec![int.clone()])]);
+
+        let out = env.resolve_associated_type(&list_list_int, "Show", "Out", &[])?;
+        assert_eq!( out, Type::Indexed("List".to_string(), vec![int.clone()]) );
+
+        let out = env.resolve_associated_type(&Type::Simple("Float".to_string()), "Show", "Out", &[]);
+        assert!( matches!( out, Err(CheckError::NoAssociatedType { .. }) ) );
+        Ok(())
+    }
+
+    #[test]
+    fn should_resolve_method() -> Result<(), CheckError> {
+        let m = parse(SHOW).unwrap();
+        let env = TraitEnv::new(&m);
+
+        let circle = Type::Simple("Circle".to_string());
+
+        match env.resolve_method(&circle, "area", &[], NOWHERE)? {
+            MethodTarget::Impl { impl_def, fun_def, .. } => {
+                assert_eq!( trait_name(impl_def), Some("Area".to_string()) );
+                assert_eq!( fun_def.sig.name, "area" );
+            },
+            x => panic!( "Expected Impl but found {:?}", x ),
+        }
+
+        match env.resolve_method(&circle, "radius", &[], NOWHERE)? {
+            MethodTarget::Impl { impl_def, .. } => assert!( impl_def.trait_type.is_none() ),
+            x => panic!( "Expected Impl but found {:?}", x ),
+        }
+
+        let list_int = Type::Indexed("List".to_string(), vec![Type::Simple("Int".to_string())]);
+        match env.resolve_method(&list_int, "show", &[], NOWHERE)? {
+            MethodTarget::Impl { bindings, .. } => assert_eq!( bindings["T"], Type::Simple("Int".to_string()) ),
+            x => panic!( "Expected Impl but found {:?}", x ),
+        }
+
+        let scope = vec![TypeParam { name: "X".to_string(), constraints: vec!["Area".to_string()] }];
+        match env.resolve_method(&Type::Simple("X".to_string()), "area", &scope, NOWHERE)? {
+            MethodTarget::Bound { trait_def, .. } => assert_eq!( trait_def.name, "Area" ),
+            x => panic!( "Expected Bound but found {:?}", x ),
+        }
+
+        let result = env.resolve_method(&Type::Simple("Square".to_string()), "area", &[], NOWHERE);
+        assert!( matches!( result, Err(CheckError::NoMethod { .. }) ) );
+        Ok(())
+    }
+
+    #[test]
+    fn should_report_ambiguous_method() {
+        let m = parse(r#"
+trait A { fun go(self : Self); }
+trait B { fun go(self : Self); }
+impl A for Int { fun go(self : Self) { } }
+impl B for Int { fun go(self : Self) { } }
+"#).unwrap();
+        let env = TraitEnv::new(&m);
+        let result = env.resolve_method(&Type::Simple("Int".to_string()), "go", &[], NOWHERE);
+        match result {
+            Err(CheckError::AmbiguousMethod { traits, .. }) => assert_eq!( traits, vec!["A".to_string(), "B".to_string()] ),
+            x => panic!( "Expected AmbiguousMethod but found {:?}", x ),
+        }
+    }
+}
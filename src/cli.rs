@@ -0,0 +1,613 @@
+
+use std::io::{self, Read, Write, BufReader};
+use std::path::{Path, PathBuf};
+
+use crate::parsing::ast::{Meta, Module};
+use crate::parsing::parser::parse;
+use crate::parsing::parse_error::{line_and_column, describe_parse_error};
+use crate::parsing::{printer, dump};
+use crate::checking::checker::check_with_warnings;
+use crate::checking::check_error::{CheckError, CheckWarning};
+use crate::compiling::{compiler, vm, module_file};
+use crate::compiling::bytecode::{Program, SourceFile};
+use crate::evaluating::runtime_error::RuntimeError;
+use crate::evaluating::sandbox::Sandbox;
+use crate::evaluating::gc;
+use crate::codegen::{c, wat};
+use crate::repl;
+use crate::stdlib;
+use crate::lsp;
+
+/* The command line driver.  Every command takes any number of files, or reads
+   standard input when there are none or one of them is `-`.  Each file is a
+   program of its own, and the exit code is that of the first file which
+   failed.
+*/
+
+pub const EXIT_OK : i32 = 0;
+/// `fmt --check` found a file which is not formatted.
+pub const EXIT_UNFORMATTED : i32 = 1;
+pub const EXIT_USAGE : i32 = 2;
+pub const EXIT_PARSE_ERROR : i32 = 3;
+/// The checker or the compiler rejected the program.
+pub const EXIT_CHECK_ERROR : i32 = 4;
+pub const EXIT_RUNTIME_ERROR : i32 = 5;
+
+const USAGE : &str = "usage: dust <command> [options] [files]
+commands:
+  run [--gc-stress] [--gc-stats] <files>
+                                     run programs, source or compiled, collecting
+                                     garbage on every allocation or reporting
+                                     what the collector did on standard error
+  check <files>                      parse and check programs without running them
+  parse [--dump-ast [--format F]] <files>
+                                     parse programs, printing their syntax trees
+                                     where F is debug (the default), json or sexpr
+  fmt [--check] <files>              format files in place, or standard input to standard output
+  repl                               start an interactive session
+  lsp                                serve the language server protocol over standard input and output
+  build [--target T] [-o out] <files>
+                                     compile programs ahead of time, where T is
+                                     bytecode (the default), c or wat, to out or
+                                     beside each file; c also writes the runtime
+                                     header next to the source, and compiling
+                                     it needs the maths library, as in
+                                     cc prog.c -lm
+files may be - for standard input, which is also read when there are no files";
+
+struct Source {
+    name : String,
+    /// `None` for standard input.
+    path : Option<PathBuf>,
+    bytes : Vec<u8>,
+}
+
+struct Streams<'a> {
+    stdin : &'a mut dyn Read,
+    stdout : &'a mut dyn Write,
+    stderr : &'a mut dyn Write,
+}
+
+type Failure = i32;
+
+/// Runs the command line in `args`, which does not include the program name,
+/// and returns the exit code.
+pub fn run(args : &[String], stdin : &mut dyn Read, stdout : &mut dyn Write, stderr : &mut dyn Write) -> i32 {
+    let mut streams = Streams { stdin, stdout, stderr };
+
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => {
+            let _ = writeln!(streams.stderr, "{}", USAGE);
+            return EXIT_USAGE;
+        },
+    };
+
+    let result = match command {
+        "run" => {
+            let (stress, rest) = flag(rest, "--gc-stress");
+            let (stats, rest) = flag(&rest, "--gc-stats");
+            gc::set_stress(stress);
+            let result = each_source(&rest, &mut streams, run_source);
+            if stats {
+                let _ = writeln!(streams.stderr, "gc: {}", gc::stats());
+            }
+            result
+        },
+        "check" => each_source(rest, &mut streams, check_source),
+        "parse" => {
+            let (dump, rest) = flag(rest, "--dump-ast");
+            let (format, rest) = match dump_format(&rest, &mut streams) {
+                Ok(parsed) => parsed,
+                Err(code) => return code,
+            };
+            let dump = if dump { Some(format) } else { None };
+            each_source(&rest, &mut streams, |s, source| parse_source(s, source, dump))
+        },
+        "fmt" => {
+            let (check_only, rest) = flag(rest, "--check");
+            each_source(&rest, &mut streams, |s, source| format_source(s, source, check_only))
+        },
+        "build" => build(rest, &mut streams),
+        "lsp" => {
+            let mut stdin = BufReader::new(&mut *streams.stdin);
+            match lsp::server::run(&mut stdin, streams.stdout) {
+                Ok(0) => Ok(()),
+                Ok(code) => Err(code),
+                Err(e) => {
+                    let _ = writeln!(streams.stderr, "lsp: {}", e);
+                    Err(EXIT_USAGE)
+                },
+            }
+        },
+        "repl" => {
+            let mut stdin = BufReader::new(&mut *streams.stdin);
+            repl::run(&mut stdin, streams.stdout, true).map_err(|e| {
+                let _ = writeln!(streams.stderr, "repl: {}", e);
+                EXIT_USAGE
+            })
+        },
+        "help" | "--help" | "-h" => {
+            let _ = writeln!(streams.stdout, "{}", USAGE);
+            Ok(())
+        },
+        // `dust file` runs the file
+        _ if !command.starts_with('-') => each_source(args, &mut streams, run_source),
+        _ => {
+            let _ = writeln!(streams.stderr, "unknown option {}\n{}", command, USAGE);
+            Err(EXIT_USAGE)
+        },
+    };
+
+    let _ = streams.stdout.flush();
+    match result {
+        Ok(()) => EXIT_OK,
+        Err(code) => code,
+    }
+}
+
+/// Whether a flag is among the arguments, along with the other arguments.
+fn flag(args : &[String], name : &str) -> (bool, Vec<String>) {
+    let rest = args.iter().filter(|a| *a != name).cloned().collect::<Vec<_>>();
+    (rest.len() != args.len(), rest)
+}
+
+fn read_sources(files : &[String], streams : &mut Streams) -> Result<Vec<Source>, Failure> {
+    if let Some(option) = files.iter().find(|f| f.starts_with('-') && *f != "-") {
+        let _ = writeln!(streams.stderr, "unknown option {}\n{}", option, USAGE);
+        return Err(EXIT_USAGE);
+    }
+
+    let stdin_only = ["-".to_string()];
+    let files = if files.is_empty() { &stdin_only[..] } else { files };
+
+    let mut sources = vec![];
+    for file in files {
+        let mut bytes = vec![];
+        if file == "-" {
+            if let Err(e) = streams.stdin.read_to_end(&mut bytes) {
+                let _ = writeln!(streams.stderr, "<stdin>: {}", e);
+                return Err(EXIT_USAGE);
+            }
+            sources.push(Source { name: "<stdin>".to_string(), path: None, bytes });
+        }
+        else {
+            match std::fs::read(file) {
+                Ok(bytes) => sources.push(Source { name: file.clone(), path: Some(PathBuf::from(file)), bytes }),
+                Err(e) => {
+                    let _ = writeln!(streams.stderr, "{}: {}", file, e);
+                    return Err(EXIT_USAGE);
+                },
+            }
+        }
+    }
+    Ok(sources)
+}
+
+fn each_source(files : &[String], streams : &mut Streams, mut action : impl FnMut(&mut Streams, &Source) -> Result<(), Failure>) -> Result<(), Failure> {
+    let mut result = Ok(());
+    for source in read_sources(files, streams)? {
+        if let Err(code) = action(streams, &source) {
+            if result.is_ok() {
+                result = Err(code);
+            }
+        }
+    }
+    result
+}
+
+fn text<'a>(streams : &mut Streams, source : &'a Source) -> Result<&'a str, Failure> {
+    std::str::from_utf8(&source.bytes).map_err(|e| {
+        let _ = writeln!(streams.stderr, "{}: {}", source.name, e);
+        EXIT_USAGE
+    })
+}
+
+/// A failed assert or panic is reported where it happened, followed by the
+/// functions it left.
+pub fn describe_runtime_error(name : &str, program : &Program, e : &RuntimeError) -> String {
+    let location = |at : Option<Meta>| match at {
+        Some(at) => program.location(at).unwrap_or_else(|| format!("{} (byte {})", name, at.start)),
+        None => name.to_string(),
+    };
+    match e {
+        RuntimeError::Failed(failure) => {
+            let mut text = format!("{}: runtime error: {}", location(Some(failure.at)), e);
+            for (function, at) in failure.frames() {
+                text.push_str(&format!("\n  at {} ({})", function, location(at)));
+            }
+            text
+        },
+        e => format!("{}: runtime error: {}", name, e),
+    }
+}
+
+/// A check error is reported where it is when the checker knows, and against
+/// the whole file otherwise.
+pub fn describe_check_error(name : &str, text : &str, e : &CheckError) -> String {
+    match e.at() {
+        Some(at) => {
+            let (line, column) = line_and_column(text, at.start);
+            format!("{}:{}:{}: check error: {}", name, line, column, e)
+        },
+        None => format!("{}: check error: {}", name, e),
+    }
+}
+
+fn parse_text(streams : &mut Streams, source : &Source) -> Result<Module, Failure> {
+    let text = text(streams, source)?;
+    parse(text).map_err(|e| {
+        let _ = writeln!(streams.stderr, "{}", describe_parse_error(&source.name, text, &e));
+        EXIT_PARSE_ERROR
+    })
+}
+
+fn checked(streams : &mut Streams, source : &Source) -> Result<Module, Failure> {
+    let mut module = parse_text(streams, source)?;
+    let text = text(streams, source)?;
+    let mut errors = stdlib::link(&mut module).err().unwrap_or_default();
+    let (mut check_errors, warnings) = check_with_warnings(&module);
+    errors.append(&mut check_errors);
+    for w in &warnings {
+        let CheckWarning::UnusedMut { declared, .. } = w;
+        let (line, column) = line_and_column(text, declared.start);
+        let _ = writeln!(streams.stderr, "{}:{}:{}: warning: {}", source.name, line, column, w);
+    }
+    for e in &errors {
+        let _ = writeln!(streams.stderr, "{}", describe_check_error(&source.name, text, e));
+    }
+    if errors.is_empty() { Ok(module) } else { Err(EXIT_CHECK_ERROR) }
+}
+
+fn compiled(streams : &mut Streams, source : &Source) -> Result<Program, Failure> {
+    if source.bytes.starts_with(module_file::MAGIC) {
+        return module_file::read_program(&source.bytes).map_err(|e| {
+            let _ = writeln!(streams.stderr, "{}: {}", source.name, e);
+            EXIT_USAGE
+        });
+    }
+
+    let module = checked(streams, source)?;
+    let mut program = compiler::compile(&module).map_err(|e| {
+        let _ = writeln!(streams.stderr, "{}: compile error: {}", source.name, e);
+        EXIT_CHECK_ERROR
+    })?;
+    program.source = Some(SourceFile { name: source.name.clone(), text: text(streams, source)?.to_string() });
+    Ok(program)
+}
+
+fn run_source(streams : &mut Streams, source : &Source) -> Result<(), Failure> {
+    let program = compiled(streams, source)?;
+    vm::Vm::new(&program, streams.stdout).with_sandbox(&Sandbox::trusted()).run_main().map(|_| ()).map_err(|e| {
+        let _ = streams.stdout.flush();
+        let _ = writeln!(streams.stderr, "{}", describe_runtime_error(&source.name, &program, &e));
+        EXIT_RUNTIME_ERROR
+    })
+}
+
+fn check_source(streams : &mut Streams, source : &Source) -> Result<(), Failure> {
+    compiled(streams, source).map(|_| ())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum DumpFormat {
+    Debug,
+    Json,
+    Sexpr,
+}
+
+/// The value of `--format`, along with the other arguments.
+fn dump_format(args : &[String], streams : &mut Streams) -> Result<(DumpFormat, Vec<String>), Failure> {
+    let mut format = DumpFormat::Debug;
+    let mut rest = vec![];
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = match args.next().map(String::as_str) {
+                Some("debug") => DumpFormat::Debug,
+                Some("json") => DumpFormat::Json,
+                Some("sexpr") => DumpFormat::Sexpr,
+                other => {
+                    let _ = writeln!(streams.stderr, "unknown format {}\n{}", other.unwrap_or(""), USAGE);
+                    return Err(EXIT_USAGE);
+                },
+            },
+            _ => rest.push(arg.clone()),
+        }
+    }
+
+    Ok((format, rest))
+}
+
+fn parse_source(streams : &mut Streams, source : &Source, dump : Option<DumpFormat>) -> Result<(), Failure> {
+    let module = parse_text(streams, source)?;
+    let _ = match dump {
+        Some(DumpFormat::Debug) => writeln!(streams.stdout, "{:#?}", module),
+        Some(DumpFormat::Json) => writeln!(streams.stdout, "{}", dump::to_json(&module)),
+        Some(DumpFormat::Sexpr) => writeln!(streams.stdout, "{}", dump::to_sexpr(&module)),
+        None => Ok(()),
+    };
+    Ok(())
+}
+
+fn format_source(streams : &mut Streams, source : &Source, check_only : bool) -> Result<(), Failure> {
+    let text = text(streams, source)?;
+    let formatted = printer::format(text).map_err(|e| {
+        let _ = writeln!(streams.stderr, "{}", describe_parse_error(&source.name, text, &e));
+        EXIT_PARSE_ERROR
+    })?;
+
+    match (&source.path, check_only) {
+        (_, true) if formatted != text => {
+            let _ = writeln!(streams.stdout, "{}", source.name);
+            Err(EXIT_UNFORMATTED)
+        },
+        (_, true) => Ok(()),
+        (None, false) => {
+            let _ = write!(streams.stdout, "{}", formatted);
+            Ok(())
+        },
+        (Some(_), false) if formatted == text => Ok(()),
+        (Some(path), false) => std::fs::write(path, formatted).map_err(|e| {
+            let _ = writeln!(streams.stderr, "{}: {}", source.name, e);
+            EXIT_USAGE
+        }),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Target {
+    Bytecode,
+    C,
+    Wat,
+}
+
+fn build(args : &[String], streams : &mut Streams) -> Result<(), Failure> {
+    let mut target = Target::Bytecode;
+    let mut output = None;
+    let mut files = vec![];
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--target" => target = match args.next().map(String::as_str) {
+                Some("bytecode") => Target::Bytecode,
+                Some("c") => Target::C,
+                Some("wat") => Target::Wat,
+                other => {
+                    let _ = writeln!(streams.stderr, "unknown target {}\n{}", other.unwrap_or(""), USAGE);
+                    return Err(EXIT_USAGE);
+                },
+            },
+            "-o" => match args.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => {
+                    let _ = writeln!(streams.stderr, "-o needs a path\n{}", USAGE);
+                    return Err(EXIT_USAGE);
+                },
+            },
+            _ => files.push(arg.clone()),
+        }
+    }
+
+    if output.is_some() && files.len() > 1 {
+        let _ = writeln!(streams.stderr, "-o can only be used with one file");
+        return Err(EXIT_USAGE);
+    }
+
+    each_source(&files, streams, |streams, source| build_source(streams, source, target, output.as_deref()))
+}
+
+fn build_source(streams : &mut Streams, source : &Source, target : Target, output : Option<&Path>) -> Result<(), Failure> {
+    let io_error = |streams : &mut Streams, e : io::Error| {
+        let _ = writeln!(streams.stderr, "{}: {}", source.name, e);
+        EXIT_USAGE
+    };
+    let beside = |extension : &str| source.path.as_ref().map(|p| p.with_extension(extension));
+
+    match target {
+        Target::Bytecode => {
+            let program = compiled(streams, source)?;
+            let bytes = module_file::write_program(&program);
+            match output.map(Path::to_path_buf).or_else(|| beside("dustc")) {
+                Some(path) => std::fs::write(path, bytes).map_err(|e| io_error(streams, e)),
+                None => streams.stdout.write_all(&bytes).map_err(|e| io_error(streams, e)),
+            }
+        },
+        Target::C => {
+            let program = compiled(streams, source)?;
+            let path = match output.map(Path::to_path_buf).or_else(|| beside("c")) {
+                Some(path) => path,
+                None => {
+                    let _ = writeln!(streams.stderr, "{}: building C needs -o for the source it writes", source.name);
+                    return Err(EXIT_USAGE);
+                },
+            };
+            c::write(&program, &path).map_err(|e| io_error(streams, e))
+        },
+        Target::Wat => {
+            let module = checked(streams, source)?;
+            let text = wat::generate(&module).map_err(|e| {
+                let _ = writeln!(streams.stderr, "{}: {}", source.name, e);
+                EXIT_CHECK_ERROR
+            })?;
+            match output.map(Path::to_path_buf).or_else(|| beside("wat")) {
+                Some(path) => std::fs::write(path, text).map_err(|e| io_error(streams, e)),
+                None => streams.stdout.write_all(text.as_bytes()).map_err(|e| io_error(streams, e)),
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dust(args : &[&str], stdin : &str) -> (i32, String, String) {
+        let args = args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let (mut out, mut err) = (vec![], vec![]);
+        let code = run(&args, &mut stdin.as_bytes(), &mut out, &mut err);
+        (code, String::from_utf8(out).unwrap(), String::from_utf8(err).unwrap())
+    }
+
+    fn temp_dir(name : &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dust_cli_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn should_run_standard_input_and_files() {
+        assert_eq!( dust(&["run"], "fun main() { println(1 + 2); }"), (0, "3\n".to_string(), String::new()) );
+        assert_eq!( dust(&["run", "-"], "fun main() { println(\"x\"); }"), (0, "x\n".to_string(), String::new()) );
+
+        let dir = temp_dir("run");
+        let (a, b) = (dir.join("a.ds"), dir.join("b.ds"));
+        std::fs::write(&a, "fun main() { println(\"a\"); }").unwrap();
+        std::fs::write(&b, "fun main() { println(\"b\"); }").unwrap();
+        let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+        assert_eq!( dust(&["run", a, b], ""), (0, "a\nb\n".to_string(), String::new()) );
+        assert_eq!( dust(&[a], ""), (0, "a\n".to_string(), String::new()) );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn should_report_what_the_collector_did() {
+        let src = "fun main() { let mut i = 0; while i < 3000 { let mut xs = []; xs.push(xs); i = i + 1; } println(i); }";
+        let (code, out, err) = dust(&["run", "--gc-stats"], src);
+        assert_eq!( (code, out.as_str()), (EXIT_OK, "3000\n") );
+        assert!( err.starts_with("gc: ") && err.contains("collections"), "{}", err );
+
+        let (code, out, _) = dust(&["run", "--gc-stress"], "fun main() { let mut xs = [[1], [2]]; xs.push(xs); println(xs[1], xs.len()); }");
+        crate::evaluating::gc::set_stress(false);
+        assert_eq!( (code, out.as_str()), (EXIT_OK, "[2] 3\n") );
+    }
+
+    #[test]
+    fn should_run_conformance_programs() {
+        for case in crate::evaluating::conformance::CASES {
+            let (code, out, err) = dust(&["run"], case.source);
+            assert_eq!( out, case.output, "{}", case.name );
+            assert!( code == EXIT_OK || code == EXIT_RUNTIME_ERROR, "{}: {}", case.name, err );
+        }
+    }
+
+    #[test]
+    fn should_distinguish_failures_by_exit_code() {
+        let (code, _, err) = dust(&["run"], "fun main() {\n    println(1 +);\n}");
+        assert_eq!( code, EXIT_PARSE_ERROR );
+        assert!( err.starts_with("<stdin>:2:") && err.contains("parse error"), "{}", err );
+
+        let (code, _, err) = dust(&["run"], "fun main() { let x = 1; x = 2; }");
+        assert_eq!( code, EXIT_CHECK_ERROR );
+        assert!( err.starts_with("<stdin>:1:25: check error: cannot assign to x"), "{}", err );
+
+        let (code, out, err) = dust(&["run"], "fun main() { println(1); println(1 / 0); }");
+        assert_eq!( (code, out.as_str()), (EXIT_RUNTIME_ERROR, "1\n") );
+        assert_eq!( err, "<stdin>: runtime error: division by zero\n" );
+
+        let (code, _, err) = dust(&["run"], "fun f(x : Int) {\n    assert(x == 2, \"x was {}\");\n}\n\nfun main() {\n    f(1)\n}");
+        assert_eq!( code, EXIT_RUNTIME_ERROR );
+        assert_eq!( err, "<stdin>:2:5: runtime error: assertion failed: x == 2: x was {}\n  left: 1\n  right: 2\n  at f (<stdin>:2:5)\n  at main (<stdin>:6:5)\n" );
+
+        assert_eq!( dust(&["run", "/nonexistent/file.ds"], "").0, EXIT_USAGE );
+        assert_eq!( dust(&[], "").0, EXIT_USAGE );
+        assert_eq!( dust(&["run", "--fast"], "").0, EXIT_USAGE );
+    }
+
+    #[test]
+    fn should_check_and_parse_without_running() {
+        assert_eq!( dust(&["check"], "fun main() { println(1 / 0); }"), (0, String::new(), String::new()) );
+        assert_eq!( dust(&["check"], "fun main() { let mut x = 1; }"), (0, String::new(), "<stdin>:1:14: warning: x is declared mut but never changed\n".to_string()) );
+        assert_eq!( dust(&["check"], "use std::{*};\nfun main() { }"), (0, String::new(), String::new()) );
+        assert_eq!( dust(&["check"], "fun main() { let x = 1; x = 2; }").0, EXIT_CHECK_ERROR );
+        let (code, _, err) = dust(&["check"], "fun main() {\n    missing(1);\n}");
+        assert_eq!( code, EXIT_CHECK_ERROR );
+        assert!( err.starts_with("<stdin>: compile error: "), "{}", err );
+        let (code, _, err) = dust(&["check"], "struct P { x : Int }\nfun main() {\n    let p = P { x: 1 };\n    p.nothing();\n}");
+        assert_eq!( (code, err.as_str()), (EXIT_CHECK_ERROR, "<stdin>:4:5: check error: P has no method nothing\n") );
+
+        assert_eq!( dust(&["parse"], "fun main() { let x = 1; x = 2; }"), (0, String::new(), String::new()) );
+        let (code, out, _) = dust(&["parse", "--dump-ast"], "fun main() { }");
+        assert_eq!( code, 0 );
+        assert!( out.starts_with("Module {\n    fun_defs: [\n        FunDef {") );
+        assert_eq!( dust(&["parse", "--dump-ast"], "fun main( { }").0, EXIT_PARSE_ERROR );
+
+        let (code, out, _) = dust(&["parse", "--dump-ast", "--format", "json"], "fun main() { }");
+        assert_eq!( code, 0 );
+        assert!( out.starts_with(r#"{"kind":"Module","fun_defs":[{"kind":"FunDef","sig":{"kind":"FunSig","name":"main""#) );
+        assert!( dump::from_json::<Module>(out.trim_end()).is_ok() );
+        let (code, out, _) = dust(&["parse", "--format", "sexpr", "--dump-ast"], "mod m;");
+        assert_eq!( (code, out.as_str()), (0, "(Module :fun_defs () :uses () :struct_defs () :enum_defs () :trait_defs () :impl_defs () :const_defs () :extern_defs () :mods (\"m\"))\n") );
+        assert_eq!( dust(&["parse", "--dump-ast", "--format", "xml"], "").0, EXIT_USAGE );
+    }
+
+    #[test]
+    fn should_serve_the_language_server_protocol() {
+        let frame = |m : &str| format!("Content-Length: {}\r\n\r\n{}", m.len(), m);
+        let input = [ r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#
+                    , r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#
+                    , r#"{"jsonrpc":"2.0","method":"exit"}"#
+                    ].iter().map(|m| frame(m)).collect::<String>();
+        let (code, out, _) = dust(&["lsp"], &input);
+        assert_eq!( code, 0 );
+        assert!( out.ends_with(&frame(r#"{"jsonrpc":"2.0","id":2,"result":null}"#)) );
+
+        assert_eq!( dust(&["lsp"], "").0, 1 );
+    }
+
+    #[test]
+    fn should_format_standard_input_and_files() {
+        assert_eq!( dust(&["fmt"], "fun main() { println( 1 ) }"), (0, "fun main() {\n    println(1)\n}\n".to_string(), String::new()) );
+
+        let dir = temp_dir("fmt");
+        let file = dir.join("a.ds");
+        std::fs::write(&file, "fun main()  { }").unwrap();
+        let name = file.to_str().unwrap();
+        assert_eq!( dust(&["fmt", "--check", name], ""), (EXIT_UNFORMATTED, format!("{}\n", name), String::new()) );
+        assert_eq!( dust(&["fmt", name], ""), (0, String::new(), String::new()) );
+        assert_eq!( std::fs::read_to_string(&file).unwrap(), "fun main() { }\n" );
+        assert_eq!( dust(&["fmt", "--check", name], ""), (0, String::new(), String::new()) );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn should_build_ahead_of_time() {
+        let dir = temp_dir("build");
+        let file = dir.join("prog.ds");
+        std::fs::write(&file, "fun main() { println(\"built\"); }").unwrap();
+        let name = file.to_str().unwrap();
+
+        assert_eq!( dust(&["build", name], ""), (0, String::new(), String::new()) );
+        let compiled = dir.join("prog.dustc");
+        assert_eq!( dust(&["run", compiled.to_str().unwrap()], ""), (0, "built\n".to_string(), String::new()) );
+
+        let numeric = dir.join("numeric.ds");
+        std::fs::write(&numeric, "fun twice(x : Int) -> Int { x * 2 }").unwrap();
+        let wat = dir.join("out.wat");
+        assert_eq!( dust(&["build", "--target", "wat", "-o", wat.to_str().unwrap(), numeric.to_str().unwrap()], "").0, 0 );
+        assert!( std::fs::read_to_string(&wat).unwrap().contains("(func $twice") );
+
+        assert_eq!( dust(&["build", "--target", "c", name], "").0, 0 );
+        assert!( dir.join("prog.c").exists() && dir.join(c::RUNTIME_HEADER_NAME).exists() );
+        let named = dir.join("as.c");
+        assert_eq!( dust(&["build", "--target", "c", "-o", named.to_str().unwrap(), name], "").0, 0 );
+        assert!( named.is_file() );
+
+        let (code, out, _) = dust(&["build", "--target", "wat"], "fun f() -> Float { 1.5 }");
+        assert_eq!( code, 0 );
+        assert!( out.starts_with("(module") );
+        assert_eq!( dust(&["build", "--target", "wat"], "fun f() -> String { \"s\" }").0, EXIT_CHECK_ERROR );
+        assert_eq!( dust(&["build", "--target", "c"], "fun main() { }").0, EXIT_USAGE );
+        assert_eq!( dust(&["build", "--target", "js", name], "").0, EXIT_USAGE );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn should_find_lines_and_columns() {
+        assert_eq!( line_and_column("ab\ncd\n", 0), (1, 1) );
+        assert_eq!( line_and_column("ab\ncd\n", 4), (2, 2) );
+        assert_eq!( line_and_column("ab\ncd\n", 100), (3, 1) );
+    }
+}
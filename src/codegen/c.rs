@@ -0,0 +1,693 @@
+
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::parsing::ast::{BinOp, Meta, Type, UnaryOp};
+use crate::compiling::bytecode::*;
+
+/* Lowers a compiled `Program` to C99.  Every dust struct becomes a C struct
+   and every dust enum a tagged union, whose Bool, Int, Float and String fields
+   are plain C values, along with a layout which lets the runtime find those
+   fields.  Every dust function becomes a C function with an array of local
+   slots and an operand stack, instructions become statements and jumps become
+   gotos.  Values and everything which depends on them live in the runtime
+   header, which is written next to the generated source and mirrors the
+   semantics of the virtual machine.  It uses the maths library, so the source
+   has to be linked with `-lm`.
+*/
+
+pub const RUNTIME_HEADER_NAME : &str = "dust_runtime.h";
+pub const RUNTIME_HEADER : &str = include_str!("dust_runtime.h");
+
+/// Writes the C source to `source` and the runtime header next to it.
+pub fn write(program : &Program, source : &Path) -> std::io::Result<()> {
+    let dir = source.parent().unwrap_or_else(|| Path::new(""));
+    std::fs::write(dir.join(RUNTIME_HEADER_NAME), RUNTIME_HEADER)?;
+    std::fs::write(source, generate(program))
+}
+
+pub fn generate(program : &Program) -> String {
+    let mut out = String::new();
+    let o = &mut out;
+
+    writeln!(o, "/* Generated by the dust compiler.  Link with the maths library:  cc prog.c -lm */").unwrap();
+    writeln!(o, "#include \"{}\"", RUNTIME_HEADER_NAME).unwrap();
+    writeln!(o).unwrap();
+
+    for (i, info) in program.types.iter().enumerate() {
+        generate_type(o, i, info);
+    }
+
+    writeln!(o, "static dust_value dust_constants[{}];", program.constants.len().max(1)).unwrap();
+    for (i, function) in program.functions.iter().enumerate() {
+        writeln!(o, "static dust_value dust_fn_{}(dust_value *args, dust_value *upvalues);", i).unwrap();
+        if function.generator {
+            writeln!(o, "static int dust_resume_{}(struct dust_iterator *g, dust_value *item);", i).unwrap();
+        }
+    }
+    writeln!(o).unwrap();
+
+    for (i, pattern) in program.patterns.iter().enumerate() {
+        writeln!(o, "static int dust_pattern_{}(dust_value v, dust_value *locals) {{", i).unwrap();
+        writeln!(o, "    (void)locals;").unwrap();
+        writeln!(o, "    return {};", pattern_test(pattern, "v")).unwrap();
+        writeln!(o, "}}\n").unwrap();
+    }
+
+    for (i, function) in program.functions.iter().enumerate() {
+        generate_function(o, program, i, function);
+    }
+
+    let functions = program.functions.iter()
+                                     .enumerate()
+                                     .map(|(i, f)| format!( "{{ {}, {}, dust_fn_{}, {} }}"
+                                                          , c_string(&f.name)
+                                                          , f.arity
+                                                          , i
+                                                          , if f.generator { format!("dust_resume_{}", i) } else { "NULL".to_string() }
+                                                          ));
+    table(o, "dust_function", "dust_functions", functions.collect(), "{ NULL, 0, NULL, NULL }");
+
+    let globals = program.funs.iter().map(|(name, f)| format!("{{ {}, {} }}", c_string(name), f));
+    table(o, "dust_global", "dust_globals", globals.collect(), "{ NULL, 0 }");
+
+    let methods = program.methods.iter().map(|m| format!("{{ {}, {}, {}, {} }}"
+                                                        , c_option(&m.type_name)
+                                                        , c_option(&m.trait_name)
+                                                        , c_string(&m.name)
+                                                        , m.function
+                                                        ));
+    table(o, "dust_method", "dust_methods", methods.collect(), "{ NULL, NULL, NULL, 0 }");
+
+    let traits = program.traits.iter().map(|t| c_string(t));
+    table(o, "char *const", "dust_traits", traits.collect(), "NULL");
+
+    let layouts = (0..program.types.len()).map(|i| format!("&dust_layout_{}", i));
+    table(o, "dust_layout *const", "dust_layouts", layouts.collect(), "NULL");
+
+    writeln!(o, "static const dust_program dust_program_data = {{ dust_functions, dust_globals, {}, dust_methods, {}, dust_traits, {}, dust_layouts, {} }};\n"
+            , program.funs.len(), program.methods.len(), program.traits.len(), program.types.len()).unwrap();
+
+    writeln!(o, "int main(void) {{").unwrap();
+    writeln!(o, "    dust_prog = &dust_program_data;").unwrap();
+    for (i, constant) in program.constants.iter().enumerate() {
+        let value = match constant {
+            Constant::Int(n) => format!("dust_int(INT64_C({}))", n),
+            Constant::Float(x) => format!("dust_float_bits(UINT64_C(0x{:016x}))", x.to_bits()),
+            Constant::String(s) => format!("dust_string_n({}, {})", c_string(s), s.len()),
+        };
+        writeln!(o, "    dust_constants[{}] = {};", i, value).unwrap();
+    }
+    match program.fun("main") {
+        Some(main) if program.functions[main].arity == 0 => writeln!(o, "    dust_call_function({}, 0, NULL, NULL);", main).unwrap(),
+        _ => writeln!(o, "    dust_fail(\"no `fun main()` to run\");").unwrap(),
+    }
+    writeln!(o, "    return 0;").unwrap();
+    writeln!(o, "}}").unwrap();
+
+    out
+}
+
+/// A field of a struct or enum case as a member of its C type.  The items of
+/// a tuple case have no names and are `_0`, `_1` and so on.
+struct Member {
+    name : Option<String>,
+    member : String,
+    c_type : &'static str,
+    kind : &'static str,
+}
+
+/// The C type of a struct or enum, then its layout.  The tags of an enum are
+/// named after its cases, and only the cases with fields are in its union.
+fn generate_type(o : &mut String, index : usize, info : &TypeInfo) {
+    let c_type = type_name(index, &info.name);
+    let member = |name : Option<&String>, i : usize, t : &Type| {
+        let (c_type, kind) = field_type(t, &info.type_params);
+        Member { name: name.cloned(), member: name.map_or(format!("_{}", i), |n| ident(n)), c_type, kind }
+    };
+    let named = |fields : &[(String, Type)]| fields.iter().enumerate().map(|(i, (n, t))| member(Some(n), i, t)).collect::<Vec<_>>();
+    // a struct is a single case without a name
+    let cases = match &info.shape {
+        Shape::Struct(fields) => vec![(None, "DUST_CASE_STRUCT", named(fields))],
+        Shape::Enum(cases) => cases.iter().map(|(name, shape)| match shape {
+            CaseShape::Empty => (Some(name), "DUST_CASE_EMPTY", vec![]),
+            CaseShape::Tuple(types) => (Some(name), "DUST_CASE_TUPLE", types.iter().enumerate().map(|(i, t)| member(None, i, t)).collect()),
+            CaseShape::Struct(fields) => (Some(name), "DUST_CASE_STRUCT", named(fields)),
+        }).collect(),
+    };
+    let declare = |fields : &[Member]| fields.iter().map(|f| format!("{}{};", f.c_type, f.member)).collect::<Vec<_>>().join(" ");
+
+    match &info.shape {
+        Shape::Struct(_) => {
+            writeln!(o, "/* struct {} */", info.name).unwrap();
+            writeln!(o, "typedef struct {{").unwrap();
+            // C has no empty structs
+            match declare(&cases[0].2) {
+                members if members.is_empty() => writeln!(o, "    char empty;").unwrap(),
+                members => writeln!(o, "    {}", members).unwrap(),
+            }
+            writeln!(o, "}} {};", c_type).unwrap();
+        },
+        Shape::Enum(_) => {
+            writeln!(o, "/* enum {} */", info.name).unwrap();
+            let tags = cases.iter().filter_map(|(name, _, _)| name.map(|n| case_tag(index, &info.name, n)));
+            writeln!(o, "enum {{ {} }};", tags.collect::<Vec<_>>().join(", ")).unwrap();
+            writeln!(o, "typedef struct {{").unwrap();
+            writeln!(o, "    int tag;").unwrap();
+            if cases.iter().any(|(_, _, fields)| !fields.is_empty()) {
+                writeln!(o, "    union {{").unwrap();
+                for (name, _, fields) in cases.iter().filter(|(_, _, fields)| !fields.is_empty()) {
+                    writeln!(o, "        struct {{ {} }} {};", declare(fields), name.map_or(String::new(), |n| ident(n))).unwrap();
+                }
+                writeln!(o, "    }} as;").unwrap();
+            }
+            writeln!(o, "}} {};", c_type).unwrap();
+        },
+    }
+
+    for (c, (name, _, fields)) in cases.iter().enumerate().filter(|(_, (_, _, fields))| !fields.is_empty()) {
+        let rows = fields.iter().map(|f| {
+            let path = match name {
+                Some(name) => format!("as.{}.{}", ident(name), f.member),
+                None => f.member.clone(),
+            };
+            format!("{{ {}, {}, offsetof({}, {}) }}", c_option(&f.name), f.kind, c_type, path)
+        });
+        writeln!(o, "static const dust_field dust_fields_{}_{}[] = {{ {} }};", index, c, rows.collect::<Vec<_>>().join(", ")).unwrap();
+    }
+    let rows = cases.iter().enumerate().map(|(c, (name, kind, fields))| format!( "{{ {}, {}, {}, {} }}"
+                                                                              , name.map_or("NULL".to_string(), |n| c_string(n))
+                                                                              , kind
+                                                                              , fields.len()
+                                                                              , if fields.is_empty() { "NULL".to_string() } else { format!("dust_fields_{}_{}", index, c) }
+                                                                              ));
+    writeln!(o, "static const dust_case dust_cases_{}[] = {{ {} }};", index, rows.collect::<Vec<_>>().join(", ")).unwrap();
+    writeln!(o, "static const dust_layout dust_layout_{} = {{ {}, sizeof({}), {}, {}, dust_cases_{} }};\n"
+            , index, c_string(&info.name), c_type, matches!( info.shape, Shape::Enum(_) ) as i32, cases.len(), index).unwrap();
+}
+
+/// How a field of a declared type is kept:  Bool, Int, Float and String as C
+/// values and everything else, type parameters included, as a `dust_value`.
+fn field_type(t : &Type, type_params : &[String]) -> (&'static str, &'static str) {
+    match t {
+        Type::Simple(name) if !type_params.contains(name) => match name.as_str() {
+            "Bool" => ("int ", "DUST_FIELD_BOOL"),
+            "Int" => ("int64_t ", "DUST_FIELD_INT"),
+            "Float" => ("double ", "DUST_FIELD_FLOAT"),
+            "String" => ("struct dust_string *", "DUST_FIELD_STRING"),
+            _ => ("dust_value ", "DUST_FIELD_VALUE"),
+        },
+        _ => ("dust_value ", "DUST_FIELD_VALUE"),
+    }
+}
+
+/// The C type of the declared type at `index`, which keeps types of the same
+/// name in different modules apart.
+fn type_name(index : usize, name : &str) -> String {
+    format!("dust_t{}_{}", index, ident(name))
+}
+
+fn case_tag(index : usize, type_name : &str, case_name : &str) -> String {
+    format!("dust_t{}_{}_{}", index, ident(type_name), ident(case_name))
+}
+
+/// The layout and tag of a struct or enum case, when the program declares its type.
+fn layout(program : &Program, type_name : &str, case_name : Option<&str>) -> Option<(usize, String)> {
+    let index = program.types.iter().position(|t| t.name == type_name)?;
+    match (&program.types[index].shape, case_name) {
+        (Shape::Struct(_), None) => Some((index, "0".to_string())),
+        (Shape::Enum(cases), Some(case_name)) if cases.iter().any(|(n, _)| n == case_name) => Some((index, case_tag(index, type_name, case_name))),
+        _ => None,
+    }
+}
+
+const C_KEYWORDS : &[&str] = &[ "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else", "enum"
+                              , "extern", "float", "for", "goto", "if", "inline", "int", "long", "register", "restrict", "return"
+                              , "short", "signed", "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned", "void"
+                              , "volatile", "while", "_Bool", "_Complex", "_Imaginary"
+                              ];
+
+/// A dust name as a C identifier.  Underscores are doubled and everything
+/// other than ASCII letters and digits is written as its code point between
+/// underscores, so different names stay different, and a C keyword gets an
+/// underscore after it.
+fn ident(name : &str) -> String {
+    if C_KEYWORDS.contains(&name) {
+        return format!("{}_", name);
+    }
+    let mut out = String::new();
+    for c in name.chars() {
+        match c {
+            '_' => out.push_str("__"),
+            c if c.is_ascii_alphanumeric() => out.push(c),
+            c => write!(out, "_{:x}_", c as u32).unwrap(),
+        }
+    }
+    out
+}
+
+fn table(o : &mut String, element : &str, name : &str, rows : Vec<String>, sentinel : &str) {
+    writeln!(o, "static const {} {}[] = {{", element, name).unwrap();
+    for row in rows {
+        writeln!(o, "    {},", row).unwrap();
+    }
+    writeln!(o, "    {}", sentinel).unwrap();
+    writeln!(o, "}};\n").unwrap();
+}
+
+fn generate_function(o : &mut String, program : &Program, index : usize, function : &Function) {
+    let name = |n : u32| c_string(&program.names[n as usize]);
+    let mut targets = vec![];
+    for op in &function.code {
+        match op {
+            Op::Jump(t) | Op::JumpIfFalse(t) | Op::Match(_, t) | Op::Propagate(t) | Op::Assert(_, t) | Op::Next(t) => targets.push(*t),
+            Op::Switch(s) => {
+                let table = &program.switches[*s as usize];
+                targets.extend(table.cases.iter().map(|(_, t)| *t));
+                targets.push(table.default);
+            },
+            _ => (),
+        }
+    }
+
+    writeln!(o, "/* {} */", function.name).unwrap();
+    writeln!(o, "static dust_value dust_fn_{}(dust_value *args, dust_value *upvalues) {{", index).unwrap();
+    if function.generator {
+        // calling a generator only sets up the state its resume function runs from
+        writeln!(o, "    struct dust_iterator *g = dust_generator({}, {}, {});", index, function.locals.max(1), function.code.len().max(1)).unwrap();
+        writeln!(o, "    dust_value *locals = g->locals;").unwrap();
+        writeln!(o, "    size_t i;").unwrap();
+    }
+    else {
+        writeln!(o, "    dust_value locals[{}];", function.locals.max(1)).unwrap();
+        writeln!(o, "    dust_value stack[{}];", function.code.len().max(1)).unwrap();
+        writeln!(o, "    size_t sp = 0, i;").unwrap();
+    }
+    writeln!(o, "    (void)locals, (void)upvalues;").unwrap();
+    writeln!(o, "    for (i = 0; i < {}; i++) {{", function.locals).unwrap();
+    writeln!(o, "        locals[i] = i < {} ? args[i] : dust_unit();", function.arity).unwrap();
+    writeln!(o, "    }}").unwrap();
+    for (i, capture) in function.captures.iter().enumerate() {
+        writeln!(o, "    locals[{}] = upvalues[{}];", capture.to, i).unwrap();
+    }
+    if function.generator {
+        writeln!(o, "    return dust_iterator_value(g);").unwrap();
+        writeln!(o, "}}\n").unwrap();
+        writeln!(o, "static int dust_resume_{}(struct dust_iterator *g, dust_value *item) {{", index).unwrap();
+        writeln!(o, "    dust_value *locals = g->locals, *stack = g->stack;").unwrap();
+        writeln!(o, "    size_t sp = g->sp;").unwrap();
+        writeln!(o, "    (void)locals;").unwrap();
+        writeln!(o, "    switch (g->resume) {{").unwrap();
+        for (ip, op) in function.code.iter().enumerate() {
+            if let Op::Yield = op {
+                writeln!(o, "        case {}: goto R{};", ip + 1, ip + 1).unwrap();
+            }
+        }
+        writeln!(o, "    }}").unwrap();
+    }
+
+    for (ip, op) in function.code.iter().enumerate() {
+        if targets.contains(&(ip as u32)) {
+            writeln!(o, "L{}:", ip).unwrap();
+        }
+        if let (Op::Call(_) | Op::CallFun(_, _) | Op::CallMethod(_, _), Some(at)) = (op, function.span_at(ip)) {
+            writeln!(o, "    dust_at({});", c_string(&location(program, at))).unwrap();
+        }
+        let statement = match *op {
+            Op::Constant(c) => format!("stack[sp++] = dust_constants[{}];", c),
+            Op::Unit => "stack[sp++] = dust_unit();".to_string(),
+            Op::Bool(b) => format!("stack[sp++] = dust_bool({});", b as i32),
+            Op::GetLocal(s) => format!("stack[sp++] = locals[{}];", s),
+            Op::SetLocal(s) => format!("locals[{}] = stack[--sp];", s),
+            Op::Pop => "sp--;".to_string(),
+            Op::PopN(n) => format!("sp -= {};", n),
+            Op::Fun(n) => format!("stack[sp++] = dust_global_fun({});", name(n)),
+            Op::Closure(f) => {
+                let captures = &program.functions[f as usize].captures;
+                if captures.is_empty() {
+                    format!("stack[sp++] = dust_closure({}, 0, NULL);", f)
+                }
+                else {
+                    let upvalues = captures.iter().map(|c| format!("locals[{}]", c.from)).collect::<Vec<_>>();
+                    format!("{{ dust_value up[] = {{ {} }}; stack[sp++] = dust_closure({}, {}, up); }}", upvalues.join(", "), f, upvalues.len())
+                }
+            },
+            Op::CaseFun(e, c, arity) => format!("stack[sp++] = dust_case_fun({}, {}, {});", name(e), name(c), arity),
+            Op::MethodFun(t, n) => format!("stack[sp++] = dust_method_fun({}, {});", name(t), name(n)),
+            Op::EmptyCase(e, c) => match layout(program, &program.names[e as usize], Some(&program.names[c as usize])) {
+                Some((t, tag)) => format!("stack[sp++] = dust_record_new(&dust_layout_{}, {}, NULL);", t, tag),
+                None => format!("stack[sp++] = dust_case_named({}, {}, 0, NULL);", name(e), name(c)),
+            },
+            Op::TupleCase(e, c, n) => match layout(program, &program.names[e as usize], Some(&program.names[c as usize])) {
+                Some((t, tag)) => format!("sp -= {n}; stack[sp] = dust_record_new(&dust_layout_{}, {}, &stack[sp]); sp++;", t, tag, n = n),
+                None => format!("sp -= {n}; stack[sp] = dust_case_named({}, {}, {n}, &stack[sp]); sp++;", name(e), name(c), n = n),
+            },
+            Op::Record(r) => {
+                let record = &program.records[r as usize];
+                match layout(program, &record.type_name, record.case_name.as_deref()) {
+                    Some((t, tag)) => {
+                        let mut s = format!("{{ dust_value vs[{}]; sp -= {};", record.fields.len().max(1), record.values);
+                        for (i, source) in record.sources.iter().enumerate() {
+                            write!(s, " vs[{}] = stack[sp + {}];", i, source).unwrap();
+                        }
+                        write!(s, " stack[sp++] = dust_record_new(&dust_layout_{}, {}, vs); }}", t, tag).unwrap();
+                        s
+                    },
+                    None => format!("dust_fail(\"unknown type %s\", {});", c_string(&record.type_name)),
+                }
+            },
+            Op::Tuple(n) => format!("sp -= {n}; stack[sp] = dust_tuple({n}, &stack[sp]); sp++;", n = n),
+            Op::List(n) => format!("sp -= {n}; stack[sp] = dust_list({n}, &stack[sp]); sp++;", n = n),
+            Op::Dict(n) => format!("sp -= {}; stack[sp] = dust_dict({}, &stack[sp]); sp++;", 2 * n, n),
+            Op::GetField(n) => format!("stack[sp - 1] = dust_get_field(stack[sp - 1], {});", name(n)),
+            Op::SetField(n) => format!("sp -= 2; dust_set_field(stack[sp + 1], {}, stack[sp]);", name(n)),
+            Op::Index => "sp--; stack[sp - 1] = dust_index(stack[sp - 1], stack[sp]);".to_string(),
+            Op::SetIndex => "sp -= 3; dust_set_index(stack[sp + 1], stack[sp + 2], stack[sp]);".to_string(),
+            Op::Unary(op) => format!("stack[sp - 1] = dust_unary({}, stack[sp - 1]);", unary_name(op)),
+            Op::Binary(op) => format!("sp--; stack[sp - 1] = dust_binary({}, stack[sp - 1], stack[sp]);", binary_name(op)),
+            Op::CheckBool => "dust_expect_bool(stack[sp - 1]);".to_string(),
+            Op::Jump(t) => format!("goto L{};", t),
+            Op::JumpIfFalse(t) => format!("if (!dust_expect_bool(stack[--sp])) goto L{};", t),
+            Op::Call(n) => format!("sp -= {n}; stack[sp - 1] = dust_call(stack[sp - 1], {n}, &stack[sp]);", n = n),
+            Op::CallFun(f, n) => format!("sp -= {n}; stack[sp] = dust_call_function({}, {n}, &stack[sp], NULL); sp++;", f, n = n),
+            Op::CallBuiltin(b, n) => format!("sp -= {n}; stack[sp] = dust_builtin({}, {n}, &stack[sp]); sp++;", name(b), n = n),
+            Op::CallMethod(m, n) =>
+                format!("sp -= {n}; stack[sp - 1] = dust_call_method(stack[sp - 1], {}, {n}, &stack[sp]);", name(m), n = n),
+            Op::Return if function.generator => "return 0;".to_string(),
+            Op::Return => "return stack[sp - 1];".to_string(),
+            Op::Match(p, t) => format!("if (!dust_pattern_{}(stack[--sp], locals)) goto L{};", p, t),
+            Op::Switch(s) => {
+                let table = &program.switches[s as usize];
+                let mut text = "{ const char *c = dust_case_name(stack[--sp]);".to_string();
+                for (case_name, target) in &table.cases {
+                    write!(text, " if (c && strcmp(c, {}) == 0) goto L{};", c_string(case_name), target).unwrap();
+                }
+                write!(text, " goto L{}; }}", table.default).unwrap();
+                text
+            },
+            Op::NoMatch => "dust_no_match(stack[--sp]);".to_string(),
+            Op::Propagate(t) => format!("if (dust_propagate(&stack[sp - 1])) goto L{};", t),
+            Op::Assert(s, t) => match program.asserts[s as usize].operands {
+                2 => format!("if (dust_expect_bool(dust_binary(DUST_EQ, stack[sp - 2], stack[sp - 1]))) {{ sp -= 2; goto L{}; }}", t),
+                _ => format!("if (dust_expect_bool(stack[sp - 1])) {{ sp--; goto L{}; }}", t),
+            },
+            Op::Fail(s) => {
+                let site = &program.asserts[s as usize];
+                format!("sp -= {}; dust_failed({}, {}, {}, {});"
+                       , site.operands + site.message as u32
+                       , c_string(&location(program, site.at))
+                       , site.expression.as_ref().map_or("NULL".to_string(), |e| c_string(e))
+                       , if site.message { format!("&stack[sp + {}]", site.operands) } else { "NULL".to_string() }
+                       , if site.operands == 2 { "&stack[sp]" } else { "NULL" }
+                       )
+            },
+            Op::Iterate => "stack[sp - 1] = dust_iterate(stack[sp - 1]);".to_string(),
+            Op::Next(t) => format!("if (!dust_next(stack[sp - 1], &stack[sp - 1])) {{ sp--; goto L{}; }}", t),
+            Op::Range(start, end, inclusive) => {
+                let bounds = start as u32 + end as u32;
+                let start = if start { "&stack[sp]" } else { "NULL" };
+                let end = match (end, bounds) {
+                    (false, _) => "NULL",
+                    (true, 1) => "&stack[sp]",
+                    (true, _) => "&stack[sp + 1]",
+                };
+                format!("sp -= {}; stack[sp] = dust_range({}, {}, {}); sp++;", bounds, start, end, inclusive as i32)
+            },
+            Op::Yield => format!("*item = stack[--sp]; stack[sp++] = dust_unit(); g->sp = sp; g->resume = {ip}; return 1; R{ip}:;", ip = ip + 1),
+        };
+        writeln!(o, "    {}", statement).unwrap();
+    }
+
+    writeln!(o, "    return {};", if function.generator { "0" } else { "dust_unit()" }).unwrap();
+    writeln!(o, "}}\n").unwrap();
+}
+
+/// Where a span is, for messages printed by the generated program.
+fn location(program : &Program, at : Meta) -> String {
+    program.location(at).unwrap_or_else(|| format!("byte {}", at.start))
+}
+
+/// A C expression which is true when the value `v` matches the pattern, binding variables into `locals`.
+fn pattern_test(pattern : &MatchPattern, v : &str) -> String {
+    let all = |tests : Vec<String>| tests.join(" && ");
+
+    match pattern {
+        MatchPattern::Wildcard => "1".to_string(),
+        MatchPattern::Bind(slot) => format!("(locals[{}] = {}, 1)", slot, v),
+        MatchPattern::Literal(c) => format!("dust_literal_eq(dust_constants[{}], {})", c, v),
+        MatchPattern::Bool(b) => format!("dust_is_bool({}, {})", v, *b as i32),
+        MatchPattern::Tuple(ps) => {
+            let mut tests = vec![format!("dust_is_tuple({}, {})", v, ps.len())];
+            tests.extend(ps.iter().enumerate().map(|(i, p)| pattern_test(p, &format!("dust_item({}, {})", v, i))));
+            format!("({})", all(tests))
+        },
+        MatchPattern::EmptyCase { enum_name, case_name } =>
+            format!("dust_is_case({}, {}, {}, DUST_CASE_EMPTY, 0)", v, c_option(enum_name), c_string(case_name)),
+        MatchPattern::TupleCase { enum_name, case_name, items } => {
+            let mut tests = vec![format!("dust_is_case({}, {}, {}, DUST_CASE_TUPLE, {})", v, c_option(enum_name), c_string(case_name), items.len())];
+            tests.extend(items.iter().enumerate().map(|(i, p)| pattern_test(p, &format!("dust_item({}, {})", v, i))));
+            format!("({})", all(tests))
+        },
+        MatchPattern::StructCase { enum_name, case_name, fields } => {
+            let mut tests = vec![format!("dust_is_case({}, {}, {}, DUST_CASE_STRUCT, 0)", v, c_option(enum_name), c_string(case_name))];
+            for (field, p) in fields {
+                tests.push(format!("dust_has_field({}, {})", v, c_string(field)));
+                tests.push(pattern_test(p, &format!("dust_get_field({}, {})", v, c_string(field))));
+            }
+            format!("({})", all(tests))
+        },
+        MatchPattern::Range { start, end, inclusive } => {
+            let bound = |c : &Option<u32>| c.map_or("NULL".to_string(), |c| format!("&dust_constants[{}]", c));
+            format!("dust_in_range({}, {}, {}, {})", v, bound(start), bound(end), *inclusive as i32)
+        },
+        MatchPattern::Or(ps) if ps.is_empty() => "0".to_string(),
+        MatchPattern::Or(ps) => format!("({})", ps.iter().map(|p| pattern_test(p, v)).collect::<Vec<_>>().join(" || ")),
+    }
+}
+
+fn unary_name(op : UnaryOp) -> &'static str {
+    match op {
+        UnaryOp::Neg => "DUST_NEG",
+        UnaryOp::Not => "DUST_NOT",
+    }
+}
+
+fn binary_name(op : BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "DUST_ADD",
+        BinOp::Sub => "DUST_SUB",
+        BinOp::Mul => "DUST_MUL",
+        BinOp::Div => "DUST_DIV",
+        BinOp::Rem => "DUST_REM",
+        BinOp::Eq => "DUST_EQ",
+        BinOp::NotEq => "DUST_NOT_EQ",
+        BinOp::Less => "DUST_LESS",
+        BinOp::LessEq => "DUST_LESS_EQ",
+        BinOp::Greater => "DUST_GREATER",
+        BinOp::GreaterEq => "DUST_GREATER_EQ",
+        BinOp::And => "DUST_AND",
+        BinOp::Or => "DUST_OR",
+    }
+}
+
+/// A C string literal.  Everything outside of printable ASCII is written as
+/// octal escapes of its UTF-8 bytes, and `?` is escaped so no trigraphs appear.
+pub fn c_string(s : &str) -> String {
+    let mut out = "\"".to_string();
+    for b in s.bytes() {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'?' => out.push_str("\\?"),
+            0x20..=0x7e => out.push(b as char),
+            _ => write!(out, "\\{:03o}", b).unwrap(),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn c_option(s : &Option<String>) -> String {
+    match s {
+        Some(s) => c_string(s),
+        None => "NULL".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::process::Command;
+    use crate::parsing::parser::parse;
+    use crate::compiling::compiler::compile;
+    use crate::evaluating::interpreter;
+    use crate::evaluating::conformance::CASES;
+    use crate::parsing::ast::Module;
+    use crate::stdlib;
+
+    fn linked(src : &str) -> Module {
+        let mut module = parse(src).unwrap();
+        stdlib::link(&mut module).unwrap();
+        module
+    }
+
+    /// Generates, compiles and runs a program, returning its exit code, stdout and stderr.
+    fn run_c(name : &str, src : &str) -> (i32, String, String) {
+        run_c_program(name, &compile(&linked(src)).unwrap())
+    }
+
+    fn run_c_program(name : &str, program : &Program) -> (i32, String, String) {
+        let dir = std::env::temp_dir().join(format!("dust-c-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("main.c");
+        write(program, &source).unwrap();
+        let exe = dir.join("main");
+
+        let cc = Command::new("cc").arg("-std=c99")
+                                   .arg("-pedantic")
+                                   .arg("-Wall")
+                                   .arg("-Werror")
+                                   .arg("-o").arg(&exe)
+                                   .arg(&source)
+                                   .arg("-lm")
+                                   .output()
+                                   .expect("a C compiler is needed to test the C backend");
+        assert!( cc.status.success(), "{} did not compile:\n{}", name, String::from_utf8_lossy(&cc.stderr) );
+
+        let run = Command::new(&exe).output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        ( run.status.code().unwrap_or(-1)
+        , String::from_utf8(run.stdout).unwrap()
+        , String::from_utf8(run.stderr).unwrap()
+        )
+    }
+
+    fn interpret(src : &str) -> String {
+        let mut out = vec![];
+        interpreter::run(&linked(src), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn should_match_interpreter_on_conformance_cases() {
+        for (i, case) in CASES.iter().enumerate() {
+            let (code, output, errors) = run_c(&format!("case{}", i), case.source);
+            assert_eq!( code, 0, "{} failed: {}", case.name, errors );
+            assert_eq!( output, interpret(case.source), "{}", case.name );
+        }
+    }
+
+    #[test]
+    fn should_print_values_like_the_interpreter() {
+        let src = r#"
+enum E { A, B(Int, String), C { x : Float } }
+fun main() {
+    println(0.1 + 0.2, 1.0 / 3.0, 2.5e-7, 1e21, -0.0, 1.0 / 0.0, 123456.789);
+    println(E::A, E::B(-3, "q\"uote\n"), E::C { x : 2.0 }, [E::B(1, "s")]);
+    println((1, (2.5, "x")), ["é" : [1]], "日本"[1], "日本".len());
+    println(main, E::B, || 1, 9223372036854775807, -9223372036854775807 - 1);
+}
+"#;
+        let (code, output, errors) = run_c("values", src);
+        assert_eq!( code, 0, "{}", errors );
+        assert_eq!( output, interpret(src) );
+    }
+
+    #[test]
+    fn should_run_the_standard_library_like_the_interpreter() {
+        let src = r#"
+use std::{list, string, math, fmt, dict, iter};
+fun main() {
+    let xs = [3, -1, 2, -1];
+    xs.sort();
+    xs.insert(4, 9);
+    println(xs, xs.remove(0), xs.contains(2), sort_by(["bb", "a", "cc"], |a, b| a.len() < b.len()));
+    xs.reverse();
+    println(xs, maximum(xs), ["k" : 1].values(), get(["k" : 1], "j"));
+    println("a, b,,c".split(","), "héllo".split(""), "a--b--".split("--"), " \t x y \n".trim(), "Ab1".to_upper(), "Ab1".to_lower());
+    println("hello".contains("ll"), "hello".contains(""), "hello".starts_with("he"), "hello".ends_with("lo"), "lo".ends_with("hello"));
+    println("-42".parse_int(), "+7".parse_int(), "9223372036854775807".parse_int(), "-9223372036854775808".parse_int());
+    println("9223372036854775808".parse_int(), "".parse_int(), "-".parse_int(), "1x".parse_int());
+    println((-5).abs(), 3.to_float(), (-2.5).abs(), (2.0).sqrt(), (-1.5).floor(), (1.2).ceil(), (-7.9).to_int());
+    println(format("{}/{}", [gcd(84, 36), pow(2, 10)]), join(words(" a  b "), "+"), collect(take(2, enumerate("xyz"))));
+}
+"#;
+        let (code, output, errors) = run_c("stdlib", src);
+        assert_eq!( code, 0, "{}", errors );
+        assert_eq!( output, interpret(src) );
+    }
+
+    #[test]
+    fn should_fail_with_runtime_errors() {
+        let (code, output, errors) = run_c("errors", "fun main() { println(1); let xs = [1, 2]; xs[5] }");
+        assert_eq!( code, 1 );
+        assert_eq!( output, "1\n" );
+        assert_eq!( errors, "runtime error: index 5 is out of bounds for length 2\n" );
+
+        let (code, _, errors) = run_c("overflow", "fun main() { 9223372036854775807 + 1 }");
+        assert_eq!( code, 1 );
+        assert_eq!( errors, "runtime error: integer overflow\n" );
+    }
+
+    #[test]
+    fn should_report_failed_asserts_with_a_trace() {
+        let mut program = compile(&parse("fun f(x : Int) { assert(x == 2) }\nfun main() {\n  f(1)\n}").unwrap()).unwrap();
+        let (code, _, errors) = run_c_program("assert", &program);
+        assert_eq!( code, 1 );
+        assert_eq!( errors, "byte 17: runtime error: assertion failed: x == 2\n  left: 1\n  right: 2\n  at f (byte 17)\n  at main (byte 49)\n" );
+
+        program.source = Some(SourceFile { name: "p.dust".to_string(), text: "fun f(x : Int) { assert(x == 2) }\nfun main() {\n  f(1)\n}".to_string() });
+        let (_, _, errors) = run_c_program("assert_source", &program);
+        assert!( errors.starts_with("p.dust:1:18: runtime error: ") && errors.ends_with("  at f (p.dust:1:18)\n  at main (p.dust:3:3)\n"), "{}", errors );
+
+        let (code, output, errors) = run_c("panic", "fun main() { print(1); panic(\"stop\") }");
+        assert_eq!( (code, output.as_str()), (1, "1") );
+        assert_eq!( errors, "byte 23: runtime error: panic: stop\n  at main (byte 23)\n" );
+    }
+
+    #[test]
+    fn should_lower_structs_and_enums_to_c_types() {
+        let src = r#"
+struct Point { x : Float, y : Float, label : String }
+struct Empty { }
+enum Shape<T> { Dot, Circle(Point, Float), Box { width : Int, height : Int, tag : T } }
+fun main() {
+    let p = Point { label : "p", y : 2.5, x : 1.0 };
+    p.x = p.x + 1.0;
+    let shapes = [Shape::Dot, Shape::Circle(p, 3.0), Shape::Box { height : 2, width : 4, tag : [true] }];
+    foreach s in shapes {
+        match s {
+            Shape::Box { width, height, tag } => println(width * height, tag),
+            other => println(other),
+        }
+    }
+    println(Empty { }, p == Point { x : 2.0, y : 2.5, label : "p" }, Shape::Circle);
+}
+"#;
+        let source = generate(&compile(&linked(src)).unwrap());
+        assert!( source.contains("typedef struct {\n    double x; double y; struct dust_string *label;\n} dust_t0_Point;"), "{}", source );
+        assert!( source.contains("typedef struct {\n    char empty;\n} dust_t1_Empty;"), "{}", source );
+        assert!( source.contains("enum { dust_t2_Shape_Dot, dust_t2_Shape_Circle, dust_t2_Shape_Box };\n\
+                                  typedef struct {\n    int tag;\n    union {\n\
+                                  \x20       struct { dust_value _0; double _1; } Circle;\n\
+                                  \x20       struct { int64_t width; int64_t height; dust_value tag; } Box;\n    } as;\n} dust_t2_Shape;"), "{}", source );
+        assert!( source.contains("dust_record_new(&dust_layout_2, dust_t2_Shape_Dot, NULL)"), "{}", source );
+
+        let (code, output, errors) = run_c("lowered", src);
+        assert_eq!( code, 0, "{}", errors );
+        assert_eq!( output, interpret(src) );
+    }
+
+    #[test]
+    fn should_name_c_identifiers_apart() {
+        assert_eq!( ident("Point"), "Point" );
+        assert_eq!( ident("a_b"), "a__b" );
+        assert_eq!( ident("Ωé"), "_3a9__e9_" );
+        assert_eq!( ident("int"), "int_" );
+    }
+
+    #[test]
+    fn should_escape_c_strings() {
+        assert_eq!( c_string("a\"b\\c??=\né"), r#""a\"b\\c\?\?=\012\303\251""# );
+    }
+}
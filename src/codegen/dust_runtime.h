@@ -0,0 +1,1820 @@
+/* Runtime support for C generated by the dust compiler.
+ *
+ * Values are a tagged union.  Compound values (lists, dictionaries, structs
+ * and enums) are shared by reference like they are in the dust virtual
+ * machine.  A struct or enum value is kept in the C type the generated source
+ * declares for it, a struct for a struct and a tagged union for an enum, and
+ * points to the layout of that type, which tells the runtime where each field
+ * is.  Memory is never freed; generated programs are expected to be short
+ * lived.
+ *
+ * A generator function is compiled twice:  once as a function which gives an
+ * iterator holding its locals and operand stack, and once as a function which
+ * jumps back to where the iterator last yielded and runs to the next yield.
+ *
+ * Everything here is static so the header can be included by exactly one
+ * generated C99 source file, which has to be linked with the maths library
+ * (-lm).  The functions are also inline, so that those a program never calls
+ * draw no warnings.
+ */
+#ifndef DUST_RUNTIME_H
+#define DUST_RUNTIME_H
+
+#include <math.h>
+#include <stdarg.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef enum {
+    DUST_UNIT, DUST_BOOL, DUST_INT, DUST_FLOAT, DUST_STRING, DUST_TUPLE,
+    DUST_LIST, DUST_DICT, DUST_STRUCT, DUST_ENUM, DUST_FUN, DUST_ITERATOR, DUST_RANGE
+} dust_tag;
+
+struct dust_string;
+struct dust_seq;
+struct dust_dict;
+struct dust_record;
+struct dust_fun;
+struct dust_iterator;
+struct dust_range;
+
+typedef struct {
+    dust_tag tag;
+    union {
+        int b;
+        int64_t i;
+        double f;
+        struct dust_string *s;
+        struct dust_seq *seq;
+        struct dust_dict *dict;
+        struct dust_record *rec;
+        struct dust_fun *fun;
+        struct dust_iterator *it;
+        struct dust_range *range;
+    } as;
+} dust_value;
+
+struct dust_string { size_t len; char *data; };
+struct dust_seq { size_t len, cap; dust_value *items; };
+struct dust_dict { size_t len, cap; dust_value *keys; dust_value *values; };
+/* A missing bound is 0 so that ranges can be compared field by field. */
+struct dust_range { int has_start, has_end, inclusive; int64_t start, end; };
+
+enum { DUST_CASE_EMPTY, DUST_CASE_TUPLE, DUST_CASE_STRUCT };
+
+/* How a field is kept in the C type of its struct or enum:  Bool, Int, Float
+ * and String fields as C values, and fields of any other type as values. */
+enum { DUST_FIELD_VALUE, DUST_FIELD_BOOL, DUST_FIELD_INT, DUST_FIELD_FLOAT, DUST_FIELD_STRING };
+
+/* The fields of a tuple case have no names. */
+typedef struct { const char *name; int kind; size_t offset; } dust_field;
+typedef struct { const char *name; int kind; size_t len; const dust_field *fields; } dust_case;
+
+/* A struct has a single case without a name and always has named fields.  An
+ * enum is tagged, and the first member of its C type is the index of its case. */
+typedef struct {
+    const char *name;
+    size_t size;
+    int tagged;
+    size_t case_count;
+    const dust_case *cases;
+} dust_layout;
+
+struct dust_record {
+    const dust_layout *layout;
+    void *data;
+};
+
+/* Option as the runtime makes it when the program does not declare it. */
+typedef struct { int tag; union { struct { dust_value _0; } Some; } as; } dust_option;
+static const dust_field dust_option_some[] = { { NULL, DUST_FIELD_VALUE, offsetof(dust_option, as.Some._0) } };
+static const dust_case dust_option_cases[] = { { "None", DUST_CASE_EMPTY, 0, NULL }, { "Some", DUST_CASE_TUPLE, 1, dust_option_some } };
+static const dust_layout dust_option_layout = { "Option", sizeof(dust_option), 1, 2, dust_option_cases };
+
+enum { DUST_FUN_GLOBAL, DUST_FUN_CASE, DUST_FUN_METHOD, DUST_FUN_CLOSURE };
+
+struct dust_fun {
+    int kind;
+    const char *owner;
+    const char *name;
+    size_t function;
+    size_t arity;
+    size_t count;
+    dust_value *upvalues;
+};
+
+enum {
+    DUST_ITER_ITEMS, DUST_ITER_CHARS, DUST_ITER_KEYS, DUST_ITER_NEXT, DUST_ITER_COUNT,
+    DUST_ITER_GENERATOR, DUST_ITER_RUNNING, DUST_ITER_FINISHED
+};
+
+/* `source` and `index` step through a list, string or dictionary, or hold a
+ * value with a `next` method.  Counting through a range goes from `next` up to
+ * and including `last`, unless the range in `source` has no end.  A generator
+ * keeps the point to resume at along with its locals and operand stack. */
+struct dust_iterator {
+    int kind;
+    dust_value source;
+    size_t index;
+    int64_t next, last;
+    size_t function;
+    size_t resume;
+    size_t sp;
+    dust_value *locals;
+    dust_value *stack;
+};
+
+typedef dust_value (*dust_code)(dust_value *args, dust_value *upvalues);
+/* Runs a generator to its next yield, returning 0 when it finishes instead. */
+typedef int (*dust_resume)(struct dust_iterator *g, dust_value *item);
+
+typedef struct { const char *name; size_t arity; dust_code code; dust_resume resume; } dust_function;
+typedef struct { const char *name; size_t function; } dust_global;
+typedef struct { const char *type_name; const char *trait_name; const char *name; size_t function; } dust_method;
+
+typedef struct {
+    const dust_function *functions;
+    const dust_global *globals;
+    size_t global_count;
+    const dust_method *methods;
+    size_t method_count;
+    const char *const *traits;
+    size_t trait_count;
+    const dust_layout *const *layouts;
+    size_t layout_count;
+} dust_program;
+
+static const dust_program *dust_prog;
+
+enum { DUST_NEG, DUST_NOT };
+enum {
+    DUST_ADD, DUST_SUB, DUST_MUL, DUST_DIV, DUST_REM, DUST_EQ, DUST_NOT_EQ,
+    DUST_LESS, DUST_LESS_EQ, DUST_GREATER, DUST_GREATER_EQ, DUST_AND, DUST_OR
+};
+
+static const char *const dust_op_names[] = { "+", "-", "*", "/", "%", "==", "!=", "<", "<=", ">", ">=", "&&", "||" };
+
+/* Errors */
+
+static inline void dust_fail(const char *format, ...) {
+    va_list args;
+    fflush(stdout);
+    fputs("runtime error: ", stderr);
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fputc('\n', stderr);
+    exit(1);
+}
+
+/* The functions being run, each with where it last made a call, so that a
+ * failed assert or panic can show how it was reached.  Calls deeper than
+ * the array are counted but left out of the trace. */
+#define DUST_MAX_FRAMES 1024
+typedef struct { const char *function; const char *site; } dust_frame;
+static dust_frame dust_frames[DUST_MAX_FRAMES];
+static size_t dust_depth;
+
+static inline void dust_at(const char *site) {
+    if (dust_depth - 1 < DUST_MAX_FRAMES) {
+        dust_frames[dust_depth - 1].site = site;
+    }
+}
+
+static inline void *dust_alloc(size_t size) {
+    void *p = calloc(1, size ? size : 1);
+    if (!p) {
+        dust_fail("out of memory");
+    }
+    return p;
+}
+
+/* Text */
+
+typedef struct { size_t len, cap; char *data; } dust_buf;
+
+static inline void dust_buf_put(dust_buf *b, const char *s, size_t n) {
+    if (b->len + n + 1 > b->cap) {
+        size_t cap = b->cap ? b->cap : 32;
+        char *data;
+        while (cap < b->len + n + 1) {
+            cap *= 2;
+        }
+        data = dust_alloc(cap);
+        if (b->data) {
+            memcpy(data, b->data, b->len);
+        }
+        b->data = data;
+        b->cap = cap;
+    }
+    memcpy(b->data + b->len, s, n);
+    b->len += n;
+    b->data[b->len] = '\0';
+}
+
+static inline void dust_buf_puts(dust_buf *b, const char *s) {
+    dust_buf_put(b, s, strlen(s));
+}
+
+/* Floats print like Rust prints them:  whole numbers with one decimal and
+   everything else with the shortest digits which read back as the same
+   number, never in exponent notation. */
+static inline void dust_buf_float(dust_buf *b, double x) {
+    char digits[32], text[400];
+    int precision, exponent, n, i;
+    char *e;
+
+    if (x != x) {
+        dust_buf_puts(b, "NaN");
+        return;
+    }
+    if (x == HUGE_VAL || x == -HUGE_VAL) {
+        dust_buf_puts(b, x > 0 ? "inf" : "-inf");
+        return;
+    }
+    if (x == floor(x)) {
+        snprintf(text, sizeof text, "%.1f", x);
+        dust_buf_puts(b, text);
+        return;
+    }
+
+    for (precision = 1; precision <= 17; precision++) {
+        snprintf(text, sizeof text, "%.*e", precision - 1, x);
+        if (strtod(text, NULL) == x) {
+            break;
+        }
+    }
+
+    e = strchr(text, 'e');
+    exponent = atoi(e + 1);
+    n = 0;
+    for (i = 0; text + i < e; i++) {
+        if (text[i] >= '0' && text[i] <= '9') {
+            digits[n++] = text[i];
+        }
+    }
+    while (n > 1 && digits[n - 1] == '0') {
+        n--;
+    }
+
+    if (x < 0) {
+        dust_buf_puts(b, "-");
+    }
+    if (exponent < 0) {
+        dust_buf_puts(b, "0.");
+        for (i = 0; i < -exponent - 1; i++) {
+            dust_buf_puts(b, "0");
+        }
+        dust_buf_put(b, digits, n);
+    }
+    else {
+        for (i = 0; i <= exponent; i++) {
+            dust_buf_put(b, i < n ? &digits[i] : "0", 1);
+        }
+        dust_buf_puts(b, ".");
+        dust_buf_put(b, digits + exponent + 1, n - exponent - 1);
+    }
+}
+
+/* Constructors */
+
+static inline dust_value dust_unit(void) {
+    dust_value v;
+    v.tag = DUST_UNIT;
+    v.as.i = 0;
+    return v;
+}
+
+static inline dust_value dust_bool(int b) {
+    dust_value v;
+    v.tag = DUST_BOOL;
+    v.as.b = b != 0;
+    return v;
+}
+
+static inline dust_value dust_int(int64_t i) {
+    dust_value v;
+    v.tag = DUST_INT;
+    v.as.i = i;
+    return v;
+}
+
+static inline dust_value dust_float(double f) {
+    dust_value v;
+    v.tag = DUST_FLOAT;
+    v.as.f = f;
+    return v;
+}
+
+static inline dust_value dust_float_bits(uint64_t bits) {
+    double f;
+    memcpy(&f, &bits, sizeof f);
+    return dust_float(f);
+}
+
+static inline dust_value dust_string_n(const char *s, size_t len) {
+    dust_value v;
+    v.tag = DUST_STRING;
+    v.as.s = dust_alloc(sizeof *v.as.s);
+    v.as.s->data = dust_alloc(len + 1);
+    memcpy(v.as.s->data, s, len);
+    v.as.s->len = len;
+    return v;
+}
+
+static inline dust_value dust_string(const char *s) {
+    return dust_string_n(s, strlen(s));
+}
+
+static inline struct dust_seq *dust_seq_new(size_t n, const dust_value *items) {
+    struct dust_seq *seq = dust_alloc(sizeof *seq);
+    seq->items = dust_alloc(n * sizeof *items);
+    if (n) {
+        memcpy(seq->items, items, n * sizeof *items);
+    }
+    seq->len = seq->cap = n;
+    return seq;
+}
+
+static inline dust_value dust_tuple(size_t n, const dust_value *items) {
+    dust_value v;
+    if (n == 0) {
+        return dust_unit();
+    }
+    v.tag = DUST_TUPLE;
+    v.as.seq = dust_seq_new(n, items);
+    return v;
+}
+
+static inline dust_value dust_list(size_t n, const dust_value *items) {
+    dust_value v;
+    v.tag = DUST_LIST;
+    v.as.seq = dust_seq_new(n, items);
+    return v;
+}
+
+static inline void dust_seq_push(struct dust_seq *seq, dust_value item) {
+    if (seq->len == seq->cap) {
+        size_t cap = seq->cap ? seq->cap * 2 : 4;
+        dust_value *items = dust_alloc(cap * sizeof *items);
+        if (seq->len) {
+            memcpy(items, seq->items, seq->len * sizeof *items);
+        }
+        seq->items = items;
+        seq->cap = cap;
+    }
+    seq->items[seq->len++] = item;
+}
+
+static inline const char *dust_type_name(dust_value v);
+
+static inline const dust_case *dust_case_of(const struct dust_record *r) {
+    return &r->layout->cases[r->layout->tagged ? *(const int *)r->data : 0];
+}
+
+static inline dust_value dust_load(const struct dust_record *r, size_t i) {
+    const dust_field *f = &dust_case_of(r)->fields[i];
+    const char *p = (const char *)r->data + f->offset;
+    dust_value v;
+    switch (f->kind) {
+        case DUST_FIELD_BOOL: return dust_bool(*(const int *)p);
+        case DUST_FIELD_INT: return dust_int(*(const int64_t *)p);
+        case DUST_FIELD_FLOAT: return dust_float(*(const double *)p);
+        case DUST_FIELD_STRING:
+            v.tag = DUST_STRING;
+            v.as.s = *(struct dust_string *const *)p;
+            return v;
+        default: return *(const dust_value *)p;
+    }
+}
+
+static inline void dust_store(struct dust_record *r, size_t i, dust_value v) {
+    static const dust_tag tags[] = { DUST_UNIT, DUST_BOOL, DUST_INT, DUST_FLOAT, DUST_STRING };
+    static const char *const names[] = { "", "Bool", "Int", "Float", "String" };
+    const dust_field *f = &dust_case_of(r)->fields[i];
+    char *p = (char *)r->data + f->offset;
+    if (f->kind != DUST_FIELD_VALUE && v.tag != tags[f->kind]) {
+        dust_fail("expected %s but found %s", names[f->kind], dust_type_name(v));
+    }
+    switch (f->kind) {
+        case DUST_FIELD_BOOL: *(int *)p = v.as.b; break;
+        case DUST_FIELD_INT: *(int64_t *)p = v.as.i; break;
+        case DUST_FIELD_FLOAT: *(double *)p = v.as.f; break;
+        case DUST_FIELD_STRING: *(struct dust_string **)p = v.as.s; break;
+        default: *(dust_value *)p = v; break;
+    }
+}
+
+/* A value of case `c` of a struct or enum, given its fields in the order they are declared. */
+static inline dust_value dust_record_new(const dust_layout *layout, int c, const dust_value *values) {
+    dust_value v;
+    size_t i;
+    v.tag = layout->tagged ? DUST_ENUM : DUST_STRUCT;
+    v.as.rec = dust_alloc(sizeof *v.as.rec);
+    v.as.rec->layout = layout;
+    v.as.rec->data = dust_alloc(layout->size);
+    if (layout->tagged) {
+        *(int *)v.as.rec->data = c;
+    }
+    for (i = 0; i < layout->cases[c].len; i++) {
+        dust_store(v.as.rec, i, values[i]);
+    }
+    return v;
+}
+
+static inline const dust_layout *dust_layout_named(const char *name) {
+    size_t i;
+    for (i = 0; i < dust_prog->layout_count; i++) {
+        if (strcmp(dust_prog->layouts[i]->name, name) == 0) {
+            return dust_prog->layouts[i];
+        }
+    }
+    if (strcmp(name, "Option") == 0) {
+        return &dust_option_layout;
+    }
+    dust_fail("unknown type %s", name);
+    return NULL;
+}
+
+/* A case of an enum the generated source could not name, or which the runtime makes. */
+static inline dust_value dust_case_named(const char *enum_name, const char *case_name, size_t n, const dust_value *values) {
+    const dust_layout *layout = dust_layout_named(enum_name);
+    size_t c;
+    for (c = 0; c < layout->case_count; c++) {
+        if (layout->cases[c].name && strcmp(layout->cases[c].name, case_name) == 0) {
+            if (layout->cases[c].len != n) {
+                dust_fail("%s::%s expects %lu arguments but was given %lu", enum_name, case_name,
+                          (unsigned long)layout->cases[c].len, (unsigned long)n);
+            }
+            return dust_record_new(layout, (int)c, values);
+        }
+    }
+    dust_fail("%s has no case %s", enum_name, case_name);
+    return dust_unit();
+}
+
+static inline dust_value dust_fun_new(int kind, const char *owner, const char *name, size_t function, size_t arity,
+                               size_t count, const dust_value *upvalues) {
+    dust_value v;
+    v.tag = DUST_FUN;
+    v.as.fun = dust_alloc(sizeof *v.as.fun);
+    v.as.fun->kind = kind;
+    v.as.fun->owner = owner;
+    v.as.fun->name = name;
+    v.as.fun->function = function;
+    v.as.fun->arity = arity;
+    v.as.fun->count = count;
+    v.as.fun->upvalues = dust_alloc(count * sizeof *upvalues);
+    if (count) {
+        memcpy(v.as.fun->upvalues, upvalues, count * sizeof *upvalues);
+    }
+    return v;
+}
+
+static inline dust_value dust_global_fun(const char *name) {
+    return dust_fun_new(DUST_FUN_GLOBAL, NULL, name, 0, 0, 0, NULL);
+}
+
+static inline dust_value dust_case_fun(const char *enum_name, const char *case_name, size_t arity) {
+    return dust_fun_new(DUST_FUN_CASE, enum_name, case_name, 0, arity, 0, NULL);
+}
+
+static inline dust_value dust_method_fun(const char *owner, const char *name) {
+    return dust_fun_new(DUST_FUN_METHOD, owner, name, 0, 0, 0, NULL);
+}
+
+static inline dust_value dust_closure(size_t function, size_t count, const dust_value *upvalues) {
+    return dust_fun_new(DUST_FUN_CLOSURE, NULL, "closure", function, 0, count, upvalues);
+}
+
+/* Inspecting values */
+
+static inline const char *dust_type_name(dust_value v) {
+    switch (v.tag) {
+        case DUST_UNIT: return "Unit";
+        case DUST_BOOL: return "Bool";
+        case DUST_INT: return "Int";
+        case DUST_FLOAT: return "Float";
+        case DUST_STRING: return "String";
+        case DUST_TUPLE: return "Tuple";
+        case DUST_LIST: return "List";
+        case DUST_DICT: return "Dict";
+        case DUST_STRUCT: return v.as.rec->layout->name;
+        case DUST_ENUM: return v.as.rec->layout->name;
+        case DUST_FUN: return "Fun";
+        case DUST_ITERATOR: return "Iterator";
+        case DUST_RANGE: return "Range";
+    }
+    return "?";
+}
+
+static inline int dust_eq(dust_value a, dust_value b);
+
+static inline int dust_seq_eq(const struct dust_seq *a, const struct dust_seq *b) {
+    size_t i;
+    if (a == b) {
+        return 1;
+    }
+    if (a->len != b->len) {
+        return 0;
+    }
+    for (i = 0; i < a->len; i++) {
+        if (!dust_eq(a->items[i], b->items[i])) {
+            return 0;
+        }
+    }
+    return 1;
+}
+
+static inline dust_value *dust_dict_find(const struct dust_dict *d, dust_value key) {
+    size_t i;
+    for (i = 0; i < d->len; i++) {
+        if (dust_eq(d->keys[i], key)) {
+            return &d->values[i];
+        }
+    }
+    return NULL;
+}
+
+static inline int dust_eq(dust_value a, dust_value b) {
+    size_t i;
+    if (a.tag == DUST_INT && b.tag == DUST_FLOAT) {
+        return (double)a.as.i == b.as.f;
+    }
+    if (a.tag == DUST_FLOAT && b.tag == DUST_INT) {
+        return a.as.f == (double)b.as.i;
+    }
+    if (a.tag != b.tag) {
+        return 0;
+    }
+    switch (a.tag) {
+        case DUST_UNIT: return 1;
+        case DUST_BOOL: return a.as.b == b.as.b;
+        case DUST_INT: return a.as.i == b.as.i;
+        case DUST_FLOAT: return a.as.f == b.as.f;
+        case DUST_STRING: return a.as.s->len == b.as.s->len && memcmp(a.as.s->data, b.as.s->data, a.as.s->len) == 0;
+        case DUST_TUPLE:
+        case DUST_LIST: return dust_seq_eq(a.as.seq, b.as.seq);
+        case DUST_DICT:
+            if (a.as.dict == b.as.dict) {
+                return 1;
+            }
+            if (a.as.dict->len != b.as.dict->len) {
+                return 0;
+            }
+            for (i = 0; i < a.as.dict->len; i++) {
+                dust_value *v = dust_dict_find(b.as.dict, a.as.dict->keys[i]);
+                if (!v || !dust_eq(a.as.dict->values[i], *v)) {
+                    return 0;
+                }
+            }
+            return 1;
+        case DUST_STRUCT:
+        case DUST_ENUM: {
+            const dust_case *ca, *cb;
+            if (a.as.rec == b.as.rec) {
+                return 1;
+            }
+            ca = dust_case_of(a.as.rec);
+            cb = dust_case_of(b.as.rec);
+            if (strcmp(a.as.rec->layout->name, b.as.rec->layout->name) != 0 || ca->kind != cb->kind || ca->len != cb->len) {
+                return 0;
+            }
+            if (a.tag == DUST_ENUM && strcmp(ca->name, cb->name) != 0) {
+                return 0;
+            }
+            for (i = 0; i < ca->len; i++) {
+                if (ca->fields[i].name && strcmp(ca->fields[i].name, cb->fields[i].name) != 0) {
+                    return 0;
+                }
+                if (!dust_eq(dust_load(a.as.rec, i), dust_load(b.as.rec, i))) {
+                    return 0;
+                }
+            }
+            return 1;
+        }
+        case DUST_FUN: return a.as.fun == b.as.fun;
+        case DUST_ITERATOR: return a.as.it == b.as.it;
+        case DUST_RANGE:
+            return a.as.range->has_start == b.as.range->has_start && a.as.range->has_end == b.as.range->has_end
+                && a.as.range->inclusive == b.as.range->inclusive
+                && a.as.range->start == b.as.range->start && a.as.range->end == b.as.range->end;
+    }
+    return 0;
+}
+
+static inline void dust_show(dust_buf *b, dust_value v);
+
+static inline void dust_display(dust_buf *b, dust_value v) {
+    char text[32];
+    size_t i;
+    switch (v.tag) {
+        case DUST_UNIT:
+            dust_buf_puts(b, "()");
+            break;
+        case DUST_BOOL:
+            dust_buf_puts(b, v.as.b ? "true" : "false");
+            break;
+        case DUST_INT:
+            snprintf(text, sizeof text, "%lld", (long long)v.as.i);
+            dust_buf_puts(b, text);
+            break;
+        case DUST_FLOAT:
+            dust_buf_float(b, v.as.f);
+            break;
+        case DUST_STRING:
+            dust_buf_put(b, v.as.s->data, v.as.s->len);
+            break;
+        case DUST_TUPLE:
+        case DUST_LIST:
+            dust_buf_puts(b, v.tag == DUST_TUPLE ? "(" : "[");
+            for (i = 0; i < v.as.seq->len; i++) {
+                if (i) {
+                    dust_buf_puts(b, ", ");
+                }
+                dust_show(b, v.as.seq->items[i]);
+            }
+            dust_buf_puts(b, v.tag == DUST_TUPLE ? ")" : "]");
+            break;
+        case DUST_DICT:
+            if (v.as.dict->len == 0) {
+                dust_buf_puts(b, "[:]");
+                break;
+            }
+            dust_buf_puts(b, "[");
+            for (i = 0; i < v.as.dict->len; i++) {
+                if (i) {
+                    dust_buf_puts(b, ", ");
+                }
+                dust_show(b, v.as.dict->keys[i]);
+                dust_buf_puts(b, " : ");
+                dust_show(b, v.as.dict->values[i]);
+            }
+            dust_buf_puts(b, "]");
+            break;
+        case DUST_STRUCT:
+        case DUST_ENUM: {
+            const dust_case *c = dust_case_of(v.as.rec);
+            dust_buf_puts(b, v.as.rec->layout->name);
+            if (v.tag == DUST_ENUM) {
+                dust_buf_puts(b, "::");
+                dust_buf_puts(b, c->name);
+            }
+            if (c->kind == DUST_CASE_TUPLE) {
+                dust_buf_puts(b, "(");
+            }
+            else if (c->kind == DUST_CASE_STRUCT) {
+                dust_buf_puts(b, " { ");
+            }
+            for (i = 0; i < c->len; i++) {
+                if (i) {
+                    dust_buf_puts(b, ", ");
+                }
+                if (c->kind == DUST_CASE_STRUCT) {
+                    dust_buf_puts(b, c->fields[i].name);
+                    dust_buf_puts(b, " : ");
+                }
+                dust_show(b, dust_load(v.as.rec, i));
+            }
+            if (c->kind == DUST_CASE_TUPLE) {
+                dust_buf_puts(b, ")");
+            }
+            else if (c->kind == DUST_CASE_STRUCT) {
+                dust_buf_puts(b, " }");
+            }
+            break;
+        }
+        case DUST_FUN:
+            switch (v.as.fun->kind) {
+                case DUST_FUN_GLOBAL:
+                    dust_buf_puts(b, "<fun ");
+                    dust_buf_puts(b, v.as.fun->name);
+                    dust_buf_puts(b, ">");
+                    break;
+                case DUST_FUN_CLOSURE:
+                    dust_buf_puts(b, "<closure>");
+                    break;
+                default:
+                    dust_buf_puts(b, "<fun ");
+                    dust_buf_puts(b, v.as.fun->owner);
+                    dust_buf_puts(b, "::");
+                    dust_buf_puts(b, v.as.fun->name);
+                    dust_buf_puts(b, ">");
+                    break;
+            }
+            break;
+        case DUST_ITERATOR:
+            dust_buf_puts(b, "<iterator>");
+            break;
+        case DUST_RANGE:
+            if (v.as.range->has_start) {
+                snprintf(text, sizeof text, "%lld", (long long)v.as.range->start);
+                dust_buf_puts(b, text);
+            }
+            dust_buf_puts(b, v.as.range->inclusive ? "..=" : "..");
+            if (v.as.range->has_end) {
+                snprintf(text, sizeof text, "%lld", (long long)v.as.range->end);
+                dust_buf_puts(b, text);
+            }
+            break;
+    }
+}
+
+/* Strings are quoted and escaped inside of other values. */
+static inline void dust_show(dust_buf *b, dust_value v) {
+    size_t i;
+    char text[16];
+    if (v.tag != DUST_STRING) {
+        dust_display(b, v);
+        return;
+    }
+    dust_buf_puts(b, "\"");
+    for (i = 0; i < v.as.s->len; i++) {
+        unsigned char c = (unsigned char)v.as.s->data[i];
+        switch (c) {
+            case '"': dust_buf_puts(b, "\\\""); break;
+            case '\\': dust_buf_puts(b, "\\\\"); break;
+            case '\n': dust_buf_puts(b, "\\n"); break;
+            case '\r': dust_buf_puts(b, "\\r"); break;
+            case '\t': dust_buf_puts(b, "\\t"); break;
+            case '\0': dust_buf_puts(b, "\\0"); break;
+            default:
+                if (c < 0x20 || c == 0x7f) {
+                    snprintf(text, sizeof text, "\\u{%x}", c);
+                    dust_buf_puts(b, text);
+                }
+                else {
+                    dust_buf_put(b, (const char *)&c, 1);
+                }
+        }
+    }
+    dust_buf_puts(b, "\"");
+}
+
+static inline const char *dust_show_text(dust_value v) {
+    dust_buf b = { 0, 0, NULL };
+    dust_show(&b, v);
+    return b.data;
+}
+
+static inline const char *dust_display_text(dust_value v) {
+    dust_buf b = { 0, 0, NULL };
+    dust_display(&b, v);
+    return b.data ? b.data : "";
+}
+
+/* Operators */
+
+static inline int dust_expect_bool(dust_value v) {
+    if (v.tag != DUST_BOOL) {
+        dust_fail("expected Bool but found %s", dust_type_name(v));
+    }
+    return v.as.b;
+}
+
+static inline dust_value dust_unary(int op, dust_value v) {
+    if (op == DUST_NEG && v.tag == DUST_INT) {
+        if (v.as.i == INT64_MIN) {
+            dust_fail("integer overflow");
+        }
+        return dust_int(-v.as.i);
+    }
+    if (op == DUST_NEG && v.tag == DUST_FLOAT) {
+        return dust_float(-v.as.f);
+    }
+    if (op == DUST_NOT && v.tag == DUST_BOOL) {
+        return dust_bool(!v.as.b);
+    }
+    dust_fail("expected %s but found %s", op == DUST_NEG ? "number" : "Bool", dust_type_name(v));
+    return v;
+}
+
+static inline int dust_mul_overflows(int64_t a, int64_t b) {
+    if (a == 0 || b == 0) {
+        return 0;
+    }
+    if (a > 0) {
+        return b > 0 ? a > INT64_MAX / b : b < INT64_MIN / a;
+    }
+    return b > 0 ? a < INT64_MIN / b : a < INT64_MAX / b;
+}
+
+static inline dust_value dust_int_op(int op, int64_t a, int64_t b) {
+    switch (op) {
+        case DUST_ADD:
+            if ((b > 0 && a > INT64_MAX - b) || (b < 0 && a < INT64_MIN - b)) {
+                dust_fail("integer overflow");
+            }
+            return dust_int(a + b);
+        case DUST_SUB:
+            if ((b < 0 && a > INT64_MAX + b) || (b > 0 && a < INT64_MIN + b)) {
+                dust_fail("integer overflow");
+            }
+            return dust_int(a - b);
+        case DUST_MUL:
+            if (dust_mul_overflows(a, b)) {
+                dust_fail("integer overflow");
+            }
+            return dust_int(a * b);
+        case DUST_DIV:
+        case DUST_REM:
+            if (b == 0) {
+                dust_fail("division by zero");
+            }
+            if (a == INT64_MIN && b == -1) {
+                dust_fail("integer overflow");
+            }
+            return dust_int(op == DUST_DIV ? a / b : a % b);
+        case DUST_LESS: return dust_bool(a < b);
+        case DUST_LESS_EQ: return dust_bool(a <= b);
+        case DUST_GREATER: return dust_bool(a > b);
+        default: return dust_bool(a >= b);
+    }
+}
+
+static inline dust_value dust_float_op(int op, double a, double b) {
+    switch (op) {
+        case DUST_ADD: return dust_float(a + b);
+        case DUST_SUB: return dust_float(a - b);
+        case DUST_MUL: return dust_float(a * b);
+        case DUST_DIV: return dust_float(a / b);
+        case DUST_REM: return dust_float(fmod(a, b));
+        case DUST_LESS: return dust_bool(a < b);
+        case DUST_LESS_EQ: return dust_bool(a <= b);
+        case DUST_GREATER: return dust_bool(a > b);
+        default: return dust_bool(a >= b);
+    }
+}
+
+static inline int dust_string_cmp(const struct dust_string *a, const struct dust_string *b) {
+    size_t n = a->len < b->len ? a->len : b->len;
+    int c = memcmp(a->data, b->data, n);
+    if (c != 0) {
+        return c;
+    }
+    return a->len < b->len ? -1 : a->len > b->len ? 1 : 0;
+}
+
+static inline dust_value dust_binary(int op, dust_value l, dust_value r) {
+    if (op == DUST_EQ) {
+        return dust_bool(dust_eq(l, r));
+    }
+    if (op == DUST_NOT_EQ) {
+        return dust_bool(!dust_eq(l, r));
+    }
+    if (op == DUST_AND || op == DUST_OR) {
+        if (l.tag == DUST_BOOL && r.tag == DUST_BOOL) {
+            return dust_bool(op == DUST_AND ? l.as.b && r.as.b : l.as.b || r.as.b);
+        }
+    }
+    else if (l.tag == DUST_INT && r.tag == DUST_INT) {
+        return dust_int_op(op, l.as.i, r.as.i);
+    }
+    else if ((l.tag == DUST_INT || l.tag == DUST_FLOAT) && (r.tag == DUST_INT || r.tag == DUST_FLOAT)) {
+        return dust_float_op(op, l.tag == DUST_INT ? (double)l.as.i : l.as.f, r.tag == DUST_INT ? (double)r.as.i : r.as.f);
+    }
+    else if (op == DUST_ADD && l.tag == DUST_STRING && r.tag == DUST_STRING) {
+        dust_value v = dust_string_n(l.as.s->data, l.as.s->len);
+        dust_buf b = { 0, 0, NULL };
+        dust_buf_put(&b, l.as.s->data, l.as.s->len);
+        dust_buf_put(&b, r.as.s->data, r.as.s->len);
+        v.as.s->data = b.data;
+        v.as.s->len = b.len;
+        return v;
+    }
+    else if (op == DUST_ADD && l.tag == DUST_LIST && r.tag == DUST_LIST) {
+        dust_value v = dust_list(l.as.seq->len, l.as.seq->items);
+        size_t i;
+        for (i = 0; i < r.as.seq->len; i++) {
+            dust_seq_push(v.as.seq, r.as.seq->items[i]);
+        }
+        return v;
+    }
+    else if (op >= DUST_LESS && op <= DUST_GREATER_EQ && l.tag == DUST_STRING && r.tag == DUST_STRING) {
+        int c = dust_string_cmp(l.as.s, r.as.s);
+        switch (op) {
+            case DUST_LESS: return dust_bool(c < 0);
+            case DUST_LESS_EQ: return dust_bool(c <= 0);
+            case DUST_GREATER: return dust_bool(c > 0);
+            default: return dust_bool(c >= 0);
+        }
+    }
+    dust_fail("cannot apply %s to %s and %s", dust_op_names[op], dust_type_name(l), dust_type_name(r));
+    return l;
+}
+
+/* Ranges */
+
+static inline dust_value dust_range(const dust_value *start, const dust_value *end, int inclusive) {
+    dust_value v;
+    if (start && start->tag != DUST_INT) {
+        dust_fail("expected Int but found %s", dust_type_name(*start));
+    }
+    if (end && end->tag != DUST_INT) {
+        dust_fail("expected Int but found %s", dust_type_name(*end));
+    }
+    v.tag = DUST_RANGE;
+    v.as.range = dust_alloc(sizeof *v.as.range);
+    v.as.range->has_start = start != NULL;
+    v.as.range->has_end = end != NULL;
+    v.as.range->inclusive = inclusive;
+    v.as.range->start = start ? start->as.i : 0;
+    v.as.range->end = end ? end->as.i : 0;
+    return v;
+}
+
+/* Where a range slices a sequence of `length` items, checking that both ends are within it. */
+static inline void dust_slice_bounds(const struct dust_range *r, size_t length, size_t *start, size_t *end) {
+    int64_t s = r->has_start ? r->start : 0;
+    int64_t e = r->has_end ? r->end : (int64_t)length;
+    if (r->has_end && r->inclusive) {
+        if (e == INT64_MAX) {
+            dust_fail("index %lld is out of bounds for length %lu", (long long)e, (unsigned long)length);
+        }
+        e++;
+    }
+    if (s < 0 || (uint64_t)s > length) {
+        dust_fail("index %lld is out of bounds for length %lu", (long long)s, (unsigned long)length);
+    }
+    if (e < 0 || (uint64_t)e > length) {
+        dust_fail("index %lld is out of bounds for length %lu", (long long)(r->has_end ? r->end : e), (unsigned long)length);
+    }
+    if (s > e) {
+        dust_fail("slice starts at %lld but ends at %lld", (long long)s, (long long)e);
+    }
+    *start = (size_t)s;
+    *end = (size_t)e;
+}
+
+/* Indexing and fields */
+
+static inline size_t dust_position(dust_value index, size_t length) {
+    if (index.tag != DUST_INT) {
+        dust_fail("expected Int but found %s", dust_type_name(index));
+    }
+    if (index.as.i < 0 || (uint64_t)index.as.i >= length) {
+        dust_fail("index %lld is out of bounds for length %lu", (long long)index.as.i, (unsigned long)length);
+    }
+    return (size_t)index.as.i;
+}
+
+/* The byte offset of each character of a UTF-8 string, followed by its length. */
+static inline size_t dust_chars(const struct dust_string *s, size_t *offsets) {
+    size_t i, n = 0;
+    for (i = 0; i < s->len; i++) {
+        if (((unsigned char)s->data[i] & 0xc0) != 0x80) {
+            if (offsets) {
+                offsets[n] = i;
+            }
+            n++;
+        }
+    }
+    if (offsets) {
+        offsets[n] = s->len;
+    }
+    return n;
+}
+
+static inline void dust_dict_insert(struct dust_dict *d, dust_value key, dust_value value) {
+    dust_value *slot = dust_dict_find(d, key);
+    if (slot) {
+        *slot = value;
+        return;
+    }
+    if (d->len == d->cap) {
+        size_t cap = d->cap ? d->cap * 2 : 4;
+        dust_value *keys = dust_alloc(cap * sizeof *keys), *values = dust_alloc(cap * sizeof *values);
+        if (d->len) {
+            memcpy(keys, d->keys, d->len * sizeof *keys);
+            memcpy(values, d->values, d->len * sizeof *values);
+        }
+        d->keys = keys;
+        d->values = values;
+        d->cap = cap;
+    }
+    d->keys[d->len] = key;
+    d->values[d->len] = value;
+    d->len++;
+}
+
+/* Takes keys and values alternately. */
+static inline dust_value dust_dict(size_t n, const dust_value *pairs) {
+    dust_value v;
+    size_t i;
+    v.tag = DUST_DICT;
+    v.as.dict = dust_alloc(sizeof *v.as.dict);
+    for (i = 0; i < n; i++) {
+        dust_dict_insert(v.as.dict, pairs[2 * i], pairs[2 * i + 1]);
+    }
+    return v;
+}
+
+static inline dust_value dust_index(dust_value v, dust_value index) {
+    size_t start, end;
+    if (index.tag == DUST_RANGE && v.tag == DUST_LIST) {
+        dust_slice_bounds(index.as.range, v.as.seq->len, &start, &end);
+        return dust_list(end - start, v.as.seq->items + start);
+    }
+    if (index.tag == DUST_RANGE && v.tag == DUST_STRING) {
+        size_t *offsets = dust_alloc((v.as.s->len + 1) * sizeof *offsets);
+        dust_slice_bounds(index.as.range, dust_chars(v.as.s, offsets), &start, &end);
+        return dust_string_n(v.as.s->data + offsets[start], offsets[end] - offsets[start]);
+    }
+    switch (v.tag) {
+        case DUST_LIST:
+        case DUST_TUPLE:
+            return v.as.seq->items[dust_position(index, v.as.seq->len)];
+        case DUST_STRING: {
+            size_t *offsets = dust_alloc((v.as.s->len + 1) * sizeof *offsets);
+            size_t i = dust_position(index, dust_chars(v.as.s, offsets));
+            return dust_string_n(v.as.s->data + offsets[i], offsets[i + 1] - offsets[i]);
+        }
+        case DUST_DICT: {
+            dust_value *found = dust_dict_find(v.as.dict, index);
+            if (!found) {
+                dust_fail("key %s not found", dust_show_text(index));
+            }
+            return *found;
+        }
+        default:
+            dust_fail("expected List, Dict, Tuple or String but found %s", dust_type_name(v));
+            return v;
+    }
+}
+
+static inline void dust_set_index(dust_value target, dust_value index, dust_value value) {
+    switch (target.tag) {
+        case DUST_LIST:
+            target.as.seq->items[dust_position(index, target.as.seq->len)] = value;
+            break;
+        case DUST_DICT:
+            dust_dict_insert(target.as.dict, index, value);
+            break;
+        default:
+            dust_fail("expected List or Dict but found %s", dust_type_name(target));
+    }
+}
+
+/* Finds a field of a struct or struct case by name, returning 0 when it has none. */
+static inline int dust_field_index(dust_value v, const char *name, size_t *index) {
+    const dust_case *c;
+    size_t i;
+    if (v.tag != DUST_STRUCT && v.tag != DUST_ENUM) {
+        return 0;
+    }
+    c = dust_case_of(v.as.rec);
+    if (c->kind != DUST_CASE_STRUCT) {
+        return 0;
+    }
+    for (i = 0; i < c->len; i++) {
+        if (strcmp(c->fields[i].name, name) == 0) {
+            *index = i;
+            return 1;
+        }
+    }
+    return 0;
+}
+
+static inline dust_value dust_get_field(dust_value v, const char *name) {
+    size_t i;
+    if (!dust_field_index(v, name, &i)) {
+        dust_fail("%s has no field %s", dust_type_name(v), name);
+    }
+    return dust_load(v.as.rec, i);
+}
+
+static inline void dust_set_field(dust_value target, const char *name, dust_value value) {
+    size_t i;
+    if (!dust_field_index(target, name, &i)) {
+        dust_fail("%s has no field %s", dust_type_name(target), name);
+    }
+    dust_store(target.as.rec, i, value);
+}
+
+/* Patterns */
+
+static inline int dust_literal_eq(dust_value literal, dust_value v) {
+    if (literal.tag == DUST_STRING && v.tag != DUST_STRING) {
+        return 0;
+    }
+    return dust_eq(literal, v);
+}
+
+/* Whether a number is within the bounds of a range pattern, which are number constants. */
+static inline int dust_in_range(dust_value v, const dust_value *start, const dust_value *end, int inclusive) {
+    if (v.tag != DUST_INT && v.tag != DUST_FLOAT) {
+        return 0;
+    }
+    if (start && dust_binary(DUST_LESS, v, *start).as.b) {
+        return 0;
+    }
+    if (end) {
+        return inclusive ? !dust_binary(DUST_LESS, *end, v).as.b : dust_binary(DUST_LESS, v, *end).as.b;
+    }
+    return 1;
+}
+
+static inline int dust_is_bool(dust_value v, int b) {
+    return v.tag == DUST_BOOL && v.as.b == b;
+}
+
+static inline int dust_is_tuple(dust_value v, size_t n) {
+    return v.tag == DUST_TUPLE && v.as.seq->len == n;
+}
+
+/* Without an enum name a struct case pattern also matches structs named like the case. */
+static inline int dust_is_case(dust_value v, const char *enum_name, const char *case_name, int kind, size_t n) {
+    const dust_case *c;
+    if (v.tag == DUST_STRUCT) {
+        return !enum_name && kind == DUST_CASE_STRUCT && strcmp(v.as.rec->layout->name, case_name) == 0;
+    }
+    if (v.tag != DUST_ENUM) {
+        return 0;
+    }
+    c = dust_case_of(v.as.rec);
+    if (strcmp(c->name, case_name) != 0 || c->kind != kind) {
+        return 0;
+    }
+    if (enum_name && strcmp(v.as.rec->layout->name, enum_name) != 0) {
+        return 0;
+    }
+    return kind != DUST_CASE_TUPLE || c->len == n;
+}
+
+static inline dust_value dust_item(dust_value v, size_t i) {
+    return v.tag == DUST_TUPLE ? v.as.seq->items[i] : dust_load(v.as.rec, i);
+}
+
+static inline int dust_has_field(dust_value v, const char *name) {
+    size_t i;
+    return dust_field_index(v, name, &i);
+}
+
+static inline const char *dust_case_name(dust_value v) {
+    return v.tag == DUST_ENUM ? dust_case_of(v.as.rec)->name : NULL;
+}
+
+static inline void dust_no_match(dust_value v) {
+    dust_fail("no match arm matches %s", dust_show_text(v));
+}
+
+/* `?` replaces `Ok(x)` with `x` and is true for it, and leaves `Err(e)` as it is. */
+static inline int dust_propagate(dust_value *v) {
+    if (dust_is_case(*v, NULL, "Ok", DUST_CASE_TUPLE, 1)) {
+        *v = dust_load(v->as.rec, 0);
+        return 1;
+    }
+    if (!dust_is_case(*v, NULL, "Err", DUST_CASE_TUPLE, 1)) {
+        dust_fail("expected Ok or Err but found %s", dust_type_name(*v));
+    }
+    return 0;
+}
+
+/* Asserts and panics.  A panic has no expression and the sides are those of
+ * an asserted `==`. */
+static inline void dust_failed(const char *at, const char *expression, const dust_value *message, const dust_value *sides) {
+    size_t i;
+    fflush(stdout);
+    fprintf(stderr, "%s: runtime error: ", at);
+    if (expression) {
+        fprintf(stderr, "assertion failed: %s", expression);
+        if (message) {
+            fprintf(stderr, ": %s", dust_display_text(*message));
+        }
+    }
+    else {
+        fprintf(stderr, "panic: %s", message ? dust_display_text(*message) : "");
+    }
+    if (sides) {
+        fprintf(stderr, "\n  left: %s\n  right: %s", dust_show_text(sides[0]), dust_show_text(sides[1]));
+    }
+    fputc('\n', stderr);
+    for (i = dust_depth < DUST_MAX_FRAMES ? dust_depth : DUST_MAX_FRAMES; i-- > 0;) {
+        const char *site = i + 1 == dust_depth ? at : dust_frames[i].site;
+        fprintf(stderr, "  at %s (%s)\n", dust_frames[i].function, site ? site : "?");
+    }
+    exit(1);
+}
+
+/* Calls */
+
+static inline void dust_arity(const char *name, size_t expected, size_t found) {
+    if (expected != found) {
+        dust_fail("%s expects %lu arguments but was given %lu", name, (unsigned long)expected, (unsigned long)found);
+    }
+}
+
+static inline dust_value dust_call_function(size_t index, size_t argc, dust_value *args, dust_value *upvalues) {
+    const dust_function *f = &dust_prog->functions[index];
+    dust_value result;
+    dust_arity(f->name, f->arity, argc);
+    if (dust_depth < DUST_MAX_FRAMES) {
+        dust_frames[dust_depth].function = f->name;
+        dust_frames[dust_depth].site = NULL;
+    }
+    dust_depth++;
+    result = f->code(args, upvalues);
+    dust_depth--;
+    return result;
+}
+
+static inline int dust_builtin_exists(const char *name) {
+    return strcmp(name, "print") == 0 || strcmp(name, "println") == 0;
+}
+
+static inline dust_value dust_builtin(const char *name, size_t argc, dust_value *args) {
+    dust_buf b = { 0, 0, NULL };
+    size_t i;
+    if (!dust_builtin_exists(name)) {
+        dust_fail("%s cannot be called from a compiled executable", name);
+    }
+    for (i = 0; i < argc; i++) {
+        if (i) {
+            dust_buf_puts(&b, " ");
+        }
+        dust_display(&b, args[i]);
+    }
+    if (strcmp(name, "println") == 0) {
+        dust_buf_puts(&b, "\n");
+    }
+    if (b.len) {
+        fwrite(b.data, 1, b.len, stdout);
+    }
+    return dust_unit();
+}
+
+/* Inherent methods win over trait methods for the type, which win over
+   blanket impls.  More than one candidate trait is ambiguous. */
+static inline int dust_find_method(const char *type_name, const char *name, size_t *function) {
+    size_t i, count = 0;
+    int pass;
+    dust_buf traits = { 0, 0, NULL };
+
+    for (i = 0; i < dust_prog->method_count; i++) {
+        const dust_method *m = &dust_prog->methods[i];
+        if (m->type_name && !m->trait_name && strcmp(m->type_name, type_name) == 0 && strcmp(m->name, name) == 0) {
+            *function = m->function;
+            count++;
+        }
+    }
+    if (count) {
+        return 1;
+    }
+
+    for (pass = 0; pass < 2; pass++) {
+        count = 0;
+        for (i = 0; i < dust_prog->method_count; i++) {
+            const dust_method *m = &dust_prog->methods[i];
+            int for_type = pass == 0 ? m->type_name && strcmp(m->type_name, type_name) == 0 : !m->type_name;
+            if (for_type && m->trait_name && strcmp(m->name, name) == 0) {
+                if (count) {
+                    dust_buf_puts(&traits, ", ");
+                }
+                dust_buf_puts(&traits, m->trait_name);
+                *function = m->function;
+                count++;
+            }
+        }
+        if (count == 1) {
+            return 1;
+        }
+        if (count > 1) {
+            dust_fail("method %s of %s is ambiguous between traits %s", name, type_name, traits.data);
+        }
+    }
+    return 0;
+}
+
+/* Iteration */
+
+static inline dust_value dust_call_method(dust_value receiver, const char *name, size_t argc, dust_value *args);
+
+static inline dust_value dust_iterator_value(struct dust_iterator *it) {
+    dust_value v;
+    v.tag = DUST_ITERATOR;
+    v.as.it = it;
+    return v;
+}
+
+static inline dust_value dust_iterate(dust_value v) {
+    struct dust_iterator *it;
+    if (v.tag == DUST_ITERATOR) {
+        return v;
+    }
+    it = dust_alloc(sizeof *it);
+    it->source = v;
+    switch (v.tag) {
+        case DUST_LIST: it->kind = DUST_ITER_ITEMS; break;
+        case DUST_STRING: it->kind = DUST_ITER_CHARS; break;
+        case DUST_DICT: it->kind = DUST_ITER_KEYS; break;
+        case DUST_STRUCT:
+        case DUST_ENUM: it->kind = DUST_ITER_NEXT; break;
+        case DUST_RANGE:
+            if (!v.as.range->has_start) {
+                dust_fail("a range without a start cannot be iterated");
+            }
+            it->kind = DUST_ITER_COUNT;
+            it->next = v.as.range->start;
+            it->last = v.as.range->end;
+            if (v.as.range->has_end && !v.as.range->inclusive) {
+                if (it->last == INT64_MIN) {
+                    it->kind = DUST_ITER_FINISHED;
+                }
+                else {
+                    it->last--;
+                }
+            }
+            break;
+        default: dust_fail("%s is not iterable", dust_type_name(v));
+    }
+    return dust_iterator_value(it);
+}
+
+/* A suspended call of a generator which has not started yet. */
+static inline struct dust_iterator *dust_generator(size_t function, size_t locals, size_t stack) {
+    struct dust_iterator *g = dust_alloc(sizeof *g);
+    g->kind = DUST_ITER_GENERATOR;
+    g->function = function;
+    g->locals = dust_alloc(locals * sizeof *g->locals);
+    g->stack = dust_alloc(stack * sizeof *g->stack);
+    return g;
+}
+
+/* Takes the next item from an iterator, returning 0 when there are no more. */
+static inline int dust_next(dust_value v, dust_value *item) {
+    struct dust_iterator *it;
+    int more = 0;
+    if (v.tag != DUST_ITERATOR) {
+        dust_fail("%s is not iterable", dust_type_name(v));
+    }
+    it = v.as.it;
+    switch (it->kind) {
+        case DUST_ITER_ITEMS:
+            more = it->index < it->source.as.seq->len;
+            if (more) {
+                *item = it->source.as.seq->items[it->index++];
+            }
+            break;
+        case DUST_ITER_CHARS: {
+            const struct dust_string *s = it->source.as.s;
+            size_t end = it->index;
+            more = end < s->len;
+            if (more) {
+                do {
+                    end++;
+                } while (end < s->len && ((unsigned char)s->data[end] & 0xc0) == 0x80);
+                *item = dust_string_n(s->data + it->index, end - it->index);
+                it->index = end;
+            }
+            break;
+        }
+        case DUST_ITER_KEYS:
+            more = it->index < it->source.as.dict->len;
+            if (more) {
+                *item = it->source.as.dict->keys[it->index++];
+            }
+            break;
+        case DUST_ITER_NEXT: {
+            dust_value option = dust_call_method(it->source, "next", 0, NULL);
+            if (dust_is_case(option, NULL, "Some", DUST_CASE_TUPLE, 1)) {
+                *item = dust_load(option.as.rec, 0);
+                return 1;
+            }
+            if (!dust_is_case(option, NULL, "None", DUST_CASE_EMPTY, 0)) {
+                dust_fail("expected Some or None but found %s", dust_type_name(option));
+            }
+            return 0;
+        }
+        case DUST_ITER_COUNT:
+            more = !it->source.as.range->has_end || it->next <= it->last;
+            if (more) {
+                *item = dust_int(it->next);
+                /* after INT64_MAX there is nothing left to count */
+                if (it->next == INT64_MAX) {
+                    it->kind = DUST_ITER_FINISHED;
+                    return 1;
+                }
+                it->next++;
+            }
+            break;
+        case DUST_ITER_GENERATOR: {
+            const dust_function *f = &dust_prog->functions[it->function];
+            it->kind = DUST_ITER_RUNNING;
+            if (dust_depth < DUST_MAX_FRAMES) {
+                dust_frames[dust_depth].function = f->name;
+                dust_frames[dust_depth].site = NULL;
+            }
+            dust_depth++;
+            more = f->resume(it, item);
+            dust_depth--;
+            it->kind = DUST_ITER_GENERATOR;
+            break;
+        }
+        case DUST_ITER_RUNNING:
+            dust_fail("a generator cannot be resumed while it is running");
+            break;
+        case DUST_ITER_FINISHED:
+            return 0;
+    }
+    if (!more) {
+        it->kind = DUST_ITER_FINISHED;
+    }
+    return more;
+}
+
+/* Native methods */
+
+static inline const struct dust_string *dust_expect_string(dust_value v) {
+    if (v.tag != DUST_STRING) {
+        dust_fail("expected String but found %s", dust_type_name(v));
+    }
+    return v.as.s;
+}
+
+/* Where `needle` next appears in `s` at or after byte `from`, or `s->len`. */
+static inline size_t dust_find_text(const struct dust_string *s, const struct dust_string *needle, size_t from) {
+    size_t i;
+    for (i = from; i + needle->len <= s->len; i++) {
+        if (memcmp(s->data + i, needle->data, needle->len) == 0) {
+            return i;
+        }
+    }
+    return s->len;
+}
+
+static inline dust_value dust_char_list(const struct dust_string *s) {
+    size_t *offsets = dust_alloc((s->len + 1) * sizeof *offsets);
+    size_t i, n = dust_chars(s, offsets);
+    dust_value list = dust_list(0, NULL);
+    for (i = 0; i < n; i++) {
+        dust_seq_push(list.as.seq, dust_string_n(s->data + offsets[i], offsets[i + 1] - offsets[i]));
+    }
+    return list;
+}
+
+/* An empty separator splits between characters. */
+static inline dust_value dust_split(const struct dust_string *s, const struct dust_string *separator) {
+    dust_value list;
+    size_t from = 0;
+    if (separator->len == 0) {
+        return dust_char_list(s);
+    }
+    list = dust_list(0, NULL);
+    for (;;) {
+        size_t at = dust_find_text(s, separator, from);
+        dust_seq_push(list.as.seq, dust_string_n(s->data + from, at - from));
+        if (at == s->len) {
+            return list;
+        }
+        from = at + separator->len;
+    }
+}
+
+static inline int dust_is_space(char c) {
+    return c == ' ' || c == '\t' || c == '\n' || c == '\f' || c == '\r';
+}
+
+static inline dust_value dust_trim(const struct dust_string *s) {
+    size_t start = 0, end = s->len;
+    while (start < end && dust_is_space(s->data[start])) {
+        start++;
+    }
+    while (end > start && dust_is_space(s->data[end - 1])) {
+        end--;
+    }
+    return dust_string_n(s->data + start, end - start);
+}
+
+static inline dust_value dust_ascii_case(const struct dust_string *s, int upper) {
+    dust_value v = dust_string_n(s->data, s->len);
+    size_t i;
+    for (i = 0; i < s->len; i++) {
+        char c = v.as.s->data[i];
+        if (upper && c >= 'a' && c <= 'z') {
+            v.as.s->data[i] = (char)(c - 'a' + 'A');
+        }
+        if (!upper && c >= 'A' && c <= 'Z') {
+            v.as.s->data[i] = (char)(c - 'A' + 'a');
+        }
+    }
+    return v;
+}
+
+/* A decimal integer with an optional sign, as `Option::Some` or `Option::None`. */
+static inline dust_value dust_parse_int(const struct dust_string *s) {
+    size_t i = 0;
+    int negative = 0;
+    uint64_t n = 0, limit;
+    dust_value v;
+    if (i < s->len && (s->data[i] == '+' || s->data[i] == '-')) {
+        negative = s->data[i] == '-';
+        i++;
+    }
+    limit = negative ? (uint64_t)INT64_MAX + 1 : (uint64_t)INT64_MAX;
+    if (i == s->len) {
+        return dust_case_named("Option", "None", 0, NULL);
+    }
+    for (; i < s->len; i++) {
+        unsigned digit = (unsigned char)s->data[i] - '0';
+        if (digit > 9 || n > (limit - digit) / 10) {
+            return dust_case_named("Option", "None", 0, NULL);
+        }
+        n = n * 10 + digit;
+    }
+    v = dust_int(negative ? (int64_t)(0 - n) : (int64_t)n);
+    return dust_case_named("Option", "Some", 1, &v);
+}
+
+/* An insertion sort by `<`, making the same comparisons as the dust runtime. */
+static inline void dust_sort(struct dust_seq *seq) {
+    size_t i, at;
+    for (i = 1; i < seq->len; i++) {
+        dust_value v = seq->items[i];
+        at = i;
+        while (at > 0 && dust_expect_bool(dust_binary(DUST_LESS, v, seq->items[at - 1]))) {
+            seq->items[at] = seq->items[at - 1];
+            at--;
+        }
+        seq->items[at] = v;
+    }
+}
+
+static inline dust_value dust_native_method(dust_value receiver, const char *name, size_t argc, dust_value *args, int *found) {
+    *found = 1;
+    if (strcmp(name, "to_string") == 0) {
+        dust_buf b = { 0, 0, NULL };
+        dust_arity(name, 0, argc);
+        dust_display(&b, receiver);
+        return dust_string_n(b.data ? b.data : "", b.len);
+    }
+    if (strcmp(name, "len") == 0 && (receiver.tag == DUST_LIST || receiver.tag == DUST_TUPLE)) {
+        dust_arity(name, 0, argc);
+        return dust_int((int64_t)receiver.as.seq->len);
+    }
+    if (strcmp(name, "len") == 0 && receiver.tag == DUST_STRING) {
+        dust_arity(name, 0, argc);
+        return dust_int((int64_t)dust_chars(receiver.as.s, NULL));
+    }
+    if (strcmp(name, "len") == 0 && receiver.tag == DUST_DICT) {
+        dust_arity(name, 0, argc);
+        return dust_int((int64_t)receiver.as.dict->len);
+    }
+    if (receiver.tag == DUST_LIST && strcmp(name, "push") == 0) {
+        dust_arity(name, 1, argc);
+        dust_seq_push(receiver.as.seq, args[0]);
+        return dust_unit();
+    }
+    if (receiver.tag == DUST_LIST && strcmp(name, "pop") == 0) {
+        dust_arity(name, 0, argc);
+        if (receiver.as.seq->len == 0) {
+            dust_fail("index -1 is out of bounds for length 0");
+        }
+        return receiver.as.seq->items[--receiver.as.seq->len];
+    }
+    if (receiver.tag == DUST_DICT && strcmp(name, "insert") == 0) {
+        dust_arity(name, 2, argc);
+        dust_dict_insert(receiver.as.dict, args[0], args[1]);
+        return dust_unit();
+    }
+    if (receiver.tag == DUST_DICT && strcmp(name, "contains") == 0) {
+        dust_arity(name, 1, argc);
+        return dust_bool(dust_dict_find(receiver.as.dict, args[0]) != NULL);
+    }
+    if (receiver.tag == DUST_DICT && strcmp(name, "remove") == 0) {
+        struct dust_dict *d = receiver.as.dict;
+        size_t i;
+        dust_arity(name, 1, argc);
+        for (i = 0; i < d->len; i++) {
+            if (dust_eq(d->keys[i], args[0])) {
+                dust_value v = d->values[i];
+                memmove(&d->keys[i], &d->keys[i + 1], (d->len - i - 1) * sizeof *d->keys);
+                memmove(&d->values[i], &d->values[i + 1], (d->len - i - 1) * sizeof *d->values);
+                d->len--;
+                return v;
+            }
+        }
+        dust_fail("key %s not found", dust_show_text(args[0]));
+    }
+    if (receiver.tag == DUST_DICT && strcmp(name, "keys") == 0) {
+        dust_arity(name, 0, argc);
+        return dust_list(receiver.as.dict->len, receiver.as.dict->keys);
+    }
+    if (receiver.tag == DUST_LIST && strcmp(name, "contains") == 0) {
+        size_t i;
+        dust_arity(name, 1, argc);
+        for (i = 0; i < receiver.as.seq->len; i++) {
+            if (dust_eq(receiver.as.seq->items[i], args[0])) {
+                return dust_bool(1);
+            }
+        }
+        return dust_bool(0);
+    }
+    if (receiver.tag == DUST_LIST && strcmp(name, "reverse") == 0) {
+        struct dust_seq *seq = receiver.as.seq;
+        size_t i;
+        dust_arity(name, 0, argc);
+        for (i = 0; i < seq->len / 2; i++) {
+            dust_value v = seq->items[i];
+            seq->items[i] = seq->items[seq->len - 1 - i];
+            seq->items[seq->len - 1 - i] = v;
+        }
+        return dust_unit();
+    }
+    if (receiver.tag == DUST_LIST && strcmp(name, "sort") == 0) {
+        dust_arity(name, 0, argc);
+        dust_sort(receiver.as.seq);
+        return dust_unit();
+    }
+    if (receiver.tag == DUST_LIST && strcmp(name, "insert") == 0) {
+        struct dust_seq *seq = receiver.as.seq;
+        size_t at;
+        dust_arity(name, 2, argc);
+        at = args[0].tag == DUST_INT && args[0].as.i == (int64_t)seq->len ? seq->len : dust_position(args[0], seq->len);
+        dust_seq_push(seq, args[1]);
+        memmove(&seq->items[at + 1], &seq->items[at], (seq->len - at - 1) * sizeof *seq->items);
+        seq->items[at] = args[1];
+        return dust_unit();
+    }
+    if (receiver.tag == DUST_LIST && strcmp(name, "remove") == 0) {
+        struct dust_seq *seq = receiver.as.seq;
+        size_t at;
+        dust_value v;
+        dust_arity(name, 1, argc);
+        at = dust_position(args[0], seq->len);
+        v = seq->items[at];
+        memmove(&seq->items[at], &seq->items[at + 1], (seq->len - at - 1) * sizeof *seq->items);
+        seq->len--;
+        return v;
+    }
+    if (receiver.tag == DUST_DICT && strcmp(name, "values") == 0) {
+        dust_arity(name, 0, argc);
+        return dust_list(receiver.as.dict->len, receiver.as.dict->values);
+    }
+    if (receiver.tag == DUST_STRING) {
+        const struct dust_string *s = receiver.as.s;
+        if (strcmp(name, "chars") == 0) {
+            dust_arity(name, 0, argc);
+            return dust_char_list(s);
+        }
+        if (strcmp(name, "split") == 0) {
+            dust_arity(name, 1, argc);
+            return dust_split(s, dust_expect_string(args[0]));
+        }
+        if (strcmp(name, "trim") == 0) {
+            dust_arity(name, 0, argc);
+            return dust_trim(s);
+        }
+        if (strcmp(name, "contains") == 0) {
+            const struct dust_string *needle;
+            dust_arity(name, 1, argc);
+            needle = dust_expect_string(args[0]);
+            return dust_bool(needle->len <= s->len && dust_find_text(s, needle, 0) + needle->len <= s->len);
+        }
+        if (strcmp(name, "starts_with") == 0) {
+            const struct dust_string *prefix;
+            dust_arity(name, 1, argc);
+            prefix = dust_expect_string(args[0]);
+            return dust_bool(prefix->len <= s->len && memcmp(s->data, prefix->data, prefix->len) == 0);
+        }
+        if (strcmp(name, "ends_with") == 0) {
+            const struct dust_string *suffix;
+            dust_arity(name, 1, argc);
+            suffix = dust_expect_string(args[0]);
+            return dust_bool(suffix->len <= s->len && memcmp(s->data + s->len - suffix->len, suffix->data, suffix->len) == 0);
+        }
+        if (strcmp(name, "to_upper") == 0 || strcmp(name, "to_lower") == 0) {
+            dust_arity(name, 0, argc);
+            return dust_ascii_case(s, name[3] == 'u');
+        }
+        if (strcmp(name, "parse_int") == 0) {
+            dust_arity(name, 0, argc);
+            return dust_parse_int(s);
+        }
+    }
+    if (receiver.tag == DUST_INT && strcmp(name, "abs") == 0) {
+        dust_arity(name, 0, argc);
+        if (receiver.as.i == INT64_MIN) {
+            dust_fail("integer overflow");
+        }
+        return dust_int(receiver.as.i < 0 ? -receiver.as.i : receiver.as.i);
+    }
+    if (receiver.tag == DUST_INT && strcmp(name, "to_float") == 0) {
+        dust_arity(name, 0, argc);
+        return dust_float((double)receiver.as.i);
+    }
+    if (receiver.tag == DUST_FLOAT) {
+        double x = receiver.as.f;
+        if (strcmp(name, "abs") == 0) {
+            dust_arity(name, 0, argc);
+            return dust_float(fabs(x));
+        }
+        if (strcmp(name, "sqrt") == 0) {
+            dust_arity(name, 0, argc);
+            return dust_float(sqrt(x));
+        }
+        if (strcmp(name, "floor") == 0) {
+            dust_arity(name, 0, argc);
+            return dust_float(floor(x));
+        }
+        if (strcmp(name, "ceil") == 0) {
+            dust_arity(name, 0, argc);
+            return dust_float(ceil(x));
+        }
+        if (strcmp(name, "to_int") == 0) {
+            dust_arity(name, 0, argc);
+            if (x != x || x >= 9223372036854775808.0 || x < -9223372036854775808.0) {
+                dust_fail("integer overflow");
+            }
+            return dust_int((int64_t)x);
+        }
+    }
+    if (receiver.tag == DUST_ITERATOR && strcmp(name, "next") == 0) {
+        dust_value item;
+        dust_arity(name, 0, argc);
+        if (dust_next(receiver, &item)) {
+            return dust_case_named("Option", "Some", 1, &item);
+        }
+        return dust_case_named("Option", "None", 0, NULL);
+    }
+    *found = 0;
+    return dust_unit();
+}
+
+static inline dust_value dust_call_method(dust_value receiver, const char *name, size_t argc, dust_value *args) {
+    size_t function, i;
+    int found;
+    dust_value result;
+
+    if (dust_find_method(dust_type_name(receiver), name, &function)) {
+        dust_value *all = dust_alloc((argc + 1) * sizeof *all);
+        all[0] = receiver;
+        for (i = 0; i < argc; i++) {
+            all[i + 1] = args[i];
+        }
+        return dust_call_function(function, argc + 1, all, NULL);
+    }
+    result = dust_native_method(receiver, name, argc, args, &found);
+    if (!found) {
+        dust_fail("%s has no method %s", dust_type_name(receiver), name);
+    }
+    return result;
+}
+
+static inline int dust_is_trait(const char *name) {
+    size_t i;
+    for (i = 0; i < dust_prog->trait_count; i++) {
+        if (strcmp(dust_prog->traits[i], name) == 0) {
+            return 1;
+        }
+    }
+    return 0;
+}
+
+static inline dust_value dust_call(dust_value fun, size_t argc, dust_value *args) {
+    size_t i, function;
+    struct dust_fun *f;
+
+    if (fun.tag != DUST_FUN) {
+        dust_fail("%s is not callable", dust_type_name(fun));
+    }
+    f = fun.as.fun;
+
+    switch (f->kind) {
+        case DUST_FUN_GLOBAL:
+            for (i = 0; i < dust_prog->global_count; i++) {
+                if (strcmp(dust_prog->globals[i].name, f->name) == 0) {
+                    return dust_call_function(dust_prog->globals[i].function, argc, args, NULL);
+                }
+            }
+            if (dust_builtin_exists(f->name)) {
+                return dust_builtin(f->name, argc, args);
+            }
+            dust_fail("unknown function %s", f->name);
+            break;
+        case DUST_FUN_CASE:
+            if (argc != f->arity) {
+                dust_fail("%s::%s expects %lu arguments but was given %lu", f->owner, f->name, (unsigned long)f->arity, (unsigned long)argc);
+            }
+            return dust_case_named(f->owner, f->name, argc, args);
+        case DUST_FUN_METHOD:
+            if (dust_is_trait(f->owner)) {
+                if (argc == 0) {
+                    dust_fail("%s::%s expects 1 arguments but was given 0", f->owner, f->name);
+                }
+                return dust_call_method(args[0], f->name, argc - 1, args + 1);
+            }
+            if (dust_find_method(f->owner, f->name, &function)) {
+                return dust_call_function(function, argc, args, NULL);
+            }
+            dust_fail("%s has no method %s", f->owner, f->name);
+            break;
+        case DUST_FUN_CLOSURE:
+            return dust_call_function(f->function, argc, args, f->upvalues);
+    }
+    return dust_unit();
+}
+
+#endif
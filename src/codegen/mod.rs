@@ -0,0 +1,4 @@
+
+pub mod c;
+pub mod wat;
+pub mod wat_check;
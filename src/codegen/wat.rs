@@ -0,0 +1,618 @@
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::parsing::ast::*;
+use crate::evaluating::ops;
+use crate::evaluating::value::Value;
+use crate::evaluating::consts::{self, Consts};
+
+/* Lowers a subset of dust to the WebAssembly text format.  The subset is what
+   maps directly onto wasm:  functions over `Int` (i64), `Float` (f64) and
+   `Bool` (i32), structs of those allocated in linear memory and passed around
+   as i32 pointers, and `if`, `while`, `break`, `continue`, `return`, `let`,
+   assignment and calls between such functions.  Anything else is reported as
+   unsupported rather than approximated.
+
+   Unlike the virtual machine, integer arithmetic wraps on overflow and
+   division by zero traps.  Every struct field takes eight bytes and memory is
+   handed out by a bump allocator which never frees.
+
+   The output is checked by `wat_check` rather than run, as no wasm engine is
+   among the dependencies.
+*/
+
+#[derive(Debug, PartialEq)]
+pub enum WatError {
+    Unsupported { function : String, what : String },
+    UnknownVariable { function : String, name : String },
+    TypeMismatch { function : String, expected : String, found : String },
+}
+
+impl fmt::Display for WatError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WatError::Unsupported { function, what } => write!(f, "{}: {} cannot be compiled to WebAssembly", function, what),
+            WatError::UnknownVariable { function, name } => write!(f, "{}: unknown variable {}", function, name),
+            WatError::TypeMismatch { function, expected, found } => write!(f, "{}: expected {} but found {}", function, expected, found),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum WType {
+    Bool,
+    Int,
+    Float,
+    Struct(String),
+    Unit,
+    /// The type of `break`, `continue` and `return`, which never produce a value.
+    Never,
+}
+
+impl WType {
+    fn wasm(&self) -> Option<&'static str> {
+        match self {
+            WType::Bool | WType::Struct(_) => Some("i32"),
+            WType::Int => Some("i64"),
+            WType::Float => Some("f64"),
+            WType::Unit | WType::Never => None,
+        }
+    }
+
+    fn result(&self) -> String {
+        match self.wasm() {
+            Some(t) => format!(" (result {})", t),
+            None => String::new(),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            WType::Bool => "Bool".to_string(),
+            WType::Int => "Int".to_string(),
+            WType::Float => "Float".to_string(),
+            WType::Struct(name) => name.clone(),
+            WType::Unit => "Unit".to_string(),
+            WType::Never => "Never".to_string(),
+        }
+    }
+}
+
+const FIELD_SIZE : usize = 8;
+
+struct Signature {
+    params : Vec<WType>,
+    result : WType,
+}
+
+struct Generator<'a> {
+    structs : HashMap<&'a str, &'a StructDef>,
+    funs : HashMap<&'a str, Signature>,
+    consts : Consts,
+}
+
+struct FunGen<'a, 'b> {
+    generator : &'b Generator<'a>,
+    function : String,
+    locals : Vec<(String, WType)>,
+    scope : Vec<(String, String, WType)>,
+    loops : Vec<usize>,
+    labels : usize,
+    temps : usize,
+}
+
+pub fn generate(module : &Module) -> Result<String, WatError> {
+    let mut generator = Generator { structs: module.struct_defs.iter().map(|s| (s.name.as_str(), s)).collect()
+                                  , funs: HashMap::new()
+                                  , consts: consts::evaluate(module).0
+                                  };
+
+    for fun_def in &module.fun_defs {
+        let function = &fun_def.sig.name;
+        let mut params = vec![];
+        for p in &fun_def.sig.params {
+            params.push(generator.value_type(function, &p.param_type)?);
+        }
+        let result = generator.value_type(function, &fun_def.sig.return_type)?;
+        generator.funs.insert(function.as_str(), Signature { params, result });
+    }
+
+    let mut out = String::new();
+    out.push_str("(module\n");
+    out.push_str("  (memory (export \"memory\") 1)\n");
+    out.push_str("  (global $dust_heap (mut i32) (i32.const 8))\n");
+    out.push_str(ALLOC);
+    for fun_def in &module.fun_defs {
+        out.push_str(&generator.function(fun_def)?);
+    }
+    out.push_str(")\n");
+    Ok(out)
+}
+
+/// Bump allocation, growing memory when the heap passes its end.
+const ALLOC : &str = "  (func $dust_alloc (param $size i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $dust_heap))
+    (global.set $dust_heap (i32.add (local.get $ptr) (local.get $size)))
+    (if (i32.gt_u (global.get $dust_heap) (i32.mul (memory.size) (i32.const 65536)))
+      (then (drop (memory.grow (i32.add (i32.div_u (i32.sub (global.get $dust_heap) (i32.mul (memory.size) (i32.const 65536))) (i32.const 65536)) (i32.const 1))))))
+    (local.get $ptr))
+";
+
+impl<'a> Generator<'a> {
+    fn value_type(&self, function : &str, t : &Type) -> Result<WType, WatError> {
+        match t {
+            Type::Unit => Ok(WType::Unit),
+            Type::Simple(n) if n == "Int" => Ok(WType::Int),
+            Type::Simple(n) if n == "Float" => Ok(WType::Float),
+            Type::Simple(n) if n == "Bool" => Ok(WType::Bool),
+            Type::Simple(n) if self.structs.get(n.as_str()).is_some_and(|s| s.type_params.is_empty()) => Ok(WType::Struct(n.clone())),
+            t => Err(WatError::Unsupported { function: function.to_string(), what: format!("the type {:?}", t) }),
+        }
+    }
+
+    /// The offset and type of a field of a struct.
+    fn field(&self, function : &str, struct_name : &str, field : &str) -> Result<(usize, WType), WatError> {
+        let struct_def = self.structs[struct_name];
+        match struct_def.fields.iter().position(|f| f.name == field) {
+            Some(i) => Ok((i * FIELD_SIZE, self.value_type(function, &struct_def.fields[i].field_type)?)),
+            None => Err(WatError::UnknownVariable { function: function.to_string(), name: format!("{}.{}", struct_name, field) }),
+        }
+    }
+
+    fn function(&self, fun_def : &FunDef) -> Result<String, WatError> {
+        let name = &fun_def.sig.name;
+        let signature = &self.funs[name.as_str()];
+        let mut gen = FunGen { generator: self
+                             , function: name.clone()
+                             , locals: vec![]
+                             , scope: vec![]
+                             , loops: vec![]
+                             , labels: 0
+                             , temps: 0
+                             };
+
+        let mut header = format!("  (func ${} (export \"{}\")", name, name);
+        for (p, t) in fun_def.sig.params.iter().zip(&signature.params) {
+            header.push_str(&format!(" (param ${} {})", p.name, t.wasm().unwrap_or("i32")));
+            gen.scope.push((p.name.clone(), p.name.clone(), t.clone()));
+        }
+        if signature.params.contains(&WType::Unit) {
+            return Err(WatError::Unsupported { function: name.clone(), what: "a Unit parameter".to_string() });
+        }
+        header.push_str(&signature.result.result());
+
+        let (body, t) = gen.expr(&fun_def.body)?;
+        let body = match (&signature.result, &t) {
+            (_, WType::Never) => body,
+            (WType::Unit, WType::Unit) => body,
+            (WType::Unit, _) => format!("(drop {})", body),
+            (expected, _) => gen.coerce(body, &t, expected)?,
+        };
+
+        let mut out = header;
+        out.push('\n');
+        for (local, t) in &gen.locals {
+            out.push_str(&format!("    (local ${} {})\n", local, t.wasm().unwrap()));
+        }
+        if !body.is_empty() {
+            out.push_str(&format!("    {}\n", body));
+        }
+        out.push_str("  )\n");
+        Ok(out)
+    }
+}
+
+impl<'a, 'b> FunGen<'a, 'b> {
+    fn unsupported<T>(&self, what : &str) -> Result<T, WatError> {
+        Err(WatError::Unsupported { function: self.function.clone(), what: what.to_string() })
+    }
+
+    fn mismatch<T>(&self, expected : &WType, found : &WType) -> Result<T, WatError> {
+        Err(WatError::TypeMismatch { function: self.function.clone(), expected: expected.name(), found: found.name() })
+    }
+
+    /// Converts code of one type to another, which only ever turns an `Int` into a `Float`.
+    fn coerce(&self, code : String, from : &WType, to : &WType) -> Result<String, WatError> {
+        match (from, to) {
+            (a, b) if a == b => Ok(code),
+            (WType::Never, _) => Ok(code),
+            (WType::Int, WType::Float) => Ok(format!("(f64.convert_i64_s {})", code)),
+            _ => self.mismatch(to, from),
+        }
+    }
+
+    fn new_local(&mut self, name : &str, t : WType) -> String {
+        let mut local = name.to_string();
+        let mut n = 0;
+        while self.locals.iter().any(|(l, _)| *l == local) || self.scope.iter().any(|(_, l, _)| *l == local) {
+            n += 1;
+            local = format!("{}.{}", name, n);
+        }
+        self.locals.push((local.clone(), t));
+        local
+    }
+
+    fn sequence(&mut self, exprs : &[Expr]) -> Result<(String, WType), WatError> {
+        let mut code = vec![];
+        let mut t = WType::Unit;
+        for (i, e) in exprs.iter().enumerate() {
+            let (c, et) = self.expr(e)?;
+            let last = i + 1 == exprs.len();
+            if !last && et.wasm().is_some() {
+                code.push(format!("(drop {})", c));
+            }
+            else if !c.is_empty() {
+                code.push(c);
+            }
+            if last || et == WType::Never {
+                t = et;
+            }
+        }
+        // a sequence which returned part way through never produces a value either
+        if t == WType::Unit && exprs.iter().any(diverges) {
+            t = WType::Never;
+        }
+        Ok((code.join(" "), t))
+    }
+
+    fn expr(&mut self, expr : &Expr) -> Result<(String, WType), WatError> {
+        match expr {
+            Expr::Number(n) => match ops::number(n) {
+                Ok(Value::Int(i)) => Ok((format!("(i64.const {})", i), WType::Int)),
+                Ok(Value::Float(x)) => Ok((format!("(f64.const {:?})", x), WType::Float)),
+                _ => self.unsupported(&format!("the number {}", n)),
+            },
+            Expr::Bool(b) => Ok((format!("(i32.const {})", *b as i32), WType::Bool)),
+            Expr::Unit => Ok((String::new(), WType::Unit)),
+            Expr::Variable(name) => match self.scope.iter().rev().find(|(n, _, _)| n == name) {
+                Some((_, local, t)) => Ok((format!("(local.get ${})", local), t.clone())),
+                None => match self.generator.consts.expr(name) {
+                    Some(literal) => self.expr(&literal.clone()),
+                    None if self.generator.funs.contains_key(name.as_str()) => self.unsupported("a function used as a value"),
+                    None => Err(WatError::UnknownVariable { function: self.function.clone(), name: name.clone() }),
+                },
+            },
+            Expr::Block(exprs) => {
+                let depth = self.scope.len();
+                let (code, t) = self.sequence(exprs)?;
+                self.scope.truncate(depth);
+                Ok((format!("(block{} {}){}", t.result(), code, unreachable(&t)), t))
+            },
+            Expr::Let { name, let_type, value, .. } => {
+                let (code, t) = self.expr(value)?;
+                let t = match let_type {
+                    Type::Infer => t,
+                    declared => {
+                        let declared = self.generator.value_type(&self.function, declared)?;
+                        let code = self.coerce(code, &t, &declared)?;
+                        let local = self.new_local(name, declared.clone());
+                        self.scope.push((name.clone(), local.clone(), declared));
+                        return Ok((format!("(local.set ${} {})", local, code), WType::Unit));
+                    },
+                };
+                if t.wasm().is_none() {
+                    return self.unsupported(&format!("a {} variable", t.name()));
+                }
+                let local = self.new_local(name, t.clone());
+                self.scope.push((name.clone(), local.clone(), t));
+                Ok((format!("(local.set ${} {})", local, code), WType::Unit))
+            },
+            Expr::Assign { target, value, .. } => {
+                let (code, t) = self.expr(value)?;
+                match &**target {
+                    Expr::Variable(name) => match self.scope.iter().rev().find(|(n, _, _)| n == name).cloned() {
+                        Some((_, local, expected)) => {
+                            let code = self.coerce(code, &t, &expected)?;
+                            Ok((format!("(local.set ${} {})", local, code), WType::Unit))
+                        },
+                        None => Err(WatError::UnknownVariable { function: self.function.clone(), name: name.clone() }),
+                    },
+                    Expr::Dot { expr, name } => {
+                        let (pointer, offset, field_type) = self.field(expr, name)?;
+                        let code = self.coerce(code, &t, &field_type)?;
+                        Ok((format!("({}.store offset={} {} {})", field_type.wasm().unwrap(), offset, pointer, code), WType::Unit))
+                    },
+                    _ => self.unsupported("assigning to anything but a variable or field"),
+                }
+            },
+            Expr::Dot { expr, name } => {
+                let (pointer, offset, field_type) = self.field(expr, name)?;
+                Ok((format!("({}.load offset={} {})", field_type.wasm().unwrap(), offset, pointer), field_type))
+            },
+            Expr::Struct { namespace, name, fields } if namespace.is_empty() && self.generator.structs.contains_key(name.as_str()) => {
+                let struct_def = self.generator.structs[name.as_str()];
+                let temp = format!("tmp.{}", self.temps);
+                self.temps += 1;
+                self.locals.push((temp.clone(), WType::Struct(name.clone())));
+
+                let mut code = format!("(block (result i32) (local.set ${} (call $dust_alloc (i32.const {})))"
+                                      , temp, (struct_def.fields.len() * FIELD_SIZE).max(FIELD_SIZE));
+                for field in &struct_def.fields {
+                    let value = match fields.iter().find(|(n, _)| *n == field.name) {
+                        Some((_, value)) => value,
+                        None => return self.unsupported(&format!("a {} without its field {}", name, field.name)),
+                    };
+                    let (value, t) = self.expr(value)?;
+                    let (offset, field_type) = self.generator.field(&self.function, name, &field.name)?;
+                    let value = self.coerce(value, &t, &field_type)?;
+                    code.push_str(&format!(" ({}.store offset={} (local.get ${}) {})", field_type.wasm().unwrap(), offset, temp, value));
+                }
+                if let Some((extra, _)) = fields.iter().find(|(n, _)| !struct_def.fields.iter().any(|f| f.name == *n)) {
+                    return Err(WatError::UnknownVariable { function: self.function.clone(), name: format!("{}.{}", name, extra) });
+                }
+                code.push_str(&format!(" (local.get ${}))", temp));
+                Ok((code, WType::Struct(name.clone())))
+            },
+            Expr::Call { fun, args, .. } => {
+                let name = match &**fun {
+                    Expr::Variable(name) if self.generator.funs.contains_key(name.as_str()) && !self.scope.iter().any(|(n, _, _)| n == name) => name,
+                    _ => return self.unsupported("calling anything but a top level function"),
+                };
+                let signature = &self.generator.funs[name.as_str()];
+                if signature.params.len() != args.len() {
+                    return self.unsupported(&format!("calling {} with {} arguments", name, args.len()));
+                }
+                let mut code = format!("(call ${}", name);
+                for (arg, expected) in args.iter().zip(&signature.params) {
+                    let (arg, t) = self.expr(arg)?;
+                    code.push(' ');
+                    code.push_str(&self.coerce(arg, &t, expected)?);
+                }
+                code.push(')');
+                Ok((code, signature.result.clone()))
+            },
+            Expr::Unary { op: UnaryOp::Neg, expr } => match self.expr(expr)? {
+                (code, WType::Int) => Ok((format!("(i64.sub (i64.const 0) {})", code), WType::Int)),
+                (code, WType::Float) => Ok((format!("(f64.neg {})", code), WType::Float)),
+                (_, t) => self.mismatch(&WType::Int, &t),
+            },
+            Expr::Unary { op: UnaryOp::Not, expr } => {
+                let code = self.condition(expr)?;
+                Ok((format!("(i32.eqz {})", code), WType::Bool))
+            },
+            Expr::Binary { op: BinOp::And, left, right } => {
+                let (left, right) = (self.condition(left)?, self.condition(right)?);
+                Ok((format!("(if (result i32) {} (then {}) (else (i32.const 0)))", left, right), WType::Bool))
+            },
+            Expr::Binary { op: BinOp::Or, left, right } => {
+                let (left, right) = (self.condition(left)?, self.condition(right)?);
+                Ok((format!("(if (result i32) {} (then (i32.const 1)) (else {}))", left, right), WType::Bool))
+            },
+            Expr::Binary { op, left, right } => self.binary(*op, left, right),
+            Expr::If { condition, then, otherwise } => {
+                let condition = self.condition(condition)?;
+                let (then, then_type) = self.expr(then)?;
+                let (otherwise, otherwise_type) = self.expr(otherwise)?;
+                let t = match (&then_type, &otherwise_type) {
+                    (WType::Never, t) | (t, WType::Never) => t.clone(),
+                    (a, b) if a == b => a.clone(),
+                    (WType::Int, WType::Float) | (WType::Float, WType::Int) => WType::Float,
+                    _ => WType::Unit,
+                };
+                let branch = |code : String, from : &WType| -> Result<String, WatError> {
+                    match (from.wasm(), t.wasm()) {
+                        (Some(_), None) => Ok(format!("(drop {})", code)),
+                        _ => self.coerce(code, from, &t),
+                    }
+                };
+                let (then, otherwise) = (branch(then, &then_type)?, branch(otherwise, &otherwise_type)?);
+                let t = if then_type == WType::Never && otherwise_type == WType::Never { WType::Never } else { t };
+                Ok((format!("(if{} {} (then {}) (else {})){}", t.result(), condition, then, otherwise, unreachable(&t)), t))
+            },
+            Expr::While { condition, body } => {
+                let label = self.labels;
+                self.labels += 1;
+                let condition = self.condition(condition)?;
+                self.loops.push(label);
+                let body = self.expr(body);
+                self.loops.pop();
+                let body = match body? {
+                    (code, t) if t.wasm().is_some() => format!("(drop {})", code),
+                    (code, _) => code,
+                };
+                Ok((format!("(block $break.{l} (loop $continue.{l} (br_if $break.{l} (i32.eqz {})) {} (br $continue.{l})))", condition, body, l = label)
+                   , WType::Unit))
+            },
+            Expr::Break | Expr::Continue => match self.loops.last() {
+                Some(label) if matches!( expr, Expr::Break ) => Ok((format!("(br $break.{})", label), WType::Never)),
+                Some(label) => Ok((format!("(br $continue.{})", label), WType::Never)),
+                None => self.unsupported("break or continue outside of a loop"),
+            },
+            Expr::Return(e) => {
+                let (code, t) = self.expr(e)?;
+                let expected = self.generator.funs[self.function.as_str()].result.clone();
+                match expected.wasm() {
+                    Some(_) => Ok((format!("(return {})", self.coerce(code, &t, &expected)?), WType::Never)),
+                    None if code.is_empty() => Ok(("(return)".to_string(), WType::Never)),
+                    None => Ok((format!("(drop {}) (return)", code), WType::Never)),
+                }
+            },
+            Expr::DString(_) => self.unsupported("a string"),
+            Expr::Tuple(_) => self.unsupported("a tuple"),
+            Expr::List(_) => self.unsupported("a list"),
+            Expr::Dict(_) => self.unsupported("a dictionary"),
+            Expr::Struct { .. } => self.unsupported("an enum case"),
+            Expr::Namespace(_, _) => self.unsupported("a path"),
+            Expr::MethodCall { .. } => self.unsupported("a method call"),
+            Expr::Index { .. } => self.unsupported("indexing"),
+            Expr::Lambda { .. } => self.unsupported("a closure"),
+            Expr::Match { .. } => self.unsupported("a match"),
+            Expr::Try(_) => self.unsupported("a try block"),
+            Expr::Propagate(_) => self.unsupported("the ? operator"),
+            Expr::Assert { .. } => self.unsupported("an assert"),
+            Expr::Panic { .. } => self.unsupported("a panic"),
+            Expr::Foreach { .. } => self.unsupported("a foreach"),
+            Expr::Yield(_) => self.unsupported("a yield"),
+            Expr::Range { .. } => self.unsupported("a range"),
+        }
+    }
+
+    fn condition(&mut self, expr : &Expr) -> Result<String, WatError> {
+        match self.expr(expr)? {
+            (code, WType::Bool) | (code, WType::Never) => Ok(code),
+            (_, t) => self.mismatch(&WType::Bool, &t),
+        }
+    }
+
+    /// The code of the pointer to a struct along with the offset and type of one of its fields.
+    fn field(&mut self, expr : &Expr, name : &str) -> Result<(String, usize, WType), WatError> {
+        match self.expr(expr)? {
+            (pointer, WType::Struct(struct_name)) => {
+                let (offset, t) = self.generator.field(&self.function, &struct_name, name)?;
+                Ok((pointer, offset, t))
+            },
+            (_, t) => self.unsupported(&format!("the field {} of {}", name, t.name())),
+        }
+    }
+
+    fn binary(&mut self, op : BinOp, left : &Expr, right : &Expr) -> Result<(String, WType), WatError> {
+        let (left, left_type) = self.expr(left)?;
+        let (right, right_type) = self.expr(right)?;
+
+        let (operand, left, right) = match (&left_type, &right_type) {
+            (WType::Int, WType::Int) => (WType::Int, left, right),
+            (WType::Float, WType::Float) => (WType::Float, left, right),
+            (WType::Int, WType::Float) => (WType::Float, format!("(f64.convert_i64_s {})", left), right),
+            (WType::Float, WType::Int) => (WType::Float, left, format!("(f64.convert_i64_s {})", right)),
+            (WType::Bool, WType::Bool) if matches!( op, BinOp::Eq | BinOp::NotEq ) => (WType::Bool, left, right),
+            _ => return self.unsupported(&format!("{:?} of {} and {}", op, left_type.name(), right_type.name())),
+        };
+
+        let (instruction, result) = match (&operand, op) {
+            (WType::Bool, BinOp::Eq) => ("i32.eq", WType::Bool),
+            (WType::Bool, _) => ("i32.ne", WType::Bool),
+            (WType::Int, BinOp::Add) => ("i64.add", WType::Int),
+            (WType::Int, BinOp::Sub) => ("i64.sub", WType::Int),
+            (WType::Int, BinOp::Mul) => ("i64.mul", WType::Int),
+            (WType::Int, BinOp::Div) => ("i64.div_s", WType::Int),
+            (WType::Int, BinOp::Rem) => ("i64.rem_s", WType::Int),
+            (WType::Int, BinOp::Eq) => ("i64.eq", WType::Bool),
+            (WType::Int, BinOp::NotEq) => ("i64.ne", WType::Bool),
+            (WType::Int, BinOp::Less) => ("i64.lt_s", WType::Bool),
+            (WType::Int, BinOp::LessEq) => ("i64.le_s", WType::Bool),
+            (WType::Int, BinOp::Greater) => ("i64.gt_s", WType::Bool),
+            (WType::Int, BinOp::GreaterEq) => ("i64.ge_s", WType::Bool),
+            (_, BinOp::Add) => ("f64.add", WType::Float),
+            (_, BinOp::Sub) => ("f64.sub", WType::Float),
+            (_, BinOp::Mul) => ("f64.mul", WType::Float),
+            (_, BinOp::Div) => ("f64.div", WType::Float),
+            (_, BinOp::Eq) => ("f64.eq", WType::Bool),
+            (_, BinOp::NotEq) => ("f64.ne", WType::Bool),
+            (_, BinOp::Less) => ("f64.lt", WType::Bool),
+            (_, BinOp::LessEq) => ("f64.le", WType::Bool),
+            (_, BinOp::Greater) => ("f64.gt", WType::Bool),
+            (_, BinOp::GreaterEq) => ("f64.ge", WType::Bool),
+            (_, op) => return self.unsupported(&format!("{:?} of floats", op)),
+        };
+        Ok((format!("({} {} {})", instruction, left, right), result))
+    }
+}
+
+/// Code which never falls through is followed by `unreachable`, so whatever
+/// expects a value after it still validates.
+fn unreachable(t : &WType) -> &'static str {
+    if *t == WType::Never { " (unreachable)" } else { "" }
+}
+
+fn diverges(expr : &Expr) -> bool {
+    matches!( expr, Expr::Return(_) | Expr::Break | Expr::Continue )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+    use super::super::wat_check::validate;
+
+    fn generate_src(src : &str) -> Result<String, WatError> {
+        generate(&parse(src).unwrap())
+    }
+
+    #[test]
+    fn should_generate_valid_numeric_functions() {
+        let wat = generate_src(r#"
+fun fib(n : Int) -> Int {
+    if n < 2 { n } else { fib(n - 1) + fib(n - 2) }
+}
+
+fun mean(a : Int, b : Float) -> Float { (a + b) / 2 }
+
+fun is_small(x : Int) -> Bool { x > -10 && !(x >= 10) }
+
+fun first_over(limit : Int) -> Int {
+    let mut i = 0;
+    while true {
+        i = i + 1;
+        if i % 2 == 0 { continue; }
+        if i * i > limit { return i; }
+    }
+    -1
+}
+"#).unwrap();
+
+        assert_eq!( validate(&wat), Ok(()), "{}", wat );
+        assert!( wat.contains("(func $fib (export \"fib\") (param $n i64) (result i64)") );
+        assert!( wat.contains("(f64.convert_i64_s (local.get $a))") );
+    }
+
+    #[test]
+    fn should_keep_structs_in_linear_memory() {
+        let wat = generate_src(r#"
+struct Point { x : Float, y : Float }
+struct Segment { from : Point, to : Point, visible : Bool }
+
+fun length_squared(s : Segment) -> Float {
+    let dx = s.to.x - s.from.x;
+    let dy = s.to.y - s.from.y;
+    dx * dx + dy * dy
+}
+
+fun make(n : Int) -> Segment {
+    let s = Segment { from : Point { x : 0, y : 0 }, to : Point { x : n, y : 1.5 }, visible : true };
+    s.to.y = 2.5;
+    s
+}
+"#).unwrap();
+
+        assert_eq!( validate(&wat), Ok(()), "{}", wat );
+        assert!( wat.contains("(f64.load offset=8 (i32.load offset=8 (local.get $s)))") );
+        assert!( wat.contains("(call $dust_alloc (i32.const 24))") );
+    }
+
+    #[test]
+    fn should_rename_shadowed_variables() {
+        let wat = generate_src("fun f(x : Int) -> Int { let y = x; { let y = 2.5; } y }").unwrap();
+        assert_eq!( validate(&wat), Ok(()), "{}", wat );
+        assert!( wat.contains("(local $y i64)") );
+        assert!( wat.contains("(local $y.1 f64)") );
+    }
+
+    #[test]
+    fn should_inline_constants() {
+        let wat = generate_src("const fun square(x : Int) -> Int { x * x }\nconst LIMIT : Int = square(6) + 6;\nfun over(x : Int) -> Bool { x > LIMIT }").unwrap();
+        assert_eq!( validate(&wat), Ok(()), "{}", wat );
+        assert!( wat.contains("(i64.gt_s (local.get $x) (i64.const 42))"), "{}", wat );
+    }
+
+    #[test]
+    fn should_validate_functions_which_only_return() {
+        let wat = generate_src(r#"
+fun f(x : Int) -> Int { return x; }
+fun g(x : Bool) -> Float { if x { return 1; } else { return 2.5; } }
+fun h(x : Int) { while x > 0 { return; } }
+"#).unwrap();
+        assert_eq!( validate(&wat), Ok(()), "{}", wat );
+    }
+
+    #[test]
+    fn should_reject_code_outside_of_the_subset() {
+        assert_eq!( generate_src(r#"fun f() -> Int { let s = "hi"; 1 }"#)
+                  , Err(WatError::Unsupported { function: "f".to_string(), what: "a string".to_string() }) );
+        assert_eq!( generate_src("fun f(xs : List<Int>) { }")
+                  , Err(WatError::Unsupported { function: "f".to_string(), what: "the type Indexed(\"List\", [Simple(\"Int\")])".to_string() }) );
+        assert_eq!( generate_src("fun f(x : Int) -> Bool { x }")
+                  , Err(WatError::TypeMismatch { function: "f".to_string(), expected: "Bool".to_string(), found: "Int".to_string() }) );
+    }
+}
@@ -0,0 +1,888 @@
+
+use std::collections::HashMap;
+use std::fmt;
+
+/* A structural validator for the WebAssembly text format, covering what the
+   generator in `wat` emits:  functions, params, results, locals, globals, one
+   memory, exports and imports of functions, with instructions in either folded
+   or flat form (apart from flat `block`/`loop`/`if`).  Identifiers are resolved
+   and every instruction sequence is type checked against an operand stack the
+   way the wasm validation algorithm does it, so code which validates here
+   should be accepted by any engine.
+*/
+
+#[derive(Debug, PartialEq)]
+pub enum ValidationError {
+    Syntax { position : usize, message : String },
+    Unknown { kind : &'static str, name : String },
+    TypeMismatch { context : String, expected : String, found : String },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::Syntax { position, message } => write!(f, "syntax error at byte {}: {}", position, message),
+            ValidationError::Unknown { kind, name } => write!(f, "unknown {} {}", kind, name),
+            ValidationError::TypeMismatch { context, expected, found } => write!(f, "{}: expected {} but found {}", context, expected, found),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum SExpr {
+    Atom(String, usize),
+    List(Vec<SExpr>, usize),
+}
+
+impl SExpr {
+    fn position(&self) -> usize {
+        match self {
+            SExpr::Atom(_, p) | SExpr::List(_, p) => *p,
+        }
+    }
+
+    fn atom(&self) -> Option<&str> {
+        match self {
+            SExpr::Atom(a, _) => Some(a),
+            SExpr::List(_, _) => None,
+        }
+    }
+
+    /// The keyword at the head of a list such as `(param ...)`.
+    fn head(&self) -> Option<&str> {
+        match self {
+            SExpr::List(items, _) => items.first().and_then(SExpr::atom),
+            SExpr::Atom(_, _) => None,
+        }
+    }
+}
+
+fn syntax<T>(position : usize, message : &str) -> Result<T, ValidationError> {
+    Err(ValidationError::Syntax { position, message: message.to_string() })
+}
+
+fn parse(text : &str) -> Result<Vec<SExpr>, ValidationError> {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    let mut stack : Vec<(Vec<SExpr>, usize)> = vec![(vec![], 0)];
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b' ' | b'\t' | b'\n' | b'\r' => i += 1,
+            b';' if bytes.get(i + 1) == Some(&b';') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            },
+            b'(' if bytes.get(i + 1) == Some(&b';') => {
+                let start = i;
+                let mut depth = 0;
+                loop {
+                    if i + 1 >= bytes.len() {
+                        return syntax(start, "unterminated block comment");
+                    }
+                    if bytes[i] == b'(' && bytes[i + 1] == b';' {
+                        depth += 1;
+                        i += 2;
+                    }
+                    else if bytes[i] == b';' && bytes[i + 1] == b')' {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    else {
+                        i += 1;
+                    }
+                }
+            },
+            b'(' => {
+                stack.push((vec![], i));
+                i += 1;
+            },
+            b')' => {
+                if stack.len() == 1 {
+                    return syntax(i, "unexpected )");
+                }
+                let (items, start) = stack.pop().unwrap();
+                stack.last_mut().unwrap().0.push(SExpr::List(items, start));
+                i += 1;
+            },
+            b'"' => {
+                let start = i;
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                if i >= bytes.len() {
+                    return syntax(start, "unterminated string");
+                }
+                i += 1;
+                stack.last_mut().unwrap().0.push(SExpr::Atom(text[start..i].to_string(), start));
+            },
+            _ => {
+                let start = i;
+                while i < bytes.len() && !matches!( bytes[i], b' ' | b'\t' | b'\n' | b'\r' | b'(' | b')' | b'"' | b';' ) {
+                    i += 1;
+                }
+                stack.last_mut().unwrap().0.push(SExpr::Atom(text[start..i].to_string(), start));
+            },
+        }
+    }
+
+    if stack.len() > 1 {
+        return syntax(stack.last().unwrap().1, "unclosed (");
+    }
+    Ok(stack.pop().unwrap().0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ValType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ValType {
+    fn parse(name : &str) -> Option<ValType> {
+        match name {
+            "i32" => Some(ValType::I32),
+            "i64" => Some(ValType::I64),
+            "f32" => Some(ValType::F32),
+            "f64" => Some(ValType::F64),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ValType::I32 => "i32",
+            ValType::I64 => "i64",
+            ValType::F32 => "f32",
+            ValType::F64 => "f64",
+        }
+    }
+}
+
+fn names(types : &[ValType]) -> String {
+    format!("[{}]", types.iter().map(ValType::name).collect::<Vec<_>>().join(" "))
+}
+
+fn val_type(expr : &SExpr) -> Result<ValType, ValidationError> {
+    match expr.atom().and_then(ValType::parse) {
+        Some(t) => Ok(t),
+        None => syntax(expr.position(), "expected a value type"),
+    }
+}
+
+fn is_id(expr : Option<&SExpr>) -> bool {
+    expr.and_then(SExpr::atom).is_some_and(|a| a.starts_with('$'))
+}
+
+/// Signatures of the functions, in index order, along with their identifiers.
+struct Signature {
+    params : Vec<ValType>,
+    results : Vec<ValType>,
+}
+
+struct ModuleContext {
+    funcs : Vec<Signature>,
+    func_ids : HashMap<String, usize>,
+    globals : Vec<(ValType, bool)>,
+    global_ids : HashMap<String, usize>,
+    memories : usize,
+}
+
+fn lookup(ids : &HashMap<String, usize>, count : usize, kind : &'static str, expr : Option<&SExpr>) -> Result<usize, ValidationError> {
+    let name = match expr.and_then(SExpr::atom) {
+        Some(name) => name,
+        None => return Err(ValidationError::Unknown { kind, name: "<missing>".to_string() }),
+    };
+    let index = if name.starts_with('$') { ids.get(name).copied() } else { name.parse::<usize>().ok().filter(|i| *i < count) };
+    index.ok_or_else(|| ValidationError::Unknown { kind, name: name.to_string() })
+}
+
+/// Reads the `(param ...)` and `(result ...)` clauses of a function type, returning it along
+/// with the names of the params and the index of the first item after the clauses.
+fn signature(items : &[SExpr], mut i : usize) -> Result<(Signature, Vec<Option<String>>, usize), ValidationError> {
+    let mut params = vec![];
+    let mut param_names = vec![];
+    let mut results = vec![];
+    while let Some(SExpr::List(clause, _)) = items.get(i) {
+        match clause.first().and_then(SExpr::atom) {
+            Some("param") if is_id(clause.get(1)) => {
+                if clause.len() != 3 {
+                    return syntax(clause[0].position(), "a named param takes exactly one type");
+                }
+                params.push(val_type(&clause[2])?);
+                param_names.push(clause[1].atom().map(str::to_string));
+            },
+            Some("param") => for t in &clause[1..] {
+                params.push(val_type(t)?);
+                param_names.push(None);
+            },
+            Some("result") => for t in &clause[1..] {
+                results.push(val_type(t)?);
+            },
+            _ => break,
+        }
+        i += 1;
+    }
+    Ok((Signature { params, results }, param_names, i))
+}
+
+pub fn validate(text : &str) -> Result<(), ValidationError> {
+    let top = parse(text)?;
+    let module = match top.as_slice() {
+        [m @ SExpr::List(_, _)] if m.head() == Some("module") => m,
+        [] => return syntax(0, "expected a module"),
+        other => return syntax(other[0].position(), "expected a single (module ...)"),
+    };
+    let fields = match module {
+        SExpr::List(items, _) => &items[if is_id(items.get(1)) { 2 } else { 1 }..],
+        SExpr::Atom(_, _) => unreachable!(),
+    };
+
+    let mut context = ModuleContext { funcs: vec![], func_ids: HashMap::new(), globals: vec![], global_ids: HashMap::new(), memories: 0 };
+    let mut exports = vec![];
+
+    // imports come first in the index spaces, so declare them before anything else
+    for field in fields {
+        if field.head() == Some("import") {
+            let items = match field { SExpr::List(items, _) => items, _ => unreachable!() };
+            match items.get(3) {
+                Some(desc) if desc.head() == Some("func") && items.len() == 4 => {
+                    let desc_items = match desc { SExpr::List(items, _) => items, _ => unreachable!() };
+                    let start = if is_id(desc_items.get(1)) { 2 } else { 1 };
+                    let (sig, _, end) = signature(desc_items, start)?;
+                    if end != desc_items.len() {
+                        return syntax(desc_items[end].position(), "unexpected item in imported function");
+                    }
+                    declare(&mut context.func_ids, desc_items.get(1), context.funcs.len(), "function")?;
+                    context.funcs.push(sig);
+                },
+                _ => return syntax(field.position(), "only function imports are supported"),
+            }
+        }
+    }
+
+    for field in fields {
+        let items = match field {
+            SExpr::List(items, _) => items,
+            SExpr::Atom(_, p) => return syntax(*p, "expected a module field"),
+        };
+        match field.head() {
+            Some("import") => { },
+            Some("func") => {
+                let mut i = if is_id(items.get(1)) { 2 } else { 1 };
+                while items.get(i).is_some_and(|e| e.head() == Some("export")) {
+                    exports.push((&items[i], "func", items.get(1).filter(|_| is_id(items.get(1))).cloned()));
+                    i += 1;
+                }
+                let (sig, _, _) = signature(items, i)?;
+                declare(&mut context.func_ids, items.get(1), context.funcs.len(), "function")?;
+                context.funcs.push(sig);
+            },
+            Some("global") => {
+                let mut i = if is_id(items.get(1)) { 2 } else { 1 };
+                while items.get(i).is_some_and(|e| e.head() == Some("export")) {
+                    exports.push((&items[i], "global", items.get(1).filter(|_| is_id(items.get(1))).cloned()));
+                    i += 1;
+                }
+                let global = match items.get(i) {
+                    Some(SExpr::List(m, _)) if m.first().and_then(SExpr::atom) == Some("mut") && m.len() == 2 => (val_type(&m[1])?, true),
+                    Some(t @ SExpr::Atom(_, _)) => (val_type(t)?, false),
+                    _ => return syntax(field.position(), "expected the type of a global"),
+                };
+                declare(&mut context.global_ids, items.get(1), context.globals.len(), "global")?;
+                context.globals.push(global);
+            },
+            Some("memory") => {
+                let mut i = if is_id(items.get(1)) { 2 } else { 1 };
+                while items.get(i).is_some_and(|e| e.head() == Some("export")) {
+                    exports.push((&items[i], "memory", None));
+                    i += 1;
+                }
+                let limits = &items[i..];
+                if limits.is_empty() || limits.len() > 2 || limits.iter().any(|l| l.atom().and_then(|a| a.parse::<u32>().ok()).is_none()) {
+                    return syntax(field.position(), "expected the limits of a memory");
+                }
+                context.memories += 1;
+            },
+            Some("export") => {
+                match items.get(2) {
+                    Some(desc @ SExpr::List(d, _)) if d.len() == 2 => {
+                        let kind = match desc.head() {
+                            Some("func") => "func",
+                            Some("global") => "global",
+                            Some("memory") => "memory",
+                            _ => return syntax(desc.position(), "unsupported export"),
+                        };
+                        exports.push((field, kind, d.get(1).cloned()));
+                    },
+                    _ => return syntax(field.position(), "expected an export description"),
+                }
+            },
+            Some("type") | Some("data") | Some("start") => { },
+            _ => return syntax(field.position(), "unknown module field"),
+        }
+    }
+
+    if context.memories > 1 {
+        return syntax(module.position(), "only one memory is allowed");
+    }
+
+    let mut export_names = vec![];
+    for (export, kind, target) in exports {
+        let name = match export { SExpr::List(items, _) => items.get(1).and_then(SExpr::atom), _ => None };
+        let name = match name {
+            Some(name) if name.starts_with('"') => name,
+            _ => return syntax(export.position(), "expected an export name"),
+        };
+        if export_names.contains(&name) {
+            return syntax(export.position(), "duplicate export name");
+        }
+        export_names.push(name);
+        match kind {
+            "func" if target.is_some() => { lookup(&context.func_ids, context.funcs.len(), "function", target.as_ref())?; },
+            "global" if target.is_some() => { lookup(&context.global_ids, context.globals.len(), "global", target.as_ref())?; },
+            "memory" if context.memories == 0 => return Err(ValidationError::Unknown { kind: "memory", name: "0".to_string() }),
+            _ => { },
+        }
+    }
+
+    let mut global_index = 0;
+    for field in fields {
+        if let SExpr::List(items, _) = field {
+            match field.head() {
+                Some("func") => check_function(&context, items)?,
+                Some("global") => {
+                    let (t, _) = context.globals[global_index];
+                    global_index += 1;
+                    let init = items.last().unwrap();
+                    let mut checker = Checker::new(&context, vec![], vec![], "global initialiser".to_string());
+                    checker.frames.push(Frame { label: None, kind: FrameKind::Block, results: vec![t], height: 0, unreachable: false });
+                    checker.instruction_sequence(std::slice::from_ref(init))?;
+                    checker.end_frame()?;
+                },
+                _ => { },
+            }
+        }
+    }
+    Ok(())
+}
+
+fn declare(ids : &mut HashMap<String, usize>, id : Option<&SExpr>, index : usize, kind : &str) -> Result<(), ValidationError> {
+    if let Some(SExpr::Atom(name, position)) = id {
+        if name.starts_with('$') && ids.insert(name.clone(), index).is_some() {
+            return syntax(*position, &format!("duplicate {} {}", kind, name));
+        }
+    }
+    Ok(())
+}
+
+fn check_function(context : &ModuleContext, items : &[SExpr]) -> Result<(), ValidationError> {
+    let name = items.get(1).and_then(SExpr::atom).filter(|a| a.starts_with('$')).unwrap_or("func").to_string();
+    let mut i = if is_id(items.get(1)) { 2 } else { 1 };
+    while items.get(i).is_some_and(|e| e.head() == Some("export")) {
+        i += 1;
+    }
+    let (sig, param_names, mut i) = signature(items, i)?;
+
+    let mut locals = sig.params.clone();
+    let mut local_ids = HashMap::new();
+    for (index, name) in param_names.iter().enumerate() {
+        if let Some(name) = name {
+            if local_ids.insert(name.clone(), index).is_some() {
+                return syntax(items[0].position(), &format!("duplicate local {}", name));
+            }
+        }
+    }
+    while let Some(clause @ SExpr::List(l, _)) = items.get(i) {
+        if clause.head() != Some("local") {
+            break;
+        }
+        if is_id(l.get(1)) {
+            if l.len() != 3 {
+                return syntax(clause.position(), "a named local takes exactly one type");
+            }
+            let id = l[1].atom().unwrap().to_string();
+            if local_ids.insert(id.clone(), locals.len()).is_some() {
+                return syntax(l[1].position(), &format!("duplicate local {}", id));
+            }
+            locals.push(val_type(&l[2])?);
+        }
+        else {
+            for t in &l[1..] {
+                locals.push(val_type(t)?);
+            }
+        }
+        i += 1;
+    }
+
+    let mut checker = Checker::new(context, locals, vec![], name);
+    checker.local_ids = local_ids;
+    checker.frames.push(Frame { label: None, kind: FrameKind::Function, results: sig.results.clone(), height: 0, unreachable: false });
+    checker.instruction_sequence(&items[i..])?;
+    checker.end_frame()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FrameKind {
+    Function,
+    Block,
+    Loop,
+}
+
+struct Frame {
+    label : Option<String>,
+    kind : FrameKind,
+    results : Vec<ValType>,
+    height : usize,
+    unreachable : bool,
+}
+
+struct Checker<'a> {
+    context : &'a ModuleContext,
+    locals : Vec<ValType>,
+    local_ids : HashMap<String, usize>,
+    /// `None` is a value of unknown type, popped from the stack of unreachable code.
+    stack : Vec<Option<ValType>>,
+    frames : Vec<Frame>,
+    function : String,
+}
+
+impl<'a> Checker<'a> {
+    fn new(context : &'a ModuleContext, locals : Vec<ValType>, stack : Vec<Option<ValType>>, function : String) -> Checker<'a> {
+        Checker { context, locals, local_ids: HashMap::new(), stack, frames: vec![], function }
+    }
+
+    fn mismatch<T>(&self, instruction : &str, expected : &str, found : &str) -> Result<T, ValidationError> {
+        Err(ValidationError::TypeMismatch { context: format!("{} {}", self.function, instruction)
+                                          , expected: expected.to_string()
+                                          , found: found.to_string()
+                                          })
+    }
+
+    fn push(&mut self, t : ValType) {
+        self.stack.push(Some(t));
+    }
+
+    fn pop(&mut self, instruction : &str) -> Result<Option<ValType>, ValidationError> {
+        let frame = self.frames.last().unwrap();
+        if self.stack.len() == frame.height {
+            if frame.unreachable {
+                return Ok(None);
+            }
+            return self.mismatch(instruction, "an operand", "an empty stack");
+        }
+        Ok(self.stack.pop().unwrap())
+    }
+
+    fn pop_expect(&mut self, instruction : &str, expected : ValType) -> Result<(), ValidationError> {
+        match self.pop(instruction)? {
+            Some(t) if t != expected => self.mismatch(instruction, expected.name(), t.name()),
+            _ => Ok(()),
+        }
+    }
+
+    fn pop_all(&mut self, instruction : &str, types : &[ValType]) -> Result<(), ValidationError> {
+        for t in types.iter().rev() {
+            self.pop_expect(instruction, *t)?;
+        }
+        Ok(())
+    }
+
+    fn set_unreachable(&mut self) {
+        let frame = self.frames.last_mut().unwrap();
+        self.stack.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    fn end_frame(&mut self) -> Result<(), ValidationError> {
+        let results = self.frames.last().unwrap().results.clone();
+        self.pop_all("end", &results)?;
+        let frame = self.frames.pop().unwrap();
+        if self.stack.len() != frame.height {
+            let left = self.stack[frame.height..].iter().map(|t| t.map_or("?", |t| t.name())).collect::<Vec<_>>().join(" ");
+            return self.mismatch("end", &names(&results), &format!("extra values [{}]", left));
+        }
+        for t in results {
+            self.push(t);
+        }
+        Ok(())
+    }
+
+    /// The types a branch to a label has to provide.
+    fn label_types(&self, label : Option<&SExpr>) -> Result<Vec<ValType>, ValidationError> {
+        let name = match label.and_then(SExpr::atom) {
+            Some(name) => name,
+            None => return Err(ValidationError::Unknown { kind: "label", name: "<missing>".to_string() }),
+        };
+        let depth = if name.starts_with('$') {
+            self.frames.iter().rev().position(|f| f.label.as_deref() == Some(name))
+        }
+        else {
+            name.parse::<usize>().ok().filter(|d| *d < self.frames.len())
+        };
+        match depth {
+            Some(d) => {
+                let frame = &self.frames[self.frames.len() - 1 - d];
+                Ok(if frame.kind == FrameKind::Loop { vec![] } else { frame.results.clone() })
+            },
+            None => Err(ValidationError::Unknown { kind: "label", name: name.to_string() }),
+        }
+    }
+
+    fn local(&self, id : Option<&SExpr>) -> Result<usize, ValidationError> {
+        lookup(&self.local_ids, self.locals.len(), "local", id)
+    }
+
+    fn instruction_sequence(&mut self, items : &[SExpr]) -> Result<(), ValidationError> {
+        let mut i = 0;
+        while i < items.len() {
+            match &items[i] {
+                SExpr::List(_, _) => {
+                    self.folded(&items[i])?;
+                    i += 1;
+                },
+                SExpr::Atom(op, position) => {
+                    if matches!( op.as_str(), "block" | "loop" | "if" | "else" | "end" ) {
+                        return syntax(*position, "flat control instructions are not supported");
+                    }
+                    let count = immediate_count(op, &items[i + 1..]);
+                    self.plain(op, &items[i + 1..i + 1 + count], *position)?;
+                    i += 1 + count;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn folded(&mut self, expr : &SExpr) -> Result<(), ValidationError> {
+        let items = match expr { SExpr::List(items, _) => items, SExpr::Atom(_, _) => unreachable!() };
+        let (op, position) = match items.first() {
+            Some(SExpr::Atom(op, position)) => (op.as_str(), *position),
+            _ => return syntax(expr.position(), "expected an instruction"),
+        };
+
+        match op {
+            "block" | "loop" => {
+                let label = items.get(1).and_then(SExpr::atom).filter(|a| a.starts_with('$')).map(str::to_string);
+                let start = if label.is_some() { 2 } else { 1 };
+                let (sig, _, body) = signature(items, start)?;
+                if !sig.params.is_empty() {
+                    return syntax(position, "block parameters are not supported");
+                }
+                let kind = if op == "loop" { FrameKind::Loop } else { FrameKind::Block };
+                self.frames.push(Frame { label, kind, results: sig.results, height: self.stack.len(), unreachable: false });
+                self.instruction_sequence(&items[body..])?;
+                self.end_frame()
+            },
+            "if" => {
+                let label = items.get(1).and_then(SExpr::atom).filter(|a| a.starts_with('$')).map(str::to_string);
+                let start = if label.is_some() { 2 } else { 1 };
+                let (sig, _, mut i) = signature(items, start)?;
+
+                while i < items.len() && !matches!( items[i].head(), Some("then") | Some("else") ) {
+                    if items[i].atom().is_some() {
+                        return syntax(items[i].position(), "expected a folded condition");
+                    }
+                    self.folded(&items[i])?;
+                    i += 1;
+                }
+                self.pop_expect("if", ValType::I32)?;
+
+                let then = match items.get(i) {
+                    Some(SExpr::List(t, _)) if t[0].atom() == Some("then") => t,
+                    _ => return syntax(position, "expected (then ...)"),
+                };
+                let otherwise = match items.get(i + 1) {
+                    Some(SExpr::List(e, _)) if e[0].atom() == Some("else") => Some(e),
+                    None => None,
+                    Some(other) => return syntax(other.position(), "expected (else ...)"),
+                };
+                if items.len() > i + 2 {
+                    return syntax(items[i + 2].position(), "unexpected item after else");
+                }
+                if otherwise.is_none() && !sig.results.is_empty() {
+                    return self.mismatch("if", &names(&sig.results), "an if without an else");
+                }
+
+                let height = self.stack.len();
+                self.frames.push(Frame { label: label.clone(), kind: FrameKind::Block, results: sig.results.clone(), height, unreachable: false });
+                self.instruction_sequence(&then[1..])?;
+                self.end_frame()?;
+                self.stack.truncate(height);
+
+                self.frames.push(Frame { label, kind: FrameKind::Block, results: sig.results.clone(), height, unreachable: false });
+                if let Some(otherwise) = otherwise {
+                    self.instruction_sequence(&otherwise[1..])?;
+                }
+                self.end_frame()
+            },
+            _ => {
+                let count = immediate_count(op, &items[1..]);
+                let operands = &items[1 + count..];
+                for operand in operands {
+                    if operand.atom().is_some() {
+                        return syntax(operand.position(), "unexpected immediate");
+                    }
+                    self.folded(operand)?;
+                }
+                self.plain(op, &items[1..1 + count], position)
+            },
+        }
+    }
+
+    fn plain(&mut self, op : &str, immediates : &[SExpr], position : usize) -> Result<(), ValidationError> {
+        match op {
+            "nop" => { },
+            "unreachable" => self.set_unreachable(),
+            "drop" => { self.pop(op)?; },
+            "select" => {
+                self.pop_expect(op, ValType::I32)?;
+                let a = self.pop(op)?;
+                let b = self.pop(op)?;
+                match (a, b) {
+                    (Some(a), Some(b)) if a != b => return self.mismatch(op, a.name(), b.name()),
+                    (Some(t), _) | (_, Some(t)) => self.push(t),
+                    (None, None) => self.stack.push(None),
+                }
+            },
+            "br" => {
+                let types = self.label_types(immediates.first())?;
+                self.pop_all(op, &types)?;
+                self.set_unreachable();
+            },
+            "br_if" => {
+                self.pop_expect(op, ValType::I32)?;
+                let types = self.label_types(immediates.first())?;
+                self.pop_all(op, &types)?;
+                for t in types {
+                    self.push(t);
+                }
+            },
+            "return" => {
+                let results = self.frames[0].results.clone();
+                self.pop_all(op, &results)?;
+                self.set_unreachable();
+            },
+            "call" => {
+                let index = lookup(&self.context.func_ids, self.context.funcs.len(), "function", immediates.first())?;
+                let sig = &self.context.funcs[index];
+                let (params, results) = (sig.params.clone(), sig.results.clone());
+                self.pop_all(op, &params)?;
+                for t in results {
+                    self.push(t);
+                }
+            },
+            "local.get" | "local.set" | "local.tee" => {
+                let t = self.locals[self.local(immediates.first())?];
+                if op != "local.get" {
+                    self.pop_expect(op, t)?;
+                }
+                if op != "local.set" {
+                    self.push(t);
+                }
+            },
+            "global.get" | "global.set" => {
+                let index = lookup(&self.context.global_ids, self.context.globals.len(), "global", immediates.first())?;
+                let (t, mutable) = self.context.globals[index];
+                if op == "global.set" {
+                    if !mutable {
+                        return self.mismatch(op, "a mutable global", "an immutable global");
+                    }
+                    self.pop_expect(op, t)?;
+                }
+                else {
+                    self.push(t);
+                }
+            },
+            "memory.size" | "memory.grow" => {
+                if self.context.memories == 0 {
+                    return Err(ValidationError::Unknown { kind: "memory", name: "0".to_string() });
+                }
+                if op == "memory.grow" {
+                    self.pop_expect(op, ValType::I32)?;
+                }
+                self.push(ValType::I32);
+            },
+            _ => {
+                let (params, results) = match numeric(op) {
+                    Some(sig) => sig,
+                    None => return Err(ValidationError::Unknown { kind: "instruction", name: op.to_string() }),
+                };
+                if op.contains(".const") {
+                    check_constant(op, immediates.first(), position)?;
+                }
+                if op.contains(".load") || op.contains(".store") {
+                    if self.context.memories == 0 {
+                        return Err(ValidationError::Unknown { kind: "memory", name: "0".to_string() });
+                    }
+                    for immediate in immediates {
+                        let valid = immediate.atom().and_then(|a| a.strip_prefix("offset=").or_else(|| a.strip_prefix("align=")))
+                                             .is_some_and(|n| n.parse::<u32>().is_ok());
+                        if !valid {
+                            return syntax(immediate.position(), "expected offset= or align=");
+                        }
+                    }
+                }
+                self.pop_all(op, &params)?;
+                for t in results {
+                    self.push(t);
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+/// How many of the atoms following an instruction are its immediates.
+fn immediate_count(op : &str, rest : &[SExpr]) -> usize {
+    match op {
+        "br" | "br_if" | "call" | "local.get" | "local.set" | "local.tee" | "global.get" | "global.set"
+            | "i32.const" | "i64.const" | "f32.const" | "f64.const" => rest.first().map_or(0, |e| e.atom().is_some() as usize),
+        op if op.contains(".load") || op.contains(".store") =>
+            rest.iter().take_while(|e| e.atom().is_some_and(|a| a.starts_with("offset=") || a.starts_with("align="))).count(),
+        _ => 0,
+    }
+}
+
+fn check_constant(op : &str, value : Option<&SExpr>, position : usize) -> Result<(), ValidationError> {
+    let value = match value.and_then(SExpr::atom) {
+        Some(value) => value.replace('_', ""),
+        None => return syntax(position, "expected a constant"),
+    };
+    let digits = value.trim_start_matches(['-', '+']);
+    let valid = match op {
+        "i32.const" | "i64.const" => match digits.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).is_ok(),
+            None => digits.parse::<u64>().is_ok(),
+        },
+        _ => digits.parse::<f64>().is_ok() || digits == "inf" || digits.starts_with("nan") || digits.starts_with("0x"),
+    };
+    if valid { Ok(()) } else { syntax(position, &format!("invalid constant {}", value)) }
+}
+
+/// The operand and result types of the numeric, conversion and memory access instructions.
+fn numeric(op : &str) -> Option<(Vec<ValType>, Vec<ValType>)> {
+    use ValType::*;
+    let (prefix, name) = op.split_once('.')?;
+    let t = ValType::parse(prefix)?;
+    let integer = matches!( t, I32 | I64 );
+
+    let binary = (vec![t, t], vec![t]);
+    let compare = (vec![t, t], vec![I32]);
+    let unary = (vec![t], vec![t]);
+    let convert = |from : ValType| Some((vec![from], vec![t]));
+
+    match name {
+        "const" => Some((vec![], vec![t])),
+        "add" | "sub" | "mul" => Some(binary),
+        "div_s" | "div_u" | "rem_s" | "rem_u" | "and" | "or" | "xor" | "shl" | "shr_s" | "shr_u" | "rotl" | "rotr" if integer => Some(binary),
+        "eq" | "ne" => Some(compare),
+        "lt_s" | "lt_u" | "gt_s" | "gt_u" | "le_s" | "le_u" | "ge_s" | "ge_u" if integer => Some(compare),
+        "eqz" if integer => Some((vec![t], vec![I32])),
+        "clz" | "ctz" | "popcnt" if integer => Some(unary),
+        "div" | "min" | "max" | "copysign" if !integer => Some(binary),
+        "lt" | "gt" | "le" | "ge" if !integer => Some(compare),
+        "neg" | "abs" | "sqrt" | "ceil" | "floor" | "trunc" | "nearest" if !integer => Some(unary),
+        "wrap_i64" if t == I32 => convert(I64),
+        "extend_i32_s" | "extend_i32_u" if t == I64 => convert(I32),
+        "trunc_f32_s" | "trunc_f32_u" if integer => convert(F32),
+        "trunc_f64_s" | "trunc_f64_u" if integer => convert(F64),
+        "convert_i32_s" | "convert_i32_u" if !integer => convert(I32),
+        "convert_i64_s" | "convert_i64_u" if !integer => convert(I64),
+        "promote_f32" if t == F64 => convert(F32),
+        "demote_f64" if t == F32 => convert(F64),
+        "reinterpret_f32" if t == I32 => convert(F32),
+        "reinterpret_f64" if t == I64 => convert(F64),
+        "reinterpret_i32" if t == F32 => convert(I32),
+        "reinterpret_i64" if t == F64 => convert(I64),
+        "load" => Some((vec![I32], vec![t])),
+        "load8_s" | "load8_u" | "load16_s" | "load16_u" if integer => Some((vec![I32], vec![t])),
+        "load32_s" | "load32_u" if t == I64 => Some((vec![I32], vec![t])),
+        "store" => Some((vec![I32, t], vec![])),
+        "store8" | "store16" if integer => Some((vec![I32, t], vec![])),
+        "store32" if t == I64 => Some((vec![I32, t], vec![])),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_accept_folded_and_flat_instructions() {
+        let wat = r#"
+(module
+  ;; a comment
+  (import "env" "log" (func $log (param i64)))
+  (memory 1)
+  (global $g (mut i32) (i32.const 0))
+  (func $add (export "add") (param $a i64) (param $b i64) (result i64)
+    (; block (; nested ;) comment ;)
+    local.get $a
+    local.get $b
+    i64.add)
+  (func (export "loop") (param i32) (result i32) (local $i i32)
+    (block $done (result i32)
+      (loop $again
+        (br_if $done (local.get $i) (i32.ge_s (local.get $i) (local.get 0)))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $again))
+      (unreachable)))
+  (func $store (param $p i32)
+    (i64.store offset=8 (local.get $p) (call $add (i64.const 1) (i64.const 0x10)))
+    (call $log (i64.load offset=8 align=8 (local.get $p)))
+    (global.set $g (memory.grow (i32.const 1)))
+    (if (i32.eqz (global.get $g)) (then (return)))
+    (drop (f64.const 1.5e3))))
+"#;
+        assert_eq!( validate(wat), Ok(()) );
+    }
+
+    #[test]
+    fn should_reject_malformed_text() {
+        assert!( matches!( validate("(module (func)"), Err(ValidationError::Syntax { .. }) ) );
+        assert!( matches!( validate("(module (func))) "), Err(ValidationError::Syntax { .. }) ) );
+        assert!( matches!( validate("(module (func (i32.const x)))"), Err(ValidationError::Syntax { .. }) ) );
+        assert!( matches!( validate("(module (memory 1) (memory 1))"), Err(ValidationError::Syntax { .. }) ) );
+        assert!( matches!( validate("(module (func (export \"f\")) (func (export \"f\")))"), Err(ValidationError::Syntax { .. }) ) );
+        assert!( matches!( validate("(modul)"), Err(ValidationError::Syntax { .. }) ) );
+    }
+
+    #[test]
+    fn should_reject_unknown_identifiers() {
+        assert_eq!( validate("(module (func (drop (local.get $x))))")
+                  , Err(ValidationError::Unknown { kind: "local", name: "$x".to_string() }) );
+        assert_eq!( validate("(module (func (call $f)))")
+                  , Err(ValidationError::Unknown { kind: "function", name: "$f".to_string() }) );
+        assert_eq!( validate("(module (func (block $a (br $b))))")
+                  , Err(ValidationError::Unknown { kind: "label", name: "$b".to_string() }) );
+        assert_eq!( validate("(module (func (drop (i32.load (i32.const 0)))))")
+                  , Err(ValidationError::Unknown { kind: "memory", name: "0".to_string() }) );
+        assert_eq!( validate("(module (func (i32.frobnicate)))")
+                  , Err(ValidationError::Unknown { kind: "instruction", name: "i32.frobnicate".to_string() }) );
+    }
+
+    #[test]
+    fn should_reject_ill_typed_code() {
+        let mismatch = |wat| match validate(wat) {
+            Err(ValidationError::TypeMismatch { expected, found, .. }) => (expected, found),
+            other => panic!("Expected TypeMismatch but found {:?}", other),
+        };
+        assert_eq!( mismatch("(module (func (result i32) (i64.const 1)))"), ("i32".to_string(), "i64".to_string()) );
+        assert_eq!( mismatch("(module (func (i32.add (i32.const 1))))"), ("an operand".to_string(), "an empty stack".to_string()) );
+        assert_eq!( mismatch("(module (func (i32.const 1)))"), ("[]".to_string(), "extra values [i32]".to_string()) );
+        assert_eq!( mismatch("(module (func (result i32) (if (result i32) (i32.const 1) (then (i32.const 2)))))")
+                  , ("[i32]".to_string(), "an if without an else".to_string()) );
+        assert_eq!( mismatch("(module (global $g i32 (i32.const 0)) (func (global.set $g (i32.const 1))))")
+                  , ("a mutable global".to_string(), "an immutable global".to_string()) );
+        assert_eq!( mismatch("(module (func (param $x f64) (local.set $x (i64.const 1))))"), ("f64".to_string(), "i64".to_string()) );
+    }
+}
@@ -0,0 +1,230 @@
+
+use crate::parsing::ast::{BinOp, UnaryOp, Type, Meta};
+use crate::parsing::parse_error::line_and_column;
+use crate::evaluating::ffi::Signature;
+
+/* A compiled program.  Every function has its own code and a fixed number of
+   local slots (parameters come first).  Operands refer to the program wide
+   pools:  `constants` for literal values, `names` for identifiers, `records`
+   for struct layouts, `patterns` for match arms and `switches` for the
+   dispatch tables of matches over enum cases.  `asserts` describe each
+   `assert` and `panic` so that a failure can be reported without the source.
+
+   Closures capture by value.  When a closure is created the captured slots of
+   the enclosing function are copied into its upvalues, and when it is called
+   the upvalues are copied into the slots the closure reads them from.
+
+   A generator function runs until its first `Yield` only once it is stepped,
+   and between steps its instruction pointer, operand stack and locals are
+   kept in the iterator.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Constant(u32),
+    Unit,
+    Bool(bool),
+    GetLocal(u32),
+    SetLocal(u32),
+    Pop,
+    PopN(u32),
+    /// Pushes a top level function by name.
+    Fun(u32),
+    /// Pushes a closure over the function with the given index.
+    Closure(u32),
+    /// Pushes an enum case constructor:  enum name, case name and arity.
+    CaseFun(u32, u32, u32),
+    /// Pushes `Type::method` or `Trait::method` as a value.
+    MethodFun(u32, u32),
+    EmptyCase(u32, u32),
+    TupleCase(u32, u32, u32),
+    Record(u32),
+    Tuple(u32),
+    List(u32),
+    Dict(u32),
+    GetField(u32),
+    /// Pops a value and then the target whose field is set.
+    SetField(u32),
+    Index,
+    /// Pops a value, a target and an index.
+    SetIndex,
+    Unary(UnaryOp),
+    Binary(BinOp),
+    CheckBool,
+    Jump(u32),
+    JumpIfFalse(u32),
+    Call(u32),
+    CallFun(u32, u32),
+    CallBuiltin(u32, u32),
+    CallMethod(u32, u32),
+    Return,
+    /// Pops a value and matches it against a pattern, jumping to the target when it does not match.
+    Match(u32, u32),
+    Switch(u32),
+    NoMatch,
+    /// Replaces an `Ok(x)` on top of the stack with `x` and jumps to the target,
+    /// leaving an `Err` where it is.
+    Propagate(u32),
+    /// Checks the operands of an assert and jumps to the target when it holds,
+    /// taking them off the stack.  When it fails they are left for `Fail`.
+    Assert(u32, u32),
+    /// Takes the message, if there is one, and the operands of an assert or
+    /// panic off the stack and fails.
+    Fail(u32),
+    /// Replaces the value on top of the stack with an iterator over it.
+    Iterate,
+    /// Pops an iterator and pushes its next item, or jumps to the target when it has none.
+    Next(u32),
+    /// Pops an item and suspends the generator.  When it is resumed a unit is pushed in its place.
+    Yield,
+    /// Pops the bounds a range has, its end on top, and pushes the range:  whether
+    /// it has a start, whether it has an end and whether it includes its end.
+    Range(bool, bool, bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture {
+    pub from : u32,
+    pub to : u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name : String,
+    pub arity : u32,
+    pub locals : u32,
+    pub captures : Vec<Capture>,
+    pub code : Vec<Op>,
+    pub param_types : Vec<Type>,
+    pub return_type : Type,
+    /// Whether calling the function gives an iterator instead of running it.
+    pub generator : bool,
+    /// Debug info:  the source span of the instructions from each index up to the next entry.
+    pub spans : Vec<(u32, Option<Meta>)>,
+}
+
+impl Function {
+    pub fn span_at(&self, ip : usize) -> Option<Meta> {
+        self.spans.iter().rev().find(|(start, _)| *start as usize <= ip).and_then(|(_, span)| *span)
+    }
+}
+
+/// The shape of a user defined type, kept so tools can describe values without the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeInfo {
+    pub name : String,
+    pub type_params : Vec<String>,
+    pub shape : Shape,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Struct(Vec<(String, Type)>),
+    Enum(Vec<(String, CaseShape)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaseShape {
+    Empty,
+    Tuple(Vec<Type>),
+    Struct(Vec<(String, Type)>),
+}
+
+/// The layout of a struct or struct case literal.  The literal pushes `values`
+/// values in source order, `fields` is in declaration order and `sources[i]` is
+/// the position of the value of `fields[i]` among the pushed values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub type_name : String,
+    pub case_name : Option<String>,
+    pub fields : Vec<String>,
+    pub sources : Vec<u32>,
+    pub values : u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchPattern {
+    Wildcard,
+    Bind(u32),
+    Literal(u32),
+    Bool(bool),
+    Tuple(Vec<MatchPattern>),
+    EmptyCase { enum_name : Option<String>, case_name : String },
+    TupleCase { enum_name : Option<String>, case_name : String, items : Vec<MatchPattern> },
+    /// Without an enum name this also matches structs named `case_name`.
+    StructCase { enum_name : Option<String>, case_name : String, fields : Vec<(String, MatchPattern)> },
+    Or(Vec<MatchPattern>),
+    /// Constants bounding the numbers matched and whether the end is included.
+    Range { start : Option<u32>, end : Option<u32>, inclusive : bool },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwitchTable {
+    pub cases : Vec<(String, u32)>,
+    pub default : u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssertSite {
+    /// The asserted condition as source text, which a panic does not have.
+    pub expression : Option<String>,
+    /// 0 for a panic, 2 for the sides of a `==` and 1 for any other condition.
+    pub operands : u32,
+    pub message : bool,
+    pub at : Meta,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodEntry {
+    pub type_name : Option<String>,
+    pub trait_name : Option<String>,
+    pub name : String,
+    pub function : u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    /// Top level functions by name.
+    pub funs : Vec<(String, u32)>,
+    pub constants : Vec<Constant>,
+    pub names : Vec<String>,
+    pub functions : Vec<Function>,
+    pub records : Vec<Record>,
+    pub patterns : Vec<MatchPattern>,
+    pub switches : Vec<SwitchTable>,
+    pub asserts : Vec<AssertSite>,
+    pub methods : Vec<MethodEntry>,
+    pub traits : Vec<String>,
+    pub types : Vec<TypeInfo>,
+    /// The extern functions a program can call, by name.
+    pub externs : Vec<Signature>,
+    pub source : Option<SourceFile>,
+}
+
+/// The source a program was compiled from, which turns spans into lines and columns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    pub name : String,
+    pub text : String,
+}
+
+impl Program {
+    pub fn fun(&self, name : &str) -> Option<usize> {
+        self.funs.iter().find(|(n, _)| n == name).map(|(_, f)| *f as usize)
+    }
+
+    /// Where a span starts as `file:line:column`, when the source is known.
+    pub fn location(&self, at : Meta) -> Option<String> {
+        self.source.as_ref().map(|s| {
+            let (line, column) = line_and_column(&s.text, at.start);
+            format!("{}:{}:{}", s.name, line, column)
+        })
+    }
+}
@@ -0,0 +1,32 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum CompileError {
+    InvalidNumber(String),
+    UnknownVariable(String),
+    UnknownCase { name : String },
+    UnknownField { type_name : String, field : String },
+    MissingField { type_name : String, field : String },
+    InvalidAssignTarget,
+    BreakOutsideLoop,
+    /// An extern function takes or returns a type native code cannot.
+    NotFfiSafe { function : String, found : String },
+    /// A constant which could not be evaluated, and why.
+    Const(String),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::InvalidNumber(n) => write!(f, "invalid number literal {}", n),
+            CompileError::UnknownVariable(name) => write!(f, "unknown variable {}", name),
+            CompileError::UnknownCase { name } => write!(f, "unknown enum case {}", name),
+            CompileError::UnknownField { type_name, field } => write!(f, "{} has no field {}", type_name, field),
+            CompileError::MissingField { type_name, field } => write!(f, "missing field {} when constructing {}", field, type_name),
+            CompileError::InvalidAssignTarget => write!(f, "invalid assignment target"),
+            CompileError::BreakOutsideLoop => write!(f, "break or continue outside of a loop"),
+            CompileError::NotFfiSafe { function, found } => write!(f, "extern function {} cannot pass {}", function, found),
+            CompileError::Const(reason) => write!(f, "{}", reason),
+        }
+    }
+}
@@ -0,0 +1,965 @@
+
+use std::collections::{HashMap, HashSet};
+
+use crate::parsing::ast::*;
+use crate::evaluating::ops;
+use crate::evaluating::value::Value;
+use crate::evaluating::dispatch::impl_methods;
+use crate::evaluating::ffi::Signature;
+use crate::evaluating::consts::{self, Consts};
+use crate::evaluating::interpreter::case_name;
+use super::bytecode::*;
+use super::compile_error::CompileError;
+use crate::parsing::printer::{print_expr, print_type};
+use crate::checking::generators::contains_yield;
+
+/* Compiles a module into a `Program` for the virtual machine.  Every
+   expression leaves exactly one value on the operand stack.  Locals live in
+   slots which are never reused within a function, so a closure can copy the
+   slots it captures when it is created.  The compiler tracks how deep the
+   operand stack is so that `break` and `continue` can drop whatever the
+   enclosing expressions had pushed before jumping.
+*/
+
+struct Loop {
+    start : usize,
+    depth : usize,
+    breaks : Vec<usize>,
+}
+
+/// A `try` block, which `?` jumps to the end of with the operand stack as it was at the start plus the error.
+struct Try {
+    depth : usize,
+    ends : Vec<usize>,
+}
+
+struct FunState {
+    scope : Vec<(String, u32)>,
+    captured : Vec<(String, u32)>,
+    captures : Vec<Capture>,
+    locals : u32,
+    code : Vec<Op>,
+    depth : usize,
+    loops : Vec<Loop>,
+    tries : Vec<Try>,
+    spans : Vec<(u32, Option<Meta>)>,
+}
+
+pub struct Compiler<'a> {
+    program : Program,
+    funs : HashMap<&'a str, u32>,
+    structs : HashMap<&'a str, &'a StructDef>,
+    enums : HashMap<&'a str, &'a EnumDef>,
+    /// Functions which the application running the program provides.
+    natives : Vec<String>,
+    externs : HashSet<&'a str>,
+    consts : Consts,
+    states : Vec<FunState>,
+    span : Option<Meta>,
+}
+
+pub fn compile(module : &Module) -> Result<Program, CompileError> {
+    Compiler::new(module).compile(module)
+}
+
+/// Compiles a module which can also call the functions named in `natives`,
+/// like it calls builtins.
+pub fn compile_with_natives(module : &Module, natives : &[String]) -> Result<Program, CompileError> {
+    let mut compiler = Compiler::new(module);
+    compiler.natives = natives.to_vec();
+    compiler.compile(module)
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new(module : &'a Module) -> Compiler<'a> {
+        let program = Program { funs: vec![]
+                              , constants: vec![]
+                              , names: vec![]
+                              , functions: vec![]
+                              , records: vec![]
+                              , patterns: vec![]
+                              , switches: vec![]
+                              , asserts: vec![]
+                              , methods: vec![]
+                              , traits: module.trait_defs.iter().map(|t| t.name.clone()).collect()
+                              , types: type_infos(module)
+                              , externs: vec![]
+                              , source: None
+                              };
+
+        Compiler { program
+                 , funs: module.fun_defs.iter().enumerate().map(|(i, f)| (f.sig.name.as_str(), i as u32)).collect()
+                 , structs: module.struct_defs.iter().map(|s| (s.name.as_str(), s)).collect()
+                 , enums: module.enum_defs.iter().map(|e| (e.name.as_str(), e)).collect()
+                 , natives: vec![]
+                 , externs: module.extern_defs.iter().map(|e| e.sig.name.as_str()).collect()
+                 , consts: Consts::default()
+                 , states: vec![]
+                 , span: None
+                 }
+    }
+
+    pub fn compile(mut self, module : &'a Module) -> Result<Program, CompileError> {
+        let mut bodies = vec![];
+
+        let (consts, errors) = consts::evaluate(module);
+        if let Some(error) = errors.into_iter().next() {
+            return Err(CompileError::Const(error.to_string()));
+        }
+        self.consts = consts;
+
+        for extern_def in &module.extern_defs {
+            let signature = Signature::of(extern_def).map_err(|t| CompileError::NotFfiSafe { function: extern_def.sig.name.clone(), found: print_type(&t) })?;
+            self.program.externs.push(signature);
+        }
+
+        for fun_def in &module.fun_defs {
+            let index = self.reserve(&fun_def.sig.name);
+            self.program.funs.push((fun_def.sig.name.clone(), index));
+            bodies.push((index, fun_def));
+        }
+
+        for (type_name, trait_name, fun_def) in impl_methods(module) {
+            let index = self.reserve(&fun_def.sig.name);
+            self.program.methods.push(MethodEntry { type_name, trait_name, name: fun_def.sig.name.clone(), function: index });
+            bodies.push((index, fun_def));
+        }
+
+        for (index, fun_def) in bodies {
+            self.function(index, &fun_def.sig.params, &fun_def.sig.return_type, &fun_def.body)?;
+        }
+
+        Ok(self.program)
+    }
+
+    fn reserve(&mut self, name : &str) -> u32 {
+        self.program.functions.push(Function { name: name.to_string()
+                                             , arity: 0
+                                             , locals: 0
+                                             , captures: vec![]
+                                             , code: vec![]
+                                             , param_types: vec![]
+                                             , return_type: Type::Infer
+                                             , generator: false
+                                             , spans: vec![]
+                                             });
+        (self.program.functions.len() - 1) as u32
+    }
+
+    fn function(&mut self, index : u32, params : &[Param], return_type : &Type, body : &Expr) -> Result<(), CompileError> {
+        let scope = params.iter().enumerate().map(|(i, p)| (p.name.clone(), i as u32)).collect();
+        self.states.push(FunState { scope
+                                  , captured: vec![]
+                                  , captures: vec![]
+                                  , locals: params.len() as u32
+                                  , code: vec![]
+                                  , depth: 0
+                                  , loops: vec![]
+                                  , tries: vec![]
+                                  , spans: vec![]
+                                  });
+
+        let result = self.expr(body);
+        self.emit(Op::Return);
+        let state = self.states.pop().unwrap();
+        result?;
+
+        let function = &mut self.program.functions[index as usize];
+        function.arity = params.len() as u32;
+        function.locals = state.locals;
+        function.captures = state.captures;
+        function.code = state.code;
+        function.param_types = params.iter().map(|p| p.param_type.clone()).collect();
+        function.return_type = return_type.clone();
+        function.generator = contains_yield(body);
+        function.spans = state.spans;
+        Ok(())
+    }
+
+    fn state(&mut self) -> &mut FunState {
+        self.states.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, op : Op) -> usize {
+        let effect = self.effect(op);
+        let span = self.span;
+        let state = self.states.last_mut().unwrap();
+        if state.spans.last().and_then(|(_, s)| *s) != span {
+            state.spans.push((state.code.len() as u32, span));
+        }
+        state.depth = (state.depth as i64 + effect) as usize;
+        state.code.push(op);
+        state.code.len() - 1
+    }
+
+    /// How many values an instruction leaves on the operand stack minus how many it takes off.
+    fn effect(&self, op : Op) -> i64 {
+        match op {
+            Op::Constant(_) | Op::Unit | Op::Bool(_) | Op::GetLocal(_) | Op::Fun(_) | Op::Closure(_)
+                | Op::CaseFun(_, _, _) | Op::MethodFun(_, _) | Op::EmptyCase(_, _) => 1,
+            Op::SetLocal(_) | Op::Pop | Op::Index | Op::Binary(_) | Op::JumpIfFalse(_) | Op::Return
+                | Op::Match(_, _) | Op::Switch(_) | Op::NoMatch => -1,
+            Op::GetField(_) | Op::Unary(_) | Op::CheckBool | Op::Jump(_) | Op::Propagate(_) | Op::Assert(_, _)
+                | Op::Iterate | Op::Next(_) | Op::Yield => 0,
+            Op::SetField(_) => -2,
+            Op::SetIndex => -3,
+            Op::PopN(n) => -(n as i64),
+            Op::Record(r) => 1 - self.program.records[r as usize].values as i64,
+            Op::TupleCase(_, _, n) | Op::Tuple(n) | Op::List(n) | Op::CallFun(_, n) | Op::CallBuiltin(_, n) => 1 - n as i64,
+            Op::Dict(n) => 1 - 2 * n as i64,
+            Op::Call(n) | Op::CallMethod(_, n) => -(n as i64),
+            Op::Range(start, end, _) => 1 - start as i64 - end as i64,
+            Op::Fail(s) => {
+                let site = &self.program.asserts[s as usize];
+                -(site.operands as i64 + site.message as i64)
+            },
+        }
+    }
+
+    fn here(&mut self) -> usize {
+        self.state().code.len()
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at : usize) {
+        let target = self.here() as u32;
+        let code = &mut self.state().code;
+        code[at] = match code[at] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::Match(pattern, _) => Op::Match(pattern, target),
+            Op::Propagate(_) => Op::Propagate(target),
+            Op::Assert(site, _) => Op::Assert(site, target),
+            Op::Next(_) => Op::Next(target),
+            op => op,
+        };
+    }
+
+    fn set_depth(&mut self, depth : usize) {
+        self.state().depth = depth;
+    }
+
+    fn new_local(&mut self) -> u32 {
+        let state = self.state();
+        state.locals += 1;
+        state.locals - 1
+    }
+
+    fn constant(&mut self, constant : Constant) -> u32 {
+        // floats are compared by their bits so that 0.0 and -0.0 stay distinct
+        let same = |c : &Constant| match (c, &constant) {
+            (Constant::Float(a), Constant::Float(b)) => a.to_bits() == b.to_bits(),
+            (a, b) => a == b,
+        };
+        match self.program.constants.iter().position(same) {
+            Some(i) => i as u32,
+            None => {
+                self.program.constants.push(constant);
+                (self.program.constants.len() - 1) as u32
+            },
+        }
+    }
+
+    fn name(&mut self, name : &str) -> u32 {
+        match self.program.names.iter().position(|n| n == name) {
+            Some(i) => i as u32,
+            None => {
+                self.program.names.push(name.to_string());
+                (self.program.names.len() - 1) as u32
+            },
+        }
+    }
+
+    fn number(&mut self, literal : &str) -> Result<u32, CompileError> {
+        match ops::number(literal) {
+            Ok(Value::Int(i)) => Ok(self.constant(Constant::Int(i))),
+            Ok(Value::Float(x)) => Ok(self.constant(Constant::Float(x))),
+            _ => Err(CompileError::InvalidNumber(literal.to_string())),
+        }
+    }
+
+    /// Finds the slot of a local, capturing it from the enclosing functions if needed.
+    fn resolve(&mut self, name : &str) -> Option<u32> {
+        let level = self.states.len() - 1;
+        self.resolve_at(level, name)
+    }
+
+    fn resolve_at(&mut self, level : usize, name : &str) -> Option<u32> {
+        let state = &self.states[level];
+        if let Some((_, slot)) = state.scope.iter().rev().find(|(n, _)| n == name) {
+            return Some(*slot);
+        }
+        if let Some((_, slot)) = state.captured.iter().find(|(n, _)| n == name) {
+            return Some(*slot);
+        }
+        if level == 0 {
+            return None;
+        }
+
+        let from = self.resolve_at(level - 1, name)?;
+        let state = &mut self.states[level];
+        let to = state.locals;
+        state.locals += 1;
+        state.captured.push((name.to_string(), to));
+        state.captures.push(Capture { from, to });
+        Some(to)
+    }
+
+    fn exprs(&mut self, exprs : &[Expr]) -> Result<(), CompileError> {
+        for e in exprs {
+            self.expr(e)?;
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, expr : &Expr) -> Result<(), CompileError> {
+        let span = self.span;
+        match expr {
+            Expr::Let { meta, .. } | Expr::Assign { meta, .. } | Expr::Call { meta, .. } | Expr::MethodCall { meta, .. }
+                | Expr::Assert { meta, .. } | Expr::Panic { meta, .. } => self.span = Some(*meta),
+            _ => (),
+        }
+        let result = self.expr_inner(expr);
+        self.span = span;
+        result
+    }
+
+    fn expr_inner(&mut self, expr : &Expr) -> Result<(), CompileError> {
+        match expr {
+            Expr::Number(n) => {
+                let c = self.number(n)?;
+                self.emit(Op::Constant(c));
+            },
+            Expr::DString(s) => {
+                let c = self.constant(Constant::String(s.clone()));
+                self.emit(Op::Constant(c));
+            },
+            Expr::Bool(b) => { self.emit(Op::Bool(*b)); },
+            Expr::Unit => { self.emit(Op::Unit); },
+            Expr::Variable(name) => {
+                if let Some(slot) = self.resolve(name) {
+                    self.emit(Op::GetLocal(slot));
+                }
+                else if let Some(literal) = self.consts.expr(name).cloned() {
+                    self.expr_inner(&literal)?;
+                }
+                else if self.funs.contains_key(name.as_str()) || self.is_builtin(name) {
+                    let n = self.name(name);
+                    self.emit(Op::Fun(n));
+                }
+                else {
+                    return Err(CompileError::UnknownVariable(name.clone()));
+                }
+            },
+            Expr::Namespace(path, name) => self.path(path, name)?,
+            Expr::Tuple(exprs) => {
+                self.exprs(exprs)?;
+                self.emit(Op::Tuple(exprs.len() as u32));
+            },
+            Expr::List(exprs) => {
+                self.exprs(exprs)?;
+                self.emit(Op::List(exprs.len() as u32));
+            },
+            Expr::Dict(pairs) => {
+                for (k, v) in pairs {
+                    self.expr(k)?;
+                    self.expr(v)?;
+                }
+                self.emit(Op::Dict(pairs.len() as u32));
+            },
+            Expr::Struct { namespace, name, fields } => {
+                for (_, e) in fields {
+                    self.expr(e)?;
+                }
+                let record = self.record(namespace, name, fields)?;
+                self.emit(Op::Record(record));
+            },
+            Expr::Block(exprs) => {
+                let scope = self.state().scope.len();
+                if exprs.is_empty() {
+                    self.emit(Op::Unit);
+                }
+                for (i, e) in exprs.iter().enumerate() {
+                    self.expr(e)?;
+                    if i + 1 < exprs.len() {
+                        self.emit(Op::Pop);
+                    }
+                }
+                self.state().scope.truncate(scope);
+            },
+            Expr::Call { fun, args, .. } => self.call(fun, args)?,
+            Expr::Dot { expr, name } => {
+                self.expr(expr)?;
+                let n = self.name(name);
+                self.emit(Op::GetField(n));
+            },
+            Expr::MethodCall { receiver, name, args, .. } => {
+                self.expr(receiver)?;
+                self.exprs(args)?;
+                let n = self.name(name);
+                self.emit(Op::CallMethod(n, args.len() as u32));
+            },
+            Expr::Index { expr, index } => {
+                self.expr(expr)?;
+                self.expr(index)?;
+                self.emit(Op::Index);
+            },
+            Expr::Range { start, end, inclusive } => {
+                for e in start.iter().chain(end) {
+                    self.expr(e)?;
+                }
+                self.emit(Op::Range(start.is_some(), end.is_some(), *inclusive));
+            },
+            Expr::Let { name, value, .. } => {
+                self.expr(value)?;
+                let slot = self.new_local();
+                self.emit(Op::SetLocal(slot));
+                self.state().scope.push((name.clone(), slot));
+                self.emit(Op::Unit);
+            },
+            Expr::Assign { target, value, .. } => {
+                self.expr(value)?;
+                self.assign(target)?;
+                self.emit(Op::Unit);
+            },
+            Expr::Lambda { params, body } => {
+                let index = self.reserve("closure");
+                self.function(index, params, &Type::Infer, body)?;
+                self.emit(Op::Closure(index));
+            },
+            Expr::Unary { op, expr } => {
+                self.expr(expr)?;
+                self.emit(Op::Unary(*op));
+            },
+            Expr::Binary { op: BinOp::And, left, right } => {
+                self.expr(left)?;
+                let otherwise = self.emit(Op::JumpIfFalse(0));
+                self.expr(right)?;
+                self.emit(Op::CheckBool);
+                let end = self.emit(Op::Jump(0));
+                self.patch(otherwise);
+                let depth = self.state().depth;
+                self.set_depth(depth - 1);
+                self.emit(Op::Bool(false));
+                self.patch(end);
+            },
+            Expr::Binary { op: BinOp::Or, left, right } => {
+                self.expr(left)?;
+                let otherwise = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Bool(true));
+                let end = self.emit(Op::Jump(0));
+                self.patch(otherwise);
+                let depth = self.state().depth;
+                self.set_depth(depth - 1);
+                self.expr(right)?;
+                self.emit(Op::CheckBool);
+                self.patch(end);
+            },
+            Expr::Binary { op, left, right } => {
+                self.expr(left)?;
+                self.expr(right)?;
+                self.emit(Op::Binary(*op));
+            },
+            Expr::If { condition, then, otherwise } => {
+                self.expr(condition)?;
+                let jump = self.emit(Op::JumpIfFalse(0));
+                let depth = self.state().depth;
+                self.expr(then)?;
+                let end = self.emit(Op::Jump(0));
+                self.patch(jump);
+                self.set_depth(depth);
+                self.expr(otherwise)?;
+                self.patch(end);
+            },
+            Expr::While { condition, body } => {
+                let start = self.here();
+                self.expr(condition)?;
+                let exit = self.emit(Op::JumpIfFalse(0));
+                let depth = self.state().depth;
+                self.state().loops.push(Loop { start, depth, breaks: vec![] });
+                let result = self.expr(body);
+                let the_loop = self.state().loops.pop().unwrap();
+                result?;
+                self.emit(Op::Pop);
+                self.emit(Op::Jump(start as u32));
+                self.patch(exit);
+                for b in the_loop.breaks {
+                    self.patch(b);
+                }
+                self.emit(Op::Unit);
+            },
+            Expr::Foreach { name, iterable, body, .. } => {
+                self.expr(iterable)?;
+                self.emit(Op::Iterate);
+                let iterator = self.new_local();
+                self.emit(Op::SetLocal(iterator));
+                let start = self.emit(Op::GetLocal(iterator));
+                let exit = self.emit(Op::Next(0));
+                let slot = self.new_local();
+                self.emit(Op::SetLocal(slot));
+                let scope = self.state().scope.len();
+                self.state().scope.push((name.clone(), slot));
+                let depth = self.state().depth;
+                self.state().loops.push(Loop { start, depth, breaks: vec![] });
+                let result = self.expr(body);
+                let the_loop = self.state().loops.pop().unwrap();
+                self.state().scope.truncate(scope);
+                result?;
+                self.emit(Op::Pop);
+                self.emit(Op::Jump(start as u32));
+                self.patch(exit);
+                for b in the_loop.breaks {
+                    self.patch(b);
+                }
+                self.emit(Op::Unit);
+            },
+            Expr::Yield(e) => {
+                self.expr(e)?;
+                self.emit(Op::Yield);
+            },
+            Expr::Break | Expr::Continue => {
+                let (start, loop_depth) = match self.state().loops.last() {
+                    Some(l) => (l.start, l.depth),
+                    None => return Err(CompileError::BreakOutsideLoop),
+                };
+                let depth = self.state().depth;
+                if depth > loop_depth {
+                    self.emit(Op::PopN((depth - loop_depth) as u32));
+                }
+                if matches!( expr, Expr::Break ) {
+                    let jump = self.emit(Op::Jump(0));
+                    self.state().loops.last_mut().unwrap().breaks.push(jump);
+                }
+                else {
+                    self.emit(Op::Jump(start as u32));
+                }
+                // the value of the expression is never used, but it keeps the depth consistent
+                self.set_depth(depth);
+                self.emit(Op::Unit);
+            },
+            Expr::Return(e) => {
+                self.expr(e)?;
+                self.emit(Op::Return);
+                self.emit(Op::Unit);
+            },
+            Expr::Try(body) => {
+                let depth = self.state().depth;
+                self.state().tries.push(Try { depth, ends: vec![] });
+                let result = self.expr(body);
+                let the_try = self.state().tries.pop().unwrap();
+                result?;
+                for end in the_try.ends {
+                    self.patch(end);
+                }
+            },
+            Expr::Propagate(e) => {
+                self.expr(e)?;
+                let ok = self.emit(Op::Propagate(0));
+                let depth = self.state().depth;
+                match self.state().tries.last().map(|t| t.depth) {
+                    None => { self.emit(Op::Return); },
+                    Some(try_depth) => {
+                        // keep the error and drop what the enclosing expressions had pushed
+                        if depth - 1 > try_depth {
+                            let temp = self.new_local();
+                            self.emit(Op::SetLocal(temp));
+                            self.emit(Op::PopN((depth - 1 - try_depth) as u32));
+                            self.emit(Op::GetLocal(temp));
+                        }
+                        let jump = self.emit(Op::Jump(0));
+                        self.state().tries.last_mut().unwrap().ends.push(jump);
+                    },
+                }
+                self.patch(ok);
+                self.set_depth(depth);
+            },
+            Expr::Assert { condition, message, meta } => {
+                let depth = self.state().depth;
+                let operands = match &**condition {
+                    Expr::Binary { op: BinOp::Eq, left, right } => {
+                        self.expr(left)?;
+                        self.expr(right)?;
+                        2
+                    },
+                    condition => {
+                        self.expr(condition)?;
+                        1
+                    },
+                };
+                let site = self.assert_site(Some(print_expr(condition, 0)), operands, message.is_some(), *meta);
+                let holds = self.emit(Op::Assert(site, 0));
+                if let Some(message) = message {
+                    self.expr(message)?;
+                }
+                self.emit(Op::Fail(site));
+                self.patch(holds);
+                self.set_depth(depth);
+                self.emit(Op::Unit);
+            },
+            Expr::Panic { message, meta } => {
+                self.expr(message)?;
+                let site = self.assert_site(None, 0, true, *meta);
+                self.emit(Op::Fail(site));
+                self.emit(Op::Unit);
+            },
+            Expr::Match { expr, arms } => self.match_expr(expr, arms)?,
+        }
+        Ok(())
+    }
+
+    fn assert_site(&mut self, expression : Option<String>, operands : u32, message : bool, at : Meta) -> u32 {
+        self.program.asserts.push(AssertSite { expression, operands, message, at });
+        (self.program.asserts.len() - 1) as u32
+    }
+
+    fn path(&mut self, path : &[String], name : &str) -> Result<(), CompileError> {
+        let owner = match path.last() {
+            Some(owner) => owner,
+            None => return Err(CompileError::UnknownVariable(name.to_string())),
+        };
+
+        let case = self.enums.get(owner.as_str()).and_then(|e| e.cases.iter().find(|c| case_name(c) == name));
+        let (o, n) = (self.name(owner), self.name(name));
+        match case {
+            Some(EnumCase::EmptyCase { .. }) => { self.emit(Op::EmptyCase(o, n)); },
+            Some(EnumCase::TypeCase { types, .. }) => { self.emit(Op::CaseFun(o, n, types.len() as u32)); },
+            Some(EnumCase::StructCase { .. }) => return Err(CompileError::UnknownCase { name: format!("{}::{}", owner, name) }),
+            None => { self.emit(Op::MethodFun(o, n)); },
+        }
+        Ok(())
+    }
+
+    fn is_builtin(&self, name : &str) -> bool {
+        ops::BUILTIN_FUNS.contains(&name) || self.natives.iter().any(|n| n == name) || self.externs.contains(name)
+    }
+
+    fn call(&mut self, fun : &Expr, args : &[Expr]) -> Result<(), CompileError> {
+        let argc = args.len() as u32;
+
+        match fun {
+            Expr::Variable(name) if self.resolve(name).is_none() && !self.consts.contains(name) => {
+                if let Some(index) = self.funs.get(name.as_str()).copied() {
+                    self.exprs(args)?;
+                    self.emit(Op::CallFun(index, argc));
+                    return Ok(());
+                }
+                if self.is_builtin(name) {
+                    self.exprs(args)?;
+                    let n = self.name(name);
+                    self.emit(Op::CallBuiltin(n, argc));
+                    return Ok(());
+                }
+            },
+            Expr::Namespace(path, name) => {
+                let arity = path.last()
+                                .and_then(|owner| self.enums.get(owner.as_str()))
+                                .and_then(|e| e.cases.iter().find(|c| case_name(c) == name))
+                                .and_then(|c| match c {
+                                    EnumCase::TypeCase { types, .. } => Some(types.len()),
+                                    _ => None,
+                                });
+                if arity == Some(args.len()) {
+                    self.exprs(args)?;
+                    let (o, n) = (self.name(path.last().unwrap()), self.name(name));
+                    self.emit(Op::TupleCase(o, n, argc));
+                    return Ok(());
+                }
+            },
+            _ => (),
+        }
+
+        self.expr(fun)?;
+        self.exprs(args)?;
+        self.emit(Op::Call(argc));
+        Ok(())
+    }
+
+    fn record(&mut self, namespace : &[String], name : &str, values : &[(String, Expr)]) -> Result<u32, CompileError> {
+        let (type_name, case, fields) = match namespace.last() {
+            Some(owner) => {
+                let fields = self.enums.get(owner.as_str())
+                                       .and_then(|e| e.cases.iter().find(|c| case_name(c) == name))
+                                       .and_then(|c| match c {
+                                           EnumCase::StructCase { fields, .. } => Some(fields),
+                                           _ => None,
+                                       })
+                                       .ok_or_else(|| CompileError::UnknownCase { name: format!("{}::{}", owner, name) })?;
+                (owner.clone(), Some(name.to_string()), fields)
+            },
+            None => {
+                let fields = self.structs.get(name)
+                                         .map(|s| &s.fields)
+                                         .ok_or_else(|| CompileError::UnknownVariable(name.to_string()))?;
+                (name.to_string(), None, fields)
+            },
+        };
+        let display_name = match &case {
+            Some(case) => format!("{}::{}", type_name, case),
+            None => type_name.clone(),
+        };
+
+        if let Some((extra, _)) = values.iter().find(|(n, _)| !fields.iter().any(|f| &f.name == n)) {
+            return Err(CompileError::UnknownField { type_name: display_name, field: extra.clone() });
+        }
+
+        let mut sources = vec![];
+        for field in fields {
+            match values.iter().position(|(n, _)| n == &field.name) {
+                Some(i) => sources.push(i as u32),
+                None => return Err(CompileError::MissingField { type_name: display_name, field: field.name.clone() }),
+            }
+        }
+
+        self.program.records.push(Record { type_name
+                                         , case_name: case
+                                         , fields: fields.iter().map(|f| f.name.clone()).collect()
+                                         , sources
+                                         , values: values.len() as u32
+                                         });
+        Ok((self.program.records.len() - 1) as u32)
+    }
+
+    fn assign(&mut self, target : &Expr) -> Result<(), CompileError> {
+        match target {
+            Expr::Variable(name) => match self.resolve(name) {
+                Some(slot) => { self.emit(Op::SetLocal(slot)); },
+                None => return Err(CompileError::UnknownVariable(name.clone())),
+            },
+            Expr::Dot { expr, name } => {
+                self.expr(expr)?;
+                let n = self.name(name);
+                self.emit(Op::SetField(n));
+            },
+            Expr::Index { expr, index } => {
+                self.expr(expr)?;
+                self.expr(index)?;
+                self.emit(Op::SetIndex);
+            },
+            _ => return Err(CompileError::InvalidAssignTarget),
+        }
+        Ok(())
+    }
+
+    /* The value being matched is kept in a slot and every arm matches it in
+       turn.  When the first arms all match different enum cases, a switch
+       table jumps straight to the arm for the case of the value.
+    */
+    fn match_expr(&mut self, expr : &Expr, arms : &[MatchArm]) -> Result<(), CompileError> {
+        self.expr(expr)?;
+        let value = self.new_local();
+        self.emit(Op::SetLocal(value));
+
+        let (cases, rest) = switch_cases(arms);
+        let switch = match cases.len() {
+            0 | 1 => None,
+            _ => {
+                self.emit(Op::GetLocal(value));
+                self.program.switches.push(SwitchTable { cases: vec![], default: 0 });
+                let table = (self.program.switches.len() - 1) as u32;
+                self.emit(Op::Switch(table));
+                Some(table)
+            },
+        };
+
+        let depth = self.state().depth;
+        let mut starts = vec![];
+        let mut ends = vec![];
+        for arm in arms {
+            starts.push(self.here() as u32);
+            self.emit(Op::GetLocal(value));
+
+            let scope = self.state().scope.len();
+            let mut bindings = vec![];
+            let pattern = self.pattern(&arm.pattern, &mut bindings);
+            self.program.patterns.push(pattern);
+            let test = self.emit(Op::Match((self.program.patterns.len() - 1) as u32, 0));
+            self.state().scope.extend(bindings);
+
+            let span = self.span;
+            self.span = Some(arm.meta);
+            let result = self.expr(&arm.body);
+            self.span = span;
+            self.state().scope.truncate(scope);
+            result?;
+
+            ends.push(self.emit(Op::Jump(0)));
+            self.patch(test);
+            self.set_depth(depth);
+        }
+
+        starts.push(self.here() as u32);
+        self.emit(Op::GetLocal(value));
+        self.emit(Op::NoMatch);
+
+        if let Some(table) = switch {
+            let table = &mut self.program.switches[table as usize];
+            table.cases = cases.iter().map(|(name, arm)| (name.clone(), starts[*arm])).collect();
+            table.default = starts[rest];
+        }
+
+        for end in ends {
+            self.patch(end);
+        }
+        self.set_depth(depth + 1);
+        Ok(())
+    }
+
+    fn pattern(&mut self, pattern : &Pattern, bindings : &mut Vec<(String, u32)>) -> MatchPattern {
+        match pattern {
+            Pattern::Wildcard => MatchPattern::Wildcard,
+            Pattern::Number(n) => match self.number(n) {
+                Ok(c) => MatchPattern::Literal(c),
+                // a literal which is not a number can never match
+                Err(_) => MatchPattern::Or(vec![]),
+            },
+            Pattern::DString(s) => MatchPattern::Literal(self.constant(Constant::String(s.clone()))),
+            Pattern::Range { start, end, inclusive } => {
+                let mut bound = |n : &Option<String>| n.as_ref().map(|n| self.number(n)).transpose();
+                match (bound(start), bound(end)) {
+                    (Ok(start), Ok(end)) => MatchPattern::Range { start, end, inclusive: *inclusive },
+                    _ => MatchPattern::Or(vec![]),
+                }
+            },
+            Pattern::Bool(b) => MatchPattern::Bool(*b),
+            Pattern::Variable(name) if self.consts.contains(name) => {
+                let constant = self.consts.substitute(pattern).into_owned();
+                self.pattern(&constant, bindings)
+            },
+            Pattern::Variable(name) => match bindings.iter().find(|(n, _)| n == name) {
+                Some((_, slot)) => MatchPattern::Bind(*slot),
+                None => {
+                    let slot = self.new_local();
+                    bindings.push((name.clone(), slot));
+                    MatchPattern::Bind(slot)
+                },
+            },
+            Pattern::Tuple(ps) => MatchPattern::Tuple(ps.iter().map(|p| self.pattern(p, bindings)).collect()),
+            Pattern::Or(ps) => MatchPattern::Or(ps.iter().map(|p| self.pattern(p, bindings)).collect()),
+            Pattern::Case { namespace, name, contents } => {
+                let enum_name = namespace.last().cloned();
+                let case_name = name.clone();
+                match contents {
+                    CasePattern::Empty => MatchPattern::EmptyCase { enum_name, case_name },
+                    CasePattern::Tuple(ps) =>
+                        MatchPattern::TupleCase { enum_name, case_name, items: ps.iter().map(|p| self.pattern(p, bindings)).collect() },
+                    CasePattern::Struct { fields, .. } =>
+                        MatchPattern::StructCase { enum_name
+                                                 , case_name
+                                                 , fields: fields.iter().map(|(n, p)| (n.clone(), self.pattern(p, bindings))).collect()
+                                                 },
+                }
+            },
+        }
+    }
+}
+
+fn type_infos(module : &Module) -> Vec<TypeInfo> {
+    let fields = |fields : &[StructField]| fields.iter().map(|f| (f.name.clone(), f.field_type.clone())).collect();
+    let params = |type_params : &[TypeParam]| type_params.iter().map(|p| p.name.clone()).collect();
+
+    let structs = module.struct_defs.iter().map(|s| TypeInfo { name: s.name.clone()
+                                                              , type_params: params(&s.type_params)
+                                                              , shape: Shape::Struct(fields(&s.fields))
+                                                              });
+    let enums = module.enum_defs.iter().map(|e| {
+        let cases = e.cases.iter().map(|c| match c {
+            EnumCase::EmptyCase { name } => (name.clone(), CaseShape::Empty),
+            EnumCase::TypeCase { name, types } => (name.clone(), CaseShape::Tuple(types.clone())),
+            EnumCase::StructCase { name, fields: fs } => (name.clone(), CaseShape::Struct(fields(fs))),
+        }).collect();
+        TypeInfo { name: e.name.clone(), type_params: params(&e.type_params), shape: Shape::Enum(cases) }
+    });
+
+    structs.chain(enums).collect()
+}
+
+/// The case names of the leading arms which match enum cases along with the
+/// first arm for each case, and the first arm which could match any case.
+fn switch_cases(arms : &[MatchArm]) -> (Vec<(String, usize)>, usize) {
+    let mut cases : Vec<(String, usize)> = vec![];
+    for (i, arm) in arms.iter().enumerate() {
+        match &arm.pattern {
+            Pattern::Case { namespace, name, .. } if !namespace.is_empty() => {
+                if !cases.iter().any(|(n, _)| n == name) {
+                    cases.push((name.clone(), i));
+                }
+            },
+            _ => return (cases, i),
+        }
+    }
+    (cases, arms.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+
+    fn compile_src(src : &str) -> Result<Program, CompileError> {
+        compile(&parse(src).unwrap())
+    }
+
+    #[test]
+    fn should_compile_locals_into_slots() {
+        let program = compile_src("fun main(a : Int) { let b = a; b }").unwrap();
+        let main = &program.functions[0];
+        assert_eq!( main.arity, 1 );
+        assert_eq!( main.locals, 2 );
+        assert_eq!( main.code, vec![ Op::GetLocal(0), Op::SetLocal(1), Op::Unit, Op::Pop, Op::GetLocal(1), Op::Return ] );
+    }
+
+    #[test]
+    fn should_capture_locals_of_enclosing_functions() {
+        let program = compile_src("fun main() { let a = 1; let f = || || a; f }").unwrap();
+        let outer = &program.functions[1];
+        let inner = &program.functions[2];
+        assert_eq!( outer.captures, vec![Capture { from: 0, to: 0 }] );
+        assert_eq!( inner.captures, vec![Capture { from: 0, to: 0 }] );
+    }
+
+    #[test]
+    fn should_build_switch_tables_for_enum_matches() {
+        let program = compile_src(r#"
+enum Shape { Circle(Int), Square(Int), Dot }
+fun area(s : Shape) -> Int {
+    match s {
+        Shape::Circle(r) => 3 * r * r,
+        Shape::Square(w) => w * w,
+        _ => 0,
+    }
+}
+"#).unwrap();
+        assert_eq!( program.switches.len(), 1 );
+        let table = &program.switches[0];
+        let names = table.cases.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+        assert_eq!( names, vec!["Circle", "Square"] );
+
+        let code = &program.functions[0].code;
+        for (_, target) in &table.cases {
+            assert!( matches!( code[*target as usize], Op::GetLocal(_) ) );
+        }
+        assert!( matches!( code[table.default as usize + 1], Op::Match(_, _) ) );
+    }
+
+    #[test]
+    fn should_record_source_spans_of_instructions() {
+        let src = "fun main() { let x = 1; x.show() }";
+        let program = compile_src(src).unwrap();
+        let main = &program.functions[0];
+        let text = |ip : usize| main.span_at(ip).map(|m| &src[m.start..m.end]);
+
+        assert_eq!( text(0), Some("let x = 1") );
+        assert_eq!( text(1), Some("let x = 1") );
+        assert_eq!( text(3), None );
+        assert_eq!( text(5), Some("x.show()") );
+    }
+
+    #[test]
+    fn should_report_compile_errors() {
+        assert_eq!( compile_src("fun main() { x }").unwrap_err(), CompileError::UnknownVariable("x".to_string()) );
+        assert_eq!( compile_src("fun main() { break }").unwrap_err(), CompileError::BreakOutsideLoop );
+        assert_eq!( compile_src("fun main() { while true { || { break } } }").unwrap_err(), CompileError::BreakOutsideLoop );
+        assert_eq!( compile_src("struct P { x : Int } fun main() { P { y : 1 } }").unwrap_err()
+                  , CompileError::UnknownField { type_name: "P".to_string(), field: "y".to_string() } );
+    }
+}
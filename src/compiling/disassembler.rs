@@ -0,0 +1,99 @@
+
+use std::fmt::Write;
+
+use super::bytecode::*;
+
+/* A readable listing of a compiled program for debugging the compiler.  Each
+   instruction is printed with its index and, where an operand refers into one
+   of the program's pools, what it refers to.
+*/
+
+pub fn disassemble(program : &Program) -> String {
+    let mut out = String::new();
+
+    for (i, function) in program.functions.iter().enumerate() {
+        if i != 0 {
+            out.push('\n');
+        }
+        let kind = if function.generator { ", generator" } else { "" };
+        writeln!(out, "fun #{} {} (arity {}, locals {}{})", i, function.name, function.arity, function.locals, kind).unwrap();
+        for capture in &function.captures {
+            writeln!(out, "    capture {} -> {}", capture.from, capture.to).unwrap();
+        }
+        for (ip, op) in function.code.iter().enumerate() {
+            match comment(program, *op) {
+                Some(comment) => writeln!(out, "    {:04}  {:<20} ; {}", ip, format!("{:?}", op), comment).unwrap(),
+                None => writeln!(out, "    {:04}  {:?}", ip, op).unwrap(),
+            }
+        }
+    }
+
+    if !program.methods.is_empty() {
+        out.push('\n');
+        for m in &program.methods {
+            let type_name = m.type_name.as_deref().unwrap_or("_");
+            match &m.trait_name {
+                Some(trait_name) => writeln!(out, "impl {} for {} : {} -> #{}", trait_name, type_name, m.name, m.function).unwrap(),
+                None => writeln!(out, "impl {} : {} -> #{}", type_name, m.name, m.function).unwrap(),
+            }
+        }
+    }
+
+    out
+}
+
+fn comment(program : &Program, op : Op) -> Option<String> {
+    let name = |n : u32| &program.names[n as usize];
+    let function = |f : u32| &program.functions[f as usize].name;
+
+    match op {
+        Op::Constant(c) => Some(match &program.constants[c as usize] {
+            Constant::Int(i) => i.to_string(),
+            Constant::Float(x) => format!("{:?}", x),
+            Constant::String(s) => format!("{:?}", s),
+        }),
+        Op::Fun(n) | Op::GetField(n) | Op::SetField(n) | Op::CallBuiltin(n, _) | Op::CallMethod(n, _) => Some(name(n).clone()),
+        Op::Closure(f) | Op::CallFun(f, _) => Some(format!("#{} {}", f, function(f))),
+        Op::CaseFun(a, b, _) | Op::MethodFun(a, b) | Op::EmptyCase(a, b) | Op::TupleCase(a, b, _) =>
+            Some(format!("{}::{}", name(a), name(b))),
+        Op::Record(r) => {
+            let record = &program.records[r as usize];
+            match &record.case_name {
+                Some(case_name) => Some(format!("{}::{} {{ {} }}", record.type_name, case_name, record.fields.join(", "))),
+                None => Some(format!("{} {{ {} }}", record.type_name, record.fields.join(", "))),
+            }
+        },
+        Op::Match(p, _) => Some(format!("{:?}", program.patterns[p as usize])),
+        Op::Switch(t) => {
+            let table = &program.switches[t as usize];
+            let cases = table.cases.iter().map(|(n, target)| format!("{} => {}", n, target)).collect::<Vec<_>>();
+            Some(format!("{}, _ => {}", cases.join(", "), table.default))
+        },
+        Op::Assert(a, _) | Op::Fail(a) => Some(program.asserts[a as usize].expression.clone().unwrap_or_else(|| "panic".to_string())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+    use super::super::compiler::compile;
+
+    #[test]
+    fn should_list_instructions_with_their_operands() {
+        let program = compile(&parse(r#"fun main() { let s = "hi"; println(s, 1.5) }"#).unwrap()).unwrap();
+        let listing = disassemble(&program);
+
+        assert_eq!( listing, r#"fun #0 main (arity 0, locals 1)
+    0000  Constant(0)          ; "hi"
+    0001  SetLocal(0)
+    0002  Unit
+    0003  Pop
+    0004  GetLocal(0)
+    0005  Constant(1)          ; 1.5
+    0006  CallBuiltin(0, 2)    ; println
+    0007  Return
+"# );
+    }
+}
@@ -0,0 +1,7 @@
+
+pub mod bytecode;
+pub mod compile_error;
+pub mod compiler;
+pub mod vm;
+pub mod disassembler;
+pub mod module_file;
@@ -0,0 +1,971 @@
+
+use std::fmt;
+use std::convert::TryInto;
+
+use crate::parsing::ast::{BinOp, UnaryOp, Type, Meta};
+use crate::evaluating::ffi::{CType, Signature};
+use super::bytecode::*;
+
+/* The binary format of compiled modules.  A file starts with a header:
+
+       magic "DUST", format version (u16), CRC-32 of the payload (u32), payload length (u32)
+
+   followed by the payload, which holds every pool of the `Program` in order.
+   All integers are little endian and strings and lists are prefixed with their
+   length.  The version is checked before anything else, so a file written by
+   another version of dust is reported as such rather than as corrupt.
+*/
+
+pub const MAGIC : &[u8; 4] = b"DUST";
+pub const FORMAT_VERSION : u16 = 6;
+
+const HEADER_LEN : usize = 14;
+
+#[derive(Debug, PartialEq)]
+pub enum LoadError {
+    NotAModule,
+    VersionMismatch { expected : u16, found : u16 },
+    ChecksumMismatch { expected : u32, found : u32 },
+    Truncated,
+    Invalid(String),
+    Io(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::NotAModule => write!(f, "not a compiled dust module"),
+            LoadError::VersionMismatch { expected, found } =>
+                write!(f, "compiled module has format version {} but this dust reads version {}; recompile it", found, expected),
+            LoadError::ChecksumMismatch { expected, found } =>
+                write!(f, "compiled module is corrupt (checksum {:08x} but contents hash to {:08x})", expected, found),
+            LoadError::Truncated => write!(f, "compiled module is truncated"),
+            LoadError::Invalid(message) => write!(f, "compiled module is invalid: {}", message),
+            LoadError::Io(message) => write!(f, "io error: {}", message),
+        }
+    }
+}
+
+pub fn save(program : &Program, path : &str) -> Result<(), LoadError> {
+    std::fs::write(path, write_program(program)).map_err(|e| LoadError::Io(e.to_string()))
+}
+
+pub fn load(path : &str) -> Result<Program, LoadError> {
+    let bytes = std::fs::read(path).map_err(|e| LoadError::Io(e.to_string()))?;
+    read_program(&bytes)
+}
+
+pub fn write_program(program : &Program) -> Vec<u8> {
+    let mut w = Writer { bytes: vec![] };
+    w.program(program);
+    let payload = w.bytes;
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(FORMAT_VERSION.to_le_bytes());
+    bytes.extend(crc32(&payload).to_le_bytes());
+    bytes.extend((payload.len() as u32).to_le_bytes());
+    bytes.extend(payload);
+    bytes
+}
+
+pub fn read_program(bytes : &[u8]) -> Result<Program, LoadError> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(LoadError::NotAModule);
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(LoadError::Truncated);
+    }
+
+    let mut header = Reader { bytes: &bytes[..HEADER_LEN], position: MAGIC.len() };
+    let version = header.u16()?;
+    if version != FORMAT_VERSION {
+        return Err(LoadError::VersionMismatch { expected: FORMAT_VERSION, found: version });
+    }
+    let checksum = header.u32()?;
+    let length = header.u32()? as usize;
+
+    let payload = &bytes[HEADER_LEN..];
+    if payload.len() < length {
+        return Err(LoadError::Truncated);
+    }
+    let payload = &payload[..length];
+    let found = crc32(payload);
+    if found != checksum {
+        return Err(LoadError::ChecksumMismatch { expected: checksum, found });
+    }
+
+    let mut r = Reader { bytes: payload, position: 0 };
+    let program = r.program()?;
+    if r.position != payload.len() {
+        return Err(LoadError::Invalid("trailing bytes after program".to_string()));
+    }
+    verify(&program)?;
+    Ok(program)
+}
+
+/* Checks a decoded program before the VM runs it.  The VM indexes its tables and
+   code without checking, trusting the compiler, so every operand has to be in
+   range, every jump has to land inside its function and the operand stack has to
+   have the same height on every path to an instruction and never go below the
+   frame it belongs to.
+*/
+fn verify(program : &Program) -> Result<(), LoadError> {
+    let fail = |message : String| Err(LoadError::Invalid(message));
+    let functions = program.functions.len();
+
+    for (name, f) in &program.funs {
+        if *f as usize >= functions {
+            return fail(format!("fun {}: function {} out of range", name, f));
+        }
+    }
+    for m in &program.methods {
+        if m.function as usize >= functions {
+            return fail(format!("method {}: function {} out of range", m.name, m.function));
+        }
+    }
+    for (i, record) in program.records.iter().enumerate() {
+        if record.sources.len() != record.fields.len() || record.sources.iter().any(|s| *s >= record.values) {
+            return fail(format!("record {}: field sources out of range", i));
+        }
+    }
+    for function in &program.functions {
+        verify_function(program, function).map_err(|message| LoadError::Invalid(format!("function {}: {}", function.name, message)))?;
+    }
+    Ok(())
+}
+
+fn verify_function(program : &Program, function : &Function) -> Result<(), String> {
+    let code = &function.code;
+    let locals = function.locals;
+    if function.arity > locals {
+        return Err(format!("arity {} but {} locals", function.arity, locals));
+    }
+    if let Some(c) = function.captures.iter().find(|c| c.to >= locals) {
+        return Err(format!("capture into local {} out of range", c.to));
+    }
+
+    let index = |what : &str, i : u32, len : usize, ip : usize| {
+        if (i as usize) < len { Ok(()) } else { Err(format!("{} {} out of range at {}", what, i, ip)) }
+    };
+
+    // the stack height before each instruction, once some path reaches it
+    let mut heights : Vec<Option<u32>> = vec![None; code.len()];
+    let mut pending = vec![(0, 0)];
+    while let Some((ip, height)) = pending.pop() {
+        if ip >= code.len() {
+            return Err(format!("jump to {} past the end", ip));
+        }
+        match heights[ip] {
+            Some(h) if h == height => continue,
+            Some(h) => return Err(format!("stack height {} and {} at {}", h, height, ip)),
+            None => heights[ip] = Some(height),
+        }
+
+        let (pops, pushes) = match code[ip] {
+            Op::Constant(c) => { index("constant", c, program.constants.len(), ip)?; (0, 1) },
+            Op::Unit | Op::Bool(_) => (0, 1),
+            Op::GetLocal(slot) => { index("local", slot, locals as usize, ip)?; (0, 1) },
+            Op::SetLocal(slot) => { index("local", slot, locals as usize, ip)?; (1, 0) },
+            Op::Pop => (1, 0),
+            Op::PopN(n) => (n, 0),
+            Op::Fun(n) => { index("name", n, program.names.len(), ip)?; (0, 1) },
+            Op::Closure(f) => {
+                index("function", f, program.functions.len(), ip)?;
+                if program.functions[f as usize].captures.iter().any(|c| c.from >= locals) {
+                    return Err(format!("closure captures a local out of range at {}", ip));
+                }
+                (0, 1)
+            },
+            Op::CaseFun(e, c, _) | Op::MethodFun(e, c) | Op::EmptyCase(e, c) => {
+                index("name", e, program.names.len(), ip)?;
+                index("name", c, program.names.len(), ip)?;
+                (0, 1)
+            },
+            Op::TupleCase(e, c, n) => {
+                index("name", e, program.names.len(), ip)?;
+                index("name", c, program.names.len(), ip)?;
+                (n, 1)
+            },
+            Op::Record(r) => { index("record", r, program.records.len(), ip)?; (program.records[r as usize].values, 1) },
+            Op::Tuple(n) | Op::List(n) => (n, 1),
+            Op::Dict(n) => (n.checked_mul(2).ok_or_else(|| format!("dict of {} pairs at {}", n, ip))?, 1),
+            Op::GetField(n) => { index("name", n, program.names.len(), ip)?; (1, 1) },
+            Op::SetField(n) => { index("name", n, program.names.len(), ip)?; (2, 0) },
+            Op::Index => (2, 1),
+            Op::SetIndex => (3, 0),
+            Op::Unary(_) | Op::Iterate | Op::Yield => (1, 1),
+            Op::Binary(_) => (2, 1),
+            Op::CheckBool => (1, 1),
+            Op::Jump(_) => (0, 0),
+            Op::JumpIfFalse(_) | Op::Match(_, _) | Op::Switch(_) => (1, 0),
+            // what is left when they jump is handled with their targets below
+            Op::Propagate(_) | Op::Next(_) => (1, 1),
+            Op::Call(n) => (n.checked_add(1).ok_or_else(|| format!("call with {} arguments at {}", n, ip))?, 1),
+            Op::CallFun(f, n) => { index("function", f, program.functions.len(), ip)?; (n, 1) },
+            Op::CallBuiltin(b, n) => { index("name", b, program.names.len(), ip)?; (n, 1) },
+            Op::CallMethod(m, n) => {
+                index("name", m, program.names.len(), ip)?;
+                (n.checked_add(1).ok_or_else(|| format!("call with {} arguments at {}", n, ip))?, 1)
+            },
+            Op::Return | Op::NoMatch => (1, 0),
+            Op::Assert(s, _) => {
+                index("assert", s, program.asserts.len(), ip)?;
+                if height < program.asserts[s as usize].operands.max(1) {
+                    return Err(format!("stack underflow at {}", ip));
+                }
+                (0, 0)
+            },
+            Op::Fail(s) => {
+                index("assert", s, program.asserts.len(), ip)?;
+                let site = &program.asserts[s as usize];
+                (site.operands + site.message as u32, 0)
+            },
+            Op::Range(start, end, _) => (start as u32 + end as u32, 1),
+        };
+        if height < pops {
+            return Err(format!("stack underflow at {}", ip));
+        }
+        let after = height - pops + pushes;
+
+        match code[ip] {
+            Op::Jump(target) => pending.push((target as usize, height)),
+            Op::JumpIfFalse(target) => {
+                pending.push((target as usize, after));
+                pending.push((ip + 1, after));
+            },
+            Op::Match(p, target) => {
+                index("pattern", p, program.patterns.len(), ip)?;
+                verify_pattern(program, &program.patterns[p as usize], locals).map_err(|e| format!("{} at {}", e, ip))?;
+                pending.push((target as usize, after));
+                pending.push((ip + 1, after));
+            },
+            Op::Switch(t) => {
+                index("switch", t, program.switches.len(), ip)?;
+                let table = &program.switches[t as usize];
+                for target in table.cases.iter().map(|(_, target)| *target).chain(Some(table.default)) {
+                    pending.push((target as usize, after));
+                }
+            },
+            Op::Propagate(target) => {
+                pending.push((target as usize, after));
+                pending.push((ip + 1, after));
+            },
+            Op::Assert(s, target) => {
+                pending.push((target as usize, height - program.asserts[s as usize].operands));
+                pending.push((ip + 1, height));
+            },
+            Op::Next(target) => {
+                pending.push((target as usize, after - 1));
+                pending.push((ip + 1, after));
+            },
+            Op::Return | Op::NoMatch | Op::Fail(_) => (),
+            _ => pending.push((ip + 1, after)),
+        }
+    }
+    Ok(())
+}
+
+fn verify_pattern(program : &Program, pattern : &MatchPattern, locals : u32) -> Result<(), String> {
+    let constant = |c : u32| if (c as usize) < program.constants.len() { Ok(()) } else { Err(format!("pattern constant {} out of range", c)) };
+    match pattern {
+        MatchPattern::Wildcard | MatchPattern::Bool(_) | MatchPattern::EmptyCase { .. } => Ok(()),
+        MatchPattern::Bind(slot) if *slot < locals => Ok(()),
+        MatchPattern::Bind(slot) => Err(format!("pattern local {} out of range", slot)),
+        MatchPattern::Literal(c) => constant(*c),
+        MatchPattern::Range { start, end, .. } => start.iter().chain(end).try_for_each(|c| constant(*c)),
+        MatchPattern::Tuple(items) | MatchPattern::TupleCase { items, .. } | MatchPattern::Or(items) =>
+            items.iter().try_for_each(|p| verify_pattern(program, p, locals)),
+        MatchPattern::StructCase { fields, .. } => fields.iter().try_for_each(|(_, p)| verify_pattern(program, p, locals)),
+    }
+}
+
+pub fn crc32(bytes : &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for b in bytes {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+const UNARY_OPS : [UnaryOp; 2] = [UnaryOp::Neg, UnaryOp::Not];
+const BIN_OPS : [BinOp; 13] = [ BinOp::Add, BinOp::Sub, BinOp::Mul, BinOp::Div, BinOp::Rem
+                              , BinOp::Eq, BinOp::NotEq, BinOp::Less, BinOp::LessEq, BinOp::Greater, BinOp::GreaterEq
+                              , BinOp::And, BinOp::Or
+                              ];
+
+struct Writer {
+    bytes : Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, x : u8) {
+        self.bytes.push(x);
+    }
+
+    fn u32(&mut self, x : u32) {
+        self.bytes.extend(x.to_le_bytes());
+    }
+
+    fn len(&mut self, x : usize) {
+        self.u32(x as u32);
+    }
+
+    fn str(&mut self, s : &str) {
+        self.len(s.len());
+        self.bytes.extend(s.as_bytes());
+    }
+
+    fn list<T>(&mut self, items : &[T], mut f : impl FnMut(&mut Writer, &T)) {
+        self.len(items.len());
+        for item in items {
+            f(self, item);
+        }
+    }
+
+    fn option<T>(&mut self, item : &Option<T>, f : impl FnOnce(&mut Writer, &T)) {
+        match item {
+            Some(item) => {
+                self.u8(1);
+                f(self, item);
+            },
+            None => self.u8(0),
+        }
+    }
+
+    fn program(&mut self, p : &Program) {
+        self.list(&p.funs, |w, (name, f)| { w.str(name); w.u32(*f); });
+        self.list(&p.constants, |w, c| w.constant(c));
+        self.list(&p.names, |w, n| w.str(n));
+        self.list(&p.functions, |w, f| w.function(f));
+        self.list(&p.records, |w, r| w.record(r));
+        self.list(&p.patterns, |w, p| w.pattern(p));
+        self.list(&p.switches, |w, s| {
+            w.list(&s.cases, |w, (name, target)| { w.str(name); w.u32(*target); });
+            w.u32(s.default);
+        });
+        self.list(&p.methods, |w, m| {
+            w.option(&m.type_name, |w, t| w.str(t));
+            w.option(&m.trait_name, |w, t| w.str(t));
+            w.str(&m.name);
+            w.u32(m.function);
+        });
+        self.list(&p.traits, |w, t| w.str(t));
+        self.list(&p.types, |w, t| w.type_info(t));
+        self.list(&p.asserts, |w, a| {
+            w.option(&a.expression, |w, e| w.str(e));
+            w.u32(a.operands);
+            w.u8(a.message as u8);
+            w.len(a.at.start);
+            w.len(a.at.end);
+        });
+        self.list(&p.externs, |w, e| {
+            w.str(&e.name);
+            w.str(&e.library);
+            w.list(&e.params, |w, t| w.c_type(*t));
+            w.c_type(e.result);
+        });
+        self.option(&p.source, |w, s| { w.str(&s.name); w.str(&s.text); });
+    }
+
+    fn c_type(&mut self, t : CType) {
+        self.u8(match t {
+            CType::Int => 0,
+            CType::Float => 1,
+            CType::Bool => 2,
+            CType::String => 3,
+            CType::Void => 4,
+        });
+    }
+
+    fn constant(&mut self, c : &Constant) {
+        match c {
+            Constant::Int(i) => {
+                self.u8(0);
+                self.bytes.extend(i.to_le_bytes());
+            },
+            Constant::Float(x) => {
+                self.u8(1);
+                self.bytes.extend(x.to_bits().to_le_bytes());
+            },
+            Constant::String(s) => {
+                self.u8(2);
+                self.str(s);
+            },
+        }
+    }
+
+    fn function(&mut self, f : &Function) {
+        self.str(&f.name);
+        self.u32(f.arity);
+        self.u32(f.locals);
+        self.list(&f.captures, |w, c| { w.u32(c.from); w.u32(c.to); });
+        self.list(&f.code, |w, op| w.op(*op));
+        self.list(&f.param_types, |w, t| w.type_(t));
+        self.type_(&f.return_type);
+        self.u8(f.generator as u8);
+        self.list(&f.spans, |w, (ip, span)| {
+            w.u32(*ip);
+            w.option(span, |w, m| { w.len(m.start); w.len(m.end); });
+        });
+    }
+
+    fn op(&mut self, op : Op) {
+        let (code, operands) : (u8, &[u32]) = match op {
+            Op::Constant(a) => (0, &[a]),
+            Op::Unit => (1, &[]),
+            Op::Bool(b) => (2, &[b as u32]),
+            Op::GetLocal(a) => (3, &[a]),
+            Op::SetLocal(a) => (4, &[a]),
+            Op::Pop => (5, &[]),
+            Op::PopN(a) => (6, &[a]),
+            Op::Fun(a) => (7, &[a]),
+            Op::Closure(a) => (8, &[a]),
+            Op::CaseFun(a, b, c) => (9, &[a, b, c]),
+            Op::MethodFun(a, b) => (10, &[a, b]),
+            Op::EmptyCase(a, b) => (11, &[a, b]),
+            Op::TupleCase(a, b, c) => (12, &[a, b, c]),
+            Op::Record(a) => (13, &[a]),
+            Op::Tuple(a) => (14, &[a]),
+            Op::List(a) => (15, &[a]),
+            Op::Dict(a) => (16, &[a]),
+            Op::GetField(a) => (17, &[a]),
+            Op::SetField(a) => (18, &[a]),
+            Op::Index => (19, &[]),
+            Op::SetIndex => (20, &[]),
+            Op::Unary(op) => (21, &[UNARY_OPS.iter().position(|o| *o == op).unwrap() as u32]),
+            Op::Binary(op) => (22, &[BIN_OPS.iter().position(|o| *o == op).unwrap() as u32]),
+            Op::CheckBool => (23, &[]),
+            Op::Jump(a) => (24, &[a]),
+            Op::JumpIfFalse(a) => (25, &[a]),
+            Op::Call(a) => (26, &[a]),
+            Op::CallFun(a, b) => (27, &[a, b]),
+            Op::CallBuiltin(a, b) => (28, &[a, b]),
+            Op::CallMethod(a, b) => (29, &[a, b]),
+            Op::Return => (30, &[]),
+            Op::Match(a, b) => (31, &[a, b]),
+            Op::Switch(a) => (32, &[a]),
+            Op::NoMatch => (33, &[]),
+            Op::Propagate(a) => (34, &[a]),
+            Op::Assert(a, b) => (35, &[a, b]),
+            Op::Fail(a) => (36, &[a]),
+            Op::Iterate => (37, &[]),
+            Op::Next(a) => (38, &[a]),
+            Op::Yield => (39, &[]),
+            Op::Range(start, end, inclusive) => (40, &[start as u32, end as u32, inclusive as u32]),
+        };
+        self.u8(code);
+        for operand in operands {
+            self.u32(*operand);
+        }
+    }
+
+    fn record(&mut self, r : &Record) {
+        self.str(&r.type_name);
+        self.option(&r.case_name, |w, c| w.str(c));
+        self.list(&r.fields, |w, f| w.str(f));
+        self.list(&r.sources, |w, s| w.u32(*s));
+        self.u32(r.values);
+    }
+
+    fn pattern(&mut self, p : &MatchPattern) {
+        match p {
+            MatchPattern::Wildcard => self.u8(0),
+            MatchPattern::Bind(slot) => {
+                self.u8(1);
+                self.u32(*slot);
+            },
+            MatchPattern::Literal(c) => {
+                self.u8(2);
+                self.u32(*c);
+            },
+            MatchPattern::Bool(b) => {
+                self.u8(3);
+                self.u8(*b as u8);
+            },
+            MatchPattern::Tuple(ps) => {
+                self.u8(4);
+                self.list(ps, |w, p| w.pattern(p));
+            },
+            MatchPattern::EmptyCase { enum_name, case_name } => {
+                self.u8(5);
+                self.option(enum_name, |w, e| w.str(e));
+                self.str(case_name);
+            },
+            MatchPattern::TupleCase { enum_name, case_name, items } => {
+                self.u8(6);
+                self.option(enum_name, |w, e| w.str(e));
+                self.str(case_name);
+                self.list(items, |w, p| w.pattern(p));
+            },
+            MatchPattern::StructCase { enum_name, case_name, fields } => {
+                self.u8(7);
+                self.option(enum_name, |w, e| w.str(e));
+                self.str(case_name);
+                self.list(fields, |w, (n, p)| { w.str(n); w.pattern(p); });
+            },
+            MatchPattern::Or(ps) => {
+                self.u8(8);
+                self.list(ps, |w, p| w.pattern(p));
+            },
+            MatchPattern::Range { start, end, inclusive } => {
+                self.u8(9);
+                self.option(start, |w, c| w.u32(*c));
+                self.option(end, |w, c| w.u32(*c));
+                self.u8(*inclusive as u8);
+            },
+        }
+    }
+
+    fn type_(&mut self, t : &Type) {
+        match t {
+            Type::Unit => self.u8(0),
+            Type::Simple(n) => {
+                self.u8(1);
+                self.str(n);
+            },
+            Type::Indexed(n, ts) => {
+                self.u8(2);
+                self.str(n);
+                self.list(ts, |w, t| w.type_(t));
+            },
+            Type::Arrow { input, output } => {
+                self.u8(3);
+                self.type_(input);
+                self.type_(output);
+            },
+            Type::Tuple(ts) => {
+                self.u8(4);
+                self.list(ts, |w, t| w.type_(t));
+            },
+            Type::Namespace(path, t) => {
+                self.u8(5);
+                self.list(path, |w, n| w.str(n));
+                self.type_(t);
+            },
+            Type::Infer => self.u8(6),
+            Type::Array { element, length } => {
+                self.u8(7);
+                self.type_(element);
+                self.str(length);
+            },
+        }
+    }
+
+    fn type_info(&mut self, t : &TypeInfo) {
+        let fields = |w : &mut Writer, fields : &Vec<(String, Type)>| w.list(fields, |w, (n, t)| { w.str(n); w.type_(t); });
+
+        self.str(&t.name);
+        self.list(&t.type_params, |w, p| w.str(p));
+        match &t.shape {
+            Shape::Struct(fs) => {
+                self.u8(0);
+                fields(self, fs);
+            },
+            Shape::Enum(cases) => {
+                self.u8(1);
+                self.list(cases, |w, (name, case)| {
+                    w.str(name);
+                    match case {
+                        CaseShape::Empty => w.u8(0),
+                        CaseShape::Tuple(ts) => {
+                            w.u8(1);
+                            w.list(ts, |w, t| w.type_(t));
+                        },
+                        CaseShape::Struct(fs) => {
+                            w.u8(2);
+                            fields(w, fs);
+                        },
+                    }
+                });
+            },
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes : &'a [u8],
+    position : usize,
+}
+
+fn invalid<T>(what : &str, tag : u8) -> Result<T, LoadError> {
+    Err(LoadError::Invalid(format!("unknown {} tag {}", what, tag)))
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n : usize) -> Result<&'a [u8], LoadError> {
+        if self.bytes.len() - self.position < n {
+            return Err(LoadError::Truncated);
+        }
+        let bytes = &self.bytes[self.position..self.position + n];
+        self.position += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, LoadError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> Result<usize, LoadError> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn str(&mut self) -> Result<String, LoadError> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| LoadError::Invalid("string is not utf-8".to_string()))
+    }
+
+    fn list<T>(&mut self, mut f : impl FnMut(&mut Reader<'a>) -> Result<T, LoadError>) -> Result<Vec<T>, LoadError> {
+        let len = self.len()?;
+        // every item takes at least one byte, which stops a corrupt length from allocating too much
+        if len > self.bytes.len() - self.position {
+            return Err(LoadError::Truncated);
+        }
+        let mut items = Vec::with_capacity(len);
+        for _ in 0..len {
+            items.push(f(self)?);
+        }
+        Ok(items)
+    }
+
+    fn option<T>(&mut self, f : impl FnOnce(&mut Reader<'a>) -> Result<T, LoadError>) -> Result<Option<T>, LoadError> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(f(self)?)),
+            tag => invalid("option", tag),
+        }
+    }
+
+    fn program(&mut self) -> Result<Program, LoadError> {
+        let funs = self.list(|r| Ok((r.str()?, r.u32()?)))?;
+        let constants = self.list(|r| r.constant())?;
+        let names = self.list(|r| r.str())?;
+        let functions = self.list(|r| r.function())?;
+        let records = self.list(|r| r.record())?;
+        let patterns = self.list(|r| r.pattern())?;
+        let switches = self.list(|r| Ok(SwitchTable { cases: r.list(|r| Ok((r.str()?, r.u32()?)))?, default: r.u32()? }))?;
+        let methods = self.list(|r| Ok(MethodEntry { type_name: r.option(|r| r.str())?
+                                                   , trait_name: r.option(|r| r.str())?
+                                                   , name: r.str()?
+                                                   , function: r.u32()?
+                                                   }))?;
+        let traits = self.list(|r| r.str())?;
+        let types = self.list(|r| r.type_info())?;
+        let asserts = self.list(|r| Ok(AssertSite { expression: r.option(|r| r.str())?
+                                                  , operands: r.u32()?
+                                                  , message: r.u8()? != 0
+                                                  , at: Meta { start: r.len()?, end: r.len()? }
+                                                  }))?;
+        let externs = self.list(|r| Ok(Signature { name: r.str()?
+                                                 , library: r.str()?
+                                                 , params: r.list(|r| r.c_type())?
+                                                 , result: r.c_type()?
+                                                 }))?;
+        let source = self.option(|r| Ok(SourceFile { name: r.str()?, text: r.str()? }))?;
+
+        Ok(Program { funs, constants, names, functions, records, patterns, switches, asserts, methods, traits, types, externs, source })
+    }
+
+    fn c_type(&mut self) -> Result<CType, LoadError> {
+        match self.u8()? {
+            0 => Ok(CType::Int),
+            1 => Ok(CType::Float),
+            2 => Ok(CType::Bool),
+            3 => Ok(CType::String),
+            4 => Ok(CType::Void),
+            tag => invalid("C type", tag),
+        }
+    }
+
+    fn constant(&mut self) -> Result<Constant, LoadError> {
+        match self.u8()? {
+            0 => Ok(Constant::Int(self.u64()? as i64)),
+            1 => Ok(Constant::Float(f64::from_bits(self.u64()?))),
+            2 => Ok(Constant::String(self.str()?)),
+            tag => invalid("constant", tag),
+        }
+    }
+
+    fn function(&mut self) -> Result<Function, LoadError> {
+        Ok(Function { name: self.str()?
+                    , arity: self.u32()?
+                    , locals: self.u32()?
+                    , captures: self.list(|r| Ok(Capture { from: r.u32()?, to: r.u32()? }))?
+                    , code: self.list(|r| r.op())?
+                    , param_types: self.list(|r| r.type_())?
+                    , return_type: self.type_()?
+                    , generator: self.u8()? != 0
+                    , spans: self.list(|r| Ok((r.u32()?, r.option(|r| Ok(Meta { start: r.len()?, end: r.len()? }))?)))?
+                    })
+    }
+
+    fn op(&mut self) -> Result<Op, LoadError> {
+        let op = match self.u8()? {
+            0 => Op::Constant(self.u32()?),
+            1 => Op::Unit,
+            2 => Op::Bool(self.u32()? != 0),
+            3 => Op::GetLocal(self.u32()?),
+            4 => Op::SetLocal(self.u32()?),
+            5 => Op::Pop,
+            6 => Op::PopN(self.u32()?),
+            7 => Op::Fun(self.u32()?),
+            8 => Op::Closure(self.u32()?),
+            9 => Op::CaseFun(self.u32()?, self.u32()?, self.u32()?),
+            10 => Op::MethodFun(self.u32()?, self.u32()?),
+            11 => Op::EmptyCase(self.u32()?, self.u32()?),
+            12 => Op::TupleCase(self.u32()?, self.u32()?, self.u32()?),
+            13 => Op::Record(self.u32()?),
+            14 => Op::Tuple(self.u32()?),
+            15 => Op::List(self.u32()?),
+            16 => Op::Dict(self.u32()?),
+            17 => Op::GetField(self.u32()?),
+            18 => Op::SetField(self.u32()?),
+            19 => Op::Index,
+            20 => Op::SetIndex,
+            21 => match UNARY_OPS.get(self.u32()? as usize) {
+                Some(op) => Op::Unary(*op),
+                None => return Err(LoadError::Invalid("unknown unary operator".to_string())),
+            },
+            22 => match BIN_OPS.get(self.u32()? as usize) {
+                Some(op) => Op::Binary(*op),
+                None => return Err(LoadError::Invalid("unknown binary operator".to_string())),
+            },
+            23 => Op::CheckBool,
+            24 => Op::Jump(self.u32()?),
+            25 => Op::JumpIfFalse(self.u32()?),
+            26 => Op::Call(self.u32()?),
+            27 => Op::CallFun(self.u32()?, self.u32()?),
+            28 => Op::CallBuiltin(self.u32()?, self.u32()?),
+            29 => Op::CallMethod(self.u32()?, self.u32()?),
+            30 => Op::Return,
+            31 => Op::Match(self.u32()?, self.u32()?),
+            32 => Op::Switch(self.u32()?),
+            33 => Op::NoMatch,
+            34 => Op::Propagate(self.u32()?),
+            35 => Op::Assert(self.u32()?, self.u32()?),
+            36 => Op::Fail(self.u32()?),
+            37 => Op::Iterate,
+            38 => Op::Next(self.u32()?),
+            39 => Op::Yield,
+            40 => Op::Range(self.u32()? != 0, self.u32()? != 0, self.u32()? != 0),
+            tag => return invalid("instruction", tag),
+        };
+        Ok(op)
+    }
+
+    fn record(&mut self) -> Result<Record, LoadError> {
+        Ok(Record { type_name: self.str()?
+                  , case_name: self.option(|r| r.str())?
+                  , fields: self.list(|r| r.str())?
+                  , sources: self.list(|r| r.u32())?
+                  , values: self.u32()?
+                  })
+    }
+
+    fn pattern(&mut self) -> Result<MatchPattern, LoadError> {
+        match self.u8()? {
+            0 => Ok(MatchPattern::Wildcard),
+            1 => Ok(MatchPattern::Bind(self.u32()?)),
+            2 => Ok(MatchPattern::Literal(self.u32()?)),
+            3 => Ok(MatchPattern::Bool(self.u8()? != 0)),
+            4 => Ok(MatchPattern::Tuple(self.list(|r| r.pattern())?)),
+            5 => Ok(MatchPattern::EmptyCase { enum_name: self.option(|r| r.str())?, case_name: self.str()? }),
+            6 => Ok(MatchPattern::TupleCase { enum_name: self.option(|r| r.str())?
+                                            , case_name: self.str()?
+                                            , items: self.list(|r| r.pattern())?
+                                            }),
+            7 => Ok(MatchPattern::StructCase { enum_name: self.option(|r| r.str())?
+                                             , case_name: self.str()?
+                                             , fields: self.list(|r| Ok((r.str()?, r.pattern()?)))?
+                                             }),
+            8 => Ok(MatchPattern::Or(self.list(|r| r.pattern())?)),
+            9 => Ok(MatchPattern::Range { start: self.option(|r| r.u32())?, end: self.option(|r| r.u32())?, inclusive: self.u8()? != 0 }),
+            tag => invalid("pattern", tag),
+        }
+    }
+
+    fn type_(&mut self) -> Result<Type, LoadError> {
+        match self.u8()? {
+            0 => Ok(Type::Unit),
+            1 => Ok(Type::Simple(self.str()?)),
+            2 => Ok(Type::Indexed(self.str()?, self.list(|r| r.type_())?)),
+            3 => Ok(Type::Arrow { input: Box::new(self.type_()?), output: Box::new(self.type_()?) }),
+            4 => Ok(Type::Tuple(self.list(|r| r.type_())?)),
+            5 => Ok(Type::Namespace(self.list(|r| r.str())?, Box::new(self.type_()?))),
+            6 => Ok(Type::Infer),
+            7 => Ok(Type::Array { element: Box::new(self.type_()?), length: self.str()? }),
+            tag => invalid("type", tag),
+        }
+    }
+
+    fn fields(&mut self) -> Result<Vec<(String, Type)>, LoadError> {
+        self.list(|r| Ok((r.str()?, r.type_()?)))
+    }
+
+    fn type_info(&mut self) -> Result<TypeInfo, LoadError> {
+        let name = self.str()?;
+        let type_params = self.list(|r| r.str())?;
+        let shape = match self.u8()? {
+            0 => Shape::Struct(self.fields()?),
+            1 => Shape::Enum(self.list(|r| {
+                let name = r.str()?;
+                let case = match r.u8()? {
+                    0 => CaseShape::Empty,
+                    1 => CaseShape::Tuple(r.list(|r| r.type_())?),
+                    2 => CaseShape::Struct(r.fields()?),
+                    tag => return invalid("enum case", tag),
+                };
+                Ok((name, case))
+            })?),
+            tag => return invalid("type shape", tag),
+        };
+        Ok(TypeInfo { name, type_params, shape })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+    use crate::evaluating::conformance::CASES;
+    use super::super::compiler::compile;
+    use super::super::vm;
+
+    fn compile_src(src : &str) -> Program {
+        compile(&parse(src).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn should_round_trip_programs() {
+        for case in CASES {
+            let program = compile_src(case.source);
+            let loaded = read_program(&write_program(&program)).unwrap();
+            assert_eq!( loaded, program, "{}", case.name );
+
+            let mut out = vec![];
+            vm::run(&loaded, &mut out).unwrap();
+            assert_eq!( String::from_utf8(out).unwrap(), case.output, "{}", case.name );
+        }
+    }
+
+    #[test]
+    fn should_keep_type_metadata_and_spans() {
+        let program = compile_src(r#"
+struct Pair<T> { left : T, right : T }
+enum Shape { Circle(Float), Rect { w : Float, h : Float }, Empty }
+fun area(s : Shape) -> Float { let zero = 0.0; zero }
+"#);
+        let loaded = read_program(&write_program(&program)).unwrap();
+
+        assert_eq!( loaded.types.len(), 2 );
+        match &loaded.types[1].shape {
+            Shape::Enum(cases) => assert!( matches!( &cases[1], (name, CaseShape::Struct(fields)) if name == "Rect" && fields.len() == 2 ) ),
+            x => panic!("Expected Enum but found {:?}", x),
+        }
+        assert_eq!( loaded.functions[0].param_types, vec![Type::Simple("Shape".to_string())] );
+        assert!( loaded.functions[0].span_at(0).is_some() );
+    }
+
+    #[test]
+    fn should_reject_other_format_versions() {
+        let mut bytes = write_program(&compile_src("fun main() { }"));
+        bytes[4] = (FORMAT_VERSION + 1) as u8;
+
+        assert_eq!( read_program(&bytes), Err(LoadError::VersionMismatch { expected: FORMAT_VERSION, found: FORMAT_VERSION + 1 }) );
+    }
+
+    #[test]
+    fn should_detect_corruption() {
+        let bytes = write_program(&compile_src("fun main() { println(1) }"));
+
+        let mut corrupt = bytes.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0xff;
+        assert!( matches!( read_program(&corrupt), Err(LoadError::ChecksumMismatch { .. }) ) );
+
+        assert_eq!( read_program(&bytes[..bytes.len() - 3]), Err(LoadError::Truncated) );
+        assert_eq!( read_program(b"#!/bin/dust"), Err(LoadError::NotAModule) );
+    }
+
+    fn rejects(program : &Program) -> bool {
+        matches!( read_program(&write_program(program)), Err(LoadError::Invalid(_)) )
+    }
+
+    #[test]
+    fn should_reject_operands_out_of_range() {
+        let program = compile_src("fun main() { let x = 1; println(x) }");
+        let main = program.fun("main").unwrap();
+        let at = |op : fn(&Op) -> bool| program.functions[main].code.iter().position(op).unwrap();
+
+        let mut constant = program.clone();
+        let ip = at(|op| matches!( op, Op::Constant(_) ));
+        constant.functions[main].code[ip] = Op::Constant(program.constants.len() as u32);
+        assert!( rejects(&constant) );
+
+        let mut local = program.clone();
+        let ip = at(|op| matches!( op, Op::GetLocal(_) ));
+        local.functions[main].code[ip] = Op::GetLocal(program.functions[main].locals);
+        assert!( rejects(&local) );
+
+        let mut name = program.clone();
+        let ip = at(|op| matches!( op, Op::CallBuiltin(_, _) ));
+        name.functions[main].code[ip] = Op::CallBuiltin(program.names.len() as u32, 1);
+        assert!( rejects(&name) );
+
+        let mut fun = program.clone();
+        fun.funs[0].1 = program.functions.len() as u32;
+        assert!( rejects(&fun) );
+    }
+
+    #[test]
+    fn should_reject_bad_jumps_and_stack_heights() {
+        let program = compile_src("fun main() { if true { println(1) } }");
+        let main = program.fun("main").unwrap();
+        let code = &program.functions[main].code;
+
+        let mut jump = program.clone();
+        let ip = code.iter().position(|op| matches!( op, Op::JumpIfFalse(_) )).unwrap();
+        jump.functions[main].code[ip] = Op::JumpIfFalse(code.len() as u32);
+        assert!( rejects(&jump) );
+
+        let mut underflow = program.clone();
+        underflow.functions[main].code.insert(0, Op::Pop);
+        assert!( rejects(&underflow) );
+
+        let mut off_the_end = program.clone();
+        off_the_end.functions[main].code.pop();
+        assert!( rejects(&off_the_end) );
+    }
+
+    #[test]
+    fn should_reject_jumps_with_nothing_to_pop() {
+        let mut program = compile_src("fun main() { }");
+        let main = program.fun("main").unwrap();
+
+        program.functions[main].code = vec![Op::JumpIfFalse(0)];
+        assert!( rejects(&program) );
+        program.functions[main].code = vec![Op::JumpIfFalse(1), Op::Return];
+        assert!( rejects(&program) );
+    }
+
+    #[test]
+    fn should_compute_standard_crc32() {
+        assert_eq!( crc32(b"123456789"), 0xcbf4_3926 );
+    }
+}
@@ -0,0 +1,706 @@
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use crate::evaluating::value::*;
+use crate::evaluating::ops;
+use crate::evaluating::dispatch::MethodTable;
+use crate::evaluating::runtime_error::RuntimeError;
+use crate::evaluating::sandbox::{Sandbox, Capabilities, Capability, Budget};
+use crate::evaluating::ffi::Libraries;
+use super::bytecode::*;
+
+/* Runs a compiled `Program`.  A call pushes a `Frame` with the function, its
+   position, where its operands start on the shared operand stack and its local
+   slots, and `Return` pops it again, all inside one `execute`, so that how
+   deep a program recurses is not bounded by the stack of the thread running
+   it.  Only what calls back into the program from Rust, like the `next` of an
+   iterator written in the language, starts another `execute`.  Values,
+   operators, builtins and method lookup are shared with the interpreter, so a
+   program behaves the same whichever way it is run.
+*/
+
+/// A function the application running a program provides, which the
+/// program calls by name like a builtin.
+pub type Native = Rc<dyn Fn(&[Value]) -> Result<Value, RuntimeError>>;
+
+/// A function which has been called, waiting for the functions it called to return.
+struct Frame<'a> {
+    function : &'a Function,
+    ip : usize,
+    /// The height of the operand stack when the function was entered.
+    base : usize,
+    locals : Vec<Value>,
+}
+
+/// What calling something comes to:  a function to run or a value right away.
+enum Call {
+    Enter(usize, Vec<Value>),
+    Done(Value),
+}
+
+pub struct Vm<'a> {
+    program : &'a Program,
+    constants : Vec<Value>,
+    methods : MethodTable<usize>,
+    natives : HashMap<String, Native>,
+    capabilities : Capabilities,
+    budget : Budget,
+    libraries : Libraries,
+    out : &'a mut dyn Write,
+}
+
+pub fn run(program : &Program, out : &mut dyn Write) -> Result<Value, RuntimeError> {
+    Vm::new(program, out).run_main()
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(program : &'a Program, out : &'a mut dyn Write) -> Vm<'a> {
+        let mut methods = MethodTable::new();
+        for m in &program.methods {
+            methods.insert(m.type_name.as_deref(), m.trait_name.as_deref(), &m.name, m.function as usize);
+        }
+
+        Vm { program
+           , constants: program.constants.iter().map(constant_value).collect()
+           , methods
+           , natives: HashMap::new()
+           , capabilities: Capabilities::default()
+           , budget: Budget::new(Default::default())
+           , libraries: Libraries::default()
+           , out
+           }
+    }
+
+    pub fn with_natives(mut self, natives : HashMap<String, Native>) -> Vm<'a> {
+        self.natives = natives;
+        self
+    }
+
+    pub fn with_sandbox(mut self, sandbox : &Sandbox) -> Vm<'a> {
+        self.capabilities = sandbox.capabilities.clone();
+        self.budget = Budget::new(sandbox.limits);
+        self
+    }
+
+    pub fn run_main(&mut self) -> Result<Value, RuntimeError> {
+        match self.program.fun("main") {
+            Some(main) if self.program.functions[main].arity == 0 => self.call_function(main, vec![], &[]),
+            _ => Err(RuntimeError::NoMain),
+        }
+    }
+
+    pub fn call(&mut self, name : &str, args : Vec<Value>) -> Result<Value, RuntimeError> {
+        self.call_value(&Value::Fun(Rc::new(Callable::Fun(name.to_string()))), args)
+    }
+
+    fn call_function(&mut self, index : usize, args : Vec<Value>, upvalues : &[Value]) -> Result<Value, RuntimeError> {
+        let call = self.function_call(index, args, upvalues)?;
+        self.finish(call)
+    }
+
+    /// Runs what a call comes to until it returns.
+    fn finish(&mut self, call : Call) -> Result<Value, RuntimeError> {
+        match call {
+            Call::Done(value) => Ok(value),
+            Call::Enter(index, mut locals) => {
+                let function = &self.program.functions[index];
+                self.budget.enter()?;
+                let result = self.execute(function, &mut 0, &mut vec![], &mut locals);
+                self.budget.leave();
+                match result {
+                    Ok(Step::Done(value) | Step::Yielded(value)) => Ok(value),
+                    Err(e) => Err(e.leaving(&function.name)),
+                }
+            },
+        }
+    }
+
+    fn function_call(&mut self, index : usize, args : Vec<Value>, upvalues : &[Value]) -> Result<Call, RuntimeError> {
+        let function = &self.program.functions[index];
+        if function.arity as usize != args.len() {
+            return Err(RuntimeError::ArityMismatch { name: function.name.clone(), expected: function.arity as usize, found: args.len() });
+        }
+
+        let mut locals = args;
+        locals.resize(function.locals as usize, Value::Unit);
+        for (capture, value) in function.captures.iter().zip(upvalues) {
+            locals[capture.to as usize] = value.clone();
+        }
+        if function.generator {
+            let state = Suspended::Frame { function: index, ip: 0, stack: vec![], locals };
+            return Ok(Call::Done(Value::iterator(Iteration::Generator { name: function.name.clone(), state })));
+        }
+        Ok(Call::Enter(index, locals))
+    }
+
+    pub fn call_value(&mut self, fun : &Value, args : Vec<Value>) -> Result<Value, RuntimeError> {
+        let call = self.value_call(fun, args)?;
+        self.finish(call)
+    }
+
+    fn value_call(&mut self, fun : &Value, args : Vec<Value>) -> Result<Call, RuntimeError> {
+        let callable = match fun {
+            Value::Fun(c) => c,
+            v => return Err(RuntimeError::NotCallable { type_name: v.type_name() }),
+        };
+
+        match &**callable {
+            Callable::Fun(name) => {
+                if let Some(index) = self.program.fun(name) {
+                    return self.function_call(index, args, &[]);
+                }
+                self.builtin(name, &args).map(Call::Done)
+            },
+            Callable::Case { enum_name, case_name, arity } => {
+                if args.len() != *arity {
+                    return Err(RuntimeError::ArityMismatch { name: format!("{}::{}", enum_name, case_name), expected: *arity, found: args.len() });
+                }
+                Ok(Call::Done(Value::case(enum_name, case_name, CaseValue::Tuple(args))))
+            },
+            Callable::Method { type_name, name } => {
+                if self.program.traits.contains(type_name) {
+                    let mut args = args;
+                    if args.is_empty() {
+                        return Err(RuntimeError::ArityMismatch { name: format!("{}::{}", type_name, name), expected: 1, found: 0 });
+                    }
+                    let receiver = args.remove(0);
+                    return self.method_call(receiver, name, args);
+                }
+                match self.methods.find(type_name, name)? {
+                    Some(index) => self.function_call(index, args, &[]),
+                    None => Err(RuntimeError::NoMethod { type_name: type_name.clone(), name: name.clone() }),
+                }
+            },
+            Callable::Closure { function, upvalues } => self.function_call(*function, args, upvalues),
+            // lambdas only exist in values the interpreter created
+            Callable::Lambda { .. } => Err(RuntimeError::NotCallable { type_name: fun.type_name() }),
+        }
+    }
+
+    fn builtin(&mut self, name : &str, args : &[Value]) -> Result<Value, RuntimeError> {
+        if let Some(result) = ops::builtin(name, args, self.out, &self.capabilities) {
+            return result;
+        }
+        if let Some(native) = self.natives.get(name) {
+            return native(args);
+        }
+        match self.program.externs.iter().find(|e| e.name == name) {
+            Some(signature) => {
+                self.capabilities.check(Capability::Externs)?;
+                self.libraries.call(signature, args)
+            },
+            None => Err(RuntimeError::UnknownFunction(name.to_string())),
+        }
+    }
+
+    fn call_method(&mut self, receiver : Value, name : &str, args : Vec<Value>) -> Result<Value, RuntimeError> {
+        let call = self.method_call(receiver, name, args)?;
+        self.finish(call)
+    }
+
+    fn method_call(&mut self, receiver : Value, name : &str, args : Vec<Value>) -> Result<Call, RuntimeError> {
+        let type_name = receiver.type_name();
+        match self.methods.find(&type_name, name)? {
+            Some(index) => {
+                let mut all = vec![receiver];
+                all.extend(args);
+                self.function_call(index, all, &[])
+            },
+            None => match (&receiver, name) {
+                (Value::Iterator(iterator), "next") if args.is_empty() => Ok(Call::Done(ops::to_option(self.next(iterator)?))),
+                _ => match ops::native_method(&receiver, name, &args) {
+                    Some(result) => result.map(Call::Done),
+                    None => Err(RuntimeError::NoMethod { type_name, name: name.to_string() }),
+                },
+            },
+        }
+    }
+
+    fn next(&mut self, iterator : &RefCell<Iteration>) -> Result<Option<Value>, RuntimeError> {
+        ops::advance(iterator, |state| match state {
+            Iteration::Next(value) => {
+                let item = ops::option(self.call_method(value.clone(), "next", vec![])?)?;
+                Ok((Iteration::Next(value), item))
+            },
+            Iteration::Generator { name, state: Suspended::Frame { function, mut ip, mut stack, mut locals } } => {
+                let program = self.program;
+                let code = program.functions.get(function).filter(|f| f.name == name).ok_or_else(|| RuntimeError::UnknownFunction(name.clone()))?;
+                match self.execute(code, &mut ip, &mut stack, &mut locals) {
+                    Ok(Step::Yielded(item)) => Ok((Iteration::Generator { name, state: Suspended::Frame { function, ip, stack, locals } }, Some(item))),
+                    Ok(Step::Done(_)) => Ok((Iteration::Finished, None)),
+                    Err(e) => Err(e.leaving(&name)),
+                }
+            },
+            // generators from the interpreter only exist in values the interpreter created
+            _ => Err(RuntimeError::NotIterable { type_name: "Iterator".to_string() }),
+        })
+    }
+
+    fn next_item(&mut self, iterator : &Value) -> Result<Option<Value>, RuntimeError> {
+        match iterator {
+            Value::Iterator(iterator) => self.next(iterator),
+            v => Err(RuntimeError::NotIterable { type_name: v.type_name() }),
+        }
+    }
+
+    /// Runs a function from `start` until it returns or yields, leaving `start`
+    /// after the `Yield` so that a generator can be resumed.  The functions it
+    /// calls run in the same loop on frames of their own.
+    fn execute(&mut self, function : &'a Function, start : &mut usize, stack : &mut Vec<Value>, locals : &mut Vec<Value>) -> Result<Step, RuntimeError> {
+        let mut frames = vec![];
+        let mut current = Frame { function, ip: *start, base: stack.len(), locals: std::mem::take(locals) };
+        match self.run_frames(&mut current, &mut frames, stack) {
+            Ok(Step::Yielded(item)) => {
+                *start = current.ip;
+                *locals = current.locals;
+                Ok(Step::Yielded(item))
+            },
+            Ok(done) => Ok(done),
+            Err(mut e) => {
+                while let Some(caller) = frames.pop() {
+                    e = e.leaving(&current.function.name).called_at(caller.function.span_at(caller.ip - 1));
+                    self.budget.leave();
+                    current = caller;
+                }
+                Err(e)
+            },
+        }
+    }
+
+    /// Enters what a call comes to, or pushes its value when there is nothing to run.
+    fn enter(&mut self, call : Call, current : &mut Frame<'a>, frames : &mut Vec<Frame<'a>>, stack : &mut Vec<Value>) -> Result<(), RuntimeError> {
+        match call {
+            Call::Done(value) => stack.push(value),
+            Call::Enter(index, locals) => {
+                self.budget.enter()?;
+                let callee = Frame { function: &self.program.functions[index], ip: 0, base: stack.len(), locals };
+                frames.push(std::mem::replace(current, callee));
+            },
+        }
+        Ok(())
+    }
+
+    fn run_frames(&mut self, current : &mut Frame<'a>, frames : &mut Vec<Frame<'a>>, stack : &mut Vec<Value>) -> Result<Step, RuntimeError> {
+        let program = self.program;
+        let name = |n : u32| program.names[n as usize].as_str();
+
+        loop {
+            self.budget.step()?;
+            let op = current.function.code[current.ip];
+            current.ip += 1;
+            let (function, ip) = (current.function, current.ip);
+            let site = || function.span_at(ip - 1);
+
+            match op {
+                Op::Constant(c) => stack.push(self.constants[c as usize].clone()),
+                Op::Unit => stack.push(Value::Unit),
+                Op::Bool(b) => stack.push(Value::Bool(b)),
+                Op::GetLocal(slot) => stack.push(current.locals[slot as usize].clone()),
+                Op::SetLocal(slot) => current.locals[slot as usize] = pop(stack),
+                Op::Pop => { pop(stack); },
+                Op::PopN(n) => stack.truncate(stack.len() - n as usize),
+                Op::Fun(n) => stack.push(Value::Fun(Rc::new(Callable::Fun(name(n).to_string())))),
+                Op::Closure(f) => {
+                    let upvalues = program.functions[f as usize].captures.iter().map(|c| current.locals[c.from as usize].clone()).collect();
+                    stack.push(Value::closure(Callable::Closure { function: f as usize, upvalues }));
+                },
+                Op::CaseFun(e, c, arity) =>
+                    stack.push(Value::Fun(Rc::new(Callable::Case { enum_name: name(e).to_string(), case_name: name(c).to_string(), arity: arity as usize }))),
+                Op::MethodFun(t, n) =>
+                    stack.push(Value::Fun(Rc::new(Callable::Method { type_name: name(t).to_string(), name: name(n).to_string() }))),
+                Op::EmptyCase(e, c) => stack.push(Value::case(name(e), name(c), CaseValue::Empty)),
+                Op::TupleCase(e, c, n) => {
+                    let values = pop_n(stack, n);
+                    stack.push(Value::case(name(e), name(c), CaseValue::Tuple(values)));
+                },
+                Op::Record(r) => {
+                    let record = &program.records[r as usize];
+                    let values = pop_n(stack, record.values);
+                    let fields = record.fields.iter()
+                                              .zip(&record.sources)
+                                              .map(|(f, s)| (f.clone(), values[*s as usize].clone()))
+                                              .collect();
+                    stack.push(match &record.case_name {
+                        Some(case_name) => Value::case(&record.type_name, case_name, CaseValue::Struct(fields)),
+                        None => Value::structure(&record.type_name, fields),
+                    });
+                },
+                Op::Tuple(n) => {
+                    let values = pop_n(stack, n);
+                    stack.push(Value::tuple(values));
+                },
+                Op::List(n) => {
+                    let values = pop_n(stack, n);
+                    stack.push(Value::list(values));
+                },
+                Op::Dict(n) => {
+                    let mut pairs = vec![];
+                    let mut values = pop_n(stack, 2 * n).into_iter();
+                    while let (Some(k), Some(v)) = (values.next(), values.next()) {
+                        ops::insert(&mut pairs, k, v);
+                    }
+                    stack.push(Value::dict(pairs));
+                },
+                Op::GetField(n) => {
+                    let value = pop(stack);
+                    stack.push(ops::field(&value, name(n))?);
+                },
+                Op::SetField(n) => {
+                    let target = pop(stack);
+                    let value = pop(stack);
+                    ops::set_field(&target, name(n), value)?;
+                },
+                Op::Index => {
+                    let index = pop(stack);
+                    let value = pop(stack);
+                    stack.push(ops::index(&value, &index)?);
+                },
+                Op::Range(start, end, inclusive) => {
+                    let end = if end { Some(pop(stack)) } else { None };
+                    let start = if start { Some(pop(stack)) } else { None };
+                    stack.push(ops::range(start, end, inclusive)?);
+                },
+                Op::SetIndex => {
+                    let index = pop(stack);
+                    let target = pop(stack);
+                    let value = pop(stack);
+                    ops::set_index(&target, index, value)?;
+                },
+                Op::Unary(op) => {
+                    let value = pop(stack);
+                    stack.push(ops::unary(op, value)?);
+                },
+                Op::Binary(op) => {
+                    let right = pop(stack);
+                    let left = pop(stack);
+                    stack.push(ops::binary(op, left, right)?);
+                },
+                Op::CheckBool => { ops::expect_bool(stack.last().unwrap())?; },
+                Op::Jump(target) => current.ip = target as usize,
+                Op::JumpIfFalse(target) => {
+                    if !ops::expect_bool(&pop(stack))? {
+                        current.ip = target as usize;
+                    }
+                },
+                Op::Call(n) => {
+                    let args = pop_n(stack, n);
+                    let fun = pop(stack);
+                    let call = self.value_call(&fun, args).map_err(|e| e.called_at(site()))?;
+                    self.enter(call, current, frames, stack).map_err(|e| e.called_at(site()))?;
+                },
+                Op::CallFun(f, n) => {
+                    let args = pop_n(stack, n);
+                    let call = self.function_call(f as usize, args, &[]).map_err(|e| e.called_at(site()))?;
+                    self.enter(call, current, frames, stack).map_err(|e| e.called_at(site()))?;
+                },
+                Op::CallBuiltin(b, n) => {
+                    let args = pop_n(stack, n);
+                    stack.push(self.builtin(name(b), &args)?);
+                },
+                Op::CallMethod(m, n) => {
+                    let args = pop_n(stack, n);
+                    let receiver = pop(stack);
+                    let call = self.method_call(receiver, name(m), args).map_err(|e| e.called_at(site()))?;
+                    self.enter(call, current, frames, stack).map_err(|e| e.called_at(site()))?;
+                },
+                Op::Return => {
+                    let value = pop(stack);
+                    match frames.pop() {
+                        Some(caller) => {
+                            stack.truncate(current.base);
+                            *current = caller;
+                            self.budget.leave();
+                            stack.push(value);
+                        },
+                        None => return Ok(Step::Done(value)),
+                    }
+                },
+                Op::Match(p, target) => {
+                    let value = pop(stack);
+                    if !self.match_value(&program.patterns[p as usize], &value, &mut current.locals) {
+                        current.ip = target as usize;
+                    }
+                },
+                Op::Switch(t) => {
+                    let table = &program.switches[t as usize];
+                    current.ip = match &pop(stack) {
+                        Value::Enum(e) => table.cases.iter()
+                                                     .find(|(n, _)| *n == e.case_name)
+                                                     .map_or(table.default, |(_, target)| *target) as usize,
+                        _ => table.default as usize,
+                    };
+                },
+                Op::NoMatch => return Err(RuntimeError::NoMatchingArm { value: show(&pop(stack)) }),
+                Op::Propagate(target) => {
+                    let value = pop(stack);
+                    match ops::propagate(value)? {
+                        Ok(value) => {
+                            stack.push(value);
+                            current.ip = target as usize;
+                        },
+                        Err(value) => stack.push(value),
+                    }
+                },
+                Op::Assert(s, target) => {
+                    let holds = match program.asserts[s as usize].operands {
+                        2 => {
+                            let sides = &stack[stack.len() - 2..];
+                            ops::assert_eq(sides[0].clone(), sides[1].clone())?.is_none()
+                        },
+                        _ => ops::expect_bool(stack.last().unwrap())?,
+                    };
+                    if holds {
+                        stack.truncate(stack.len() - program.asserts[s as usize].operands as usize);
+                        current.ip = target as usize;
+                    }
+                },
+                Op::Fail(s) => {
+                    let site = &program.asserts[s as usize];
+                    let message = if site.message { Some(pop(stack)) } else { None };
+                    let operands = pop_n(stack, site.operands);
+                    let sides = match &operands[..] {
+                        [left, right] => ops::assert_eq(left.clone(), right.clone())?,
+                        _ => None,
+                    };
+                    return Err(ops::failure(site.expression.clone(), message.as_ref(), sides, site.at));
+                },
+                Op::Iterate => {
+                    let value = pop(stack);
+                    stack.push(ops::iterate(value)?);
+                },
+                Op::Next(target) => {
+                    let iterator = pop(stack);
+                    match self.next_item(&iterator)? {
+                        Some(item) => stack.push(item),
+                        None => current.ip = target as usize,
+                    }
+                },
+                Op::Yield => {
+                    let item = pop(stack);
+                    stack.push(Value::Unit);
+                    // generators are never entered as frames, so this is the bottom one
+                    return Ok(Step::Yielded(item));
+                },
+            }
+        }
+    }
+
+    fn match_value(&self, pattern : &MatchPattern, value : &Value, locals : &mut Vec<Value>) -> bool {
+        match (pattern, value) {
+            (MatchPattern::Wildcard, _) => true,
+            (MatchPattern::Bind(slot), v) => {
+                locals[*slot as usize] = v.clone();
+                true
+            },
+            (MatchPattern::Literal(c), v) => match (&self.constants[*c as usize], v) {
+                (Value::String(_), Value::String(_)) | (Value::Int(_) | Value::Float(_), _) => self.constants[*c as usize] == *v,
+                _ => false,
+            },
+            (MatchPattern::Range { start, end, inclusive }, v) => {
+                let bound = |c : &Option<u32>| c.map(|c| &self.constants[c as usize]);
+                ops::in_range(v, bound(start), bound(end), *inclusive)
+            },
+            (MatchPattern::Bool(b), Value::Bool(v)) => b == v,
+            (MatchPattern::Tuple(ps), Value::Tuple(vs)) if ps.len() == vs.len() => self.match_all(ps, vs, locals),
+            (MatchPattern::Or(ps), v) => ps.iter().any(|p| self.match_value(p, v, locals)),
+            (MatchPattern::EmptyCase { enum_name, case_name }, Value::Enum(e)) =>
+                same_case(enum_name, case_name, e) && matches!( *e.contents.borrow(), CaseValue::Empty ),
+            (MatchPattern::TupleCase { enum_name, case_name, items }, Value::Enum(e)) if same_case(enum_name, case_name, e) =>
+                match &*e.contents.borrow() {
+                    CaseValue::Tuple(vs) if vs.len() == items.len() => self.match_all(items, vs, locals),
+                    _ => false,
+                },
+            (MatchPattern::StructCase { enum_name, case_name, fields }, Value::Enum(e)) if same_case(enum_name, case_name, e) =>
+                match &*e.contents.borrow() {
+                    CaseValue::Struct(vs) => self.match_fields(fields, vs, locals),
+                    _ => false,
+                },
+            (MatchPattern::StructCase { enum_name: None, case_name, fields }, Value::Struct(s)) =>
+                *case_name == s.name && self.match_fields(fields, &s.fields.borrow(), locals),
+            _ => false,
+        }
+    }
+
+    fn match_all(&self, patterns : &[MatchPattern], values : &[Value], locals : &mut Vec<Value>) -> bool {
+        patterns.iter().zip(values).all(|(p, v)| self.match_value(p, v, locals))
+    }
+
+    fn match_fields(&self, patterns : &[(String, MatchPattern)], values : &[(String, Value)], locals : &mut Vec<Value>) -> bool {
+        patterns.iter().all(|(field, p)| match values.iter().find(|(n, _)| n == field) {
+            Some((_, v)) => self.match_value(p, v, locals),
+            None => false,
+        })
+    }
+}
+
+fn same_case(enum_name : &Option<String>, case_name : &str, value : &EnumValue) -> bool {
+    value.case_name == case_name && enum_name.as_ref().is_none_or(|n| *n == value.enum_name)
+}
+
+pub fn constant_value(constant : &Constant) -> Value {
+    match constant {
+        Constant::Int(i) => Value::Int(*i),
+        Constant::Float(x) => Value::Float(*x),
+        Constant::String(s) => Value::string(s),
+    }
+}
+
+fn pop(stack : &mut Vec<Value>) -> Value {
+    stack.pop().expect("operand stack underflow")
+}
+
+fn pop_n(stack : &mut Vec<Value>, n : u32) -> Vec<Value> {
+    stack.split_off(stack.len() - n as usize)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+    use crate::evaluating::interpreter;
+    use crate::evaluating::conformance::CASES;
+    use super::super::compiler::compile;
+
+    fn run_src(src : &str) -> (Result<Value, RuntimeError>, String) {
+        let program = compile(&parse(src).unwrap()).unwrap();
+        let mut out = vec![];
+        let result = run(&program, &mut out);
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn should_pass_conformance_cases() {
+        for case in CASES {
+            let (result, output) = run_src(case.source);
+            assert!( result.is_ok(), "{} failed with {:?}", case.name, result );
+            assert_eq!( output, case.output, "{}", case.name );
+        }
+    }
+
+    #[test]
+    fn should_capture_values_when_closures_are_created() {
+        let (result, output) = run_src(r#"
+fun adder(n : Int) -> Fun {
+    |x| |y| x + y + n
+}
+fun main() -> Int {
+    let mut a = 1;
+    let f = || a;
+    a = 2;
+    println(f(), a);
+    let mut total = 0;
+    let mut i = 0;
+    while i < 3 {
+        let add = adder(i);
+        total = total + add(10)(100);
+        i = i + 1;
+    }
+    total
+}
+"#);
+        assert_eq!( output, "1 2\n" );
+        assert_eq!( result, Ok(Value::Int(333)) );
+    }
+
+    #[test]
+    fn should_drop_pending_values_on_break_and_continue() {
+        let (result, _) = run_src(r#"
+fun main() -> Int {
+    let mut i = 0;
+    let mut xs = [];
+    while true {
+        i = i + 1;
+        xs.push(1 + if i % 2 == 0 { continue } else { i });
+        [i, if i > 6 { break } else { 0 }];
+    }
+    xs.len() * 100 + i
+}
+"#);
+        assert_eq!( result, Ok(Value::Int(407)) );
+    }
+
+    #[test]
+    fn should_drop_pending_values_when_propagating_to_a_try() {
+        let (result, _) = run_src(r#"
+enum R { Ok(Int), Err(Int) }
+fun check(x : Int) -> R { if x < 3 { R::Ok(x) } else { R::Err(x * 100) } }
+fun main() -> Int {
+    let mut i = 0;
+    let r = try {
+        while true {
+            [i, match i { n => 1 + n * check(n)? }];
+            i = i + 1;
+        }
+        R::Ok(0)
+    };
+    match r { R::Err(e) => e + i, R::Ok(_) => -1 }
+}
+"#);
+        assert_eq!( result, Ok(Value::Int(303)) );
+    }
+
+    #[test]
+    fn should_dispatch_matches_through_switch_tables() {
+        let (result, _) = run_src(r#"
+enum E { A(Int), B(Int), C }
+fun f(e : E) -> Int {
+    match e {
+        E::A(0) => 100,
+        E::B(x) => x,
+        E::A(x) => x + 1,
+        _ => 7,
+    }
+}
+fun main() -> Int { f(E::A(0)) + f(E::A(1)) + f(E::B(3)) * 10 + f(E::C) * 1000 }
+"#);
+        assert_eq!( result, Ok(Value::Int(7132)) );
+    }
+
+    #[test]
+    fn should_recurse_deeper_than_the_thread_stack_would_allow() {
+        let (result, _) = run_src(r#"
+struct Counter { step : Int }
+impl Counter {
+    fun down(self : Self, n : Int) -> Int { if n == 0 { 0 } else { self.down(n - 1) + self.step } }
+}
+fun sum(n : Int) -> Int { if n == 0 { 0 } else { n + sum(n - 1) } }
+fun main() -> Int {
+    let f = |n| sum(n);
+    let c = Counter { step : 2 };
+    f(100000) + c.down(100000)
+}
+"#);
+        assert_eq!( result, Ok(Value::Int(5_000_050_000 + 200_000)) );
+    }
+
+    #[test]
+    fn should_report_the_same_runtime_errors_as_the_interpreter() {
+        let sources = [ "fun main() { let xs = [1, 2]; xs[5] }"
+                      , "fun main() { 1 / 0 }"
+                      , "fun main() { match 3 { 1 => 1, 2 => 2 } }"
+                      , "fun main() { 5.grow() }"
+                      , "fun main() { if 1 { 2 } }"
+                      , "fun f(x : Int) { x } fun main() { f(1, 2) }"
+                      , "fun start() { }"
+                      , "fun main() { 5? }"
+                      , "fun f(x : Int) { assert(x + 1 == 3, x) } fun main() { [1].len(); f(1) }"
+                      , "fun main() { let f = |s| panic(s); f(\"stop\") }"
+                      , "struct S { a : Int } impl S { fun m(self : Self) { assert(self.a < 0) } } fun main() { let s = S { a : 1 }; s.m() }"
+                      , "fun main() { assert(1) }"
+                      , "fun main() { foreach x in 5 { } }"
+                      , "fun g(x : Int) { yield 1; assert(x == 2); } fun main() { foreach y in g(3) { } }"
+                      , "fun g(xs : List<Iterator>) { yield 1; xs[0].next(); } fun main() { let xs = []; let it = g(xs); xs.push(it); it.next(); it.next() }"
+                      ];
+        for src in sources {
+            let module = parse(src).unwrap();
+            let mut out = vec![];
+            let expected = interpreter::run(&module, &mut out);
+            let (result, _) = run_src(src);
+            assert!( result.is_err(), "{}", src );
+            assert_eq!( result, expected, "{}", src );
+        }
+    }
+}
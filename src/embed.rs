@@ -0,0 +1,657 @@
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::parsing::ast::*;
+use crate::parsing::parser::parse;
+use crate::parsing::parse_error::describe_parse_error;
+use crate::checking::checker::check;
+use crate::checking::check_error::CheckError;
+use crate::compiling::compiler::compile_with_natives;
+use crate::compiling::compile_error::CompileError;
+use crate::compiling::bytecode::Program;
+use crate::compiling::module_file;
+use crate::compiling::vm::{Vm, Native};
+use crate::evaluating::value::*;
+use crate::evaluating::ops;
+use crate::evaluating::runtime_error::RuntimeError;
+use crate::evaluating::sandbox::Sandbox;
+use crate::stdlib;
+
+/* Hosting dust programs inside a Rust application.  An `Engine` holds the
+   program loaded so far, compiled for the virtual machine, along with the
+   Rust functions and types the program can use.
+
+   A Rust function is a native which the program calls by name like a
+   builtin.  A Rust type is a dust struct with the same fields, and its
+   methods are natives which dust methods of the struct forward to, so the
+   checker sees them like any other method.  Natives and types have to be
+   registered before loading the sources which use them.
+
+   Arguments and results cross between Rust and dust through `IntoValue` and
+   `FromValue`, and a dust panic or failed assert comes back as an
+   `EmbedError::Runtime`.  Each call runs in the engine's sandbox, which grants
+   nothing until the application says otherwise but does not limit steps,
+   depth or memory either, so an application running code it does not trust
+   should set limits too.
+*/
+
+#[derive(Debug)]
+pub enum EmbedError {
+    Parse(String),
+    Check(Vec<CheckError>),
+    Compile(CompileError),
+    Load(String),
+    /// Calling a function before anything was loaded.
+    NothingLoaded,
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for EmbedError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmbedError::Parse(message) | EmbedError::Load(message) => write!(f, "{}", message),
+            EmbedError::Check(errors) => {
+                let errors = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                write!(f, "check error: {}", errors.join(", "))
+            },
+            EmbedError::Compile(e) => write!(f, "compile error: {}", e),
+            EmbedError::NothingLoaded => write!(f, "no program is loaded"),
+            EmbedError::Runtime(e) => write!(f, "runtime error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EmbedError { }
+
+impl From<RuntimeError> for EmbedError {
+    fn from(e : RuntimeError) -> EmbedError {
+        EmbedError::Runtime(e)
+    }
+}
+
+/// A Rust value which dust code can be given.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+/// A Rust value which can be made from what dust code gives back.
+pub trait FromValue : Sized {
+    fn from_value(value : &Value) -> Result<Self, RuntimeError>;
+}
+
+/// A Rust type which is a dust struct of the same name.
+pub trait HostType : Sized {
+    const NAME : &'static str;
+    /// The name and dust type of each field, in order, such as `("x", "Float")`.
+    const FIELDS : &'static [(&'static str, &'static str)];
+
+    /// The values of the fields, in the order of `FIELDS`.
+    fn into_fields(self) -> Vec<Value>;
+    fn from_fields(fields : &[Value]) -> Result<Self, RuntimeError>;
+}
+
+fn mismatch<T>(expected : &str, found : &Value) -> Result<T, RuntimeError> {
+    Err(RuntimeError::TypeMismatch { expected: expected.to_string(), found: found.type_name() })
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value { self }
+}
+
+impl FromValue for Value {
+    fn from_value(value : &Value) -> Result<Value, RuntimeError> { Ok(value.clone()) }
+}
+
+macro_rules! scalar {
+    ($t:ty, $variant:ident, $name:expr) => {
+        impl IntoValue for $t {
+            fn into_value(self) -> Value { Value::$variant(self) }
+        }
+
+        impl FromValue for $t {
+            fn from_value(value : &Value) -> Result<$t, RuntimeError> {
+                match value {
+                    Value::$variant(x) => Ok(*x),
+                    v => mismatch($name, v),
+                }
+            }
+        }
+    };
+}
+
+scalar!(bool, Bool, "Bool");
+scalar!(i64, Int, "Int");
+scalar!(f64, Float, "Float");
+
+impl IntoValue for () {
+    fn into_value(self) -> Value { Value::Unit }
+}
+
+impl FromValue for () {
+    fn from_value(value : &Value) -> Result<(), RuntimeError> {
+        match value {
+            Value::Unit => Ok(()),
+            v => mismatch("()", v),
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value { Value::string(&self) }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value { Value::string(self) }
+}
+
+impl FromValue for String {
+    fn from_value(value : &Value) -> Result<String, RuntimeError> {
+        match value {
+            Value::String(s) => Ok(s.to_string()),
+            v => mismatch("String", v),
+        }
+    }
+}
+
+impl<T : IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value { Value::list(self.into_iter().map(IntoValue::into_value).collect()) }
+}
+
+impl<T : FromValue> FromValue for Vec<T> {
+    fn from_value(value : &Value) -> Result<Vec<T>, RuntimeError> {
+        match value {
+            Value::List(values) => values.borrow().iter().map(T::from_value).collect(),
+            v => mismatch("List", v),
+        }
+    }
+}
+
+/// `Option::Some` and `Option::None` in dust.
+impl<T : IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value { ops::to_option(self.map(IntoValue::into_value)) }
+}
+
+impl<T : FromValue> FromValue for Option<T> {
+    fn from_value(value : &Value) -> Result<Option<T>, RuntimeError> {
+        ops::option(value.clone())?.as_ref().map(T::from_value).transpose()
+    }
+}
+
+impl<A : IntoValue, B : IntoValue> IntoValue for (A, B) {
+    fn into_value(self) -> Value { Value::tuple(vec![self.0.into_value(), self.1.into_value()]) }
+}
+
+impl<A : FromValue, B : FromValue> FromValue for (A, B) {
+    fn from_value(value : &Value) -> Result<(A, B), RuntimeError> {
+        match value {
+            Value::Tuple(values) if values.len() == 2 => Ok((A::from_value(&values[0])?, B::from_value(&values[1])?)),
+            v => mismatch("a pair", v),
+        }
+    }
+}
+
+impl<T : HostType> IntoValue for T {
+    fn into_value(self) -> Value {
+        let fields = T::FIELDS.iter().map(|(name, _)| name.to_string()).zip(self.into_fields()).collect();
+        Value::structure(T::NAME, fields)
+    }
+}
+
+impl<T : HostType> FromValue for T {
+    fn from_value(value : &Value) -> Result<T, RuntimeError> {
+        match value {
+            Value::Struct(s) if s.name == T::NAME => {
+                let fields = s.fields.borrow();
+                let values = T::FIELDS.iter()
+                                      .map(|(name, _)| fields.iter()
+                                                             .find(|(n, _)| n == name)
+                                                             .map(|(_, v)| v.clone())
+                                                             .ok_or_else(|| RuntimeError::MissingField { type_name: T::NAME.to_string(), field: name.to_string() }))
+                                      .collect::<Result<Vec<_>, _>>()?;
+                T::from_fields(&values)
+            },
+            v => mismatch(T::NAME, v),
+        }
+    }
+}
+
+/// What a Rust function gives back to dust:  a value, or a `Result` whose
+/// error becomes a `RuntimeError::Native`.
+pub trait IntoResult {
+    fn into_result(self) -> Result<Value, RuntimeError>;
+}
+
+impl<T : IntoValue> IntoResult for T {
+    fn into_result(self) -> Result<Value, RuntimeError> { Ok(self.into_value()) }
+}
+
+impl<T : IntoValue, E : fmt::Display> IntoResult for Result<T, E> {
+    fn into_result(self) -> Result<Value, RuntimeError> {
+        self.map(IntoValue::into_value).map_err(|e| RuntimeError::Native(e.to_string()))
+    }
+}
+
+/// The arguments of a call from Rust into dust.
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<Value>;
+}
+
+impl IntoArgs for Vec<Value> {
+    fn into_args(self) -> Vec<Value> { self }
+}
+
+/// A Rust function dust code can call, taking arguments of the types `Args`.
+pub trait HostFn<Args> {
+    fn arity(&self) -> usize;
+    fn call(&self, args : &[Value]) -> Result<Value, RuntimeError>;
+}
+
+macro_rules! arity {
+    ($($arg:ident)*) => { 0 $(+ { stringify!($arg); 1 })* };
+}
+
+macro_rules! tuples {
+    ($($arg:ident)*) => {
+        impl<$($arg : IntoValue),*> IntoArgs for ($($arg,)*) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Vec<Value> {
+                let ($($arg,)*) = self;
+                vec![$($arg.into_value()),*]
+            }
+        }
+
+        impl<F, R, $($arg),*> HostFn<($($arg,)*)> for F
+            where F : Fn($($arg),*) -> R, R : IntoResult, $($arg : FromValue),*
+        {
+            fn arity(&self) -> usize { arity!($($arg)*) }
+
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            fn call(&self, args : &[Value]) -> Result<Value, RuntimeError> {
+                let mut args = args.iter();
+                $(let $arg = $arg::from_value(args.next().expect("the arity was checked"))?;)*
+                self($($arg),*).into_result()
+            }
+        }
+    };
+}
+
+tuples!();
+tuples!(A);
+tuples!(A B);
+tuples!(A B C);
+tuples!(A B C D);
+
+/// A method of a Rust type, or of a dust trait implemented by one.
+struct HostMethod {
+    type_name : String,
+    trait_name : Option<String>,
+    name : String,
+    arity : usize,
+}
+
+pub struct Engine {
+    sources : Vec<String>,
+    program : Option<Program>,
+    natives : HashMap<String, Native>,
+    /// The struct declarations of the registered Rust types.
+    types : Vec<String>,
+    methods : Vec<HostMethod>,
+    sandbox : Sandbox,
+    out : Box<dyn Write>,
+}
+
+impl Default for Engine {
+    fn default() -> Engine {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine { sources: vec![]
+               , program: None
+               , natives: HashMap::new()
+               , types: vec![]
+               , methods: vec![]
+               , sandbox: Sandbox::default()
+               , out: Box::new(io::stdout())
+               }
+    }
+
+    /// Where `print` and `println` write, which is standard output to start with.
+    pub fn set_output(&mut self, out : Box<dyn Write>) {
+        self.out = out;
+    }
+
+    /// The limits and capabilities of every call from now on.
+    pub fn set_sandbox(&mut self, sandbox : Sandbox) {
+        self.sandbox = sandbox;
+    }
+
+    /// Adds source to the program.  Sources loaded before stay loaded, and
+    /// nothing is added when the program with this source fails to check.
+    pub fn load_source(&mut self, source : &str) -> Result<(), EmbedError> {
+        self.sources.push(source.to_string());
+        match self.build() {
+            Ok(program) => {
+                self.program = Some(program);
+                Ok(())
+            },
+            Err(e) => {
+                self.sources.pop();
+                Err(e)
+            },
+        }
+    }
+
+    /// Replaces the program with one compiled ahead of time by `dust build`
+    /// or `compiled`.  The module is verified before it replaces anything, so
+    /// bytes which do not hold a well formed program are a `Load` error rather
+    /// than a panic when they run.
+    pub fn load_compiled(&mut self, bytes : &[u8]) -> Result<(), EmbedError> {
+        let program = module_file::read_program(bytes).map_err(|e| EmbedError::Load(e.to_string()))?;
+        self.sources.clear();
+        self.program = Some(program);
+        Ok(())
+    }
+
+    /// The program loaded so far as a compiled module.
+    pub fn compiled(&self) -> Option<Vec<u8>> {
+        self.program.as_ref().map(module_file::write_program)
+    }
+
+    pub fn register_fn<Args, F : HostFn<Args> + 'static>(&mut self, name : &str, f : F) {
+        self.native(name.to_string(), f);
+    }
+
+    pub fn register_type<T : HostType>(&mut self) {
+        let fields = T::FIELDS.iter().map(|(name, t)| format!("{} : {}", name, t)).collect::<Vec<_>>();
+        self.types.push(format!("struct {} {{ {} }}", T::NAME, fields.join(", ")));
+    }
+
+    /// Adds a method to a type, where `f` takes the receiver first.
+    pub fn register_method<Args, F : HostFn<Args> + 'static>(&mut self, type_name : &str, name : &str, f : F) {
+        self.method(type_name, None, name, f);
+    }
+
+    /// Implements a method of a dust trait for a type.  A trait's methods are
+    /// implemented together, so every one of them has to be registered.
+    pub fn register_trait_method<Args, F : HostFn<Args> + 'static>(&mut self, trait_name : &str, type_name : &str, name : &str, f : F) {
+        self.method(type_name, Some(trait_name), name, f);
+    }
+
+    fn method<Args, F : HostFn<Args> + 'static>(&mut self, type_name : &str, trait_name : Option<&str>, name : &str, f : F) {
+        let arity = f.arity();
+        self.native(native_name(type_name, trait_name, name), f);
+        self.methods.push(HostMethod { type_name: type_name.to_string()
+                                     , trait_name: trait_name.map(str::to_string)
+                                     , name: name.to_string()
+                                     , arity
+                                     });
+    }
+
+    fn native<Args, F : HostFn<Args> + 'static>(&mut self, name : String, f : F) {
+        let native_name = name.clone();
+        let native : Native = Rc::new(move |args| {
+            if args.len() != f.arity() {
+                return Err(RuntimeError::ArityMismatch { name: native_name.clone(), expected: f.arity(), found: args.len() });
+            }
+            f.call(args)
+        });
+        self.natives.insert(name, native);
+    }
+
+    /// Calls a function of the program, converting its arguments and result.
+    pub fn call<R : FromValue>(&mut self, name : &str, args : impl IntoArgs) -> Result<R, EmbedError> {
+        let program = self.program.as_ref().ok_or(EmbedError::NothingLoaded)?;
+        let mut vm = Vm::new(program, &mut *self.out).with_natives(self.natives.clone()).with_sandbox(&self.sandbox);
+        let result = vm.call(name, args.into_args())?;
+        Ok(R::from_value(&result)?)
+    }
+
+    fn build(&self) -> Result<Program, EmbedError> {
+        let mut module = parse("").expect("nothing parses");
+        // Sources are named by the order they were loaded in, counting from one.
+        let types = self.types.iter().map(|t| ("<host types>".to_string(), t));
+        let sources = self.sources.iter().enumerate().map(|(i, s)| (format!("<source {}>", i + 1), s));
+        for (name, source) in types.chain(sources) {
+            let items = parse(source).map_err(|e| EmbedError::Parse(describe_parse_error(&name, source, &e)))?;
+            module.fun_defs.extend(items.fun_defs);
+            module.uses.extend(items.uses);
+            module.struct_defs.extend(items.struct_defs);
+            module.enum_defs.extend(items.enum_defs);
+            module.trait_defs.extend(items.trait_defs);
+            module.impl_defs.extend(items.impl_defs);
+            module.const_defs.extend(items.const_defs);
+            module.extern_defs.extend(items.extern_defs);
+        }
+        let impls = self.method_impls(&module);
+        module.impl_defs.extend(impls);
+
+        stdlib::link(&mut module).map_err(EmbedError::Check)?;
+        check(&module).map_err(EmbedError::Check)?;
+        let natives = self.natives.keys().cloned().collect::<Vec<_>>();
+        compile_with_natives(&module, &natives).map_err(EmbedError::Compile)
+    }
+
+    /// An impl for each type and trait with methods, whose methods call the
+    /// natives.  A trait method takes the types its trait declares, and an
+    /// inherent one leaves them to be inferred.
+    fn method_impls(&self, module : &Module) -> Vec<ImplDef> {
+        let mut impls : Vec<ImplDef> = vec![];
+        for m in &self.methods {
+            let declared = module.trait_defs.iter()
+                                 .filter(|t| Some(&t.name) == m.trait_name.as_ref())
+                                 .flat_map(|t| &t.items)
+                                 .find_map(|item| match item {
+                                     TraitItem::Fun(sig) if sig.name == m.name => Some(sig),
+                                     _ => None,
+                                 });
+            let param_type = |i : usize| match declared.and_then(|sig| sig.params.get(i)) {
+                Some(p) => p.param_type.clone(),
+                None if i == 0 => Type::Simple("Self".to_string()),
+                None => Type::Infer,
+            };
+            let params = (0..m.arity).map(|i| Param { name: if i == 0 { "self".to_string() } else { format!("arg{}", i) }
+                                                  , param_type: param_type(i)
+                                                  , mutable: false
+                                                  , meta: Meta { start: 0, end: 0 }
+                                                  })
+                                    .collect::<Vec<_>>();
+            let body = Expr::Call { fun: Box::new(Expr::Variable(native_name(&m.type_name, m.trait_name.as_deref(), &m.name)))
+                                  , args: params.iter().map(|p| Expr::Variable(p.name.clone())).collect()
+                                  , meta: Meta { start: 0, end: 0 }
+                                  };
+            let return_type = declared.map(|sig| sig.return_type.clone()).unwrap_or(Type::Infer);
+            let fun_def = FunDef { sig: FunSig { name: m.name.clone(), type_params: vec![], params, return_type }, body, constant: false, meta: Meta { start: 0, end: 0 } };
+
+            let impl_type = Type::Simple(m.type_name.clone());
+            let trait_type = m.trait_name.clone().map(Type::Simple);
+            match impls.iter_mut().find(|i| i.impl_type == impl_type && i.trait_type == trait_type) {
+                Some(i) => i.items.push(ImplItem::Fun(fun_def)),
+                None => impls.push(ImplDef { type_params: vec![], trait_type, impl_type, items: vec![ImplItem::Fun(fun_def)] }),
+            }
+        }
+        impls
+    }
+}
+
+/// The name a method's native goes by, which no dust function can have.
+fn native_name(type_name : &str, trait_name : Option<&str>, name : &str) -> String {
+    match trait_name {
+        Some(t) => format!("{} for {}.{}", t, type_name, name),
+        None => format!("{}.{}", type_name, name),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use crate::evaluating::sandbox::{Limits, Limit, Capability};
+    use crate::compiling::bytecode::Op;
+
+    /// Output which the test can read after handing it to an engine.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes : &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Point { x : f64, y : f64 }
+
+    impl HostType for Point {
+        const NAME : &'static str = "Point";
+        const FIELDS : &'static [(&'static str, &'static str)] = &[("x", "Float"), ("y", "Float")];
+
+        fn into_fields(self) -> Vec<Value> {
+            vec![self.x.into_value(), self.y.into_value()]
+        }
+
+        fn from_fields(fields : &[Value]) -> Result<Point, RuntimeError> {
+            Ok(Point { x: f64::from_value(&fields[0])?, y: f64::from_value(&fields[1])? })
+        }
+    }
+
+    #[test]
+    fn should_call_dust_functions_with_converted_values() {
+        let mut engine = Engine::new();
+        engine.load_source(r#"
+use std::{option};
+fun add(a : Int, b : Int) -> Int { a + b }
+fun shout(words : List<String>) -> List<String> { let mut out = []; foreach w in words { out.push(w + "!"); } out }
+fun half(n : Int) -> Option<Int> { if n % 2 == 0 { Option::Some(n / 2) } else { Option::None } }
+"#).unwrap();
+        engine.load_source("fun pair(a : Int) -> (Int, Bool) { (add(a, 1), a > 0) }").unwrap();
+
+        assert_eq!( engine.call::<i64>("add", (2, 3)).unwrap(), 5 );
+        assert_eq!( engine.call::<Vec<String>>("shout", (vec!["hi", "yo"],)).unwrap(), vec!["hi!", "yo!"] );
+        assert_eq!( engine.call::<Option<i64>>("half", (8,)).unwrap(), Some(4) );
+        assert_eq!( engine.call::<Option<i64>>("half", (7,)).unwrap(), None );
+        assert_eq!( engine.call::<(i64, bool)>("pair", (-4,)).unwrap(), (-3, false) );
+
+        assert!( matches!( engine.call::<String>("add", (1, 2)), Err(EmbedError::Runtime(RuntimeError::TypeMismatch { .. })) ) );
+        assert!( matches!( engine.call::<i64>("missing", ()), Err(EmbedError::Runtime(RuntimeError::UnknownFunction(_))) ) );
+        assert!( matches!( engine.load_source("fun broken( { }"), Err(EmbedError::Parse(_)) ) );
+        assert!( matches!( engine.load_source("fun add() { }\nuse std::{nope};"), Err(EmbedError::Check(_)) ) );
+        assert_eq!( engine.call::<i64>("add", (2, 2)).unwrap(), 4 );
+    }
+
+    #[test]
+    fn should_describe_errors_by_the_source_they_are_in() {
+        let mut engine = Engine::new();
+        engine.register_type::<Point>();
+        engine.load_source("fun one() -> Int { 1 }").unwrap();
+
+        let parse_error = engine.load_source("fun two() -> Int {\n    2 +\n}").unwrap_err();
+        assert!( parse_error.to_string().starts_with("<source 2>:3:1: parse error: "), "{}", parse_error );
+
+        let check_error = engine.load_source("use std::{nope};").unwrap_err();
+        assert!( matches!( check_error, EmbedError::Check(_) ) );
+        assert!( check_error.to_string().starts_with("check error: ") );
+        assert!( !check_error.to_string().contains('{'), "{}", check_error );
+    }
+
+    #[test]
+    fn should_let_dust_call_registered_functions_and_types() {
+        let mut engine = Engine::new();
+        let out = Shared::default();
+        engine.set_output(Box::new(out.clone()));
+        engine.register_fn("scale", |p : Point, k : f64| Point { x: p.x * k, y: p.y * k });
+        engine.register_fn("checked_div", |a : i64, b : i64| if b == 0 { Err("divide by zero") } else { Ok(a / b) });
+        engine.register_type::<Point>();
+        engine.register_method("Point", "norm", |p : Point| (p.x * p.x + p.y * p.y).sqrt());
+        engine.register_trait_method("Describe", "Point", "describe", |p : Point| format!("<{}, {}>", p.x, p.y));
+        engine.load_source(r#"
+trait Describe { fun describe(self : Self) -> String; }
+fun longest(points : List<Point>) -> Float {
+    let mut best = 0.0;
+    foreach p in points { if p.norm() > best { best = p.norm(); } }
+    best
+}
+fun main() {
+    let p = scale(Point { x : 1.5, y : 2.0 }, 2.0);
+    println(p, p.norm(), p.describe(), checked_div(7, 2));
+}
+fun divide(a : Int, b : Int) -> Int { checked_div(a, b) }
+"#).unwrap();
+
+        engine.call::<()>("main", ()).unwrap();
+        assert_eq!( String::from_utf8(out.0.borrow().clone()).unwrap(), "Point { x : 3.0, y : 4.0 } 5.0 <3, 4> 3\n" );
+        assert_eq!( engine.call::<f64>("longest", (vec![Point { x: 0.0, y: 1.0 }, Point { x: 6.0, y: 8.0 }],)).unwrap(), 10.0 );
+        assert_eq!( engine.call::<Point>("scale", (Point { x: 1.0, y: 0.5 }, 4.0)).unwrap(), Point { x: 4.0, y: 2.0 } );
+
+        let error = engine.call::<i64>("divide", (1, 0)).unwrap_err();
+        assert!( matches!( &error, EmbedError::Runtime(RuntimeError::Native(m)) if m == "divide by zero" ), "{}", error );
+        assert!( matches!( engine.call::<i64>("scale", (1,)), Err(EmbedError::Runtime(RuntimeError::ArityMismatch { .. })) ) );
+    }
+
+    #[test]
+    fn should_report_dust_panics_as_errors() {
+        let mut engine = Engine::new();
+        assert!( matches!( engine.call::<()>("main", ()), Err(EmbedError::NothingLoaded) ) );
+
+        engine.load_source("fun check(n : Int) -> Int { assert(n > 0, \"not positive\"); n }").unwrap();
+        match engine.call::<i64>("check", (-1,)) {
+            Err(EmbedError::Runtime(RuntimeError::Failed(failure))) => {
+                assert_eq!( failure.message.as_deref(), Some("not positive") );
+                assert_eq!( failure.trace[0].function, "check" );
+            },
+            other => panic!( "expected a failed assert but found {:?}", other ),
+        }
+        assert_eq!( engine.call::<i64>("check", (3,)).unwrap(), 3 );
+    }
+
+    #[test]
+    fn should_keep_calls_within_the_sandbox() {
+        let mut engine = Engine::new();
+        engine.load_source("fun spin() { while true { } }\nfun stamp() -> Float { now() }\nfun ok() -> Int { 1 }").unwrap();
+        assert!( matches!( engine.call::<f64>("stamp", ()), Err(EmbedError::Runtime(RuntimeError::NotPermitted(Capability::Clock))) ) );
+
+        engine.set_sandbox(Sandbox { limits: Limits { steps: Some(1000), ..Limits::default() }, ..Sandbox::trusted() });
+        let error = engine.call::<()>("spin", ()).unwrap_err();
+        assert_eq!( error.to_string(), "runtime error: limit exceeded: 1000 steps" );
+        assert!( engine.call::<f64>("stamp", ()).unwrap() > 0.0 );
+        assert_eq!( engine.call::<i64>("ok", ()).unwrap(), 1 );
+        assert!( matches!( error, EmbedError::Runtime(RuntimeError::LimitExceeded(Limit::Steps(1000))) ) );
+    }
+
+    #[test]
+    fn should_load_compiled_modules() {
+        let mut engine = Engine::new();
+        engine.register_fn("twice", |n : i64| n * 2);
+        engine.load_source("fun quadruple(n : Int) -> Int { twice(twice(n)) }").unwrap();
+        let bytes = engine.compiled().unwrap();
+
+        let mut other = Engine::new();
+        other.register_fn("twice", |n : i64| n * 2);
+        other.load_compiled(&bytes).unwrap();
+        assert_eq!( other.call::<i64>("quadruple", (5,)).unwrap(), 20 );
+        assert!( matches!( other.load_compiled(b"nope"), Err(EmbedError::Load(_)) ) );
+    }
+
+    #[test]
+    fn should_refuse_modules_which_would_break_the_vm() {
+        let mut engine = Engine::new();
+        engine.load_source("fun answer() -> Int { 42 }").unwrap();
+        let mut program = module_file::read_program(&engine.compiled().unwrap()).unwrap();
+        let answer = program.fun("answer").unwrap();
+        program.functions[answer].code.insert(0, Op::Pop);
+
+        let mut other = Engine::new();
+        let error = other.load_compiled(&module_file::write_program(&program)).unwrap_err();
+        assert_eq!( error.to_string(), "compiled module is invalid: function answer: stack underflow at 0" );
+        assert!( matches!( other.call::<i64>("answer", ()), Err(EmbedError::NothingLoaded) ) );
+    }
+}
@@ -0,0 +1,423 @@
+
+/* Programs with their expected output.  Every way of running dust programs
+   has to produce exactly this output for each of them.
+*/
+
+pub struct Case {
+    pub name : &'static str,
+    pub source : &'static str,
+    pub output : &'static str,
+}
+
+pub const CASES : &[Case] = &[
+    Case { name: "arithmetic"
+         , source: r#"
+fun main() {
+    println(1 + 2 * 3, (1 + 2) * 3, 7 / 2, 7 % 2, -4 + 1);
+    println(1.5 * 2, 7 / 2.0, 1-2);
+    println(1 < 2, 2 <= 1, 3 == 3.0, "a" != "b", !true);
+    println("con" + "cat");
+}
+"#
+         , output: "7 9 3 1 -3\n3.0 3.5 -1\ntrue false true true false\nconcat\n"
+         },
+
+    Case { name: "short circuit"
+         , source: r#"
+fun loud(b : Bool) -> Bool {
+    print("!");
+    b
+}
+
+fun main() {
+    println(false && loud(true), true || loud(false), true && loud(false));
+}
+"#
+         , output: "!false true false\n"
+         },
+
+    Case { name: "recursion"
+         , source: r#"
+fun fib(n : Int) -> Int {
+    if n < 2 { n } else { fib(n - 1) + fib(n - 2) }
+}
+
+fun main() {
+    println(fib(15));
+}
+"#
+         , output: "610\n"
+         },
+
+    Case { name: "loops and assignment"
+         , source: r#"
+fun main() {
+    let mut i = 0;
+    let mut total = 0;
+    while true {
+        i = i + 1;
+        if i > 10 { break; }
+        if i % 2 == 0 { continue; }
+        total = total + i;
+    }
+    println(total);
+}
+"#
+         , output: "25\n"
+         },
+
+    Case { name: "early return"
+         , source: r#"
+fun first_over(xs : List<Int>, limit : Int) -> Int {
+    let mut i = 0;
+    while i < xs.len() {
+        if xs[i] > limit {
+            return xs[i];
+        }
+        i = i + 1;
+    }
+    -1
+}
+
+fun main() {
+    println(first_over([1, 5, 9, 12], 6), first_over([1], 6));
+}
+"#
+         , output: "9 -1\n"
+         },
+
+    Case { name: "collections"
+         , source: r#"
+fun main() {
+    let mut xs = [1, 2];
+    xs.push(3);
+    xs[0] = 10;
+    println(xs, xs.len());
+    println(xs.pop(), xs);
+
+    let mut d = ["a" : 1, "b" : 2];
+    d["c"] = 3;
+    d.insert("a", 4);
+    println(d, d["b"], d.contains("z"), d.len());
+
+    let t = (1, "two", [:]);
+    println(t, t[1]);
+}
+"#
+         , output: "[10, 2, 3] 3\n3 [10, 2]\n[\"a\" : 4, \"b\" : 2, \"c\" : 3] 2 false 3\n(1, \"two\", [:]) two\n"
+         },
+
+    Case { name: "structs"
+         , source: r#"
+struct Point { x : Int, y : Int }
+
+impl Point {
+    fun sum(self : Self) -> Int { self.x + self.y }
+    fun shift(mut self : Self, d : Int) { self.x = self.x + d; }
+    fun origin() -> Point { Point { y : 0, x : 0 } }
+}
+
+fun main() {
+    let mut p = Point { x : 1, y : 2 };
+    p.shift(10);
+    println(p, p.sum(), Point::origin());
+}
+"#
+         , output: "Point { x : 11, y : 2 } 13 Point { x : 0, y : 0 }\n"
+         },
+
+    Case { name: "enums and match"
+         , source: r#"
+enum Shape {
+    Circle(Int),
+    Rect { w : Int, h : Int },
+    Dot,
+}
+
+fun area(s : Shape) -> Int {
+    match s {
+        Shape::Circle(r) => 3 * r * r,
+        Shape::Rect { w, h : 0 } => 0,
+        Shape::Rect { w, h } => w * h,
+        Shape::Dot => 0,
+    }
+}
+
+fun main() {
+    let shapes = [Shape::Circle(2), Shape::Rect { w : 3, h : 4 }, Shape::Rect { w : 3, h : 0 }, Shape::Dot];
+    let mut i = 0;
+    while i < shapes.len() {
+        println(shapes[i], area(shapes[i]));
+        i = i + 1;
+    }
+}
+"#
+         , output: "Shape::Circle(2) 12\nShape::Rect { w : 3, h : 4 } 12\nShape::Rect { w : 3, h : 0 } 0\nShape::Dot 0\n"
+         },
+
+    Case { name: "literal, tuple and or patterns"
+         , source: r#"
+fun describe(x : (Int, String)) -> String {
+    match x {
+        (0, _) => "zero",
+        (1 | 2, "a") => "small a",
+        (n, s) => s,
+    }
+}
+
+fun main() {
+    println(describe((0, "q")), describe((2, "a")), describe((5, "other")));
+    println(match true { true => 1, false => 0 });
+}
+"#
+         , output: "zero small a other\n1\n"
+         },
+
+    Case { name: "closures"
+         , source: r#"
+fun apply(f : Int -> Int, x : Int) -> Int { f(x) }
+
+fun compose(f : Int -> Int, g : Int -> Int) -> Int -> Int {
+    |x| f(g(x))
+}
+
+fun main() {
+    let k = 10;
+    let add_k = |x| x + k;
+    let double = |x : Int| x * 2;
+    println(apply(add_k, 1), compose(add_k, double)(5), apply(|x| x - 1, 0));
+
+    let mut counts = [0];
+    let bump = || { counts[0] = counts[0] + 1; };
+    bump();
+    bump();
+    println(counts[0]);
+}
+"#
+         , output: "11 20 -1\n2\n"
+         },
+
+    Case { name: "trait dispatch"
+         , source: r#"
+trait Speak {
+    fun speak(self : Self) -> String;
+}
+
+struct Dog { name : String }
+struct Cat { lives : Int }
+
+impl Speak for Dog {
+    fun speak(self : Self) -> String { self.name + " says woof" }
+}
+
+impl Speak for Cat {
+    fun speak(self : Self) -> String { "meow" }
+}
+
+impl Speak for Int {
+    fun speak(self : Self) -> String { "number" }
+}
+
+fun loud<T : Speak>(t : T) -> String { t.speak() + "!" }
+
+fun main() {
+    println(loud(Dog { name : "rex" }), loud(Cat { lives : 9 }), 4.speak(), Speak::speak(Cat { lives : 1 }));
+}
+"#
+         , output: "rex says woof! meow! number meow\n"
+         },
+
+    Case { name: "functions as values"
+         , source: r#"
+enum Option<T> {
+    Some(T),
+    None,
+}
+
+fun twice(f : Int -> Int, x : Int) -> Int { f(f(x)) }
+fun inc(x : Int) -> Int { x + 1 }
+
+fun main() {
+    let wrap = Option::Some;
+    println(twice(inc, 1), wrap(5), Option::None);
+}
+"#
+         , output: "3 Option::Some(5) Option::None\n"
+         },
+
+    Case { name: "try and propagation"
+         , source: r#"
+enum Result<T, E> {
+    Ok(T),
+    Err(E),
+}
+
+fun digit(c : String) -> Result<Int, String> {
+    match c {
+        "0" => Result::Ok(0),
+        "1" => Result::Ok(1),
+        "2" => Result::Ok(2),
+        _ => Result::Err("not a digit: " + c),
+    }
+}
+
+fun sum(digits : List<String>) -> Result<Int, String> {
+    let mut total = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        total = total + digit(digits[i])?;
+        i = i + 1;
+    }
+    Result::Ok(total)
+}
+
+fun first_error(items : List<String>) -> String {
+    let result = try {
+        let mut i = 0;
+        while i < items.len() {
+            match items[i] {
+                "skip" => { },
+                item => { digit(item)?; },
+            }
+            i = i + 1;
+        }
+        Result::Ok("none")
+    };
+    match result {
+        Result::Ok(s) => s,
+        Result::Err(e) => e,
+    }
+}
+
+fun main() {
+    println(sum(["1", "2", "2"]), sum(["1", "x", "2"]));
+    println(first_error(["0", "skip", "7", "2"]), first_error(["1"]));
+    println(try { 10 + try { digit("y")? }? }, try { (1, digit("2")?) });
+}
+"#
+         , output: "Result::Ok(5) Result::Err(\"not a digit: x\")\nnot a digit: 7 none\nResult::Err(\"not a digit: y\") (1, 2)\n"
+         },
+    Case { name: "asserts which hold"
+         , source: r#"
+fun halve(n : Int) -> Int {
+    assert(n % 2 == 0, "odd");
+    n / 2
+}
+
+fun main() {
+    let xs = [halve(8), halve(-2)];
+    assert(xs == [4, -1]);
+    assert(xs.len() > 1 && xs[0] != 0, panic("not reached"));
+    println(assert(true), xs);
+}
+"#
+         , output: "() [4, -1]\n"
+         },
+
+    Case { name: "generators and foreach"
+         , source: r#"
+enum Option<T> {
+    Some(T),
+    None,
+}
+
+trait Iterator {
+    fun next(mut self : Self) -> Option<Int>;
+}
+
+struct Countdown { n : Int }
+
+impl Iterator for Countdown {
+    fun next(mut self : Self) -> Option<Int> {
+        if self.n == 0 { return Option::None }
+        self.n = self.n - 1;
+        Option::Some(self.n + 1)
+    }
+}
+
+fun naturals() {
+    let mut n = 0;
+    while true {
+        yield n;
+        n = n + 1;
+    }
+}
+
+fun evens(xs : Iterator) {
+    foreach x in xs {
+        if x % 2 == 0 { yield x } else { }
+    }
+}
+
+fun take(n : Int, xs : Iterator) {
+    let mut left = n;
+    foreach x in xs {
+        if left == 0 { return () }
+        left = left - 1;
+        yield x;
+    }
+}
+
+fun main() {
+    let mut total = 0;
+    foreach x in [1, 2, 3] { total = total + x; }
+    let mut chars = [];
+    foreach c in "héllo" {
+        if c == "l" { continue }
+        if c == "o" { break }
+        chars.push(c);
+    }
+    foreach k in ["a" : 1, "b" : 2] { chars.push(k); }
+    println(total, chars);
+
+    let mut got = [];
+    foreach x in take(4, evens(naturals())) { got.push(x); }
+    foreach x in Countdown { n : 3 } { got.push(x); }
+    println(got);
+
+    let it = naturals();
+    it.next();
+    println(it.next(), it, take(0, it).next());
+}
+"#
+         , output: "6 [\"h\", \"é\", \"a\", \"b\"]\n[0, 2, 4, 6, 3, 2, 1]\nOption::Some(1) <iterator> Option::None\n"
+         },
+
+    Case { name: "ranges and slices"
+         , source: r#"
+fun grade(score : Float) -> String {
+    match score {
+        ..50 => "fail",
+        50..70 => "pass",
+        70..=100 => "merit",
+        _ => "invalid",
+    }
+}
+
+fun main() {
+    let mut squares = [];
+    foreach i in 0..5 { squares.push(i * i); }
+    let mut down = [];
+    foreach i in -2..=0 { down.push(i); }
+    println(squares, down);
+
+    let n = squares.len();
+    println(squares[1..3], squares[..=1], squares[n - 2..], squares[..], squares[2..2]);
+
+    let word = "slicé";
+    println(word[1..], word[..3], word[4..=4]);
+
+    println(grade(49.5), grade(50), grade(70.0), grade(100), grade(101));
+
+    let mut firsts = [];
+    foreach i in 10.. {
+        if i == 13 { break }
+        firsts.push(i);
+    }
+    let r = 1..=3;
+    println(firsts, r, ..2, r == (1..=3));
+}
+"#
+         , output: "[0, 1, 4, 9, 16] [-2, -1, 0]\n[1, 4] [0, 1] [9, 16] [0, 1, 4, 9, 16] []\nlicé sli é\nfail pass merit merit invalid\n[10, 11, 12] 1..=3 ..2 true\n"
+         },
+];
@@ -0,0 +1,847 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::thread;
+
+use crate::parsing::ast::*;
+use crate::parsing::printer::print_expr;
+use super::value::*;
+use super::ops;
+use super::interpreter::{case_name, construct, match_pattern};
+use super::runtime_error::RuntimeError;
+use super::sandbox::{Budget, Limits, Sandbox};
+
+/* Constants are evaluated when a program is checked or compiled, each the
+   first time something needs it, so that they can be declared in any order.
+   The evaluator only knows what cannot depend on the program running:
+   literals, operators, the native methods of builtin values, building
+   structs and enum cases, local variables and control flow, other constants
+   and calls to `const fun`s.  Anything else is an error.
+
+   The value of a constant is turned back into a literal expression, which is
+   what the interpreter and the compiler use wherever the constant is named.
+   Evaluating the literal makes a fresh copy of the value every time, so a
+   list constant cannot be changed through one of its uses.  A constant
+   named in a pattern matches its value, when the value can be written as a
+   pattern at all.
+
+   Evaluating runs on a `Budget` like a sandboxed run, so a constant which
+   runs away stops with an error instead of hanging the checker or
+   overflowing its stack.  Constants are evaluated on a thread of their own,
+   with the stack of a main thread, so calls of `const fun`s may go as deep as
+   those of a program run from the command line whatever thread is checking.
+*/
+
+const MAX_STEPS : u64 = 1_000_000;
+/// The stack of the thread constants are evaluated on, which has room to
+/// spare for the stack a trusted run may take.
+const STACK_SIZE : usize = 8 * 1024 * 1024;
+/// How deeply values may nest to be written as literals.
+const MAX_NESTING : usize = 64;
+
+#[derive(Debug, PartialEq)]
+pub enum ConstError {
+    /// A constant using something which is only known when the program runs.
+    NotConstant { name : String, expr : String },
+    /// Constants whose values depend on each other.
+    Cycle { names : Vec<String> },
+    Failed { name : String, error : RuntimeError },
+}
+
+impl fmt::Display for ConstError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConstError::NotConstant { name, expr } => write!(f, "constant {} uses {}, which is not constant", name, expr),
+            ConstError::Cycle { names } => write!(f, "constants {} depend on each other", names.join(", ")),
+            ConstError::Failed { name, error } => write!(f, "evaluating constant {} failed: {}", name, error),
+        }
+    }
+}
+
+/// The values of the constants of a module.
+#[derive(Default)]
+pub struct Consts {
+    exprs : HashMap<String, Arc<Expr>>,
+    patterns : HashMap<String, Pattern>,
+}
+
+impl Consts {
+    /// The literal giving the value of the constant `name`.
+    pub fn expr(&self, name : &str) -> Option<&Arc<Expr>> {
+        self.exprs.get(name)
+    }
+
+    pub fn contains(&self, name : &str) -> bool {
+        self.exprs.contains_key(name)
+    }
+
+    /// The value of the constant `name` when it is an `Int`.
+    pub fn int(&self, name : &str) -> Option<i64> {
+        match self.expr(name).map(|e| &**e) {
+            Some(Expr::Number(n)) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// The pattern matching the value of the constant `name`, or `None` when
+    /// the value cannot be matched, like a list.
+    pub fn pattern(&self, name : &str) -> Option<&Pattern> {
+        self.patterns.get(name)
+    }
+
+    /// Replaces the constants named in `pattern` with the patterns for their
+    /// values.  A constant which cannot be matched never matches.
+    pub fn substitute<'p>(&self, pattern : &'p Pattern) -> Cow<'p, Pattern> {
+        substitute(pattern, &|name| match self.contains(name) {
+            true => Some(self.pattern(name).cloned().unwrap_or(Pattern::Or(vec![]))),
+            false => None,
+        })
+    }
+
+    fn insert(&mut self, name : &str, value : &Value) {
+        if let Some(expr) = literal(value) {
+            self.exprs.insert(name.to_string(), Arc::new(expr));
+        }
+        if let Some(pattern) = pattern(value) {
+            self.patterns.insert(name.to_string(), pattern);
+        }
+    }
+}
+
+/// Evaluates every constant of `module`, giving the values of those which
+/// could be evaluated and the errors of those which could not.
+pub fn evaluate(module : &Module) -> (Consts, Vec<ConstError>) {
+    thread::scope(|scope| {
+        let evaluating = thread::Builder::new().stack_size(STACK_SIZE).spawn_scoped(scope, || evaluate_here(module));
+        match evaluating.expect("Could not start a thread to evaluate constants").join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    })
+}
+
+fn evaluate_here(module : &Module) -> (Consts, Vec<ConstError>) {
+    let mut evaluator = Evaluator::new(module);
+    for const_def in &module.const_defs {
+        let _ = evaluator.value_of(&const_def.name);
+    }
+
+    let mut consts = Consts::default();
+    for (name, value) in &evaluator.values {
+        consts.insert(name, value);
+    }
+    (consts, evaluator.errors)
+}
+
+/// The first expression of the body of a `const fun` which can never be
+/// evaluated at compile time, whatever the arguments are.
+pub fn non_constant<'e>(module : &Module, fun_def : &'e FunDef) -> Option<&'e Expr> {
+    let mut locals = fun_def.sig.params.iter().map(|p| p.name.clone()).collect();
+    first_non_constant(module, &fun_def.body, &mut locals)
+}
+
+fn first_non_constant<'e>(module : &Module, expr : &'e Expr, locals : &mut Vec<String>) -> Option<&'e Expr> {
+    let all = |exprs : &mut dyn Iterator<Item = &'e Expr>, locals : &mut Vec<String>| {
+        for e in exprs {
+            if let Some(found) = first_non_constant(module, e, locals) {
+                return Some(found);
+            }
+        }
+        None
+    };
+    match expr {
+        Expr::Number(_) | Expr::DString(_) | Expr::Bool(_) | Expr::Unit | Expr::Variable(_) | Expr::Break | Expr::Continue => None,
+        Expr::Namespace(path, name) => {
+            let is_case = path.last()
+                              .and_then(|owner| module.enum_defs.iter().find(|e| &e.name == owner))
+                              .is_some_and(|e| e.cases.iter().any(|c| case_name(c) == name));
+            if is_case { None } else { Some(expr) }
+        },
+        Expr::Call { fun, args, .. } => {
+            let callable = match &**fun {
+                Expr::Variable(name) => locals.contains(name) || module.fun_defs.iter().any(|f| &f.sig.name == name && f.constant),
+                _ => true,
+            };
+            if !callable {
+                return Some(expr);
+            }
+            first_non_constant(module, fun, locals).or_else(|| all(&mut args.iter(), locals))
+        },
+        Expr::Tuple(exprs) | Expr::List(exprs) => all(&mut exprs.iter(), locals),
+        Expr::Block(exprs) => {
+            let depth = locals.len();
+            let found = all(&mut exprs.iter(), locals);
+            locals.truncate(depth);
+            found
+        },
+        Expr::Dict(pairs) => all(&mut pairs.iter().flat_map(|(k, v)| [k, v]), locals),
+        Expr::Struct { fields, .. } => all(&mut fields.iter().map(|(_, e)| e), locals),
+        Expr::MethodCall { receiver, args, .. } => all(&mut std::iter::once(&**receiver).chain(args), locals),
+        Expr::Let { name, value, .. } => {
+            let found = first_non_constant(module, value, locals);
+            locals.push(name.clone());
+            found
+        },
+        Expr::Dot { expr, .. } | Expr::Unary { expr, .. } | Expr::Return(expr) => first_non_constant(module, expr, locals),
+        Expr::Assign { target, value, .. } | Expr::Index { expr: target, index: value } | Expr::Binary { left: target, right: value, .. }
+            | Expr::While { condition: target, body: value } => all(&mut [&**target, &**value].iter().copied(), locals),
+        Expr::Range { start, end, .. } => all(&mut start.iter().chain(end).map(|e| &**e), locals),
+        Expr::If { condition, then, otherwise } => all(&mut [&**condition, &**then, &**otherwise].iter().copied(), locals),
+        Expr::Match { expr, arms } => first_non_constant(module, expr, locals).or_else(|| arms.iter().find_map(|arm| {
+            let depth = locals.len();
+            locals.extend(variables(&arm.pattern).into_iter().map(str::to_string));
+            let found = first_non_constant(module, &arm.body, locals);
+            locals.truncate(depth);
+            found
+        })),
+        Expr::Lambda { .. } | Expr::Foreach { .. } | Expr::Yield(_) | Expr::Try(_) | Expr::Propagate(_) | Expr::Assert { .. } | Expr::Panic { .. } => Some(expr),
+    }
+}
+
+/// The literal expression which evaluates to `value`, if there is one.
+pub fn literal(value : &Value) -> Option<Expr> {
+    literal_within(value, 0)
+}
+
+fn literal_within(value : &Value, depth : usize) -> Option<Expr> {
+    if depth > MAX_NESTING {
+        return None;
+    }
+    let all = |values : &[Value]| values.iter().map(|v| literal_within(v, depth + 1)).collect::<Option<Vec<_>>>();
+    let fields = |fields : &[(String, Value)]| fields.iter()
+                                                     .map(|(n, v)| Some((n.clone(), literal_within(v, depth + 1)?)))
+                                                     .collect::<Option<Vec<_>>>();
+    let number = |n : String| Box::new(Expr::Number(n));
+
+    Some(match value {
+        Value::Unit => Expr::Unit,
+        Value::Bool(b) => Expr::Bool(*b),
+        Value::Int(i) => Expr::Number(i.to_string()),
+        Value::Float(x) if x.is_finite() => Expr::Number(format!("{:?}", x)),
+        // there are no literals for infinities and NaN, but dividing by zero makes them
+        Value::Float(x) => {
+            let dividend = if x.is_nan() { "0.0" } else if *x > 0.0 { "1.0" } else { "-1.0" };
+            Expr::Binary { op: BinOp::Div, left: number(dividend.to_string()), right: number("0.0".to_string()) }
+        },
+        Value::String(s) => Expr::DString(s.to_string()),
+        Value::Tuple(values) => Expr::Tuple(all(values)?),
+        Value::List(values) => Expr::List(all(&values.borrow())?),
+        Value::Dict(pairs) => {
+            let pairs = pairs.borrow();
+            let keys = all(&pairs.iter().map(|(k, _)| k.clone()).collect::<Vec<_>>())?;
+            let values = all(&pairs.iter().map(|(_, v)| v.clone()).collect::<Vec<_>>())?;
+            Expr::Dict(keys.into_iter().zip(values).collect())
+        },
+        Value::Struct(s) => Expr::Struct { namespace: vec![], name: s.name.clone(), fields: fields(&s.fields.borrow())? },
+        Value::Enum(e) => {
+            let namespace = vec![e.enum_name.clone()];
+            match &*e.contents.borrow() {
+                CaseValue::Empty => Expr::Namespace(namespace, e.case_name.clone()),
+                CaseValue::Tuple(values) => Expr::Call { fun: Box::new(Expr::Namespace(namespace, e.case_name.clone()))
+                                                       , args: all(values)?
+                                                       , meta: Meta { start: 0, end: 0 }
+                                                       },
+                CaseValue::Struct(values) => Expr::Struct { namespace, name: e.case_name.clone(), fields: fields(values)? },
+            }
+        },
+        Value::Range(range) => Expr::Range { start: range.start.map(|i| number(i.to_string()))
+                                           , end: range.end.map(|i| number(i.to_string()))
+                                           , inclusive: range.inclusive
+                                           },
+        Value::Fun(_) | Value::Iterator(_) => return None,
+    })
+}
+
+/// The pattern matching exactly `value`, if there is one.
+pub fn pattern(value : &Value) -> Option<Pattern> {
+    pattern_within(value, 0)
+}
+
+fn pattern_within(value : &Value, depth : usize) -> Option<Pattern> {
+    if depth > MAX_NESTING {
+        return None;
+    }
+    let all = |values : &[Value]| values.iter().map(|v| pattern_within(v, depth + 1)).collect::<Option<Vec<_>>>();
+    let fields = |fields : &[(String, Value)]| fields.iter()
+                                                     .map(|(n, v)| Some((n.clone(), pattern_within(v, depth + 1)?)))
+                                                     .collect::<Option<Vec<_>>>();
+
+    Some(match value {
+        Value::Bool(b) => Pattern::Bool(*b),
+        Value::Int(i) => Pattern::Number(i.to_string()),
+        Value::Float(x) if x.is_finite() => Pattern::Number(format!("{:?}", x)),
+        Value::String(s) => Pattern::DString(s.to_string()),
+        Value::Tuple(values) => Pattern::Tuple(all(values)?),
+        Value::Struct(s) => Pattern::Case { namespace: vec![]
+                                          , name: s.name.clone()
+                                          , contents: CasePattern::Struct { fields: fields(&s.fields.borrow())?, rest: false }
+                                          },
+        Value::Enum(e) => Pattern::Case { namespace: vec![e.enum_name.clone()]
+                                        , name: e.case_name.clone()
+                                        , contents: match &*e.contents.borrow() {
+                                            CaseValue::Empty => CasePattern::Empty,
+                                            CaseValue::Tuple(values) => CasePattern::Tuple(all(values)?),
+                                            CaseValue::Struct(values) => CasePattern::Struct { fields: fields(values)?, rest: false },
+                                        }
+                                        },
+        _ => return None,
+    })
+}
+
+/// Replaces the variables of `pattern` which `lookup` gives a pattern for.
+fn substitute<'p>(pattern : &'p Pattern, lookup : &dyn Fn(&str) -> Option<Pattern>) -> Cow<'p, Pattern> {
+    if variables(pattern).into_iter().all(|n| lookup(n).is_none()) {
+        return Cow::Borrowed(pattern);
+    }
+    let all = |ps : &[Pattern]| ps.iter().map(|p| substitute(p, lookup).into_owned()).collect();
+    Cow::Owned(match pattern {
+        Pattern::Variable(name) => lookup(name).expect("the variable is a constant"),
+        Pattern::Tuple(ps) => Pattern::Tuple(all(ps)),
+        Pattern::Or(ps) => Pattern::Or(all(ps)),
+        Pattern::Case { namespace, name, contents } => Pattern::Case {
+            namespace: namespace.clone(),
+            name: name.clone(),
+            contents: match contents {
+                CasePattern::Empty => CasePattern::Empty,
+                CasePattern::Tuple(ps) => CasePattern::Tuple(all(ps)),
+                CasePattern::Struct { fields, rest } => CasePattern::Struct {
+                    fields: fields.iter().map(|(n, p)| (n.clone(), substitute(p, lookup).into_owned())).collect(),
+                    rest: *rest,
+                },
+            },
+        },
+        p => p.clone(),
+    })
+}
+
+/// Every variable named in `pattern`, including those of each alternative.
+fn variables(pattern : &Pattern) -> Vec<&str> {
+    fn collect<'p>(pattern : &'p Pattern, names : &mut Vec<&'p str>) {
+        match pattern {
+            Pattern::Variable(name) => names.push(name),
+            Pattern::Tuple(ps) | Pattern::Or(ps) | Pattern::Case { contents: CasePattern::Tuple(ps), .. } => {
+                for p in ps {
+                    collect(p, names);
+                }
+            },
+            Pattern::Case { contents: CasePattern::Struct { fields, .. }, .. } => {
+                for (_, p) in fields {
+                    collect(p, names);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    let mut names = vec![];
+    collect(pattern, &mut names);
+    names
+}
+
+enum Flow {
+    Return(Value),
+    Break,
+    Continue,
+    /// The source of an expression which can only be evaluated when the program runs.
+    NotConstant(String),
+    /// Boxed to keep `Eval` small, as in the interpreter.
+    Error(Box<RuntimeError>),
+    /// A constant which could not be evaluated was used, and its error reported.
+    Failed,
+}
+
+impl From<RuntimeError> for Flow {
+    fn from(e : RuntimeError) -> Flow {
+        Flow::Error(Box::new(e))
+    }
+}
+
+type Eval = Result<Value, Flow>;
+
+fn not_constant(expr : &Expr) -> Flow {
+    Flow::NotConstant(print_expr(expr, 0))
+}
+
+struct Evaluator<'a> {
+    defs : HashMap<&'a str, &'a ConstDef>,
+    /// The `const fun`s, which are all the functions constants can call.
+    funs : HashMap<&'a str, &'a FunDef>,
+    structs : HashMap<&'a str, &'a StructDef>,
+    enums : HashMap<&'a str, &'a EnumDef>,
+    values : HashMap<&'a str, Value>,
+    failed : HashSet<&'a str>,
+    /// The constants being evaluated, outermost first.
+    evaluating : Vec<&'a str>,
+    errors : Vec<ConstError>,
+    budget : Budget,
+}
+
+impl<'a> Evaluator<'a> {
+    fn new(module : &'a Module) -> Evaluator<'a> {
+        Evaluator { defs: module.const_defs.iter().map(|c| (c.name.as_str(), c)).collect()
+                  , funs: module.fun_defs.iter().filter(|f| f.constant).map(|f| (f.sig.name.as_str(), f)).collect()
+                  , structs: module.struct_defs.iter().map(|s| (s.name.as_str(), s)).collect()
+                  , enums: module.enum_defs.iter().map(|e| (e.name.as_str(), e)).collect()
+                  , values: HashMap::new()
+                  , failed: HashSet::new()
+                  , evaluating: vec![]
+                  , errors: vec![]
+                  , budget: Budget::new(Limits { steps: Some(MAX_STEPS), ..Sandbox::trusted().limits })
+                  }
+    }
+
+    /// A copy of the value of the constant `name`, evaluating it if it has not been yet.
+    fn value_of(&mut self, name : &'a str) -> Eval {
+        if let Some(value) = self.values.get(name) {
+            return Ok(copy(value));
+        }
+        if self.failed.contains(name) {
+            return Err(Flow::Failed);
+        }
+        if let Some(i) = self.evaluating.iter().position(|n| *n == name) {
+            let cycle = self.evaluating[i..].to_vec();
+            self.errors.push(ConstError::Cycle { names: cycle.iter().map(|n| n.to_string()).collect() });
+            self.failed.extend(cycle);
+            return Err(Flow::Failed);
+        }
+
+        let const_def = self.defs[name];
+        self.evaluating.push(name);
+        let result = self.budget.enter().map_err(Flow::from).and_then(|()| {
+            let result = finish(self.eval(&const_def.value, &mut vec![]));
+            self.budget.leave();
+            result
+        });
+        self.evaluating.pop();
+
+        let error = match result {
+            Ok(value) if literal(&value).is_some() => {
+                self.values.insert(name, copy(&value));
+                return Ok(value);
+            },
+            Ok(_) => Some(ConstError::NotConstant { name: name.to_string(), expr: print_expr(&const_def.value, 0) }),
+            Err(Flow::NotConstant(expr)) => Some(ConstError::NotConstant { name: name.to_string(), expr }),
+            Err(Flow::Error(error)) => Some(ConstError::Failed { name: name.to_string(), error: *error }),
+            Err(_) => None,
+        };
+        self.errors.extend(error);
+        self.failed.insert(name);
+        Err(Flow::Failed)
+    }
+
+    fn call_fun(&mut self, fun_def : &'a FunDef, args : Vec<Value>) -> Eval {
+        let params = &fun_def.sig.params;
+        if params.len() != args.len() {
+            return Err(RuntimeError::ArityMismatch { name: fun_def.sig.name.clone(), expected: params.len(), found: args.len() }.into());
+        }
+
+        let mut locals = params.iter().map(|p| p.name.clone()).zip(args).collect::<Vec<_>>();
+        self.budget.enter()?;
+        let result = finish(self.eval(&fun_def.body, &mut locals));
+        self.budget.leave();
+        result
+    }
+
+    fn eval_all(&mut self, exprs : &'a [Expr], locals : &mut Vec<(String, Value)>) -> Result<Vec<Value>, Flow> {
+        let mut values = vec![];
+        for e in exprs {
+            values.push(self.eval(e, locals)?);
+        }
+        Ok(values)
+    }
+
+    /* As in the interpreter, each kind of expression is evaluated by a method
+       of its own to keep the frame of `eval` small.
+    */
+    fn eval(&mut self, expr : &'a Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        if let Err(e) = self.budget.step() {
+            return Err(e.into());
+        }
+        match expr {
+            Expr::Number(n) => ops::number(n).map_err(Flow::from),
+            Expr::DString(s) => Ok(Value::string(s)),
+            Expr::Bool(b) => Ok(Value::Bool(*b)),
+            Expr::Unit => Ok(Value::Unit),
+            Expr::Variable(name) => self.variable(expr, name, locals),
+            Expr::Namespace(path, name) => self.namespace(expr, path, name),
+            Expr::Tuple(exprs) => self.eval_all(exprs, locals).map(Value::tuple),
+            Expr::List(exprs) => self.eval_all(exprs, locals).map(Value::list),
+            Expr::Dict(pairs) => self.dict(pairs, locals),
+            Expr::Struct { namespace, name, fields } => self.structure(namespace, name, fields, locals),
+            Expr::Block(exprs) => self.block(exprs, locals),
+            Expr::Call { fun, args, .. } => self.call(expr, fun, args, locals),
+            Expr::Dot { expr, name } => self.dot(expr, name, locals),
+            Expr::MethodCall { receiver, name, args, .. } => self.method_call(expr, receiver, name, args, locals),
+            Expr::Index { expr, index } => self.index(expr, index, locals),
+            Expr::Range { start, end, inclusive } => self.range(start.as_deref(), end.as_deref(), *inclusive, locals),
+            Expr::Let { name, value, .. } => self.bind(name, value, locals),
+            Expr::Assign { target, value, .. } => self.assign(expr, target, value, locals),
+            Expr::Unary { op, expr } => self.unary(*op, expr, locals),
+            Expr::Binary { op, left, right } => self.binary(*op, left, right, locals),
+            Expr::If { condition, then, otherwise } => self.choose(condition, then, otherwise, locals),
+            Expr::While { condition, body } => self.repeat(condition, body, locals),
+            Expr::Break => Err(Flow::Break),
+            Expr::Continue => Err(Flow::Continue),
+            Expr::Return(e) => self.eval(e, locals).and_then(|value| Err(Flow::Return(value))),
+            Expr::Match { expr, arms } => self.match_arms(expr, arms, locals),
+            Expr::Lambda { .. } | Expr::Foreach { .. } | Expr::Yield(_) | Expr::Try(_) | Expr::Propagate(_)
+                | Expr::Assert { .. } | Expr::Panic { .. } => Err(not_constant(expr)),
+        }
+    }
+
+    fn variable(&mut self, expr : &'a Expr, name : &str, locals : &[(String, Value)]) -> Eval {
+        if let Some((_, v)) = locals.iter().rev().find(|(n, _)| n == name) {
+            return Ok(v.clone());
+        }
+        match self.defs.get_key_value(name) {
+            Some((name, _)) => self.value_of(name),
+            None => Err(not_constant(expr)),
+        }
+    }
+
+    fn namespace(&mut self, expr : &'a Expr, path : &[String], name : &str) -> Eval {
+        let owner = path.last().ok_or_else(|| not_constant(expr))?;
+        match self.enums.get(owner.as_str()).and_then(|e| e.cases.iter().find(|c| case_name(c) == name)) {
+            Some(EnumCase::EmptyCase { .. }) => Ok(Value::case(owner, name, CaseValue::Empty)),
+            Some(EnumCase::TypeCase { types, .. }) =>
+                Ok(Value::Fun(Rc::new(Callable::Case { enum_name: owner.clone(), case_name: name.to_string(), arity: types.len() }))),
+            _ => Err(not_constant(expr)),
+        }
+    }
+
+    fn dict(&mut self, pairs : &'a [(Expr, Expr)], locals : &mut Vec<(String, Value)>) -> Eval {
+        let mut values = vec![];
+        for (k, v) in pairs {
+            let k = self.eval(k, locals)?;
+            let v = self.eval(v, locals)?;
+            ops::insert(&mut values, k, v);
+        }
+        Ok(Value::dict(values))
+    }
+
+    fn structure(&mut self, namespace : &[String], name : &str, fields : &'a [(String, Expr)], locals : &mut Vec<(String, Value)>) -> Eval {
+        let mut values = vec![];
+        for (n, e) in fields {
+            values.push((n.clone(), self.eval(e, locals)?));
+        }
+        Ok(construct(&self.structs, &self.enums, namespace, name, values)?)
+    }
+
+    fn block(&mut self, exprs : &'a [Expr], locals : &mut Vec<(String, Value)>) -> Eval {
+        let depth = locals.len();
+        let mut result = Ok(Value::Unit);
+        for e in exprs {
+            result = self.eval(e, locals);
+            if result.is_err() {
+                break;
+            }
+        }
+        locals.truncate(depth);
+        result
+    }
+
+    fn dot(&mut self, expr : &'a Expr, name : &str, locals : &mut Vec<(String, Value)>) -> Eval {
+        let value = self.eval(expr, locals)?;
+        Ok(ops::field(&value, name)?)
+    }
+
+    fn method_call(&mut self, expr : &'a Expr, receiver : &'a Expr, name : &str, args : &'a [Expr], locals : &mut Vec<(String, Value)>) -> Eval {
+        let receiver = self.eval(receiver, locals)?;
+        let args = self.eval_all(args, locals)?;
+        match ops::native_method(&receiver, name, &args) {
+            Some(result) => Ok(result?),
+            None => Err(not_constant(expr)),
+        }
+    }
+
+    fn index(&mut self, expr : &'a Expr, index : &'a Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        let value = self.eval(expr, locals)?;
+        let index = self.eval(index, locals)?;
+        Ok(ops::index(&value, &index)?)
+    }
+
+    fn bind(&mut self, name : &str, value : &'a Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        let value = self.eval(value, locals)?;
+        locals.push((name.to_string(), value));
+        Ok(Value::Unit)
+    }
+
+    fn unary(&mut self, op : UnaryOp, expr : &'a Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        let value = self.eval(expr, locals)?;
+        Ok(ops::unary(op, value)?)
+    }
+
+    fn binary(&mut self, op : BinOp, left : &'a Expr, right : &'a Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        if let BinOp::And | BinOp::Or = op {
+            return self.logic(op, left, right, locals);
+        }
+        let left = self.eval(left, locals)?;
+        let right = self.eval(right, locals)?;
+        ops::binary(op, left, right).map_err(Flow::from)
+    }
+
+    fn logic(&mut self, op : BinOp, left : &'a Expr, right : &'a Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        let left = ops::expect_bool(&self.eval(left, locals)?)?;
+        if left == (op == BinOp::Or) {
+            return Ok(Value::Bool(left));
+        }
+        Ok(Value::Bool(ops::expect_bool(&self.eval(right, locals)?)?))
+    }
+
+    fn choose(&mut self, condition : &'a Expr, then : &'a Expr, otherwise : &'a Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        match ops::expect_bool(&self.eval(condition, locals)?)? {
+            true => self.eval(then, locals),
+            false => self.eval(otherwise, locals),
+        }
+    }
+
+    fn repeat(&mut self, condition : &'a Expr, body : &'a Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        while ops::expect_bool(&self.eval(condition, locals)?)? {
+            match self.eval(body, locals) {
+                Ok(_) | Err(Flow::Continue) => (),
+                Err(Flow::Break) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Value::Unit)
+    }
+
+    fn call(&mut self, expr : &'a Expr, fun : &'a Expr, args : &'a [Expr], locals : &mut Vec<(String, Value)>) -> Eval {
+        if let Expr::Variable(name) = fun {
+            if !locals.iter().any(|(n, _)| n == name) {
+                let fun_def = *self.funs.get(name.as_str()).ok_or_else(|| not_constant(expr))?;
+                let args = self.eval_all(args, locals)?;
+                return self.call_fun(fun_def, args);
+            }
+        }
+        let fun = self.eval(fun, locals)?;
+        let args = self.eval_all(args, locals)?;
+        match &fun {
+            Value::Fun(callable) => match &**callable {
+                Callable::Case { enum_name, case_name, arity } if *arity == args.len() =>
+                    Ok(Value::case(enum_name, case_name, CaseValue::Tuple(args))),
+                Callable::Case { enum_name, case_name, arity } =>
+                    Err(RuntimeError::ArityMismatch { name: format!("{}::{}", enum_name, case_name), expected: *arity, found: args.len() }.into()),
+                _ => Err(not_constant(expr)),
+            },
+            v => Err(RuntimeError::NotCallable { type_name: v.type_name() }.into()),
+        }
+    }
+
+    fn assign(&mut self, expr : &'a Expr, target : &'a Expr, value : &'a Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        let value = self.eval(value, locals)?;
+        match target {
+            Expr::Variable(name) => match locals.iter_mut().rev().find(|(n, _)| n == name) {
+                Some((_, slot)) => *slot = value,
+                None => return Err(not_constant(expr)),
+            },
+            Expr::Dot { expr, name } => {
+                let target = self.eval(expr, locals)?;
+                ops::set_field(&target, name, value)?;
+            },
+            Expr::Index { expr, index } => {
+                let target = self.eval(expr, locals)?;
+                let index = self.eval(index, locals)?;
+                ops::set_index(&target, index, value)?;
+            },
+            _ => return Err(RuntimeError::InvalidAssignTarget.into()),
+        }
+        Ok(Value::Unit)
+    }
+
+    fn match_arms(&mut self, expr : &'a Expr, arms : &'a [MatchArm], locals : &mut Vec<(String, Value)>) -> Eval {
+        let value = self.eval(expr, locals)?;
+        for arm in arms {
+            let pattern = self.resolve(&arm.pattern)?;
+            let mut bindings = vec![];
+            if match_pattern(&pattern, &value, &mut bindings)? {
+                let depth = locals.len();
+                locals.extend(bindings);
+                let result = self.eval(&arm.body, locals);
+                locals.truncate(depth);
+                return result;
+            }
+        }
+        Err(RuntimeError::NoMatchingArm { value: show(&value) }.into())
+    }
+
+    fn range(&mut self, start : Option<&'a Expr>, end : Option<&'a Expr>, inclusive : bool, locals : &mut Vec<(String, Value)>) -> Eval {
+        let start = match start {
+            Some(e) => Some(self.eval(e, locals)?),
+            None => None,
+        };
+        let end = match end {
+            Some(e) => Some(self.eval(e, locals)?),
+            None => None,
+        };
+        Ok(ops::range(start, end, inclusive)?)
+    }
+
+    /// `p` with the constants it names replaced by the patterns of their values.
+    fn resolve<'p>(&mut self, p : &'p Pattern) -> Result<Cow<'p, Pattern>, Flow> {
+        for name in variables(p) {
+            if let Some((name, _)) = self.defs.get_key_value(name) {
+                self.value_of(name)?;
+            }
+        }
+        let values = &self.values;
+        Ok(substitute(p, &|name| values.get(name).map(|v| pattern(v).unwrap_or(Pattern::Or(vec![])))))
+    }
+}
+
+fn finish(result : Eval) -> Eval {
+    match result {
+        Ok(v) | Err(Flow::Return(v)) => Ok(v),
+        Err(Flow::Break) | Err(Flow::Continue) => Err(RuntimeError::BreakOutsideLoop.into()),
+        Err(flow) => Err(flow),
+    }
+}
+
+/// A copy of `value` sharing nothing which could be changed with it.
+fn copy(value : &Value) -> Value {
+    let all = |values : &[Value]| values.iter().map(copy).collect();
+    let fields = |fields : &[(String, Value)]| fields.iter().map(|(n, v)| (n.clone(), copy(v))).collect();
+    match value {
+        Value::Tuple(values) => Value::tuple(all(values)),
+        Value::List(values) => Value::list(all(&values.borrow())),
+        Value::Dict(pairs) => Value::dict(pairs.borrow().iter().map(|(k, v)| (copy(k), copy(v))).collect()),
+        Value::Struct(s) => Value::structure(&s.name, fields(&s.fields.borrow())),
+        Value::Enum(e) => Value::case(&e.enum_name, &e.case_name, match &*e.contents.borrow() {
+            CaseValue::Empty => CaseValue::Empty,
+            CaseValue::Tuple(values) => CaseValue::Tuple(all(values)),
+            CaseValue::Struct(values) => CaseValue::Struct(fields(values)),
+        }),
+        v => v.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+    use crate::checking::checker::check;
+    use crate::compiling::{compiler, vm};
+    use crate::evaluating::interpreter;
+    use crate::evaluating::sandbox::Limit;
+
+    fn trusted_stack() -> usize {
+        Sandbox::trusted().limits.stack.unwrap()
+    }
+
+    /// Runs a program with the interpreter and the virtual machine, which must agree.
+    fn run(src : &str) -> String {
+        let module = parse(src).unwrap();
+        check(&module).unwrap();
+
+        let mut out = vec![];
+        interpreter::run(&module, &mut out).unwrap();
+        let mut vm_out = vec![];
+        vm::run(&compiler::compile(&module).unwrap(), &mut vm_out).unwrap();
+        assert_eq!( String::from_utf8_lossy(&out), String::from_utf8_lossy(&vm_out) );
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn should_evaluate_constants_before_the_program_runs() {
+        let output = run(r#"
+struct Point { x : Int, y : Int }
+enum Shape { Dot, Circle(Point, Float), Rect { corner : Point, size : (Int, Int) } }
+
+const fun fact(n : Int) -> Int { if n <= 1 { 1 } else { n * fact(n - 1) } }
+const fun sum_to(n : Int) -> Int {
+    let mut total = 0;
+    let mut i = 0;
+    while i <= n { total = total + i; i = i + 1; }
+    total
+}
+
+const SIZE : Int = fact(5) / WIDTH;
+const WIDTH : Int = 2 * 3;
+const GREETING : String = "hello, " + "world".to_upper();
+const ORIGIN : Point = Point { y : 0, x : 0 };
+const SHAPES : [Shape; 3] = [Shape::Dot, Shape::Circle(ORIGIN, 1.5), Shape::Rect { corner : ORIGIN, size : (WIDTH, SIZE) }];
+const TRIANGLE : Int = sum_to(4);
+const INFINITY : Float = 1.0 / 0.0;
+
+fun main() {
+    println(SIZE, WIDTH, GREETING, TRIANGLE, INFINITY, -INFINITY);
+    println(ORIGIN, SHAPES[2]);
+    let xs : [Int; WIDTH] = [1, 2, 3, 4, 5, 6];
+    let mut shapes = SHAPES;
+    shapes.push(Shape::Dot);
+    println(xs.len(), shapes.len(), SHAPES.len());
+}
+"#);
+        assert_eq!( output, "20 6 hello, WORLD 10 inf -inf\n\
+                             Point { x : 0, y : 0 } Shape::Rect { corner : Point { x : 0, y : 0 }, size : (6, 20) }\n\
+                             6 4 3\n" );
+    }
+
+    #[test]
+    fn should_match_the_values_of_constants() {
+        let output = run(r#"
+enum Color { Red, Rgb(Int, Int, Int) }
+const LIMIT : Int = 10;
+const BLACK : Color = Color::Rgb(0, 0, 0);
+const ANSWER : (Int, String) = (6 * 7, "yes");
+
+fun describe(n : Int) -> String {
+    match n { LIMIT => "the limit", 0 => "zero", _ => "other" }
+}
+
+fun main() {
+    let LIMIT = 3;
+    println(describe(10), describe(LIMIT), describe(0));
+    foreach c in [BLACK, Color::Red, Color::Rgb(0, 1, 0)] {
+        println(match c { BLACK => "black", Color::Red => "red", Color::Rgb(_, g, _) => "green " + g.to_string() });
+    }
+    println(match (42, "yes") { ANSWER => true, _ => false });
+}
+"#);
+        assert_eq!( output, "the limit other zero\nblack\nred\ngreen 1\ntrue\n" );
+    }
+
+    #[test]
+    fn should_turn_values_back_into_literals() {
+        let (consts, errors) = evaluate(&parse(r#"
+const F : Float = 2.0 * 0.25;
+const NAN : Float = 0.0 / 0.0;
+const R : Int = 1..=3;
+const D : Int = ["k" : [1]];
+"#).unwrap());
+        assert!( errors.is_empty(), "{:?}", errors );
+        assert_eq!( print_expr(consts.expr("F").unwrap(), 0), "0.5" );
+        assert_eq!( print_expr(consts.expr("NAN").unwrap(), 0), "0.0 / 0.0" );
+        assert_eq!( print_expr(consts.expr("R").unwrap(), 0), "1..=3" );
+        assert_eq!( print_expr(consts.expr("D").unwrap(), 0), "[\"k\" : [1]]" );
+        assert_eq!( consts.pattern("F"), Some(&Pattern::Number("0.5".to_string())) );
+        assert!( consts.pattern("NAN").is_none() && consts.pattern("D").is_none() );
+    }
+
+    #[test]
+    fn should_stop_runaway_constants() {
+        let (_, errors) = evaluate(&parse(r#"
+const fun forever(n : Int) -> Int { forever(n + 1) }
+const fun spin() -> Int { while true { } 0 }
+const A : Int = forever(0);
+const B : Int = spin();
+"#).unwrap());
+        assert_eq!( errors, vec![ ConstError::Failed { name: "A".to_string(), error: RuntimeError::LimitExceeded(Limit::Stack(trusted_stack())) }
+                                , ConstError::Failed { name: "B".to_string(), error: RuntimeError::LimitExceeded(Limit::Steps(MAX_STEPS)) }
+                                ] );
+    }
+
+    #[test]
+    fn should_recurse_until_the_stack_runs_short() {
+        let (consts, errors) = evaluate(&parse(r#"
+const fun sum(n : Int) -> Int { if n == 0 { 0 } else { sum(n - 1) + n } }
+const Y : Int = sum(100);
+const DEEP : Int = sum(1000000);
+"#).unwrap());
+        assert_eq!( errors, vec![ ConstError::Failed { name: "DEEP".to_string(), error: RuntimeError::LimitExceeded(Limit::Stack(trusted_stack())) } ] );
+        assert_eq!( consts.int("Y"), Some(5050) );
+    }
+}
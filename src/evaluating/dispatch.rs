@@ -0,0 +1,123 @@
+
+use std::collections::HashMap;
+
+use crate::parsing::ast::*;
+use crate::checking::trait_solver::trait_name;
+use super::runtime_error::RuntimeError;
+
+/* Methods are found by the runtime type name of the receiver.  Inherent impls
+   win over trait impls, trait impls for the type win over blanket impls
+   (`impl<T> Trait for T`), and a name provided by more than one trait is
+   ambiguous.  `F` is whatever the backend calls:  a function definition for
+   the interpreter or a function index for the virtual machine.
+*/
+
+pub struct MethodTable<F> {
+    inherent : HashMap<(String, String), F>,
+    trait_methods : HashMap<(String, String), Vec<(String, F)>>,
+    blanket_methods : HashMap<String, Vec<(String, F)>>,
+}
+
+impl<F : Copy> Default for MethodTable<F> {
+    fn default() -> MethodTable<F> {
+        MethodTable::new()
+    }
+}
+
+impl<F : Copy> MethodTable<F> {
+    pub fn new() -> MethodTable<F> {
+        MethodTable { inherent: HashMap::new(), trait_methods: HashMap::new(), blanket_methods: HashMap::new() }
+    }
+
+    /// Adds a method.  `type_name` is `None` for a blanket impl and `trait_name` is `None` for an inherent impl.
+    pub fn insert(&mut self, type_name : Option<&str>, trait_name : Option<&str>, name : &str, f : F) {
+        match (type_name, trait_name) {
+            (Some(t), None) => { self.inherent.insert((t.to_string(), name.to_string()), f); },
+            (Some(t), Some(tr)) => self.trait_methods.entry((t.to_string(), name.to_string())).or_default().push((tr.to_string(), f)),
+            (None, Some(tr)) => self.blanket_methods.entry(name.to_string()).or_default().push((tr.to_string(), f)),
+            (None, None) => (),
+        }
+    }
+
+    pub fn find(&self, type_name : &str, name : &str) -> Result<Option<F>, RuntimeError> {
+        let key = (type_name.to_string(), name.to_string());
+
+        if let Some(f) = self.inherent.get(&key) {
+            return Ok(Some(*f));
+        }
+
+        let candidates = match self.trait_methods.get(&key) {
+            Some(candidates) => candidates,
+            None => match self.blanket_methods.get(name) {
+                Some(candidates) => candidates,
+                None => return Ok(None),
+            },
+        };
+
+        match &candidates[..] {
+            [(_, f)] => Ok(Some(*f)),
+            _ => Err(RuntimeError::AmbiguousMethod { type_name: type_name.to_string()
+                                                   , name: name.to_string()
+                                                   , traits: candidates.iter().map(|(t, _)| t.clone()).collect()
+                                                   }),
+        }
+    }
+}
+
+/// The runtime type name of values of a type, which is what impls are keyed by.
+pub fn type_head(t : &Type) -> Option<String> {
+    match t {
+        Type::Simple(n) | Type::Indexed(n, _) => Some(n.clone()),
+        Type::Namespace(_, t) => type_head(t),
+        Type::Unit => Some("Unit".to_string()),
+        Type::Tuple(_) => Some("Tuple".to_string()),
+        Type::Array { .. } => Some("List".to_string()),
+        Type::Arrow { .. } => Some("Fun".to_string()),
+        Type::Infer => None,
+    }
+}
+
+/// Every function in an impl along with the type it is for (`None` for a
+/// blanket impl) and the trait it implements (`None` for an inherent impl).
+pub fn impl_methods(module : &Module) -> Vec<(Option<String>, Option<String>, &FunDef)> {
+    let mut methods = vec![];
+
+    for impl_def in &module.impl_defs {
+        let head = match type_head(&impl_def.impl_type) {
+            Some(head) => head,
+            None => continue,
+        };
+        let blanket = impl_def.type_params.iter().any(|tp| tp.name == head);
+        let type_name = if blanket { None } else { Some(head) };
+
+        for item in &impl_def.items {
+            if let ImplItem::Fun(fun_def) = item {
+                methods.push((type_name.clone(), trait_name(impl_def), fun_def));
+            }
+        }
+    }
+
+    methods
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_prefer_inherent_then_trait_then_blanket_methods() {
+        let mut table = MethodTable::new();
+        table.insert(None, Some("Show"), "show", 0);
+        table.insert(Some("Point"), Some("Show"), "show", 1);
+        table.insert(Some("Point"), None, "len", 2);
+        table.insert(Some("Point"), Some("Size"), "len", 3);
+
+        assert_eq!( table.find("Point", "show"), Ok(Some(1)) );
+        assert_eq!( table.find("Int", "show"), Ok(Some(0)) );
+        assert_eq!( table.find("Point", "len"), Ok(Some(2)) );
+        assert_eq!( table.find("Point", "area"), Ok(None) );
+
+        table.insert(Some("Point"), Some("Debug"), "show", 4);
+        assert!( matches!( table.find("Point", "show"), Err(RuntimeError::AmbiguousMethod { .. }) ) );
+    }
+}
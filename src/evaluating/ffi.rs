@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+
+use crate::parsing::ast::{ExternDef, Type};
+use super::value::Value;
+use super::runtime_error::RuntimeError;
+
+/* Calling the `extern` functions of shared libraries.  A library is opened
+   with `dlopen` the first time one of its functions is called and stays open
+   as long as the interpreter or virtual machine does.
+
+   Only a few types cross:  `Int` is an `int64_t`, `Float` a `double`, `Bool` a
+   `bool` and `String` a `const char *` which the call borrows.  A returned
+   string is copied and stays owned by the library.  Functions returning `()`
+   return `void`.
+
+   There is no libffi here.  On x86-64 and AArch64 Linux, integer and floating
+   point arguments go in two separate sequences of registers, so any function
+   whose arguments all fit in registers can be called through one pointer type
+   taking every integer register and then every floating point register, with
+   the unused ones ignored by the callee.  That limits an extern to
+   `MAX_INTEGER_PARAMS` integers, booleans and strings and `MAX_FLOAT_PARAMS`
+   floats, which the checker enforces.
+*/
+
+pub const MAX_INTEGER_PARAMS : usize = 6;
+pub const MAX_FLOAT_PARAMS : usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CType {
+    Int,
+    Float,
+    Bool,
+    String,
+    Void,
+}
+
+/// What calling an extern function needs to know about it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub name : String,
+    pub library : String,
+    pub params : Vec<CType>,
+    pub result : CType,
+}
+
+impl CType {
+    /// The C type a parameter of a dust type is passed as, if it can be.
+    pub fn param(t : &Type) -> Option<CType> {
+        match t {
+            Type::Simple(name) => match name.as_str() {
+                "Int" => Some(CType::Int),
+                "Float" => Some(CType::Float),
+                "Bool" => Some(CType::Bool),
+                "String" => Some(CType::String),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn result(t : &Type) -> Option<CType> {
+        match t {
+            Type::Unit => Some(CType::Void),
+            t => CType::param(t),
+        }
+    }
+}
+
+impl Signature {
+    /// The signature of an extern, or the first of its types which cannot cross.
+    pub fn of(extern_def : &ExternDef) -> Result<Signature, Type> {
+        let sig = &extern_def.sig;
+        if let Some(tp) = sig.type_params.first() {
+            return Err(Type::Simple(tp.name.clone()));
+        }
+        let params = sig.params.iter()
+                               .map(|p| CType::param(&p.param_type).ok_or_else(|| p.param_type.clone()))
+                               .collect::<Result<Vec<_>, _>>()?;
+        let result = CType::result(&sig.return_type).ok_or_else(|| sig.return_type.clone())?;
+        Ok(Signature { name: sig.name.clone(), library: extern_def.library.clone(), params, result })
+    }
+
+    /// Whether every argument goes in a register, which is all that can be called.
+    pub fn fits_in_registers(&self) -> bool {
+        let floats = self.params.iter().filter(|p| **p == CType::Float).count();
+        floats <= MAX_FLOAT_PARAMS && self.params.len() - floats <= MAX_INTEGER_PARAMS
+    }
+}
+
+/// The libraries opened so far and the functions found in them.
+#[derive(Default)]
+pub struct Libraries {
+    handles : HashMap<String, *mut c_void>,
+    symbols : HashMap<(String, String), *mut c_void>,
+}
+
+impl Libraries {
+    pub fn call(&mut self, sig : &Signature, args : &[Value]) -> Result<Value, RuntimeError> {
+        if args.len() != sig.params.len() {
+            return Err(RuntimeError::ArityMismatch { name: sig.name.clone(), expected: sig.params.len(), found: args.len() });
+        }
+        if !sig.fits_in_registers() {
+            return Err(RuntimeError::Extern(format!("{} has more arguments than fit in registers", sig.name)));
+        }
+
+        // the strings have to outlive the call which borrows them
+        let mut strings = vec![];
+        let mut ints = [0i64; MAX_INTEGER_PARAMS];
+        let mut floats = [0f64; MAX_FLOAT_PARAMS];
+        let (mut i, mut f) = (0, 0);
+        for (param, arg) in sig.params.iter().zip(args) {
+            match (param, arg) {
+                (CType::Int, Value::Int(n)) => ints[i] = *n,
+                (CType::Bool, Value::Bool(b)) => ints[i] = *b as i64,
+                (CType::String, Value::String(s)) => {
+                    let s = CString::new(s.as_bytes()).map_err(|_| RuntimeError::Extern(format!("a string passed to {} contains a NUL byte", sig.name)))?;
+                    ints[i] = s.as_ptr() as i64;
+                    strings.push(s);
+                },
+                (CType::Float, Value::Float(x)) => {
+                    floats[f] = *x;
+                    f += 1;
+                    continue;
+                },
+                (expected, found) => return Err(RuntimeError::TypeMismatch { expected: format!("{:?}", expected), found: found.type_name() }),
+            }
+            i += 1;
+        }
+
+        let function = self.symbol(&sig.library, &sig.name)?;
+        let (int_result, float_result) = invoke(function, ints, floats, sig.result);
+        drop(strings);
+
+        Ok(match sig.result {
+            CType::Int => Value::Int(int_result),
+            // only the low byte of a returned `bool` is defined
+            CType::Bool => Value::Bool(int_result & 0xff != 0),
+            CType::Float => Value::Float(float_result),
+            CType::Void => Value::Unit,
+            CType::String if int_result == 0 => return Err(RuntimeError::Extern(format!("{} returned a null string", sig.name))),
+            CType::String => {
+                let s = unsafe { CStr::from_ptr(int_result as *const c_char) };
+                Value::string(&s.to_string_lossy())
+            },
+        })
+    }
+
+    fn symbol(&mut self, library : &str, name : &str) -> Result<*mut c_void, RuntimeError> {
+        let key = (library.to_string(), name.to_string());
+        if let Some(symbol) = self.symbols.get(&key) {
+            return Ok(*symbol);
+        }
+        let handle = match self.handles.get(library) {
+            Some(handle) => *handle,
+            None => {
+                let handle = dl::open(library)?;
+                self.handles.insert(library.to_string(), handle);
+                handle
+            },
+        };
+        let symbol = dl::symbol(handle, name)?;
+        self.symbols.insert(key, symbol);
+        Ok(symbol)
+    }
+}
+
+impl Drop for Libraries {
+    fn drop(&mut self) {
+        for handle in self.handles.values() {
+            dl::close(*handle);
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn invoke(function : *mut c_void, ints : [i64; MAX_INTEGER_PARAMS], floats : [f64; MAX_FLOAT_PARAMS], result : CType) -> (i64, f64) {
+    type IntFn = extern "C" fn(i64, i64, i64, i64, i64, i64, f64, f64, f64, f64, f64, f64, f64, f64) -> i64;
+    type FloatFn = extern "C" fn(i64, i64, i64, i64, i64, i64, f64, f64, f64, f64, f64, f64, f64, f64) -> f64;
+    let [a, b, c, d, e, f] = ints;
+    let [p, q, r, s, t, u, v, w] = floats;
+    // SAFETY: the checker only lets through signatures whose arguments all go
+    // in registers, where extra arguments are ignored by the callee
+    unsafe {
+        match result {
+            CType::Float => (0, std::mem::transmute::<*mut c_void, FloatFn>(function)(a, b, c, d, e, f, p, q, r, s, t, u, v, w)),
+            _ => (std::mem::transmute::<*mut c_void, IntFn>(function)(a, b, c, d, e, f, p, q, r, s, t, u, v, w), 0.0),
+        }
+    }
+}
+
+#[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+fn invoke(_ : *mut c_void, _ : [i64; MAX_INTEGER_PARAMS], _ : [f64; MAX_FLOAT_PARAMS], _ : CType) -> (i64, f64) {
+    unreachable!("no library can be opened on this platform")
+}
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod dl {
+    use super::*;
+
+    const RTLD_NOW : c_int = 2;
+
+    #[link(name = "dl")]
+    extern "C" {
+        fn dlopen(filename : *const c_char, flags : c_int) -> *mut c_void;
+        fn dlsym(handle : *mut c_void, symbol : *const c_char) -> *mut c_void;
+        fn dlclose(handle : *mut c_void) -> c_int;
+        fn dlerror() -> *mut c_char;
+    }
+
+    fn error(fallback : String) -> RuntimeError {
+        let message = unsafe { dlerror() };
+        if message.is_null() {
+            RuntimeError::Extern(fallback)
+        } else {
+            RuntimeError::Extern(unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned())
+        }
+    }
+
+    fn c_string(s : &str) -> Result<CString, RuntimeError> {
+        CString::new(s).map_err(|_| RuntimeError::Extern(format!("{:?} contains a NUL byte", s)))
+    }
+
+    pub fn open(library : &str) -> Result<*mut c_void, RuntimeError> {
+        let name = c_string(library)?;
+        let handle = unsafe { dlopen(name.as_ptr(), RTLD_NOW) };
+        if handle.is_null() { Err(error(format!("cannot open {}", library))) } else { Ok(handle) }
+    }
+
+    pub fn symbol(handle : *mut c_void, name : &str) -> Result<*mut c_void, RuntimeError> {
+        let symbol_name = c_string(name)?;
+        let symbol = unsafe { dlsym(handle, symbol_name.as_ptr()) };
+        if symbol.is_null() { Err(error(format!("cannot find {}", name))) } else { Ok(symbol) }
+    }
+
+    pub fn close(handle : *mut c_void) {
+        unsafe { dlclose(handle) };
+    }
+}
+
+#[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+mod dl {
+    use super::*;
+
+    pub fn open(_ : &str) -> Result<*mut c_void, RuntimeError> {
+        Err(RuntimeError::Extern("extern functions need Linux on x86-64 or AArch64".to_string()))
+    }
+
+    pub fn symbol(_ : *mut c_void, _ : &str) -> Result<*mut c_void, RuntimeError> {
+        unreachable!("no library can be opened on this platform")
+    }
+
+    pub fn close(_ : *mut c_void) { }
+}
+
+#[cfg(all(test, target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod test {
+    use super::*;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+    use crate::parsing::parser::parse;
+    use crate::checking::checker::check;
+    use crate::evaluating::interpreter::Interpreter;
+    use crate::evaluating::sandbox::{Sandbox, Capabilities, Capability};
+    use crate::compiling::{compiler, vm::Vm};
+
+    const LIBRARY : &str = r#"
+#include <stdbool.h>
+#include <stdint.h>
+#include <string.h>
+
+int64_t add(int64_t a, int64_t b) { return a + b; }
+double scale(int64_t n, double by, int64_t m, double plus) { return n * by * m + plus; }
+bool longer(const char *a, const char *b) { return strlen(a) > strlen(b); }
+const char *greeting(bool loud) { return loud ? "HELLO" : "hello"; }
+static int64_t total = 0;
+void bump(int64_t by) { total += by; }
+int64_t read_total(void) { return total; }
+"#;
+
+    /// Builds the test library, which every test builds for itself.
+    fn library(name : &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dust_ffi_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (source, library) = (dir.join("lib.c"), dir.join("libtest.so"));
+        std::fs::write(&source, LIBRARY).unwrap();
+        let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+        let status = Command::new(compiler).arg("-shared").arg("-fPIC").arg("-o").arg(&library).arg(&source).status().unwrap();
+        assert!( status.success() );
+        library
+    }
+
+    fn externs(library : &Path) -> String {
+        let library = library.to_str().unwrap();
+        ["fun add(a : Int, b : Int) -> Int", "fun scale(n : Int, by : Float, m : Int, plus : Float) -> Float",
+         "fun longer(a : String, b : String) -> Bool", "fun greeting(loud : Bool) -> String",
+         "fun bump(by : Int)", "fun read_total() -> Int"]
+            .iter()
+            .map(|sig| format!("extern {:?} {};\n", library, sig))
+            .collect()
+    }
+
+    fn sandbox() -> Sandbox {
+        Sandbox { capabilities: Capabilities { externs: true, ..Capabilities::default() }, ..Sandbox::default() }
+    }
+
+    #[test]
+    fn should_call_functions_of_shared_libraries() {
+        let library = library("call");
+        let src = externs(&library) + r#"
+fun main() {
+    let f = add;
+    bump(2);
+    bump(3);
+    println(add(40, 2), f(-1, 1), scale(2, 1.5, 3, 0.25), longer("abc", "de"), longer("", "x"));
+    println(greeting(true), greeting(false), read_total());
+}
+"#;
+        let module = parse(&src).unwrap();
+        check(&module).unwrap();
+
+        let mut out = vec![];
+        Interpreter::new(&module, &mut out).with_sandbox(&sandbox()).run_main().unwrap();
+        let program = compiler::compile(&module).unwrap();
+        Vm::new(&program, &mut out).with_sandbox(&sandbox()).run_main().unwrap();
+        let program = crate::compiling::module_file::read_program(&crate::compiling::module_file::write_program(&program)).unwrap();
+        Vm::new(&program, &mut out).with_sandbox(&sandbox()).run_main().unwrap();
+
+        // every run opens the library afresh and closes it when done, so the total starts over
+        assert_eq!( String::from_utf8(out).unwrap(), "42 0 9.25 true false\nHELLO hello 5\n".repeat(3) );
+        std::fs::remove_dir_all(library.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn should_report_what_cannot_be_called() {
+        let library = library("errors");
+        let module = parse(&(externs(&library) + "fun main() { add(1, 2) }")).unwrap();
+        let mut out = vec![];
+        assert_eq!( Interpreter::new(&module, &mut out).run_main(), Err(RuntimeError::NotPermitted(Capability::Externs)) );
+
+        let module = parse("extern \"libmissing.so\" fun gone() -> Int;\nextern \"libc.so.6\" fun not_in_libc() -> Int;\n\
+                            fun main() { gone() }\nfun other() { not_in_libc() }").unwrap();
+        let program = compiler::compile(&module).unwrap();
+        let mut vm = Vm::new(&program, &mut out).with_sandbox(&sandbox());
+        assert!( matches!( vm.run_main(), Err(RuntimeError::Extern(m)) if m.contains("libmissing.so") ) );
+        assert!( matches!( vm.call("other", vec![]), Err(RuntimeError::Extern(m)) if m.contains("not_in_libc") ) );
+        std::fs::remove_dir_all(library.parent().unwrap()).unwrap();
+    }
+}
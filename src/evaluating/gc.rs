@@ -0,0 +1,387 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+use super::value::*;
+use super::sandbox;
+
+/* A tracing collector for the cycles reference counting cannot free, like a
+   list holding a closure which captured the list, or an enum case pointing
+   back at itself through a struct.
+
+   Every container a program makes is tracked by a weak reference.  A
+   collection counts, for each container, the references the other containers
+   hold to it, and whatever has more references than that is held from outside
+   the heap:  from the operand stack or locals of a running call, the
+   constants, or a value the application holds.  Those are the roots.  The
+   collector marks everything reachable from them and clears the contents of
+   every container it did not reach, which breaks the cycles so that the
+   reference counts free them.
+
+   Only lists, dicts, structs, enums and iterators are cleared.  Tuples and
+   closures are tracked and traced but cannot change after they are made, so
+   every cycle goes through one of the others.
+
+   A container whose contents are borrowed while a collection runs is taken to
+   be a root.  The collector runs when a container is made and the number of
+   tracked containers has doubled since the last collection, or on every
+   container made in stress mode.
+*/
+
+/// How many containers are tracked before the first collection.
+const FIRST_COLLECTION : usize = 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    pub collections : u64,
+    /// The containers made since the thread started.
+    pub tracked : u64,
+    /// The containers the collector freed.
+    pub freed : u64,
+    /// The containers which survived the last collection.
+    pub live : usize,
+}
+
+enum Handle {
+    Tuple(Weak<Vec<Value>>),
+    List(Weak<RefCell<Vec<Value>>>),
+    Dict(Weak<RefCell<Vec<(Value, Value)>>>),
+    Struct(Weak<StructValue>),
+    Enum(Weak<EnumValue>),
+    Fun(Weak<Callable>),
+    Iterator(Weak<RefCell<Iteration>>),
+}
+
+struct Heap {
+    handles : Vec<Handle>,
+    next_collection : usize,
+    stress : bool,
+    stats : Stats,
+}
+
+thread_local! {
+    static HEAP : RefCell<Heap> = RefCell::new(Heap { handles: vec![], next_collection: FIRST_COLLECTION, stress: false, stats: Stats::default() });
+}
+
+/// Collects on every container made, which finds values the collector frees too early.
+pub fn set_stress(stress : bool) {
+    HEAP.with(|heap| heap.borrow_mut().stress = stress);
+}
+
+pub fn stats() -> Stats {
+    HEAP.with(|heap| heap.borrow().stats)
+}
+
+/// Tracks a container which was just made, collecting first when it is time to.
+pub fn track(value : &Value) {
+    let handle = match value {
+        Value::Tuple(t) => Handle::Tuple(Rc::downgrade(t)),
+        Value::List(l) => Handle::List(Rc::downgrade(l)),
+        Value::Dict(d) => Handle::Dict(Rc::downgrade(d)),
+        Value::Struct(s) => Handle::Struct(Rc::downgrade(s)),
+        Value::Enum(e) => Handle::Enum(Rc::downgrade(e)),
+        Value::Fun(f) => Handle::Fun(Rc::downgrade(f)),
+        Value::Iterator(i) => Handle::Iterator(Rc::downgrade(i)),
+        _ => return,
+    };
+    let due = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.stats.tracked += 1;
+        heap.stress || heap.handles.len() >= heap.next_collection
+    });
+    if due {
+        collect();
+    }
+    HEAP.with(|heap| heap.borrow_mut().handles.push(handle));
+}
+
+/// Frees the containers nothing outside of the heap can reach, giving how many there were.
+pub fn collect() -> usize {
+    let handles = HEAP.with(|heap| std::mem::take(&mut heap.borrow_mut().handles));
+    let objects = handles.iter().filter_map(upgrade).collect::<Vec<_>>();
+    drop(handles);
+    let index = objects.iter().enumerate().filter_map(|(i, o)| Some((address(o)?, i))).collect::<HashMap<_, _>>();
+    let find = |value : &Value| address(value).and_then(|a| index.get(&a).copied());
+
+    // the references from outside the heap, leaving out the one `objects` holds
+    let mut outside = objects.iter().map(|o| strong_count(o) - 1).collect::<Vec<_>>();
+    let mut roots = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        if !each_child(object, &mut |child| if let Some(j) = find(child) { outside[j] -= 1 }) {
+            roots.push(i);
+        }
+    }
+    roots.extend((0..objects.len()).filter(|i| outside[*i] > 0));
+
+    let mut live = vec![false; objects.len()];
+    while let Some(i) = roots.pop() {
+        if !live[i] {
+            live[i] = true;
+            each_child(&objects[i], &mut |child| if let Some(j) = find(child) { roots.push(j) });
+        }
+    }
+
+    let freed = live.iter().filter(|l| !**l).count();
+    for (object, _) in objects.iter().zip(&live).filter(|(_, l)| !**l) {
+        clear(object);
+    }
+    let survivors = objects.iter().zip(&live).filter(|(_, l)| **l).map(|(o, _)| downgrade(o)).collect::<Vec<_>>();
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.stats.collections += 1;
+        heap.stats.freed += freed as u64;
+        heap.stats.live = survivors.len();
+        heap.next_collection = FIRST_COLLECTION.max(2 * survivors.len());
+        let made_while_collecting = std::mem::replace(&mut heap.handles, survivors);
+        heap.handles.extend(made_while_collecting);
+    });
+    // the garbage goes when `objects` does, now that its cycles are broken
+    freed
+}
+
+fn upgrade(handle : &Handle) -> Option<Value> {
+    match handle {
+        Handle::Tuple(t) => t.upgrade().map(Value::Tuple),
+        Handle::List(l) => l.upgrade().map(Value::List),
+        Handle::Dict(d) => d.upgrade().map(Value::Dict),
+        Handle::Struct(s) => s.upgrade().map(Value::Struct),
+        Handle::Enum(e) => e.upgrade().map(Value::Enum),
+        Handle::Fun(f) => f.upgrade().map(Value::Fun),
+        Handle::Iterator(i) => i.upgrade().map(Value::Iterator),
+    }
+}
+
+fn downgrade(value : &Value) -> Handle {
+    match value {
+        Value::Tuple(t) => Handle::Tuple(Rc::downgrade(t)),
+        Value::List(l) => Handle::List(Rc::downgrade(l)),
+        Value::Dict(d) => Handle::Dict(Rc::downgrade(d)),
+        Value::Struct(s) => Handle::Struct(Rc::downgrade(s)),
+        Value::Enum(e) => Handle::Enum(Rc::downgrade(e)),
+        Value::Fun(f) => Handle::Fun(Rc::downgrade(f)),
+        Value::Iterator(i) => Handle::Iterator(Rc::downgrade(i)),
+        _ => unreachable!("only containers are tracked"),
+    }
+}
+
+/// Where a container is, which is what tells two containers apart.
+fn address(value : &Value) -> Option<usize> {
+    match value {
+        Value::Tuple(t) => Some(Rc::as_ptr(t) as *const () as usize),
+        Value::List(l) => Some(Rc::as_ptr(l) as *const () as usize),
+        Value::Dict(d) => Some(Rc::as_ptr(d) as *const () as usize),
+        Value::Struct(s) => Some(Rc::as_ptr(s) as *const () as usize),
+        Value::Enum(e) => Some(Rc::as_ptr(e) as *const () as usize),
+        Value::Fun(f) => Some(Rc::as_ptr(f) as *const () as usize),
+        Value::Iterator(i) => Some(Rc::as_ptr(i) as *const () as usize),
+        _ => None,
+    }
+}
+
+fn strong_count(value : &Value) -> isize {
+    let count = match value {
+        Value::Tuple(t) => Rc::strong_count(t),
+        Value::List(l) => Rc::strong_count(l),
+        Value::Dict(d) => Rc::strong_count(d),
+        Value::Struct(s) => Rc::strong_count(s),
+        Value::Enum(e) => Rc::strong_count(e),
+        Value::Fun(f) => Rc::strong_count(f),
+        Value::Iterator(i) => Rc::strong_count(i),
+        _ => 0,
+    };
+    count as isize
+}
+
+/// Calls `f` with each value a container holds, or gives `false` when they
+/// are borrowed for changing.
+fn each_child(value : &Value, f : &mut dyn FnMut(&Value)) -> bool {
+    match value {
+        Value::Tuple(values) => values.iter().for_each(f),
+        Value::List(values) => match values.try_borrow() {
+            Ok(values) => values.iter().for_each(f),
+            Err(_) => return false,
+        },
+        Value::Dict(pairs) => match pairs.try_borrow() {
+            Ok(pairs) => pairs.iter().for_each(|(k, v)| { f(k); f(v) }),
+            Err(_) => return false,
+        },
+        Value::Struct(s) => match s.fields.try_borrow() {
+            Ok(fields) => fields.iter().for_each(|(_, v)| f(v)),
+            Err(_) => return false,
+        },
+        Value::Enum(e) => match e.contents.try_borrow() {
+            Ok(contents) => match &*contents {
+                CaseValue::Empty => (),
+                CaseValue::Tuple(values) => values.iter().for_each(f),
+                CaseValue::Struct(fields) => fields.iter().for_each(|(_, v)| f(v)),
+            },
+            Err(_) => return false,
+        },
+        Value::Fun(callable) => match &**callable {
+            Callable::Lambda { captured, .. } => captured.iter().for_each(|(_, v)| f(v)),
+            Callable::Closure { upvalues, .. } => upvalues.iter().for_each(f),
+            Callable::Fun(_) | Callable::Case { .. } | Callable::Method { .. } => (),
+        },
+        Value::Iterator(iteration) => match iteration.try_borrow() {
+            Ok(iteration) => match &*iteration {
+                Iteration::Items { list, .. } => f(&Value::List(list.clone())),
+                Iteration::Keys { dict, .. } => f(&Value::Dict(dict.clone())),
+                Iteration::Next(value) => f(value),
+                Iteration::Generator { state: Suspended::Tree { cursors, locals, .. }, .. } => {
+                    locals.iter().for_each(|(_, v)| f(v));
+                    for cursor in cursors {
+                        if let Cursor::Foreach { iterator, .. } = cursor {
+                            f(iterator);
+                        }
+                    }
+                },
+                Iteration::Generator { state: Suspended::Frame { stack, locals, .. }, .. } => stack.iter().chain(locals).for_each(f),
+                Iteration::Chars { .. } | Iteration::Count { .. } | Iteration::Running | Iteration::Finished => (),
+            },
+            Err(_) => return false,
+        },
+        _ => (),
+    }
+    true
+}
+
+/// Empties a container nothing can reach any more.
+fn clear(value : &Value) {
+    match value {
+        Value::List(values) => if let Ok(mut values) = values.try_borrow_mut() {
+            sandbox::release(values.len(), 0);
+            values.clear()
+        },
+        Value::Dict(pairs) => if let Ok(mut pairs) = pairs.try_borrow_mut() {
+            sandbox::release(2 * pairs.len(), 0);
+            pairs.clear()
+        },
+        Value::Struct(s) => if let Ok(mut fields) = s.fields.try_borrow_mut() {
+            sandbox::release(fields.len(), 0);
+            fields.clear()
+        },
+        Value::Enum(e) => if let Ok(mut contents) = e.contents.try_borrow_mut() {
+            let held = match &*contents {
+                CaseValue::Empty => 0,
+                CaseValue::Tuple(values) => values.len(),
+                CaseValue::Struct(fields) => fields.len(),
+            };
+            sandbox::release(held, 0);
+            *contents = CaseValue::Empty
+        },
+        Value::Iterator(iteration) => if let Ok(mut iteration) = iteration.try_borrow_mut() { *iteration = Iteration::Finished },
+        _ => (),
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} collections, {} of {} containers freed, {} live after the last collection", self.collections, self.freed, self.tracked, self.live)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+    use crate::evaluating::interpreter;
+    use crate::evaluating::conformance::CASES;
+    use crate::compiling::{compiler, vm};
+
+    fn weak(value : &Value) -> Handle {
+        downgrade(value)
+    }
+
+    fn alive(handle : &Handle) -> bool {
+        upgrade(handle).is_some()
+    }
+
+    #[test]
+    fn should_free_cycles_nothing_else_holds() {
+        let list = Value::list(vec![Value::Int(1)]);
+        let holder = Value::structure("Holder", vec![("list".to_string(), list.clone())]);
+        if let Value::List(values) = &list {
+            values.borrow_mut().push(holder.clone());
+        }
+        let kept = Value::list(vec![]);
+        if let Value::List(values) = &kept {
+            values.borrow_mut().push(kept.clone());
+        }
+        let (list_handle, holder_handle) = (weak(&list), weak(&holder));
+        drop((list, holder));
+        assert!( alive(&list_handle) && alive(&holder_handle) );
+
+        collect();
+        assert!( !alive(&list_handle) && !alive(&holder_handle) );
+        assert!( alive(&weak(&kept)) );
+        if let Value::List(values) = &kept {
+            assert_eq!( values.borrow().len(), 1 );
+        }
+    }
+
+    #[test]
+    fn should_keep_what_running_code_still_holds() {
+        let dict = Value::dict(vec![]);
+        let case = Value::case("Node", "Next", CaseValue::Tuple(vec![dict.clone()]));
+        if let Value::Dict(pairs) = &dict {
+            pairs.borrow_mut().push((Value::string("next"), case.clone()));
+        }
+        let handle = weak(&case);
+        drop(case);
+
+        let borrowed = match &dict { Value::Dict(pairs) => pairs.borrow_mut(), _ => unreachable!() };
+        collect();
+        drop(borrowed);
+        assert!( alive(&handle) );
+
+        collect();
+        assert!( alive(&handle) );
+        drop(dict);
+        collect();
+        assert!( !alive(&handle) );
+    }
+
+    #[test]
+    fn should_run_programs_the_same_collecting_on_every_allocation() {
+        set_stress(true);
+        for case in CASES {
+            let module = parse(case.source).unwrap();
+            let mut out = vec![];
+            interpreter::run(&module, &mut out).unwrap();
+            assert_eq!( String::from_utf8(out).unwrap(), case.output, "{}", case.name );
+
+            let mut out = vec![];
+            vm::run(&compiler::compile(&module).unwrap(), &mut out).unwrap();
+            assert_eq!( String::from_utf8(out).unwrap(), case.output, "{}", case.name );
+        }
+        set_stress(false);
+    }
+
+    #[test]
+    fn should_count_what_it_frees() {
+        let before = stats();
+        let module = parse(r#"
+enum Chain { Link(List<Chain>), End }
+fun main() {
+    let mut i = 0;
+    while i < 2000 {
+        let xs = [];
+        xs.push(Chain::Link(xs));
+        let f = || xs.len();
+        xs.push(Chain::End);
+        i = i + 1;
+    }
+}
+"#).unwrap();
+        let mut out = vec![];
+        vm::run(&compiler::compile(&module).unwrap(), &mut out).unwrap();
+        interpreter::run(&module, &mut out).unwrap();
+        collect();
+
+        let after = stats();
+        assert!( after.collections > before.collections );
+        assert!( after.freed - before.freed >= 4000, "{}", after );
+        assert!( after.live < 100, "{}", after );
+    }
+}
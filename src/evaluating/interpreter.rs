@@ -0,0 +1,985 @@
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::cell::RefCell;
+
+use crate::parsing::ast::*;
+use super::value::*;
+use super::ops;
+use super::dispatch::{MethodTable, impl_methods};
+use super::runtime_error::RuntimeError;
+use super::sandbox::{Sandbox, Capabilities, Capability, Budget};
+use super::ffi::{Libraries, Signature};
+use super::consts::{self, Consts};
+use crate::parsing::printer::{print_expr, print_type};
+use crate::checking::generators::contains_yield;
+
+/* The interpreter walks the untyped AST.  A program starts at `fun main()`.
+   Methods are dispatched on the runtime type of the receiver through a
+   `MethodTable`, falling back to the builtin methods of builtin types.
+
+   Calling a generator gives an iterator holding its locals and a stack of
+   cursors, one for each statement on the way from the body to the `yield` it
+   stopped at.  Resuming walks back down the cursors and carries on from
+   there, evaluating everything which does not yield as usual.
+*/
+
+enum Flow {
+    Return(Value),
+    /// An `Err` from `?`, which goes to the nearest `try` or else returns.
+    Propagate(Value),
+    Break,
+    Continue,
+    /// Boxed to keep `Eval` small, since every level of evaluation holds a few.
+    Error(Box<RuntimeError>),
+}
+
+impl From<RuntimeError> for Flow {
+    fn from(e : RuntimeError) -> Flow {
+        Flow::Error(Box::new(e))
+    }
+}
+
+type Eval = Result<Value, Flow>;
+
+pub struct Interpreter<'a> {
+    funs : HashMap<&'a str, &'a FunDef>,
+    externs : HashMap<&'a str, &'a ExternDef>,
+    structs : HashMap<&'a str, &'a StructDef>,
+    enums : HashMap<&'a str, &'a EnumDef>,
+    traits : HashMap<&'a str, &'a TraitDef>,
+    /// The values of the constants, leaving out those the checker reports errors for.
+    consts : Consts,
+    methods : MethodTable<&'a FunDef>,
+    /// Every function and method, which a suspended generator refers to by index.
+    bodies : Vec<&'a FunDef>,
+    capabilities : Capabilities,
+    budget : Budget,
+    libraries : Libraries,
+    out : &'a mut dyn Write,
+}
+
+pub fn run(module : &Module, out : &mut dyn Write) -> Result<Value, RuntimeError> {
+    Interpreter::new(module, out).run_main()
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(module : &'a Module, out : &'a mut dyn Write) -> Interpreter<'a> {
+        let mut methods = MethodTable::new();
+        for (type_name, trait_name, fun_def) in impl_methods(module) {
+            methods.insert(type_name.as_deref(), trait_name.as_deref(), &fun_def.sig.name, fun_def);
+        }
+
+        let impl_funs = module.impl_defs.iter().flat_map(|i| &i.items).filter_map(|item| match item {
+            ImplItem::Fun(fun_def) => Some(fun_def),
+            _ => None,
+        });
+
+        Interpreter { funs: module.fun_defs.iter().map(|f| (f.sig.name.as_str(), f)).collect()
+                    , externs: module.extern_defs.iter().map(|e| (e.sig.name.as_str(), e)).collect()
+                    , structs: module.struct_defs.iter().map(|s| (s.name.as_str(), s)).collect()
+                    , enums: module.enum_defs.iter().map(|e| (e.name.as_str(), e)).collect()
+                    , traits: module.trait_defs.iter().map(|t| (t.name.as_str(), t)).collect()
+                    , consts: consts::evaluate(module).0
+                    , methods
+                    , bodies: module.fun_defs.iter().chain(impl_funs).collect()
+                    , capabilities: Capabilities::default()
+                    , budget: Budget::new(Default::default())
+                    , libraries: Libraries::default()
+                    , out
+                    }
+    }
+
+    pub fn with_sandbox(mut self, sandbox : &Sandbox) -> Interpreter<'a> {
+        self.capabilities = sandbox.capabilities.clone();
+        self.budget = Budget::new(sandbox.limits);
+        self
+    }
+
+    pub fn run_main(&mut self) -> Result<Value, RuntimeError> {
+        match self.funs.get("main") {
+            Some(main) if main.sig.params.is_empty() => {
+                let main = *main;
+                self.call_fun(main, vec![])
+            },
+            _ => Err(RuntimeError::NoMain),
+        }
+    }
+
+    pub fn call(&mut self, name : &str, args : Vec<Value>) -> Result<Value, RuntimeError> {
+        self.call_value(&Value::Fun(Rc::new(Callable::Fun(name.to_string()))), args)
+    }
+
+    /// Evaluates a statement outside of any function, leaving the variables it
+    /// declares in `locals` so that later statements can see them.
+    pub fn eval_statement(&mut self, expr : &Expr, locals : &mut Vec<(String, Value)>) -> Result<Value, RuntimeError> {
+        finish(self.eval(expr, locals))
+    }
+
+    fn call_fun(&mut self, fun_def : &'a FunDef, args : Vec<Value>) -> Result<Value, RuntimeError> {
+        let params = &fun_def.sig.params;
+        if params.len() != args.len() {
+            return Err(RuntimeError::ArityMismatch { name: fun_def.sig.name.clone(), expected: params.len(), found: args.len() });
+        }
+
+        let mut locals = params.iter().map(|p| p.name.clone()).zip(args).collect::<Vec<_>>();
+        if contains_yield(&fun_def.body) {
+            let fun = self.bodies.iter().position(|f| std::ptr::eq(*f, fun_def)).expect("function is in the module");
+            let state = Suspended::Tree { fun, cursors: vec![], locals };
+            return Ok(Value::iterator(Iteration::Generator { name: fun_def.sig.name.clone(), state }));
+        }
+        self.budget.enter()?;
+        let result = finish(self.eval(&fun_def.body, &mut locals));
+        self.budget.leave();
+        result.map_err(|e| e.leaving(&fun_def.sig.name))
+    }
+
+    pub fn call_value(&mut self, fun : &Value, args : Vec<Value>) -> Result<Value, RuntimeError> {
+        let callable = match fun {
+            Value::Fun(c) => c,
+            v => return Err(RuntimeError::NotCallable { type_name: v.type_name() }),
+        };
+
+        match &**callable {
+            Callable::Fun(name) => {
+                if let Some(fun_def) = self.funs.get(name.as_str()) {
+                    let fun_def = *fun_def;
+                    return self.call_fun(fun_def, args);
+                }
+                if let Some(result) = ops::builtin(name, &args, self.out, &self.capabilities) {
+                    return result;
+                }
+                match self.externs.get(name.as_str()) {
+                    Some(extern_def) => {
+                        self.capabilities.check(Capability::Externs)?;
+                        let signature = Signature::of(extern_def).map_err(|t| RuntimeError::Extern(format!("{} cannot pass {}", name, print_type(&t))))?;
+                        self.libraries.call(&signature, &args)
+                    },
+                    None => Err(RuntimeError::UnknownFunction(name.clone())),
+                }
+            },
+            Callable::Case { enum_name, case_name, arity } => {
+                if args.len() != *arity {
+                    return Err(RuntimeError::ArityMismatch { name: format!("{}::{}", enum_name, case_name), expected: *arity, found: args.len() });
+                }
+                Ok(Value::case(enum_name, case_name, CaseValue::Tuple(args)))
+            },
+            Callable::Method { type_name, name } => {
+                if self.traits.contains_key(type_name.as_str()) {
+                    let mut args = args;
+                    if args.is_empty() {
+                        return Err(RuntimeError::ArityMismatch { name: format!("{}::{}", type_name, name), expected: 1, found: 0 });
+                    }
+                    let receiver = args.remove(0);
+                    return self.call_method(receiver, name, args);
+                }
+                match self.find_method(type_name, name)? {
+                    Some(fun_def) => self.call_fun(fun_def, args),
+                    None => Err(RuntimeError::NoMethod { type_name: type_name.clone(), name: name.clone() }),
+                }
+            },
+            Callable::Lambda { params, body, captured } => {
+                if params.len() != args.len() {
+                    return Err(RuntimeError::ArityMismatch { name: "closure".to_string(), expected: params.len(), found: args.len() });
+                }
+                let mut locals = captured.clone();
+                locals.extend(params.iter().cloned().zip(args));
+                self.budget.enter()?;
+                let result = finish(self.eval(body, &mut locals));
+                self.budget.leave();
+                result.map_err(|e| e.leaving("closure"))
+            },
+            // closures from the virtual machine only exist in values the virtual machine created
+            Callable::Closure { .. } => Err(RuntimeError::NotCallable { type_name: fun.type_name() }),
+        }
+    }
+
+    fn find_method(&self, type_name : &str, name : &str) -> Result<Option<&'a FunDef>, RuntimeError> {
+        self.methods.find(type_name, name)
+    }
+
+    fn call_method(&mut self, receiver : Value, name : &str, args : Vec<Value>) -> Result<Value, RuntimeError> {
+        let type_name = receiver.type_name();
+        match self.find_method(&type_name, name)? {
+            Some(fun_def) => {
+                let mut all = vec![receiver];
+                all.extend(args);
+                self.call_fun(fun_def, all)
+            },
+            None => match (&receiver, name) {
+                (Value::Iterator(iterator), "next") if args.is_empty() => Ok(ops::to_option(self.next(iterator)?)),
+                _ => match ops::native_method(&receiver, name, &args) {
+                    Some(result) => result,
+                    None => Err(RuntimeError::NoMethod { type_name, name: name.to_string() }),
+                },
+            },
+        }
+    }
+
+    fn next(&mut self, iterator : &RefCell<Iteration>) -> Result<Option<Value>, RuntimeError> {
+        ops::advance(iterator, |state| match state {
+            Iteration::Next(value) => {
+                let item = ops::option(self.call_method(value.clone(), "next", vec![])?)?;
+                Ok((Iteration::Next(value), item))
+            },
+            Iteration::Generator { name, state: Suspended::Tree { fun, mut cursors, mut locals } } => {
+                let fun_def = *self.bodies.get(fun).filter(|f| f.sig.name == name).ok_or_else(|| RuntimeError::UnknownFunction(name.clone()))?;
+                match self.resume(&fun_def.body, &mut cursors, 0, &mut locals) {
+                    Ok(Step::Yielded(item)) => Ok((Iteration::Generator { name, state: Suspended::Tree { fun, cursors, locals } }, Some(item))),
+                    Ok(Step::Done(_)) | Err(Flow::Return(_)) | Err(Flow::Propagate(_)) => Ok((Iteration::Finished, None)),
+                    Err(Flow::Break) | Err(Flow::Continue) => Err(RuntimeError::BreakOutsideLoop.leaving(&name)),
+                    Err(Flow::Error(e)) => Err(e.leaving(&name)),
+                }
+            },
+            // generators from the virtual machine only exist in values the virtual machine created
+            _ => Err(RuntimeError::NotIterable { type_name: "Iterator".to_string() }),
+        })
+    }
+
+    fn next_item(&mut self, iterator : &Value) -> Result<Option<Value>, RuntimeError> {
+        match iterator {
+            Value::Iterator(iterator) => self.next(iterator),
+            v => Err(RuntimeError::NotIterable { type_name: v.type_name() }),
+        }
+    }
+
+    /// Runs a statement of a generator until it yields or finishes, carrying on
+    /// from the cursor at `level` when there is one.
+    fn resume(&mut self, expr : &Expr, cursors : &mut Vec<Cursor>, level : usize, locals : &mut Vec<(String, Value)>) -> Result<Step, Flow> {
+        if !contains_yield(expr) {
+            return self.eval(expr, locals).map(Step::Done);
+        }
+        let step = self.resume_inner(expr, cursors, level, locals);
+        if !matches!( step, Ok(Step::Yielded(_)) ) {
+            cursors.truncate(level);
+        }
+        step
+    }
+
+    fn resume_inner(&mut self, expr : &Expr, cursors : &mut Vec<Cursor>, level : usize, locals : &mut Vec<(String, Value)>) -> Result<Step, Flow> {
+        let mut resuming = cursors.len() > level;
+        match expr {
+            Expr::Yield(e) => {
+                if resuming {
+                    return Ok(Step::Done(Value::Unit));
+                }
+                let item = self.eval(e, locals)?;
+                cursors.push(Cursor::Yield);
+                Ok(Step::Yielded(item))
+            },
+            Expr::Block(exprs) => {
+                let (start, depth) = match cursors.get(level) {
+                    Some(Cursor::Block { index, depth }) => (*index, *depth),
+                    _ => {
+                        cursors.push(Cursor::Block { index: 0, depth: locals.len() });
+                        (0, locals.len())
+                    },
+                };
+                let mut result = Value::Unit;
+                for (index, e) in exprs.iter().enumerate().skip(start) {
+                    cursors[level] = Cursor::Block { index, depth };
+                    match self.resume(e, cursors, level + 1, locals) {
+                        Ok(Step::Done(v)) => result = v,
+                        Ok(yielded) => return Ok(yielded),
+                        Err(flow) => {
+                            locals.truncate(depth);
+                            return Err(flow);
+                        },
+                    }
+                }
+                locals.truncate(depth);
+                Ok(Step::Done(result))
+            },
+            Expr::If { condition, then, otherwise } => {
+                let take_then = match cursors.get(level) {
+                    Some(Cursor::If { then }) => *then,
+                    _ => {
+                        let take_then = ops::expect_bool(&self.eval(condition, locals)?)?;
+                        cursors.push(Cursor::If { then: take_then });
+                        take_then
+                    },
+                };
+                self.resume(if take_then { then } else { otherwise }, cursors, level + 1, locals)
+            },
+            Expr::While { condition, body } => {
+                if !resuming {
+                    cursors.push(Cursor::While);
+                }
+                loop {
+                    if !resuming && !ops::expect_bool(&self.eval(condition, locals)?)? {
+                        break;
+                    }
+                    resuming = false;
+                    match self.resume(body, cursors, level + 1, locals) {
+                        Ok(Step::Yielded(item)) => return Ok(Step::Yielded(item)),
+                        Ok(Step::Done(_)) | Err(Flow::Continue) => (),
+                        Err(Flow::Break) => break,
+                        Err(flow) => return Err(flow),
+                    }
+                }
+                Ok(Step::Done(Value::Unit))
+            },
+            Expr::Foreach { name, iterable, body, .. } => {
+                let (iterator, depth) = match cursors.get(level) {
+                    Some(Cursor::Foreach { iterator, depth }) => (iterator.clone(), *depth),
+                    _ => {
+                        let iterator = ops::iterate(self.eval(iterable, locals)?)?;
+                        cursors.push(Cursor::Foreach { iterator: iterator.clone(), depth: locals.len() });
+                        (iterator, locals.len())
+                    },
+                };
+                loop {
+                    if !resuming {
+                        locals.truncate(depth);
+                        match self.next_item(&iterator)? {
+                            Some(item) => locals.push((name.clone(), item)),
+                            None => break,
+                        }
+                    }
+                    resuming = false;
+                    match self.resume(body, cursors, level + 1, locals) {
+                        Ok(Step::Yielded(item)) => return Ok(Step::Yielded(item)),
+                        Ok(Step::Done(_)) | Err(Flow::Continue) => (),
+                        Err(Flow::Break) => break,
+                        Err(flow) => {
+                            locals.truncate(depth);
+                            return Err(flow);
+                        },
+                    }
+                }
+                locals.truncate(depth);
+                Ok(Step::Done(Value::Unit))
+            },
+            Expr::Match { expr, arms } => {
+                let (arm, depth) = match cursors.get(level) {
+                    Some(Cursor::Match { arm, depth }) => (*arm, *depth),
+                    _ => {
+                        let value = self.eval(expr, locals)?;
+                        let depth = locals.len();
+                        let mut found = None;
+                        for (i, arm) in arms.iter().enumerate() {
+                            let mut bindings = vec![];
+                            if match_pattern(&self.consts.substitute(&arm.pattern), &value, &mut bindings)? {
+                                locals.extend(bindings);
+                                found = Some(i);
+                                break;
+                            }
+                        }
+                        let arm = found.ok_or_else(|| RuntimeError::NoMatchingArm { value: show(&value) })?;
+                        cursors.push(Cursor::Match { arm, depth });
+                        (arm, depth)
+                    },
+                };
+                let step = self.resume(&arms[arm].body, cursors, level + 1, locals);
+                if !matches!( step, Ok(Step::Yielded(_)) ) {
+                    locals.truncate(depth);
+                }
+                step
+            },
+            _ => Err(RuntimeError::MisplacedYield.into()),
+        }
+    }
+
+    fn eval_all(&mut self, exprs : &[Expr], locals : &mut Vec<(String, Value)>) -> Result<Vec<Value>, Flow> {
+        let mut values = vec![];
+        for e in exprs {
+            values.push(self.eval(e, locals)?);
+        }
+        Ok(values)
+    }
+
+    /* Each kind of expression is evaluated by a method of its own, so that the
+       frame of `eval`, which every level of a deep recursion goes through
+       several times, only has to hold what a single call returns.
+    */
+    fn eval(&mut self, expr : &Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        if let Err(e) = self.budget.step() {
+            return Err(e.into());
+        }
+        match expr {
+            Expr::Number(n) => ops::number(n).map_err(Flow::from),
+            Expr::DString(s) => Ok(Value::string(s)),
+            Expr::Bool(b) => Ok(Value::Bool(*b)),
+            Expr::Unit => Ok(Value::Unit),
+            Expr::Variable(name) => self.variable(name, locals),
+            Expr::Namespace(path, name) => self.resolve_path(path, name).map_err(Flow::from),
+            Expr::Tuple(exprs) => self.eval_all(exprs, locals).map(Value::tuple),
+            Expr::List(exprs) => self.eval_all(exprs, locals).map(Value::list),
+            Expr::Dict(pairs) => self.dict(pairs, locals),
+            Expr::Struct { namespace, name, fields } => self.structure(namespace, name, fields, locals),
+            Expr::Block(exprs) => self.block(exprs, locals),
+            Expr::Call { fun, args, meta } => self.apply(fun, args, *meta, locals),
+            Expr::Dot { expr, name } => self.dot(expr, name, locals),
+            Expr::MethodCall { receiver, name, args, meta } => self.method_call(receiver, name, args, *meta, locals),
+            Expr::Index { expr, index } => self.index(expr, index, locals),
+            Expr::Range { start, end, inclusive } => self.range(start.as_deref(), end.as_deref(), *inclusive, locals),
+            Expr::Let { name, value, .. } => self.bind(name, value, locals),
+            Expr::Assign { target, value, .. } => self.assign(target, value, locals).map(|()| Value::Unit),
+            Expr::Lambda { params, body } => Ok(self.lambda(params, body, locals)),
+            Expr::Unary { op, expr } => self.unary(*op, expr, locals),
+            Expr::Binary { op, left, right } => self.binary(*op, left, right, locals),
+            Expr::If { condition, then, otherwise } => self.choose(condition, then, otherwise, locals),
+            Expr::While { condition, body } => self.repeat(condition, body, locals),
+            Expr::Foreach { name, iterable, body, .. } => self.foreach(name, iterable, body, locals),
+            Expr::Yield(_) => Err(RuntimeError::MisplacedYield.into()),
+            Expr::Break => Err(Flow::Break),
+            Expr::Continue => Err(Flow::Continue),
+            Expr::Return(e) => self.eval(e, locals).and_then(|value| Err(Flow::Return(value))),
+            Expr::Try(body) => self.eval(body, locals).or_else(|flow| match flow {
+                Flow::Propagate(value) => Ok(value),
+                flow => Err(flow),
+            }),
+            Expr::Propagate(e) => self.propagate(e, locals),
+            Expr::Assert { condition, message, meta } => self.assert(condition, message.as_deref(), *meta, locals),
+            Expr::Panic { message, meta } => self.panic(message, *meta, locals),
+            Expr::Match { expr, arms } => self.eval_match(expr, arms, locals),
+        }
+    }
+
+    fn variable(&mut self, name : &str, locals : &mut Vec<(String, Value)>) -> Eval {
+        if let Some((_, v)) = locals.iter().rev().find(|(n, _)| n == name) {
+            return Ok(v.clone());
+        }
+        if let Some(literal) = self.consts.expr(name).cloned() {
+            return self.eval(&literal, locals);
+        }
+        if self.funs.contains_key(name) || self.externs.contains_key(name) || ops::BUILTIN_FUNS.contains(&name) {
+            return Ok(Value::Fun(Rc::new(Callable::Fun(name.to_string()))));
+        }
+        Err(RuntimeError::UnknownVariable(name.to_string()).into())
+    }
+
+    fn dict(&mut self, pairs : &[(Expr, Expr)], locals : &mut Vec<(String, Value)>) -> Eval {
+        let mut values = vec![];
+        for (k, v) in pairs {
+            let k = self.eval(k, locals)?;
+            let v = self.eval(v, locals)?;
+            ops::insert(&mut values, k, v);
+        }
+        Ok(Value::dict(values))
+    }
+
+    fn structure(&mut self, namespace : &[String], name : &str, fields : &[(String, Expr)], locals : &mut Vec<(String, Value)>) -> Eval {
+        let mut values = vec![];
+        for (n, e) in fields {
+            values.push((n.clone(), self.eval(e, locals)?));
+        }
+        Ok(construct(&self.structs, &self.enums, namespace, name, values)?)
+    }
+
+    fn block(&mut self, exprs : &[Expr], locals : &mut Vec<(String, Value)>) -> Eval {
+        let depth = locals.len();
+        let mut result = Ok(Value::Unit);
+        for e in exprs {
+            result = self.eval(e, locals);
+            if result.is_err() {
+                break;
+            }
+        }
+        locals.truncate(depth);
+        result
+    }
+
+    fn apply(&mut self, fun : &Expr, args : &[Expr], meta : Meta, locals : &mut Vec<(String, Value)>) -> Eval {
+        let fun = self.eval(fun, locals)?;
+        let args = self.eval_all(args, locals)?;
+        Ok(self.call_value(&fun, args).map_err(|e| e.called_at(Some(meta)))?)
+    }
+
+    fn dot(&mut self, expr : &Expr, name : &str, locals : &mut Vec<(String, Value)>) -> Eval {
+        let value = self.eval(expr, locals)?;
+        Ok(ops::field(&value, name)?)
+    }
+
+    fn method_call(&mut self, receiver : &Expr, name : &str, args : &[Expr], meta : Meta, locals : &mut Vec<(String, Value)>) -> Eval {
+        let receiver = self.eval(receiver, locals)?;
+        let args = self.eval_all(args, locals)?;
+        Ok(self.call_method(receiver, name, args).map_err(|e| e.called_at(Some(meta)))?)
+    }
+
+    fn index(&mut self, expr : &Expr, index : &Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        let value = self.eval(expr, locals)?;
+        let index = self.eval(index, locals)?;
+        Ok(ops::index(&value, &index)?)
+    }
+
+    fn bind(&mut self, name : &str, value : &Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        let value = self.eval(value, locals)?;
+        locals.push((name.to_string(), value));
+        Ok(Value::Unit)
+    }
+
+    fn lambda(&mut self, params : &[Param], body : &Arc<Expr>, locals : &[(String, Value)]) -> Value {
+        Value::closure(Callable::Lambda { params: params.iter().map(|p| p.name.clone()).collect()
+                                        , body: body.clone()
+                                        , captured: locals.to_vec()
+                                        })
+    }
+
+    fn unary(&mut self, op : UnaryOp, expr : &Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        let value = self.eval(expr, locals)?;
+        Ok(ops::unary(op, value)?)
+    }
+
+    fn binary(&mut self, op : BinOp, left : &Expr, right : &Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        if let BinOp::And | BinOp::Or = op {
+            return self.logic(op, left, right, locals);
+        }
+        let left = self.eval(left, locals)?;
+        let right = self.eval(right, locals)?;
+        ops::binary(op, left, right).map_err(Flow::from)
+    }
+
+    /// `&&` and `||`, which only evaluate their right side when they need it.
+    fn logic(&mut self, op : BinOp, left : &Expr, right : &Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        let left = ops::expect_bool(&self.eval(left, locals)?)?;
+        if left == (op == BinOp::Or) {
+            return Ok(Value::Bool(left));
+        }
+        Ok(Value::Bool(ops::expect_bool(&self.eval(right, locals)?)?))
+    }
+
+    fn choose(&mut self, condition : &Expr, then : &Expr, otherwise : &Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        match ops::expect_bool(&self.eval(condition, locals)?)? {
+            true => self.eval(then, locals),
+            false => self.eval(otherwise, locals),
+        }
+    }
+
+    fn repeat(&mut self, condition : &Expr, body : &Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        while ops::expect_bool(&self.eval(condition, locals)?)? {
+            match self.eval(body, locals) {
+                Ok(_) | Err(Flow::Continue) => (),
+                Err(Flow::Break) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Value::Unit)
+    }
+
+    fn foreach(&mut self, name : &str, iterable : &Expr, body : &Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        let iterator = ops::iterate(self.eval(iterable, locals)?)?;
+        while let Some(item) = self.next_item(&iterator)? {
+            locals.push((name.to_string(), item));
+            let result = self.eval(body, locals);
+            locals.pop();
+            match result {
+                Ok(_) | Err(Flow::Continue) => (),
+                Err(Flow::Break) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Value::Unit)
+    }
+
+    fn propagate(&mut self, e : &Expr, locals : &mut Vec<(String, Value)>) -> Eval {
+        let value = self.eval(e, locals)?;
+        match ops::propagate(value)? {
+            Ok(value) => Ok(value),
+            Err(value) => Err(Flow::Propagate(value)),
+        }
+    }
+
+    fn assert(&mut self, condition : &Expr, message : Option<&Expr>, meta : Meta, locals : &mut Vec<(String, Value)>) -> Eval {
+        let sides = match condition {
+            Expr::Binary { op: BinOp::Eq, left, right } => {
+                let left = self.eval(left, locals)?;
+                let right = self.eval(right, locals)?;
+                match ops::assert_eq(left, right)? {
+                    None => return Ok(Value::Unit),
+                    sides => sides,
+                }
+            },
+            condition => match ops::expect_bool(&self.eval(condition, locals)?)? {
+                true => return Ok(Value::Unit),
+                false => None,
+            },
+        };
+        let message = match message {
+            Some(message) => Some(self.eval(message, locals)?),
+            None => None,
+        };
+        Err(ops::failure(Some(print_expr(condition, 0)), message.as_ref(), sides, meta).into())
+    }
+
+    fn panic(&mut self, message : &Expr, meta : Meta, locals : &mut Vec<(String, Value)>) -> Eval {
+        let message = self.eval(message, locals)?;
+        Err(ops::failure(None, Some(&message), None, meta).into())
+    }
+
+    fn eval_match(&mut self, expr : &Expr, arms : &[MatchArm], locals : &mut Vec<(String, Value)>) -> Eval {
+        let value = self.eval(expr, locals)?;
+        for arm in arms {
+            let mut bindings = vec![];
+            if match_pattern(&self.consts.substitute(&arm.pattern), &value, &mut bindings)? {
+                let depth = locals.len();
+                locals.extend(bindings);
+                let result = self.eval(&arm.body, locals);
+                locals.truncate(depth);
+                return result;
+            }
+        }
+        Err(RuntimeError::NoMatchingArm { value: show(&value) }.into())
+    }
+
+    fn resolve_path(&self, path : &[String], name : &str) -> Result<Value, RuntimeError> {
+        let owner = match path.last() {
+            Some(owner) => owner,
+            None => return Err(RuntimeError::UnknownVariable(name.to_string())),
+        };
+
+        if let Some(enum_def) = self.enums.get(owner.as_str()) {
+            match enum_def.cases.iter().find(|c| case_name(c) == name) {
+                Some(EnumCase::EmptyCase { .. }) => return Ok(Value::case(owner, name, CaseValue::Empty)),
+                Some(EnumCase::TypeCase { types, .. }) =>
+                    return Ok(Value::Fun(Rc::new(Callable::Case { enum_name: owner.clone(), case_name: name.to_string(), arity: types.len() }))),
+                Some(EnumCase::StructCase { .. }) => return Err(RuntimeError::UnknownCase { name: format!("{}::{}", owner, name) }),
+                None => (),
+            }
+        }
+
+        Ok(Value::Fun(Rc::new(Callable::Method { type_name: owner.clone(), name: name.to_string() })))
+    }
+
+    fn range(&mut self, start : Option<&Expr>, end : Option<&Expr>, inclusive : bool, locals : &mut Vec<(String, Value)>) -> Eval {
+        let start = start.map(|e| self.eval(e, locals)).transpose()?;
+        let end = end.map(|e| self.eval(e, locals)).transpose()?;
+        Ok(ops::range(start, end, inclusive)?)
+    }
+
+    fn assign(&mut self, target : &Expr, value : &Expr, locals : &mut Vec<(String, Value)>) -> Result<(), Flow> {
+        let value = self.eval(value, locals)?;
+        match target {
+            Expr::Variable(name) => match locals.iter_mut().rev().find(|(n, _)| n == name) {
+                Some((_, slot)) => {
+                    *slot = value;
+                    Ok(())
+                },
+                None => Err(RuntimeError::UnknownVariable(name.clone()).into()),
+            },
+            Expr::Dot { expr, name } => {
+                let target = self.eval(expr, locals)?;
+                Ok(ops::set_field(&target, name, value)?)
+            },
+            Expr::Index { expr, index } => {
+                let target = self.eval(expr, locals)?;
+                let index = self.eval(index, locals)?;
+                Ok(ops::set_index(&target, index, value)?)
+            },
+            _ => Err(RuntimeError::InvalidAssignTarget.into()),
+        }
+    }
+}
+
+fn finish(result : Eval) -> Result<Value, RuntimeError> {
+    match result {
+        Ok(v) | Err(Flow::Return(v)) | Err(Flow::Propagate(v)) => Ok(v),
+        Err(Flow::Break) | Err(Flow::Continue) => Err(RuntimeError::BreakOutsideLoop),
+        Err(Flow::Error(e)) => Err(*e),
+    }
+}
+
+pub fn case_name(case : &EnumCase) -> &str {
+    match case {
+        EnumCase::EmptyCase { name } => name,
+        EnumCase::StructCase { name, .. } => name,
+        EnumCase::TypeCase { name, .. } => name,
+    }
+}
+
+/// Makes the struct `name`, or the struct case `name` of the enum which ends
+/// `namespace`, from fields in any order.
+pub fn construct( structs : &HashMap<&str, &StructDef>
+                , enums : &HashMap<&str, &EnumDef>
+                , namespace : &[String]
+                , name : &str
+                , mut values : Vec<(String, Value)>
+                ) -> Result<Value, RuntimeError> {
+    let (type_name, fields) = match namespace.last() {
+        Some(owner) => {
+            let fields = enums.get(owner.as_str())
+                              .and_then(|e| e.cases.iter().find(|c| case_name(c) == name))
+                              .and_then(|c| match c {
+                                  EnumCase::StructCase { fields, .. } => Some(fields),
+                                  _ => None,
+                              })
+                              .ok_or_else(|| RuntimeError::UnknownCase { name: format!("{}::{}", owner, name) })?;
+            (format!("{}::{}", owner, name), fields)
+        },
+        None => {
+            let fields = structs.get(name)
+                                .map(|s| &s.fields)
+                                .ok_or_else(|| RuntimeError::UnknownVariable(name.to_string()))?;
+            (name.to_string(), fields)
+        },
+    };
+
+    if let Some((extra, _)) = values.iter().find(|(n, _)| !fields.iter().any(|f| &f.name == n)) {
+        return Err(RuntimeError::UnknownField { type_name, field: extra.clone() });
+    }
+
+    let mut ordered = vec![];
+    for field in fields {
+        match values.iter().position(|(n, _)| n == &field.name) {
+            Some(i) => ordered.push(values.remove(i)),
+            None => return Err(RuntimeError::MissingField { type_name, field: field.name.clone() }),
+        }
+    }
+
+    match namespace.last() {
+        Some(owner) => Ok(Value::case(owner, name, CaseValue::Struct(ordered))),
+        None => Ok(Value::structure(name, ordered)),
+    }
+}
+
+pub fn match_pattern(pattern : &Pattern, value : &Value, bindings : &mut Vec<(String, Value)>) -> Result<bool, RuntimeError> {
+    match (pattern, value) {
+        (Pattern::Wildcard, _) => Ok(true),
+        (Pattern::Variable(name), v) => {
+            bindings.push((name.clone(), v.clone()));
+            Ok(true)
+        },
+        (Pattern::Number(n), v) => Ok(ops::number(n)? == *v),
+        (Pattern::Range { start, end, inclusive }, v) => {
+            let start = start.as_deref().map(ops::number).transpose()?;
+            let end = end.as_deref().map(ops::number).transpose()?;
+            Ok(ops::in_range(v, start.as_ref(), end.as_ref(), *inclusive))
+        },
+        (Pattern::DString(s), Value::String(v)) => Ok(**s == **v),
+        (Pattern::Bool(b), Value::Bool(v)) => Ok(b == v),
+        (Pattern::Tuple(ps), Value::Tuple(vs)) if ps.len() == vs.len() => match_all(ps, vs, bindings),
+        (Pattern::Or(ps), v) => {
+            let depth = bindings.len();
+            for p in ps {
+                if match_pattern(p, v, bindings)? {
+                    return Ok(true);
+                }
+                bindings.truncate(depth);
+            }
+            Ok(false)
+        },
+        (Pattern::Case { namespace, name, contents }, Value::Enum(e)) => {
+            if &e.case_name != name || namespace.last().is_some_and(|n| n != &e.enum_name) {
+                return Ok(false);
+            }
+            match (contents, &*e.contents.borrow()) {
+                (CasePattern::Empty, CaseValue::Empty) => Ok(true),
+                (CasePattern::Tuple(ps), CaseValue::Tuple(vs)) if ps.len() == vs.len() => match_all(ps, vs, bindings),
+                (CasePattern::Struct { fields, .. }, CaseValue::Struct(vs)) => match_fields(fields, vs, bindings),
+                _ => Ok(false),
+            }
+        },
+        (Pattern::Case { namespace, name, contents: CasePattern::Struct { fields, .. } }, Value::Struct(s)) if namespace.is_empty() => {
+            if &s.name != name {
+                return Ok(false);
+            }
+            match_fields(fields, &s.fields.borrow(), bindings)
+        },
+        _ => Ok(false),
+    }
+}
+
+fn match_all(patterns : &[Pattern], values : &[Value], bindings : &mut Vec<(String, Value)>) -> Result<bool, RuntimeError> {
+    for (p, v) in patterns.iter().zip(values) {
+        if !match_pattern(p, v, bindings)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn match_fields(patterns : &[(String, Pattern)], values : &[(String, Value)], bindings : &mut Vec<(String, Value)>) -> Result<bool, RuntimeError> {
+    for (field, p) in patterns {
+        match values.iter().find(|(n, _)| n == field) {
+            Some((_, v)) => {
+                if !match_pattern(p, v, bindings)? {
+                    return Ok(false);
+                }
+            },
+            None => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::runtime_error::{Failure, Frame};
+    use crate::parsing::parser::parse;
+    use super::super::conformance::CASES;
+
+    fn run_src(src : &str) -> (Result<Value, RuntimeError>, String) {
+        let module = parse(src).unwrap();
+        let mut out = vec![];
+        let result = run(&module, &mut out);
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn should_pass_conformance_cases() {
+        for case in CASES {
+            let (result, output) = run_src(case.source);
+            assert!( result.is_ok(), "{} failed with {:?}", case.name, result );
+            assert_eq!( output, case.output, "{}", case.name );
+        }
+    }
+
+    #[test]
+    fn should_return_value_of_main() {
+        let (result, _) = run_src("fun main() -> Int { 6 * 7 }");
+        assert_eq!( result, Ok(Value::Int(42)) );
+    }
+
+    #[test]
+    fn should_report_missing_main() {
+        let (result, _) = run_src("fun start() { }");
+        assert_eq!( result, Err(RuntimeError::NoMain) );
+    }
+
+    #[test]
+    fn should_report_runtime_errors() {
+        let (result, _) = run_src("fun main() { let xs = [1, 2]; xs[5] }");
+        assert_eq!( result, Err(RuntimeError::IndexOutOfBounds { index: 5, length: 2 }) );
+
+        let (result, _) = run_src("fun main() { 1 / 0 }");
+        assert_eq!( result, Err(RuntimeError::DivisionByZero) );
+
+        let (result, _) = run_src("fun main() { match 3 { 1 => 1, 2 => 2 } }");
+        assert_eq!( result, Err(RuntimeError::NoMatchingArm { value: "3".to_string() }) );
+
+        let (result, _) = run_src("fun main() { 5.grow() }");
+        assert_eq!( result, Err(RuntimeError::NoMethod { type_name: "Int".to_string(), name: "grow".to_string() }) );
+    }
+
+    #[test]
+    fn should_report_failed_asserts_and_panics_with_a_trace() {
+        let src = "fun f(x : Int) {\n  assert(x * 2 == 3, \"x is {}\");\n}\nfun main() { f(1) }";
+        let (result, _) = run_src(src);
+        let at = |text : &str| {
+            let start = src.find(text).unwrap();
+            Meta { start, end: start + text.len() }
+        };
+        let failure = Failure { expression: Some("x * 2 == 3".to_string())
+                              , message: Some("x is {}".to_string())
+                              , sides: Some(("2".to_string(), "3".to_string()))
+                              , at: at("assert(x * 2 == 3, \"x is {}\")")
+                              , trace: vec![ Frame { function: "f".to_string(), called_at: Some(at("f(1)")) }
+                                           , Frame { function: "main".to_string(), called_at: None }
+                                           ]
+                              };
+        assert_eq!( result, Err(RuntimeError::Failed(Box::new(failure))) );
+
+        let (result, output) = run_src("fun main() { print(1); panic([\"a\", 2]); print(2) }");
+        assert_eq!( output, "1" );
+        let e = result.unwrap_err();
+        assert_eq!( e.to_string(), "panic: [\"a\", 2]" );
+        assert!( matches!( e, RuntimeError::Failed(f) if f.expression.is_none() && f.trace.len() == 1 ) );
+
+        let (result, _) = run_src("fun main() { assert(\"a\" == \"b\") }");
+        assert_eq!( result.unwrap_err().to_string(), "assertion failed: \"a\" == \"b\"\n  left: \"a\"\n  right: \"b\"" );
+    }
+
+    #[test]
+    fn should_run_generators_only_as_far_as_they_are_stepped() {
+        let (result, output) = run_src(r#"
+fun numbered(xs : List<String>) {
+    let mut i = 0;
+    foreach x in xs {
+        print("<" + x + ">");
+        yield (i, x);
+        i = i + 1;
+    }
+    print("done");
+}
+fun main() -> Int {
+    let it = numbered(["a", "b", "c"]);
+    foreach pair in it {
+        match pair { (1, _) => break, p => print(p) }
+    }
+    print(it.next(), it.next(), it.next());
+    7
+}
+"#);
+        assert_eq!( result, Ok(Value::Int(7)) );
+        assert_eq!( output, "<a>(0, \"a\")<b><c>doneOption::Some((2, \"c\")) Option::None Option::None" );
+    }
+
+    #[test]
+    fn should_report_errors_from_inside_generators() {
+        let (result, _) = run_src("fun g(x : Int) { yield 1; assert(x == 2); }\nfun main() { foreach y in g(3) { } }");
+        match result {
+            Err(RuntimeError::Failed(f)) => assert_eq!( f.trace.iter().map(|f| f.function.as_str()).collect::<Vec<_>>(), ["g", "main"] ),
+            r => panic!( "Expected a failure but found {:?}", r ),
+        }
+
+        let (result, _) = run_src("fun g(xs : List<Iterator>) { yield 1; xs[0].next(); }\nfun main() { let xs = []; let it = g(xs); xs.push(it); it.next(); it.next() }");
+        assert_eq!( result, Err(RuntimeError::GeneratorRunning) );
+
+        let (result, _) = run_src("fun main() { foreach x in 5 { } }");
+        assert_eq!( result, Err(RuntimeError::NotIterable { type_name: "Int".to_string() }) );
+
+        let (result, _) = run_src("fun g() { let x = yield 1; }\nfun main() { g().next() }");
+        assert_eq!( result, Err(RuntimeError::MisplacedYield) );
+    }
+
+    #[test]
+    fn should_count_match_and_slice_with_ranges() {
+        let (result, output) = run_src(r#"
+fun size(n : Int) -> String {
+    match n { ..0 => "negative", 0 => "zero", 1..=9 => "small", 10.. => "large" }
+}
+fun main() -> String {
+    let mut total = 0;
+    foreach i in 1..=4 { total = total + i; }
+    let xs = [1, 2, 3, 4, 5];
+    println(total, xs[1..3], xs[..2], xs[3..], size(-2), size(0), size(7), size(10));
+    let r = 2..;
+    "dusty"[r]
+}
+"#);
+        assert_eq!( result, Ok(Value::string("sty")) );
+        assert_eq!( output, "10 [2, 3] [1, 2] [4, 5] negative zero small large\n" );
+
+        let (result, _) = run_src("fun main() { let s = \"abc\"; s[1..=3] }");
+        assert_eq!( result, Err(RuntimeError::IndexOutOfBounds { index: 3, length: 3 }) );
+
+        let (result, _) = run_src("fun main() { foreach i in ..3 { } }");
+        assert_eq!( result, Err(RuntimeError::OpenRange) );
+    }
+
+    #[test]
+    fn should_propagate_errors_to_the_nearest_try_or_function() {
+        let (result, output) = run_src(r#"
+enum R { Ok(Int), Err(String) }
+fun half(x : Int) -> R { if x % 2 == 0 { R::Ok(x / 2) } else { R::Err("odd") } }
+fun quarter(x : Int) -> R { R::Ok(half(half(x)?)?) }
+fun main() -> Int {
+    let f = |x| { println(half(x)?); R::Ok(0) };
+    println(quarter(8), quarter(6), f(3));
+    let r = try { while true { half(1)?; } R::Ok(1) };
+    match r { R::Err(_) => 1, R::Ok(_) => 2 }
+}
+"#);
+        assert_eq!( result, Ok(Value::Int(1)) );
+        assert_eq!( output, "R::Ok(2) R::Err(\"odd\") R::Err(\"odd\")\n" );
+
+        let (result, _) = run_src("fun main() { 5? }");
+        assert_eq!( result, Err(RuntimeError::TypeMismatch { expected: "Ok or Err".to_string(), found: "Int".to_string() }) );
+    }
+
+    #[test]
+    fn should_report_ambiguous_trait_methods() {
+        let (result, _) = run_src(r#"
+trait A { fun name(self : Self) -> String; }
+trait B { fun name(self : Self) -> String; }
+struct S { id : Int }
+impl A for S { fun name(self : Self) -> String { "a" } }
+impl B for S { fun name(self : Self) -> String { "b" } }
+fun main() { let s = S { id : 1 }; s.name() }
+"#);
+        assert!( matches!( result, Err(RuntimeError::AmbiguousMethod { .. }) ), "{:?}", result );
+    }
+}
@@ -0,0 +1,13 @@
+
+pub mod value;
+pub mod runtime_error;
+pub mod ops;
+pub mod dispatch;
+pub mod sandbox;
+pub mod gc;
+pub mod ffi;
+pub mod consts;
+pub mod interpreter;
+
+#[cfg(test)]
+pub mod conformance;
@@ -0,0 +1,645 @@
+
+use std::io::Write;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::parsing::ast::{BinOp, Meta, UnaryOp};
+use super::value::*;
+use super::runtime_error::{Failure, RuntimeError};
+use super::sandbox::{self, Capabilities, Capability};
+
+/* Operations on values which do not depend on how the program is being run.
+   Both the tree walking interpreter and the virtual machine use these so that
+   they agree on the meaning of every operator and builtin.
+*/
+
+pub const BUILTIN_FUNS : &[&str] = &["print", "println", "read_file", "write_file", "now", "env"];
+
+pub fn number(literal : &str) -> Result<Value, RuntimeError> {
+    let invalid = || RuntimeError::InvalidNumber(literal.to_string());
+    if literal.contains(['.', 'e', 'E']) {
+        literal.parse::<f64>().map(Value::Float).map_err(|_| invalid())
+    }
+    else {
+        literal.parse::<i64>().map(Value::Int).map_err(|_| invalid())
+    }
+}
+
+pub fn expect_bool(value : &Value) -> Result<bool, RuntimeError> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        v => Err(RuntimeError::TypeMismatch { expected: "Bool".to_string(), found: v.type_name() }),
+    }
+}
+
+pub fn unary(op : UnaryOp, value : Value) -> Result<Value, RuntimeError> {
+    match (op, value) {
+        (UnaryOp::Neg, Value::Int(i)) => i.checked_neg().map(Value::Int).ok_or(RuntimeError::Overflow),
+        (UnaryOp::Neg, Value::Float(x)) => Ok(Value::Float(-x)),
+        (UnaryOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+        (UnaryOp::Neg, v) => Err(RuntimeError::TypeMismatch { expected: "number".to_string(), found: v.type_name() }),
+        (UnaryOp::Not, v) => Err(RuntimeError::TypeMismatch { expected: "Bool".to_string(), found: v.type_name() }),
+    }
+}
+
+fn op_name(op : BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+        BinOp::Rem => "%",
+        BinOp::Eq => "==",
+        BinOp::NotEq => "!=",
+        BinOp::Less => "<",
+        BinOp::LessEq => "<=",
+        BinOp::Greater => ">",
+        BinOp::GreaterEq => ">=",
+        BinOp::And => "&&",
+        BinOp::Or => "||",
+    }
+}
+
+fn int_op(op : BinOp, a : i64, b : i64) -> Result<Value, RuntimeError> {
+    let result = match op {
+        BinOp::Add => a.checked_add(b),
+        BinOp::Sub => a.checked_sub(b),
+        BinOp::Mul => a.checked_mul(b),
+        BinOp::Div | BinOp::Rem if b == 0 => return Err(RuntimeError::DivisionByZero),
+        BinOp::Div => a.checked_div(b),
+        BinOp::Rem => a.checked_rem(b),
+        BinOp::Less => return Ok(Value::Bool(a < b)),
+        BinOp::LessEq => return Ok(Value::Bool(a <= b)),
+        BinOp::Greater => return Ok(Value::Bool(a > b)),
+        BinOp::GreaterEq => return Ok(Value::Bool(a >= b)),
+        _ => unreachable!(),
+    };
+    result.map(Value::Int).ok_or(RuntimeError::Overflow)
+}
+
+fn float_op(op : BinOp, a : f64, b : f64) -> Value {
+    match op {
+        BinOp::Add => Value::Float(a + b),
+        BinOp::Sub => Value::Float(a - b),
+        BinOp::Mul => Value::Float(a * b),
+        BinOp::Div => Value::Float(a / b),
+        BinOp::Rem => Value::Float(a % b),
+        BinOp::Less => Value::Bool(a < b),
+        BinOp::LessEq => Value::Bool(a <= b),
+        BinOp::Greater => Value::Bool(a > b),
+        BinOp::GreaterEq => Value::Bool(a >= b),
+        _ => unreachable!(),
+    }
+}
+
+/// Applies a binary operator to evaluated operands.  `&&` and `||` are only
+/// handled here for already evaluated operands; callers short circuit them.
+pub fn binary(op : BinOp, left : Value, right : Value) -> Result<Value, RuntimeError> {
+    match (op, &left, &right) {
+        (BinOp::Eq, l, r) => Ok(Value::Bool(l == r)),
+        (BinOp::NotEq, l, r) => Ok(Value::Bool(l != r)),
+        (BinOp::And, Value::Bool(a), Value::Bool(b)) => Ok(Value::Bool(*a && *b)),
+        (BinOp::Or, Value::Bool(a), Value::Bool(b)) => Ok(Value::Bool(*a || *b)),
+        (BinOp::And | BinOp::Or, _, _) => invalid(op, &left, &right),
+        (_, Value::Int(a), Value::Int(b)) => int_op(op, *a, *b),
+        (_, Value::Float(a), Value::Float(b)) => Ok(float_op(op, *a, *b)),
+        (_, Value::Int(a), Value::Float(b)) => Ok(float_op(op, *a as f64, *b)),
+        (_, Value::Float(a), Value::Int(b)) => Ok(float_op(op, *a, *b as f64)),
+        (BinOp::Add, Value::String(a), Value::String(b)) => Ok(Value::string(&format!("{}{}", a, b))),
+        (BinOp::Add, Value::List(a), Value::List(b)) => {
+            let mut values = a.borrow().clone();
+            values.extend(b.borrow().iter().cloned());
+            Ok(Value::list(values))
+        },
+        (BinOp::Less, Value::String(a), Value::String(b)) => Ok(Value::Bool(a < b)),
+        (BinOp::LessEq, Value::String(a), Value::String(b)) => Ok(Value::Bool(a <= b)),
+        (BinOp::Greater, Value::String(a), Value::String(b)) => Ok(Value::Bool(a > b)),
+        (BinOp::GreaterEq, Value::String(a), Value::String(b)) => Ok(Value::Bool(a >= b)),
+        _ => invalid(op, &left, &right),
+    }
+}
+
+fn invalid(op : BinOp, left : &Value, right : &Value) -> Result<Value, RuntimeError> {
+    Err(RuntimeError::InvalidOperands { op: op_name(op).to_string(), left: left.type_name(), right: right.type_name() })
+}
+
+fn position(index : &Value, length : usize) -> Result<usize, RuntimeError> {
+    match index {
+        Value::Int(i) if *i >= 0 && (*i as usize) < length => Ok(*i as usize),
+        Value::Int(i) => Err(RuntimeError::IndexOutOfBounds { index: *i, length }),
+        v => Err(RuntimeError::TypeMismatch { expected: "Int".to_string(), found: v.type_name() }),
+    }
+}
+
+fn bound(value : Value) -> Result<i64, RuntimeError> {
+    match value {
+        Value::Int(i) => Ok(i),
+        v => Err(RuntimeError::TypeMismatch { expected: "Int".to_string(), found: v.type_name() }),
+    }
+}
+
+/// Builds the range `start..end`, or `start..=end` when it is inclusive.
+pub fn range(start : Option<Value>, end : Option<Value>, inclusive : bool) -> Result<Value, RuntimeError> {
+    Ok(Value::Range(Rc::new(Range { start: start.map(bound).transpose()?, end: end.map(bound).transpose()?, inclusive })))
+}
+
+/// Where a range slices a sequence of `length` items, checking that both ends are within it.
+fn slice_bounds(range : &Range, length : usize) -> Result<(usize, usize), RuntimeError> {
+    let out_of_bounds = |index| RuntimeError::IndexOutOfBounds { index, length };
+    let start = range.start.unwrap_or(0);
+    let end = match range.end {
+        Some(end) if range.inclusive => end.checked_add(1).ok_or_else(|| out_of_bounds(end))?,
+        Some(end) => end,
+        None => length as i64,
+    };
+    if start < 0 || start as usize > length {
+        return Err(out_of_bounds(start));
+    }
+    if end < 0 || end as usize > length {
+        return Err(out_of_bounds(range.end.unwrap_or(end)));
+    }
+    if start > end {
+        return Err(RuntimeError::InvalidSlice { start, end });
+    }
+    Ok((start as usize, end as usize))
+}
+
+/// Whether a number is within the bounds of a range pattern.
+pub fn in_range(value : &Value, start : Option<&Value>, end : Option<&Value>, inclusive : bool) -> bool {
+    let less = |a : &Value, b : &Value| matches!( binary(BinOp::Less, a.clone(), b.clone()), Ok(Value::Bool(true)) );
+    matches!( value, Value::Int(_) | Value::Float(_) )
+        && !start.is_some_and(|start| less(value, start))
+        && end.is_none_or(|end| if inclusive { !less(end, value) } else { less(value, end) })
+}
+
+fn key_not_found(key : &Value) -> RuntimeError {
+    RuntimeError::KeyNotFound { key: show(key) }
+}
+
+pub fn index(value : &Value, index : &Value) -> Result<Value, RuntimeError> {
+    match (value, index) {
+        (Value::List(values), Value::Range(range)) => {
+            let values = values.borrow();
+            let (start, end) = slice_bounds(range, values.len())?;
+            return Ok(Value::list(values[start..end].to_vec()));
+        },
+        (Value::String(s), Value::Range(range)) => {
+            let chars = s.chars().collect::<Vec<_>>();
+            let (start, end) = slice_bounds(range, chars.len())?;
+            return Ok(Value::string(&chars[start..end].iter().collect::<String>()));
+        },
+        _ => (),
+    }
+    match value {
+        Value::List(values) => {
+            let values = values.borrow();
+            Ok(values[position(index, values.len())?].clone())
+        },
+        Value::Tuple(values) => Ok(values[position(index, values.len())?].clone()),
+        Value::String(s) => {
+            let chars = s.chars().collect::<Vec<_>>();
+            Ok(Value::string(&chars[position(index, chars.len())?].to_string()))
+        },
+        Value::Dict(pairs) => pairs.borrow()
+                                   .iter()
+                                   .find(|(k, _)| k == index)
+                                   .map(|(_, v)| v.clone())
+                                   .ok_or_else(|| key_not_found(index)),
+        v => Err(RuntimeError::TypeMismatch { expected: "List, Dict, Tuple or String".to_string(), found: v.type_name() }),
+    }
+}
+
+pub fn set_index(target : &Value, index : Value, value : Value) -> Result<(), RuntimeError> {
+    match target {
+        Value::List(values) => {
+            let mut values = values.borrow_mut();
+            let i = position(&index, values.len())?;
+            values[i] = value;
+            Ok(())
+        },
+        Value::Dict(pairs) => {
+            insert(&mut pairs.borrow_mut(), index, value);
+            Ok(())
+        },
+        v => Err(RuntimeError::TypeMismatch { expected: "List or Dict".to_string(), found: v.type_name() }),
+    }
+}
+
+pub fn insert(pairs : &mut Vec<(Value, Value)>, key : Value, value : Value) -> Option<Value> {
+    match pairs.iter_mut().find(|(k, _)| *k == key) {
+        Some((_, v)) => Some(std::mem::replace(v, value)),
+        None => {
+            sandbox::allocate(2, 0);
+            pairs.push((key, value));
+            None
+        },
+    }
+}
+
+pub fn field(value : &Value, name : &str) -> Result<Value, RuntimeError> {
+    let unknown = || RuntimeError::UnknownField { type_name: value.type_name(), field: name.to_string() };
+    match value {
+        Value::Struct(s) => s.fields.borrow().iter().find(|(n, _)| n == name).map(|(_, v)| v.clone()).ok_or_else(unknown),
+        Value::Enum(e) => match &*e.contents.borrow() {
+            CaseValue::Struct(fields) => fields.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone()).ok_or_else(unknown),
+            _ => Err(unknown()),
+        },
+        _ => Err(unknown()),
+    }
+}
+
+pub fn set_field(target : &Value, name : &str, value : Value) -> Result<(), RuntimeError> {
+    let unknown = || RuntimeError::UnknownField { type_name: target.type_name(), field: name.to_string() };
+    let slot = |fields : &mut Vec<(String, Value)>| -> Result<(), RuntimeError> {
+        match fields.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => {
+                *v = value.clone();
+                Ok(())
+            },
+            None => Err(unknown()),
+        }
+    };
+    match target {
+        Value::Struct(s) => slot(&mut s.fields.borrow_mut()),
+        Value::Enum(e) => match &mut *e.contents.borrow_mut() {
+            CaseValue::Struct(fields) => slot(fields),
+            _ => Err(unknown()),
+        },
+        _ => Err(unknown()),
+    }
+}
+
+/// Looks inside a value for `?`, which must be an `Ok` or `Err` case with one
+/// field.  `Ok(x)` gives `x` and `Err(e)` gives the whole `Err(e)` back as the
+/// value to propagate.
+pub fn propagate(value : Value) -> Result<Result<Value, Value>, RuntimeError> {
+    let mismatch = || RuntimeError::TypeMismatch { expected: "Ok or Err".to_string(), found: value.type_name() };
+    let ok = match &value {
+        Value::Enum(e) => match (e.case_name.as_str(), &*e.contents.borrow()) {
+            ("Ok", CaseValue::Tuple(items)) if items.len() == 1 => Some(items[0].clone()),
+            ("Err", CaseValue::Tuple(items)) if items.len() == 1 => None,
+            _ => return Err(mismatch()),
+        },
+        _ => return Err(mismatch()),
+    };
+    match ok {
+        Some(v) => Ok(Ok(v)),
+        None => Ok(Err(value)),
+    }
+}
+
+/// Checks the sides of an asserted `==`, giving them back shown when they
+/// differ.
+pub fn assert_eq(left : Value, right : Value) -> Result<Option<(String, String)>, RuntimeError> {
+    match binary(BinOp::Eq, left.clone(), right.clone())? {
+        Value::Bool(true) => Ok(None),
+        _ => Ok(Some((show(&left), show(&right)))),
+    }
+}
+
+/// The error for a failed `assert`, or for a `panic` when there is no
+/// `expression`.  The trace starts empty and is filled in on the way out.
+pub fn failure(expression : Option<String>, message : Option<&Value>, sides : Option<(String, String)>, at : Meta) -> RuntimeError {
+    RuntimeError::Failed(Box::new(Failure { expression
+                                          , message: message.map(|m| m.to_string())
+                                          , sides
+                                          , at
+                                          , trace: vec![]
+                                          }))
+}
+
+fn arity(name : &str, expected : usize, args : &[Value]) -> Result<(), RuntimeError> {
+    if args.len() == expected {
+        Ok(())
+    }
+    else {
+        Err(RuntimeError::ArityMismatch { name: name.to_string(), expected, found: args.len() })
+    }
+}
+
+/// The iterator `foreach` steps through for a value:  the items of a list,
+/// the characters of a string, the keys of a dictionary, the integers of a
+/// range or what the `next` method of a value whose type the checker found
+/// implements `Iterator` gives.
+pub fn iterate(value : Value) -> Result<Value, RuntimeError> {
+    let iteration = match &value {
+        Value::Iterator(_) => return Ok(value),
+        Value::Range(range) => match **range {
+            Range { start: None, .. } => return Err(RuntimeError::OpenRange),
+            Range { start: Some(next), end: None, .. } => Iteration::Count { next, last: None },
+            Range { start: Some(next), end: Some(end), inclusive: true } => Iteration::Count { next, last: Some(end) },
+            Range { start: Some(next), end: Some(end), inclusive: false } =>
+                end.checked_sub(1).map_or(Iteration::Finished, |last| Iteration::Count { next, last: Some(last) }),
+        },
+        Value::List(list) => Iteration::Items { list: list.clone(), index: 0 },
+        Value::String(text) => Iteration::Chars { text: text.clone(), offset: 0 },
+        Value::Dict(dict) => Iteration::Keys { dict: dict.clone(), index: 0 },
+        Value::Struct(_) | Value::Enum(_) => Iteration::Next(value),
+        v => return Err(RuntimeError::NotIterable { type_name: v.type_name() }),
+    };
+    Ok(Value::iterator(iteration))
+}
+
+/// Takes the next item from an iterator.  Builtin iterations are stepped here
+/// and generators and `next` methods by `resume`, which gives back the state
+/// to continue from.  An iterator which fails is finished.
+pub fn advance( iterator : &RefCell<Iteration>
+              , resume : impl FnOnce(Iteration) -> Result<(Iteration, Option<Value>), RuntimeError>
+              ) -> Result<Option<Value>, RuntimeError> {
+    let result = match iterator.replace(Iteration::Running) {
+        Iteration::Items { list, index } => {
+            let item = list.borrow().get(index).cloned();
+            Ok((Iteration::Items { list, index: index + 1 }, item))
+        },
+        Iteration::Chars { text, offset } => {
+            let item = text[offset..].chars().next();
+            let offset = offset + item.map_or(0, char::len_utf8);
+            Ok((Iteration::Chars { text, offset }, item.map(|c| Value::string(c.encode_utf8(&mut [0; 4])))))
+        },
+        Iteration::Keys { dict, index } => {
+            let item = dict.borrow().get(index).map(|(k, _)| k.clone());
+            Ok((Iteration::Keys { dict, index: index + 1 }, item))
+        },
+        Iteration::Count { next, last } => {
+            let item = last.is_none_or(|last| next <= last).then_some(Value::Int(next));
+            // after `i64::MAX` there is nothing left to count
+            let state = next.checked_add(1).map_or(Iteration::Finished, |next| Iteration::Count { next, last });
+            Ok((state, item))
+        },
+        Iteration::Running => Err(RuntimeError::GeneratorRunning),
+        Iteration::Finished => Ok((Iteration::Finished, None)),
+        state => resume(state),
+    };
+    match result {
+        Ok((state, item)) => {
+            iterator.replace(if item.is_some() { state } else { Iteration::Finished });
+            Ok(item)
+        },
+        Err(e) => {
+            iterator.replace(Iteration::Finished);
+            Err(e)
+        },
+    }
+}
+
+/// The item in what a `next` method returned.
+pub fn option(value : Value) -> Result<Option<Value>, RuntimeError> {
+    if let Value::Enum(e) = &value {
+        match (e.case_name.as_str(), &*e.contents.borrow()) {
+            ("Some", CaseValue::Tuple(items)) if items.len() == 1 => return Ok(Some(items[0].clone())),
+            ("None", CaseValue::Empty) => return Ok(None),
+            _ => (),
+        }
+    }
+    Err(RuntimeError::TypeMismatch { expected: "Some or None".to_string(), found: value.type_name() })
+}
+
+/// What `next` called on an iterator returns.
+pub fn to_option(item : Option<Value>) -> Value {
+    match item {
+        Some(item) => Value::case("Option", "Some", CaseValue::Tuple(vec![item])),
+        None => Value::case("Option", "None", CaseValue::Empty),
+    }
+}
+
+/// Methods every value of a builtin type has.  Returns `None` when there is no
+/// such method so that callers can report their own error.
+pub fn native_method(receiver : &Value, name : &str, args : &[Value]) -> Option<Result<Value, RuntimeError>> {
+    let result = match (receiver, name) {
+        (_, "to_string") => arity(name, 0, args).map(|_| Value::string(&receiver.to_string())),
+        (Value::List(values), "len") => arity(name, 0, args).map(|_| Value::Int(values.borrow().len() as i64)),
+        (Value::List(values), "push") => arity(name, 1, args).map(|_| {
+            sandbox::allocate(1, 0);
+            values.borrow_mut().push(args[0].clone());
+            Value::Unit
+        }),
+        (Value::List(values), "pop") => arity(name, 0, args).and_then(|_| {
+            let length = values.borrow().len();
+            let last = values.borrow_mut().pop().ok_or(RuntimeError::IndexOutOfBounds { index: -1, length })?;
+            sandbox::release(1, 0);
+            Ok(last)
+        }),
+        (Value::Dict(pairs), "len") => arity(name, 0, args).map(|_| Value::Int(pairs.borrow().len() as i64)),
+        (Value::Dict(pairs), "insert") => arity(name, 2, args).map(|_| {
+            insert(&mut pairs.borrow_mut(), args[0].clone(), args[1].clone());
+            Value::Unit
+        }),
+        (Value::Dict(pairs), "contains") => arity(name, 1, args).map(|_| Value::Bool(pairs.borrow().iter().any(|(k, _)| *k == args[0]))),
+        (Value::Dict(pairs), "remove") => arity(name, 1, args).and_then(|_| {
+            let mut pairs = pairs.borrow_mut();
+            match pairs.iter().position(|(k, _)| *k == args[0]) {
+                Some(i) => {
+                    sandbox::release(2, 0);
+                    Ok(pairs.remove(i).1)
+                },
+                None => Err(key_not_found(&args[0])),
+            }
+        }),
+        (Value::Dict(pairs), "keys") => arity(name, 0, args).map(|_| Value::list(pairs.borrow().iter().map(|(k, _)| k.clone()).collect())),
+        (Value::List(values), "contains") => arity(name, 1, args).map(|_| Value::Bool(values.borrow().contains(&args[0]))),
+        (Value::List(values), "reverse") => arity(name, 0, args).map(|_| {
+            values.borrow_mut().reverse();
+            Value::Unit
+        }),
+        (Value::List(values), "sort") => arity(name, 0, args).and_then(|_| sort(&mut values.borrow_mut())).map(|_| Value::Unit),
+        (Value::List(values), "insert") => arity(name, 2, args).and_then(|_| {
+            let mut values = values.borrow_mut();
+            let length = values.len();
+            let at = if args[0] == Value::Int(length as i64) { length } else { position(&args[0], length)? };
+            sandbox::allocate(1, 0);
+            values.insert(at, args[1].clone());
+            Ok(Value::Unit)
+        }),
+        (Value::List(values), "remove") => arity(name, 1, args).and_then(|_| {
+            let mut values = values.borrow_mut();
+            let at = position(&args[0], values.len())?;
+            sandbox::release(1, 0);
+            Ok(values.remove(at))
+        }),
+        (Value::Dict(pairs), "values") => arity(name, 0, args).map(|_| Value::list(pairs.borrow().iter().map(|(_, v)| v.clone()).collect())),
+        (Value::String(s), "len") => arity(name, 0, args).map(|_| Value::Int(s.chars().count() as i64)),
+        (Value::String(s), "chars") => arity(name, 0, args).map(|_| Value::list(chars(s))),
+        (Value::String(s), "split") => arity(name, 1, args).and_then(|_| {
+            let separator = text(&args[0])?;
+            if separator.is_empty() {
+                return Ok(Value::list(chars(s)));
+            }
+            Ok(Value::list(s.split(separator).map(Value::string).collect()))
+        }),
+        (Value::String(s), "trim") => arity(name, 0, args).map(|_| Value::string(s.trim_matches(|c : char| c.is_ascii_whitespace()))),
+        (Value::String(s), "contains") => arity(name, 1, args).and_then(|_| Ok(Value::Bool(s.contains(text(&args[0])?)))),
+        (Value::String(s), "starts_with") => arity(name, 1, args).and_then(|_| Ok(Value::Bool(s.starts_with(text(&args[0])?)))),
+        (Value::String(s), "ends_with") => arity(name, 1, args).and_then(|_| Ok(Value::Bool(s.ends_with(text(&args[0])?)))),
+        (Value::String(s), "to_upper") => arity(name, 0, args).map(|_| Value::string(&s.to_ascii_uppercase())),
+        (Value::String(s), "to_lower") => arity(name, 0, args).map(|_| Value::string(&s.to_ascii_lowercase())),
+        (Value::String(s), "parse_int") => arity(name, 0, args).map(|_| to_option(s.parse::<i64>().ok().map(Value::Int))),
+        (Value::Int(i), "abs") => arity(name, 0, args).and_then(|_| i.checked_abs().map(Value::Int).ok_or(RuntimeError::Overflow)),
+        (Value::Int(i), "to_float") => arity(name, 0, args).map(|_| Value::Float(*i as f64)),
+        (Value::Float(x), "abs") => arity(name, 0, args).map(|_| Value::Float(x.abs())),
+        (Value::Float(x), "sqrt") => arity(name, 0, args).map(|_| Value::Float(x.sqrt())),
+        (Value::Float(x), "floor") => arity(name, 0, args).map(|_| Value::Float(x.floor())),
+        (Value::Float(x), "ceil") => arity(name, 0, args).map(|_| Value::Float(x.ceil())),
+        (Value::Float(x), "to_int") => arity(name, 0, args).and_then(|_| {
+            // rounds toward zero, and `i64::MAX as f64` is already out of range
+            if x.is_nan() || *x >= i64::MAX as f64 || *x < i64::MIN as f64 {
+                return Err(RuntimeError::Overflow);
+            }
+            Ok(Value::Int(*x as i64))
+        }),
+        (Value::Tuple(values), "len") => arity(name, 0, args).map(|_| Value::Int(values.len() as i64)),
+        _ => return None,
+    };
+    Some(result)
+}
+
+fn text(value : &Value) -> Result<&str, RuntimeError> {
+    match value {
+        Value::String(s) => Ok(s),
+        v => Err(RuntimeError::TypeMismatch { expected: "String".to_string(), found: v.type_name() }),
+    }
+}
+
+fn chars(s : &str) -> Vec<Value> {
+    s.chars().map(|c| Value::string(c.encode_utf8(&mut [0; 4]))).collect()
+}
+
+/// Sorts in place with `<`, keeping equal items in order.  Items which `<`
+/// cannot compare are an error, and the list is left as it was.
+fn sort(values : &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let mut sorted : Vec<Value> = Vec::with_capacity(values.len());
+    for value in values.iter() {
+        // an insertion sort, which the C runtime can make exactly the same comparisons as
+        let mut at = sorted.len();
+        while at > 0 && expect_bool(&binary(BinOp::Less, value.clone(), sorted[at - 1].clone())?)? {
+            at -= 1;
+        }
+        sorted.insert(at, value.clone());
+    }
+    *values = sorted;
+    Ok(())
+}
+
+/// Whether values of a builtin type have a native method, for callers which
+/// only know the type.
+pub fn has_native_method(type_name : &str, name : &str) -> bool {
+    matches!( (type_name, name), (_, "to_string")
+                               | ("List", "len") | ("List", "push") | ("List", "pop") | ("List", "contains") | ("List", "reverse")
+                               | ("List", "sort") | ("List", "insert") | ("List", "remove")
+                               | ("Dict", "len") | ("Dict", "insert") | ("Dict", "contains") | ("Dict", "remove") | ("Dict", "keys") | ("Dict", "values")
+                               | ("String", "len") | ("String", "chars") | ("String", "split") | ("String", "trim") | ("String", "contains")
+                               | ("String", "starts_with") | ("String", "ends_with") | ("String", "to_upper") | ("String", "to_lower")
+                               | ("String", "parse_int")
+                               | ("Int", "abs") | ("Int", "to_float")
+                               | ("Float", "abs") | ("Float", "sqrt") | ("Float", "floor") | ("Float", "ceil") | ("Float", "to_int")
+                               | ("Tuple", "len")
+                               | ("Iterator", "next") )
+}
+
+/// Whether a native method changes the value it is called on, which only
+/// a mutable binding may do.
+pub fn native_method_mutates(type_name : &str, name : &str) -> bool {
+    matches!( (type_name, name), ("List", "push") | ("List", "pop") | ("List", "reverse") | ("List", "sort") | ("List", "insert") | ("List", "remove")
+                               | ("Dict", "insert") | ("Dict", "remove") )
+}
+
+/// The builtin types with native methods.
+pub const NATIVE_TYPES : &[&str] = &["List", "Dict", "String", "Int", "Float", "Tuple", "Iterator"];
+
+/// Free functions which are always in scope unless a module defines a function with the same name.
+/// The builtin functions, where the ones reaching outside of the program need
+/// their capability.
+pub fn builtin(name : &str, args : &[Value], out : &mut dyn Write, capabilities : &Capabilities) -> Option<Result<Value, RuntimeError>> {
+    let text = || args.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(" ");
+    let io = |e : std::io::Error| RuntimeError::Io(e.to_string());
+    let result = match name {
+        "print" => write!(out, "{}", text()).map(|_| Value::Unit).map_err(io),
+        "println" => writeln!(out, "{}", text()).map(|_| Value::Unit).map_err(io),
+        "read_file" => arity(name, 1, args).and_then(|_| {
+            let path = capabilities.file(self::text(&args[0])?)?;
+            std::fs::read_to_string(path).map(|s| Value::string(&s)).map_err(io)
+        }),
+        "write_file" => arity(name, 2, args).and_then(|_| {
+            let path = capabilities.file(self::text(&args[0])?)?;
+            std::fs::write(path, self::text(&args[1])?).map(|_| Value::Unit).map_err(io)
+        }),
+        "now" => arity(name, 0, args).and_then(|_| {
+            capabilities.check(Capability::Clock)?;
+            let since = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_err(|e| RuntimeError::Io(e.to_string()))?;
+            Ok(Value::Float(since.as_secs_f64()))
+        }),
+        "env" => arity(name, 1, args).and_then(|_| {
+            capabilities.check(Capability::Env)?;
+            Ok(to_option(std::env::var(self::text(&args[0])?).ok().map(|v| Value::string(&v))))
+        }),
+        _ => return None,
+    };
+    Some(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_parse_number_literals() {
+        assert_eq!( number("12"), Ok(Value::Int(12)) );
+        assert_eq!( number("-1.5"), Ok(Value::Float(-1.5)) );
+        assert_eq!( number("1e3"), Ok(Value::Float(1000.0)) );
+        assert!( matches!( number("99999999999999999999"), Err(RuntimeError::InvalidNumber(_)) ) );
+    }
+
+    #[test]
+    fn should_apply_arithmetic() {
+        assert_eq!( binary(BinOp::Add, Value::Int(1), Value::Int(2)), Ok(Value::Int(3)) );
+        assert_eq!( binary(BinOp::Div, Value::Int(7), Value::Int(2)), Ok(Value::Int(3)) );
+        assert_eq!( binary(BinOp::Mul, Value::Int(2), Value::Float(1.5)), Ok(Value::Float(3.0)) );
+        assert_eq!( binary(BinOp::Add, Value::string("a"), Value::string("b")), Ok(Value::string("ab")) );
+        assert_eq!( binary(BinOp::Rem, Value::Int(1), Value::Int(0)), Err(RuntimeError::DivisionByZero) );
+        assert_eq!( binary(BinOp::Add, Value::Int(i64::MAX), Value::Int(1)), Err(RuntimeError::Overflow) );
+        assert!( matches!( binary(BinOp::Sub, Value::string("a"), Value::Int(1)), Err(RuntimeError::InvalidOperands { .. }) ) );
+    }
+
+    #[test]
+    fn should_report_index_and_length_when_out_of_bounds() {
+        let list = Value::list(vec![Value::Int(1), Value::Int(2)]);
+        assert_eq!( index(&list, &Value::Int(1)), Ok(Value::Int(2)) );
+        assert_eq!( index(&list, &Value::Int(2)), Err(RuntimeError::IndexOutOfBounds { index: 2, length: 2 }) );
+        assert_eq!( index(&Value::dict(vec![]), &Value::string("k")), Err(RuntimeError::KeyNotFound { key: "\"k\"".to_string() }) );
+    }
+
+    #[test]
+    fn should_slice_lists_and_strings_with_ranges() {
+        let slice = |start : Option<i64>, end : Option<i64>, inclusive| range(start.map(Value::Int), end.map(Value::Int), inclusive).unwrap();
+        let list = Value::list(vec![Value::Int(1), Value::Int(2), Value::Int(3)]);
+        let text = Value::string("héllo");
+
+        assert_eq!( index(&list, &slice(Some(1), None, false)), Ok(Value::list(vec![Value::Int(2), Value::Int(3)])) );
+        assert_eq!( index(&list, &slice(None, Some(0), true)), Ok(Value::list(vec![Value::Int(1)])) );
+        assert_eq!( index(&list, &slice(Some(3), Some(3), false)), Ok(Value::list(vec![])) );
+        assert_eq!( index(&text, &slice(Some(1), Some(3), false)), Ok(Value::string("él")) );
+        assert_eq!( index(&text, &slice(None, None, false)), Ok(text.clone()) );
+
+        assert_eq!( index(&list, &slice(Some(1), Some(4), false)), Err(RuntimeError::IndexOutOfBounds { index: 4, length: 3 }) );
+        assert_eq!( index(&list, &slice(None, Some(3), true)), Err(RuntimeError::IndexOutOfBounds { index: 3, length: 3 }) );
+        assert_eq!( index(&text, &slice(Some(-1), None, false)), Err(RuntimeError::IndexOutOfBounds { index: -1, length: 5 }) );
+        assert_eq!( index(&list, &slice(Some(2), Some(1), false)), Err(RuntimeError::InvalidSlice { start: 2, end: 1 }) );
+        assert!( matches!( range(Some(Value::Float(1.0)), None, false), Err(RuntimeError::TypeMismatch { .. }) ) );
+    }
+
+    #[test]
+    fn should_count_through_ranges() {
+        let items = |value : Value| {
+            let iterator = match &iterate(value).unwrap() {
+                Value::Iterator(it) => it.clone(),
+                v => panic!( "Expected Iterator but found {:?}", v ),
+            };
+            std::iter::from_fn(|| advance(&iterator, |_| unreachable!()).unwrap()).take(4).collect::<Vec<_>>()
+        };
+
+        assert_eq!( items(range(Some(Value::Int(1)), Some(Value::Int(3)), false).unwrap()), vec![Value::Int(1), Value::Int(2)] );
+        assert_eq!( items(range(Some(Value::Int(1)), Some(Value::Int(3)), true).unwrap()).len(), 3 );
+        assert_eq!( items(range(Some(Value::Int(5)), None, false).unwrap()).len(), 4 );
+        assert_eq!( items(range(Some(Value::Int(i64::MAX)), None, false).unwrap()), vec![Value::Int(i64::MAX)] );
+        assert_eq!( iterate(range(None, Some(Value::Int(3)), false).unwrap()), Err(RuntimeError::OpenRange) );
+    }
+}
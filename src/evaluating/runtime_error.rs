@@ -0,0 +1,142 @@
+
+use std::fmt;
+
+use crate::parsing::ast::Meta;
+use super::sandbox::{Limit, Capability};
+
+#[derive(Debug, PartialEq)]
+pub enum RuntimeError {
+    NoMain,
+    InvalidNumber(String),
+    UnknownVariable(String),
+    UnknownFunction(String),
+    UnknownCase { name : String },
+    UnknownField { type_name : String, field : String },
+    MissingField { type_name : String, field : String },
+    NoMethod { type_name : String, name : String },
+    AmbiguousMethod { type_name : String, name : String, traits : Vec<String> },
+    ArityMismatch { name : String, expected : usize, found : usize },
+    TypeMismatch { expected : String, found : String },
+    InvalidOperands { op : String, left : String, right : String },
+    NotCallable { type_name : String },
+    NotIterable { type_name : String },
+    OpenRange,
+    GeneratorRunning,
+    MisplacedYield,
+    DivisionByZero,
+    Overflow,
+    IndexOutOfBounds { index : i64, length : usize },
+    InvalidSlice { start : i64, end : i64 },
+    KeyNotFound { key : String },
+    NoMatchingArm { value : String },
+    InvalidAssignTarget,
+    BreakOutsideLoop,
+    Io(String),
+    /// The message of an error a native function gave.
+    Native(String),
+    /// A run went over one of the limits of its sandbox.
+    LimitExceeded(Limit),
+    /// A run used something its sandbox does not grant.
+    NotPermitted(Capability),
+    /// A native library or one of its functions could not be loaded or called.
+    Extern(String),
+    Failed(Box<Failure>),
+}
+
+/// A failed `assert` or a `panic`.
+#[derive(Debug, PartialEq)]
+pub struct Failure {
+    /// The asserted condition as source text, which a panic does not have.
+    pub expression : Option<String>,
+    pub message : Option<String>,
+    /// Both sides of a failed `==`.
+    pub sides : Option<(String, String)>,
+    pub at : Meta,
+    /// The functions the failure left, innermost first.
+    pub trace : Vec<Frame>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    pub function : String,
+    /// Where the next function out called this one.
+    pub called_at : Option<Meta>,
+}
+
+impl Failure {
+    /// Each function in the trace with where it was when the failure left it.
+    pub fn frames(&self) -> impl Iterator<Item = (&str, Option<Meta>)> + '_ {
+        let sites = std::iter::once(Some(self.at)).chain(self.trace.iter().map(|f| f.called_at));
+        self.trace.iter().zip(sites).map(|(frame, site)| (frame.function.as_str(), site))
+    }
+}
+
+impl RuntimeError {
+    /// Records that a failure left the function `name`.
+    pub fn leaving(mut self, function : &str) -> RuntimeError {
+        if let RuntimeError::Failed(failure) = &mut self {
+            failure.trace.push(Frame { function: function.to_string(), called_at: None });
+        }
+        self
+    }
+
+    /// Records where the function a failure last left was called from.
+    pub fn called_at(mut self, site : Option<Meta>) -> RuntimeError {
+        if let RuntimeError::Failed(failure) = &mut self {
+            if let Some(frame) = failure.trace.last_mut().filter(|f| f.called_at.is_none()) {
+                frame.called_at = site;
+            }
+        }
+        self
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::NoMain => write!(f, "no `fun main()` to run"),
+            RuntimeError::InvalidNumber(n) => write!(f, "invalid number literal {}", n),
+            RuntimeError::UnknownVariable(name) => write!(f, "unknown variable {}", name),
+            RuntimeError::UnknownFunction(name) => write!(f, "unknown function {}", name),
+            RuntimeError::UnknownCase { name } => write!(f, "unknown enum case {}", name),
+            RuntimeError::UnknownField { type_name, field } => write!(f, "{} has no field {}", type_name, field),
+            RuntimeError::MissingField { type_name, field } => write!(f, "missing field {} when constructing {}", field, type_name),
+            RuntimeError::NoMethod { type_name, name } => write!(f, "{} has no method {}", type_name, name),
+            RuntimeError::AmbiguousMethod { type_name, name, traits } =>
+                write!(f, "method {} of {} is ambiguous between traits {}", name, type_name, traits.join(", ")),
+            RuntimeError::ArityMismatch { name, expected, found } =>
+                write!(f, "{} expects {} arguments but was given {}", name, expected, found),
+            RuntimeError::TypeMismatch { expected, found } => write!(f, "expected {} but found {}", expected, found),
+            RuntimeError::InvalidOperands { op, left, right } => write!(f, "cannot apply {} to {} and {}", op, left, right),
+            RuntimeError::NotCallable { type_name } => write!(f, "{} is not callable", type_name),
+            RuntimeError::NotIterable { type_name } => write!(f, "{} is not iterable", type_name),
+            RuntimeError::OpenRange => write!(f, "a range without a start cannot be iterated"),
+            RuntimeError::GeneratorRunning => write!(f, "a generator cannot be resumed while it is running"),
+            RuntimeError::MisplacedYield => write!(f, "yield outside of a statement of a generator"),
+            RuntimeError::DivisionByZero => write!(f, "division by zero"),
+            RuntimeError::Overflow => write!(f, "integer overflow"),
+            RuntimeError::IndexOutOfBounds { index, length } => write!(f, "index {} is out of bounds for length {}", index, length),
+            RuntimeError::InvalidSlice { start, end } => write!(f, "slice starts at {} but ends at {}", start, end),
+            RuntimeError::KeyNotFound { key } => write!(f, "key {} not found", key),
+            RuntimeError::NoMatchingArm { value } => write!(f, "no match arm matches {}", value),
+            RuntimeError::InvalidAssignTarget => write!(f, "invalid assignment target"),
+            RuntimeError::BreakOutsideLoop => write!(f, "break or continue outside of a loop"),
+            RuntimeError::Io(message) => write!(f, "io error: {}", message),
+            RuntimeError::Native(message) => write!(f, "native function failed: {}", message),
+            RuntimeError::LimitExceeded(limit) => write!(f, "limit exceeded: {}", limit),
+            RuntimeError::NotPermitted(capability) => write!(f, "not permitted: {}", capability),
+            RuntimeError::Extern(message) => write!(f, "extern function failed: {}", message),
+            RuntimeError::Failed(failure) => {
+                match (&failure.expression, &failure.message) {
+                    (Some(expression), Some(message)) => write!(f, "assertion failed: {}: {}", expression, message)?,
+                    (Some(expression), None) => write!(f, "assertion failed: {}", expression)?,
+                    (None, message) => write!(f, "panic: {}", message.as_deref().unwrap_or(""))?,
+                }
+                match &failure.sides {
+                    Some((left, right)) => write!(f, "\n  left: {}\n  right: {}", left, right),
+                    None => Ok(()),
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use std::cell::Cell;
+use std::fmt;
+use std::mem::size_of;
+use std::path::{Component, Path, PathBuf};
+
+use super::value::Value;
+use super::runtime_error::RuntimeError;
+
+/* Running code which is not trusted.  `Limits` bound how many steps a run
+   takes, how deep its calls go and how much memory it holds, and
+   `Capabilities` are what it may reach outside of itself:  files, the clock,
+   the environment and the native libraries of `extern` functions.  A run has
+   no capabilities and no limits but the default for the stack unless the
+   application running it gives them, and the command line grants every
+   capability.
+
+   A step is an instruction of the virtual machine or an expression of the
+   interpreter, so the same budget lets the interpreter do less.  The allocation
+   limit is on the bytes of the strings, lists, dicts, tuples, structs and enums
+   the run holds at once:  they are counted when they are made or grow and given
+   back when they shrink or are freed, so a run which keeps making values it
+   drops soon after stays within it.  Values are freed, compared and displayed
+   a level at a time, so nesting them however deep cannot overflow the stack.
+
+   The interpreter calls functions on the stack of the thread running it, so
+   besides the depth a run's calls may only take so many bytes of that stack,
+   measured from where its outermost call started.  That keeps deep recursion
+   from overflowing the stack whatever the depth limit is, as long as the
+   thread has `Limits::stack` bytes to spare, which any thread Rust starts has
+   for the default.  The virtual machine keeps its calls in frames of its own
+   and only takes the stack when Rust calls back into the program, for the
+   `next` of an iterator written in the language.
+
+   Going over a limit is a `RuntimeError::LimitExceeded` and using what was not
+   granted is a `RuntimeError::NotPermitted`, which the application gets back
+   like any other runtime error.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Steps(u64),
+    /// In bytes.
+    Allocation(usize),
+    Depth(usize),
+    /// In bytes.
+    Stack(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capability {
+    Files,
+    Clock,
+    Env,
+    Externs,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    pub steps : Option<u64>,
+    /// The bytes of the values a run holds at once.
+    pub allocation : Option<usize>,
+    pub depth : Option<usize>,
+    /// The bytes of its thread's stack which the calls of a run may take,
+    /// `DEFAULT_STACK` when not given.
+    pub stack : Option<usize>,
+}
+
+/// How much stack the calls of a run take at most unless its limits say
+/// otherwise, which leaves room to spare in the 2MB of a thread Rust starts.
+pub const DEFAULT_STACK : usize = 1024 * 1024;
+
+/// How much stack the calls of a trusted run may take, which leaves room to
+/// spare in the 8MB of the main thread on Linux and macOS.
+const MAIN_STACK : usize = 6 * 1024 * 1024;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Files {
+    #[default]
+    Denied,
+    /// Relative paths within the directory, which cannot climb out of it with
+    /// `..` or through links.
+    Within(PathBuf),
+    Everywhere,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Capabilities {
+    /// What `read_file` and `write_file` may use.
+    pub files : Files,
+    /// Whether `now` may read the clock.
+    pub clock : bool,
+    /// Whether `env` may read environment variables.
+    pub env : bool,
+    /// Whether `extern` functions may load and call native libraries.
+    pub externs : bool,
+}
+
+/// The default grants no capabilities but sets no limits either besides the
+/// default stack, so untrusted code run in it can still loop forever or take
+/// all memory.  Give it `Limits` for that.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sandbox {
+    pub limits : Limits,
+    pub capabilities : Capabilities,
+}
+
+impl Capabilities {
+    pub fn all() -> Capabilities {
+        Capabilities { files: Files::Everywhere, clock: true, env: true, externs: true }
+    }
+
+    pub fn check(&self, capability : Capability) -> Result<(), RuntimeError> {
+        let granted = match capability {
+            Capability::Files => self.files != Files::Denied,
+            Capability::Clock => self.clock,
+            Capability::Env => self.env,
+            Capability::Externs => self.externs,
+        };
+        if granted { Ok(()) } else { Err(RuntimeError::NotPermitted(capability)) }
+    }
+
+    /// Where a program may find the file it calls `path`.
+    pub fn file(&self, path : &str) -> Result<PathBuf, RuntimeError> {
+        match &self.files {
+            Files::Denied => Err(RuntimeError::NotPermitted(Capability::Files)),
+            Files::Everywhere => Ok(PathBuf::from(path)),
+            Files::Within(root) => {
+                let denied = || RuntimeError::NotPermitted(Capability::Files);
+                let path = Path::new(path);
+                if !path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+                    return Err(denied());
+                }
+                let root = root.canonicalize().map_err(|_| denied())?;
+                let joined = root.join(path);
+                let resolved = match joined.canonicalize() {
+                    Ok(resolved) => resolved,
+                    // a file yet to be made is in its directory, unless it is a link to somewhere yet to be made
+                    Err(_) if joined.symlink_metadata().is_err() => match (joined.parent().map(Path::canonicalize), joined.file_name()) {
+                        (Some(Ok(directory)), Some(name)) => directory.join(name),
+                        _ => return Err(denied()),
+                    },
+                    Err(_) => return Err(denied()),
+                };
+                if resolved.starts_with(&root) { Ok(resolved) } else { Err(denied()) }
+            },
+        }
+    }
+}
+
+/// How deep the calls of a trusted run may go, which keeps runaway recursion
+/// in the virtual machine, whose calls take no stack, from taking all memory.
+const TRUSTED_DEPTH : usize = 1_000_000;
+
+impl Sandbox {
+    /// Every capability and no limits but the depth and stack the main thread
+    /// has room for, for programs run from the command line.
+    pub fn trusted() -> Sandbox {
+        let limits = Limits { depth: Some(TRUSTED_DEPTH), stack: Some(MAIN_STACK), ..Limits::default() };
+        Sandbox { limits, capabilities: Capabilities::all() }
+    }
+}
+
+thread_local! {
+    static ALLOCATED : Cell<usize> = const { Cell::new(0) };
+}
+
+/// Counts `values` values and `bytes` other bytes as held on this thread.
+pub fn allocate(values : usize, bytes : usize) {
+    let size = values.saturating_mul(size_of::<Value>()).saturating_add(bytes);
+    ALLOCATED.with(|allocated| allocated.set(allocated.get().saturating_add(size)));
+}
+
+/// Counts `values` values and `bytes` other bytes allocated before as freed.
+pub fn release(values : usize, bytes : usize) {
+    let size = values.saturating_mul(size_of::<Value>()).saturating_add(bytes);
+    // freeing a value while the thread goes away finds nothing to count against
+    let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get().saturating_sub(size)));
+}
+
+fn allocated() -> usize {
+    ALLOCATED.with(Cell::get)
+}
+
+/// What a run has used of its limits.
+pub struct Budget {
+    limits : Limits,
+    steps : u64,
+    depth : usize,
+    /// Where on the stack the outermost call running started.
+    stack_base : usize,
+    /// What the thread held before the run started.
+    allocated_before : usize,
+}
+
+impl Budget {
+    pub fn new(limits : Limits) -> Budget {
+        Budget { limits, steps: 0, depth: 0, stack_base: 0, allocated_before: allocated() }
+    }
+
+    pub fn step(&mut self) -> Result<(), RuntimeError> {
+        self.steps += 1;
+        match self.limits {
+            Limits { steps: Some(steps), .. } if self.steps > steps => Err(RuntimeError::LimitExceeded(Limit::Steps(steps))),
+            Limits { allocation: Some(bytes), .. } if allocated().saturating_sub(self.allocated_before) > bytes => Err(RuntimeError::LimitExceeded(Limit::Allocation(bytes))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Enters a call, which `leave` has to match when `enter` succeeds.
+    pub fn enter(&mut self) -> Result<(), RuntimeError> {
+        let here = stack_address();
+        if self.depth == 0 {
+            self.stack_base = here;
+        }
+        let stack = self.limits.stack.unwrap_or(DEFAULT_STACK);
+        match self.limits.depth {
+            Some(depth) if self.depth >= depth => Err(RuntimeError::LimitExceeded(Limit::Depth(depth))),
+            // the stack grows down on every mainstream platform
+            _ if self.stack_base.saturating_sub(here) > stack => Err(RuntimeError::LimitExceeded(Limit::Stack(stack))),
+            _ => {
+                self.depth += 1;
+                Ok(())
+            },
+        }
+    }
+
+    pub fn leave(&mut self) {
+        self.depth -= 1;
+    }
+}
+
+/// Roughly where the stack of this thread ends now.
+#[inline(never)]
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Steps(steps) => write!(f, "{} steps", steps),
+            Limit::Allocation(bytes) => write!(f, "{} bytes of values", bytes),
+            Limit::Depth(depth) => write!(f, "a call depth of {}", depth),
+            Limit::Stack(bytes) => write!(f, "{} bytes of stack", bytes),
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Capability::Files => write!(f, "file access"),
+            Capability::Clock => write!(f, "the clock"),
+            Capability::Env => write!(f, "the environment"),
+            Capability::Externs => write!(f, "native libraries"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+    use crate::evaluating::interpreter::Interpreter;
+    use crate::compiling::{compiler, vm::Vm};
+
+    /// Runs a program with the interpreter and the virtual machine, giving both results.
+    fn run(src : &str, sandbox : &Sandbox) -> (Result<Value, RuntimeError>, Result<Value, RuntimeError>) {
+        let module = parse(src).unwrap();
+        let mut out = vec![];
+        let interpreted = Interpreter::new(&module, &mut out).with_sandbox(sandbox).run_main();
+        let program = compiler::compile(&module).unwrap();
+        let compiled = Vm::new(&program, &mut out).with_sandbox(sandbox).run_main();
+        (interpreted, compiled)
+    }
+
+    fn limited(limits : Limits) -> Sandbox {
+        Sandbox { limits, capabilities: Capabilities::default() }
+    }
+
+    #[test]
+    fn should_stop_runs_which_go_over_their_limits() {
+        let steps = limited(Limits { steps: Some(10_000), ..Limits::default() });
+        let (interpreted, compiled) = run("fun main() { while true { } }", &steps);
+        assert_eq!( interpreted, Err(RuntimeError::LimitExceeded(Limit::Steps(10_000))) );
+        assert_eq!( compiled, Err(RuntimeError::LimitExceeded(Limit::Steps(10_000))) );
+        let (interpreted, compiled) = run("fun main() { let mut i = 0; while i < 10 { i = i + 1; } i }", &steps);
+        assert_eq!( (interpreted, compiled), (Ok(Value::Int(10)), Ok(Value::Int(10))) );
+
+        let depth = limited(Limits { depth: Some(50), ..Limits::default() });
+        let (interpreted, compiled) = run("fun down(n : Int) -> Int { down(n + 1) }\nfun main() { down(0) }", &depth);
+        assert_eq!( interpreted, Err(RuntimeError::LimitExceeded(Limit::Depth(50))) );
+        assert_eq!( compiled, Err(RuntimeError::LimitExceeded(Limit::Depth(50))) );
+        let (interpreted, compiled) = run("fun down(n : Int) -> Int { if n == 0 { 0 } else { down(n - 1) } }\nfun main() { down(40) }", &depth);
+        assert_eq!( (interpreted, compiled), (Ok(Value::Int(0)), Ok(Value::Int(0))) );
+
+        // more calls than the stack has room for stop the interpreter before they overflow it
+        let deep = limited(Limits { depth: Some(100_000), ..Limits::default() });
+        let (interpreted, compiled) = run("fun down(n : Int) -> Int { if n == 0 { 0 } else { down(n - 1) + 1 } }\nfun main() { down(99000) }", &deep);
+        assert_eq!( interpreted, Err(RuntimeError::LimitExceeded(Limit::Stack(DEFAULT_STACK))) );
+        assert_eq!( compiled, Ok(Value::Int(99_000)) );
+        let (_, compiled) = run("fun down(n : Int) -> Int { down(n + 1) }\nfun main() { down(0) }", &deep);
+        assert_eq!( compiled, Err(RuntimeError::LimitExceeded(Limit::Depth(100_000))) );
+
+        let allocation = limited(Limits { allocation: Some(100_000), ..Limits::default() });
+        let (interpreted, compiled) = run("fun main() { let xs = []; while true { xs.push(\"some text\"); } }", &allocation);
+        assert_eq!( interpreted, Err(RuntimeError::LimitExceeded(Limit::Allocation(100_000))) );
+        assert_eq!( compiled, Err(RuntimeError::LimitExceeded(Limit::Allocation(100_000))) );
+        // values which are dropped again are given back
+        let src = "fun main() { let mut i = 0; while i < 50000 { [\"some text\", \"more\"]; (i, \"x\" + \"y\"); i = i + 1; } i }";
+        let (interpreted, compiled) = run(src, &allocation);
+        assert_eq!( (interpreted, compiled), (Ok(Value::Int(50_000)), Ok(Value::Int(50_000))) );
+        let (interpreted, compiled) = run("fun main() { [1, 2, 3].len() }", &allocation);
+        assert_eq!( (interpreted, compiled), (Ok(Value::Int(3)), Ok(Value::Int(3))) );
+    }
+
+    #[test]
+    fn should_free_compare_and_show_deeply_nested_values() {
+        let every = limited(Limits { steps: Some(100_000_000), allocation: Some(1 << 30), depth: Some(1000), stack: Some(DEFAULT_STACK) });
+        let src = "fun main() {
+                       let mut x = [];
+                       let mut y = [];
+                       let mut i = 0;
+                       while i < 200000 { x = [x]; y = [y]; i = i + 1; }
+                       (x == y, x.to_string().len())
+                   }";
+        let (interpreted, compiled) = run(src, &every);
+        assert_eq!( interpreted.unwrap().to_string(), "(true, 400002)" );
+        assert_eq!( compiled.unwrap().to_string(), "(true, 400002)" );
+    }
+
+    #[test]
+    fn should_only_reach_out_with_granted_capabilities() {
+        let dir = std::env::temp_dir().join(format!("dust_sandbox_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = "fun main() { write_file(\"note.txt\", \"hi\"); read_file(\"note.txt\") + \"!\" }";
+
+        let (interpreted, compiled) = run(src, &Sandbox::default());
+        assert_eq!( interpreted, Err(RuntimeError::NotPermitted(Capability::Files)) );
+        assert_eq!( compiled, Err(RuntimeError::NotPermitted(Capability::Files)) );
+
+        let within = Sandbox { capabilities: Capabilities { files: Files::Within(dir.clone()), ..Capabilities::default() }, ..Sandbox::default() };
+        let (interpreted, compiled) = run(src, &within);
+        assert_eq!( (interpreted, compiled), (Ok(Value::string("hi!")), Ok(Value::string("hi!"))) );
+        assert_eq!( std::fs::read_to_string(dir.join("note.txt")).unwrap(), "hi" );
+        let (interpreted, compiled) = run("fun main() { read_file(\"../secret\") }", &within);
+        assert_eq!( interpreted, Err(RuntimeError::NotPermitted(Capability::Files)) );
+        assert_eq!( compiled, Err(RuntimeError::NotPermitted(Capability::Files)) );
+        let (interpreted, _) = run("fun main() { read_file(\"missing.txt\") }", &within);
+        assert!( matches!( interpreted, Err(RuntimeError::Io(_)) ) );
+        #[cfg(unix)]
+        {
+            let outside = std::env::temp_dir().join(format!("dust_sandbox_outside_{}", std::process::id()));
+            std::fs::create_dir_all(&outside).unwrap();
+            std::fs::write(outside.join("secret"), "42").unwrap();
+            std::os::unix::fs::symlink(&outside, dir.join("out")).unwrap();
+            std::os::unix::fs::symlink(outside.join("made"), dir.join("made")).unwrap();
+            let (interpreted, compiled) = run("fun main() { read_file(\"out/secret\") }", &within);
+            assert_eq!( interpreted, Err(RuntimeError::NotPermitted(Capability::Files)) );
+            assert_eq!( compiled, Err(RuntimeError::NotPermitted(Capability::Files)) );
+            let (interpreted, compiled) = run("fun main() { write_file(\"made\", \"x\") }", &within);
+            assert_eq!( interpreted, Err(RuntimeError::NotPermitted(Capability::Files)) );
+            assert_eq!( compiled, Err(RuntimeError::NotPermitted(Capability::Files)) );
+            assert!( !outside.join("made").exists() );
+            std::fs::remove_dir_all(&outside).unwrap();
+        }
+
+        let (interpreted, compiled) = run("fun main() { now() }", &Sandbox::default());
+        assert_eq!( interpreted, Err(RuntimeError::NotPermitted(Capability::Clock)) );
+        assert_eq!( compiled, Err(RuntimeError::NotPermitted(Capability::Clock)) );
+        let (interpreted, compiled) = run("fun main() { env(\"PATH\") }", &Sandbox::default());
+        assert_eq!( interpreted, Err(RuntimeError::NotPermitted(Capability::Env)) );
+        assert_eq!( compiled, Err(RuntimeError::NotPermitted(Capability::Env)) );
+
+        let src = "fun main() { (now() > 0, env(\"DUST_SANDBOX_UNSET\")) }";
+        let (interpreted, compiled) = run(src, &Sandbox::trusted());
+        assert_eq!( interpreted.unwrap().to_string(), "(true, Option::None)" );
+        assert_eq!( compiled.unwrap().to_string(), "(true, Option::None)" );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
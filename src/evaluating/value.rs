@@ -0,0 +1,521 @@
+
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::cell::RefCell;
+
+use crate::parsing::ast::Expr;
+use super::{sandbox, gc};
+
+/* Compound values (lists, dictionaries, structs and enums) are shared by
+   reference, so a method taking `mut self` updates the caller's value.  Tuples
+   and strings are immutable and can be shared freely.
+
+   A range is immutable like a tuple:  it is only a pair of bounds, and
+   indexing or iterating with it makes whatever it is used for.
+
+   An iterator is stepped in place, so every copy of it sees the items the
+   others have taken.  Generators are iterators which keep the suspended state
+   of a call:  where the interpreter was in the function body, or the
+   instruction, operand stack and locals of the virtual machine.
+*/
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Unit,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(Rc<str>),
+    Tuple(Rc<Vec<Value>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Dict(Rc<RefCell<Vec<(Value, Value)>>>),
+    Struct(Rc<StructValue>),
+    Enum(Rc<EnumValue>),
+    Fun(Rc<Callable>),
+    Iterator(Rc<RefCell<Iteration>>),
+    Range(Rc<Range>),
+}
+
+/// The integers from `start` up to `end`, where a missing bound is open.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start : Option<i64>,
+    pub end : Option<i64>,
+    pub inclusive : bool,
+}
+
+#[derive(Debug)]
+pub struct StructValue {
+    pub name : String,
+    pub fields : RefCell<Vec<(String, Value)>>,
+}
+
+#[derive(Debug)]
+pub struct EnumValue {
+    pub enum_name : String,
+    pub case_name : String,
+    pub contents : RefCell<CaseValue>,
+}
+
+#[derive(Debug)]
+pub enum CaseValue {
+    Empty,
+    Tuple(Vec<Value>),
+    Struct(Vec<(String, Value)>),
+}
+
+#[derive(Debug)]
+pub enum Callable {
+    Fun(String),
+    Case { enum_name : String, case_name : String, arity : usize },
+    Method { type_name : String, name : String },
+    Lambda { params : Vec<String>, body : Arc<Expr>, captured : Vec<(String, Value)> },
+    Closure { function : usize, upvalues : Vec<Value> },
+}
+
+#[derive(Debug)]
+pub enum Iteration {
+    /// The items of a list, read as they are reached so that pushes during the loop are seen.
+    Items { list : Rc<RefCell<Vec<Value>>>, index : usize },
+    Chars { text : Rc<str>, offset : usize },
+    Keys { dict : Rc<RefCell<Vec<(Value, Value)>>>, index : usize },
+    /// The integers of a range from `next` up to and including `last`, which is `None` when the range has no end.
+    Count { next : i64, last : Option<i64> },
+    /// A value whose `next` method gives `Some(item)` or `None`.
+    Next(Value),
+    Generator { name : String, state : Suspended },
+    /// A generator which is being resumed, so that it cannot be resumed again from inside itself.
+    Running,
+    Finished,
+}
+
+#[derive(Debug)]
+pub enum Suspended {
+    /// The index of the function body in the interpreter, the path from the
+    /// body to the `yield` it stopped at and the locals.
+    Tree { fun : usize, cursors : Vec<Cursor>, locals : Vec<(String, Value)> },
+    Frame { function : usize, ip : usize, stack : Vec<Value>, locals : Vec<Value> },
+}
+
+/// Where a suspended generator is within one of the expressions enclosing its `yield`.
+#[derive(Debug)]
+pub enum Cursor {
+    /// At the statement `index` of a block, which truncates the locals back to `depth` when it ends.
+    Block { index : usize, depth : usize },
+    If { then : bool },
+    While,
+    Foreach { iterator : Value, depth : usize },
+    Match { arm : usize, depth : usize },
+    Yield,
+}
+
+/// How far running a generator got.
+pub enum Step {
+    Yielded(Value),
+    Done(Value),
+}
+
+impl Drop for Value {
+    /// Takes what the last reference to a container holds out of it before
+    /// it goes, and frees that here a container at a time, so that freeing
+    /// values nested however deep does not recurse.
+    fn drop(&mut self) {
+        let mut held = vec![];
+        free(self, &mut held);
+        while let Some(mut value) = held.pop() {
+            free(&mut value, &mut held);
+        }
+    }
+}
+
+thread_local! {
+    /// Put in place of a tuple or closure whose contents are being taken out.
+    static NO_VALUES : Rc<Vec<Value>> = Rc::new(vec![]);
+    static NO_CALLABLE : Rc<Callable> = Rc::new(Callable::Fun(String::new()));
+}
+
+/// Moves what a container holds into `held` when this is the last reference
+/// to it, giving back what the sandbox counted for it.
+fn free(value : &mut Value, held : &mut Vec<Value>) {
+    match value {
+        Value::String(s) if Rc::strong_count(s) == 1 => sandbox::release(0, s.len()),
+        Value::Tuple(t) if Rc::strong_count(t) == 1 => {
+            sandbox::release(t.len() + 1, 0);
+            if let Ok(empty) = NO_VALUES.try_with(Rc::clone) {
+                if let Ok(values) = Rc::try_unwrap(std::mem::replace(t, empty)) {
+                    held.extend(values);
+                }
+            }
+        },
+        Value::List(l) if Rc::strong_count(l) == 1 => if let Ok(mut values) = l.try_borrow_mut() {
+            sandbox::release(values.len() + 1, 0);
+            held.append(&mut values);
+        },
+        Value::Dict(d) if Rc::strong_count(d) == 1 => if let Ok(mut pairs) = d.try_borrow_mut() {
+            sandbox::release(2 * pairs.len() + 1, 0);
+            for (k, v) in pairs.drain(..) {
+                held.push(k);
+                held.push(v);
+            }
+        },
+        Value::Struct(s) if Rc::strong_count(s) == 1 => if let Ok(mut fields) = s.fields.try_borrow_mut() {
+            sandbox::release(fields.len() + 1, 0);
+            held.extend(fields.drain(..).map(|(_, v)| v));
+        },
+        Value::Enum(e) if Rc::strong_count(e) == 1 => if let Ok(mut contents) = e.contents.try_borrow_mut() {
+            match std::mem::replace(&mut *contents, CaseValue::Empty) {
+                CaseValue::Empty => sandbox::release(1, 0),
+                CaseValue::Tuple(values) => {
+                    sandbox::release(values.len() + 1, 0);
+                    held.extend(values);
+                },
+                CaseValue::Struct(fields) => {
+                    sandbox::release(fields.len() + 1, 0);
+                    held.extend(fields.into_iter().map(|(_, v)| v));
+                },
+            }
+        },
+        Value::Fun(f) if Rc::strong_count(f) == 1 => {
+            if let Ok(none) = NO_CALLABLE.try_with(Rc::clone) {
+                match Rc::try_unwrap(std::mem::replace(f, none)) {
+                    Ok(Callable::Lambda { captured, .. }) => held.extend(captured.into_iter().map(|(_, v)| v)),
+                    Ok(Callable::Closure { upvalues, .. }) => held.extend(upvalues),
+                    _ => (),
+                }
+            }
+        },
+        Value::Iterator(i) if Rc::strong_count(i) == 1 => if let Ok(mut iteration) = i.try_borrow_mut() {
+            match std::mem::replace(&mut *iteration, Iteration::Finished) {
+                Iteration::Items { list, .. } => held.push(Value::List(list)),
+                Iteration::Keys { dict, .. } => held.push(Value::Dict(dict)),
+                Iteration::Next(value) => held.push(value),
+                Iteration::Generator { state: Suspended::Tree { cursors, locals, .. }, .. } => {
+                    held.extend(locals.into_iter().map(|(_, v)| v));
+                    for cursor in cursors {
+                        if let Cursor::Foreach { iterator, .. } = cursor {
+                            held.push(iterator);
+                        }
+                    }
+                },
+                Iteration::Generator { state: Suspended::Frame { stack, locals, .. }, .. } => {
+                    held.extend(stack);
+                    held.extend(locals);
+                },
+                Iteration::Chars { .. } | Iteration::Count { .. } | Iteration::Running | Iteration::Finished => (),
+            }
+        },
+        _ => (),
+    }
+}
+
+/// A container the collector knows about, so that it can free its cycles.
+fn tracked(value : Value) -> Value {
+    gc::track(&value);
+    value
+}
+
+impl Value {
+    pub fn string(s : &str) -> Value {
+        sandbox::allocate(0, s.len());
+        Value::String(Rc::from(s))
+    }
+
+    pub fn list(values : Vec<Value>) -> Value {
+        sandbox::allocate(values.len() + 1, 0);
+        tracked(Value::List(Rc::new(RefCell::new(values))))
+    }
+
+    pub fn dict(pairs : Vec<(Value, Value)>) -> Value {
+        sandbox::allocate(2 * pairs.len() + 1, 0);
+        tracked(Value::Dict(Rc::new(RefCell::new(pairs))))
+    }
+
+    pub fn tuple(values : Vec<Value>) -> Value {
+        match values.len() {
+            0 => Value::Unit,
+            n => {
+                sandbox::allocate(n + 1, 0);
+                tracked(Value::Tuple(Rc::new(values)))
+            },
+        }
+    }
+
+    pub fn structure(name : &str, fields : Vec<(String, Value)>) -> Value {
+        sandbox::allocate(fields.len() + 1, 0);
+        tracked(Value::Struct(Rc::new(StructValue { name: name.to_string(), fields: RefCell::new(fields) })))
+    }
+
+    pub fn iterator(iteration : Iteration) -> Value {
+        tracked(Value::Iterator(Rc::new(RefCell::new(iteration))))
+    }
+
+    /// A lambda or closure, which holds the values it captured.
+    pub fn closure(callable : Callable) -> Value {
+        tracked(Value::Fun(Rc::new(callable)))
+    }
+
+    pub fn case(enum_name : &str, case_name : &str, contents : CaseValue) -> Value {
+        sandbox::allocate(1 + match &contents {
+            CaseValue::Empty => 0,
+            CaseValue::Tuple(values) => values.len(),
+            CaseValue::Struct(fields) => fields.len(),
+        }, 0);
+        tracked(Value::Enum(Rc::new(EnumValue { enum_name: enum_name.to_string()
+                                              , case_name: case_name.to_string()
+                                              , contents: RefCell::new(contents)
+                                              })))
+    }
+
+    /// The name used to find impls for a value, which matches the type name written in an impl.
+    pub fn type_name(&self) -> String {
+        match self {
+            Value::Unit => "Unit".to_string(),
+            Value::Bool(_) => "Bool".to_string(),
+            Value::Int(_) => "Int".to_string(),
+            Value::Float(_) => "Float".to_string(),
+            Value::String(_) => "String".to_string(),
+            Value::Tuple(_) => "Tuple".to_string(),
+            Value::List(_) => "List".to_string(),
+            Value::Dict(_) => "Dict".to_string(),
+            Value::Struct(s) => s.name.clone(),
+            Value::Enum(e) => e.enum_name.clone(),
+            Value::Fun(_) => "Fun".to_string(),
+            Value::Iterator(_) => "Iterator".to_string(),
+            Value::Range(_) => "Range".to_string(),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    /// Compares containers a level at a time, so that comparing values nested
+    /// however deep does not recurse.
+    fn eq(&self, other : &Value) -> bool {
+        let mut pending = vec![];
+        if !shallow_eq(self, other, &mut pending) {
+            return false;
+        }
+        while let Some((a, b)) = pending.pop() {
+            if !shallow_eq(&a, &b, &mut pending) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Whether two values are equal as far as their own level goes, leaving the
+/// pairs of values they hold in `pending`.
+fn shallow_eq(a : &Value, b : &Value, pending : &mut Vec<(Value, Value)>) -> bool {
+    let mut all = |a : &[Value], b : &[Value]| {
+        pending.extend(a.iter().cloned().zip(b.iter().cloned()));
+        a.len() == b.len()
+    };
+    match (a, b) {
+        (Value::Unit, Value::Unit) => true,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Int(a), Value::Int(b)) => a == b,
+        (Value::Float(a), Value::Float(b)) => a == b,
+        (Value::Int(a), Value::Float(b)) | (Value::Float(b), Value::Int(a)) => (*a as f64) == *b,
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Tuple(a), Value::Tuple(b)) => all(a, b),
+        (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b) || all(&a.borrow(), &b.borrow()),
+        (Value::Dict(a), Value::Dict(b)) => {
+            if Rc::ptr_eq(a, b) {
+                return true;
+            }
+            let (a, b) = (a.borrow(), b.borrow());
+            // keys are never repeated, so the value of each key is all there is to compare
+            a.len() == b.len() && a.iter().all(|(k, v)| match b.iter().find(|(k2, _)| k == k2) {
+                Some((_, v2)) => {
+                    pending.push((v.clone(), v2.clone()));
+                    true
+                },
+                None => false,
+            })
+        },
+        (Value::Struct(a), Value::Struct(b)) =>
+            Rc::ptr_eq(a, b) || (a.name == b.name && fields_eq(&a.fields.borrow(), &b.fields.borrow(), pending)),
+        (Value::Enum(a), Value::Enum(b)) => {
+            if Rc::ptr_eq(a, b) {
+                return true;
+            }
+            if a.enum_name != b.enum_name || a.case_name != b.case_name {
+                return false;
+            }
+            match (&*a.contents.borrow(), &*b.contents.borrow()) {
+                (CaseValue::Empty, CaseValue::Empty) => true,
+                (CaseValue::Tuple(a), CaseValue::Tuple(b)) => all(a, b),
+                (CaseValue::Struct(a), CaseValue::Struct(b)) => fields_eq(a, b, pending),
+                _ => false,
+            }
+        },
+        (Value::Fun(a), Value::Fun(b)) => Rc::ptr_eq(a, b),
+        (Value::Iterator(a), Value::Iterator(b)) => Rc::ptr_eq(a, b),
+        (Value::Range(a), Value::Range(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn fields_eq(a : &[(String, Value)], b : &[(String, Value)], pending : &mut Vec<(Value, Value)>) -> bool {
+    if a.len() != b.len() || a.iter().zip(b).any(|((n, _), (m, _))| n != m) {
+        return false;
+    }
+    pending.extend(a.iter().zip(b).map(|((_, v), (_, w))| (v.clone(), w.clone())));
+    true
+}
+
+impl PartialEq for CaseValue {
+    fn eq(&self, other : &CaseValue) -> bool {
+        match (self, other) {
+            (CaseValue::Empty, CaseValue::Empty) => true,
+            (CaseValue::Tuple(a), CaseValue::Tuple(b)) => a == b,
+            (CaseValue::Struct(a), CaseValue::Struct(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+// Strings display without quotes at the top level and with quotes inside of other values.
+pub fn show(value : &Value) -> String {
+    match value {
+        Value::String(s) => format!("{:?}", s),
+        v => v.to_string(),
+    }
+}
+
+/// What is left to write of a value being displayed.
+enum Piece {
+    Text(String),
+    Shown(Value),
+}
+
+fn text(s : &str) -> Piece {
+    Piece::Text(s.to_string())
+}
+
+/// The pieces of a value inside of another one, in order.
+fn pieces(value : &Value) -> Vec<Piece> {
+    let separated = |open : String, values : &[Value], close : &str| {
+        let mut pieces = vec![Piece::Text(open)];
+        for (i, v) in values.iter().enumerate() {
+            if i > 0 {
+                pieces.push(text(", "));
+            }
+            pieces.push(Piece::Shown(v.clone()));
+        }
+        pieces.push(text(close));
+        pieces
+    };
+    let fields = |open : String, fields : &[(String, Value)]| {
+        let mut pieces = vec![Piece::Text(open)];
+        for (i, (n, v)) in fields.iter().enumerate() {
+            pieces.push(Piece::Text(format!("{}{} : ", if i > 0 { ", " } else { "" }, n)));
+            pieces.push(Piece::Shown(v.clone()));
+        }
+        pieces.push(text(" }"));
+        pieces
+    };
+    match value {
+        Value::String(s) => vec![Piece::Text(format!("{:?}", s))],
+        Value::Tuple(vs) => separated("(".to_string(), vs, ")"),
+        Value::List(vs) => separated("[".to_string(), &vs.borrow(), "]"),
+        Value::Dict(pairs) if pairs.borrow().is_empty() => vec![text("[:]")],
+        Value::Dict(pairs) => {
+            let mut pieces = vec![text("[")];
+            for (i, (k, v)) in pairs.borrow().iter().enumerate() {
+                if i > 0 {
+                    pieces.push(text(", "));
+                }
+                pieces.extend(vec![Piece::Shown(k.clone()), text(" : "), Piece::Shown(v.clone())]);
+            }
+            pieces.push(text("]"));
+            pieces
+        },
+        Value::Struct(s) => fields(format!("{} {{ ", s.name), &s.fields.borrow()),
+        Value::Enum(e) => match &*e.contents.borrow() {
+            CaseValue::Empty => vec![Piece::Text(format!("{}::{}", e.enum_name, e.case_name))],
+            CaseValue::Tuple(vs) => separated(format!("{}::{}(", e.enum_name, e.case_name), vs, ")"),
+            CaseValue::Struct(fs) => fields(format!("{}::{} {{ ", e.enum_name, e.case_name), fs),
+        },
+        v => vec![Piece::Text(v.to_string())],
+    }
+}
+
+impl fmt::Display for Value {
+    /// Writes containers a piece at a time, so that displaying values nested
+    /// however deep does not recurse.
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Unit => write!(f, "()"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) if x.fract() == 0.0 && x.is_finite() => write!(f, "{:.1}", x),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Fun(c) => match &**c {
+                Callable::Fun(name) => write!(f, "<fun {}>", name),
+                Callable::Case { enum_name, case_name, .. } => write!(f, "<fun {}::{}>", enum_name, case_name),
+                Callable::Method { type_name, name } => write!(f, "<fun {}::{}>", type_name, name),
+                Callable::Lambda { .. } | Callable::Closure { .. } => write!(f, "<closure>"),
+            },
+            Value::Iterator(_) => write!(f, "<iterator>"),
+            Value::Range(r) => {
+                if let Some(start) = r.start {
+                    write!(f, "{}", start)?;
+                }
+                write!(f, "{}", if r.inclusive { "..=" } else { ".." })?;
+                match r.end {
+                    Some(end) => write!(f, "{}", end),
+                    None => Ok(()),
+                }
+            },
+            container => {
+                let mut pending = pieces(container);
+                pending.reverse();
+                while let Some(piece) = pending.pop() {
+                    match piece {
+                        Piece::Text(t) => f.write_str(&t)?,
+                        Piece::Shown(v) => pending.extend(pieces(&v).into_iter().rev()),
+                    }
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_display_nested_values() {
+        let point = Value::Struct(Rc::new(StructValue { name: "Point".to_string()
+                                                      , fields: RefCell::new(vec![ ("x".to_string(), Value::Int(1))
+                                                                                 , ("y".to_string(), Value::Float(2.0))
+                                                                                 ])
+                                                      }));
+        let value = Value::list(vec![ point
+                                    , Value::string("a")
+                                    , Value::case("Option", "Some", CaseValue::Tuple(vec![Value::Bool(true)]))
+                                    , Value::dict(vec![(Value::string("k"), Value::Unit)])
+                                    , Value::dict(vec![])
+                                    ]);
+
+        assert_eq!( value.to_string(), r#"[Point { x : 1, y : 2.0 }, "a", Option::Some(true), ["k" : ()], [:]]"# );
+        assert_eq!( Value::string("a").to_string(), "a" );
+    }
+
+    #[test]
+    fn should_compare_values_structurally() {
+        let a = Value::list(vec![Value::Int(1), Value::tuple(vec![Value::string("x"), Value::Float(2.0)])]);
+        let b = Value::list(vec![Value::Int(1), Value::tuple(vec![Value::string("x"), Value::Int(2)])]);
+        let c = Value::list(vec![Value::Int(1)]);
+
+        assert_eq!( a, b );
+        assert_ne!( a, c );
+        assert_eq!( Value::dict(vec![(Value::Int(1), Value::Unit), (Value::Int(2), Value::Unit)])
+                  , Value::dict(vec![(Value::Int(2), Value::Unit), (Value::Int(1), Value::Unit)]) );
+    }
+}
@@ -0,0 +1,14 @@
+pub mod parsing;
+pub mod checking;
+pub mod evaluating;
+pub mod compiling;
+pub mod codegen;
+pub mod stdlib;
+pub mod embed;
+pub mod cli;
+mod repl;
+mod lsp;
+
+pub use embed::{Engine, EmbedError, FromValue, IntoValue, HostType};
+pub use evaluating::value::Value;
+pub use evaluating::runtime_error::RuntimeError;
@@ -0,0 +1,1026 @@
+
+use crate::parsing::ast::*;
+use crate::parsing::parser::{parse_items_in, module_from_items};
+use crate::parsing::parse_error::ParseError;
+use crate::parsing::incremental::Document;
+use crate::parsing::printer::{print_sig, print_param, print_type, print_type_params};
+use crate::checking::check_error::{CheckError, CheckWarning};
+use crate::checking::checker::{check_with_warnings, pattern_variables};
+use crate::evaluating::ops::BUILTIN_FUNS;
+use crate::stdlib;
+
+/* What the language server knows about one document.
+
+   Most of the syntax tree has no positions, so names are found by scanning the
+   tokens of the source within the spans the parser does keep: whole items,
+   params, `let`, match patterns.  A reference is resolved by where it is: after a
+   `.` it is a field or method, after `::` an enum case or item, and otherwise
+   the innermost local in scope or else a top level item.  Fields and methods
+   are resolved by name alone, so a field shared by two structs is one symbol.
+
+   When a document has a parse error every top level item is parsed on its
+   own, so one broken item does not hide the definitions in the rest of it.
+   Otherwise the items the document already parsed are used.  The checker only
+   runs when everything parses.
+*/
+
+const ITEM_KEYWORDS : [&str; 9] = ["fun", "use", "struct", "enum", "trait", "impl", "mod", "const", "extern"];
+
+pub const KEYWORDS : [&str; 29] = [ "fun", "use", "struct", "enum", "trait", "impl", "mod", "const", "extern", "let", "mut", "if"
+                                  , "else", "while", "match", "return", "break", "continue", "try", "assert", "panic", "true"
+                                  , "false", "type", "own", "for", "foreach", "in", "yield"
+                                  ];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Ident,
+    Number,
+    String,
+    Comment,
+    Punct,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Token {
+    pub kind : TokenKind,
+    pub start : usize,
+    pub end : usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Fun,
+    Struct,
+    Enum,
+    Trait,
+    Mod,
+    Const,
+    Field,
+    Case,
+    Method,
+    Param,
+    Local,
+}
+
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub name : String,
+    pub kind : SymbolKind,
+    pub name_span : Meta,
+    /// The whole definition, for items; the name otherwise.
+    pub span : Meta,
+    /// Where references may find this definition.
+    pub scope : Meta,
+    /// The signature or type shown on hover.
+    pub detail : String,
+    pub doc : Option<String>,
+    /// The struct, enum, trait or function which this belongs to.
+    pub parent : Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub span : Meta,
+    pub severity : Severity,
+    pub message : String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    pub label : String,
+    pub kind : Option<SymbolKind>,
+    pub detail : String,
+}
+
+pub struct Analysis {
+    pub text : String,
+    pub tokens : Vec<Token>,
+    pub definitions : Vec<Definition>,
+    pub diagnostics : Vec<Diagnostic>,
+    /// The start of each `{` and the end of its `}`.
+    blocks : Vec<(usize, usize)>,
+}
+
+pub fn tokenize(text : &str) -> Vec<Token> {
+    let cs = text.char_indices().collect::<Vec<_>>();
+    let at = |i : usize| cs.get(i).map(|(o, _)| *o).unwrap_or(text.len());
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < cs.len() {
+        let c = cs[i].1;
+        let next = cs.get(i + 1).map(|(_, c)| *c);
+        let start = i;
+        let kind = if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        else if c == '/' && next == Some('*') {
+            let mut depth = 0;
+            while i < cs.len() {
+                match (cs[i].1, cs.get(i + 1).map(|(_, c)| *c)) {
+                    ('/', Some('*')) => { depth += 1; i += 2; },
+                    ('*', Some('/')) => {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    },
+                    _ => i += 1,
+                }
+            }
+            TokenKind::Comment
+        }
+        else if c == '"' {
+            i += 1;
+            while i < cs.len() && cs[i].1 != '"' {
+                i += if cs[i].1 == '\\' { 2 } else { 1 };
+            }
+            i = (i + 1).min(cs.len());
+            TokenKind::String
+        }
+        else if c.is_alphabetic() || c == '_' {
+            while i < cs.len() && (cs[i].1.is_alphanumeric() || cs[i].1 == '_') {
+                i += 1;
+            }
+            TokenKind::Ident
+        }
+        else if c.is_ascii_digit() {
+            while i < cs.len() && (cs[i].1.is_ascii_digit() || (cs[i].1 == '.' && matches!( cs.get(i + 1), Some((_, d)) if d.is_ascii_digit() ))) {
+                i += 1;
+            }
+            TokenKind::Number
+        }
+        else {
+            let pair = next.map(|n| [c, n].iter().collect::<String>());
+            i += match pair.as_deref() {
+                Some("::") | Some("->") | Some("=>") | Some("==") | Some("!=") | Some("<=") | Some(">=") | Some("&&") | Some("..") => 2,
+                _ => 1,
+            };
+            TokenKind::Punct
+        };
+        tokens.push(Token { kind, start: at(start), end: at(i) });
+    }
+
+    tokens
+}
+
+/// Splits a document into the ranges of its top level items, each starting at
+/// an item keyword outside of any brackets or at the start of a line.
+pub fn item_ranges(text : &str, tokens : &[Token]) -> Vec<(usize, usize)> {
+    let mut starts = vec![];
+    let mut depth = 0i32;
+    // after `const` or `extern`, the `fun` of `const fun` or `extern "lib" fun`
+    let mut prefixed = false;
+    for t in tokens {
+        match (t.kind, &text[t.start..t.end]) {
+            (TokenKind::Punct, "{") | (TokenKind::Punct, "(") | (TokenKind::Punct, "[") => depth += 1,
+            (TokenKind::Punct, "}") | (TokenKind::Punct, ")") | (TokenKind::Punct, "]") => depth = (depth - 1).max(0),
+            (TokenKind::Ident, "fun") if prefixed => (),
+            // an item keyword at the start of a line starts an item even after
+            // unbalanced brackets
+            (TokenKind::Ident, word) if ITEM_KEYWORDS.contains(&word) && (depth == 0 || t.start == 0 || text[..t.start].ends_with('\n')) => {
+                starts.push(t.start);
+                depth = 0;
+                prefixed = word == "const" || word == "extern";
+                continue;
+            },
+            _ => (),
+        }
+        if !matches!( t.kind, TokenKind::String | TokenKind::Comment ) {
+            prefixed = false;
+        }
+    }
+
+    let first = tokens.iter().find(|t| t.kind != TokenKind::Comment).map(|t| t.start);
+    if first.is_some() && first != starts.first().copied() {
+        starts.insert(0, 0);
+    }
+
+    let mut ranges = vec![];
+    for (i, start) in starts.iter().enumerate() {
+        let start = if i == 0 { 0 } else { *start };
+        ranges.push((start, starts.get(i + 1).copied().unwrap_or(text.len())));
+    }
+    ranges
+}
+
+fn is_ident(name : &str) -> bool {
+    name.starts_with(|c : char| c.is_alphabetic() || c == '_') && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Whether `name` can be given to something by a rename.
+pub fn is_valid_name(name : &str) -> bool {
+    is_ident(name) && !KEYWORDS.contains(&name)
+}
+
+fn literal_type(e : &Expr) -> Option<String> {
+    match e {
+        Expr::Number(n) if n.contains('.') => Some("Float".to_string()),
+        Expr::Number(_) => Some("Int".to_string()),
+        Expr::DString(_) => Some("String".to_string()),
+        Expr::Bool(_) => Some("Bool".to_string()),
+        Expr::List(_) => Some("List".to_string()),
+        Expr::Dict(_) => Some("Dict".to_string()),
+        Expr::Range { .. } => Some("Range".to_string()),
+        Expr::Struct { namespace, name, .. } => Some(namespace.last().unwrap_or(name).clone()),
+        _ => None,
+    }
+}
+
+/// The text of a comment without its delimiters or leading `*`s.
+fn comment_text(comment : &str) -> String {
+    let inner = comment.trim_start_matches("/*").trim_end_matches("*/");
+    let lines = inner.lines()
+                     .map(|l| l.trim().trim_start_matches('*').trim())
+                     .collect::<Vec<_>>();
+    lines.join("\n").trim().to_string()
+}
+
+impl Analysis {
+    pub fn new(text : &str) -> Analysis {
+        Analysis::analyse(text, None)
+    }
+
+    /// Analyses a document, reusing its items when all of it parses.
+    pub fn of(document : &Document) -> Analysis {
+        Analysis::analyse(document.text(), document.items().ok())
+    }
+
+    fn analyse(text : &str, parsed : Option<&[(Item, Meta)]>) -> Analysis {
+        let tokens = tokenize(text);
+
+        let mut blocks = vec![];
+        let mut open = vec![];
+        for t in &tokens {
+            match (t.kind, &text[t.start..t.end]) {
+                (TokenKind::Punct, "{") => open.push(t.start),
+                (TokenKind::Punct, "}") => if let Some(start) = open.pop() {
+                    blocks.push((start, t.end));
+                },
+                _ => (),
+            }
+        }
+        blocks.sort();
+
+        let mut items = parsed.map(<[_]>::to_vec).unwrap_or_default();
+        let mut diagnostics = vec![];
+        let ranges = if parsed.is_some() { vec![] } else { item_ranges(text, &tokens) };
+        for (start, end) in ranges {
+            match parse_items_in(text, start, end) {
+                Ok(mut parsed) => items.append(&mut parsed),
+                Err(ParseError::ErrorAt(offset, message)) => {
+                    let span = tokens.iter()
+                                     .find(|t| t.start <= offset && offset < t.end)
+                                     .map(|t| Meta { start: t.start, end: t.end })
+                                     .unwrap_or(Meta { start: offset, end: offset });
+                    diagnostics.push(Diagnostic { span, severity: Severity::Error, message: format!("parse error: {}", message) });
+                },
+                Err(ParseError::EndOfFile(message)) => {
+                    let span = Meta { start: end, end };
+                    diagnostics.push(Diagnostic { span, severity: Severity::Error, message: format!("parse error: unexpected end of file: {}", message) });
+                },
+            }
+        }
+
+        let mut analysis = Analysis { text: text.to_string(), tokens, definitions: vec![], diagnostics, blocks };
+        for (item, span) in &items {
+            analysis.collect_item(item, *span);
+        }
+
+        if analysis.diagnostics.is_empty() {
+            let mut module = module_from_items(items);
+            let mut errors = stdlib::link(&mut module).err().unwrap_or_default();
+            let (mut check_errors, warnings) = check_with_warnings(&module);
+            errors.append(&mut check_errors);
+            for e in &errors {
+                let span = analysis.check_error_span(e);
+                analysis.diagnostics.push(Diagnostic { span, severity: Severity::Error, message: format!("check error: {:?}", e) });
+            }
+            for w in &warnings {
+                let CheckWarning::UnusedMut { declared, .. } = w;
+                analysis.diagnostics.push(Diagnostic { span: *declared, severity: Severity::Warning, message: format!("warning: {:?}", w) });
+            }
+        }
+
+        analysis
+    }
+
+    fn token_text(&self, t : &Token) -> &str {
+        &self.text[t.start..t.end]
+    }
+
+    /// The first token which starts at or after `offset`.
+    fn token_index(&self, offset : usize) -> usize {
+        self.tokens.partition_point(|t| t.start < offset)
+    }
+
+    fn find_ident(&self, from : usize, to : usize, name : &str) -> Option<Meta> {
+        self.tokens[self.token_index(from)..].iter()
+                                             .take_while(|t| t.end <= to)
+                                             .find(|t| t.kind == TokenKind::Ident && self.token_text(t) == name)
+                                             .map(|t| Meta { start: t.start, end: t.end })
+    }
+
+    /// The name after `keyword`, such as the name of `fun` or `let`.
+    fn find_after(&self, from : usize, to : usize, keyword : &str, name : &str) -> Option<Meta> {
+        let tokens = &self.tokens[self.token_index(from)..];
+        tokens.windows(2)
+              .take_while(|w| w[1].end <= to)
+              .find(|w| self.token_text(&w[0]) == keyword && self.token_text(&w[1]) == name)
+              .map(|w| Meta { start: w[1].start, end: w[1].end })
+    }
+
+    /// The comment right before `offset`.
+    fn doc_before(&self, offset : usize) -> Option<String> {
+        let i = self.token_index(offset);
+        match i.checked_sub(1).map(|i| self.tokens[i]) {
+            Some(t) if t.kind == TokenKind::Comment && self.text[t.end..offset].trim().is_empty() => Some(comment_text(self.token_text(&t))),
+            _ => None,
+        }
+    }
+
+    /// The end of the innermost block around `offset`.
+    fn block_end(&self, offset : usize) -> usize {
+        self.blocks.iter()
+                   .filter(|(start, end)| *start < offset && offset < *end)
+                   .map(|(_, end)| *end)
+                   .min()
+                   .unwrap_or(self.text.len())
+    }
+
+    fn define(&mut self, name : &str, kind : SymbolKind, name_span : Option<Meta>, scope : Meta, detail : String, parent : Option<usize>) -> Option<usize> {
+        let name_span = name_span?;
+        let doc = match kind {
+            SymbolKind::Param | SymbolKind::Local => None,
+            _ => self.doc_before(name_span.start),
+        };
+        self.definitions.push(Definition { name: name.to_string(), kind, name_span, span: name_span, scope, detail, doc, parent });
+        Some(self.definitions.len() - 1)
+    }
+
+    /// Defines something which is seen everywhere and, when it has a span, is
+    /// documented by the comment before that span.
+    fn define_item(&mut self, name : &str, kind : SymbolKind, name_span : Option<Meta>, span : Option<Meta>, detail : String, parent : Option<usize>) -> Option<usize> {
+        let d = self.define(name, kind, name_span, Meta { start: 0, end: self.text.len() }, detail, parent)?;
+        if let Some(span) = span {
+            self.definitions[d].span = span;
+            self.definitions[d].doc = self.doc_before(span.start);
+        }
+        Some(d)
+    }
+
+    fn collect_item(&mut self, item : &Item, span : Meta) {
+        match item {
+            Item::Fun(fun_def) => {
+                let name_span = self.find_after(span.start, span.end, "fun", &fun_def.sig.name);
+                let constant = if fun_def.constant { "const " } else { "" };
+                let detail = format!("{}{}", constant, print_sig(&fun_def.sig));
+                let parent = self.define_item(&fun_def.sig.name, SymbolKind::Fun, name_span, Some(span), detail, None);
+                self.collect_fun(fun_def, span, parent);
+            },
+            Item::Struct(struct_def) => {
+                let name_span = self.find_after(span.start, span.end, "struct", &struct_def.name);
+                let detail = format!("struct {}{}", struct_def.name, print_type_params(&struct_def.type_params));
+                let parent = self.define_item(&struct_def.name, SymbolKind::Struct, name_span, Some(span), detail, None);
+                self.collect_fields(&struct_def.fields, name_span.map(|m| m.end).unwrap_or(span.end), span.end, parent);
+            },
+            Item::Enum(enum_def) => {
+                let name_span = self.find_after(span.start, span.end, "enum", &enum_def.name);
+                let detail = format!("enum {}{}", enum_def.name, print_type_params(&enum_def.type_params));
+                let parent = self.define_item(&enum_def.name, SymbolKind::Enum, name_span, Some(span), detail, None);
+                let mut cursor = name_span.map(|m| m.end).unwrap_or(span.end);
+                for case in &enum_def.cases {
+                    let (name, detail) = match case {
+                        EnumCase::EmptyCase { name } => (name, format!("{}::{}", enum_def.name, name)),
+                        EnumCase::TypeCase { name, types } => {
+                            let types = types.iter().map(print_type).collect::<Vec<_>>();
+                            (name, format!("{}::{}({})", enum_def.name, name, types.join(", ")))
+                        },
+                        EnumCase::StructCase { name, .. } => (name, format!("{}::{} {{ .. }}", enum_def.name, name)),
+                    };
+                    let name_span = self.find_ident(cursor, span.end, name);
+                    cursor = name_span.map(|m| m.end).unwrap_or(cursor);
+                    let case_def = self.define_item(name, SymbolKind::Case, name_span, None, detail, parent);
+                    if let EnumCase::StructCase { fields, .. } = case {
+                        cursor = self.collect_fields(fields, cursor, span.end, case_def);
+                    }
+                }
+            },
+            Item::Trait(trait_def) => {
+                let name_span = self.find_after(span.start, span.end, "trait", &trait_def.name);
+                let detail = format!("trait {}{}", trait_def.name, print_type_params(&trait_def.type_params));
+                let parent = self.define_item(&trait_def.name, SymbolKind::Trait, name_span, Some(span), detail, None);
+                let mut cursor = name_span.map(|m| m.end).unwrap_or(span.end);
+                for item in &trait_def.items {
+                    if let TraitItem::Fun(sig) = item {
+                        let name_span = self.find_after(cursor, span.end, "fun", &sig.name);
+                        cursor = name_span.map(|m| m.end).unwrap_or(cursor);
+                        self.define_item(&sig.name, SymbolKind::Method, name_span, None, print_sig(sig), parent);
+                    }
+                }
+            },
+            Item::Impl(impl_def) => {
+                let mut cursor = span.start;
+                for item in &impl_def.items {
+                    if let ImplItem::Fun(fun_def) = item {
+                        let name_span = self.find_after(cursor, span.end, "fun", &fun_def.sig.name);
+                        let fun_span = name_span.map(|m| self.fun_span(m, span.end));
+                        cursor = fun_span.map(|m| m.end).unwrap_or(cursor);
+                        let parent = self.define_item(&fun_def.sig.name, SymbolKind::Method, name_span, fun_span, print_sig(&fun_def.sig), None);
+                        if let Some(fun_span) = fun_span {
+                            self.collect_fun(fun_def, fun_span, parent);
+                        }
+                    }
+                }
+            },
+            Item::Const(const_def) => {
+                let name_span = self.find_after(span.start, span.end, "const", &const_def.name);
+                let detail = format!("const {} : {}", const_def.name, print_type(&const_def.const_type));
+                self.define_item(&const_def.name, SymbolKind::Const, name_span, Some(span), detail, None);
+                self.collect_expr(&const_def.value, None);
+            },
+            Item::Extern(extern_def) => {
+                let name_span = self.find_after(span.start, span.end, "fun", &extern_def.sig.name);
+                let detail = format!("extern {}", print_sig(&extern_def.sig));
+                self.define_item(&extern_def.sig.name, SymbolKind::Fun, name_span, Some(span), detail, None);
+            },
+            Item::Mod(name) => {
+                let name_span = self.find_after(span.start, span.end, "mod", name);
+                self.define_item(name, SymbolKind::Mod, name_span, Some(span), format!("mod {}", name), None);
+            },
+            Item::Use(_) => (),
+        }
+    }
+
+    /// From the `fun` before a method's name to the end of its body.
+    fn fun_span(&self, name_span : Meta, limit : usize) -> Meta {
+        let start = self.tokens[..self.token_index(name_span.start)].last().map(|t| t.start).unwrap_or(name_span.start);
+        let mut depth = 0;
+        for t in &self.tokens[self.token_index(name_span.end)..] {
+            match self.token_text(t) {
+                "(" => depth += 1,
+                ")" => depth -= 1,
+                "{" if depth == 0 => {
+                    let end = self.blocks.iter().find(|(s, _)| *s == t.start).map(|(_, e)| *e).unwrap_or(limit);
+                    return Meta { start, end };
+                },
+                _ => (),
+            }
+        }
+        Meta { start, end: limit }
+    }
+
+    fn collect_fields(&mut self, fields : &[StructField], mut cursor : usize, end : usize, parent : Option<usize>) -> usize {
+        for field in fields {
+            let name_span = self.find_ident(cursor, end, &field.name);
+            cursor = name_span.map(|m| m.end).unwrap_or(cursor);
+            let detail = format!("{} : {}", field.name, print_type(&field.field_type));
+            self.define_item(&field.name, SymbolKind::Field, name_span, None, detail, parent);
+        }
+        cursor
+    }
+
+    fn param_name(&self, param : &Param) -> Option<Meta> {
+        self.tokens[self.token_index(param.meta.start)..].iter()
+                                                         .take_while(|t| t.end <= param.meta.end)
+                                                         .find(|t| t.kind == TokenKind::Ident && self.token_text(t) != "mut")
+                                                         .map(|t| Meta { start: t.start, end: t.end })
+    }
+
+    fn collect_fun(&mut self, fun_def : &FunDef, span : Meta, parent : Option<usize>) {
+        for param in &fun_def.sig.params {
+            let name_span = self.param_name(param);
+            self.define(&param.name, SymbolKind::Param, name_span, span, print_param(param), parent);
+        }
+        self.collect_expr(&fun_def.body, parent);
+    }
+
+    fn collect_expr(&mut self, expr : &Expr, parent : Option<usize>) {
+        match expr {
+            Expr::Number(_) | Expr::DString(_) | Expr::Unit | Expr::Variable(_) | Expr::Namespace(_, _) => (),
+            Expr::Bool(_) | Expr::Break | Expr::Continue => (),
+            Expr::Tuple(exprs) | Expr::Block(exprs) | Expr::List(exprs) => {
+                for e in exprs {
+                    self.collect_expr(e, parent);
+                }
+            },
+            Expr::Call { fun, args, .. } => {
+                self.collect_expr(fun, parent);
+                for e in args {
+                    self.collect_expr(e, parent);
+                }
+            },
+            Expr::MethodCall { receiver, args, .. } => {
+                self.collect_expr(receiver, parent);
+                for e in args {
+                    self.collect_expr(e, parent);
+                }
+            },
+            Expr::Dot { expr, .. } | Expr::Return(expr) | Expr::Yield(expr) | Expr::Unary { expr, .. } => self.collect_expr(expr, parent),
+            Expr::Try(expr) | Expr::Propagate(expr) | Expr::Panic { message: expr, .. } => self.collect_expr(expr, parent),
+            Expr::Assert { condition, message, .. } => {
+                self.collect_expr(condition, parent);
+                if let Some(message) = message {
+                    self.collect_expr(message, parent);
+                }
+            },
+            Expr::Range { start, end, .. } => {
+                for e in start.iter().chain(end) {
+                    self.collect_expr(e, parent);
+                }
+            },
+            Expr::Let { name, mutable, let_type, value, meta } => {
+                self.collect_expr(value, parent);
+                let name_span = self.tokens[self.token_index(meta.start)..].iter()
+                                                                           .take_while(|t| t.end <= meta.end)
+                                                                           .find(|t| t.kind == TokenKind::Ident && !["let", "mut"].contains(&self.token_text(t)))
+                                                                           .map(|t| Meta { start: t.start, end: t.end });
+                let shown_type = match let_type {
+                    Type::Infer => literal_type(value),
+                    t => Some(print_type(t)),
+                };
+                let detail = format!( "let {}{}{}"
+                                    , if *mutable { "mut " } else { "" }
+                                    , name
+                                    , shown_type.map(|t| format!(" : {}", t)).unwrap_or_default()
+                                    );
+                let scope = Meta { start: meta.end, end: self.block_end(meta.start) };
+                self.define(name, SymbolKind::Local, name_span, scope, detail, parent);
+            },
+            Expr::Assign { target, value, .. } => {
+                self.collect_expr(target, parent);
+                self.collect_expr(value, parent);
+            },
+            Expr::Match { expr, arms } => {
+                self.collect_expr(expr, parent);
+                // an arm's span is its pattern, and its variables are in scope
+                // until the next arm
+                for (i, arm) in arms.iter().enumerate() {
+                    let end = arms.get(i + 1).map(|next| next.meta.start).unwrap_or_else(|| self.block_end(arm.meta.start));
+                    for name in pattern_variables(&arm.pattern) {
+                        let name_span = self.find_ident(arm.meta.start, arm.meta.end, &name);
+                        self.define(&name, SymbolKind::Local, name_span, Meta { start: arm.meta.start, end }, name.clone(), parent);
+                    }
+                    self.collect_expr(&arm.body, parent);
+                }
+            },
+            Expr::Dict(pairs) => {
+                for (k, v) in pairs {
+                    self.collect_expr(k, parent);
+                    self.collect_expr(v, parent);
+                }
+            },
+            Expr::Struct { fields, .. } => {
+                for (_, e) in fields {
+                    self.collect_expr(e, parent);
+                }
+            },
+            Expr::Lambda { params, body } => {
+                for param in params {
+                    let name_span = self.param_name(param);
+                    let scope = Meta { start: param.meta.start, end: self.block_end(param.meta.start) };
+                    self.define(&param.name, SymbolKind::Param, name_span, scope, print_param(param), parent);
+                }
+                self.collect_expr(body, parent);
+            },
+            Expr::Index { expr, index } => {
+                self.collect_expr(expr, parent);
+                self.collect_expr(index, parent);
+            },
+            Expr::Binary { left, right, .. } => {
+                self.collect_expr(left, parent);
+                self.collect_expr(right, parent);
+            },
+            Expr::If { condition, then, otherwise } => {
+                self.collect_expr(condition, parent);
+                self.collect_expr(then, parent);
+                self.collect_expr(otherwise, parent);
+            },
+            Expr::While { condition, body } => {
+                self.collect_expr(condition, parent);
+                self.collect_expr(body, parent);
+            },
+            Expr::Foreach { name, iterable, body, meta } => {
+                self.collect_expr(iterable, parent);
+                let name_span = self.find_ident(meta.start, meta.end, name).filter(|m| m.start > meta.start);
+                let scope = Meta { start: meta.start, end: self.block_end(meta.start) };
+                self.define(name, SymbolKind::Local, name_span, scope, name.clone(), parent);
+                self.collect_expr(body, parent);
+            },
+        }
+    }
+
+    /// The name an error is about, looked for where the error is when it knows, or the
+    /// whole of that when the name is not there.
+    fn check_error_span(&self, e : &CheckError) -> Meta {
+        let name = match e {
+            CheckError::AssignToImmutable { write, .. } | CheckError::MutatingCallOnImmutable { write, .. }
+            | CheckError::MutableAliasOfImmutable { write, .. }
+            | CheckError::InvalidAssignTarget { write } => return *write,
+            CheckError::NonExhaustiveMatch { meta, .. } | CheckError::UnreachableArm { meta, .. }
+            | CheckError::RedundantAlternative { meta, .. } | CheckError::NotIterable { meta, .. } => return *meta,
+            CheckError::UnknownTrait(name) | CheckError::UnknownCase { name, .. } | CheckError::CasePatternMismatch { name, .. } => name,
+            CheckError::NoMethod { name, .. } | CheckError::AmbiguousMethod { name, .. } | CheckError::DuplicateMethod { name, .. } => name,
+            CheckError::UseAfterMove { name, .. } | CheckError::DefinedByStd { name, .. } => name,
+            CheckError::UnknownImport { .. } => "std",
+            CheckError::PropagateOutsideResult { function, .. } | CheckError::PropagateMismatch { function, .. }
+            | CheckError::PropagateNonResult { function, .. } => function,
+            CheckError::NotFfiSafe { function, .. } | CheckError::TooManyExternParams { function } => function,
+            CheckError::MissingTraitItem { trait_name, .. } | CheckError::WrongTraitArity { trait_name, .. } => trait_name,
+            CheckError::ItemNotInTrait { item, .. } | CheckError::ItemKindMismatch { item, .. } | CheckError::SignatureMismatch { item, .. } => item,
+            CheckError::UnsatisfiedConstraint { trait_name, .. } | CheckError::OverlappingImpls { trait_name, .. } => trait_name,
+            CheckError::NoAssociatedType { item, .. } => item,
+            CheckError::MisplacedYield { .. } | CheckError::YieldInClosure { .. } => "yield",
+            CheckError::NotConstant { name, .. } | CheckError::ConstFailed { name, .. } | CheckError::NotAPattern { name, .. }
+            | CheckError::ArrayLengthMismatch { name, .. } => name,
+            CheckError::ConstCycle { names } => names.first().map_or("const", String::as_str),
+            CheckError::InvalidArrayLength { length } => length,
+        };
+        match e.at() {
+            Some(at) => self.find_ident(at.start, at.end, name).unwrap_or(at),
+            None => self.find_ident(0, self.text.len(), name).unwrap_or(Meta { start: 0, end: 0 }),
+        }
+    }
+
+    /// The identifier at `offset`, including when `offset` is just after it.
+    fn ident_at(&self, offset : usize) -> Option<usize> {
+        let i = self.token_index(offset + 1).checked_sub(1)?;
+        [Some(i), i.checked_sub(1)].iter()
+                                   .flatten()
+                                   .copied()
+                                   .find(|i| {
+                                       let t = self.tokens[*i];
+                                       t.kind == TokenKind::Ident && t.start <= offset && offset <= t.end
+                                   })
+    }
+
+    fn previous(&self, i : usize) -> Option<&str> {
+        self.tokens[..i].iter().rev().find(|t| t.kind != TokenKind::Comment).map(|t| self.token_text(t))
+    }
+
+    fn visible_locals(&self, offset : usize) -> Vec<usize> {
+        let mut locals = (0..self.definitions.len()).filter(|d| {
+            let d = &self.definitions[*d];
+            matches!( d.kind, SymbolKind::Param | SymbolKind::Local )
+                && d.scope.start <= offset && offset <= d.scope.end && d.name_span.end <= offset
+        }).collect::<Vec<_>>();
+        locals.sort_by_key(|d| std::cmp::Reverse(self.definitions[*d].name_span.start));
+        locals
+    }
+
+    fn find_kind(&self, name : &str, kinds : &[SymbolKind]) -> Option<usize> {
+        kinds.iter().find_map(|k| self.definitions.iter().position(|d| d.kind == *k && d.name == name))
+    }
+
+    fn resolve(&self, i : usize) -> Option<usize> {
+        let t = self.tokens[i];
+        if let Some(d) = self.definitions.iter().position(|d| d.name_span.start == t.start) {
+            return Some(d);
+        }
+
+        let name = self.token_text(&t);
+        if KEYWORDS.contains(&name) {
+            return None;
+        }
+        let called = self.tokens.get(i + 1).map(|t| self.token_text(t)) == Some("(");
+        match self.previous(i) {
+            Some(".") if called => self.find_kind(name, &[SymbolKind::Method, SymbolKind::Field]),
+            Some(".") => self.find_kind(name, &[SymbolKind::Field, SymbolKind::Method]),
+            Some("::") => {
+                let owner = i.checked_sub(2).map(|o| self.token_text(&self.tokens[o]));
+                self.definitions.iter()
+                                .position(|d| d.kind == SymbolKind::Case && d.name == name
+                                              && d.parent.map(|p| self.definitions[p].name.as_str()) == owner)
+                                .or_else(|| self.find_kind(name, &[SymbolKind::Case, SymbolKind::Fun, SymbolKind::Const, SymbolKind::Struct, SymbolKind::Enum, SymbolKind::Trait]))
+            },
+            _ => self.visible_locals(t.start)
+                     .into_iter()
+                     .find(|d| self.definitions[*d].name == name)
+                     .or_else(|| self.find_kind(name, &[ SymbolKind::Fun, SymbolKind::Const, SymbolKind::Struct, SymbolKind::Enum, SymbolKind::Trait
+                                                       , SymbolKind::Mod, SymbolKind::Case, SymbolKind::Field, SymbolKind::Method
+                                                       ])),
+        }
+    }
+
+    /// The definition of the name at `offset`.
+    pub fn definition_at(&self, offset : usize) -> Option<usize> {
+        self.resolve(self.ident_at(offset)?)
+    }
+
+    /// Every place which names the definition, including the definition itself.
+    pub fn references(&self, definition : usize) -> Vec<Meta> {
+        let name = &self.definitions[definition].name;
+        (0..self.tokens.len()).filter(|i| self.tokens[*i].kind == TokenKind::Ident && self.token_text(&self.tokens[*i]) == name)
+                              .filter(|i| self.resolve(*i) == Some(definition))
+                              .map(|i| Meta { start: self.tokens[i].start, end: self.tokens[i].end })
+                              .collect()
+    }
+
+    /// Markdown describing the name at `offset`, along with where that name is.
+    pub fn hover(&self, offset : usize) -> Option<(Meta, String)> {
+        let i = self.ident_at(offset)?;
+        let d = &self.definitions[self.resolve(i)?];
+        let mut text = format!("```dust\n{}\n```", d.detail);
+        if let Some(doc) = &d.doc {
+            text.push_str("\n\n");
+            text.push_str(doc);
+        }
+        let t = self.tokens[i];
+        Some((Meta { start: t.start, end: t.end }, text))
+    }
+
+    /// The top level `fun`, `struct`, `enum` and `trait` items, each with its
+    /// fields, cases or methods.
+    pub fn symbols(&self) -> Vec<(usize, Vec<usize>)> {
+        let top = |d : &Definition| d.parent.is_none() && matches!( d.kind, SymbolKind::Fun | SymbolKind::Struct | SymbolKind::Enum | SymbolKind::Trait );
+        let member = |d : &Definition| matches!( d.kind, SymbolKind::Field | SymbolKind::Case | SymbolKind::Method );
+        (0..self.definitions.len()).filter(|d| top(&self.definitions[*d]))
+                                   .map(|d| (d, (0..self.definitions.len()).filter(|c| self.definitions[*c].parent == Some(d) && member(&self.definitions[*c]))
+                                                                           .collect()))
+                                   .collect()
+    }
+
+    /// Names which may be written at `offset`.  `modules` finds the source of
+    /// a module from its path, for completing `use` paths.
+    pub fn completions(&self, offset : usize, modules : &dyn Fn(&[String]) -> Option<String>) -> Vec<Completion> {
+        let (prefix, i) = match self.ident_at(offset) {
+            Some(i) if self.tokens[i].start < offset => (&self.text[self.tokens[i].start..offset], i),
+            _ => ("", self.token_index(offset)),
+        };
+
+        let mut candidates = vec![];
+        let previous = self.previous(i);
+        let in_use = self.tokens[..i].iter()
+                                     .rev()
+                                     .map(|t| self.token_text(t))
+                                     .take_while(|t| *t != ";" && *t != "}")
+                                     .any(|t| t == "use");
+
+        let path_end = match previous {
+            Some("::") => Some(i - 1),
+            Some("{") | Some(",") if in_use => self.tokens[..i].iter().rposition(|t| self.token_text(t) == "{")
+                                                                      .filter(|b| *b > 0 && self.token_text(&self.tokens[b - 1]) == "::")
+                                                                      .map(|b| b - 1),
+            _ => None,
+        };
+
+        if let Some(end) = path_end {
+            let mut path = vec![];
+            let mut j = end;
+            while j > 0 && self.tokens[j - 1].kind == TokenKind::Ident && self.token_text(&self.tokens[j - 1]) != "use" {
+                path.insert(0, self.token_text(&self.tokens[j - 1]).to_string());
+                if j >= 2 && self.token_text(&self.tokens[j - 2]) == "::" {
+                    j -= 2;
+                }
+                else {
+                    break;
+                }
+            }
+            self.path_completions(&path, modules, &mut candidates);
+        }
+        else if previous == Some(".") {
+            for d in &self.definitions {
+                if matches!( d.kind, SymbolKind::Field | SymbolKind::Method ) {
+                    candidates.push(Completion { label: d.name.clone(), kind: Some(d.kind), detail: d.detail.clone() });
+                }
+            }
+        }
+        else if in_use {
+            for d in &self.definitions {
+                if matches!( d.kind, SymbolKind::Mod | SymbolKind::Enum ) {
+                    candidates.push(Completion { label: d.name.clone(), kind: Some(d.kind), detail: d.detail.clone() });
+                }
+            }
+        }
+        else {
+            for d in self.visible_locals(offset) {
+                let d = &self.definitions[d];
+                candidates.push(Completion { label: d.name.clone(), kind: Some(d.kind), detail: d.detail.clone() });
+            }
+            for d in &self.definitions {
+                if d.parent.is_none() && matches!( d.kind, SymbolKind::Fun | SymbolKind::Const | SymbolKind::Struct | SymbolKind::Enum | SymbolKind::Trait | SymbolKind::Mod ) {
+                    candidates.push(Completion { label: d.name.clone(), kind: Some(d.kind), detail: d.detail.clone() });
+                }
+            }
+            for name in BUILTIN_FUNS {
+                candidates.push(Completion { label: name.to_string(), kind: Some(SymbolKind::Fun), detail: format!("fun {}(value)", name) });
+            }
+            for keyword in &KEYWORDS {
+                candidates.push(Completion { label: keyword.to_string(), kind: None, detail: "keyword".to_string() });
+            }
+        }
+
+        let mut seen = vec![];
+        candidates.retain(|c| {
+            let keep = c.label.starts_with(prefix) && !seen.contains(&c.label);
+            seen.push(c.label.clone());
+            keep
+        });
+        candidates
+    }
+
+    fn path_completions(&self, path : &[String], modules : &dyn Fn(&[String]) -> Option<String>, candidates : &mut Vec<Completion>) {
+        let last = match path.last() {
+            Some(last) => last,
+            None => return,
+        };
+
+        if let Some(e) = self.definitions.iter().position(|d| d.kind == SymbolKind::Enum && &d.name == last) {
+            for d in self.definitions.iter().filter(|d| d.parent == Some(e) && d.kind == SymbolKind::Case) {
+                candidates.push(Completion { label: d.name.clone(), kind: Some(d.kind), detail: d.detail.clone() });
+            }
+            return;
+        }
+
+        for split in (1..=path.len()).rev() {
+            if let Some(source) = modules(&path[..split]) {
+                let module = Analysis::new(&source);
+                if split == path.len() {
+                    for d in module.definitions.iter().filter(|d| d.parent.is_none() && !matches!( d.kind, SymbolKind::Param | SymbolKind::Local | SymbolKind::Method )) {
+                        candidates.push(Completion { label: d.name.clone(), kind: Some(d.kind), detail: d.detail.clone() });
+                    }
+                }
+                else {
+                    module.path_completions(&path[split..], &|rest : &[String]| {
+                        let mut full = path[..split].to_vec();
+                        full.extend_from_slice(rest);
+                        modules(&full)
+                    }, candidates);
+                }
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::incremental::Edit;
+
+    const SOURCE : &str = r#"
+/* A shape. */
+enum Shape { Circle(Int), Rect { w : Int, h : Int } }
+
+struct Point { x : Int, y : Int }
+
+trait Area { fun area(self : Self) -> Int; }
+
+impl Area for Point {
+    fun area(self : Self) -> Int { self.x * self.y }
+}
+
+/* Adds things
+ * up. */
+fun total(points : List<Point>, scale : Int) -> Int {
+    let mut sum = 0;
+    let x = |p : Point| p.area() * scale;
+    match Shape::Circle(1) {
+        Shape::Circle(r) => r + sum,
+        Shape::Rect { w, h } => w * h,
+    };
+    let sum = sum + 1;
+    sum
+}
+"#;
+
+    fn offset(text : &str, needle : &str, nth : usize) -> usize {
+        text.match_indices(needle).nth(nth).unwrap().0
+    }
+
+    fn definition(a : &Analysis, needle : &str, nth : usize) -> Option<(SymbolKind, usize)> {
+        a.definition_at(offset(&a.text, needle, nth)).map(|d| (a.definitions[d].kind, a.definitions[d].name_span.start))
+    }
+
+    #[test]
+    fn should_tokenize_around_strings_and_nested_comments() {
+        let text = "a./* x /* y */ z */\"b\\\"c\" 1.5 x.0 ::";
+        let tokens = tokenize(text).iter().map(|t| (t.kind, &text[t.start..t.end])).collect::<Vec<_>>();
+        assert_eq!( tokens, vec![ (TokenKind::Ident, "a"), (TokenKind::Punct, "."), (TokenKind::Comment, "/* x /* y */ z */")
+                                , (TokenKind::String, "\"b\\\"c\""), (TokenKind::Number, "1.5"), (TokenKind::Ident, "x")
+                                , (TokenKind::Punct, "."), (TokenKind::Number, "0"), (TokenKind::Punct, "::")
+                                ] );
+    }
+
+    #[test]
+    fn should_resolve_names_by_scope() {
+        let a = Analysis::new(SOURCE);
+        assert!( a.diagnostics.iter().all(|d| d.severity == Severity::Warning), "{:?}", a.diagnostics );
+
+        let sum = offset(SOURCE, "sum", 0);
+        let shadowing_sum = offset(SOURCE, "sum", 2);
+        assert_eq!( definition(&a, "r + sum", 0).map(|(k, _)| k), Some(SymbolKind::Local) );
+        assert_eq!( definition(&a, "sum,", 0), Some((SymbolKind::Local, sum)) );
+        assert_eq!( definition(&a, "sum + 1", 0), Some((SymbolKind::Local, sum)) );
+        assert_eq!( definition(&a, "sum\n}", 0), Some((SymbolKind::Local, shadowing_sum)) );
+        assert_eq!( definition(&a, "scale;", 0), Some((SymbolKind::Param, offset(SOURCE, "scale", 0))) );
+        assert_eq!( definition(&a, "p.area", 0), Some((SymbolKind::Param, offset(SOURCE, "p :", 0))) );
+        assert_eq!( definition(&a, "area() * scale", 0), Some((SymbolKind::Method, offset(SOURCE, "area", 0))) );
+        assert_eq!( definition(&a, "x * self", 0), Some((SymbolKind::Field, offset(SOURCE, "x :", 0))) );
+        assert_eq!( definition(&a, "Circle(1)", 0), Some((SymbolKind::Case, offset(SOURCE, "Circle", 0))) );
+        assert_eq!( definition(&a, "w * h", 0), Some((SymbolKind::Local, offset(SOURCE, "w, h }", 0))) );
+        assert_eq!( definition(&a, "Point>", 0), Some((SymbolKind::Struct, offset(SOURCE, "Point", 0))) );
+        assert_eq!( definition(&a, "let", 0), None );
+    }
+
+    #[test]
+    fn should_find_references_and_hover() {
+        let a = Analysis::new(SOURCE);
+        let sum = a.definition_at(offset(SOURCE, "sum", 0)).unwrap();
+        let references = a.references(sum).iter().map(|m| m.start).collect::<Vec<_>>();
+        assert_eq!( references, vec![offset(SOURCE, "sum", 0), offset(SOURCE, "sum", 1), offset(SOURCE, "sum", 3)] );
+
+        let (span, text) = a.hover(offset(SOURCE, "total", 0) + 2).unwrap();
+        assert_eq!( span.start, offset(SOURCE, "total", 0) );
+        assert_eq!( text, "```dust\nfun total(points : List<Point>, scale : Int) -> Int\n```\n\nAdds things\nup." );
+        assert_eq!( a.hover(offset(SOURCE, "sum,", 0)).unwrap().1, "```dust\nlet mut sum : Int\n```" );
+        assert_eq!( a.hover(offset(SOURCE, "Shape {", 0)).unwrap().1, "```dust\nenum Shape\n```\n\nA shape." );
+    }
+
+    #[test]
+    fn should_list_symbols_with_their_members() {
+        let a = Analysis::new(SOURCE);
+        let symbols = a.symbols().into_iter().map(|(d, children)| {
+            (a.definitions[d].name.as_str(), children.into_iter().map(|c| a.definitions[c].name.as_str()).collect::<Vec<_>>())
+        }).collect::<Vec<_>>();
+        assert_eq!( symbols, vec![ ("Shape", vec!["Circle", "Rect"])
+                                 , ("Point", vec!["x", "y"])
+                                 , ("Area", vec!["area"])
+                                 , ("total", vec![])
+                                 ] );
+    }
+
+    #[test]
+    fn should_complete_names_in_scope() {
+        let text = "enum E { Alpha, Beta }\nfun main() { let apple = 1; a }\nfun f() { E:: }\nuse E::{ }";
+        let a = Analysis::new(text);
+        let none = |_ : &[String]| None;
+        let labels = |offset| a.completions(offset, &none).into_iter().map(|c| c.label).collect::<Vec<_>>();
+
+        assert_eq!( labels(offset(text, "; a }", 0) + 3), vec!["apple", "assert"] );
+        assert_eq!( labels(offset(text, "E:: }", 0) + 3), vec!["Alpha", "Beta"] );
+        assert_eq!( labels(offset(text, "{ }", 0) + 2), vec!["Alpha", "Beta"] );
+
+        let elsewhere = labels(offset(text, "{ E", 0) + 2);
+        assert!( elsewhere.contains(&"main".to_string()) && elsewhere.contains(&"println".to_string()) );
+        assert!( !elsewhere.contains(&"apple".to_string()) );
+    }
+
+    #[test]
+    fn should_complete_use_paths_from_modules() {
+        let text = "mod geometry;\nuse geometry::shapes::";
+        let a = Analysis::new(text);
+        let modules = |path : &[String]| match path.join("/").as_str() {
+            "geometry" => Some("mod shapes;".to_string()),
+            "geometry/shapes" => Some("struct Square { s : Int }\nfun area() { }".to_string()),
+            _ => None,
+        };
+        let labels = a.completions(text.len(), &modules).into_iter().map(|c| c.label).collect::<Vec<_>>();
+        assert_eq!( labels, vec!["Square", "area"] );
+
+        let labels = a.completions(offset(text, "geometry::", 0) + 3, &modules).into_iter().map(|c| c.label).collect::<Vec<_>>();
+        assert_eq!( labels, vec!["geometry"] );
+    }
+
+    #[test]
+    fn should_keep_definitions_of_items_which_parse() {
+        let text = "fun good() { }\nfun bad( { }\nstruct S { x : Int }";
+        let a = Analysis::new(text);
+        assert_eq!( a.diagnostics.len(), 1 );
+        assert_eq!( a.diagnostics[0].severity, Severity::Error );
+        assert!( a.diagnostics[0].span.start > offset(text, "bad", 0) );
+        let names = a.definitions.iter().map(|d| d.name.as_str()).collect::<Vec<_>>();
+        assert_eq!( names, vec!["good", "S", "x"] );
+    }
+
+    #[test]
+    fn should_report_check_errors_where_they_are() {
+        let text = "fun main() {\n    let x = 1;\n    x = 2;\n}";
+        let a = Analysis::new(text);
+        assert_eq!( a.diagnostics.len(), 1 );
+        assert_eq!( &text[a.diagnostics[0].span.start..a.diagnostics[0].span.end], "x = 2" );
+        assert!( a.diagnostics[0].message.starts_with("check error: AssignToImmutable") );
+    }
+
+    #[test]
+    fn should_analyse_edited_documents_like_their_text() {
+        let mut document = Document::new("fun main() {\n    let x = 1;\n}\nstruct S { x : Int }");
+        let at = document.text().find("1;").unwrap();
+        document.edit(&Edit { offset: at + 2, removed: 0, inserted: "\n    x = 2;".to_string() });
+
+        let edited = Analysis::of(&document);
+        let fresh = Analysis::new(document.text());
+        assert_eq!( edited.diagnostics, fresh.diagnostics );
+        assert_eq!( edited.diagnostics.len(), 1 );
+        let names = |a : &Analysis| a.definitions.iter().map(|d| (d.name.clone(), d.span)).collect::<Vec<_>>();
+        assert_eq!( names(&edited), names(&fresh) );
+    }
+}
@@ -0,0 +1,296 @@
+
+use std::fmt;
+
+use crate::parsing::dump::write_string;
+
+/* JSON values for the JSON-RPC messages of the language server.  Objects keep
+   the order of their members so that replies are written the same way every
+   time.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct JsonError {
+    pub position : usize,
+    pub message : String,
+}
+
+impl Json {
+    pub fn object(members : Vec<(&str, Json)>) -> Json {
+        Json::Object(members.into_iter().map(|(n, v)| (n.to_string(), v)).collect())
+    }
+
+    pub fn string(s : &str) -> Json {
+        Json::String(s.to_string())
+    }
+
+    pub fn number(n : usize) -> Json {
+        Json::Number(n as f64)
+    }
+
+    pub fn get(&self, name : &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Follows a path of member names.
+    pub fn at(&self, path : &[&str]) -> Option<&Json> {
+        path.iter().try_fold(self, |json, name| json.get(name))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as usize),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        write(self, &mut out);
+        f.write_str(&out)
+    }
+}
+
+fn write(json : &Json, out : &mut String) {
+    match json {
+        Json::Null => out.push_str("null"),
+        Json::Bool(b) => out.push_str(&b.to_string()),
+        Json::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => out.push_str(&(*n as i64).to_string()),
+        Json::Number(n) => out.push_str(&n.to_string()),
+        Json::String(s) => write_string(s, out),
+        Json::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write(item, out);
+            }
+            out.push(']');
+        },
+        Json::Object(members) => {
+            out.push('{');
+            for (i, (name, value)) in members.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(name, out);
+                out.push(':');
+                write(value, out);
+            }
+            out.push('}');
+        },
+    }
+}
+
+/// How deeply arrays and objects can be nested, which no message of the
+/// protocol comes near but which keeps the reader from running out of stack.
+pub const MAX_DEPTH : usize = 256;
+
+pub fn parse(text : &str) -> Result<Json, JsonError> {
+    let mut reader = Reader { text, position: 0, depth: 0 };
+    let json = reader.value()?;
+    match reader.peek() {
+        None => Ok(json),
+        Some(_) => reader.error("expected the end of the input"),
+    }
+}
+
+struct Reader<'a> {
+    text : &'a str,
+    position : usize,
+    depth : usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error<T>(&self, message : &str) -> Result<T, JsonError> {
+        Err(JsonError { position: self.position, message: message.to_string() })
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+        self.text[self.position..].chars().next()
+    }
+
+    fn expect(&mut self, c : char) -> Result<(), JsonError> {
+        if self.peek() == Some(c) {
+            self.position += 1;
+            Ok(())
+        }
+        else {
+            self.error(&format!("expected {}", c))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        match self.peek() {
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('[') | Some('{') if self.depth == MAX_DEPTH => self.error("nested too deeply"),
+            Some('[') => {
+                self.expect('[')?;
+                let mut items = vec![];
+                if self.peek() == Some(']') {
+                    self.position += 1;
+                    return Ok(Json::Array(items));
+                }
+                self.depth += 1;
+                loop {
+                    items.push(self.value()?);
+                    match self.peek() {
+                        Some(',') => self.position += 1,
+                        _ => break,
+                    }
+                }
+                self.depth -= 1;
+                self.expect(']')?;
+                Ok(Json::Array(items))
+            },
+            Some('{') => {
+                self.expect('{')?;
+                let mut members = vec![];
+                if self.peek() == Some('}') {
+                    self.position += 1;
+                    return Ok(Json::Object(members));
+                }
+                self.depth += 1;
+                loop {
+                    let name = self.string()?;
+                    self.expect(':')?;
+                    members.push((name, self.value()?));
+                    match self.peek() {
+                        Some(',') => self.position += 1,
+                        _ => break,
+                    }
+                }
+                self.depth -= 1;
+                self.expect('}')?;
+                Ok(Json::Object(members))
+            },
+            Some(_) => {
+                let rest = &self.text[self.position..];
+                let end = rest.find(|c : char| !(c.is_ascii_alphanumeric() || "+-.".contains(c))).unwrap_or(rest.len());
+                let word = &rest[..end];
+                let json = match word {
+                    "null" => Json::Null,
+                    "true" => Json::Bool(true),
+                    "false" => Json::Bool(false),
+                    _ => match word.parse::<f64>() {
+                        Ok(n) if word.starts_with(|c : char| c == '-' || c.is_ascii_digit()) => Json::Number(n),
+                        _ => return self.error(&format!("unexpected {}", word)),
+                    },
+                };
+                self.position += end;
+                Ok(json)
+            },
+            None => self.error("unexpected end of input"),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut out = String::new();
+        let mut chars = self.text[self.position..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += i + 1;
+                    return Ok(out);
+                },
+                '\\' => {
+                    let c = match chars.next().map(|(_, c)| c) {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => {
+                            let high = hex(&mut chars);
+                            let code = match high {
+                                Some(high @ 0xd800..=0xdbff) => match (chars.next(), chars.next(), hex(&mut chars)) {
+                                    (Some((_, '\\')), Some((_, 'u')), Some(low @ 0xdc00..=0xdfff)) => Some(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)),
+                                    _ => None,
+                                },
+                                code => code,
+                            };
+                            match code.and_then(char::from_u32) {
+                                Some(c) => c,
+                                None => return self.error("invalid \\u escape"),
+                            }
+                        },
+                        _ => return self.error("invalid escape"),
+                    };
+                    out.push(c);
+                },
+                c => out.push(c),
+            }
+        }
+        self.error("unterminated string")
+    }
+}
+
+fn hex(chars : &mut std::str::CharIndices) -> Option<u32> {
+    let digits = (0..4).map(|_| chars.next().map(|(_, c)| c)).collect::<Option<String>>()?;
+    u32::from_str_radix(&digits, 16).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_parse_and_write_messages() {
+        let text = r#"{"jsonrpc":"2.0","id":1,"params":{"position":{"line":3,"character":-1.5},"ok":[true,false,null],"text":"a\"\né😀"}}"#;
+        let json = parse(&format!(" {} ", text)).unwrap();
+
+        assert_eq!( json.get("id").and_then(Json::as_usize), Some(1) );
+        assert_eq!( json.at(&["params", "position", "line"]).and_then(Json::as_usize), Some(3) );
+        assert_eq!( json.at(&["params", "position", "character"]), Some(&Json::Number(-1.5)) );
+        assert_eq!( json.at(&["params", "text"]).and_then(Json::as_str), Some("a\"\né😀") );
+        assert_eq!( json.to_string(), text );
+    }
+
+    #[test]
+    fn should_report_malformed_json() {
+        let nested = |depth : usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!( parse(&nested(MAX_DEPTH)).is_ok() );
+        assert_eq!( parse(&nested(MAX_DEPTH + 1)).unwrap_err().position, MAX_DEPTH );
+        assert_eq!( parse(&"[".repeat(200000)).unwrap_err().message, "nested too deeply" );
+        assert_eq!( parse("[1,]").unwrap_err().position, 3 );
+        assert_eq!( parse("{\"a\" 1}").unwrap_err().position, 5 );
+        assert!( parse("\"abc").is_err() );
+        assert!( parse("1 2").is_err() );
+        assert!( parse("nul").is_err() );
+    }
+}
@@ -0,0 +1,3 @@
+pub mod json;
+pub mod analysis;
+pub mod server;
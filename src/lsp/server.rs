@@ -0,0 +1,586 @@
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+use std::path::PathBuf;
+
+use crate::parsing::ast::Meta;
+use crate::parsing::incremental::{Document, Edit};
+use super::json::{self, Json};
+use super::analysis::{Analysis, Completion, Severity, SymbolKind, is_valid_name};
+
+/* A language server speaking JSON-RPC over standard input and output, with
+   messages framed by `Content-Length` headers.  Documents are kept in memory,
+   parsed again only around each edit to a range a change sends, and analysed
+   again after every change.
+
+   Positions are lines and UTF-16 code units, as the protocol wants, and are
+   turned into byte offsets on the way in and back on the way out.
+*/
+
+const PARSE_ERROR : i64 = -32700;
+const INVALID_REQUEST : i64 = -32600;
+const METHOD_NOT_FOUND : i64 = -32601;
+const INVALID_PARAMS : i64 = -32602;
+
+/// The largest message body the server reads.
+pub const MAX_MESSAGE : usize = 64 * 1024 * 1024;
+
+pub struct Server {
+    sources : HashMap<String, Document>,
+    documents : HashMap<String, Analysis>,
+    shutting_down : bool,
+    exit_code : Option<i32>,
+}
+
+/// The exit code for when the input ends without an `exit` notification.
+pub const EXIT_WITHOUT_EXIT : i32 = 1;
+
+/// Serves messages from `input` until the client asks the server to exit, and
+/// returns the exit code the protocol asks for.
+pub fn run(input : &mut dyn BufRead, output : &mut dyn Write) -> io::Result<i32> {
+    let mut server = Server::new();
+
+    loop {
+        let replies = match read_message(input) {
+            Ok(Some(body)) => match json::parse(&body) {
+                Ok(message) => server.handle(&message),
+                Err(e) => vec![error(Json::Null, PARSE_ERROR, &format!("{} at byte {}", e.message, e.position))],
+            },
+            Ok(None) => break,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => vec![error(Json::Null, INVALID_REQUEST, &e.to_string())],
+            Err(e) => return Err(e),
+        };
+        for reply in replies {
+            write_message(output, &reply)?;
+        }
+        if let Some(code) = server.exit_code {
+            return Ok(code);
+        }
+    }
+
+    Ok(EXIT_WITHOUT_EXIT)
+}
+
+/// Reads the body of the next message, or `None` at the end of the input.  A
+/// body over `MAX_MESSAGE` is skipped and is an `InvalidData` error.
+pub fn read_message(input : &mut dyn BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length.unwrap_or(0);
+    if length > MAX_MESSAGE {
+        io::copy(&mut Read::take(&mut *input, length as u64), &mut io::sink())?;
+        let message = format!("a message of {} bytes is over the {} bytes the server reads", length, MAX_MESSAGE);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8_lossy(&body).to_string()))
+}
+
+pub fn write_message(output : &mut dyn Write, message : &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn response(id : Json, result : Json) -> Json {
+    Json::object(vec![("jsonrpc", Json::string("2.0")), ("id", id), ("result", result)])
+}
+
+fn error(id : Json, code : i64, message : &str) -> Json {
+    let error = Json::object(vec![("code", Json::Number(code as f64)), ("message", Json::string(message))]);
+    Json::object(vec![("jsonrpc", Json::string("2.0")), ("id", id), ("error", error)])
+}
+
+fn notification(method : &str, params : Json) -> Json {
+    Json::object(vec![("jsonrpc", Json::string("2.0")), ("method", Json::string(method)), ("params", params)])
+}
+
+/// The byte offset of a line and UTF-16 column, clamped to the text.
+pub fn offset_of(text : &str, line : usize, character : usize) -> usize {
+    let mut offset = 0;
+    for _ in 0..line {
+        match text[offset..].find('\n') {
+            Some(i) => offset += i + 1,
+            None => return text.len(),
+        }
+    }
+    let mut units = 0;
+    for (i, c) in text[offset..].char_indices() {
+        if units >= character || c == '\n' {
+            return offset + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+/// The line and UTF-16 column of a byte offset.
+pub fn position_of(text : &str, offset : usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (before.matches('\n').count(), before[line_start..].encode_utf16().count())
+}
+
+fn position(text : &str, offset : usize) -> Json {
+    let (line, character) = position_of(text, offset);
+    Json::object(vec![("line", Json::number(line)), ("character", Json::number(character))])
+}
+
+fn range(text : &str, span : Meta) -> Json {
+    Json::object(vec![("start", position(text, span.start)), ("end", position(text, span.end))])
+}
+
+fn symbol_kind(kind : SymbolKind) -> usize {
+    match kind {
+        SymbolKind::Mod => 2,
+        SymbolKind::Method => 6,
+        SymbolKind::Field => 8,
+        SymbolKind::Enum => 10,
+        SymbolKind::Trait => 11,
+        SymbolKind::Fun => 12,
+        SymbolKind::Param | SymbolKind::Local => 13,
+        SymbolKind::Const => 14,
+        SymbolKind::Case => 22,
+        SymbolKind::Struct => 23,
+    }
+}
+
+fn completion_kind(kind : Option<SymbolKind>) -> usize {
+    match kind {
+        Some(SymbolKind::Method) => 2,
+        Some(SymbolKind::Fun) => 3,
+        Some(SymbolKind::Field) => 5,
+        Some(SymbolKind::Param) | Some(SymbolKind::Local) => 6,
+        Some(SymbolKind::Trait) => 8,
+        Some(SymbolKind::Mod) => 9,
+        Some(SymbolKind::Enum) => 13,
+        Some(SymbolKind::Case) => 20,
+        Some(SymbolKind::Const) => 21,
+        Some(SymbolKind::Struct) => 22,
+        None => 14,
+    }
+}
+
+/// The path of a `file:` URI.
+fn uri_path(uri : &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let bytes = path.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], path.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            },
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            },
+        }
+    }
+    Some(PathBuf::from(String::from_utf8(decoded).ok()?))
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server { sources: HashMap::new(), documents: HashMap::new(), shutting_down: false, exit_code: None }
+    }
+
+    /// Handles one message, returning the response and any notifications.
+    pub fn handle(&mut self, message : &Json) -> Vec<Json> {
+        let method = match message.get("method").and_then(Json::as_str) {
+            Some(method) => method,
+            // a response to a request from the server, which never sends any
+            None => return vec![],
+        };
+        let null = Json::Null;
+        let params = message.get("params").unwrap_or(&null);
+
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return self.notify(method, params),
+        };
+
+        if self.shutting_down {
+            return vec![error(id, INVALID_REQUEST, "the server is shutting down")];
+        }
+
+        let result = match method {
+            "initialize" => Some(Ok(capabilities())),
+            "shutdown" => {
+                self.shutting_down = true;
+                Some(Ok(Json::Null))
+            },
+            "textDocument/definition" => self.with_position(params, |a, uri, offset| Ok(definition(a, uri, offset))),
+            "textDocument/hover" => self.with_position(params, |a, _, offset| Ok(hover(a, offset))),
+            "textDocument/completion" => self.with_position(params, |a, uri, offset| Ok(self.completion(a, uri, offset))),
+            "textDocument/rename" => self.with_position(params, |a, uri, offset| {
+                let name = params.get("newName").and_then(Json::as_str).unwrap_or("");
+                rename(a, uri, offset, name)
+            }),
+            "textDocument/documentSymbol" => self.document(params).map(|(a, _)| Ok(symbols(a))),
+            _ => return vec![error(id, METHOD_NOT_FOUND, &format!("unknown method {}", method))],
+        };
+
+        match result {
+            Some(Ok(result)) => vec![response(id, result)],
+            Some(Err(message)) => vec![error(id, INVALID_PARAMS, &message)],
+            None => vec![error(id, INVALID_PARAMS, "unknown document or missing position")],
+        }
+    }
+
+    fn notify(&mut self, method : &str, params : &Json) -> Vec<Json> {
+        let uri = params.at(&["textDocument", "uri"]).and_then(Json::as_str).map(str::to_string);
+        match (method, uri) {
+            ("exit", _) => {
+                self.exit_code = Some(if self.shutting_down { 0 } else { 1 });
+                vec![]
+            },
+            ("textDocument/didOpen", Some(uri)) => {
+                let text = params.at(&["textDocument", "text"]).and_then(Json::as_str).unwrap_or("");
+                let document = Document::new(text);
+                self.documents.insert(uri.clone(), Analysis::of(&document));
+                self.sources.insert(uri.clone(), document);
+                vec![self.diagnostics(&uri)]
+            },
+            ("textDocument/didChange", Some(uri)) => {
+                let document = match self.sources.get_mut(&uri) {
+                    Some(document) => document,
+                    None => return vec![],
+                };
+                for change in params.get("contentChanges").and_then(Json::as_array).unwrap_or(&[]) {
+                    let new_text = change.get("text").and_then(Json::as_str).unwrap_or("");
+                    match change.get("range") {
+                        Some(r) => {
+                            let at = |end : &str| offset_of( document.text()
+                                                           , r.at(&[end, "line"]).and_then(Json::as_usize).unwrap_or(0)
+                                                           , r.at(&[end, "character"]).and_then(Json::as_usize).unwrap_or(0)
+                                                           );
+                            let (start, end) = (at("start"), at("end"));
+                            document.edit(&Edit { offset: start, removed: end.max(start) - start, inserted: new_text.to_string() });
+                        },
+                        None => *document = Document::new(new_text),
+                    }
+                }
+                self.documents.insert(uri.clone(), Analysis::of(document));
+                vec![self.diagnostics(&uri)]
+            },
+            ("textDocument/didClose", Some(uri)) => {
+                self.sources.remove(&uri);
+                self.documents.remove(&uri);
+                let params = Json::object(vec![("uri", Json::String(uri)), ("diagnostics", Json::Array(vec![]))]);
+                vec![notification("textDocument/publishDiagnostics", params)]
+            },
+            _ => vec![],
+        }
+    }
+
+    fn diagnostics(&self, uri : &str) -> Json {
+        let analysis = &self.documents[uri];
+        let diagnostics = analysis.diagnostics.iter().map(|d| {
+            let severity = match d.severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+            };
+            Json::object(vec![ ("range", range(&analysis.text, d.span))
+                             , ("severity", Json::number(severity))
+                             , ("source", Json::string("dust"))
+                             , ("message", Json::string(&d.message))
+                             ])
+        }).collect();
+        notification("textDocument/publishDiagnostics", Json::object(vec![("uri", Json::string(uri)), ("diagnostics", Json::Array(diagnostics))]))
+    }
+
+    fn document<'a>(&'a self, params : &'a Json) -> Option<(&'a Analysis, &'a str)> {
+        let uri = params.at(&["textDocument", "uri"]).and_then(Json::as_str)?;
+        Some((self.documents.get(uri)?, uri))
+    }
+
+    fn with_position<F>(&self, params : &Json, f : F) -> Option<Result<Json, String>>
+        where F : FnOnce(&Analysis, &str, usize) -> Result<Json, String> {
+
+        let (analysis, uri) = self.document(params)?;
+        let line = params.at(&["position", "line"]).and_then(Json::as_usize)?;
+        let character = params.at(&["position", "character"]).and_then(Json::as_usize)?;
+        Some(f(analysis, uri, offset_of(&analysis.text, line, character)))
+    }
+
+    fn completion(&self, analysis : &Analysis, uri : &str, offset : usize) -> Json {
+        let dir = uri_path(uri).and_then(|p| p.parent().map(|d| d.to_path_buf()));
+        let modules = |path : &[String]| -> Option<String> {
+            let file = dir.as_ref()?.join(path.join("/")).with_extension("ds");
+            let open = self.documents.iter().find(|(u, _)| uri_path(u).as_ref() == Some(&file));
+            match open {
+                Some((_, a)) => Some(a.text.clone()),
+                None => std::fs::read_to_string(file).ok(),
+            }
+        };
+        let items = analysis.completions(offset, &modules).into_iter().map(|Completion { label, kind, detail }| {
+            Json::object(vec![ ("label", Json::String(label))
+                             , ("kind", Json::number(completion_kind(kind)))
+                             , ("detail", Json::String(detail))
+                             ])
+        }).collect();
+        Json::Array(items)
+    }
+}
+
+impl Default for Server {
+    fn default() -> Server {
+        Server::new()
+    }
+}
+
+fn capabilities() -> Json {
+    let trigger = Json::Array(vec![Json::string("."), Json::string(":")]);
+    let capabilities = Json::object(vec![ ("textDocumentSync", Json::object(vec![("openClose", Json::Bool(true)), ("change", Json::number(2))]))
+                                        , ("definitionProvider", Json::Bool(true))
+                                        , ("hoverProvider", Json::Bool(true))
+                                        , ("documentSymbolProvider", Json::Bool(true))
+                                        , ("completionProvider", Json::object(vec![("triggerCharacters", trigger)]))
+                                        , ("renameProvider", Json::Bool(true))
+                                        ]);
+    let info = Json::object(vec![("name", Json::string("dust")), ("version", Json::string(env!("CARGO_PKG_VERSION")))]);
+    Json::object(vec![("capabilities", capabilities), ("serverInfo", info)])
+}
+
+fn definition(analysis : &Analysis, uri : &str, offset : usize) -> Json {
+    match analysis.definition_at(offset) {
+        Some(d) => Json::object(vec![("uri", Json::string(uri)), ("range", range(&analysis.text, analysis.definitions[d].name_span))]),
+        None => Json::Null,
+    }
+}
+
+fn hover(analysis : &Analysis, offset : usize) -> Json {
+    match analysis.hover(offset) {
+        Some((span, text)) => {
+            let contents = Json::object(vec![("kind", Json::string("markdown")), ("value", Json::String(text))]);
+            Json::object(vec![("contents", contents), ("range", range(&analysis.text, span))])
+        },
+        None => Json::Null,
+    }
+}
+
+fn rename(analysis : &Analysis, uri : &str, offset : usize, name : &str) -> Result<Json, String> {
+    if !is_valid_name(name) {
+        return Err(format!("{} is not a valid name", name));
+    }
+    let d = match analysis.definition_at(offset) {
+        Some(d) => d,
+        None => return Err("there is nothing to rename here".to_string()),
+    };
+    let edits = analysis.references(d).into_iter().map(|span| {
+        Json::object(vec![("range", range(&analysis.text, span)), ("newText", Json::string(name))])
+    }).collect();
+    Ok(Json::object(vec![("changes", Json::Object(vec![(uri.to_string(), Json::Array(edits))]))]))
+}
+
+fn symbols(analysis : &Analysis) -> Json {
+    let symbol = |d : usize, children : Vec<Json>| {
+        let d = &analysis.definitions[d];
+        let mut members = vec![ ("name", Json::string(&d.name))
+                              , ("detail", Json::string(&d.detail))
+                              , ("kind", Json::number(symbol_kind(d.kind)))
+                              , ("range", range(&analysis.text, d.span))
+                              , ("selectionRange", range(&analysis.text, d.name_span))
+                              ];
+        if !children.is_empty() {
+            members.push(("children", Json::Array(children)));
+        }
+        Json::object(members)
+    };
+    Json::Array(analysis.symbols().into_iter().map(|(d, children)| {
+        let children = children.into_iter().map(|c| symbol(c, vec![])).collect();
+        symbol(d, children)
+    }).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Runs a session of messages through the framing and returns the
+    /// messages written back, along with the exit code.
+    fn session(messages : &[&str]) -> (Vec<Json>, i32) {
+        let mut input = String::new();
+        for m in messages {
+            input.push_str(&format!("Content-Length: {}\r\n\r\n{}", m.len(), m));
+        }
+        let mut output = vec![];
+        let code = run(&mut Cursor::new(input.into_bytes()), &mut output).unwrap();
+
+        let mut output = Cursor::new(output);
+        let mut replies = vec![];
+        while let Some(body) = read_message(&mut output).unwrap() {
+            replies.push(json::parse(&body).unwrap());
+        }
+        (replies, code)
+    }
+
+    fn open(uri : &str, text : &str) -> String {
+        let text = Json::string(text);
+        format!(r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"{}","languageId":"dust","version":1,"text":{}}}}}}}"#, uri, text)
+    }
+
+    fn request(id : usize, method : &str, uri : &str, line : usize, character : usize, extra : &str) -> String {
+        format!( r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}}{}}}}}"#
+               , id, method, uri, line, character, extra )
+    }
+
+    const INITIALIZE : &str = r#"{"jsonrpc":"2.0","id":0,"method":"initialize","params":{"capabilities":{}}}"#;
+    const SHUTDOWN : &str = r#"{"jsonrpc":"2.0","id":99,"method":"shutdown"}"#;
+    const EXIT : &str = r#"{"jsonrpc":"2.0","method":"exit"}"#;
+
+    const SOURCE : &str = "/* Doubles é. */\nfun double(x : Int) -> Int { x * 2 }\nfun main() {\n    let é = double(1);\n    println(é)\n}\n";
+
+    #[test]
+    fn should_initialize_and_shut_down() {
+        let (replies, code) = session(&[INITIALIZE, r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#, SHUTDOWN, EXIT]);
+        assert_eq!( code, 0 );
+        assert_eq!( replies.len(), 2 );
+        assert_eq!( replies[0].at(&["result", "capabilities", "hoverProvider"]), Some(&Json::Bool(true)) );
+        assert_eq!( replies[0].at(&["result", "capabilities", "textDocumentSync", "change"]).and_then(Json::as_usize), Some(2) );
+        assert_eq!( replies[1].to_string(), r#"{"jsonrpc":"2.0","id":99,"result":null}"# );
+
+        let (_, code) = session(&[INITIALIZE, EXIT]);
+        assert_eq!( code, 1 );
+        let (_, code) = session(&[INITIALIZE]);
+        assert_eq!( code, EXIT_WITHOUT_EXIT );
+    }
+
+    #[test]
+    fn should_publish_diagnostics_as_documents_change() {
+        let uri = "file:///tmp/a.ds";
+        let change = r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///tmp/a.ds","version":2},"contentChanges":[{"range":{"start":{"line":4,"character":8},"end":{"line":4,"character":8}},"text":")"}]}}"#;
+        let close = r#"{"jsonrpc":"2.0","method":"textDocument/didClose","params":{"textDocument":{"uri":"file:///tmp/a.ds"}}}"#;
+        let (replies, _) = session(&[INITIALIZE, &open(uri, "fun main() {\n    let é = 1;\n    é = 2\n}\nfun bad( { }\n"), change, close]);
+
+        let diagnostics = |i : usize| replies[i].at(&["params", "diagnostics"]).and_then(Json::as_array).unwrap().to_vec();
+        assert_eq!( replies[1].get("method").and_then(Json::as_str), Some("textDocument/publishDiagnostics") );
+        assert_eq!( diagnostics(1).len(), 1 );
+        assert_eq!( diagnostics(1)[0].at(&["range", "start"]).unwrap().to_string(), r#"{"line":4,"character":9}"# );
+
+        // closing the parenthesis lets the checker look at the document
+        let second = diagnostics(2);
+        assert_eq!( second.len(), 1, "{:?}", second );
+        assert_eq!( second[0].get("range").unwrap().to_string(), r#"{"start":{"line":2,"character":4},"end":{"line":2,"character":9}}"# );
+        assert_eq!( second[0].get("severity").and_then(Json::as_usize), Some(1) );
+
+        assert_eq!( diagnostics(3), vec![] );
+    }
+
+    #[test]
+    fn should_answer_definition_hover_symbols_completion_and_rename() {
+        let uri = "file:///tmp/b.ds";
+        let (replies, _) = session(&[ INITIALIZE
+                                    , &open(uri, SOURCE)
+                                    // just after the é on line 4, which is two bytes but one UTF-16 unit
+                                    , &request(1, "textDocument/definition", uri, 4, 13, "")
+                                    , &request(2, "textDocument/hover", uri, 3, 14, "")
+                                    , r#"{"jsonrpc":"2.0","id":3,"method":"textDocument/documentSymbol","params":{"textDocument":{"uri":"file:///tmp/b.ds"}}}"#
+                                    , &request(4, "textDocument/completion", uri, 3, 13, "")
+                                    , &request(5, "textDocument/rename", uri, 1, 5, r#","newName":"twice""#)
+                                    , &request(6, "textDocument/rename", uri, 1, 29, r#","newName":"while""#)
+                                    , &request(7, "textDocument/hover", uri, 0, 0, "")
+                                    , &request(8, "textDocument/hover", "file:///tmp/none.ds", 0, 0, "")
+                                    , r#"{"jsonrpc":"2.0","id":9,"method":"textDocument/formatting","params":{}}"#
+                                    , SHUTDOWN
+                                    , &request(10, "textDocument/hover", uri, 0, 0, "")
+                                    , EXIT
+                                    ]);
+        let result = |id : usize| replies.iter().find(|r| r.get("id").and_then(Json::as_usize) == Some(id)).unwrap();
+
+        assert_eq!( result(1).get("result").unwrap().to_string()
+                  , r#"{"uri":"file:///tmp/b.ds","range":{"start":{"line":3,"character":8},"end":{"line":3,"character":9}}}"# );
+
+        assert_eq!( result(2).at(&["result", "contents", "value"]).and_then(Json::as_str)
+                  , Some("```dust\nfun double(x : Int) -> Int\n```\n\nDoubles é.") );
+
+        let symbols = result(3).get("result").unwrap().as_array().unwrap();
+        let names = symbols.iter().map(|s| s.get("name").and_then(Json::as_str).unwrap()).collect::<Vec<_>>();
+        assert_eq!( names, vec!["double", "main"] );
+        assert_eq!( symbols[0].get("kind").and_then(Json::as_usize), Some(12) );
+        assert_eq!( symbols[0].at(&["range", "start", "line"]).and_then(Json::as_usize), Some(1) );
+
+        let labels = result(4).get("result").unwrap().as_array().unwrap()
+                              .iter().map(|c| c.get("label").and_then(Json::as_str).unwrap()).collect::<Vec<_>>();
+        assert_eq!( labels, vec!["double"] );
+
+        let edits = result(5).at(&["result", "changes", uri]).unwrap().as_array().unwrap();
+        let starts = edits.iter().map(|e| e.at(&["range", "start"]).unwrap().to_string()).collect::<Vec<_>>();
+        assert_eq!( starts, vec![r#"{"line":1,"character":4}"#, r#"{"line":3,"character":12}"#] );
+        assert!( edits.iter().all(|e| e.get("newText").and_then(Json::as_str) == Some("twice")) );
+
+        assert_eq!( result(6).at(&["error", "code"]), Some(&Json::Number(INVALID_PARAMS as f64)) );
+        assert_eq!( result(7).get("result"), Some(&Json::Null) );
+        assert_eq!( result(8).at(&["error", "code"]), Some(&Json::Number(INVALID_PARAMS as f64)) );
+        assert_eq!( result(9).at(&["error", "code"]), Some(&Json::Number(METHOD_NOT_FOUND as f64)) );
+        assert_eq!( result(10).at(&["error", "code"]), Some(&Json::Number(INVALID_REQUEST as f64)) );
+    }
+
+    #[test]
+    fn should_complete_use_paths_from_files_beside_the_document() {
+        let dir = std::env::temp_dir().join(format!("dust_lsp_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("shapes.ds"), "struct Square { s : Int }\nenum Kind { Round, Flat }\n").unwrap();
+
+        let uri = format!("file://{}/main.ds", dir.display());
+        let text = "mod shapes;\nuse shapes::";
+        let (replies, _) = session(&[INITIALIZE, &open(&uri, text), &request(1, "textDocument/completion", &uri, 1, 12, "")]);
+        let labels = replies[2].get("result").unwrap().as_array().unwrap()
+                               .iter().map(|c| c.get("label").and_then(Json::as_str).unwrap()).collect::<Vec<_>>();
+        assert_eq!( labels, vec!["Square", "Kind"] );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn should_answer_malformed_messages_with_an_error() {
+        let nested = "[".repeat(200000);
+        let (replies, _) = session(&["{\"jsonrpc\":", &nested, EXIT]);
+        assert_eq!( replies[0].at(&["error", "code"]), Some(&Json::Number(PARSE_ERROR as f64)) );
+        assert_eq!( replies[1].at(&["error", "code"]), Some(&Json::Number(PARSE_ERROR as f64)) );
+    }
+
+    #[test]
+    fn should_refuse_messages_over_the_limit() {
+        let input = format!("Content-Length: {}\r\n\r\n{{}}", MAX_MESSAGE + 1);
+        let mut output = vec![];
+        let code = run(&mut Cursor::new(input.into_bytes()), &mut output).unwrap();
+
+        let reply = json::parse(&read_message(&mut Cursor::new(output)).unwrap().unwrap()).unwrap();
+        assert_eq!( reply.at(&["error", "code"]), Some(&Json::Number(INVALID_REQUEST as f64)) );
+        assert_eq!( code, EXIT_WITHOUT_EXIT );
+    }
+
+    #[test]
+    fn should_convert_between_offsets_and_positions() {
+        let text = "ab\n😀x\n";
+        assert_eq!( offset_of(text, 1, 2), 7 );
+        assert_eq!( offset_of(text, 1, 99), 8 );
+        assert_eq!( offset_of(text, 9, 0), text.len() );
+        assert_eq!( position_of(text, 7), (1, 2) );
+        assert_eq!( position_of(text, 8), (1, 3) );
+    }
+}
@@ -1,6 +1,14 @@
 
-mod parsing;
+use std::io;
+use std::process::exit;
+
+use dust::cli;
 
 fn main() {
-    println!("Hello, world!");
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let stderr = io::stderr();
+    let code = cli::run(&args, &mut stdin.lock(), &mut stdout.lock(), &mut stderr.lock());
+    exit(code);
 }
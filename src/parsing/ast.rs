@@ -1,17 +1,41 @@
 
-#[derive(Debug)]
+use std::sync::Arc;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Meta {
-    start : usize,
-    end : usize,
+    pub start : usize,
+    pub end : usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Module {
     pub fun_defs : Vec<FunDef>,
     pub uses : Vec<Use>,
+    pub struct_defs : Vec<StructDef>,
+    pub enum_defs : Vec<EnumDef>,
+    pub trait_defs : Vec<TraitDef>,
+    pub impl_defs : Vec<ImplDef>,
+    pub const_defs : Vec<ConstDef>,
+    pub extern_defs : Vec<ExternDef>,
+    pub mods : Vec<String>,
+}
+
+/// A top level item, for when the order of items in the source matters.
+#[derive(Debug, Clone)]
+pub enum Item {
+    Fun(FunDef),
+    Use(Use),
+    Struct(StructDef),
+    Enum(EnumDef),
+    Trait(TraitDef),
+    Impl(ImplDef),
+    Const(ConstDef),
+    Extern(ExternDef),
+    Mod(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     Unit,
     Simple(String),
@@ -19,10 +43,13 @@ pub enum Type {
     Arrow { input : Box<Type>, output : Box<Type> },
     Tuple(Vec<Type>),
     Namespace(Vec<String>, Box<Type>),
+    /// `[T; N]`, a list of exactly `N` items, where `N` is a number or the
+    /// name of a constant.
+    Array { element : Box<Type>, length : String },
     Infer,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FunSig {
     pub name : String,
     pub type_params : Vec<TypeParam>,
@@ -30,80 +57,209 @@ pub struct FunSig {
     pub return_type : Type,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FunDef {
     pub sig : FunSig,
-    // TODO defition
+    pub body : Expr,
+    /// Whether this is a `const fun`, which constants can call.
+    pub constant : bool,
+    pub meta : Meta,
 }
 
-#[derive(Debug)]
+/// `const NAME : Type = value;`, whose value is worked out before the program
+/// runs.
+#[derive(Debug, Clone)]
+pub struct ConstDef {
+    pub name : String,
+    pub const_type : Type,
+    pub value : Expr,
+}
+
+/// `extern "library" fun name(params) -> Type;`, a function of a shared
+/// library which is called with the C calling convention.
+#[derive(Debug, Clone)]
+pub struct ExternDef {
+    pub library : String,
+    pub sig : FunSig,
+}
+
+#[derive(Debug, Clone)]
 pub struct Param {
     pub name : String,
     pub param_type : Type,
     pub mutable : bool,
+    pub meta : Meta,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TypeParam {
     pub name : String,
     pub constraints : Vec<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Use {
     pub namespace : Vec<String>,
     pub imports : Vec<Import>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Import {
     Everything,
     Item(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StructField {
     pub name : String,
     pub field_type : Type,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StructDef {
     pub name : String,
     pub type_params : Vec<TypeParam>,
     pub fields : Vec<StructField>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum EnumCase {
     EmptyCase { name : String },
     StructCase { name : String, fields : Vec<StructField> },
     TypeCase { name : String, types : Vec<Type> },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EnumDef {
     pub name : String,
     pub type_params : Vec<TypeParam>,
     pub cases : Vec<EnumCase>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum TraitItem {
     Type { name : String, constraints : Vec<String> },
     Own { name : String, constraints : Vec<String> },
     Fun(FunSig),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TraitDef {
     pub name : String,
     pub type_params : Vec<TypeParam>,
     pub items : Vec<TraitItem>,
 }
 
-#[derive(Debug)] 
+#[derive(Debug, Clone)]
+pub enum ImplItem {
+    Type { name : String, item_type : Type },
+    Own { name : String, item_type : Type },
+    Fun(FunDef),
+}
+
+#[derive(Debug, Clone)]
+pub struct ImplDef {
+    pub type_params : Vec<TypeParam>,
+    pub trait_type : Option<Type>,
+    pub impl_type : Type,
+    pub items : Vec<ImplItem>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CasePattern {
+    Empty,
+    Tuple(Vec<Pattern>),
+    Struct { fields : Vec<(String, Pattern)>, rest : bool },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Wildcard,
+    Number(String),
+    DString(String),
+    Bool(bool),
+    Variable(String),
+    Tuple(Vec<Pattern>),
+    Case { namespace : Vec<String>, name : String, contents : CasePattern },
+    Or(Vec<Pattern>),
+    /// Number literals bounding the values matched, such as `1..=9`.
+    Range { start : Option<String>, end : Option<String>, inclusive : bool },
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern : Pattern,
+    pub body : Expr,
+    pub meta : Meta,
+}
+
+#[derive(Debug, Clone)]
 pub enum Expr {
     Number(String),
     DString(String),
+    Unit,
+    Variable(String),
+    Namespace(Vec<String>, String),
+    Tuple(Vec<Expr>),
+    Block(Vec<Expr>),
+    Call { fun : Box<Expr>, args : Vec<Expr>, meta : Meta },
+    Dot { expr : Box<Expr>, name : String },
+    MethodCall { receiver : Box<Expr>, name : String, args : Vec<Expr>, meta : Meta },
+    Let { name : String, mutable : bool, let_type : Type, value : Box<Expr>, meta : Meta },
+    Assign { target : Box<Expr>, value : Box<Expr>, meta : Meta },
+    Return(Box<Expr>),
+    Match { expr : Box<Expr>, arms : Vec<MatchArm> },
+    Bool(bool),
+    List(Vec<Expr>),
+    Dict(Vec<(Expr, Expr)>),
+    Struct { namespace : Vec<String>, name : String, fields : Vec<(String, Expr)> },
+    Lambda { params : Vec<Param>, body : Arc<Expr> },
+    Index { expr : Box<Expr>, index : Box<Expr> },
+    Range { start : Option<Box<Expr>>, end : Option<Box<Expr>>, inclusive : bool },
+    Unary { op : UnaryOp, expr : Box<Expr> },
+    Binary { op : BinOp, left : Box<Expr>, right : Box<Expr> },
+    If { condition : Box<Expr>, then : Box<Expr>, otherwise : Box<Expr> },
+    While { condition : Box<Expr>, body : Box<Expr> },
+    Foreach { name : String, iterable : Box<Expr>, body : Box<Expr>, meta : Meta },
+    Yield(Box<Expr>),
+    Break,
+    Continue,
+    Try(Box<Expr>),
+    Propagate(Box<Expr>),
+    Assert { condition : Box<Expr>, message : Option<Box<Expr>>, meta : Meta },
+    Panic { message : Box<Expr>, meta : Meta },
+}
+
+impl Expr {
+    /// Where in the source the expression is, for the expressions which record it.
+    pub fn meta(&self) -> Option<Meta> {
+        match self {
+            Expr::Call { meta, .. } | Expr::MethodCall { meta, .. } | Expr::Let { meta, .. } | Expr::Assign { meta, .. }
+            | Expr::Foreach { meta, .. } | Expr::Assert { meta, .. } | Expr::Panic { meta, .. } => Some(*meta),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    NotEq,
+    Less,
+    LessEq,
+    Greater,
+    GreaterEq,
+    And,
+    Or,
 }
@@ -0,0 +1,1261 @@
+
+use std::fmt;
+use std::sync::Arc;
+
+use super::ast::*;
+
+/* Serialises syntax trees as JSON or S-expressions for tools which do not link
+   against this crate, and reads both back.
+
+   Both formats write the same tree of nodes.  Every syntax tree value is an
+   object with a `kind`, which is the name of its Rust struct or enum variant,
+   and one entry per field:
+
+       {"kind":"Let","name":"x","mutable":false,"let_type":{"kind":"Infer"},
+        "value":{"kind":"Number","value":"1"},"span":{"kind":"Span","start":0,"end":9}}
+
+       (Let :name "x" :mutable false :let_type (Infer)
+            :value (Number :value "1") :span (Span :start 0 :end 9))
+
+   Fields keep their Rust names, apart from these:
+     * `meta` is `span`, a `Span` object with byte offsets `start` and `end`
+     * the payload of a single field variant is `value` for `Expr::Number`,
+       `Expr::DString`, `Pattern::Number` and `Pattern::DString`, `name` for
+       `Expr::Variable`, `Type::Simple` and `Pattern::Variable`, `expr` for
+       `Expr::Return`, `Expr::Yield` and `Expr::Propagate`, `body` for `Expr::Try`, `exprs` for `Expr::Block`, `items` for `Tuple` and
+       `List`, `alternatives` for `Pattern::Or`, and `types` for `Type::Tuple`
+     * `Expr::Namespace` and `Type::Namespace` have a `path` of names along
+       with `name` or `type`, and `Type::Indexed` has `name` and `args`
+     * a dictionary is a list of `Entry` objects with `key` and `value`, the
+       fields of a struct literal are `FieldInit` objects with `name` and
+       `value`, and the fields of a struct pattern are `FieldPattern` objects
+       with `name` and `pattern`
+     * `Import::Item` is `{"kind":"Item","name":...}` and `Import::Everything`
+       is `{"kind":"Everything"}`
+     * operators are strings such as `"Add"` and `"Not"`
+     * `type` fields are named `field_type`, `param_type`, `let_type`,
+       `item_type`, `const_type` and `return_type`, as in Rust
+   Lists are JSON arrays or parenthesised lists, an absent `trait_type`,
+   `message` or range bound is `null` or `nil`, and strings are JSON strings
+   in both formats.  An S-expression list which starts with a bare name other
+   than `true`, `false` or `nil` is an object.  Spans only exist where the
+   syntax tree keeps them:  params, `let`, assignments, method calls, match
+   arms and, when dumping items, each top level item.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Null,
+    Bool(bool),
+    Number(usize),
+    String(String),
+    List(Vec<Node>),
+    Object(String, Vec<(String, Node)>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DumpError {
+    Syntax { position : usize, message : String },
+    UnknownKind { expected : String, kind : String },
+    MissingField { kind : String, field : String },
+    WrongNode { expected : String, found : String },
+}
+
+impl fmt::Display for DumpError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DumpError::Syntax { position, message } => write!(f, "syntax error at byte {}: {}", position, message),
+            DumpError::UnknownKind { expected, kind } => write!(f, "{} is not a kind of {}", kind, expected),
+            DumpError::MissingField { kind, field } => write!(f, "{} is missing its field {}", kind, field),
+            DumpError::WrongNode { expected, found } => write!(f, "expected {} but found {}", expected, found),
+        }
+    }
+}
+
+/// Conversion of syntax trees to and from nodes.
+pub trait Dump : Sized {
+    fn dump(&self) -> Node;
+    fn undump(node : &Node) -> Result<Self, DumpError>;
+}
+
+pub fn to_json<T : Dump>(value : &T) -> String {
+    let mut out = String::new();
+    write_json(&value.dump(), &mut out);
+    out
+}
+
+pub fn to_sexpr<T : Dump>(value : &T) -> String {
+    let mut out = String::new();
+    write_sexpr(&value.dump(), &mut out);
+    out
+}
+
+pub fn from_json<T : Dump>(text : &str) -> Result<T, DumpError> {
+    T::undump(&read_json(text)?)
+}
+
+pub fn from_sexpr<T : Dump>(text : &str) -> Result<T, DumpError> {
+    T::undump(&read_sexpr(text)?)
+}
+
+fn object(kind : &str, fields : Vec<(&str, Node)>) -> Node {
+    Node::Object(kind.to_string(), fields.into_iter().map(|(n, v)| (n.to_string(), v)).collect())
+}
+
+fn describe(node : &Node) -> String {
+    match node {
+        Node::Null => "null".to_string(),
+        Node::Bool(_) => "a bool".to_string(),
+        Node::Number(_) => "a number".to_string(),
+        Node::String(_) => "a string".to_string(),
+        Node::List(_) => "a list".to_string(),
+        Node::Object(kind, _) => format!("a {}", kind),
+    }
+}
+
+/// The kind and fields of an object which is one of the kinds of `expected`.
+struct Fields<'a> {
+    kind : &'a str,
+    fields : &'a [(String, Node)],
+}
+
+fn fields<'a>(node : &'a Node, expected : &str) -> Result<Fields<'a>, DumpError> {
+    match node {
+        Node::Object(kind, fields) => Ok(Fields { kind, fields }),
+        node => Err(DumpError::WrongNode { expected: expected.to_string(), found: describe(node) }),
+    }
+}
+
+impl<'a> Fields<'a> {
+    fn get(&self, name : &str) -> Result<&'a Node, DumpError> {
+        match self.fields.iter().find(|(n, _)| n == name) {
+            Some((_, node)) => Ok(node),
+            None => Err(DumpError::MissingField { kind: self.kind.to_string(), field: name.to_string() }),
+        }
+    }
+
+    fn field<T : Dump>(&self, name : &str) -> Result<T, DumpError> {
+        T::undump(self.get(name)?)
+    }
+
+    fn unknown<T>(&self, expected : &str) -> Result<T, DumpError> {
+        Err(DumpError::UnknownKind { expected: expected.to_string(), kind: self.kind.to_string() })
+    }
+}
+
+impl Dump for String {
+    fn dump(&self) -> Node {
+        Node::String(self.clone())
+    }
+
+    fn undump(node : &Node) -> Result<String, DumpError> {
+        match node {
+            Node::String(s) => Ok(s.clone()),
+            node => Err(DumpError::WrongNode { expected: "a string".to_string(), found: describe(node) }),
+        }
+    }
+}
+
+impl Dump for bool {
+    fn dump(&self) -> Node {
+        Node::Bool(*self)
+    }
+
+    fn undump(node : &Node) -> Result<bool, DumpError> {
+        match node {
+            Node::Bool(b) => Ok(*b),
+            node => Err(DumpError::WrongNode { expected: "a bool".to_string(), found: describe(node) }),
+        }
+    }
+}
+
+impl Dump for usize {
+    fn dump(&self) -> Node {
+        Node::Number(*self)
+    }
+
+    fn undump(node : &Node) -> Result<usize, DumpError> {
+        match node {
+            Node::Number(n) => Ok(*n),
+            node => Err(DumpError::WrongNode { expected: "a number".to_string(), found: describe(node) }),
+        }
+    }
+}
+
+impl<T : Dump> Dump for Vec<T> {
+    fn dump(&self) -> Node {
+        Node::List(self.iter().map(Dump::dump).collect())
+    }
+
+    fn undump(node : &Node) -> Result<Vec<T>, DumpError> {
+        match node {
+            Node::List(items) => items.iter().map(T::undump).collect(),
+            node => Err(DumpError::WrongNode { expected: "a list".to_string(), found: describe(node) }),
+        }
+    }
+}
+
+impl<T : Dump> Dump for Option<T> {
+    fn dump(&self) -> Node {
+        match self {
+            Some(v) => v.dump(),
+            None => Node::Null,
+        }
+    }
+
+    fn undump(node : &Node) -> Result<Option<T>, DumpError> {
+        match node {
+            Node::Null => Ok(None),
+            node => T::undump(node).map(Some),
+        }
+    }
+}
+
+impl<T : Dump> Dump for Box<T> {
+    fn dump(&self) -> Node {
+        (**self).dump()
+    }
+
+    fn undump(node : &Node) -> Result<Box<T>, DumpError> {
+        T::undump(node).map(Box::new)
+    }
+}
+
+impl<T : Dump> Dump for Arc<T> {
+    fn dump(&self) -> Node {
+        (**self).dump()
+    }
+
+    fn undump(node : &Node) -> Result<Arc<T>, DumpError> {
+        T::undump(node).map(Arc::new)
+    }
+}
+
+impl Dump for Meta {
+    fn dump(&self) -> Node {
+        object("Span", vec![("start", self.start.dump()), ("end", self.end.dump())])
+    }
+
+    fn undump(node : &Node) -> Result<Meta, DumpError> {
+        let f = fields(node, "Span")?;
+        match f.kind {
+            "Span" => Ok(Meta { start: f.field("start")?, end: f.field("end")? }),
+            _ => f.unknown("Span"),
+        }
+    }
+}
+
+impl Dump for Module {
+    fn dump(&self) -> Node {
+        object("Module", vec![ ("fun_defs", self.fun_defs.dump())
+                             , ("uses", self.uses.dump())
+                             , ("struct_defs", self.struct_defs.dump())
+                             , ("enum_defs", self.enum_defs.dump())
+                             , ("trait_defs", self.trait_defs.dump())
+                             , ("impl_defs", self.impl_defs.dump())
+                             , ("const_defs", self.const_defs.dump())
+                             , ("extern_defs", self.extern_defs.dump())
+                             , ("mods", self.mods.dump())
+                             ])
+    }
+
+    fn undump(node : &Node) -> Result<Module, DumpError> {
+        let f = fields(node, "Module")?;
+        match f.kind {
+            "Module" => Ok(Module { fun_defs: f.field("fun_defs")?
+                                  , uses: f.field("uses")?
+                                  , struct_defs: f.field("struct_defs")?
+                                  , enum_defs: f.field("enum_defs")?
+                                  , trait_defs: f.field("trait_defs")?
+                                  , impl_defs: f.field("impl_defs")?
+                                  , const_defs: f.field("const_defs")?
+                                  , extern_defs: f.field("extern_defs")?
+                                  , mods: f.field("mods")?
+                                  }),
+            _ => f.unknown("Module"),
+        }
+    }
+}
+
+/// Top level items in source order, each with its span.
+impl Dump for (Item, Meta) {
+    fn dump(&self) -> Node {
+        let (kind, item) = match &self.0 {
+            Item::Fun(fun_def) => ("Fun", fun_def.dump()),
+            Item::Use(u) => ("Use", u.dump()),
+            Item::Struct(struct_def) => ("Struct", struct_def.dump()),
+            Item::Enum(enum_def) => ("Enum", enum_def.dump()),
+            Item::Trait(trait_def) => ("Trait", trait_def.dump()),
+            Item::Impl(impl_def) => ("Impl", impl_def.dump()),
+            Item::Const(const_def) => ("Const", const_def.dump()),
+            Item::Extern(extern_def) => ("Extern", extern_def.dump()),
+            Item::Mod(name) => ("Mod", name.dump()),
+        };
+        object(kind, vec![("item", item), ("span", self.1.dump())])
+    }
+
+    fn undump(node : &Node) -> Result<(Item, Meta), DumpError> {
+        let f = fields(node, "Item")?;
+        let item = match f.kind {
+            "Fun" => Item::Fun(f.field("item")?),
+            "Use" => Item::Use(f.field("item")?),
+            "Struct" => Item::Struct(f.field("item")?),
+            "Enum" => Item::Enum(f.field("item")?),
+            "Trait" => Item::Trait(f.field("item")?),
+            "Impl" => Item::Impl(f.field("item")?),
+            "Const" => Item::Const(f.field("item")?),
+            "Extern" => Item::Extern(f.field("item")?),
+            "Mod" => Item::Mod(f.field("item")?),
+            _ => return f.unknown("Item"),
+        };
+        Ok((item, f.field("span")?))
+    }
+}
+
+impl Dump for Type {
+    fn dump(&self) -> Node {
+        match self {
+            Type::Unit => object("Unit", vec![]),
+            Type::Simple(name) => object("Simple", vec![("name", name.dump())]),
+            Type::Indexed(name, args) => object("Indexed", vec![("name", name.dump()), ("args", args.dump())]),
+            Type::Arrow { input, output } => object("Arrow", vec![("input", input.dump()), ("output", output.dump())]),
+            Type::Tuple(types) => object("Tuple", vec![("types", types.dump())]),
+            Type::Namespace(path, t) => object("Namespace", vec![("path", path.dump()), ("type", t.dump())]),
+            Type::Array { element, length } => object("Array", vec![("element", element.dump()), ("length", length.dump())]),
+            Type::Infer => object("Infer", vec![]),
+        }
+    }
+
+    fn undump(node : &Node) -> Result<Type, DumpError> {
+        let f = fields(node, "Type")?;
+        match f.kind {
+            "Unit" => Ok(Type::Unit),
+            "Simple" => Ok(Type::Simple(f.field("name")?)),
+            "Indexed" => Ok(Type::Indexed(f.field("name")?, f.field("args")?)),
+            "Arrow" => Ok(Type::Arrow { input: f.field("input")?, output: f.field("output")? }),
+            "Tuple" => Ok(Type::Tuple(f.field("types")?)),
+            "Namespace" => Ok(Type::Namespace(f.field("path")?, f.field("type")?)),
+            "Array" => Ok(Type::Array { element: f.field("element")?, length: f.field("length")? }),
+            "Infer" => Ok(Type::Infer),
+            _ => f.unknown("Type"),
+        }
+    }
+}
+
+impl Dump for FunSig {
+    fn dump(&self) -> Node {
+        object("FunSig", vec![ ("name", self.name.dump())
+                             , ("type_params", self.type_params.dump())
+                             , ("params", self.params.dump())
+                             , ("return_type", self.return_type.dump())
+                             ])
+    }
+
+    fn undump(node : &Node) -> Result<FunSig, DumpError> {
+        let f = fields(node, "FunSig")?;
+        match f.kind {
+            "FunSig" => Ok(FunSig { name: f.field("name")?
+                                  , type_params: f.field("type_params")?
+                                  , params: f.field("params")?
+                                  , return_type: f.field("return_type")?
+                                  }),
+            _ => f.unknown("FunSig"),
+        }
+    }
+}
+
+impl Dump for FunDef {
+    fn dump(&self) -> Node {
+        object("FunDef", vec![("sig", self.sig.dump()), ("body", self.body.dump()), ("constant", self.constant.dump()), ("span", self.meta.dump())])
+    }
+
+    fn undump(node : &Node) -> Result<FunDef, DumpError> {
+        let f = fields(node, "FunDef")?;
+        match f.kind {
+            "FunDef" => Ok(FunDef { sig: f.field("sig")?, body: f.field("body")?, constant: f.field("constant")?, meta: f.field("span")? }),
+            _ => f.unknown("FunDef"),
+        }
+    }
+}
+
+impl Dump for Param {
+    fn dump(&self) -> Node {
+        object("Param", vec![ ("name", self.name.dump())
+                            , ("param_type", self.param_type.dump())
+                            , ("mutable", self.mutable.dump())
+                            , ("span", self.meta.dump())
+                            ])
+    }
+
+    fn undump(node : &Node) -> Result<Param, DumpError> {
+        let f = fields(node, "Param")?;
+        match f.kind {
+            "Param" => Ok(Param { name: f.field("name")?
+                                , param_type: f.field("param_type")?
+                                , mutable: f.field("mutable")?
+                                , meta: f.field("span")?
+                                }),
+            _ => f.unknown("Param"),
+        }
+    }
+}
+
+impl Dump for TypeParam {
+    fn dump(&self) -> Node {
+        object("TypeParam", vec![("name", self.name.dump()), ("constraints", self.constraints.dump())])
+    }
+
+    fn undump(node : &Node) -> Result<TypeParam, DumpError> {
+        let f = fields(node, "TypeParam")?;
+        match f.kind {
+            "TypeParam" => Ok(TypeParam { name: f.field("name")?, constraints: f.field("constraints")? }),
+            _ => f.unknown("TypeParam"),
+        }
+    }
+}
+
+impl Dump for Use {
+    fn dump(&self) -> Node {
+        object("Use", vec![("namespace", self.namespace.dump()), ("imports", self.imports.dump())])
+    }
+
+    fn undump(node : &Node) -> Result<Use, DumpError> {
+        let f = fields(node, "Use")?;
+        match f.kind {
+            "Use" => Ok(Use { namespace: f.field("namespace")?, imports: f.field("imports")? }),
+            _ => f.unknown("Use"),
+        }
+    }
+}
+
+impl Dump for Import {
+    fn dump(&self) -> Node {
+        match self {
+            Import::Everything => object("Everything", vec![]),
+            Import::Item(name) => object("Item", vec![("name", name.dump())]),
+        }
+    }
+
+    fn undump(node : &Node) -> Result<Import, DumpError> {
+        let f = fields(node, "Import")?;
+        match f.kind {
+            "Everything" => Ok(Import::Everything),
+            "Item" => Ok(Import::Item(f.field("name")?)),
+            _ => f.unknown("Import"),
+        }
+    }
+}
+
+impl Dump for StructField {
+    fn dump(&self) -> Node {
+        object("StructField", vec![("name", self.name.dump()), ("field_type", self.field_type.dump())])
+    }
+
+    fn undump(node : &Node) -> Result<StructField, DumpError> {
+        let f = fields(node, "StructField")?;
+        match f.kind {
+            "StructField" => Ok(StructField { name: f.field("name")?, field_type: f.field("field_type")? }),
+            _ => f.unknown("StructField"),
+        }
+    }
+}
+
+impl Dump for StructDef {
+    fn dump(&self) -> Node {
+        object("StructDef", vec![ ("name", self.name.dump())
+                                , ("type_params", self.type_params.dump())
+                                , ("fields", self.fields.dump())
+                                ])
+    }
+
+    fn undump(node : &Node) -> Result<StructDef, DumpError> {
+        let f = fields(node, "StructDef")?;
+        match f.kind {
+            "StructDef" => Ok(StructDef { name: f.field("name")?, type_params: f.field("type_params")?, fields: f.field("fields")? }),
+            _ => f.unknown("StructDef"),
+        }
+    }
+}
+
+impl Dump for EnumCase {
+    fn dump(&self) -> Node {
+        match self {
+            EnumCase::EmptyCase { name } => object("EmptyCase", vec![("name", name.dump())]),
+            EnumCase::StructCase { name, fields } => object("StructCase", vec![("name", name.dump()), ("fields", fields.dump())]),
+            EnumCase::TypeCase { name, types } => object("TypeCase", vec![("name", name.dump()), ("types", types.dump())]),
+        }
+    }
+
+    fn undump(node : &Node) -> Result<EnumCase, DumpError> {
+        let f = fields(node, "EnumCase")?;
+        match f.kind {
+            "EmptyCase" => Ok(EnumCase::EmptyCase { name: f.field("name")? }),
+            "StructCase" => Ok(EnumCase::StructCase { name: f.field("name")?, fields: f.field("fields")? }),
+            "TypeCase" => Ok(EnumCase::TypeCase { name: f.field("name")?, types: f.field("types")? }),
+            _ => f.unknown("EnumCase"),
+        }
+    }
+}
+
+impl Dump for EnumDef {
+    fn dump(&self) -> Node {
+        object("EnumDef", vec![ ("name", self.name.dump())
+                              , ("type_params", self.type_params.dump())
+                              , ("cases", self.cases.dump())
+                              ])
+    }
+
+    fn undump(node : &Node) -> Result<EnumDef, DumpError> {
+        let f = fields(node, "EnumDef")?;
+        match f.kind {
+            "EnumDef" => Ok(EnumDef { name: f.field("name")?, type_params: f.field("type_params")?, cases: f.field("cases")? }),
+            _ => f.unknown("EnumDef"),
+        }
+    }
+}
+
+impl Dump for TraitItem {
+    fn dump(&self) -> Node {
+        match self {
+            TraitItem::Type { name, constraints } => object("Type", vec![("name", name.dump()), ("constraints", constraints.dump())]),
+            TraitItem::Own { name, constraints } => object("Own", vec![("name", name.dump()), ("constraints", constraints.dump())]),
+            TraitItem::Fun(sig) => object("Fun", vec![("sig", sig.dump())]),
+        }
+    }
+
+    fn undump(node : &Node) -> Result<TraitItem, DumpError> {
+        let f = fields(node, "TraitItem")?;
+        match f.kind {
+            "Type" => Ok(TraitItem::Type { name: f.field("name")?, constraints: f.field("constraints")? }),
+            "Own" => Ok(TraitItem::Own { name: f.field("name")?, constraints: f.field("constraints")? }),
+            "Fun" => Ok(TraitItem::Fun(f.field("sig")?)),
+            _ => f.unknown("TraitItem"),
+        }
+    }
+}
+
+impl Dump for TraitDef {
+    fn dump(&self) -> Node {
+        object("TraitDef", vec![ ("name", self.name.dump())
+                               , ("type_params", self.type_params.dump())
+                               , ("items", self.items.dump())
+                               ])
+    }
+
+    fn undump(node : &Node) -> Result<TraitDef, DumpError> {
+        let f = fields(node, "TraitDef")?;
+        match f.kind {
+            "TraitDef" => Ok(TraitDef { name: f.field("name")?, type_params: f.field("type_params")?, items: f.field("items")? }),
+            _ => f.unknown("TraitDef"),
+        }
+    }
+}
+
+impl Dump for ImplItem {
+    fn dump(&self) -> Node {
+        match self {
+            ImplItem::Type { name, item_type } => object("Type", vec![("name", name.dump()), ("item_type", item_type.dump())]),
+            ImplItem::Own { name, item_type } => object("Own", vec![("name", name.dump()), ("item_type", item_type.dump())]),
+            ImplItem::Fun(fun_def) => object("Fun", vec![("fun_def", fun_def.dump())]),
+        }
+    }
+
+    fn undump(node : &Node) -> Result<ImplItem, DumpError> {
+        let f = fields(node, "ImplItem")?;
+        match f.kind {
+            "Type" => Ok(ImplItem::Type { name: f.field("name")?, item_type: f.field("item_type")? }),
+            "Own" => Ok(ImplItem::Own { name: f.field("name")?, item_type: f.field("item_type")? }),
+            "Fun" => Ok(ImplItem::Fun(f.field("fun_def")?)),
+            _ => f.unknown("ImplItem"),
+        }
+    }
+}
+
+impl Dump for ImplDef {
+    fn dump(&self) -> Node {
+        object("ImplDef", vec![ ("type_params", self.type_params.dump())
+                              , ("trait_type", self.trait_type.dump())
+                              , ("impl_type", self.impl_type.dump())
+                              , ("items", self.items.dump())
+                              ])
+    }
+
+    fn undump(node : &Node) -> Result<ImplDef, DumpError> {
+        let f = fields(node, "ImplDef")?;
+        match f.kind {
+            "ImplDef" => Ok(ImplDef { type_params: f.field("type_params")?
+                                    , trait_type: f.field("trait_type")?
+                                    , impl_type: f.field("impl_type")?
+                                    , items: f.field("items")?
+                                    }),
+            _ => f.unknown("ImplDef"),
+        }
+    }
+}
+
+impl Dump for ExternDef {
+    fn dump(&self) -> Node {
+        object("ExternDef", vec![("library", self.library.dump()), ("sig", self.sig.dump())])
+    }
+
+    fn undump(node : &Node) -> Result<ExternDef, DumpError> {
+        let f = fields(node, "ExternDef")?;
+        match f.kind {
+            "ExternDef" => Ok(ExternDef { library: f.field("library")?, sig: f.field("sig")? }),
+            _ => f.unknown("ExternDef"),
+        }
+    }
+}
+
+impl Dump for ConstDef {
+    fn dump(&self) -> Node {
+        object("ConstDef", vec![ ("name", self.name.dump())
+                               , ("const_type", self.const_type.dump())
+                               , ("value", self.value.dump())
+                               ])
+    }
+
+    fn undump(node : &Node) -> Result<ConstDef, DumpError> {
+        let f = fields(node, "ConstDef")?;
+        match f.kind {
+            "ConstDef" => Ok(ConstDef { name: f.field("name")?, const_type: f.field("const_type")?, value: f.field("value")? }),
+            _ => f.unknown("ConstDef"),
+        }
+    }
+}
+
+/// A name with a value, for the fields of struct literals and struct patterns.
+struct Named<'a, T>(&'static str, &'static str, &'a (String, T));
+
+impl<'a, T : Dump + Clone> Named<'a, T> {
+    fn dump(&self) -> Node {
+        let Named(kind, value_name, (name, value)) = self;
+        object(kind, vec![("name", name.dump()), (value_name, value.dump())])
+    }
+}
+
+fn undump_named<T : Dump>(node : &Node, kind : &str, value_name : &str) -> Result<(String, T), DumpError> {
+    let f = fields(node, kind)?;
+    if f.kind != kind {
+        return f.unknown(kind);
+    }
+    Ok((f.field("name")?, f.field(value_name)?))
+}
+
+fn undump_list<T>(node : &Node, undump : impl Fn(&Node) -> Result<T, DumpError>) -> Result<Vec<T>, DumpError> {
+    match node {
+        Node::List(items) => items.iter().map(undump).collect(),
+        node => Err(DumpError::WrongNode { expected: "a list".to_string(), found: describe(node) }),
+    }
+}
+
+impl Dump for CasePattern {
+    fn dump(&self) -> Node {
+        match self {
+            CasePattern::Empty => object("Empty", vec![]),
+            CasePattern::Tuple(patterns) => object("Tuple", vec![("items", patterns.dump())]),
+            CasePattern::Struct { fields, rest } => {
+                let fields = Node::List(fields.iter().map(|f| Named("FieldPattern", "pattern", f).dump()).collect());
+                object("Struct", vec![("fields", fields), ("rest", rest.dump())])
+            },
+        }
+    }
+
+    fn undump(node : &Node) -> Result<CasePattern, DumpError> {
+        let f = fields(node, "CasePattern")?;
+        match f.kind {
+            "Empty" => Ok(CasePattern::Empty),
+            "Tuple" => Ok(CasePattern::Tuple(f.field("items")?)),
+            "Struct" => Ok(CasePattern::Struct { fields: undump_list(f.get("fields")?, |n| undump_named(n, "FieldPattern", "pattern"))?
+                                               , rest: f.field("rest")?
+                                               }),
+            _ => f.unknown("CasePattern"),
+        }
+    }
+}
+
+impl Dump for Pattern {
+    fn dump(&self) -> Node {
+        match self {
+            Pattern::Wildcard => object("Wildcard", vec![]),
+            Pattern::Number(n) => object("Number", vec![("value", n.dump())]),
+            Pattern::DString(s) => object("DString", vec![("value", s.dump())]),
+            Pattern::Bool(b) => object("Bool", vec![("value", b.dump())]),
+            Pattern::Variable(name) => object("Variable", vec![("name", name.dump())]),
+            Pattern::Tuple(patterns) => object("Tuple", vec![("items", patterns.dump())]),
+            Pattern::Case { namespace, name, contents } => object("Case", vec![ ("namespace", namespace.dump())
+                                                                              , ("name", name.dump())
+                                                                              , ("contents", contents.dump())
+                                                                              ]),
+            Pattern::Or(patterns) => object("Or", vec![("alternatives", patterns.dump())]),
+            Pattern::Range { start, end, inclusive } => object("Range", vec![ ("start", start.dump())
+                                                                            , ("end", end.dump())
+                                                                            , ("inclusive", inclusive.dump())
+                                                                            ]),
+        }
+    }
+
+    fn undump(node : &Node) -> Result<Pattern, DumpError> {
+        let f = fields(node, "Pattern")?;
+        match f.kind {
+            "Wildcard" => Ok(Pattern::Wildcard),
+            "Number" => Ok(Pattern::Number(f.field("value")?)),
+            "DString" => Ok(Pattern::DString(f.field("value")?)),
+            "Bool" => Ok(Pattern::Bool(f.field("value")?)),
+            "Variable" => Ok(Pattern::Variable(f.field("name")?)),
+            "Tuple" => Ok(Pattern::Tuple(f.field("items")?)),
+            "Case" => Ok(Pattern::Case { namespace: f.field("namespace")?, name: f.field("name")?, contents: f.field("contents")? }),
+            "Or" => Ok(Pattern::Or(f.field("alternatives")?)),
+            "Range" => Ok(Pattern::Range { start: f.field("start")?, end: f.field("end")?, inclusive: f.field("inclusive")? }),
+            _ => f.unknown("Pattern"),
+        }
+    }
+}
+
+impl Dump for MatchArm {
+    fn dump(&self) -> Node {
+        object("MatchArm", vec![("pattern", self.pattern.dump()), ("body", self.body.dump()), ("span", self.meta.dump())])
+    }
+
+    fn undump(node : &Node) -> Result<MatchArm, DumpError> {
+        let f = fields(node, "MatchArm")?;
+        match f.kind {
+            "MatchArm" => Ok(MatchArm { pattern: f.field("pattern")?, body: f.field("body")?, meta: f.field("span")? }),
+            _ => f.unknown("MatchArm"),
+        }
+    }
+}
+
+const UNARY_OPS : [(UnaryOp, &str); 2] = [(UnaryOp::Neg, "Neg"), (UnaryOp::Not, "Not")];
+
+const BIN_OPS : [(BinOp, &str); 13] = [ (BinOp::Add, "Add"), (BinOp::Sub, "Sub"), (BinOp::Mul, "Mul"), (BinOp::Div, "Div")
+                                      , (BinOp::Rem, "Rem"), (BinOp::Eq, "Eq"), (BinOp::NotEq, "NotEq"), (BinOp::Less, "Less")
+                                      , (BinOp::LessEq, "LessEq"), (BinOp::Greater, "Greater"), (BinOp::GreaterEq, "GreaterEq")
+                                      , (BinOp::And, "And"), (BinOp::Or, "Or")
+                                      ];
+
+fn dump_op<T : PartialEq + Copy>(ops : &[(T, &str)], op : T) -> Node {
+    Node::String(ops.iter().find(|(o, _)| *o == op).unwrap().1.to_string())
+}
+
+fn undump_op<T : Copy>(ops : &[(T, &str)], node : &Node, expected : &str) -> Result<T, DumpError> {
+    let name = String::undump(node)?;
+    match ops.iter().find(|(_, n)| *n == name) {
+        Some((op, _)) => Ok(*op),
+        None => Err(DumpError::UnknownKind { expected: expected.to_string(), kind: name }),
+    }
+}
+
+impl Dump for Expr {
+    fn dump(&self) -> Node {
+        match self {
+            Expr::Number(n) => object("Number", vec![("value", n.dump())]),
+            Expr::DString(s) => object("DString", vec![("value", s.dump())]),
+            Expr::Unit => object("Unit", vec![]),
+            Expr::Variable(name) => object("Variable", vec![("name", name.dump())]),
+            Expr::Namespace(path, name) => object("Namespace", vec![("path", path.dump()), ("name", name.dump())]),
+            Expr::Tuple(exprs) => object("Tuple", vec![("items", exprs.dump())]),
+            Expr::Block(exprs) => object("Block", vec![("exprs", exprs.dump())]),
+            Expr::Call { fun, args, meta } => object("Call", vec![("fun", fun.dump()), ("args", args.dump()), ("span", meta.dump())]),
+            Expr::Dot { expr, name } => object("Dot", vec![("expr", expr.dump()), ("name", name.dump())]),
+            Expr::MethodCall { receiver, name, args, meta } => object("MethodCall", vec![ ("receiver", receiver.dump())
+                                                                                        , ("name", name.dump())
+                                                                                        , ("args", args.dump())
+                                                                                        , ("span", meta.dump())
+                                                                                        ]),
+            Expr::Let { name, mutable, let_type, value, meta } => object("Let", vec![ ("name", name.dump())
+                                                                                    , ("mutable", mutable.dump())
+                                                                                    , ("let_type", let_type.dump())
+                                                                                    , ("value", value.dump())
+                                                                                    , ("span", meta.dump())
+                                                                                    ]),
+            Expr::Assign { target, value, meta } => object("Assign", vec![("target", target.dump()), ("value", value.dump()), ("span", meta.dump())]),
+            Expr::Return(e) => object("Return", vec![("expr", e.dump())]),
+            Expr::Match { expr, arms } => object("Match", vec![("expr", expr.dump()), ("arms", arms.dump())]),
+            Expr::Bool(b) => object("Bool", vec![("value", b.dump())]),
+            Expr::List(exprs) => object("List", vec![("items", exprs.dump())]),
+            Expr::Dict(pairs) => {
+                let entries = pairs.iter().map(|(k, v)| object("Entry", vec![("key", k.dump()), ("value", v.dump())])).collect();
+                object("Dict", vec![("entries", Node::List(entries))])
+            },
+            Expr::Struct { namespace, name, fields } => {
+                let fields = Node::List(fields.iter().map(|f| object("FieldInit", vec![("name", f.0.dump()), ("value", f.1.dump())])).collect());
+                object("Struct", vec![("namespace", namespace.dump()), ("name", name.dump()), ("fields", fields)])
+            },
+            Expr::Lambda { params, body } => object("Lambda", vec![("params", params.dump()), ("body", body.dump())]),
+            Expr::Index { expr, index } => object("Index", vec![("expr", expr.dump()), ("index", index.dump())]),
+            Expr::Range { start, end, inclusive } => object("Range", vec![ ("start", start.dump())
+                                                                         , ("end", end.dump())
+                                                                         , ("inclusive", inclusive.dump())
+                                                                         ]),
+            Expr::Unary { op, expr } => object("Unary", vec![("op", dump_op(&UNARY_OPS, *op)), ("expr", expr.dump())]),
+            Expr::Binary { op, left, right } => object("Binary", vec![("op", dump_op(&BIN_OPS, *op)), ("left", left.dump()), ("right", right.dump())]),
+            Expr::If { condition, then, otherwise } => object("If", vec![ ("condition", condition.dump())
+                                                                        , ("then", then.dump())
+                                                                        , ("otherwise", otherwise.dump())
+                                                                        ]),
+            Expr::While { condition, body } => object("While", vec![("condition", condition.dump()), ("body", body.dump())]),
+            Expr::Foreach { name, iterable, body, meta } => object("Foreach", vec![ ("name", name.dump())
+                                                                                  , ("iterable", iterable.dump())
+                                                                                  , ("body", body.dump())
+                                                                                  , ("span", meta.dump())
+                                                                                  ]),
+            Expr::Yield(e) => object("Yield", vec![("expr", e.dump())]),
+            Expr::Break => object("Break", vec![]),
+            Expr::Continue => object("Continue", vec![]),
+            Expr::Try(body) => object("Try", vec![("body", body.dump())]),
+            Expr::Propagate(e) => object("Propagate", vec![("expr", e.dump())]),
+            Expr::Assert { condition, message, meta } => object("Assert", vec![ ("condition", condition.dump())
+                                                                              , ("message", message.dump())
+                                                                              , ("span", meta.dump())
+                                                                              ]),
+            Expr::Panic { message, meta } => object("Panic", vec![("message", message.dump()), ("span", meta.dump())]),
+        }
+    }
+
+    fn undump(node : &Node) -> Result<Expr, DumpError> {
+        let f = fields(node, "Expr")?;
+        match f.kind {
+            "Number" => Ok(Expr::Number(f.field("value")?)),
+            "DString" => Ok(Expr::DString(f.field("value")?)),
+            "Unit" => Ok(Expr::Unit),
+            "Variable" => Ok(Expr::Variable(f.field("name")?)),
+            "Namespace" => Ok(Expr::Namespace(f.field("path")?, f.field("name")?)),
+            "Tuple" => Ok(Expr::Tuple(f.field("items")?)),
+            "Block" => Ok(Expr::Block(f.field("exprs")?)),
+            "Call" => Ok(Expr::Call { fun: f.field("fun")?, args: f.field("args")?, meta: f.field("span")? }),
+            "Dot" => Ok(Expr::Dot { expr: f.field("expr")?, name: f.field("name")? }),
+            "MethodCall" => Ok(Expr::MethodCall { receiver: f.field("receiver")?, name: f.field("name")?, args: f.field("args")?, meta: f.field("span")? }),
+            "Let" => Ok(Expr::Let { name: f.field("name")?
+                                  , mutable: f.field("mutable")?
+                                  , let_type: f.field("let_type")?
+                                  , value: f.field("value")?
+                                  , meta: f.field("span")?
+                                  }),
+            "Assign" => Ok(Expr::Assign { target: f.field("target")?, value: f.field("value")?, meta: f.field("span")? }),
+            "Return" => Ok(Expr::Return(f.field("expr")?)),
+            "Match" => Ok(Expr::Match { expr: f.field("expr")?, arms: f.field("arms")? }),
+            "Bool" => Ok(Expr::Bool(f.field("value")?)),
+            "List" => Ok(Expr::List(f.field("items")?)),
+            "Dict" => Ok(Expr::Dict(undump_list(f.get("entries")?, |n| {
+                let entry = fields(n, "Entry")?;
+                match entry.kind {
+                    "Entry" => Ok((entry.field("key")?, entry.field("value")?)),
+                    _ => entry.unknown("Entry"),
+                }
+            })?)),
+            "Struct" => Ok(Expr::Struct { namespace: f.field("namespace")?
+                                        , name: f.field("name")?
+                                        , fields: undump_list(f.get("fields")?, |n| undump_named(n, "FieldInit", "value"))?
+                                        }),
+            "Lambda" => Ok(Expr::Lambda { params: f.field("params")?, body: f.field("body")? }),
+            "Index" => Ok(Expr::Index { expr: f.field("expr")?, index: f.field("index")? }),
+            "Range" => Ok(Expr::Range { start: f.field("start")?, end: f.field("end")?, inclusive: f.field("inclusive")? }),
+            "Unary" => Ok(Expr::Unary { op: undump_op(&UNARY_OPS, f.get("op")?, "UnaryOp")?, expr: f.field("expr")? }),
+            "Binary" => Ok(Expr::Binary { op: undump_op(&BIN_OPS, f.get("op")?, "BinOp")?, left: f.field("left")?, right: f.field("right")? }),
+            "If" => Ok(Expr::If { condition: f.field("condition")?, then: f.field("then")?, otherwise: f.field("otherwise")? }),
+            "While" => Ok(Expr::While { condition: f.field("condition")?, body: f.field("body")? }),
+            "Foreach" => Ok(Expr::Foreach { name: f.field("name")?, iterable: f.field("iterable")?, body: f.field("body")?, meta: f.field("span")? }),
+            "Yield" => Ok(Expr::Yield(f.field("expr")?)),
+            "Break" => Ok(Expr::Break),
+            "Continue" => Ok(Expr::Continue),
+            "Try" => Ok(Expr::Try(f.field("body")?)),
+            "Propagate" => Ok(Expr::Propagate(f.field("expr")?)),
+            "Assert" => Ok(Expr::Assert { condition: f.field("condition")?, message: f.field("message")?, meta: f.field("span")? }),
+            "Panic" => Ok(Expr::Panic { message: f.field("message")?, meta: f.field("span")? }),
+            _ => f.unknown("Expr"),
+        }
+    }
+}
+
+pub fn write_string(s : &str, out : &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+pub fn write_json(node : &Node, out : &mut String) {
+    match node {
+        Node::Null => out.push_str("null"),
+        Node::Bool(b) => out.push_str(&b.to_string()),
+        Node::Number(n) => out.push_str(&n.to_string()),
+        Node::String(s) => write_string(s, out),
+        Node::List(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json(item, out);
+            }
+            out.push(']');
+        },
+        Node::Object(kind, fields) => {
+            out.push_str("{\"kind\":");
+            write_string(kind, out);
+            for (name, value) in fields {
+                out.push(',');
+                write_string(name, out);
+                out.push(':');
+                write_json(value, out);
+            }
+            out.push('}');
+        },
+    }
+}
+
+pub fn write_sexpr(node : &Node, out : &mut String) {
+    match node {
+        Node::Null => out.push_str("nil"),
+        Node::Bool(b) => out.push_str(&b.to_string()),
+        Node::Number(n) => out.push_str(&n.to_string()),
+        Node::String(s) => write_string(s, out),
+        Node::List(items) => {
+            out.push('(');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                write_sexpr(item, out);
+            }
+            out.push(')');
+        },
+        Node::Object(kind, fields) => {
+            out.push('(');
+            out.push_str(kind);
+            for (name, value) in fields {
+                out.push_str(" :");
+                out.push_str(name);
+                out.push(' ');
+                write_sexpr(value, out);
+            }
+            out.push(')');
+        },
+    }
+}
+
+/// A reader over the characters shared by both formats.
+struct Reader<'a> {
+    text : &'a str,
+    position : usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error<T>(&self, message : &str) -> Result<T, DumpError> {
+        Err(DumpError::Syntax { position: self.position, message: message.to_string() })
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.text[self.position..].chars().next()
+    }
+
+    fn expect(&mut self, c : char) -> Result<(), DumpError> {
+        if self.peek() == Some(c) {
+            self.position += c.len_utf8();
+            Ok(())
+        }
+        else {
+            self.error(&format!("expected {}", c))
+        }
+    }
+
+    fn word(&mut self) -> &'a str {
+        self.skip_whitespace();
+        let rest = &self.text[self.position..];
+        let end = rest.find(|c : char| c.is_whitespace() || "()[]{},:\"".contains(c)).unwrap_or(rest.len());
+        self.position += end;
+        &rest[..end]
+    }
+
+    fn number(&mut self) -> Result<Node, DumpError> {
+        let start = self.position;
+        let word = self.word();
+        match word.parse::<usize>() {
+            Ok(n) => Ok(Node::Number(n)),
+            Err(_) => {
+                self.position = start;
+                self.error(&format!("expected a value but found {}", word))
+            },
+        }
+    }
+
+    fn string(&mut self) -> Result<String, DumpError> {
+        self.expect('"')?;
+        let mut out = String::new();
+        let mut chars = self.text[self.position..].char_indices();
+        loop {
+            let (i, c) = match chars.next() {
+                Some(next) => next,
+                None => return self.error("unterminated string"),
+            };
+            match c {
+                '"' => {
+                    self.position += i + 1;
+                    return Ok(out);
+                },
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('/') => out.push('/'),
+                    Some('n') => out.push('\n'),
+                    Some('r') => out.push('\r'),
+                    Some('t') => out.push('\t'),
+                    Some('b') => out.push('\u{8}'),
+                    Some('f') => out.push('\u{c}'),
+                    Some('u') => {
+                        fn hex(chars : &mut std::str::CharIndices) -> Option<u32> {
+                            let digits = (0..4).map(|_| chars.next().map(|(_, c)| c)).collect::<Option<String>>()?;
+                            u32::from_str_radix(&digits, 16).ok()
+                        }
+                        let code = match hex(&mut chars) {
+                            Some(high @ 0xd800..=0xdbff) => {
+                                match (chars.next().map(|(_, c)| c), chars.next().map(|(_, c)| c), hex(&mut chars)) {
+                                    (Some('\\'), Some('u'), Some(low @ 0xdc00..=0xdfff)) => 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00),
+                                    _ => return self.error("invalid surrogate pair"),
+                                }
+                            },
+                            Some(code) => code,
+                            None => return self.error("invalid \\u escape"),
+                        };
+                        match char::from_u32(code) {
+                            Some(c) => out.push(c),
+                            None => return self.error("invalid \\u escape"),
+                        }
+                    },
+                    _ => return self.error("invalid escape"),
+                },
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn end(&mut self) -> Result<(), DumpError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => self.error("expected the end of the input"),
+        }
+    }
+}
+
+pub fn read_json(text : &str) -> Result<Node, DumpError> {
+    fn value(r : &mut Reader) -> Result<Node, DumpError> {
+        match r.peek() {
+            Some('"') => Ok(Node::String(r.string()?)),
+            Some('[') => {
+                r.expect('[')?;
+                let mut items = vec![];
+                if r.peek() == Some(']') {
+                    r.expect(']')?;
+                    return Ok(Node::List(items));
+                }
+                loop {
+                    items.push(value(r)?);
+                    if r.peek() == Some(',') {
+                        r.expect(',')?;
+                    }
+                    else {
+                        r.expect(']')?;
+                        return Ok(Node::List(items));
+                    }
+                }
+            },
+            Some('{') => {
+                let start = r.position;
+                r.expect('{')?;
+                let mut kind = None;
+                let mut fields = vec![];
+                if r.peek() != Some('}') {
+                    loop {
+                        let name = r.string()?;
+                        r.expect(':')?;
+                        let v = value(r)?;
+                        match (name.as_str(), v) {
+                            ("kind", Node::String(k)) => kind = Some(k),
+                            ("kind", _) => return r.error("kind must be a string"),
+                            (_, v) => fields.push((name, v)),
+                        }
+                        if r.peek() == Some(',') {
+                            r.expect(',')?;
+                        }
+                        else {
+                            break;
+                        }
+                    }
+                }
+                r.expect('}')?;
+                match kind {
+                    Some(kind) => Ok(Node::Object(kind, fields)),
+                    None => Err(DumpError::Syntax { position: start, message: "object without a kind".to_string() }),
+                }
+            },
+            Some(_) => {
+                let start = r.position;
+                match r.word() {
+                    "null" => Ok(Node::Null),
+                    "true" => Ok(Node::Bool(true)),
+                    "false" => Ok(Node::Bool(false)),
+                    _ => {
+                        r.position = start;
+                        r.number()
+                    },
+                }
+            },
+            None => r.error("unexpected end of input"),
+        }
+    }
+
+    let mut r = Reader { text, position: 0 };
+    let node = value(&mut r)?;
+    r.end()?;
+    Ok(node)
+}
+
+pub fn read_sexpr(text : &str) -> Result<Node, DumpError> {
+    fn value(r : &mut Reader) -> Result<Node, DumpError> {
+        match r.peek() {
+            Some('"') => Ok(Node::String(r.string()?)),
+            Some('(') => {
+                r.expect('(')?;
+                let start = r.position;
+                let head = r.word();
+                if head.is_empty() || matches!( head, "true" | "false" | "nil" ) || head.starts_with(|c : char| c.is_ascii_digit()) {
+                    r.position = start;
+                    let mut items = vec![];
+                    while r.peek() != Some(')') {
+                        items.push(value(r)?);
+                    }
+                    r.expect(')')?;
+                    return Ok(Node::List(items));
+                }
+
+                let mut fields = vec![];
+                while r.peek() != Some(')') {
+                    r.expect(':')?;
+                    let name = r.word();
+                    if name.is_empty() {
+                        return r.error("expected a field name");
+                    }
+                    fields.push((name.to_string(), value(r)?));
+                }
+                r.expect(')')?;
+                Ok(Node::Object(head.to_string(), fields))
+            },
+            Some(_) => {
+                let start = r.position;
+                match r.word() {
+                    "nil" => Ok(Node::Null),
+                    "true" => Ok(Node::Bool(true)),
+                    "false" => Ok(Node::Bool(false)),
+                    _ => {
+                        r.position = start;
+                        r.number()
+                    },
+                }
+            },
+            None => r.error("unexpected end of input"),
+        }
+    }
+
+    let mut r = Reader { text, position: 0 };
+    let node = value(&mut r)?;
+    r.end()?;
+    Ok(node)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::{parse, parse_items};
+    use crate::evaluating::conformance::CASES;
+
+    #[test]
+    fn should_write_the_documented_shapes() {
+        let module = parse("fun f(x : Int) -> Int { let y = x; y.abs() }").unwrap();
+        let fun = &module.fun_defs[0];
+
+        assert_eq!( to_json(&fun.sig.return_type), r#"{"kind":"Simple","name":"Int"}"# );
+        assert_eq!( to_json(&fun.body)
+                  , concat!( r#"{"kind":"Block","exprs":["#
+                           , r#"{"kind":"Let","name":"y","mutable":false,"let_type":{"kind":"Infer"},"value":{"kind":"Variable","name":"x"},"span":{"kind":"Span","start":24,"end":33}},"#
+                           , r#"{"kind":"MethodCall","receiver":{"kind":"Variable","name":"y"},"name":"abs","args":[],"span":{"kind":"Span","start":35,"end":42}}]}"#
+                           ) );
+        assert_eq!( to_sexpr(&fun.sig)
+                  , r#"(FunSig :name "f" :type_params () :params ((Param :name "x" :param_type (Simple :name "Int") :mutable false :span (Span :start 6 :end 13))) :return_type (Simple :name "Int"))"# );
+    }
+
+    #[test]
+    fn should_round_trip_modules_through_both_formats() {
+        let extra = r#"
+use a::b::{c, *};
+mod m;
+struct P<T : Eq + Show> { x : T, y : List<(Int, a::B<T>)> }
+enum E { A, B(Int -> Int), C { s : String } }
+trait T<A> { type X : Eq; own Y; fun f(self : Self) -> Self; }
+impl<A> T<A> for P<A> { type X = Int; own Y = Int; fun f(self : Self) -> Self { match self { E::C { s : "\"\n\0😀", .. } | (1, _) => self, x => x } } }
+fun g() { let d = ["k" : P { x : 1, y : [] }]; -x; !y; |mut a : Int| a; a::b::c; }
+"#;
+        for source in CASES.iter().map(|c| c.source).chain(Some(extra)) {
+            let module = parse(source).unwrap();
+            let expected = format!("{:?}", module);
+
+            let json = to_json(&module);
+            assert_eq!( format!("{:?}", from_json::<Module>(&json).unwrap()), expected );
+
+            let sexpr = to_sexpr(&module);
+            assert_eq!( format!("{:?}", from_sexpr::<Module>(&sexpr).unwrap()), expected );
+        }
+    }
+
+    #[test]
+    fn should_dump_items_with_their_spans() {
+        let items = parse_items("mod m;\nfun f() { }").unwrap();
+        assert_eq!( to_json(&items)
+                  , concat!( r#"[{"kind":"Mod","item":"m","span":{"kind":"Span","start":0,"end":6}},"#
+                           , r#"{"kind":"Fun","item":{"kind":"FunDef","sig":{"kind":"FunSig","name":"f","type_params":[],"params":[],"return_type":{"kind":"Unit"}},"#
+                           , r#""body":{"kind":"Block","exprs":[{"kind":"Unit"}]},"constant":false,"span":{"kind":"Span","start":7,"end":18}},"span":{"kind":"Span","start":7,"end":18}}]"#
+                           ) );
+        let back = from_sexpr::<Vec<(Item, Meta)>>(&to_sexpr(&items)).unwrap();
+        assert_eq!( format!("{:?}", back), format!("{:?}", items) );
+    }
+
+    #[test]
+    fn should_read_json_written_by_other_tools() {
+        let t = from_json::<Type>(" { \"name\" : \"L\\u00e9\" , \"args\":[ {\"kind\":\"Unit\"} ], \"kind\" : \"Indexed\" } ").unwrap();
+        assert_eq!( t, Type::Indexed("Lé".to_string(), vec![Type::Unit]) );
+        let s = from_json::<Expr>(r#"{"kind":"DString","value":"😀\/"}"#).unwrap();
+        assert!( matches!( s, Expr::DString(s) if s == "\u{1F600}/" ) );
+    }
+
+    #[test]
+    fn should_report_malformed_input() {
+        assert!( matches!( read_json("{\"kind\":\"Unit\""), Err(DumpError::Syntax { .. }) ) );
+        assert!( matches!( read_json("[1, 2] 3"), Err(DumpError::Syntax { position: 7, .. }) ) );
+        assert!( matches!( read_json("{\"name\":\"x\"}"), Err(DumpError::Syntax { position: 0, .. }) ) );
+        assert!( matches!( read_sexpr("(Simple :name \"x\""), Err(DumpError::Syntax { .. }) ) );
+        assert!( matches!( read_sexpr("(Simple name)"), Err(DumpError::Syntax { .. }) ) );
+
+        assert_eq!( from_json::<Type>(r#"{"kind":"Simpel","name":"x"}"#)
+                  , Err(DumpError::UnknownKind { expected: "Type".to_string(), kind: "Simpel".to_string() }) );
+        assert_eq!( from_sexpr::<Type>("(Indexed :name \"x\")")
+                  , Err(DumpError::MissingField { kind: "Indexed".to_string(), field: "args".to_string() }) );
+        assert_eq!( from_sexpr::<Type>("(Simple :name 1)")
+                  , Err(DumpError::WrongNode { expected: "a string".to_string(), found: "a number".to_string() }) );
+        assert!( matches!( from_json::<Expr>(r#"{"kind":"Unary","op":"Neq","expr":{"kind":"Unit"}}"#), Err(DumpError::UnknownKind { .. }) ) );
+    }
+}
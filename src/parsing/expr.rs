@@ -1,34 +1,631 @@
 
+use std::sync::Arc;
+
 use super::ast::*;
 use super::parse_error::ParseError;
-use super::input::Input;
+use super::input::{Input, RestorePoint};
 
 impl<'a> Input<'a> {
     pub fn parse_expr(&mut self) -> Result<Expr, ParseError> {
-        Ok(Expr::DString("blah".to_string()))
+        self.nested(Self::parse_expr_inner)
+    }
+
+    fn parse_expr_inner(&mut self) -> Result<Expr, ParseError> {
+        let mark = self.mark()?;
+
+        if matches!( self.expect_keyword("let"), Ok(()) ) {
+            return self.parse_let(mark);
+        }
+
+        if matches!( self.expect_keyword("return"), Ok(()) ) {
+            let restore_point = self.create_restore();
+            return match self.parse_expr() {
+                Ok(e) => Ok(Expr::Return(Box::new(e))),
+                Err(_) => {
+                    self.restore(restore_point);
+                    Ok(Expr::Return(Box::new(Expr::Unit)))
+                },
+            };
+        }
+
+        if matches!( self.expect_keyword("yield"), Ok(()) ) {
+            let value = self.parse_expr()?;
+            return Ok(Expr::Yield(Box::new(value)));
+        }
+
+        if matches!( self.expect_keyword("match"), Ok(()) ) {
+            return self.parse_match();
+        }
+
+        if matches!( self.expect_keyword("break"), Ok(()) ) {
+            return Ok(Expr::Break);
+        }
+
+        if matches!( self.expect_keyword("continue"), Ok(()) ) {
+            return Ok(Expr::Continue);
+        }
+
+        if let Some(inclusive) = self.parse_range_op() {
+            let end = self.parse_range_end(inclusive)?;
+            return Ok(Expr::Range { start: None, end, inclusive });
+        }
+
+        let e = self.parse_binary(0)?;
+
+        if let Some(inclusive) = self.parse_range_op() {
+            let end = self.parse_range_end(inclusive)?;
+            return Ok(Expr::Range { start: Some(Box::new(e)), end, inclusive });
+        }
+
+        let restore_point = self.create_restore();
+        if matches!( self.expect("=="), Ok(()) ) || matches!( self.expect("=>"), Ok(()) ) {
+            self.restore(restore_point);
+            return Ok(e);
+        }
+
+        if matches!( self.expect("="), Ok(()) ) {
+            let value = self.parse_expr()?;
+            let meta = self.meta(mark);
+            return Ok(Expr::Assign { target: Box::new(e), value: Box::new(value), meta });
+        }
+
+        Ok(e)
+    }
+
+    pub fn parse_block(&mut self) -> Result<Expr, ParseError> {
+        self.nested(Self::parse_block_inner)
+    }
+
+    fn parse_block_inner(&mut self) -> Result<Expr, ParseError> {
+        self.expect("{")?;
+
+        let mut exprs = vec![];
+
+        loop {
+            if matches!( self.expect("}"), Ok(()) ) {
+                exprs.push(Expr::Unit);
+                break;
+            }
+
+            let e = self.parse_statement()?;
+            let ends_with_block = ends_with_block(&e);
+            exprs.push(e);
+
+            if matches!( self.expect(";"), Ok(()) ) {
+                continue;
+            }
+
+            if matches!( self.expect("}"), Ok(()) ) {
+                break;
+            }
+
+            if !ends_with_block {
+                self.expect(";")?;
+            }
+        }
+
+        Ok(Expr::Block(exprs))
+    }
+
+    // An `if`, `while`, `foreach`, `try` or block at the start of a statement ends the statement, so
+    // that `while c { } -1` is a loop followed by `-1` instead of a subtraction.
+    fn parse_statement(&mut self) -> Result<Expr, ParseError> {
+        let restore_point = self.create_restore();
+        let block_like = matches!( self.expect_keyword("if"), Ok(()) )
+                      || matches!( self.expect_keyword("while"), Ok(()) )
+                      || matches!( self.expect_keyword("foreach"), Ok(()) )
+                      || matches!( self.expect_keyword("try"), Ok(()) )
+                      || matches!( self.expect("{"), Ok(()) );
+        self.restore(restore_point);
+
+        if block_like {
+            self.parse_primary()
+        }
+        else {
+            self.parse_expr()
+        }
+    }
+
+    fn parse_let(&mut self, mark : RestorePoint<'a>) -> Result<Expr, ParseError> {
+        let mutable = matches!( self.expect_keyword("mut"), Ok(()) );
+        let name = self.parse_symbol()?;
+
+        let let_type = match self.expect(":") {
+            Ok(_) => self.parse_type()?,
+            Err(_) => Type::Infer,
+        };
+
+        self.expect("=")?;
+
+        let value = self.parse_expr()?;
+
+        let meta = self.meta(mark);
+
+        Ok(Expr::Let { name, mutable, let_type, value: Box::new(value), meta })
+    }
+
+    fn parse_match(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.parse_expr()?;
+
+        self.expect("{")?;
+
+        let mut arms = vec![];
+
+        loop {
+            if matches!( self.expect("}"), Ok(()) ) {
+                break;
+            }
+
+            let mark = self.mark()?;
+            let pattern = self.parse_pattern()?;
+            let meta = self.meta(mark);
+            self.expect("=>")?;
+            let body = self.parse_expr()?;
+            let ends_with_block = ends_with_block(&body);
+
+            arms.push(MatchArm { pattern, body, meta });
+
+            if self.expect(",").is_err() && !ends_with_block {
+                self.expect("}")?;
+                break;
+            }
+        }
+
+        Ok(Expr::Match { expr: Box::new(expr), arms })
+    }
+
+    fn parse_binary(&mut self, min_precedence : u8) -> Result<Expr, ParseError> {
+        let mut left = self.parse_unary()?;
+
+        loop {
+            let restore_point = self.create_restore();
+            let op = match self.parse_bin_op() {
+                Some(op) if precedence(op) >= min_precedence => op,
+                _ => {
+                    self.restore(restore_point);
+                    break;
+                },
+            };
+
+            let right = self.parse_binary(precedence(op) + 1)?;
+            left = Expr::Binary { op, left: Box::new(left), right: Box::new(right) };
+        }
+
+        Ok(left)
+    }
+
+    fn parse_bin_op(&mut self) -> Option<BinOp> {
+        let ops = [ ("==", BinOp::Eq)
+                  , ("!=", BinOp::NotEq)
+                  , ("<=", BinOp::LessEq)
+                  , (">=", BinOp::GreaterEq)
+                  , ("&&", BinOp::And)
+                  , ("||", BinOp::Or)
+                  , ("<", BinOp::Less)
+                  , (">", BinOp::Greater)
+                  , ("+", BinOp::Add)
+                  , ("-", BinOp::Sub)
+                  , ("*", BinOp::Mul)
+                  , ("/", BinOp::Div)
+                  , ("%", BinOp::Rem)
+                  ];
+
+        for (s, op) in ops {
+            let restore_point = self.create_restore();
+            if matches!( self.expect(s), Ok(()) ) {
+                // `=>` and `->` are not operators
+                if matches!( self.expect(">"), Ok(()) ) {
+                    self.restore(restore_point);
+                    return None;
+                }
+                return Some(op);
+            }
+        }
+
+        None
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        self.nested(Self::parse_unary_inner)
+    }
+
+    fn parse_unary_inner(&mut self) -> Result<Expr, ParseError> {
+        let mark = self.mark()?;
+
+        if matches!( self.expect("!"), Ok(()) ) {
+            let expr = self.parse_unary()?;
+            return Ok(Expr::Unary { op: UnaryOp::Not, expr: Box::new(expr) });
+        }
+
+        if matches!( self.expect("-"), Ok(()) ) {
+            self.restore(mark);
+            if self.parse_number().is_err() {
+                self.restore(mark);
+                self.expect("-")?;
+                let expr = self.parse_unary()?;
+                return Ok(Expr::Unary { op: UnaryOp::Neg, expr: Box::new(expr) });
+            }
+            self.restore(mark);
+        }
+
+        let e = self.parse_primary()?;
+        self.parse_post_fix(e, mark)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        if matches!( self.expect_keyword("true"), Ok(()) ) {
+            return Ok(Expr::Bool(true));
+        }
+
+        if matches!( self.expect_keyword("false"), Ok(()) ) {
+            return Ok(Expr::Bool(false));
+        }
+
+        if matches!( self.expect_keyword("if"), Ok(()) ) {
+            return self.parse_if();
+        }
+
+        if matches!( self.expect_keyword("while"), Ok(()) ) {
+            let condition = self.parse_expr()?;
+            let body = self.parse_block()?;
+            return Ok(Expr::While { condition: Box::new(condition), body: Box::new(body) });
+        }
+
+        let mark = self.mark()?;
+        if matches!( self.expect_keyword("foreach"), Ok(()) ) {
+            let name = self.parse_symbol()?;
+            let meta = self.meta(mark);
+            self.expect_keyword("in")?;
+            let iterable = self.parse_expr()?;
+            let body = self.parse_block()?;
+            return Ok(Expr::Foreach { name, iterable: Box::new(iterable), body: Box::new(body), meta });
+        }
+
+        if matches!( self.expect_keyword("try"), Ok(()) ) {
+            let body = self.parse_block()?;
+            return Ok(Expr::Try(Box::new(body)));
+        }
+
+        if matches!( self.expect_keyword("assert"), Ok(()) ) {
+            self.expect("(")?;
+            let condition = self.parse_expr()?;
+            let message = match self.expect(",") {
+                Ok(()) => Some(Box::new(self.parse_expr()?)),
+                Err(_) => None,
+            };
+            self.expect(")")?;
+            return Ok(Expr::Assert { condition: Box::new(condition), message, meta: self.meta(mark) });
+        }
+
+        if matches!( self.expect_keyword("panic"), Ok(()) ) {
+            self.expect("(")?;
+            let message = self.parse_expr()?;
+            self.expect(")")?;
+            return Ok(Expr::Panic { message: Box::new(message), meta: self.meta(mark) });
+        }
+
+        if matches!( self.expect("|"), Ok(()) ) {
+            return self.parse_lambda();
+        }
+
+        if matches!( self.expect("["), Ok(()) ) {
+            return self.parse_list_or_dict();
+        }
+
+        let restore_point = self.create_restore();
+        if let Ok(s) = self.parse_string() {
+            return Ok(Expr::DString(s));
+        }
+        self.restore(restore_point);
+
+        if let Ok(n) = self.parse_number() {
+            return Ok(Expr::Number(n));
+        }
+        self.restore(restore_point);
+
+        if matches!( self.expect("{"), Ok(()) ) {
+            self.restore(restore_point);
+            return self.parse_block();
+        }
+
+        if matches!( self.expect("("), Ok(()) ) {
+            return self.parse_paren_or_tuple();
+        }
+
+        let name = self.parse_symbol()?;
+
+        let mut names = vec![];
+        let mut last = name;
+        while matches!( self.expect("::"), Ok(()) ) {
+            names.push(last);
+            last = self.parse_symbol()?;
+        }
+
+        if self.at_struct_literal(&last) {
+            let fields = self.parse_field_list()?;
+            return Ok(Expr::Struct { namespace: names, name: last, fields });
+        }
+
+        if names.is_empty() {
+            Ok(Expr::Variable(last))
+        }
+        else {
+            Ok(Expr::Namespace(names, last))
+        }
+    }
+
+    fn parse_if(&mut self) -> Result<Expr, ParseError> {
+        let condition = self.parse_expr()?;
+        let then = self.parse_block()?;
+
+        let otherwise = if matches!( self.expect_keyword("else"), Ok(()) ) {
+            if matches!( self.expect_keyword("if"), Ok(()) ) {
+                self.parse_if()?
+            }
+            else {
+                self.parse_block()?
+            }
+        }
+        else {
+            Expr::Unit
+        };
+
+        Ok(Expr::If { condition: Box::new(condition), then: Box::new(then), otherwise: Box::new(otherwise) })
+    }
+
+    fn parse_lambda(&mut self) -> Result<Expr, ParseError> {
+        let mut params = vec![];
+
+        if self.expect("|").is_err() {
+            loop {
+                let mark = self.mark()?;
+                let mutable = matches!( self.expect_keyword("mut"), Ok(()) );
+                let name = self.parse_symbol()?;
+                let param_type = match self.expect(":") {
+                    Ok(_) => self.parse_type()?,
+                    Err(_) => Type::Infer,
+                };
+                let meta = self.meta(mark);
+
+                params.push(Param { name, param_type, mutable, meta });
+
+                if matches!( self.expect("|"), Ok(()) ) {
+                    break;
+                }
+
+                self.expect(",")?;
+            }
+        }
+
+        let body = self.parse_expr()?;
+
+        Ok(Expr::Lambda { params, body: Arc::new(body) })
+    }
+
+    fn parse_list_or_dict(&mut self) -> Result<Expr, ParseError> {
+        if matches!( self.expect("]"), Ok(()) ) {
+            return Ok(Expr::List(vec![]));
+        }
+
+        if matches!( self.expect(":"), Ok(()) ) {
+            self.expect("]")?;
+            return Ok(Expr::Dict(vec![]));
+        }
+
+        let first = self.parse_expr()?;
+
+        if matches!( self.expect(":"), Ok(()) ) {
+            let value = self.parse_expr()?;
+            let mut pairs = vec![(first, value)];
+            while matches!( self.expect(","), Ok(()) ) {
+                let key = self.parse_expr()?;
+                self.expect(":")?;
+                let value = self.parse_expr()?;
+                pairs.push((key, value));
+            }
+            self.expect("]")?;
+            return Ok(Expr::Dict(pairs));
+        }
+
+        let mut exprs = vec![first];
+        while matches!( self.expect(","), Ok(()) ) {
+            exprs.push(self.parse_expr()?);
+        }
+        self.expect("]")?;
+
+        Ok(Expr::List(exprs))
+    }
+
+    // A struct literal looks like `Name { field : ...`, which keeps `if x { y }` and
+    // `match x { ... }` parsing as blocks.  `Name { }` has no field to go by, so it is
+    // only a struct literal when the name is capitalized like a type.
+    fn at_struct_literal(&mut self, name : &str) -> bool {
+        let restore_point = self.create_restore();
+        let result = matches!( self.expect("{"), Ok(()) )
+                  && if matches!( self.expect("}"), Ok(()) ) {
+                      name.chars().next().is_some_and(char::is_uppercase)
+                  }
+                  else {
+                      self.parse_symbol().is_ok()
+                      && self.expect("::").is_err()
+                      && matches!( self.expect(":"), Ok(()) )
+                  };
+        self.restore(restore_point);
+        result
+    }
+
+    fn parse_field_list(&mut self) -> Result<Vec<(String, Expr)>, ParseError> {
+        self.expect("{")?;
+
+        let mut fields = vec![];
+
+        loop {
+            if matches!( self.expect("}"), Ok(()) ) {
+                break;
+            }
+
+            let name = self.parse_symbol()?;
+            self.expect(":")?;
+            let value = self.parse_expr()?;
+            fields.push((name, value));
+
+            if self.expect(",").is_err() {
+                self.expect("}")?;
+                break;
+            }
+        }
+
+        Ok(fields)
+    }
+
+    fn parse_paren_or_tuple(&mut self) -> Result<Expr, ParseError> {
+        let mut exprs = vec![];
+
+        if self.expect(")").is_err() {
+            loop {
+                exprs.push(self.parse_expr()?);
+
+                if matches!( self.expect(")"), Ok(()) ) {
+                    break;
+                }
+
+                self.expect(",")?;
+            }
+        }
+
+        match exprs.len() {
+            0 => Ok(Expr::Unit),
+            1 => Ok(exprs.remove(0)),
+            _ => Ok(Expr::Tuple(exprs)),
+        }
+    }
+
+    fn parse_arg_list(&mut self) -> Result<Vec<Expr>, ParseError> {
+        self.expect("(")?;
+
+        let mut args = vec![];
+
+        if matches!( self.expect(")"), Ok(()) ) {
+            return Ok(args);
+        }
+
+        loop {
+            args.push(self.parse_expr()?);
+
+            if matches!( self.expect(")"), Ok(()) ) {
+                break;
+            }
+
+            self.expect(",")?;
+        }
+
+        Ok(args)
     }
+
+    fn parse_post_fix(&mut self, mut e : Expr, mark : RestorePoint<'a>) -> Result<Expr, ParseError> {
+        loop {
+            let restore_point = self.create_restore();
+
+            if matches!( self.expect("("), Ok(()) ) {
+                self.restore(restore_point);
+                let args = self.parse_arg_list()?;
+                let meta = self.meta(mark);
+                e = Expr::Call { fun: Box::new(e), args, meta };
+                continue;
+            }
+
+            if matches!( self.expect("["), Ok(()) ) {
+                let index = self.parse_expr()?;
+                self.expect("]")?;
+                e = Expr::Index { expr: Box::new(e), index: Box::new(index) };
+                continue;
+            }
+
+            if matches!( self.expect("."), Ok(()) ) {
+                let name = match self.parse_symbol() {
+                    Ok(name) => name,
+                    Err(_) => {
+                        self.restore(restore_point);
+                        break;
+                    },
+                };
+
+                let arg_restore = self.create_restore();
+                if matches!( self.expect("("), Ok(()) ) {
+                    self.restore(arg_restore);
+                    let args = self.parse_arg_list()?;
+                    let meta = self.meta(mark);
+                    e = Expr::MethodCall { receiver: Box::new(e), name, args, meta };
+                }
+                else {
+                    e = Expr::Dot { expr: Box::new(e), name };
+                }
+                continue;
+            }
+
+            if matches!( self.expect("?"), Ok(()) ) {
+                e = Expr::Propagate(Box::new(e));
+                continue;
+            }
+
+            break;
+        }
+
+        Ok(e)
+    }
+
+    /// `..=` or `..`, giving whether the range includes its end.
+    pub fn parse_range_op(&mut self) -> Option<bool> {
+        if matches!( self.expect("..="), Ok(()) ) {
+            Some(true)
+        }
+        else if matches!( self.expect(".."), Ok(()) ) {
+            Some(false)
+        }
+        else {
+            None
+        }
+    }
+
+    // An open range ends before anything which cannot start an operand, and
+    // before a `{` so that `foreach i in 0.. { }` has a body.
+    fn parse_range_end(&mut self, inclusive : bool) -> Result<Option<Box<Expr>>, ParseError> {
+        if inclusive {
+            return Ok(Some(Box::new(self.parse_binary(0)?)));
+        }
+
+        let restore_point = self.create_restore();
+        if matches!( self.expect("{"), Ok(()) ) {
+            self.restore(restore_point);
+            return Ok(None);
+        }
+
+        match self.parse_binary(0) {
+            Ok(end) => Ok(Some(Box::new(end))),
+            Err(_) => {
+                self.restore(restore_point);
+                Ok(None)
+            },
+        }
+    }
+
     // TODO : Use
-    // TODO : lambda
-    // TODO : number
-    // TODO : string
-    // TODO : list
-    // TODO : dictionary
     // TODO : loop
-    // TODO : while
-    // TODO : foreach
-    // TODO : match
-    // TODO : let
-    // TODO : mut
-    // TODO : if
-    // TODO : assert 
-    // TODO : panic
-    // TODO : try
-    // TODO : return
-    // TODO : dot 
-    // TODO : { }
-    // TODO : ( )
-    // TODO : yield (?)
-    // TODO : slice
+}
+
+fn ends_with_block(e : &Expr) -> bool {
+    matches!( e, Expr::Block(_) | Expr::Match { .. } | Expr::If { .. } | Expr::While { .. } | Expr::Foreach { .. } | Expr::Try(_) )
+}
+
+fn precedence(op : BinOp) -> u8 {
+    match op {
+        BinOp::Or => 1,
+        BinOp::And => 2,
+        BinOp::Eq | BinOp::NotEq => 3,
+        BinOp::Less | BinOp::LessEq | BinOp::Greater | BinOp::GreaterEq => 4,
+        BinOp::Add | BinOp::Sub => 5,
+        BinOp::Mul | BinOp::Div | BinOp::Rem => 6,
+    }
 }
 
 
@@ -36,23 +633,436 @@ impl<'a> Input<'a> {
 mod test {
     use super::*;
 
-   /* #[test]
-    fn should_parse_type_param_list() -> Result<(), ParseError> {
-        let i = "<A : B, C : D + E + F, G, H>".char_indices().collect::<Vec<(usize, char)>>();
+    #[test]
+    fn should_parse_number() -> Result<(), ParseError> {
+        let i = "1234 ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_expr()?;
+
+        match u {
+            Expr::Number(n) => assert_eq!( n, "1234" ),
+            x => panic!( "Expected Number but found {:?}", x ),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_namespace_call() -> Result<(), ParseError> {
+        let i = "a::b::c(x, \"y\") ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_expr()?;
+
+        let (fun, mut args) = match u {
+            Expr::Call { fun, args, .. } => (*fun, args),
+            x => panic!( "Expected Call but found {:?}", x ),
+        };
+
+        match fun {
+            Expr::Namespace(names, name) => {
+                assert_eq!( names, vec!["a".to_string(), "b".to_string()] );
+                assert_eq!( name, "c" );
+            },
+            x => panic!( "Expected Namespace but found {:?}", x ),
+        }
+
+        assert_eq!( args.len(), 2 );
+
+        match args.remove(0) {
+            Expr::Variable(n) => assert_eq!( n, "x" ),
+            x => panic!( "Expected Variable but found {:?}", x ),
+        }
+
+        match args.remove(0) {
+            Expr::DString(s) => assert_eq!( s, "y" ),
+            x => panic!( "Expected DString but found {:?}", x ),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_try_blocks_and_propagation() -> Result<(), ParseError> {
+        let i = "try { f(x)?.g()? } ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_expr()?;
+
+        let body = match u {
+            Expr::Try(body) => *body,
+            x => panic!( "Expected Try but found {:?}", x ),
+        };
+
+        let mut exprs = match body {
+            Expr::Block(exprs) => exprs,
+            x => panic!( "Expected Block but found {:?}", x ),
+        };
+
+        assert_eq!( exprs.len(), 1 );
+
+        let receiver = match exprs.remove(0) {
+            Expr::Propagate(e) => match *e {
+                Expr::MethodCall { receiver, name, .. } if name == "g" => *receiver,
+                x => panic!( "Expected MethodCall but found {:?}", x ),
+            },
+            x => panic!( "Expected Propagate but found {:?}", x ),
+        };
+
+        assert!( matches!( receiver, Expr::Propagate(e) if matches!( *e, Expr::Call { .. } ) ) );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_assert_and_panic() -> Result<(), ParseError> {
+        let i = "{ assert(a == f(b)); assert(ok, \"why\"); panic(m) } ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_expr()?;
+
+        let exprs = match u {
+            Expr::Block(exprs) => exprs,
+            x => panic!( "Expected Block but found {:?}", x ),
+        };
+
+        assert_eq!( exprs.len(), 3 );
+
+        match &exprs[0] {
+            Expr::Assert { condition, message: None, meta } => {
+                assert!( matches!( **condition, Expr::Binary { op: BinOp::Eq, .. } ) );
+                assert_eq!( *meta, Meta { start: 2, end: 19 } );
+            },
+            x => panic!( "Expected Assert but found {:?}", x ),
+        }
+        assert!( matches!( &exprs[1], Expr::Assert { message: Some(m), .. } if matches!( **m, Expr::DString(_) ) ) );
+        match &exprs[2] {
+            Expr::Panic { message, meta } => {
+                assert!( matches!( **message, Expr::Variable(_) ) );
+                assert_eq!( *meta, Meta { start: 40, end: 48 } );
+            },
+            x => panic!( "Expected Panic but found {:?}", x ),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_foreach_and_yield() -> Result<(), ParseError> {
+        let i = "{ foreach x in xs.iter() { yield x * 2 } yield 1 } ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_expr()?;
+
+        let exprs = match u {
+            Expr::Block(exprs) => exprs,
+            x => panic!( "Expected Block but found {:?}", x ),
+        };
+
+        assert_eq!( exprs.len(), 2 );
+
+        match &exprs[0] {
+            Expr::Foreach { name, iterable, body, meta } => {
+                assert_eq!( name, "x" );
+                assert!( matches!( **iterable, Expr::MethodCall { .. } ) );
+                assert!( matches!( &**body, Expr::Block(b) if matches!( b[..], [Expr::Yield(ref e)] if matches!( **e, Expr::Binary { op: BinOp::Mul, .. } ) ) ) );
+                assert_eq!( *meta, Meta { start: 2, end: 11 } );
+            },
+            x => panic!( "Expected Foreach but found {:?}", x ),
+        }
+        assert!( matches!( &exprs[1], Expr::Yield(e) if matches!( **e, Expr::Number(_) ) ) );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_ranges() -> Result<(), ParseError> {
+        let i = "[0..n + 1, 1..=9, ..2, xs[1..], xs[..]] ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_expr()?;
+
+        let exprs = match u {
+            Expr::List(exprs) => exprs,
+            x => panic!( "Expected List but found {:?}", x ),
+        };
+
+        assert!( matches!( &exprs[0], Expr::Range { start: Some(s), end: Some(e), inclusive: false }
+                                      if matches!( **s, Expr::Number(_) ) && matches!( **e, Expr::Binary { op: BinOp::Add, .. } ) ) );
+        assert!( matches!( &exprs[1], Expr::Range { start: Some(_), end: Some(_), inclusive: true } ) );
+        assert!( matches!( &exprs[2], Expr::Range { start: None, end: Some(_), inclusive: false } ) );
+        assert!( matches!( &exprs[3], Expr::Index { index, .. } if matches!( **index, Expr::Range { start: Some(_), end: None, .. } ) ) );
+        assert!( matches!( &exprs[4], Expr::Index { index, .. } if matches!( **index, Expr::Range { start: None, end: None, .. } ) ) );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_end_an_open_range_before_a_loop_body() -> Result<(), ParseError> {
+        let i = "foreach i in 0.. { break } ".char_indices().collect::<Vec<(usize, char)>>();
         let mut input = Input::new(&i);
-        let u = input.parse_type_param_list()?;
-        assert_eq!( u.len(), 4 );
+        let u = input.parse_expr()?;
 
-        assert_eq!( u[0].name, "A" );
-        assert_eq!( u[0].constraints.len(), 1 );
-        assert_eq!( u[0].constraints[0], "B" );
+        match u {
+            Expr::Foreach { iterable, body, .. } => {
+                assert!( matches!( *iterable, Expr::Range { start: Some(_), end: None, inclusive: false } ) );
+                assert!( matches!( *body, Expr::Block(_) ) );
+            },
+            x => panic!( "Expected Foreach but found {:?}", x ),
+        }
 
-        assert_eq!( u[1].name, "C" );
-        assert_eq!( u[1].constraints.len(), 3 );
-        assert_eq!( u[1].constraints[0], "D" );
-        assert_eq!( u[1].constraints[1], "E" );
-        assert_eq!( u[1].constraints[2], "F" );
         Ok(())
-    }*/
+    }
+
+    #[test]
+    fn should_parse_method_call_chain() -> Result<(), ParseError> {
+        let i = "a.b.c(d).e() ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_expr()?;
+
+        let (receiver, name, args) = match u {
+            Expr::MethodCall { receiver, name, args, .. } => (*receiver, name, args),
+            x => panic!( "Expected MethodCall but found {:?}", x ),
+        };
 
+        assert_eq!( name, "e" );
+        assert_eq!( args.len(), 0 );
+
+        let (receiver, name, args) = match receiver {
+            Expr::MethodCall { receiver, name, args, .. } => (*receiver, name, args),
+            x => panic!( "Expected MethodCall but found {:?}", x ),
+        };
+
+        assert_eq!( name, "c" );
+        assert_eq!( args.len(), 1 );
+
+        match receiver {
+            Expr::Dot { expr, name } => {
+                assert_eq!( name, "b" );
+                assert!( matches!( *expr, Expr::Variable(_) ) );
+            },
+            x => panic!( "Expected Dot but found {:?}", x ),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_block() -> Result<(), ParseError> {
+        let i = "{ let mut x : T = a; let y = (x, b); { y }; return y }".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_expr()?;
+
+        let mut exprs = match u {
+            Expr::Block(exprs) => exprs,
+            x => panic!( "Expected Block but found {:?}", x ),
+        };
+
+        assert_eq!( exprs.len(), 4 );
+
+        match exprs.remove(0) {
+            Expr::Let { name, mutable, let_type, .. } => {
+                assert_eq!( name, "x" );
+                assert!( mutable );
+                assert_eq!( let_type, Type::Simple("T".to_string()) );
+            },
+            x => panic!( "Expected Let but found {:?}", x ),
+        }
+
+        match exprs.remove(0) {
+            Expr::Let { name, mutable, let_type, value, .. } => {
+                assert_eq!( name, "y" );
+                assert!( !mutable );
+                assert_eq!( let_type, Type::Infer );
+                assert!( matches!( *value, Expr::Tuple(_) ) );
+            },
+            x => panic!( "Expected Let but found {:?}", x ),
+        }
+
+        assert!( matches!( exprs.remove(0), Expr::Block(_) ) );
+        assert!( matches!( exprs.remove(0), Expr::Return(_) ) );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_block_ending_in_semicolon() -> Result<(), ParseError> {
+        let i = "{ x; }".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_expr()?;
+
+        let exprs = match u {
+            Expr::Block(exprs) => exprs,
+            x => panic!( "Expected Block but found {:?}", x ),
+        };
+
+        assert_eq!( exprs.len(), 2 );
+        assert!( matches!( exprs[1], Expr::Unit ) );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_match() -> Result<(), ParseError> {
+        let i = r#"{
+    match shape {
+        Shape::Circle(r) => r,
+        Shape::Square { side, .. } => { side }
+        _ => 0
+    }
+    x
+}"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_expr()?;
+
+        let mut exprs = match u {
+            Expr::Block(exprs) => exprs,
+            x => panic!( "Expected Block but found {:?}", x ),
+        };
+
+        assert_eq!( exprs.len(), 2 );
+
+        let (expr, arms) = match exprs.remove(0) {
+            Expr::Match { expr, arms } => (*expr, arms),
+            x => panic!( "Expected Match but found {:?}", x ),
+        };
+
+        assert!( matches!( expr, Expr::Variable(n) if n == "shape" ) );
+        assert_eq!( arms.len(), 3 );
+        assert!( matches!( arms[0].body, Expr::Variable(_) ) );
+        assert!( matches!( arms[1].body, Expr::Block(_) ) );
+        assert_eq!( arms[2].pattern, Pattern::Wildcard );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_parse_keyword_prefix_as_keyword() -> Result<(), ParseError> {
+        let i = "letter ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_expr()?;
+
+        match u {
+            Expr::Variable(n) => assert_eq!( n, "letter" ),
+            x => panic!( "Expected Variable but found {:?}", x ),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_binary_operators_by_precedence() -> Result<(), ParseError> {
+        let i = "a + b * -c == 1-2 || !d ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_expr()?;
+
+        let (left, right) = match u {
+            Expr::Binary { op: BinOp::Or, left, right } => (*left, *right),
+            x => panic!( "Expected Or but found {:?}", x ),
+        };
+
+        assert!( matches!( right, Expr::Unary { op: UnaryOp::Not, .. } ) );
+
+        let (sum, difference) = match left {
+            Expr::Binary { op: BinOp::Eq, left, right } => (*left, *right),
+            x => panic!( "Expected Eq but found {:?}", x ),
+        };
+
+        match difference {
+            Expr::Binary { op: BinOp::Sub, left, right } => {
+                assert!( matches!( *left, Expr::Number(n) if n == "1" ) );
+                assert!( matches!( *right, Expr::Number(n) if n == "2" ) );
+            },
+            x => panic!( "Expected Sub but found {:?}", x ),
+        }
+
+        match sum {
+            Expr::Binary { op: BinOp::Add, right, .. } => match *right {
+                Expr::Binary { op: BinOp::Mul, right, .. } => assert!( matches!( *right, Expr::Unary { op: UnaryOp::Neg, .. } ) ),
+                x => panic!( "Expected Mul but found {:?}", x ),
+            },
+            x => panic!( "Expected Add but found {:?}", x ),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_collections_and_struct_literals() -> Result<(), ParseError> {
+        let i = "([1, 2], [:], [\"a\" : 1], Shape::Rect { w : 1, h : 2 }, xs[0]) ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_expr()?;
+
+        let mut exprs = match u {
+            Expr::Tuple(exprs) => exprs,
+            x => panic!( "Expected Tuple but found {:?}", x ),
+        };
+
+        assert!( matches!( exprs.remove(0), Expr::List(xs) if xs.len() == 2 ) );
+        assert!( matches!( exprs.remove(0), Expr::Dict(ps) if ps.is_empty() ) );
+        assert!( matches!( exprs.remove(0), Expr::Dict(ps) if ps.len() == 1 ) );
+
+        match exprs.remove(0) {
+            Expr::Struct { namespace, name, fields } => {
+                assert_eq!( namespace, vec!["Shape".to_string()] );
+                assert_eq!( name, "Rect" );
+                assert_eq!( fields.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>(), vec!["w", "h"] );
+            },
+            x => panic!( "Expected Struct but found {:?}", x ),
+        }
+
+        assert!( matches!( exprs.remove(0), Expr::Index { .. } ) );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_struct_literals_without_fields() -> Result<(), ParseError> {
+        let i = "{ let h = H { }; if x { } Shape::Empty { } }".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_block()?;
+
+        let mut exprs = match u {
+            Expr::Block(exprs) => exprs,
+            x => panic!( "Expected Block but found {:?}", x ),
+        };
+
+        assert_eq!( exprs.len(), 3 );
+        assert!( matches!( exprs.remove(0), Expr::Let { value, .. } if matches!( &*value, Expr::Struct { name, fields, .. } if name == "H" && fields.is_empty() ) ) );
+        assert!( matches!( exprs.remove(0), Expr::If { .. } ) );
+        assert!( matches!( exprs.remove(0), Expr::Struct { namespace, name, fields } if namespace == vec!["Shape".to_string()] && name == "Empty" && fields.is_empty() ) );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_if_while_and_lambda() -> Result<(), ParseError> {
+        let i = "{ if x { y } else if z { 1 } while x { break; } |a, b : Int| a -1 }".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_block()?;
+
+        let mut exprs = match u {
+            Expr::Block(exprs) => exprs,
+            x => panic!( "Expected Block but found {:?}", x ),
+        };
+
+        assert_eq!( exprs.len(), 3 );
+
+        match exprs.remove(0) {
+            Expr::If { condition, then, otherwise } => {
+                assert!( matches!( *condition, Expr::Variable(_) ) );
+                assert!( matches!( *then, Expr::Block(_) ) );
+                assert!( matches!( *otherwise, Expr::If { .. } ) );
+            },
+            x => panic!( "Expected If but found {:?}", x ),
+        }
+
+        assert!( matches!( exprs.remove(0), Expr::While { .. } ) );
+
+        match exprs.remove(0) {
+            Expr::Lambda { params, body } => {
+                assert_eq!( params.len(), 2 );
+                assert_eq!( params[1].param_type, Type::Simple("Int".to_string()) );
+                assert!( matches!( *body, Expr::Binary { op: BinOp::Sub, .. } ) );
+            },
+            x => panic!( "Expected Lambda but found {:?}", x ),
+        }
+
+        Ok(())
+    }
 }
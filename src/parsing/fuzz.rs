@@ -0,0 +1,597 @@
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use super::ast::*;
+use super::dump::{write_json, Dump, Node};
+use super::parser::parse_items;
+use super::printer::print_item;
+use crate::evaluating::conformance::CASES;
+
+/* Property and fuzz testing for the parser.
+
+   `Generator` makes random syntax trees of the shapes the parser produces, so
+   printing one and parsing the result has to give the same tree back.
+
+   `fuzz` feeds the parser random and mutated input.  The parser must not
+   panic, must not take longer than `TIMEOUT`, and whatever it parses must
+   print back to source which parses to the same tree.  An input which breaks
+   any of those is cut down by `minimise` and written to `REGRESSIONS`, where
+   `should_handle_recorded_regressions` keeps checking it from then on.
+
+   Parsing runs on its own thread so that a panic or a loop can be reported.
+   A stack overflow still aborts the process, which is why the parser limits
+   how deeply things nest.  The thread gets the stack of a main thread, which
+   is where dust programs are parsed.
+
+   `cargo test fuzz_parser_at_length -- --ignored` fuzzes for longer, with
+   `DUST_FUZZ_RUNS` and `DUST_FUZZ_SEED` choosing how long and where from.
+*/
+
+const REGRESSIONS : &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/parsing/fuzz_regressions");
+const TIMEOUT : Duration = Duration::from_secs(5);
+const STACK_SIZE : usize = 8 * 1024 * 1024;
+
+/// A xorshift generator, so that failures can be replayed from the seed.
+pub struct Random(pub u64);
+
+impl Random {
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, n : usize) -> usize {
+        (self.next_u64() % n.max(1) as u64) as usize
+    }
+
+    pub fn chance(&mut self, percent : usize) -> bool {
+        self.below(100) < percent
+    }
+
+    pub fn pick<'a, T>(&mut self, items : &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+const NAMES : &[&str] = &["a", "b", "x", "value", "_tmp", "é", "fun_", "letter", "x1"];
+const TYPE_NAMES : &[&str] = &["Int", "String", "List", "T", "Option", "Ω"];
+const NUMBERS : &[&str] = &["0", "1", "42", "1.5", ".5", "1e3", "2E-4", "-7", "-0.25"];
+const STRINGS : &[&str] = &["", "a", "\"", "\\", "\n\t\r\0", "é😀", "/* */", "{", " "];
+
+pub struct Generator {
+    r : Random,
+    depth : usize,
+}
+
+impl Generator {
+    pub fn new(seed : u64) -> Generator {
+        Generator { r: Random(seed), depth: 0 }
+    }
+
+    /// Whether to make a leaf, which gets more likely the deeper the tree is.
+    fn leaf(&mut self) -> bool {
+        self.depth >= 10 || self.r.chance(8 * self.depth)
+    }
+
+    fn nested<T>(&mut self, f : impl FnOnce(&mut Generator) -> T) -> T {
+        self.depth += 1;
+        let t = f(self);
+        self.depth -= 1;
+        t
+    }
+
+    fn some<T>(&mut self, min : usize, max : usize, mut f : impl FnMut(&mut Generator) -> T) -> Vec<T> {
+        let n = min + self.r.below(max - min + 1);
+        self.nested(|g| (0..n).map(|_| f(g)).collect())
+    }
+
+    fn name(&mut self) -> String {
+        self.r.pick(NAMES).to_string()
+    }
+
+    fn type_name(&mut self) -> String {
+        self.r.pick(TYPE_NAMES).to_string()
+    }
+
+    pub fn items(&mut self) -> Vec<(Item, Meta)> {
+        self.some(0, 4, |g| (g.item(), Meta { start: 0, end: 0 }))
+    }
+
+    pub fn item(&mut self) -> Item {
+        match self.r.below(9) {
+            0 => Item::Use(Use {
+                namespace: self.some(1, 3, Generator::name),
+                imports: self.some(0, 3, |g| if g.r.chance(20) { Import::Everything } else { Import::Item(g.name()) }),
+            }),
+            1 => Item::Struct(StructDef { name: self.type_name(), type_params: self.type_params(), fields: self.fields(0) }),
+            2 => Item::Enum(EnumDef {
+                name: self.type_name(),
+                type_params: self.type_params(),
+                cases: self.some(0, 3, |g| match g.r.below(3) {
+                    0 => EnumCase::EmptyCase { name: g.type_name() },
+                    1 => EnumCase::TypeCase { name: g.type_name(), types: g.some(1, 2, Generator::type_) },
+                    _ => EnumCase::StructCase { name: g.type_name(), fields: g.fields(1) },
+                }),
+            }),
+            3 => Item::Trait(TraitDef {
+                name: self.type_name(),
+                type_params: self.type_params(),
+                items: self.some(0, 3, |g| match g.r.below(3) {
+                    0 => TraitItem::Type { name: g.type_name(), constraints: g.some(0, 2, Generator::type_name) },
+                    1 => TraitItem::Own { name: g.type_name(), constraints: g.some(0, 2, Generator::type_name) },
+                    _ => TraitItem::Fun(g.sig()),
+                }),
+            }),
+            4 => Item::Impl(ImplDef {
+                type_params: self.type_params(),
+                trait_type: if self.r.chance(50) { Some(self.named_type()) } else { None },
+                impl_type: self.named_type(),
+                items: self.some(0, 3, |g| match g.r.below(3) {
+                    0 => ImplItem::Type { name: g.type_name(), item_type: g.type_() },
+                    1 => ImplItem::Own { name: g.type_name(), item_type: g.type_() },
+                    _ => ImplItem::Fun(g.fun_def()),
+                }),
+            }),
+            5 => Item::Mod(self.name()),
+            6 => Item::Extern(ExternDef { library: self.r.pick(STRINGS).to_string(), sig: self.sig() }),
+            7 => Item::Const(ConstDef { name: self.name(), const_type: self.type_(), value: self.expr() }),
+            _ => Item::Fun(FunDef { constant: self.r.chance(20), ..self.fun_def() }),
+        }
+    }
+
+    fn type_params(&mut self) -> Vec<TypeParam> {
+        if self.r.chance(60) {
+            return vec![];
+        }
+        self.some(1, 2, |g| TypeParam { name: g.type_name(), constraints: g.some(0, 2, Generator::type_name) })
+    }
+
+    fn fields(&mut self, min : usize) -> Vec<StructField> {
+        self.some(min, 3, |g| StructField { name: g.name(), field_type: g.type_() })
+    }
+
+    fn sig(&mut self) -> FunSig {
+        let return_type = if self.r.chance(50) { Type::Unit } else { self.type_() };
+        FunSig {
+            name: self.name(),
+            type_params: self.type_params(),
+            params: self.some(0, 3, |g| g.param(false)),
+            return_type,
+        }
+    }
+
+    fn param(&mut self, infer : bool) -> Param {
+        let param_type = if infer && self.r.chance(50) { Type::Infer } else { self.type_() };
+        Param { name: self.name(), param_type, mutable: self.r.chance(20), meta: Meta { start: 0, end: 0 } }
+    }
+
+    fn fun_def(&mut self) -> FunDef {
+        FunDef { sig: self.sig(), body: self.block(), constant: false, meta: Meta { start: 0, end: 0 } }
+    }
+
+    /// A type which can be implemented.
+    fn named_type(&mut self) -> Type {
+        match self.r.below(3) {
+            0 => Type::Simple(self.type_name()),
+            1 => Type::Indexed(self.type_name(), self.some(1, 2, Generator::type_)),
+            _ => Type::Namespace(self.some(1, 2, Generator::name), Box::new(Type::Simple(self.type_name()))),
+        }
+    }
+
+    fn type_(&mut self) -> Type {
+        if self.leaf() {
+            return Type::Simple(self.type_name());
+        }
+        match self.r.below(6) {
+            0 => Type::Unit,
+            1 => Type::Tuple(self.some(2, 3, Generator::type_)),
+            2 => self.nested(|g| Type::Arrow { input: Box::new(g.type_()), output: Box::new(g.type_()) }),
+            3 => {
+                let length = if self.r.chance(50) { self.r.pick(NUMBERS).to_string() } else { self.name() };
+                self.nested(|g| Type::Array { element: Box::new(g.type_()), length })
+            },
+            _ => self.named_type(),
+        }
+    }
+
+    fn pattern(&mut self) -> Pattern {
+        let kinds = if self.leaf() { 6 } else { 9 };
+        match self.r.below(kinds) {
+            0 => Pattern::Wildcard,
+            1 => Pattern::Number(self.r.pick(NUMBERS).to_string()),
+            2 => Pattern::DString(self.r.pick(STRINGS).to_string()),
+            3 => Pattern::Bool(self.r.chance(50)),
+            4 => Pattern::Variable(self.name()),
+            5 => {
+                let inclusive = self.r.chance(50);
+                let start = self.r.chance(70).then(|| self.r.pick(NUMBERS).to_string());
+                let end = (inclusive || start.is_none() || self.r.chance(70)).then(|| self.r.pick(NUMBERS).to_string());
+                Pattern::Range { start, end, inclusive }
+            },
+            6 => Pattern::Tuple(match self.r.below(4) {
+                0 => vec![],
+                _ => self.some(2, 3, Generator::pattern),
+            }),
+            7 => {
+                let namespace = self.some(0, 2, Generator::type_name);
+                let name = self.type_name();
+                let contents = match self.r.below(3) {
+                    0 if !namespace.is_empty() => CasePattern::Empty,
+                    0 | 1 => CasePattern::Tuple(self.some(0, 2, Generator::pattern)),
+                    _ => CasePattern::Struct { fields: self.some(0, 2, |g| (g.name(), g.pattern())), rest: self.r.chance(30) },
+                };
+                Pattern::Case { namespace, name, contents }
+            },
+            _ => Pattern::Or(self.some(2, 3, |g| match g.pattern() {
+                Pattern::Or(_) => Pattern::Wildcard,
+                p => p,
+            })),
+        }
+    }
+
+    /// A block, which the parser ends with `()` when its last statement has a
+    /// semicolon and which always has something in it.
+    fn block(&mut self) -> Expr {
+        let mut statements = self.some(0, 3, Generator::statement);
+        if statements.is_empty() || self.r.chance(40) {
+            statements.push(Expr::Unit);
+        }
+        Expr::Block(statements)
+    }
+
+    fn statement(&mut self) -> Expr {
+        match self.r.below(12) {
+            0 => Expr::Let {
+                name: self.name(),
+                mutable: self.r.chance(30),
+                let_type: if self.r.chance(50) { Type::Infer } else { self.type_() },
+                value: Box::new(self.expr()),
+                meta: Meta { start: 0, end: 0 },
+            },
+            1 => {
+                let target = match self.r.below(3) {
+                    0 => Expr::Variable(self.name()),
+                    1 => Expr::Dot { expr: Box::new(self.expr()), name: self.name() },
+                    _ => Expr::Index { expr: Box::new(self.expr()), index: Box::new(self.expr()) },
+                };
+                Expr::Assign { target: Box::new(target), value: Box::new(self.expr()), meta: Meta { start: 0, end: 0 } }
+            },
+            2 => Expr::Return(Box::new(if self.r.chance(30) { Expr::Unit } else { self.expr() })),
+            3 => if self.r.chance(50) { Expr::Break } else { Expr::Continue },
+            _ => loop {
+                // `()` at the end of a block is the semicolon
+                match self.expr() {
+                    Expr::Unit => continue,
+                    e => break e,
+                }
+            },
+        }
+    }
+
+    /// An expression which has no struct literal at its start or end, so that
+    /// what follows it in `if`, `while`, `foreach` and `match` is read as their block.
+    fn head_expr(&mut self) -> Expr {
+        match self.r.below(4) {
+            0 => Expr::Variable(self.name()),
+            1 => Expr::Call { fun: Box::new(Expr::Variable(self.name())), args: self.some(0, 2, Generator::expr), meta: Meta { start: 0, end: 0 } },
+            2 => Expr::Binary { op: BinOp::Less, left: Box::new(Expr::Variable(self.name())), right: Box::new(Expr::Number(self.r.pick(NUMBERS).to_string())) },
+            _ => Expr::Unary { op: UnaryOp::Not, expr: Box::new(Expr::Bool(self.r.chance(50))) },
+        }
+    }
+
+    pub fn expr(&mut self) -> Expr {
+        if self.leaf() {
+            return match self.r.below(6) {
+                0 => Expr::Number(self.r.pick(NUMBERS).to_string()),
+                1 => Expr::DString(self.r.pick(STRINGS).to_string()),
+                2 => Expr::Unit,
+                3 => Expr::Bool(self.r.chance(50)),
+                4 => Expr::Namespace(self.some(1, 2, Generator::type_name), self.name()),
+                _ => Expr::Variable(self.name()),
+            };
+        }
+        self.nested(|g| match g.r.below(24) {
+            0 => Expr::Tuple(g.some(2, 3, Generator::expr)),
+            1 => Expr::List(g.some(0, 3, Generator::expr)),
+            2 => Expr::Dict(g.some(0, 2, |g| (g.expr(), g.expr()))),
+            3 => Expr::Struct { namespace: g.some(0, 1, Generator::type_name), name: g.type_name(), fields: g.some(0, 2, |g| (g.name(), g.expr())) },
+            4 => g.block(),
+            5 => Expr::Call { fun: Box::new(g.expr()), args: g.some(0, 2, Generator::expr), meta: Meta { start: 0, end: 0 } },
+            6 => Expr::Dot { expr: Box::new(g.expr()), name: g.name() },
+            7 => Expr::MethodCall { receiver: Box::new(g.expr()), name: g.name(), args: g.some(0, 2, Generator::expr), meta: Meta { start: 0, end: 0 } },
+            8 => Expr::Index { expr: Box::new(g.expr()), index: Box::new(g.expr()) },
+            9 => Expr::Unary { op: if g.r.chance(50) { UnaryOp::Neg } else { UnaryOp::Not }, expr: Box::new(g.expr()) },
+            10 | 11 => {
+                let ops = [ BinOp::Add, BinOp::Sub, BinOp::Mul, BinOp::Div, BinOp::Rem, BinOp::Eq, BinOp::NotEq
+                          , BinOp::Less, BinOp::LessEq, BinOp::Greater, BinOp::GreaterEq, BinOp::And, BinOp::Or ];
+                Expr::Binary { op: *g.r.pick(&ops), left: Box::new(g.expr()), right: Box::new(g.expr()) }
+            },
+            12 => {
+                let otherwise = match g.r.below(3) {
+                    0 => Expr::Unit,
+                    1 => g.block(),
+                    _ => Expr::If { condition: Box::new(g.head_expr()), then: Box::new(g.block()), otherwise: Box::new(Expr::Unit) },
+                };
+                Expr::If { condition: Box::new(g.head_expr()), then: Box::new(g.block()), otherwise: Box::new(otherwise) }
+            },
+            13 => Expr::While { condition: Box::new(g.head_expr()), body: Box::new(g.block()) },
+            14 => Expr::Match {
+                expr: Box::new(g.head_expr()),
+                arms: g.some(0, 3, |g| MatchArm { pattern: g.pattern(), body: g.expr(), meta: Meta { start: 0, end: 0 } }),
+            },
+            15 => Expr::Lambda { params: g.some(0, 2, |g| g.param(true)), body: Arc::new(g.expr()) },
+            16 => Expr::Try(Box::new(g.block())),
+            17 => Expr::Propagate(Box::new(g.expr())),
+            18 => Expr::Assert { condition: Box::new(g.expr()), message: g.r.chance(50).then(|| Box::new(g.expr())), meta: Meta { start: 0, end: 0 } },
+            19 => Expr::Panic { message: Box::new(g.expr()), meta: Meta { start: 0, end: 0 } },
+            20 => Expr::Foreach { name: g.name(), iterable: Box::new(g.head_expr()), body: Box::new(g.block()), meta: Meta { start: 0, end: 0 } },
+            21 => Expr::Yield(Box::new(g.expr())),
+            22 => {
+                let inclusive = g.r.chance(50);
+                let start = g.r.chance(70).then(|| Box::new(g.expr()));
+                let end = (inclusive || g.r.chance(70)).then(|| Box::new(g.expr()));
+                Expr::Range { start, end, inclusive }
+            },
+            _ => g.expr(),
+        })
+    }
+}
+
+/// A dump of a tree without its spans, which printing does not keep.
+pub fn without_spans(node : Node) -> Node {
+    match node {
+        Node::Object(kind, _) if kind == "Span" => Node::Null,
+        Node::Object(kind, fields) => Node::Object(kind, fields.into_iter().map(|(n, v)| (n, without_spans(v))).collect()),
+        Node::List(nodes) => Node::List(nodes.into_iter().map(without_spans).collect()),
+        node => node,
+    }
+}
+
+fn print_items(items : &[(Item, Meta)]) -> String {
+    items.iter().map(|(item, _)| print_item(item)).collect::<Vec<_>>().join("\n\n")
+}
+
+/// Prints items and parses them again, describing any difference.
+pub fn reprint(items : &[(Item, Meta)]) -> Result<(), String> {
+    let printed = print_items(items);
+    let reparsed = parse_items(&printed).map_err(|e| format!("printed source does not parse: {:?}\n{}", e, printed))?;
+    let before = without_spans(Node::List(items.iter().map(Dump::dump).collect()));
+    let after = without_spans(Node::List(reparsed.iter().map(Dump::dump).collect()));
+    match difference(&before, &after) {
+        None => Ok(()),
+        Some(difference) => Err(format!("printed source parses to a different tree, {}\n{}", difference, printed)),
+    }
+}
+
+/// The innermost nodes where two trees differ.
+fn difference(before : &Node, after : &Node) -> Option<String> {
+    match (before, after) {
+        _ if before == after => None,
+        (Node::List(xs), Node::List(ys)) if xs.len() == ys.len() => xs.iter().zip(ys).find_map(|(x, y)| difference(x, y)),
+        (Node::Object(k, xs), Node::Object(l, ys)) if k == l && xs.len() == ys.len() =>
+            xs.iter().zip(ys).find_map(|((_, x), (_, y))| difference(x, y)),
+        _ => {
+            let (mut x, mut y) = (String::new(), String::new());
+            write_json(before, &mut x);
+            write_json(after, &mut y);
+            Some(format!("{} became {}", x, y))
+        },
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Failure {
+    Panic(String),
+    Timeout,
+    Reprint(String),
+}
+
+impl Failure {
+    fn same_kind(&self, other : &Failure) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+/// Parses an input on its own thread, checking that the parser returns in
+/// time without panicking and that what it parses prints back the same.
+pub fn check(input : &[u8]) -> Option<Failure> {
+    let source = String::from_utf8_lossy(input).into_owned();
+    let (sender, receiver) = mpsc::channel();
+    let handle = thread::Builder::new().stack_size(STACK_SIZE).spawn(move || {
+        let result = match parse_items(&source) {
+            Ok(items) => reprint(&items).err(),
+            Err(_) => None,
+        };
+        let _ = sender.send(result);
+    }).expect("Could not start a parsing thread");
+
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(result) => result.map(Failure::Reprint),
+        Err(RecvTimeoutError::Timeout) => Some(Failure::Timeout),
+        Err(RecvTimeoutError::Disconnected) => {
+            let payload = handle.join().err();
+            let message = payload.as_ref()
+                .and_then(|p| p.downcast_ref::<String>().cloned().or_else(|| p.downcast_ref::<&str>().map(|s| s.to_string())))
+                .unwrap_or_default();
+            Some(Failure::Panic(message))
+        },
+    }
+}
+
+/// Cuts an input down while it still fails, by removing ever smaller pieces
+/// of it and then replacing what is left with spaces where that still fails.
+pub fn minimise(mut input : Vec<u8>, fails : impl Fn(&[u8]) -> bool) -> Vec<u8> {
+    let mut size = input.len() / 2;
+    while size > 0 {
+        let mut start = 0;
+        while start < input.len() {
+            let end = (start + size).min(input.len());
+            let mut smaller = input[..start].to_vec();
+            smaller.extend_from_slice(&input[end..]);
+            if fails(&smaller) {
+                input = smaller;
+            }
+            else {
+                start += size;
+            }
+        }
+        size /= 2;
+    }
+    for i in 0..input.len() {
+        if input[i] != b' ' {
+            let mut simpler = input.clone();
+            simpler[i] = b' ';
+            if fails(&simpler) {
+                input = simpler;
+            }
+        }
+    }
+    input
+}
+
+/// Writes a failing input where the regression test reads it, named by its
+/// hash so that the same failure is only kept once.
+pub fn record(input : &[u8]) -> PathBuf {
+    let hash = input.iter().fold(0xcbf2_9ce4_8422_2325u64, |h, b| (h ^ *b as u64).wrapping_mul(0x100_0000_01b3));
+    let path = PathBuf::from(REGRESSIONS).join(format!("{:016x}.dust", hash));
+    fs::create_dir_all(REGRESSIONS).and_then(|_| fs::write(&path, input)).expect("Could not record a regression");
+    path
+}
+
+const TOKENS : &[&str] = &[ "fun", "let", "mut", "match", "if", "else", "while", "foreach", "in", "yield", "return", "try", "?", "assert", "panic", "struct", "enum", "trait", "impl"
+                          , "for", "use", "mod", "const", "extern", "type", "own", "true", "false", "_", "x", "T", "é", "1", "-", "1.5e-3"
+                          , ".", "..", "::", ":", ";", ",", "=", "==", "=>", "->", "<", ">", "|", "||", "&&", "!", "+"
+                          , "*", "/", "%", "(", ")", "[", "]", "{", "}", "\"", "\\", "/*", "*/", " ", "\n", "\0"
+                          ];
+
+/// Makes an input from random bytes, or from a conformance program or printed
+/// tree with random changes.
+pub fn mutate(r : &mut Random) -> Vec<u8> {
+    let mut input = match r.below(4) {
+        0 => return (0..r.below(64)).map(|_| r.next_u64() as u8).collect(),
+        1 => print_items(&Generator::new(r.next_u64() | 1).items()).into_bytes(),
+        _ => r.pick(CASES).source.as_bytes().to_vec(),
+    };
+    for _ in 0..1 + r.below(4) {
+        let at = r.below(input.len() + 1);
+        let len = r.below(16).min(input.len() - at);
+        match r.below(5) {
+            0 => { input.drain(at..at + len); },
+            1 => { input.splice(at..at, r.pick(TOKENS).bytes()); },
+            2 => {
+                let copy = input[at..at + len].to_vec();
+                input.splice(at..at, copy);
+            },
+            3 => {
+                let token = r.pick(TOKENS).as_bytes().repeat(1 + r.below(2000));
+                input.splice(at..at, token);
+            },
+            _ => if at < input.len() {
+                input[at] = r.next_u64() as u8;
+            },
+        }
+    }
+    input
+}
+
+/// Checks `runs` inputs, and on the first failure minimises it, records it
+/// and reports it.
+pub fn fuzz(seed : u64, runs : usize) -> Result<(), String> {
+    let mut r = Random(seed);
+    for run in 0..runs {
+        let input = mutate(&mut r);
+        if let Some(failure) = check(&input) {
+            let minimal = match failure {
+                // every try at a smaller loop takes the whole timeout
+                Failure::Timeout => input,
+                _ => minimise(input, |i| check(i).is_some_and(|f| f.same_kind(&failure))),
+            };
+            let path = record(&minimal);
+            return Err(format!("run {} of seed {} failed with {:?}, recorded as {}", run, seed, failure, path.display()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_reprint_random_syntax_trees() {
+        for seed in 1..=1000 {
+            let items = Generator::new(seed).items();
+            if let Err(e) = reprint(&items) {
+                panic!("seed {}: {}", seed, e);
+            }
+        }
+    }
+
+    #[test]
+    fn should_survive_random_input() {
+        if let Err(e) = fuzz(0x9e37_79b9_7f4a_7c15, 300) {
+            panic!("{}", e);
+        }
+    }
+
+    fn parses(source : String) -> bool {
+        let parse = move || parse_items(&source).is_ok();
+        thread::Builder::new().stack_size(STACK_SIZE).spawn(parse).unwrap().join().unwrap()
+    }
+
+    #[test]
+    fn should_reject_deep_nesting() {
+        for (open, close) in [("(", ")"), ("[", "]"), ("{", "}"), ("-", ""), ("!", ""), ("|x| ", ""), ("if x {", "}"), ("f(", ")")] {
+            let source = format!("fun f() {{ {}1{} }}", open.repeat(5000), close.repeat(5000));
+            assert_eq!( check(source.as_bytes()), None, "{}", open );
+            assert!( !parses(source), "{}", open );
+        }
+        for (open, close) in [("(", ")"), ("List<", ">"), ("A -> ", "")] {
+            let source = format!("fun f(x : {}Int{}) {{ }}", open.repeat(5000), close.repeat(5000));
+            assert_eq!( check(source.as_bytes()), None, "{}", open );
+        }
+        let source = format!("fun f() {{ match x {{ {}_{} => 1 }} }}", "A(".repeat(5000), ")".repeat(5000));
+        assert_eq!( check(source.as_bytes()), None );
+
+        assert!( parses(format!("fun f() {{ {}1{} }}", "(".repeat(100), ")".repeat(100))) );
+    }
+
+    #[test]
+    fn should_minimise_failing_inputs() {
+        let input = b"fun main() { let x = [1, 2]; println(x); }".to_vec();
+        let minimal = minimise(input, |i| i.contains(&b'[') && i.contains(&b'x'));
+        assert_eq!( minimal.len(), 2 );
+
+        assert_eq!( minimise(b"abc".to_vec(), |_| true), b"" );
+    }
+
+    #[test]
+    fn should_handle_recorded_regressions() {
+        let entries = match fs::read_dir(REGRESSIONS) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries {
+            let path = entry.unwrap().path();
+            let input = fs::read(&path).unwrap();
+            assert_eq!( check(&input), None, "{}", path.display() );
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn fuzz_parser_at_length() {
+        let runs = std::env::var("DUST_FUZZ_RUNS").ok().and_then(|s| s.parse().ok()).unwrap_or(100_000);
+        let seed = std::env::var("DUST_FUZZ_SEED").ok().and_then(|s| s.parse().ok()).unwrap_or(1);
+        if let Err(e) = fuzz(seed, runs) {
+            panic!("{}", e);
+        }
+    }
+}
@@ -0,0 +1,327 @@
+
+use std::sync::Arc;
+
+use super::ast::*;
+use super::parse_error::ParseError;
+use super::parser::{parse_items, parse_items_in};
+
+/* Reparses a document after an edit without parsing all of it again.
+
+   Items which end before the edit are kept as they are.  Parsing starts again
+   after the last of them and stops at the start of an old item which comes
+   after the edit: the text from there on has not changed, and top level
+   parsing only depends on where it starts, so the rest of the old items are
+   kept with their positions moved.  Parsing stops by parsing up to the old
+   item, which fails when an item or a comment runs past it, in which case the
+   next try goes to an old item at least twice as far away.
+
+   The result is always what parsing the new text from scratch gives,
+   including its error.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    /// Where the edit starts, in bytes.
+    pub offset : usize,
+    /// How many bytes the edit removes.
+    pub removed : usize,
+    pub inserted : String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Reparse {
+    /// How many old items were kept.
+    pub reused : usize,
+    /// How many items were parsed again.
+    pub parsed : usize,
+}
+
+pub struct Document {
+    text : String,
+    items : Result<Vec<(Item, Meta)>, ParseError>,
+}
+
+impl Document {
+    pub fn new(text : &str) -> Document {
+        Document { text: text.to_string(), items: parse_items(text) }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn items(&self) -> Result<&[(Item, Meta)], &ParseError> {
+        self.items.as_deref()
+    }
+
+    pub fn into_items(self) -> Result<Vec<(Item, Meta)>, ParseError> {
+        self.items
+    }
+
+    /// Applies an edit, whose offsets must be on character boundaries, and
+    /// parses what it changed.  After an edit which did not parse, the next
+    /// edit parses the whole document.
+    pub fn edit(&mut self, edit : &Edit) -> Reparse {
+        let edit_end = edit.offset + edit.removed;
+        self.text.replace_range(edit.offset..edit_end, &edit.inserted);
+        let delta = edit.inserted.len() as isize - edit.removed as isize;
+
+        let old_items = match std::mem::replace(&mut self.items, Ok(vec![])) {
+            Ok(items) => items,
+            Err(_) => return self.parse_everything(),
+        };
+
+        let kept = old_items.iter().take_while(|(_, meta)| meta.end < edit.offset).count();
+        let start = if kept == 0 { 0 } else { old_items[kept - 1].1.end };
+        let following = old_items.iter().position(|(_, meta)| meta.start >= edit_end).unwrap_or(old_items.len());
+
+        let mut tried = 0;
+        let mut resume = following;
+        let middle = loop {
+            let end = match old_items.get(resume) {
+                Some((_, meta)) => (meta.start as isize + delta) as usize,
+                None => self.text.len(),
+            };
+            if resume < old_items.len() && end - start < 2 * tried {
+                resume += 1;
+                continue;
+            }
+            tried = end - start;
+            match parse_items_in(&self.text, start, end) {
+                Ok(items) => break Ok(items),
+                Err(e) if resume == old_items.len() => break Err(e),
+                Err(_) => resume += 1,
+            }
+        };
+
+        let middle = match middle {
+            Ok(middle) => middle,
+            Err(e) => {
+                self.items = Err(e);
+                return Reparse { reused: 0, parsed: 0 };
+            },
+        };
+
+        let parsed = middle.len();
+        let mut old_items = old_items.into_iter();
+        let mut items = old_items.by_ref().take(kept).collect::<Vec<_>>();
+        items.extend(middle);
+        for (mut item, meta) in old_items.skip(resume - kept) {
+            if !shift_item(&mut item, delta) {
+                return self.parse_everything();
+            }
+            items.push((item, shift(meta, delta)));
+        }
+
+        let reused = items.len() - parsed;
+        self.items = Ok(items);
+        Reparse { reused, parsed }
+    }
+
+    fn parse_everything(&mut self) -> Reparse {
+        self.items = parse_items(&self.text);
+        let parsed = self.items.as_ref().map(|items| items.len()).unwrap_or(0);
+        Reparse { reused: 0, parsed }
+    }
+}
+
+fn shift(meta : Meta, delta : isize) -> Meta {
+    Meta { start: (meta.start as isize + delta) as usize, end: (meta.end as isize + delta) as usize }
+}
+
+/// Moves every position in an item, which fails when the body of a lambda is
+/// shared and so cannot be changed.
+fn shift_item(item : &mut Item, delta : isize) -> bool {
+    match item {
+        Item::Fun(fun_def) => shift_fun(fun_def, delta),
+        Item::Impl(impl_def) => impl_def.items.iter_mut().all(|item| match item {
+            ImplItem::Fun(fun_def) => shift_fun(fun_def, delta),
+            _ => true,
+        }),
+        Item::Trait(trait_def) => {
+            for item in &mut trait_def.items {
+                if let TraitItem::Fun(sig) = item {
+                    shift_params(&mut sig.params, delta);
+                }
+            }
+            true
+        },
+        Item::Const(const_def) => shift_expr(&mut const_def.value, delta),
+        Item::Extern(extern_def) => {
+            shift_params(&mut extern_def.sig.params, delta);
+            true
+        },
+        Item::Use(_) | Item::Struct(_) | Item::Enum(_) | Item::Mod(_) => true,
+    }
+}
+
+fn shift_params(params : &mut [Param], delta : isize) {
+    for param in params {
+        param.meta = shift(param.meta, delta);
+    }
+}
+
+fn shift_fun(fun_def : &mut FunDef, delta : isize) -> bool {
+    fun_def.meta = shift(fun_def.meta, delta);
+    shift_params(&mut fun_def.sig.params, delta);
+    shift_expr(&mut fun_def.body, delta)
+}
+
+fn shift_expr(expr : &mut Expr, delta : isize) -> bool {
+    match expr {
+        Expr::Number(_) | Expr::DString(_) | Expr::Unit | Expr::Variable(_) | Expr::Namespace(_, _) => true,
+        Expr::Bool(_) | Expr::Break | Expr::Continue => true,
+        Expr::Tuple(exprs) | Expr::Block(exprs) | Expr::List(exprs) => exprs.iter_mut().all(|e| shift_expr(e, delta)),
+        Expr::Call { fun, args, meta } => {
+            *meta = shift(*meta, delta);
+            shift_expr(fun, delta) && args.iter_mut().all(|e| shift_expr(e, delta))
+        },
+        Expr::MethodCall { receiver, args, meta, .. } => {
+            *meta = shift(*meta, delta);
+            shift_expr(receiver, delta) && args.iter_mut().all(|e| shift_expr(e, delta))
+        },
+        Expr::Dot { expr, .. } | Expr::Return(expr) | Expr::Unary { expr, .. } => shift_expr(expr, delta),
+        Expr::Try(expr) | Expr::Propagate(expr) | Expr::Yield(expr) => shift_expr(expr, delta),
+        Expr::Assert { condition, message, meta } => {
+            *meta = shift(*meta, delta);
+            shift_expr(condition, delta) && message.as_deref_mut().is_none_or(|e| shift_expr(e, delta))
+        },
+        Expr::Panic { message, meta } => {
+            *meta = shift(*meta, delta);
+            shift_expr(message, delta)
+        },
+        Expr::Range { start, end, .. } => start.as_deref_mut().is_none_or(|e| shift_expr(e, delta))
+                                       && end.as_deref_mut().is_none_or(|e| shift_expr(e, delta)),
+        Expr::Let { value, meta, .. } => {
+            *meta = shift(*meta, delta);
+            shift_expr(value, delta)
+        },
+        Expr::Assign { target, value, meta } => {
+            *meta = shift(*meta, delta);
+            shift_expr(target, delta) && shift_expr(value, delta)
+        },
+        Expr::Match { expr, arms } => shift_expr(expr, delta) && arms.iter_mut().all(|arm| {
+            arm.meta = shift(arm.meta, delta);
+            shift_expr(&mut arm.body, delta)
+        }),
+        Expr::Dict(pairs) => pairs.iter_mut().all(|(k, v)| shift_expr(k, delta) && shift_expr(v, delta)),
+        Expr::Struct { fields, .. } => fields.iter_mut().all(|(_, e)| shift_expr(e, delta)),
+        Expr::Lambda { params, body } => {
+            shift_params(params, delta);
+            match Arc::get_mut(body) {
+                Some(body) => shift_expr(body, delta),
+                None => false,
+            }
+        },
+        Expr::Index { expr, index } => shift_expr(expr, delta) && shift_expr(index, delta),
+        Expr::Binary { left, right, .. } => shift_expr(left, delta) && shift_expr(right, delta),
+        Expr::If { condition, then, otherwise } => shift_expr(condition, delta) && shift_expr(then, delta) && shift_expr(otherwise, delta),
+        Expr::While { condition, body } => shift_expr(condition, delta) && shift_expr(body, delta),
+        Expr::Foreach { iterable, body, meta, .. } => {
+            *meta = shift(*meta, delta);
+            shift_expr(iterable, delta) && shift_expr(body, delta)
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::evaluating::conformance::CASES;
+    use crate::parsing::fuzz::Random;
+
+    fn boundary(text : &str, offset : usize) -> usize {
+        (offset..=text.len()).find(|i| text.is_char_boundary(*i)).unwrap()
+    }
+
+    const SNIPPETS : &[&str] = &[ "", " ", "\n", "x", "1", "}", "{", ";", "(", ")", "/*", "*/", "/* note */", "\"", "é"
+                                , "let y = 2;", "fun g() { }\n", "struct T { a : Int }\n", "mod m;\n", "fun", "=>", ","
+                                ];
+
+    fn random_edit(r : &mut Random, text : &str) -> Edit {
+        let offset = boundary(text, r.below(text.len() + 1));
+        let removed = match r.below(3) {
+            0 => 0,
+            _ => boundary(text, offset + r.below(8).min(text.len() - offset)) - offset,
+        };
+        let inserted = SNIPPETS[r.below(SNIPPETS.len())].to_string();
+        Edit { offset, removed, inserted }
+    }
+
+    #[test]
+    fn should_match_full_parses_after_random_edits() {
+        let mut r = Random(0x2545_f491_4f6c_dd1d);
+        let mut reused = 0;
+        for case in CASES {
+            for _ in 0..20 {
+                let mut document = Document::new(case.source);
+                for _ in 0..1 + r.below(3) {
+                    let edit = random_edit(&mut r, document.text());
+                    reused += document.edit(&edit).reused;
+
+                    let expected = parse_items(document.text());
+                    assert_eq!( format!("{:?}", document.items()), format!("{:?}", expected.as_deref())
+                              , "after {:?} in {:?}", edit, document.text() );
+                }
+            }
+        }
+        assert!( reused > 0 );
+    }
+
+    #[test]
+    fn should_reuse_items_around_an_edit() {
+        let text = "fun a() { 1 }\nfun b() { let x = 2; x }\n/* c */\nfun c(y : Int) { y.f() }\nmod m;\n";
+        let mut document = Document::new(text);
+
+        let offset = text.find("2").unwrap();
+        let reparse = document.edit(&Edit { offset, removed: 1, inserted: "200 + 1".to_string() });
+        assert_eq!( reparse, Reparse { reused: 3, parsed: 1 } );
+
+        let items = document.items().unwrap();
+        let moved = document.text().find("fun c").unwrap();
+        assert_eq!( items[2].1.start, moved );
+        match &items[2].0 {
+            Item::Fun(FunDef { sig, body: Expr::Block(exprs), .. }) => {
+                assert_eq!( sig.params[0].meta.start, moved + 6 );
+                assert!( matches!( &exprs[0], Expr::MethodCall { meta, .. } if meta.start == moved + 17 ) );
+            },
+            x => panic!("Expected fun c but found {:?}", x),
+        }
+        assert_eq!( format!("{:?}", items), format!("{:?}", parse_items(document.text()).unwrap()) );
+    }
+
+    #[test]
+    fn should_parse_past_comments_which_swallow_items() {
+        let text = "fun a() { }\nfun b() { }\nfun c() { }\n";
+        let mut document = Document::new(text);
+
+        document.edit(&Edit { offset: text.find("fun b").unwrap(), removed: 0, inserted: "/* ".to_string() });
+        assert!( document.items().is_err() );
+
+        let offset = document.text().find("fun c").unwrap();
+        let reparse = document.edit(&Edit { offset, removed: 0, inserted: "*/ ".to_string() });
+        assert_eq!( reparse, Reparse { reused: 0, parsed: 2 } );
+
+        let reparse = document.edit(&Edit { offset: text.find('}').unwrap(), removed: 0, inserted: "1 ".to_string() });
+        assert_eq!( reparse, Reparse { reused: 1, parsed: 1 } );
+        assert_eq!( format!("{:?}", document.items()), format!("{:?}", parse_items(document.text()).as_deref()) );
+    }
+
+    #[test]
+    fn should_parse_everything_when_a_lambda_body_is_shared() {
+        let text = "fun a() { 1 }\nfun b() { |x| x }\n";
+        let mut document = Document::new(text);
+        let shared = match &document.items().unwrap()[1].0 {
+            Item::Fun(FunDef { body: Expr::Block(exprs), .. }) => match &exprs[0] {
+                Expr::Lambda { body, .. } => body.clone(),
+                x => panic!("Expected lambda but found {:?}", x),
+            },
+            x => panic!("Expected fun b but found {:?}", x),
+        };
+
+        let reparse = document.edit(&Edit { offset: text.find('1').unwrap(), removed: 1, inserted: "12".to_string() });
+        assert_eq!( reparse, Reparse { reused: 0, parsed: 2 } );
+        assert_eq!( format!("{:?}", document.items()), format!("{:?}", parse_items(document.text()).as_deref()) );
+        drop(shared);
+    }
+}
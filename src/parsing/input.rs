@@ -1,8 +1,14 @@
 
 use super::parse_error::{ParseError};
+use super::ast::Meta;
+
+/// How deeply expressions, blocks, types and patterns may nest, which keeps
+/// deeply nested input from overflowing the stack.
+const MAX_DEPTH : usize = 256;
 
 pub struct Input<'a> {
-    data : &'a [(usize, char)] 
+    data : &'a [(usize, char)],
+    depth : usize,
 }
 
 #[derive(Clone, Copy)]
@@ -13,7 +19,22 @@ pub struct RestorePoint<'a> {
 impl<'a> Input<'a> {
 
     pub fn new(input : &'a [(usize, char)] ) -> Input<'a> { 
-        Input { data: input }
+        Input { data: input, depth: 0 }
+    }
+
+    /// Parses something which may contain itself, failing when that is nested
+    /// more than `MAX_DEPTH` deep.
+    pub fn nested<T>(&mut self, parse : impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        if self.depth >= MAX_DEPTH {
+            return match self.data {
+                [] => Err(ParseError::EndOfFile("Nesting too deep".to_string())),
+                [(i, _), ..] => Err(ParseError::ErrorAt(*i, "Nesting too deep".to_string())),
+            };
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
     }
 
     fn clear(&mut self) -> Result<(), ParseError> { 
@@ -63,6 +84,51 @@ impl<'a> Input<'a> {
         Ok(())
     }
 
+    pub fn expect_keyword(&mut self, s : &str) -> Result<(), ParseError> {
+        let restore_point = self.create_restore();
+        self.expect(s)?;
+        match self.data {
+            [(i, x), ..] if x.is_alphanumeric() || *x == '_' => {
+                self.restore(restore_point);
+                Err(ParseError::ErrorAt(*i, format!("Expected keyword {} but found {}", s, x)))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    pub fn at_end(&mut self) -> Result<bool, ParseError> {
+        self.clear()?;
+        Ok(self.data.is_empty())
+    }
+
+    pub fn mark(&mut self) -> Result<RestorePoint<'a>, ParseError> {
+        self.clear()?;
+        Ok(self.create_restore())
+    }
+
+    pub fn meta(&self, mark : RestorePoint<'a>) -> Meta {
+        let mut consumed = mark.data.len() - self.data.len();
+        while consumed > 1 && mark.data[consumed - 1].1.is_whitespace() {
+            consumed -= 1;
+        }
+        match (mark.data.first(), consumed) {
+            (Some((start, _)), 0) => Meta { start: *start, end: *start },
+            (Some((start, _)), _) => {
+                let (last, c) = mark.data[consumed - 1];
+                Meta { start: *start, end: last + c.len_utf8() }
+            },
+            (None, _) => Meta { start: 0, end: 0 },
+        }
+    }
+
+    pub fn position(&mut self) -> Result<usize, ParseError> {
+        self.clear()?;
+        match self.data {
+            [] => Err(ParseError::EndOfFile("position".to_string())),
+            [(i, _), ..] => Ok(*i),
+        }
+    }
+
     pub fn parse_symbol(&mut self) -> Result<String, ParseError> {
         self.clear()?;
 
@@ -114,6 +180,10 @@ impl<'a> Input<'a> {
         loop {
             match d {
                 [] => return Err(ParseError::EndOfFile("parse_number".to_string())),
+                // a '.' must be followed by a digit so that `1.max(2)` is not a number
+                [(_, '.'), (_, y), ..] if !y.is_numeric() => break,
+                // a '-' is only part of a number after an exponent so that `1-2` is a subtraction
+                [(_, '-'), ..] if !matches!( cs.last(), Some('e') | Some('E') ) => break,
                 [(_, x), rest @ ..] if x.is_numeric() 
                                     || *x == '.' 
                                     || *x == '-' 
@@ -126,6 +196,13 @@ impl<'a> Input<'a> {
             }
         }
 
+        if !cs.iter().any(|c| c.is_numeric()) {
+            return match self.data {
+                [(i, _), ..] => Err(ParseError::ErrorAt(*i, "Encountered number without digits".to_string())),
+                [] => Err(ParseError::EndOfFile("parse_number".to_string())),
+            };
+        }
+
         self.data = d;
 
         Ok(cs.into_iter().collect::<String>())
@@ -208,26 +285,26 @@ mod test {
 
     #[test]
     fn should_expect_string() -> Result<(), ParseError> {
-        let mut input = Input { data: &"::<>::".char_indices().collect::<Vec<(usize, char)>>() };
+        let mut input = Input { data: &"::<>::".char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
         input.expect("::<>::")?;
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), "".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), "".to_string() ); 
         Ok(())
     }
 
     #[test]
     fn should_parse_symbol() -> Result<(), ParseError> {
-        let mut input = Input { data: &"_Symbol_123 ".char_indices().collect::<Vec<(usize, char)>>() };
+        let mut input = Input { data: &"_Symbol_123 ".char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
         let symbol = input.parse_symbol()?;
         assert_eq!( symbol, "_Symbol_123" );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
         Ok(())
     }
 
     #[test]
     fn should_clear_whitespace() -> Result<(), ParseError> {
-        let mut input = Input { data: &"   x".char_indices().collect::<Vec<(usize, char)>>() };
+        let mut input = Input { data: &"   x".char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
         input.clear()?;
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), "x".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), "x".to_string() ); 
         Ok(())
     }
 
@@ -239,9 +316,9 @@ mod test {
 
         */
         
-        x"#.char_indices().collect::<Vec<(usize, char)>>() };
+        x"#.char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
         input.clear()?;
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), "x".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), "x".to_string() ); 
         Ok(())
     }
 
@@ -257,133 +334,152 @@ mod test {
 
         */
         
-        x"#.char_indices().collect::<Vec<(usize, char)>>() };
+        x"#.char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
         input.clear()?;
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), "x".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), "x".to_string() ); 
         Ok(())
     }
 
     #[test]
     fn should_parse_int() -> Result<(), ParseError> {
-        let mut input = Input { data: &"1234 ".char_indices().collect::<Vec<(usize, char)>>() };
+        let mut input = Input { data: &"1234 ".char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
         let number = input.parse_number()?;
         assert_eq!( number, "1234" );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
         Ok(())
     }
 
     #[test]
     fn should_parse_float() -> Result<(), ParseError> {
-        let mut input = Input { data: &"12.34 ".char_indices().collect::<Vec<(usize, char)>>() };
+        let mut input = Input { data: &"12.34 ".char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
         let number = input.parse_number()?;
         assert_eq!( number, "12.34" );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
         Ok(())
     }
 
     #[test]
     fn should_parse_float_starting_with_dot() -> Result<(), ParseError> {
-        let mut input = Input { data: &".01 ".char_indices().collect::<Vec<(usize, char)>>() };
+        let mut input = Input { data: &".01 ".char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
         let number = input.parse_number()?;
         assert_eq!( number, ".01" );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
         Ok(())
     }
 
     #[test]
     fn should_parse_scientific_notation() -> Result<(), ParseError> {
-        let mut input = Input { data: &"1234e42.0 ".char_indices().collect::<Vec<(usize, char)>>() };
+        let mut input = Input { data: &"1234e42.0 ".char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
         let number = input.parse_number()?;
         assert_eq!( number, "1234e42.0" );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
         Ok(())
     }
 
     #[test]
     fn should_parse_negative_scientific_notation() -> Result<(), ParseError> {
-        let mut input = Input { data: &"1234E-42 ".char_indices().collect::<Vec<(usize, char)>>() };
+        let mut input = Input { data: &"1234E-42 ".char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
         let number = input.parse_number()?;
         assert_eq!( number, "1234E-42" );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
         Ok(())
     }
 
     #[test]
     fn should_parse_negative_int() -> Result<(), ParseError> {
-        let mut input = Input { data: &"-1234 ".char_indices().collect::<Vec<(usize, char)>>() };
+        let mut input = Input { data: &"-1234 ".char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
         let number = input.parse_number()?;
         assert_eq!( number, "-1234" );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
         Ok(())
     }
 
     #[test]
     fn should_parse_string_with_whitespace() -> Result<(), ParseError> {
         let mut input = Input { data: &r#" /* */ " string with 123
-whitespace " "#.char_indices().collect::<Vec<(usize, char)>>() };
+whitespace " "#.char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
         let number = input.parse_string()?;
         assert_eq!( number, " string with 123\nwhitespace " );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
         Ok(())
     }
 
     #[test]
     fn should_parse_string_with_escapes() -> Result<(), ParseError> {
-        let mut input = Input { data: &r#" /* */ "\\ \0 \n \r \t \"" "#.char_indices().collect::<Vec<(usize, char)>>() };
+        let mut input = Input { data: &r#" /* */ "\\ \0 \n \r \t \"" "#.char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
         let number = input.parse_string()?;
         assert_eq!( number, "\\ \0 \n \r \t \"" );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
+        Ok(())
+    }
+
+    #[test]
+    fn should_expect_keyword() -> Result<(), ParseError> {
+        let mut input = Input { data: &"let letter".char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
+        input.expect_keyword("let")?;
+        assert!( input.expect_keyword("let").is_err() );
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " letter".to_string() );
+        Ok(())
+    }
+
+    #[test]
+    fn should_create_meta_from_mark() -> Result<(), ParseError> {
+        let mut input = Input { data: &"  /* */ symbol  ".char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
+        let mark = input.mark()?;
+        input.parse_symbol()?;
+        let meta = input.meta(mark);
+        assert_eq!( meta, Meta { start: 8, end: 14 } );
         Ok(())
     }
 
     #[test]
     fn should_restore() -> Result<(), ParseError> {
-        let mut input = Input { data: &"-1234 ".char_indices().collect::<Vec<(usize, char)>>() };
+        let mut input = Input { data: &"-1234 ".char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
         let r = input.create_restore();
         let number = input.parse_number()?;
         assert_eq!( number, "-1234" );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
 
         let number = input.parse_number();
-        assert_eq!( matches!(number, Err(_)), true );
+        assert!( number.is_err() );
 
         input.restore(r);
         let number = input.parse_number()?;
         assert_eq!( number, "-1234" );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
         Ok(()) 
     }
 
     #[test]
     fn should_handle_multiple_restores() -> Result<(), ParseError> {
-        let mut input = Input { data: &"-1234 789 ".char_indices().collect::<Vec<(usize, char)>>() };
+        let mut input = Input { data: &"-1234 789 ".char_indices().collect::<Vec<(usize, char)>>(), depth: 0 };
         let r1 = input.create_restore();
 
         let number = input.parse_number()?;
         assert_eq!( number, "-1234" );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " 789 ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " 789 ".to_string() ); 
 
         let r2 = input.create_restore();
 
         let number = input.parse_number()?;
         assert_eq!( number, "789" );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
 
         input.restore(r2);
 
         let number = input.parse_number()?;
         assert_eq!( number, "789" );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
 
         input.restore(r1);
 
         let number = input.parse_number()?;
         assert_eq!( number, "-1234" );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " 789 ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " 789 ".to_string() ); 
 
         let number = input.parse_number()?;
         assert_eq!( number, "789" );
-        assert_eq!( input.data.into_iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
+        assert_eq!( input.data.iter().map(|(_,x)| x).collect::<String>(), " ".to_string() ); 
 
         Ok(()) 
     }
@@ -4,7 +4,7 @@ use super::parse_error::ParseError;
 use super::input::Input;
 
 impl<'a> Input<'a> {
-    fn parse_use(&mut self) -> Result<Use, ParseError> {
+    pub fn parse_use(&mut self) -> Result<Use, ParseError> {
         self.expect("use")?;
 
         let mut namespace = vec![];
@@ -14,25 +14,24 @@ impl<'a> Input<'a> {
 
         loop {
             self.expect("::")?;
-            if matches!(self.expect("{"), Ok(_)) {
+            if self.expect("{").is_ok() {
                 break;
             }
             namespace.push(self.parse_symbol()?);
         }
         
         loop {
-            if matches!(self.expect("}"), Ok(_)) {
+            if self.expect("}").is_ok() {
                 break;
             }
-            if matches!(self.expect("*"), Ok(_)) {
+            if self.expect("*").is_ok() {
                 imports.push(Import::Everything);
             }
             else {
                 imports.push(Import::Item(self.parse_symbol()?));
             }
-            match self.expect(",") {
-                Ok(_) => continue,
-                _ => (),
+            if self.expect(",").is_ok() {
+                continue;
             }
         }
 
@@ -42,10 +41,18 @@ impl<'a> Input<'a> {
     }
 
     pub fn parse_type(&mut self) -> Result<Type, ParseError> {
+        self.nested(Self::parse_type_inner)
+    }
+
+    fn parse_type_inner(&mut self) -> Result<Type, ParseError> {
+        if matches!( self.expect("["), Ok(()) ) {
+            let t = self.parse_array_type()?;
+            return self.check_arrow_type(t);
+        }
+
         let tuple = self.parse_tuple_type();
-        match tuple {
-            Ok(t) => return self.check_arrow_type(t),
-            _ => (),
+        if let Ok(t) = tuple {
+            return self.check_arrow_type(t);
         }
 
         let simple = self.parse_symbol()?; 
@@ -107,11 +114,22 @@ impl<'a> Input<'a> {
         Ok(Type::Indexed( simple, types ))
     }
 
+    fn parse_array_type(&mut self) -> Result<Type, ParseError> {
+        let element = self.parse_type()?;
+        self.expect(";")?;
+        let length = match self.parse_number() {
+            Ok(n) => n,
+            Err(_) => self.parse_symbol()?,
+        };
+        self.expect("]")?;
+        Ok(Type::Array { element: Box::new(element), length })
+    }
+
     fn parse_tuple_type(&mut self) -> Result<Type, ParseError> {
         self.expect("(")?;
         let mut types = vec![];
 
-        if matches!( self.expect(")"), Err(_) ) {
+        if self.expect(")").is_err() {
             loop {
                 let t = self.parse_type()?;
 
@@ -206,10 +224,7 @@ mod test {
         assert_eq!( u.imports.len(), 2 );
         assert!( matches!( u.imports[0], Import::Everything ) );
         assert!( matches!( u.imports[1], Import::Item(_) ) );
-        match &u.imports[1] {
-            Import::Item(item) if item == "item" => (),
-            _ => assert!(false),
-        }
+        assert!( matches!( &u.imports[1], Import::Item(item) if item == "item" ) );
         
         assert_eq!( u.namespace.len(), 3);
         assert_eq!( u.namespace[0], "symb" );
@@ -226,14 +241,8 @@ mod test {
         assert_eq!( u.imports.len(), 2 );
         assert!( matches!( u.imports[0], Import::Item(_) ) );
         assert!( matches!( u.imports[1], Import::Item(_) ) );
-        match &u.imports[0] {
-            Import::Item(item) if item == "item1" => (),
-            _ => assert!(false),
-        }
-        match &u.imports[1] {
-            Import::Item(item) if item == "item2" => (),
-            _ => assert!(false),
-        }
+        assert!( matches!( &u.imports[0], Import::Item(item) if item == "item1" ) );
+        assert!( matches!( &u.imports[1], Import::Item(item) if item == "item2" ) );
         
         assert_eq!( u.namespace.len(), 3);
         assert_eq!( u.namespace[0], "symb" );
@@ -591,7 +600,7 @@ mod test {
 
         let three = types.remove(0);
 
-        assert_eq!( matches!( three, Type::Unit ), true );
+        assert!( matches!( three, Type::Unit ) );
 
         let four = types.remove(0);
 
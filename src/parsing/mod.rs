@@ -1,9 +1,15 @@
 
 pub mod ast;
-mod parse_error;
+pub mod parse_error;
 mod input;
 mod misc; 
 mod expr;
+mod pattern;
 mod top_level;
-mod parser; 
+pub mod parser;
+pub mod printer;
+pub mod dump;
+pub mod incremental;
+#[cfg(test)]
+pub mod fuzz;
 
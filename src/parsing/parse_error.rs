@@ -4,3 +4,22 @@ pub enum ParseError {
     EndOfFile(String),
     ErrorAt(usize, String),
 }
+
+/// Where a byte offset is, as a one based line and column.
+pub fn line_and_column(text : &str, offset : usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    (line, column)
+}
+
+/// A parse error as the place in the source `name` it happened, then what went wrong.
+pub fn describe_parse_error(name : &str, text : &str, e : &ParseError) -> String {
+    match e {
+        ParseError::ErrorAt(offset, message) => {
+            let (line, column) = line_and_column(text, *offset);
+            format!("{}:{}:{}: parse error: {}", name, line, column, message)
+        },
+        ParseError::EndOfFile(message) => format!("{}: parse error: unexpected end of file: {}", name, message),
+    }
+}
@@ -4,5 +4,190 @@ use super::parse_error::ParseError;
 use super::input::Input;
 
 pub fn parse( input : &str ) -> Result<Module, ParseError> {
-    Err(ParseError::EndOfFile(format!("blarg")))
+    Ok(module_from_items(parse_items(input)?))
+}
+
+/// Groups items by kind, in the order they came in.
+pub fn module_from_items( items : Vec<(Item, Meta)> ) -> Module {
+    let mut module = Module { fun_defs: vec![]
+                            , uses: vec![]
+                            , struct_defs: vec![]
+                            , enum_defs: vec![]
+                            , trait_defs: vec![]
+                            , impl_defs: vec![]
+                            , const_defs: vec![]
+                            , extern_defs: vec![]
+                            , mods: vec![]
+                            };
+
+    for (item, _) in items {
+        match item {
+            Item::Fun(fun_def) => module.fun_defs.push(fun_def),
+            Item::Use(u) => module.uses.push(u),
+            Item::Struct(struct_def) => module.struct_defs.push(struct_def),
+            Item::Enum(enum_def) => module.enum_defs.push(enum_def),
+            Item::Trait(trait_def) => module.trait_defs.push(trait_def),
+            Item::Impl(impl_def) => module.impl_defs.push(impl_def),
+            Item::Const(const_def) => module.const_defs.push(const_def),
+            Item::Extern(extern_def) => module.extern_defs.push(extern_def),
+            Item::Mod(name) => module.mods.push(name),
+        }
+    }
+
+    module
+}
+
+/// Parses the top level items in source order, along with where each one is.
+pub fn parse_items( input : &str ) -> Result<Vec<(Item, Meta)>, ParseError> {
+    parse_items_in(input, 0, input.len())
+}
+
+/// Parses the top level items in `input[start..end]`.  Positions are those of
+/// the whole input.
+pub fn parse_items_in( input : &str, start : usize, end : usize ) -> Result<Vec<(Item, Meta)>, ParseError> {
+    let i = input[start..end].char_indices().map(|(i, c)| (start + i, c)).collect::<Vec<(usize, char)>>();
+    let mut input = Input::new(&i);
+
+    let mut items = vec![];
+
+    while !input.at_end()? {
+        let mark = input.mark()?;
+
+        let item = if matches!( input.expect_keyword("fun"), Ok(()) ) {
+            input.restore(mark);
+            Item::Fun(input.parse_fun_def()?)
+        }
+        else if matches!( input.expect_keyword("use"), Ok(()) ) {
+            input.restore(mark);
+            Item::Use(input.parse_use()?)
+        }
+        else if matches!( input.expect_keyword("struct"), Ok(()) ) {
+            input.restore(mark);
+            Item::Struct(input.parse_struct_def()?)
+        }
+        else if matches!( input.expect_keyword("enum"), Ok(()) ) {
+            input.restore(mark);
+            Item::Enum(input.parse_enum_def()?)
+        }
+        else if matches!( input.expect_keyword("trait"), Ok(()) ) {
+            input.restore(mark);
+            Item::Trait(input.parse_trait_def()?)
+        }
+        else if matches!( input.expect_keyword("impl"), Ok(()) ) {
+            input.restore(mark);
+            Item::Impl(input.parse_impl_def()?)
+        }
+        else if matches!( input.expect_keyword("const"), Ok(()) ) {
+            input.restore(mark);
+            input.parse_const()?
+        }
+        else if matches!( input.expect_keyword("extern"), Ok(()) ) {
+            input.restore(mark);
+            Item::Extern(input.parse_extern_def()?)
+        }
+        else if matches!( input.expect_keyword("mod"), Ok(()) ) {
+            input.restore(mark);
+            Item::Mod(input.parse_mod()?)
+        }
+        else {
+            let i = input.position()?;
+            return Err(ParseError::ErrorAt(i, "Expected top level item".to_string()));
+        };
+
+        items.push((item, input.meta(mark)));
+    }
+
+    Ok(items)
+}
+
+/// Parses statements as though they were the body of a block.  Positions in
+/// errors are those of the given input.
+pub fn parse_statements( input : &str ) -> Result<Vec<Expr>, ParseError> {
+    let mut i = vec![(0, '{')];
+    i.extend(input.char_indices());
+    i.push((input.len(), '\n'));
+    i.push((input.len(), '}'));
+    let mut input = Input::new(&i);
+
+    let block = input.parse_block()?;
+
+    if !input.at_end()? {
+        let i = input.position()?;
+        return Err(ParseError::ErrorAt(i, "Expected end of statements".to_string()));
+    }
+
+    match block {
+        Expr::Block(exprs) => Ok(exprs),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_parse_module() -> Result<(), ParseError> {
+        let m = parse(r#"
+use std::{*};
+mod other;
+
+struct Point { x : Int, y : Int }
+
+enum Shape { Circle(Int), Square { side : Int } }
+
+trait Area {
+    fun area(self : Self) -> Int;
+}
+
+impl Area for Shape {
+    fun area(self : Self) -> Int { 0 }
+}
+
+/* comment */
+fun main() { }
+"#)?;
+
+        assert_eq!( m.uses.len(), 1 );
+        assert_eq!( m.mods, vec!["other".to_string()] );
+        assert_eq!( m.struct_defs.len(), 1 );
+        assert_eq!( m.enum_defs.len(), 1 );
+        assert_eq!( m.trait_defs.len(), 1 );
+        assert_eq!( m.impl_defs.len(), 1 );
+        assert_eq!( m.fun_defs.len(), 1 );
+        Ok(())
+    }
+
+    #[test]
+    fn should_report_unknown_item() {
+        let m = parse("fun main() { } blarg");
+        assert!( matches!( m, Err(ParseError::ErrorAt(15, _)) ) );
+    }
+
+    #[test]
+    fn should_parse_items_in_order() -> Result<(), ParseError> {
+        let source = "fun b() { }\n/* c */ struct A { x : Int }\nuse a::{*};";
+        let items = parse_items(source)?;
+        assert_eq!( items.len(), 3 );
+        assert!( matches!( items[0], (Item::Fun(_), Meta { start: 0, end: 11 }) ) );
+        assert!( matches!( items[1], (Item::Struct(_), Meta { start: 20, end: 40 }) ) );
+        assert!( matches!( items[2], (Item::Use(_), _) ) );
+        assert_eq!( &source[items[2].1.start..items[2].1.end], "use a::{*};" );
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_statements() -> Result<(), ParseError> {
+        let exprs = parse_statements("let x = 1; x + 2")?;
+        assert_eq!( exprs.len(), 2 );
+        assert!( matches!( exprs[0], Expr::Let { .. } ) );
+        assert!( matches!( exprs[1], Expr::Binary { op: BinOp::Add, .. } ) );
+
+        let exprs = parse_statements("x;")?;
+        assert!( matches!( exprs.as_slice(), [Expr::Variable(_), Expr::Unit] ) );
+
+        assert!( matches!( parse_statements("1 +; 2"), Err(ParseError::ErrorAt(3, _)) ) );
+        assert!( matches!( parse_statements("1 }"), Err(ParseError::ErrorAt(3, _)) ) );
+        Ok(())
+    }
 }
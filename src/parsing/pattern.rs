@@ -0,0 +1,272 @@
+
+use super::ast::*;
+use super::parse_error::ParseError;
+use super::input::Input;
+
+impl<'a> Input<'a> {
+    pub fn parse_pattern(&mut self) -> Result<Pattern, ParseError> {
+        self.nested(Self::parse_pattern_inner)
+    }
+
+    fn parse_pattern_inner(&mut self) -> Result<Pattern, ParseError> {
+        let mut alternatives = vec![self.parse_single_pattern()?];
+
+        loop {
+            let restore_point = self.create_restore();
+            // `||` is not an or-pattern separator
+            if matches!( self.expect("||"), Ok(()) ) {
+                self.restore(restore_point);
+                break;
+            }
+            if self.expect("|").is_err() {
+                break;
+            }
+            alternatives.push(self.parse_single_pattern()?);
+        }
+
+        match alternatives.len() {
+            1 => Ok(alternatives.remove(0)),
+            _ => Ok(Pattern::Or(alternatives)),
+        }
+    }
+
+    fn parse_single_pattern(&mut self) -> Result<Pattern, ParseError> {
+        let restore_point = self.create_restore();
+        if let Ok(s) = self.parse_string() {
+            return Ok(Pattern::DString(s));
+        }
+        self.restore(restore_point);
+
+        if let Some(inclusive) = self.parse_range_op() {
+            let end = self.parse_number()?;
+            return Ok(Pattern::Range { start: None, end: Some(end), inclusive });
+        }
+
+        if let Ok(n) = self.parse_number() {
+            return match self.parse_range_op() {
+                Some(inclusive) => self.parse_range_pattern_end(n, inclusive),
+                None => Ok(Pattern::Number(n)),
+            };
+        }
+        self.restore(restore_point);
+
+        if matches!( self.expect("("), Ok(()) ) {
+            let mut patterns = self.parse_pattern_list(")")?;
+            return match patterns.len() {
+                1 => Ok(patterns.remove(0)),
+                _ => Ok(Pattern::Tuple(patterns)),
+            };
+        }
+
+        if matches!( self.expect_keyword("true"), Ok(()) ) {
+            return Ok(Pattern::Bool(true));
+        }
+
+        if matches!( self.expect_keyword("false"), Ok(()) ) {
+            return Ok(Pattern::Bool(false));
+        }
+
+        let first = self.parse_symbol()?;
+
+        let mut namespace = vec![];
+        let mut name = first;
+        while matches!( self.expect("::"), Ok(()) ) {
+            namespace.push(name);
+            name = self.parse_symbol()?;
+        }
+
+        if matches!( self.expect("("), Ok(()) ) {
+            let patterns = self.parse_pattern_list(")")?;
+            return Ok(Pattern::Case { namespace, name, contents: CasePattern::Tuple(patterns) });
+        }
+
+        if matches!( self.expect("{"), Ok(()) ) {
+            let (fields, rest) = self.parse_field_pattern_list()?;
+            return Ok(Pattern::Case { namespace, name, contents: CasePattern::Struct { fields, rest } });
+        }
+
+        if !namespace.is_empty() {
+            return Ok(Pattern::Case { namespace, name, contents: CasePattern::Empty });
+        }
+
+        if name == "_" {
+            Ok(Pattern::Wildcard)
+        }
+        else {
+            Ok(Pattern::Variable(name))
+        }
+    }
+
+    fn parse_range_pattern_end(&mut self, start : String, inclusive : bool) -> Result<Pattern, ParseError> {
+        let restore_point = self.create_restore();
+        let end = match self.parse_number() {
+            Ok(end) => Some(end),
+            Err(e) if inclusive => return Err(e),
+            Err(_) => {
+                self.restore(restore_point);
+                None
+            },
+        };
+        Ok(Pattern::Range { start: Some(start), end, inclusive })
+    }
+
+    fn parse_pattern_list(&mut self, end : &str) -> Result<Vec<Pattern>, ParseError> {
+        let mut patterns = vec![];
+
+        if matches!( self.expect(end), Ok(()) ) {
+            return Ok(patterns);
+        }
+
+        loop {
+            patterns.push(self.parse_pattern()?);
+
+            if matches!( self.expect(end), Ok(()) ) {
+                break;
+            }
+
+            self.expect(",")?;
+        }
+
+        Ok(patterns)
+    }
+
+    fn parse_field_pattern_list(&mut self) -> Result<(Vec<(String, Pattern)>, bool), ParseError> {
+        let mut fields = vec![];
+
+        loop {
+            if matches!( self.expect("}"), Ok(()) ) {
+                return Ok((fields, false));
+            }
+
+            if matches!( self.expect(".."), Ok(()) ) {
+                self.expect("}")?;
+                return Ok((fields, true));
+            }
+
+            let name = self.parse_symbol()?;
+
+            match self.expect(":") {
+                Ok(_) => fields.push((name, self.parse_pattern()?)),
+                Err(_) => fields.push((name.clone(), Pattern::Variable(name))),
+            }
+
+            if self.expect(",").is_err() {
+                self.expect("}")?;
+                return Ok((fields, false));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_parse_wildcard_and_variable() -> Result<(), ParseError> {
+        let i = "_ ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        assert_eq!( input.parse_pattern()?, Pattern::Wildcard );
+
+        let i = "x ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        assert_eq!( input.parse_pattern()?, Pattern::Variable("x".to_string()) );
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_case_patterns() -> Result<(), ParseError> {
+        let i = "(Shape::Circle(r), Shape::Square { side : 5, .. }, Shape::Empty) ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_pattern()?;
+
+        let mut patterns = match u {
+            Pattern::Tuple(ps) => ps,
+            x => panic!( "Expected Tuple but found {:?}", x ),
+        };
+
+        assert_eq!( patterns.len(), 3 );
+
+        match patterns.remove(0) {
+            Pattern::Case { namespace, name, contents: CasePattern::Tuple(ps) } => {
+                assert_eq!( namespace, vec!["Shape".to_string()] );
+                assert_eq!( name, "Circle" );
+                assert_eq!( ps, vec![Pattern::Variable("r".to_string())] );
+            },
+            x => panic!( "Expected tuple Case but found {:?}", x ),
+        }
+
+        match patterns.remove(0) {
+            Pattern::Case { name, contents: CasePattern::Struct { fields, rest }, .. } => {
+                assert_eq!( name, "Square" );
+                assert_eq!( fields, vec![("side".to_string(), Pattern::Number("5".to_string()))] );
+                assert!( rest );
+            },
+            x => panic!( "Expected struct Case but found {:?}", x ),
+        }
+
+        match patterns.remove(0) {
+            Pattern::Case { name, contents: CasePattern::Empty, .. } => assert_eq!( name, "Empty" ),
+            x => panic!( "Expected empty Case but found {:?}", x ),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_or_pattern() -> Result<(), ParseError> {
+        let i = r#"1 | "two" | (a, _) "#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_pattern()?;
+
+        match u {
+            Pattern::Or(ps) => {
+                assert_eq!( ps.len(), 3 );
+                assert_eq!( ps[0], Pattern::Number("1".to_string()) );
+                assert_eq!( ps[1], Pattern::DString("two".to_string()) );
+                assert_eq!( ps[2], Pattern::Tuple(vec![Pattern::Variable("a".to_string()), Pattern::Wildcard]) );
+            },
+            x => panic!( "Expected Or but found {:?}", x ),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_field_shorthand() -> Result<(), ParseError> {
+        let i = "Point { x, y : _ } ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_pattern()?;
+
+        match u {
+            Pattern::Case { namespace, contents: CasePattern::Struct { fields, rest }, .. } => {
+                assert_eq!( namespace.len(), 0 );
+                assert_eq!( fields[0], ("x".to_string(), Pattern::Variable("x".to_string())) );
+                assert_eq!( fields[1], ("y".to_string(), Pattern::Wildcard) );
+                assert!( !rest );
+            },
+            x => panic!( "Expected struct Case but found {:?}", x ),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_range_patterns() -> Result<(), ParseError> {
+        let i = "(1..=9, -5..0, ..=2.5, 10..) ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_pattern()?;
+
+        let range = |start : Option<&str>, end : Option<&str>, inclusive| Pattern::Range { start: start.map(String::from)
+                                                                                        , end: end.map(String::from)
+                                                                                        , inclusive
+                                                                                        };
+        assert_eq!( u, Pattern::Tuple(vec![ range(Some("1"), Some("9"), true)
+                                          , range(Some("-5"), Some("0"), false)
+                                          , range(None, Some("2.5"), true)
+                                          , range(Some("10"), None, false)
+                                          ]) );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,639 @@
+
+use super::ast::*;
+use super::parse_error::ParseError;
+use super::parser::parse_items;
+
+/* Prints syntax trees back out as dust source in the one standard layout:
+   four space indentation, one statement per line and one space around binary
+   operators.  Printing what the parser produced and parsing it again gives
+   the same tree back.
+
+   The syntax tree has nowhere to keep comments, so `format` keeps those
+   between items as they are and leaves items with comments inside alone.
+*/
+
+const INDENT : &str = "    ";
+
+/// Formats a source file, keeping the items in their order.
+pub fn format(source : &str) -> Result<String, ParseError> {
+    let items = parse_items(source)?;
+    let mut chunks = vec![];
+    let mut previous = 0;
+
+    for (item, meta) in &items {
+        let mut chunk = String::new();
+        for comment in comments(&source[previous..meta.start]) {
+            chunk.push_str(comment);
+            chunk.push('\n');
+        }
+        let text = &source[meta.start..meta.end];
+        if text.contains("/*") {
+            chunk.push_str(text);
+        }
+        else {
+            chunk.push_str(&print_item(item));
+        }
+        chunks.push(chunk);
+        previous = meta.end;
+    }
+    let trailing = comments(&source[previous..]);
+    if !trailing.is_empty() {
+        chunks.push(trailing.join("\n"));
+    }
+
+    Ok(chunks.join("\n\n") + "\n")
+}
+
+/// The block comments, which may nest, in text which is otherwise whitespace.
+fn comments(text : &str) -> Vec<&str> {
+    let mut comments = vec![];
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i + 1 < bytes.len() {
+        if &bytes[i..i + 2] == b"/*" {
+            let start = i;
+            let mut depth = 0;
+            while i + 1 < bytes.len() {
+                if &bytes[i..i + 2] == b"/*" {
+                    depth += 1;
+                    i += 2;
+                }
+                else if &bytes[i..i + 2] == b"*/" {
+                    depth -= 1;
+                    i += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                else {
+                    i += 1;
+                }
+            }
+            comments.push(&text[start..i]);
+        }
+        else {
+            i += 1;
+        }
+    }
+    comments
+}
+
+pub fn print_module(module : &Module) -> String {
+    let mut items = vec![];
+    items.extend(module.uses.iter().map(print_use));
+    items.extend(module.mods.iter().map(|m| format!("mod {};", m)));
+    items.extend(module.struct_defs.iter().map(print_struct));
+    items.extend(module.enum_defs.iter().map(print_enum));
+    items.extend(module.trait_defs.iter().map(print_trait));
+    items.extend(module.impl_defs.iter().map(print_impl));
+    items.extend(module.const_defs.iter().map(print_const));
+    items.extend(module.extern_defs.iter().map(print_extern));
+    items.extend(module.fun_defs.iter().map(|f| print_fun(f, 0)));
+    items.iter().map(|i| format!("{}\n", i)).collect::<Vec<_>>().join("\n")
+}
+
+pub fn print_item(item : &Item) -> String {
+    match item {
+        Item::Fun(fun_def) => print_fun(fun_def, 0),
+        Item::Use(u) => print_use(u),
+        Item::Struct(struct_def) => print_struct(struct_def),
+        Item::Enum(enum_def) => print_enum(enum_def),
+        Item::Trait(trait_def) => print_trait(trait_def),
+        Item::Impl(impl_def) => print_impl(impl_def),
+        Item::Const(const_def) => print_const(const_def),
+        Item::Extern(extern_def) => print_extern(extern_def),
+        Item::Mod(name) => format!("mod {};", name),
+    }
+}
+
+fn print_const(const_def : &ConstDef) -> String {
+    format!("const {} : {} = {};", const_def.name, print_type(&const_def.const_type), print_expr(&const_def.value, 0))
+}
+
+fn print_extern(extern_def : &ExternDef) -> String {
+    format!("extern {} {};", quote(&extern_def.library), print_sig(&extern_def.sig))
+}
+
+fn print_use(u : &Use) -> String {
+    let imports = u.imports.iter().map(|i| match i {
+        Import::Everything => "*".to_string(),
+        Import::Item(name) => name.clone(),
+    }).collect::<Vec<_>>();
+    format!("use {}::{{{}}};", u.namespace.join("::"), imports.join(", "))
+}
+
+pub fn print_type_params(type_params : &[TypeParam]) -> String {
+    if type_params.is_empty() {
+        return String::new();
+    }
+    let params = type_params.iter().map(|tp| match tp.constraints.len() {
+        0 => tp.name.clone(),
+        _ => format!("{} : {}", tp.name, tp.constraints.join(" + ")),
+    }).collect::<Vec<_>>();
+    format!("<{}>", params.join(", "))
+}
+
+fn print_fields(fields : &[StructField], indent : usize) -> String {
+    if fields.is_empty() {
+        return "{ }".to_string();
+    }
+    let mut out = "{\n".to_string();
+    for field in fields {
+        out.push_str(&format!("{}{} : {},\n", INDENT.repeat(indent + 1), field.name, print_type(&field.field_type)));
+    }
+    out.push_str(&INDENT.repeat(indent));
+    out.push('}');
+    out
+}
+
+fn print_struct(struct_def : &StructDef) -> String {
+    format!("struct {}{} {}", struct_def.name, print_type_params(&struct_def.type_params), print_fields(&struct_def.fields, 0))
+}
+
+fn print_enum(enum_def : &EnumDef) -> String {
+    let mut out = format!("enum {}{} {{\n", enum_def.name, print_type_params(&enum_def.type_params));
+    for case in &enum_def.cases {
+        let case = match case {
+            EnumCase::EmptyCase { name } => name.clone(),
+            EnumCase::TypeCase { name, types } => format!("{}({})", name, types.iter().map(print_type).collect::<Vec<_>>().join(", ")),
+            EnumCase::StructCase { name, fields } => format!("{} {}", name, print_fields(fields, 1)),
+        };
+        out.push_str(&format!("{}{},\n", INDENT, case));
+    }
+    out.push('}');
+    out
+}
+
+pub fn print_sig(sig : &FunSig) -> String {
+    let params = sig.params.iter().map(print_param).collect::<Vec<_>>();
+    let mut out = format!("fun {}{}({})", sig.name, print_type_params(&sig.type_params), params.join(", "));
+    if sig.return_type != Type::Unit {
+        out.push_str(&format!(" -> {}", print_type(&sig.return_type)));
+    }
+    out
+}
+
+pub fn print_param(param : &Param) -> String {
+    let mut out = String::new();
+    if param.mutable {
+        out.push_str("mut ");
+    }
+    out.push_str(&param.name);
+    if param.param_type != Type::Infer {
+        out.push_str(&format!(" : {}", print_type(&param.param_type)));
+    }
+    out
+}
+
+fn print_fun(fun_def : &FunDef, indent : usize) -> String {
+    let constant = if fun_def.constant { "const " } else { "" };
+    format!("{}{} {}", constant, print_sig(&fun_def.sig), print_expr(&fun_def.body, indent))
+}
+
+fn print_trait(trait_def : &TraitDef) -> String {
+    let mut out = format!("trait {}{} {{\n", trait_def.name, print_type_params(&trait_def.type_params));
+    for item in &trait_def.items {
+        let item = match item {
+            TraitItem::Fun(sig) => format!("{};", print_sig(sig)),
+            TraitItem::Type { name, constraints } if constraints.is_empty() => format!("type {};", name),
+            TraitItem::Type { name, constraints } => format!("type {} : {};", name, constraints.join(" + ")),
+            TraitItem::Own { name, constraints } if constraints.is_empty() => format!("own {};", name),
+            TraitItem::Own { name, constraints } => format!("own {} : {};", name, constraints.join(" + ")),
+        };
+        out.push_str(&format!("{}{}\n", INDENT, item));
+    }
+    out.push('}');
+    out
+}
+
+fn print_impl(impl_def : &ImplDef) -> String {
+    let mut out = format!("impl{} ", print_type_params(&impl_def.type_params));
+    if let Some(trait_type) = &impl_def.trait_type {
+        out.push_str(&format!("{} for ", print_type(trait_type)));
+    }
+    out.push_str(&print_type(&impl_def.impl_type));
+
+    if impl_def.items.is_empty() {
+        out.push_str(" { }");
+        return out;
+    }
+    out.push_str(" {\n");
+    for (i, item) in impl_def.items.iter().enumerate() {
+        let item = match item {
+            ImplItem::Type { name, item_type } => format!("type {} = {};", name, print_type(item_type)),
+            ImplItem::Own { name, item_type } => format!("own {} = {};", name, print_type(item_type)),
+            ImplItem::Fun(fun_def) => print_fun(fun_def, 1),
+        };
+        if i > 0 && matches!( impl_def.items[i - 1], ImplItem::Fun(_) ) {
+            out.push('\n');
+        }
+        out.push_str(&format!("{}{}\n", INDENT, item));
+    }
+    out.push('}');
+    out
+}
+
+pub fn print_type(t : &Type) -> String {
+    match t {
+        Type::Unit => "()".to_string(),
+        Type::Simple(name) => name.clone(),
+        Type::Indexed(name, args) => format!("{}<{}>", name, args.iter().map(print_type).collect::<Vec<_>>().join(", ")),
+        Type::Arrow { input, output } => match **input {
+            Type::Arrow { .. } => format!("({}) -> {}", print_type(input), print_type(output)),
+            _ => format!("{} -> {}", print_type(input), print_type(output)),
+        },
+        Type::Tuple(types) => format!("({})", types.iter().map(print_type).collect::<Vec<_>>().join(", ")),
+        Type::Namespace(names, t) => format!("{}::{}", names.join("::"), print_type(t)),
+        Type::Array { element, length } => format!("[{}; {}]", print_type(element), length),
+        Type::Infer => "_".to_string(),
+    }
+}
+
+pub fn print_pattern(pattern : &Pattern) -> String {
+    match pattern {
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Number(n) => n.clone(),
+        Pattern::DString(s) => quote(s),
+        Pattern::Bool(b) => b.to_string(),
+        Pattern::Variable(name) => name.clone(),
+        Pattern::Range { start, end, inclusive } => format!( "{}{}{}"
+                                                           , start.as_deref().unwrap_or("")
+                                                           , if *inclusive { "..=" } else { ".." }
+                                                           , end.as_deref().unwrap_or("")
+                                                           ),
+        Pattern::Tuple(patterns) => format!("({})", patterns.iter().map(print_pattern).collect::<Vec<_>>().join(", ")),
+        Pattern::Case { namespace, name, contents } => {
+            let mut out = namespace.iter().map(|n| format!("{}::", n)).collect::<String>();
+            out.push_str(name);
+            match contents {
+                CasePattern::Empty => { },
+                CasePattern::Tuple(patterns) => out.push_str(&format!("({})", patterns.iter().map(print_pattern).collect::<Vec<_>>().join(", "))),
+                CasePattern::Struct { fields, rest } => {
+                    let mut fields = fields.iter().map(|(n, p)| match p {
+                        Pattern::Variable(v) if v == n => n.clone(),
+                        p => format!("{} : {}", n, print_pattern(p)),
+                    }).collect::<Vec<_>>();
+                    if *rest {
+                        fields.push("..".to_string());
+                    }
+                    match fields.len() {
+                        0 => out.push_str(" { }"),
+                        _ => out.push_str(&format!(" {{ {} }}", fields.join(", "))),
+                    }
+                },
+            }
+            out
+        },
+        Pattern::Or(patterns) => patterns.iter().map(print_pattern).collect::<Vec<_>>().join(" | "),
+    }
+}
+
+/// A string literal which reads back as the given string.
+pub fn quote(s : &str) -> String {
+    let mut out = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\0' => out.push_str("\\0"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn precedence(op : BinOp) -> u8 {
+    match op {
+        BinOp::Or => 1,
+        BinOp::And => 2,
+        BinOp::Eq | BinOp::NotEq => 3,
+        BinOp::Less | BinOp::LessEq | BinOp::Greater | BinOp::GreaterEq => 4,
+        BinOp::Add | BinOp::Sub => 5,
+        BinOp::Mul | BinOp::Div | BinOp::Rem => 6,
+    }
+}
+
+fn bin_op(op : BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+        BinOp::Rem => "%",
+        BinOp::Eq => "==",
+        BinOp::NotEq => "!=",
+        BinOp::Less => "<",
+        BinOp::LessEq => "<=",
+        BinOp::Greater => ">",
+        BinOp::GreaterEq => ">=",
+        BinOp::And => "&&",
+        BinOp::Or => "||",
+    }
+}
+
+fn ends_with_block(e : &Expr) -> bool {
+    matches!( e, Expr::Block(_) | Expr::Match { .. } | Expr::If { .. } | Expr::While { .. } | Expr::Foreach { .. } | Expr::Try(_) )
+}
+
+/// Whether an expression can be followed by `(`, `[` or `.` without parentheses.
+fn is_postfix_operand(e : &Expr) -> bool {
+    matches!( e, Expr::Number(_) | Expr::DString(_) | Expr::Unit | Expr::Variable(_) | Expr::Namespace(_, _)
+               | Expr::Tuple(_) | Expr::Call { .. } | Expr::Dot { .. } | Expr::MethodCall { .. } | Expr::Bool(_)
+               | Expr::List(_) | Expr::Dict(_) | Expr::Struct { .. } | Expr::Index { .. } | Expr::Propagate(_)
+               | Expr::Assert { .. } | Expr::Panic { .. } )
+}
+
+fn parens(e : &Expr, indent : usize) -> String {
+    format!("({})", print_expr(e, indent))
+}
+
+fn postfix_operand(e : &Expr, indent : usize) -> String {
+    if is_postfix_operand(e) { print_expr(e, indent) } else { parens(e, indent) }
+}
+
+/// An operand of a unary or binary operator which binds at least as tightly as `min_precedence`.
+fn operand(e : &Expr, min_precedence : u8, indent : usize) -> String {
+    match e {
+        Expr::Binary { op, .. } if precedence(*op) >= min_precedence => print_expr(e, indent),
+        Expr::Unary { .. } => print_expr(e, indent),
+        e if is_postfix_operand(e) => print_expr(e, indent),
+        e => parens(e, indent),
+    }
+}
+
+fn list(exprs : &[Expr], indent : usize) -> String {
+    exprs.iter().map(|e| print_expr(e, indent)).collect::<Vec<_>>().join(", ")
+}
+
+pub fn print_expr(expr : &Expr, indent : usize) -> String {
+    match expr {
+        Expr::Number(n) => n.clone(),
+        Expr::DString(s) => quote(s),
+        Expr::Unit => "()".to_string(),
+        Expr::Bool(b) => b.to_string(),
+        Expr::Variable(name) => name.clone(),
+        Expr::Namespace(names, name) => format!("{}::{}", names.join("::"), name),
+        Expr::Tuple(exprs) => format!("({})", list(exprs, indent)),
+        Expr::List(exprs) => format!("[{}]", list(exprs, indent)),
+        Expr::Dict(pairs) if pairs.is_empty() => "[:]".to_string(),
+        Expr::Dict(pairs) => {
+            let pairs = pairs.iter().map(|(k, v)| format!("{} : {}", print_expr(k, indent), print_expr(v, indent))).collect::<Vec<_>>();
+            format!("[{}]", pairs.join(", "))
+        },
+        Expr::Struct { namespace, name, fields } if fields.is_empty() => {
+            let prefix = namespace.iter().map(|n| format!("{}::", n)).collect::<String>();
+            format!("{}{} {{ }}", prefix, name)
+        },
+        Expr::Struct { namespace, name, fields } => {
+            let prefix = namespace.iter().map(|n| format!("{}::", n)).collect::<String>();
+            let fields = fields.iter().map(|(n, v)| format!("{} : {}", n, print_expr(v, indent))).collect::<Vec<_>>();
+            format!("{}{} {{ {} }}", prefix, name, fields.join(", "))
+        },
+        Expr::Block(exprs) => print_block(exprs, indent),
+        // `(x.f)()` calls a field where `x.f()` calls a method
+        Expr::Call { fun, args, .. } if matches!( **fun, Expr::Dot { .. } ) => format!("{}({})", parens(fun, indent), list(args, indent)),
+        Expr::Call { fun, args, .. } => format!("{}({})", postfix_operand(fun, indent), list(args, indent)),
+        Expr::Dot { expr, name } => format!("{}.{}", postfix_operand(expr, indent), name),
+        Expr::MethodCall { receiver, name, args, .. } => format!("{}.{}({})", postfix_operand(receiver, indent), name, list(args, indent)),
+        Expr::Index { expr, index } => format!("{}[{}]", postfix_operand(expr, indent), print_expr(index, indent)),
+        Expr::Range { start, end, inclusive } => {
+            let start = start.as_ref().map_or(String::new(), |e| operand(e, 0, indent));
+            let end = end.as_ref().map_or(String::new(), |e| operand(e, 0, indent));
+            format!("{}{}{}", start, if *inclusive { "..=" } else { ".." }, end)
+        },
+        Expr::Let { name, mutable, let_type, value, .. } => {
+            let mut out = "let ".to_string();
+            if *mutable {
+                out.push_str("mut ");
+            }
+            out.push_str(name);
+            if *let_type != Type::Infer {
+                out.push_str(&format!(" : {}", print_type(let_type)));
+            }
+            out.push_str(&format!(" = {}", print_expr(value, indent)));
+            out
+        },
+        Expr::Assign { target, value, .. } => format!("{} = {}", print_expr(target, indent), print_expr(value, indent)),
+        Expr::Return(e) => match **e {
+            Expr::Unit => "return".to_string(),
+            _ => format!("return {}", print_expr(e, indent)),
+        },
+        Expr::Break => "break".to_string(),
+        Expr::Continue => "continue".to_string(),
+        Expr::Match { expr, arms } => {
+            let mut out = format!("match {} {{\n", print_expr(expr, indent));
+            for arm in arms {
+                out.push_str(&format!("{}{} => {},\n", INDENT.repeat(indent + 1), print_pattern(&arm.pattern), print_expr(&arm.body, indent + 1)));
+            }
+            out.push_str(&INDENT.repeat(indent));
+            out.push('}');
+            out
+        },
+        Expr::Lambda { params, body } => format!("|{}| {}", params.iter().map(print_param).collect::<Vec<_>>().join(", "), print_expr(body, indent)),
+        Expr::Unary { op, expr } => {
+            let op = match op { UnaryOp::Neg => "-", UnaryOp::Not => "!" };
+            let e = operand(expr, u8::MAX, indent);
+            // `- 1` and `- 1.max(2)` do not start with the number `-1`, and `- -1` is not `--1`
+            if op == "-" && e.starts_with(|c : char| c.is_numeric() || c == '.' || c == '-') {
+                format!("- {}", e)
+            }
+            else {
+                format!("{}{}", op, e)
+            }
+        },
+        Expr::Binary { op, left, right } => {
+            let p = precedence(*op);
+            format!("{} {} {}", operand(left, p, indent), bin_op(*op), operand(right, p + 1, indent))
+        },
+        Expr::If { condition, then, otherwise } => {
+            let mut out = format!("if {} {}", print_expr(condition, indent), print_expr(then, indent));
+            match &**otherwise {
+                Expr::Unit => { },
+                e @ Expr::If { .. } => out.push_str(&format!(" else {}", print_expr(e, indent))),
+                e => out.push_str(&format!(" else {}", print_expr(e, indent))),
+            }
+            out
+        },
+        Expr::While { condition, body } => format!("while {} {}", print_expr(condition, indent), print_expr(body, indent)),
+        Expr::Foreach { name, iterable, body, .. } => format!("foreach {} in {} {}", name, print_expr(iterable, indent), print_expr(body, indent)),
+        Expr::Yield(e) => format!("yield {}", print_expr(e, indent)),
+        Expr::Try(body) => format!("try {}", print_expr(body, indent)),
+        Expr::Propagate(e) => format!("{}?", postfix_operand(e, indent)),
+        Expr::Assert { condition, message: None, .. } => format!("assert({})", print_expr(condition, indent)),
+        Expr::Assert { condition, message: Some(message), .. } => format!("assert({}, {})", print_expr(condition, indent), print_expr(message, indent)),
+        Expr::Panic { message, .. } => format!("panic({})", print_expr(message, indent)),
+    }
+}
+
+fn print_block(exprs : &[Expr], indent : usize) -> String {
+    // the parser ends a block with `()` when its last statement has a semicolon
+    let (statements, semicolon) = match exprs {
+        [rest @ .., Expr::Unit] => (rest, true),
+        exprs => (exprs, false),
+    };
+    if statements.is_empty() {
+        return "{ }".to_string();
+    }
+
+    let mut out = "{\n".to_string();
+    for (i, statement) in statements.iter().enumerate() {
+        let last = i + 1 == statements.len();
+        out.push_str(&INDENT.repeat(indent + 1));
+        out.push_str(&print_statement(statement, indent + 1));
+        if (last && semicolon) || (!last && !ends_with_block(statement)) {
+            out.push(';');
+        }
+        out.push('\n');
+    }
+    out.push_str(&INDENT.repeat(indent));
+    out.push('}');
+    out
+}
+
+/// A statement which starts like a block, `if`, `while` or `try` but goes on after it
+/// needs parentheses to not end early.
+fn print_statement(statement : &Expr, indent : usize) -> String {
+    let text = print_expr(statement, indent);
+    let block_like = text.starts_with('{') || text.starts_with("if ") || text.starts_with("while ")
+                  || text.starts_with("foreach ") || text.starts_with("try ");
+    if block_like && !ends_with_block(statement) {
+        format!("({})", text)
+    }
+    else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::parser::parse;
+    use crate::evaluating::conformance::CASES;
+    use crate::evaluating::interpreter;
+
+    #[test]
+    fn should_print_conformance_programs_back_to_equivalent_source() {
+        for case in CASES {
+            let printed = print_module(&parse(case.source).unwrap());
+            let module = parse(&printed).unwrap_or_else(|e| panic!("{}: {:?}\n{}", case.name, e, printed));
+            assert_eq!( print_module(&module), printed, "{}", case.name );
+
+            let mut out = vec![];
+            let _ = interpreter::run(&module, &mut out);
+            assert_eq!( String::from_utf8(out).unwrap(), case.output, "{}\n{}", case.name, printed );
+        }
+    }
+
+    #[test]
+    fn should_keep_precedence_and_statement_boundaries() {
+        let source = "fun f() -> Int { (1 + 2) * 3 - (4 - 5) - -1 + - 1 + -(-x); (if a { 1 } else { 2 }).max(3); { 1 }; if a { }; |x| x + 1; !(a && b) || c }";
+        let printed = print_module(&parse(source).unwrap());
+        assert_eq!( printed, r#"fun f() -> Int {
+    (1 + 2) * 3 - (4 - 5) - -1 + - 1 + - -x;
+    (if a {
+        1
+    } else {
+        2
+    }).max(3);
+    {
+        1
+    }
+    if a { }
+    |x| x + 1;
+    !(a && b) || c
+}
+"# );
+        assert_eq!( print_module(&parse(&printed).unwrap()), printed );
+    }
+
+    #[test]
+    fn should_keep_called_fields_and_negated_postfix_numbers() {
+        let source = "fun f() { (x.f)(1); x.f(1); -(.5[0]); - 1.max(2) }";
+        let printed = print_module(&parse(source).unwrap());
+        assert!( printed.contains("(x.f)(1);") && printed.contains("    x.f(1);") );
+        assert!( printed.contains("- .5[0];") && printed.contains("- 1.max(2)") );
+        assert_eq!( print_module(&parse(&printed).unwrap()), printed );
+    }
+
+    #[test]
+    fn should_print_items() {
+        let source = r#"use a::b::{c, *}; mod m;
+struct P<T : Eq + Show> { x : T, y : List<(Int, a::B<T>)> }
+enum E { A, B(Int -> Int, (Int -> Int) -> Int), C { s : String } }
+trait T<A> { type X : Eq; own Y; fun f(self : Self, mut y : Int) -> Self; }
+impl<A> T<A> for P<A> { type X = Int; own Y = Int; fun f(self : Self, mut y : Int) -> Self { match y { 0 | 1 => self, E::C { s : "\n", .. } => self, E::B(_, x) => self, E::C { s } => self } } }
+const N : Int = twice(2) + 1; const GRID : [[Bool; N]; 2] = [];
+extern "libm.so.6" fun pow(x : Float, y : Float) -> Float;
+const fun twice(x : Int) -> Int { x * 2 }
+fun main() { let xs : List<Int> = [1, 2]; let d = [:]; let e = ["a\"" : P { x : 1, y : [] }]; xs[0] = 3; return; }"#;
+        let printed = print_module(&parse(source).unwrap());
+        assert_eq!( printed, r#"use a::b::{c, *};
+
+mod m;
+
+struct P<T : Eq + Show> {
+    x : T,
+    y : List<(Int, a::B<T>)>,
+}
+
+enum E {
+    A,
+    B(Int -> Int, (Int -> Int) -> Int),
+    C {
+        s : String,
+    },
+}
+
+trait T<A> {
+    type X : Eq;
+    own Y;
+    fun f(self : Self, mut y : Int) -> Self;
+}
+
+impl<A> T<A> for P<A> {
+    type X = Int;
+    own Y = Int;
+    fun f(self : Self, mut y : Int) -> Self {
+        match y {
+            0 | 1 => self,
+            E::C { s : "\n", .. } => self,
+            E::B(_, x) => self,
+            E::C { s } => self,
+        }
+    }
+}
+
+const N : Int = twice(2) + 1;
+
+const GRID : [[Bool; N]; 2] = [];
+
+extern "libm.so.6" fun pow(x : Float, y : Float) -> Float;
+
+const fun twice(x : Int) -> Int {
+    x * 2
+}
+
+fun main() {
+    let xs : List<Int> = [1, 2];
+    let d = [:];
+    let e = ["a\"" : P { x : 1, y : [] }];
+    xs[0] = 3;
+    return;
+}
+"# );
+        assert_eq!( print_module(&parse(&printed).unwrap()), printed );
+    }
+
+    #[test]
+    fn should_format_keeping_order_and_comments() {
+        let source = "/* header */\nfun main()   { println( 1 ) }\n\n\n/* a point */ struct P { x : Int }\nfun f() { /* inside */ g( ) }\n/* trailing */";
+        assert_eq!( format(source).unwrap()
+                  , "/* header */\nfun main() {\n    println(1)\n}\n\n/* a point */\nstruct P {\n    x : Int,\n}\n\nfun f() { /* inside */ g( ) }\n\n/* trailing */\n" );
+        let formatted = format(source).unwrap();
+        assert_eq!( format(&formatted).unwrap(), formatted );
+    }
+}
@@ -9,7 +9,28 @@ impl<'a> Input<'a> {
     // TODO move this to misc so that we can parse local functions
     // TODO alternatively, we can just force let lambda for local functions
     // which should be okay if the let can pick up on the inference
-        Err(ParseError::EndOfFile("TODO".to_string()))
+        let mark = self.mark()?;
+        let sig = self.parse_fun_sig()?;
+        let body = self.parse_block()?;
+        Ok( FunDef { sig, body, constant: false, meta: self.meta(mark) } )
+    }
+
+    /// A `const` item, or a `const fun`.
+    pub fn parse_const(&mut self) -> Result<Item, ParseError> {
+        self.expect_keyword("const")?;
+        let mark = self.mark()?;
+        if matches!( self.expect_keyword("fun"), Ok(()) ) {
+            self.restore(mark);
+            let fun_def = self.parse_fun_def()?;
+            return Ok(Item::Fun(FunDef { constant: true, ..fun_def }));
+        }
+        let name = self.parse_symbol()?;
+        self.expect(":")?;
+        let const_type = self.parse_type()?;
+        self.expect("=")?;
+        let value = self.parse_expr()?;
+        self.expect(";")?;
+        Ok(Item::Const(ConstDef { name, const_type, value }))
     }
 
     pub fn parse_enum_def(&mut self) -> Result<EnumDef, ParseError> {
@@ -18,8 +39,9 @@ impl<'a> Input<'a> {
             input.expect("(")?;
             loop {
                 let t = input.parse_type()?;
+                types.push(t);
                 match input.expect(",") {
-                    Ok(_) => types.push(t), 
+                    Ok(_) => (),
                     Err(_) => break,
                 }
             }
@@ -31,20 +53,15 @@ impl<'a> Input<'a> {
             input.expect("{")?; 
 
             let mut cases = vec![];
-            loop {
+            while let Ok(name) = input.parse_symbol() {
 
-                let name = match input.parse_symbol() {
-                    Ok(name) => name,
-                    Err(_) => break,
-                };
-                
                 match parse_types(input) {
                     Ok(types) => {
                         cases.push( EnumCase::TypeCase { name, types } );
                     },
                     Err(_) => {
                         match input.parse_struct_field_list() {
-                            Ok(fields) if fields.len() > 0 => {
+                            Ok(fields) if !fields.is_empty() => {
                                 cases.push( EnumCase::StructCase { name, fields } );
                             },
                             _ => {
@@ -54,9 +71,8 @@ impl<'a> Input<'a> {
                     },
                 }
 
-                match input.expect(",") {
-                    Err(_) => break,
-                    _ => (),
+                if input.expect(",").is_err() {
+                    break;
                 }
             }
 
@@ -100,53 +116,44 @@ impl<'a> Input<'a> {
             let mut items = vec![];
             input.expect("{")?;
             loop {
-                match input.parse_fun_sig() {
-                    Ok(sig) => {
-                        input.expect(";")?;
-                        items.push( TraitItem::Fun(sig) );
-                        continue;
-                    },
-                    Err(_) => (),
+                if let Ok(sig) = input.parse_fun_sig() {
+                    input.expect(";")?;
+                    items.push( TraitItem::Fun(sig) );
+                    continue;
                 }
 
-                match input.expect("type") {
-                    Ok(_) => {
-                        let name = input.parse_symbol()?;
-                        match input.expect(":") {
-                            Ok(_) => {
-                                let constraints = input.parse_constraint_list()?;
-                                input.expect(";")?;
-                                items.push( TraitItem::Type { name, constraints } );
-                                continue;
-                            },
-                            Err(_) => {
-                                input.expect(";")?;
-                                items.push( TraitItem::Type { name, constraints: vec![] } );
-                                continue;
-                            },
-                        }
-                    },
-                    Err(_) => (),
+                if input.expect("type").is_ok() {
+                    let name = input.parse_symbol()?;
+                    match input.expect(":") {
+                        Ok(_) => {
+                            let constraints = input.parse_constraint_list()?;
+                            input.expect(";")?;
+                            items.push( TraitItem::Type { name, constraints } );
+                            continue;
+                        },
+                        Err(_) => {
+                            input.expect(";")?;
+                            items.push( TraitItem::Type { name, constraints: vec![] } );
+                            continue;
+                        },
+                    }
                 }
 
-                match input.expect("own") {
-                    Ok(_) => {
-                        let name = input.parse_symbol()?;
-                        match input.expect(":") {
-                            Ok(_) => {
-                                let constraints = input.parse_constraint_list()?;
-                                input.expect(";")?;
-                                items.push( TraitItem::Own { name, constraints } );
-                                continue;
-                            },
-                            Err(_) => {
-                                input.expect(";")?;
-                                items.push( TraitItem::Own { name, constraints: vec![] } );
-                                continue;
-                            },
-                        }
-                    },
-                    Err(_) => (),
+                if input.expect("own").is_ok() {
+                    let name = input.parse_symbol()?;
+                    match input.expect(":") {
+                        Ok(_) => {
+                            let constraints = input.parse_constraint_list()?;
+                            input.expect(";")?;
+                            items.push( TraitItem::Own { name, constraints } );
+                            continue;
+                        },
+                        Err(_) => {
+                            input.expect(";")?;
+                            items.push( TraitItem::Own { name, constraints: vec![] } );
+                            continue;
+                        },
+                    }
                 }
 
                 break;
@@ -171,9 +178,63 @@ impl<'a> Input<'a> {
         }
     }
 
-    pub fn parse_impl_def(&mut self) -> Result<(), ParseError> {
-        Err(ParseError::EndOfFile("TODO".to_string()))
-        // TODO type def
+    pub fn parse_impl_def(&mut self) -> Result<ImplDef, ParseError> {
+        fn parse_impl_item_list(input : &mut Input) -> Result<Vec<ImplItem>, ParseError> {
+            let mut items = vec![];
+            input.expect("{")?;
+            loop {
+                if matches!( input.expect("}"), Ok(()) ) {
+                    break;
+                }
+
+                if matches!( input.expect_keyword("type"), Ok(()) ) {
+                    let name = input.parse_symbol()?;
+                    input.expect("=")?;
+                    let item_type = input.parse_type()?;
+                    input.expect(";")?;
+                    items.push( ImplItem::Type { name, item_type } );
+                    continue;
+                }
+
+                if matches!( input.expect_keyword("own"), Ok(()) ) {
+                    let name = input.parse_symbol()?;
+                    input.expect("=")?;
+                    let item_type = input.parse_type()?;
+                    input.expect(";")?;
+                    items.push( ImplItem::Own { name, item_type } );
+                    continue;
+                }
+
+                items.push( ImplItem::Fun( input.parse_fun_def()? ) );
+            }
+            Ok(items)
+        }
+
+        self.expect_keyword("impl")?;
+
+        let type_params = self.parse_type_param_list().unwrap_or_default();
+
+        let first_type = self.parse_type()?;
+
+        match self.expect_keyword("for") {
+            Ok(_) => {
+                let impl_type = self.parse_type()?;
+                let items = parse_impl_item_list(self)?;
+                Ok( ImplDef { type_params, trait_type: Some(first_type), impl_type, items } )
+            },
+            Err(_) => {
+                let items = parse_impl_item_list(self)?;
+                Ok( ImplDef { type_params, trait_type: None, impl_type: first_type, items } )
+            },
+        }
+    }
+
+    pub fn parse_extern_def(&mut self) -> Result<ExternDef, ParseError> {
+        self.expect_keyword("extern")?;
+        let library = self.parse_string()?;
+        let sig = self.parse_fun_sig()?;
+        self.expect(";")?;
+        Ok(ExternDef { library, sig })
     }
 
     pub fn parse_mod(&mut self) -> Result<String, ParseError> {
@@ -188,18 +249,12 @@ impl<'a> Input<'a> {
         
         self.expect("{")?;
 
-        loop {
-            match self.parse_symbol() {
-                Ok(name) => {
-                    self.expect(":")?;
-                    let field_type = self.parse_type()?;
-                    fields.push( StructField { name, field_type } );
-                    match self.expect(",") {
-                        Ok(_) => (),
-                        Err(_) => break,
-                    }
-                },
-                Err(_) => break,
+        while let Ok(name) = self.parse_symbol() {
+            self.expect(":")?;
+            let field_type = self.parse_type()?;
+            fields.push( StructField { name, field_type } );
+            if self.expect(",").is_err() {
+                break;
             }
         }
 
@@ -213,9 +268,8 @@ impl<'a> Input<'a> {
         loop {
             let c = self.parse_symbol()?;
             cs.push(c);
-            match self.expect("+") {
-                Err(_) => break,
-                _ => (),
+            if self.expect("+").is_err() {
+                break;
             }
         }
         Ok(cs)
@@ -236,9 +290,8 @@ impl<'a> Input<'a> {
                 _ => params.push( TypeParam { name, constraints : vec![] } ),
             }
 
-            match self.expect(",") {
-                Err(_) => break,
-                _ => (),
+            if self.expect(",").is_err() {
+                break;
             }
         }
 
@@ -254,29 +307,31 @@ impl<'a> Input<'a> {
         let mut params = vec![];
 
         loop {
-            
-            match self.expect("mut") {
+            let mark = self.mark()?;
+
+            match self.expect_keyword("mut") {
                 Ok(_) =>  {
                     let name = self.parse_symbol()?;
                     self.expect(":")?;
                     let param_type = self.parse_type()?;
-                    params.push( Param { name, param_type, mutable: true } );
+                    let meta = self.meta(mark);
+                    params.push( Param { name, param_type, mutable: true, meta } );
                 },
                 Err(_) => {
                     match self.parse_symbol() {
                         Ok(name) => {
                             self.expect(":")?;
                             let param_type = self.parse_type()?;
-                            params.push( Param { name, param_type, mutable: false } );
+                            let meta = self.meta(mark);
+                            params.push( Param { name, param_type, mutable: false, meta } );
                         },
                         Err(_) => break, 
                     }
                 },
             }
 
-            match self.expect(",") {
-                Err(_) => break,
-                _ => (),
+            if self.expect(",").is_err() {
+                break;
             }
         }
 
@@ -290,10 +345,7 @@ impl<'a> Input<'a> {
 
         let name = self.parse_symbol()?;
 
-        let type_params = match self.parse_type_param_list() {
-            Ok(tps) => tps,
-            Err(_) => vec![],
-        };
+        let type_params = self.parse_type_param_list().unwrap_or_default();
 
         let params = self.parse_param_list()?;
 
@@ -358,7 +410,7 @@ mod test {
             x => panic!( "Expected Simple but found {:?}", x ),
         }
 
-        assert_eq!( a.mutable, true );
+        assert!( a.mutable );
 
         let b = u.remove(0);
 
@@ -380,7 +432,7 @@ mod test {
             x => panic!( "Expected Simple but found {:?}", x ),
         }
 
-        assert_eq!( b.mutable, false );
+        assert!( !b.mutable );
 
         let c = u.remove(0);
 
@@ -407,13 +459,26 @@ mod test {
 
         assert_eq!( c_type_1, "D" );
 
-        assert_eq!( c.mutable, true );
+        assert!( c.mutable );
+        assert_eq!( c.meta, Meta { start: 28, end: 42 } );
 
         Ok(())
     }
 
     #[test]
-    fn should_parse_emtpy_param_list() -> Result<(), ParseError> { 
+    fn should_parse_param_starting_with_mut() -> Result<(), ParseError> {
+        let i = "( mutex : Lock ) ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_param_list()?;
+        assert_eq!( u.len(), 1 );
+        assert_eq!( u[0].name, "mutex" );
+        assert!( !u[0].mutable );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_emtpy_param_list() -> Result<(), ParseError> {
         let i = "() ".char_indices().collect::<Vec<(usize, char)>>();
         let mut input = Input::new(&i);
         let u = input.parse_param_list()?;
@@ -568,7 +633,7 @@ enum some<T : Constraint> {
     One
 } "#.char_indices().collect::<Vec<(usize, char)>>();
         let mut input = Input::new(&i);
-        let mut u = input.parse_enum_def()?;
+        let u = input.parse_enum_def()?;
 
         assert_eq!( u.name, "some" );
         assert_eq!( u.cases.len(), 1 );
@@ -583,7 +648,7 @@ enum some<T : Constraint> {
 enum some {  
 } "#.char_indices().collect::<Vec<(usize, char)>>();
         let mut input = Input::new(&i);
-        let mut u = input.parse_enum_def()?;
+        let u = input.parse_enum_def()?;
 
         assert_eq!( u.name, "some" );
         assert_eq!( u.cases.len(), 0 );
@@ -600,7 +665,7 @@ enum some {
     Blah { a : T, b : T },
 } "#.char_indices().collect::<Vec<(usize, char)>>();
         let mut input = Input::new(&i);
-        let mut u = input.parse_enum_def()?;
+        let u = input.parse_enum_def()?;
 
         assert_eq!( u.name, "some" );
         assert_eq!( u.cases.len(), 2 );
@@ -617,12 +682,22 @@ enum some {
     Blah (T1, T2),
 } "#.char_indices().collect::<Vec<(usize, char)>>();
         let mut input = Input::new(&i);
-        let mut u = input.parse_enum_def()?;
+        let u = input.parse_enum_def()?;
 
         assert_eq!( u.name, "some" );
         assert_eq!( u.cases.len(), 2 );
         assert_eq!( u.type_params.len(), 0 );
 
+        match &u.cases[0] {
+            EnumCase::TypeCase { types, .. } => assert_eq!( types.len(), 3 ),
+            x => panic!( "expected type case but found {:?}", x ),
+        }
+
+        match &u.cases[1] {
+            EnumCase::TypeCase { types, .. } => assert_eq!( types.len(), 2 ),
+            x => panic!( "expected type case but found {:?}", x ),
+        }
+
         Ok(())
     }
 
@@ -640,10 +715,89 @@ trait Blarg<T> {
 }
  "#.char_indices().collect::<Vec<(usize, char)>>();
         let mut input = Input::new(&i);
-        let mut u = input.parse_trait_def()?;
+        let u = input.parse_trait_def()?;
 
         assert_eq!( u.name, "Blarg" );
 
         Ok(())
     }
+
+    #[test]
+    fn should_parse_fun_def() -> Result<(), ParseError> {
+        let i = "fun function(a : A) -> A { a } ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_fun_def()?;
+
+        assert_eq!( u.sig.name, "function" );
+        assert_eq!( u.sig.params.len(), 1 );
+
+        match u.body {
+            Expr::Block(exprs) => assert_eq!( exprs.len(), 1 ),
+            x => panic!( "Expected Block but found {:?}", x ),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_trait_impl_def() -> Result<(), ParseError> {
+        let i = r#"
+impl<T : Show> Blarg<T> for List<T> {
+    type A = T;
+    own B = Buffer<T>;
+    fun blah(self : Self, blah : i32) -> Self { self }
+}
+ "#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let mut u = input.parse_impl_def()?;
+
+        assert_eq!( u.type_params.len(), 1 );
+        assert_eq!( u.type_params[0].name, "T" );
+        assert_eq!( u.type_params[0].constraints, vec!["Show".to_string()] );
+
+        match u.trait_type {
+            Some(Type::Indexed(name, _)) => assert_eq!( name, "Blarg" ),
+            x => panic!( "Expected Indexed trait type but found {:?}", x ),
+        }
+
+        match u.impl_type {
+            Type::Indexed(name, _) => assert_eq!( name, "List" ),
+            x => panic!( "Expected Indexed but found {:?}", x ),
+        }
+
+        assert_eq!( u.items.len(), 3 );
+
+        match u.items.remove(0) {
+            ImplItem::Type { name, item_type } => {
+                assert_eq!( name, "A" );
+                assert_eq!( item_type, Type::Simple("T".to_string()) );
+            },
+            x => panic!( "Expected Type item but found {:?}", x ),
+        }
+
+        match u.items.remove(0) {
+            ImplItem::Own { name, .. } => assert_eq!( name, "B" ),
+            x => panic!( "Expected Own item but found {:?}", x ),
+        }
+
+        match u.items.remove(0) {
+            ImplItem::Fun(f) => assert_eq!( f.sig.name, "blah" ),
+            x => panic!( "Expected Fun item but found {:?}", x ),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_inherent_impl_def() -> Result<(), ParseError> {
+        let i = "impl Point { fun x(self : Self) -> Int { self.x } } ".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = input.parse_impl_def()?;
+
+        assert!( u.trait_type.is_none() );
+        assert_eq!( u.impl_type, Type::Simple("Point".to_string()) );
+        assert_eq!( u.items.len(), 1 );
+
+        Ok(())
+    }
 }
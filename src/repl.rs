@@ -0,0 +1,521 @@
+
+use std::io::{self, BufRead, Write};
+
+use crate::parsing::ast::*;
+use crate::parsing::parser::{parse, parse_statements};
+use crate::parsing::printer::print_type;
+use crate::parsing::parse_error::describe_parse_error;
+use crate::checking::checker::check;
+use crate::checking::check_error::CheckError;
+use crate::checking::mutability::infer_type;
+use crate::checking::trait_solver::{TraitEnv, trait_name};
+use crate::evaluating::interpreter::Interpreter;
+use crate::evaluating::sandbox::Sandbox;
+use crate::evaluating::value::{Value, show};
+use crate::cli::describe_check_error;
+use crate::stdlib;
+
+/* The repl keeps the source of every definition it has accepted and the
+   variables bound by every statement.  Each input is either definitions, which
+   are checked together with the earlier ones, or statements, which are checked
+   as the body of a function taking the variables so far and then run in the
+   interpreter against the latest definitions.  A definition replaces an
+   earlier one with the same name.
+
+   The type printed for a value is the type of the value itself.  `:type` does
+   not run its expression, so it prints what the checker can tell of its type,
+   with `_` for what only running it would.
+*/
+
+const ITEM_KEYWORDS : [&str; 9] = ["fun", "struct", "enum", "trait", "impl", "use", "mod", "const", "extern"];
+
+/// The name errors in what is typed at the prompt are reported against.
+const INPUT : &str = "<input>";
+
+pub struct Repl {
+    definitions : Vec<String>,
+    locals : Vec<(String, Value)>,
+    /// The variables declared `mut`.
+    mutable : Vec<String>,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl { definitions: vec![], locals: vec![], mutable: vec![] }
+    }
+
+    /// Handles one complete input, writing its results and any errors to `out`.
+    pub fn submit(&mut self, input : &str, out : &mut dyn Write) -> io::Result<()> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(command) = input.strip_prefix(':') {
+            let (name, argument) = match command.split_once(char::is_whitespace) {
+                Some((name, argument)) => (name, argument.trim()),
+                None => (command, ""),
+            };
+            return match name {
+                "type" => self.show_type(argument, out),
+                "ast" => show_ast(argument, out),
+                "load" => match std::fs::read_to_string(argument) {
+                    Ok(source) => self.define(argument, &source, out),
+                    Err(e) => writeln!(out, "{}: {}", argument, e),
+                },
+                "help" => writeln!(out, "{}", HELP),
+                _ => writeln!(out, "unknown command :{}, try :help", name),
+            };
+        }
+
+        if is_definition(input) {
+            self.define(INPUT, input, out)
+        }
+        else {
+            self.evaluate(input, out)
+        }
+    }
+
+    /// The module made of every accepted definition and the new ones in
+    /// `extra`, named for its errors, with later definitions replacing earlier
+    /// ones of the same name.
+    fn module(&self, extra : Option<(&str, &str)>) -> Result<Module, String> {
+        let mut module = parse("").map_err(|e| describe_parse_error(INPUT, "", &e))?;
+        let earlier = self.definitions.iter().map(|source| (INPUT, source.as_str()));
+        for (name, source) in earlier.chain(extra) {
+            let definitions = parse(source).map_err(|e| describe_parse_error(name, source, &e))?;
+            merge(&mut module, definitions);
+        }
+        stdlib::link(&mut module).map_err(|errors| errors.iter().map(|e| describe_check_error(INPUT, "", e)).collect::<Vec<_>>().join("\n"))?;
+        Ok(module)
+    }
+
+    fn define(&mut self, name : &str, source : &str, out : &mut dyn Write) -> io::Result<()> {
+        let module = match self.module(Some((name, source))) {
+            Ok(module) => module,
+            Err(e) => return writeln!(out, "{}", e),
+        };
+        if let Err(errors) = check(&module) {
+            for e in errors {
+                writeln!(out, "{}", describe_check_error(name, source, &e))?;
+            }
+            return Ok(());
+        }
+
+        let defined = parse(source).map(|m| names(&m)).unwrap_or_default();
+        self.definitions.push(source.to_string());
+        for name in defined {
+            writeln!(out, "defined {}", name)?;
+        }
+        Ok(())
+    }
+
+    /// Checks `statements` as the body of a function whose parameters are the
+    /// variables so far, and gives them back when the checker accepts them.
+    fn check_statements(&self, module : &mut Module, statements : Vec<Expr>) -> Result<Vec<Expr>, Vec<CheckError>> {
+        let params = self.locals.iter()
+                                .map(|(name, value)| Param { name: name.clone()
+                                                           , param_type: value_type(value)
+                                                           , mutable: self.mutable.contains(name)
+                                                           , meta: Meta { start: 0, end: 0 }
+                                                           })
+                                .collect();
+        let sig = FunSig { name: INPUT.to_string(), type_params: vec![], params, return_type: Type::Infer };
+        module.fun_defs.push(FunDef { sig, body: Expr::Block(statements), constant: false, meta: Meta { start: 0, end: 0 } });
+        let checked = check(module);
+        match module.fun_defs.pop().map(|f| f.body) {
+            Some(Expr::Block(statements)) => checked.map(|_| statements),
+            _ => unreachable!("the statements were pushed last"),
+        }
+    }
+
+    fn evaluate(&mut self, input : &str, out : &mut dyn Write) -> io::Result<()> {
+        let statements = match parse_statements(input) {
+            Ok(statements) => statements,
+            Err(e) => return writeln!(out, "{}", describe_parse_error(INPUT, input, &e)),
+        };
+        let mut module = match self.module(None) {
+            Ok(module) => module,
+            Err(e) => return writeln!(out, "{}", e),
+        };
+        let statements = match self.check_statements(&mut module, statements) {
+            Ok(statements) => statements,
+            Err(errors) => {
+                for e in errors {
+                    writeln!(out, "{}", describe_check_error(INPUT, input, &e))?;
+                }
+                return Ok(());
+            },
+        };
+
+        let mut result = Ok(Value::Unit);
+        {
+            let mut interpreter = Interpreter::new(&module, out).with_sandbox(&Sandbox::trusted());
+            for statement in &statements {
+                result = interpreter.eval_statement(statement, &mut self.locals);
+                if result.is_err() {
+                    break;
+                }
+                if let Expr::Let { name, mutable, .. } = statement {
+                    self.mutable.retain(|m| m != name);
+                    if *mutable {
+                        self.mutable.push(name.clone());
+                    }
+                }
+            }
+        }
+
+        match result {
+            Ok(Value::Unit) => Ok(()),
+            Ok(value) => writeln!(out, "{} : {}", show(&value), type_of(&value)),
+            Err(e) => writeln!(out, "runtime error: {}", e),
+        }
+    }
+
+    /// Writes the type of an expression as far as the checker can tell,
+    /// without running it.
+    fn show_type(&mut self, input : &str, out : &mut dyn Write) -> io::Result<()> {
+        let mut statements = match parse_statements(input) {
+            Ok(statements) => statements,
+            Err(e) => return writeln!(out, "{}", describe_parse_error(INPUT, input, &e)),
+        };
+        if statements.len() > 1 && matches!( statements.last(), Some(Expr::Unit) ) {
+            statements.pop();
+        }
+        if statements.len() != 1 || matches!( statements[0], Expr::Let { .. } ) {
+            return writeln!(out, ":type takes one expression");
+        }
+        let mut module = match self.module(None) {
+            Ok(module) => module,
+            Err(e) => return writeln!(out, "{}", e),
+        };
+        let statements = match self.check_statements(&mut module, statements) {
+            Ok(statements) => statements,
+            Err(errors) => {
+                for e in errors {
+                    writeln!(out, "{}", describe_check_error(INPUT, input, &e))?;
+                }
+                return Ok(());
+            },
+        };
+
+        let bindings = self.locals.iter().map(|(name, value)| (name.clone(), value_type(value))).collect::<Vec<_>>();
+        let env = TraitEnv::new(&module);
+        writeln!(out, "{}", print_type(&infer_type(&env, &module, &bindings, &statements[0])))
+    }
+}
+
+const HELP : &str = "enter definitions or statements, or one of
+  :type <expr>   show the type of an expression
+  :ast <input>   show the syntax tree of definitions or statements
+  :load <file>   add the definitions in a file
+  :help          show this message";
+
+/// Reads inputs until the end of `input`, asking for more lines while an
+/// input is incomplete.
+pub fn run(input : &mut dyn BufRead, out : &mut dyn Write, prompt : bool) -> io::Result<()> {
+    let mut repl = Repl::new();
+    let mut buffer = String::new();
+
+    loop {
+        if prompt {
+            write!(out, "{}", if buffer.is_empty() { "> " } else { "| " })?;
+            out.flush()?;
+        }
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            if !buffer.is_empty() {
+                repl.submit(&buffer, out)?;
+            }
+            return Ok(());
+        }
+
+        buffer.push_str(&line);
+        if !is_incomplete(&buffer) {
+            repl.submit(&buffer, out)?;
+            buffer.clear();
+        }
+    }
+}
+
+/// Whether the input has an unclosed bracket, string or block comment.
+pub fn is_incomplete(input : &str) -> bool {
+    let mut depth = 0i32;
+    let mut comment = 0;
+    let mut string = false;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if comment > 0 {
+            match c {
+                '/' if chars.peek() == Some(&'*') => { chars.next(); comment += 1; },
+                '*' if chars.peek() == Some(&'/') => { chars.next(); comment -= 1; },
+                _ => { },
+            }
+        }
+        else if string {
+            match c {
+                '\\' => { chars.next(); },
+                '"' => string = false,
+                _ => { },
+            }
+        }
+        else {
+            match c {
+                '/' if chars.peek() == Some(&'*') => { chars.next(); comment += 1; },
+                '"' => string = true,
+                '{' | '(' | '[' => depth += 1,
+                '}' | ')' | ']' => depth -= 1,
+                _ => { },
+            }
+        }
+    }
+
+    depth > 0 || comment > 0 || string
+}
+
+fn is_definition(input : &str) -> bool {
+    let word = input.split(|c : char| !(c.is_alphanumeric() || c == '_')).next().unwrap_or("");
+    ITEM_KEYWORDS.contains(&word)
+}
+
+fn show_ast(input : &str, out : &mut dyn Write) -> io::Result<()> {
+    if is_definition(input) {
+        match parse(input) {
+            Ok(module) => writeln!(out, "{:#?}", module),
+            Err(e) => writeln!(out, "{}", describe_parse_error(INPUT, input, &e)),
+        }
+    }
+    else {
+        match parse_statements(input) {
+            Ok(mut statements) => {
+                if statements.len() > 1 && matches!( statements.last(), Some(Expr::Unit) ) {
+                    statements.pop();
+                }
+                for statement in statements {
+                    writeln!(out, "{:#?}", statement)?;
+                }
+                Ok(())
+            },
+            Err(e) => writeln!(out, "{}", describe_parse_error(INPUT, input, &e)),
+        }
+    }
+}
+
+fn merge(module : &mut Module, definitions : Module) {
+    fn replace<T>(items : &mut Vec<T>, new : Vec<T>, key : impl Fn(&T) -> String) {
+        for item in new {
+            let k = key(&item);
+            items.retain(|i| key(i) != k);
+            items.push(item);
+        }
+    }
+
+    replace(&mut module.fun_defs, definitions.fun_defs, |f| f.sig.name.clone());
+    replace(&mut module.struct_defs, definitions.struct_defs, |s| s.name.clone());
+    replace(&mut module.enum_defs, definitions.enum_defs, |e| e.name.clone());
+    replace(&mut module.trait_defs, definitions.trait_defs, |t| t.name.clone());
+    replace(&mut module.impl_defs, definitions.impl_defs, impl_key);
+    replace(&mut module.const_defs, definitions.const_defs, |c| c.name.clone());
+    replace(&mut module.extern_defs, definitions.extern_defs, |e| e.sig.name.clone());
+    module.uses.extend(definitions.uses);
+    replace(&mut module.mods, definitions.mods, |m| m.clone());
+}
+
+fn impl_key(impl_def : &ImplDef) -> String {
+    match trait_name(impl_def) {
+        Some(t) => format!("impl {} for {:?}", t, impl_def.impl_type),
+        None => format!("impl {:?}", impl_def.impl_type),
+    }
+}
+
+fn names(module : &Module) -> Vec<String> {
+    let mut names = vec![];
+    names.extend(module.fun_defs.iter().map(|f| format!("fun {}", f.sig.name)));
+    names.extend(module.struct_defs.iter().map(|s| format!("struct {}", s.name)));
+    names.extend(module.enum_defs.iter().map(|e| format!("enum {}", e.name)));
+    names.extend(module.trait_defs.iter().map(|t| format!("trait {}", t.name)));
+    names.extend(module.const_defs.iter().map(|c| format!("const {}", c.name)));
+    names.extend(module.extern_defs.iter().map(|e| format!("extern fun {}", e.sig.name)));
+    names.extend(module.impl_defs.iter().map(|i| match (&i.trait_type, &i.impl_type) {
+        (Some(Type::Simple(t)), Type::Simple(n)) => format!("impl {} for {}", t, n),
+        (None, Type::Simple(n)) => format!("impl {}", n),
+        _ => "impl".to_string(),
+    }));
+    names
+}
+
+/// The type of a value as the checker sees it, with the element types of
+/// collections taken from their first element.  The checker does not know the
+/// types of functions, iterators and ranges.
+fn value_type(value : &Value) -> Type {
+    match value {
+        Value::Unit => Type::Unit,
+        Value::Tuple(vs) => Type::Tuple(vs.iter().map(value_type).collect()),
+        Value::List(vs) => Type::Indexed("List".to_string(), vec![vs.borrow().first().map_or(Type::Infer, value_type)]),
+        Value::Dict(pairs) => match pairs.borrow().first() {
+            Some((k, v)) => Type::Indexed("Dict".to_string(), vec![value_type(k), value_type(v)]),
+            None => Type::Indexed("Dict".to_string(), vec![Type::Infer, Type::Infer]),
+        },
+        Value::Fun(_) | Value::Iterator(_) | Value::Range(_) => Type::Infer,
+        v => Type::Simple(v.type_name()),
+    }
+}
+
+pub fn type_of(value : &Value) -> String {
+    match value {
+        Value::Tuple(vs) => format!("({})", vs.iter().map(type_of).collect::<Vec<_>>().join(", ")),
+        Value::List(vs) => match vs.borrow().first() {
+            Some(v) => format!("List<{}>", type_of(v)),
+            None => "List<_>".to_string(),
+        },
+        Value::Dict(pairs) => match pairs.borrow().first() {
+            Some((k, v)) => format!("Dict<{}, {}>", type_of(k), type_of(v)),
+            None => "Dict<_, _>".to_string(),
+        },
+        v => v.type_name(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn transcript(input : &str) -> String {
+        let mut out = vec![];
+        run(&mut input.as_bytes(), &mut out, false).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn should_keep_definitions_and_variables_across_inputs() {
+        let output = transcript(r#"
+let x = 20;
+fun double(n : Int) -> Int { n * 2 }
+double(x) + 2
+let mut xs = [x];
+xs.push(3);
+xs
+"hi"
+(1, 2.5, true)
+"#);
+        assert_eq!( output, "defined fun double\n42 : Int\n[20, 3] : List<Int>\n\"hi\" : String\n(1, 2.5, true) : (Int, Float, Bool)\n" );
+    }
+
+    #[test]
+    fn should_replace_redefined_items() {
+        let output = transcript(r#"
+fun f() -> Int { 1 }
+fun g() -> Int { f() + 1 }
+fun f() -> Int { 10 }
+g()
+"#);
+        assert_eq!( output, "defined fun f\ndefined fun g\ndefined fun f\n11 : Int\n" );
+    }
+
+    #[test]
+    fn should_define_constants_and_extern_functions() {
+        let output = transcript(r#"
+const fun sum(n : Int) -> Int { if n == 0 { 0 } else { sum(n - 1) + n } }
+const X : Int = 3;
+const Y : Int = sum(100);
+extern "libc.so.6" fun abs(n : Int) -> Int;
+X + Y
+"#);
+        assert_eq!( output, "defined fun sum\ndefined const X\ndefined const Y\ndefined extern fun abs\n5053 : Int\n" );
+    }
+
+    #[test]
+    fn should_continue_incomplete_input() {
+        let output = transcript(r#"
+struct Point {
+    x : Int,
+    y : Int
+}
+impl Point {
+    fun sum(self : Self) -> Int { self.x + self.y }
+}
+let p = Point { x : 1,
+                y : 2 };
+/* a comment
+   over lines */ p.sum(
+)
+"#);
+        assert_eq!( output, "defined struct Point\ndefined impl Point\n3 : Int\n" );
+    }
+
+    #[test]
+    fn should_detect_incomplete_input() {
+        assert!( is_incomplete("fun f() {") );
+        assert!( is_incomplete("f(1,") );
+        assert!( is_incomplete("/* /* */") );
+        assert!( is_incomplete("\"abc") );
+        assert!( !is_incomplete("\"{\\\"\" + \"(\"") );
+        assert!( !is_incomplete("fun f() { }") );
+        assert!( !is_incomplete("}") );
+    }
+
+    #[test]
+    fn should_report_errors_and_carry_on() {
+        let output = transcript(r#"
+1 +
+let y = 1 / 0;
+y
+let z = 5
+z
+"#);
+        assert!( output.starts_with("<input>:1:4: parse error: "), "{}", output );
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!( lines[1..], ["runtime error: division by zero", "runtime error: unknown variable y", "5 : Int"] );
+    }
+
+    #[test]
+    fn should_check_statements_before_running_them() {
+        let output = transcript(r#"
+let a = 1;
+let mut b = 1;
+println("ran"); a = 2;
+b = 2;
+b
+"#);
+        assert_eq!( output, "<input>:1:17: check error: cannot assign to a, which is not declared mut\n2 : Int\n" );
+    }
+
+    #[test]
+    fn should_find_types_without_running_anything() {
+        let output = transcript(r#"
+let mut xs = [1];
+:type xs.push(2)
+:type xs
+:type (xs, "s")
+:type let y = 1
+xs
+"#);
+        assert_eq!( output, "_\nList<Int>\n(List<Int>, String)\n:type takes one expression\n[1] : List<Int>\n" );
+    }
+
+    #[test]
+    fn should_run_commands() {
+        let dir = std::env::temp_dir().join(format!("dust_repl_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("defs.ds");
+        std::fs::write(&file, "enum Shape { Circle(Float), Square(Float) }\nfun unit() -> Shape { Shape::Square(1.0) }\n").unwrap();
+
+        let output = transcript(&format!(r#"
+:load {}
+:type unit()
+:type [[1]]
+:type println("hidden")
+:ast 1 + x
+:blarg
+"#, file.display()));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let expected_ast = format!("{:#?}\n", Expr::Binary { op: BinOp::Add
+                                                           , left: Box::new(Expr::Number("1".to_string()))
+                                                           , right: Box::new(Expr::Variable("x".to_string()))
+                                                           });
+        assert_eq!( output, format!("defined fun unit\ndefined enum Shape\nShape\nList<List<Int>>\n_\n{}unknown command :blarg, try :help\n", expected_ast) );
+    }
+}
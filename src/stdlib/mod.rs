@@ -0,0 +1,259 @@
+
+use crate::parsing::ast::*;
+use crate::parsing::parser::parse;
+use crate::checking::check_error::CheckError;
+
+/* The standard library is a tree of dust modules bundled into the binary.
+   A program imports them with `use std::{list, option};`, or items of one
+   with `use std::option::{Option};`, and `use std::{*};` imports all of
+   them.  Linking adds every item of an imported module, and of the modules
+   it needs, to the program, so there are no namespaces:  a program cannot
+   define anything an imported module defines.
+
+   The modules lean on native methods of the builtin types for the work which
+   has to be fast, like sorting a list or splitting a string, and are written
+   in dust for everything else.
+*/
+
+pub struct StdModule {
+    pub name : &'static str,
+    /// The modules whose items this one uses.
+    pub needs : &'static [&'static str],
+    pub source : &'static str,
+}
+
+pub const MODULES : &[StdModule] = &[
+    StdModule { name: "option", needs: &[], source: include_str!("option.dust") },
+    StdModule { name: "result", needs: &["option"], source: include_str!("result.dust") },
+    StdModule { name: "cmp", needs: &[], source: include_str!("cmp.dust") },
+    StdModule { name: "fmt", needs: &[], source: include_str!("fmt.dust") },
+    StdModule { name: "math", needs: &[], source: include_str!("math.dust") },
+    StdModule { name: "list", needs: &["option"], source: include_str!("list.dust") },
+    StdModule { name: "dict", needs: &["option"], source: include_str!("dict.dust") },
+    StdModule { name: "string", needs: &[], source: include_str!("string.dust") },
+    StdModule { name: "iter", needs: &["option"], source: include_str!("iter.dust") },
+];
+
+fn find(name : &str) -> Option<&'static StdModule> {
+    MODULES.iter().find(|m| m.name == name)
+}
+
+/// Adds the standard library modules which `module` uses to it.
+pub fn link( module : &mut Module ) -> Result<(), Vec<CheckError>> {
+    let mut errors = vec![];
+    let mut wanted = vec![];
+
+    for u in module.uses.iter().filter(|u| u.namespace[0] == "std") {
+        match &u.namespace[1..] {
+            [] => for import in &u.imports {
+                match import {
+                    Import::Everything => wanted.extend(MODULES),
+                    Import::Item(name) => match find(name) {
+                        Some(m) => wanted.push(m),
+                        None => errors.push(CheckError::UnknownImport { path: format!("std::{}", name) }),
+                    },
+                }
+            },
+            [name] => match find(name) {
+                Some(m) => {
+                    let items = parse(m.source).expect("the standard library parses");
+                    for import in &u.imports {
+                        if let Import::Item(item) = import {
+                            if !defined_names(&items).any(|n| n == item) {
+                                errors.push(CheckError::UnknownImport { path: format!("std::{}::{}", name, item) });
+                            }
+                        }
+                    }
+                    wanted.push(m);
+                },
+                None => errors.push(CheckError::UnknownImport { path: format!("std::{}", name) }),
+            },
+            path => errors.push(CheckError::UnknownImport { path: format!("std::{}", path.join("::")) }),
+        }
+    }
+
+    let mut linked : Vec<&StdModule> = vec![];
+    while let Some(m) = wanted.pop() {
+        if linked.iter().any(|l| l.name == m.name) {
+            continue;
+        }
+        linked.push(m);
+        wanted.extend(m.needs.iter().filter_map(|n| find(n)));
+    }
+
+    for m in linked {
+        let items = parse(m.source).expect("the standard library parses");
+        for name in defined_names(&items) {
+            if defined_names(module).any(|n| n == name) {
+                errors.push(CheckError::DefinedByStd { name: name.to_string(), module: m.name.to_string() });
+            }
+        }
+        module.fun_defs.extend(items.fun_defs);
+        module.struct_defs.extend(items.struct_defs);
+        module.enum_defs.extend(items.enum_defs);
+        module.trait_defs.extend(items.trait_defs);
+        module.impl_defs.extend(items.impl_defs);
+        module.const_defs.extend(items.const_defs);
+    }
+
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+/// The names of the functions and types `module` defines.
+fn defined_names( module : &Module ) -> impl Iterator<Item = &str> {
+    module.fun_defs.iter().map(|f| f.sig.name.as_str())
+        .chain(module.const_defs.iter().map(|c| c.name.as_str()))
+        .chain(module.extern_defs.iter().map(|e| e.sig.name.as_str()))
+        .chain(module.struct_defs.iter().map(|s| s.name.as_str()))
+        .chain(module.enum_defs.iter().map(|e| e.name.as_str()))
+        .chain(module.trait_defs.iter().map(|t| t.name.as_str()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::checking::checker::{check, check_with_warnings};
+    use crate::compiling::{compiler, vm};
+    use crate::evaluating::interpreter;
+
+    fn linked(src : &str) -> Module {
+        let mut module = parse(src).unwrap();
+        link(&mut module).unwrap();
+        module
+    }
+
+    /// Runs a program with the interpreter and the virtual machine, which must agree.
+    fn run(src : &str) -> String {
+        let module = linked(src);
+        check(&module).unwrap();
+
+        let mut out = vec![];
+        interpreter::run(&module, &mut out).unwrap();
+        let mut vm_out = vec![];
+        vm::run(&compiler::compile(&module).unwrap(), &mut vm_out).unwrap();
+        assert_eq!( String::from_utf8_lossy(&out), String::from_utf8_lossy(&vm_out) );
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn should_check_every_module_without_warnings() {
+        for m in MODULES {
+            let module = linked(&format!("use std::{{{}}}; fun main() {{ }}", m.name));
+            let (errors, warnings) = check_with_warnings(&module);
+            assert!( errors.is_empty(), "{}: {:?}", m.name, errors );
+            assert!( warnings.is_empty(), "{}: {:?}", m.name, warnings );
+        }
+    }
+
+    #[test]
+    fn should_link_only_what_is_used() {
+        let module = linked("use std::result::{Result};");
+        let names = defined_names(&module).collect::<Vec<_>>();
+        assert!( names.contains(&"Result") && names.contains(&"Option") );
+        assert!( !names.contains(&"map") );
+
+        assert_eq!( defined_names(&linked("fun main() { }")).count(), 1 );
+        assert_eq!( defined_names(&linked("use std::{*};")).count(), defined_names(&linked("use std::{option, result, cmp, fmt, math, list, dict, string, iter};")).count() );
+    }
+
+    #[test]
+    fn should_report_unknown_imports_and_conflicts() {
+        let mut module = parse("use std::{nope}; use std::option::{Option, Maybe}; use std::{math}; fun max() { }").unwrap();
+        let errors = link(&mut module).unwrap_err();
+
+        assert_eq!( errors.len(), 3, "{:?}", errors );
+        assert!( matches!( &errors[0], CheckError::UnknownImport { path } if path == "std::nope" ) );
+        assert!( matches!( &errors[1], CheckError::UnknownImport { path } if path == "std::option::Maybe" ) );
+        assert!( matches!( &errors[2], CheckError::DefinedByStd { name, module } if name == "max" && module == "math" ) );
+    }
+
+    #[test]
+    fn should_run_programs_using_the_standard_library() {
+        let output = run(r#"
+use std::{list, option, result, string, math, fmt, cmp, dict, iter};
+
+fun main() {
+    let xs = [5, 3, 8, 1];
+    println(map(xs, |x| x * 2), filter(xs, |x| x > 2), sum(xs), maximum(xs), minimum([]));
+    println(find(xs, |x| x > 6).unwrap_or(0), any(xs, |x| x == 3), all(xs, |x| x > 1), zip(xs, ["a", "b"]));
+    println(sort_by(["pear", "fig", "apple"], |a, b| a.len() < b.len()), Option::Some(2).map(|x| x + 1).is_some());
+    let r = Result::Err("bad");
+    println(r.is_err(), r.unwrap_or(7), Result::Ok(3).ok(), "7".parse_int(), "x".parse_int());
+
+    let mut ys = [3, 1, 2];
+    ys.sort();
+    ys.reverse();
+    ys.insert(3, 0);
+    println(ys, ys.remove(1), ys.contains(2), ["b", "a"].len());
+
+    println(join(words("  the quick  fox "), "-"), repeat("ab", 3), "a,b,,c".split(","), " x ".trim().to_upper());
+    println(max(2, 9), min(2.5, 1.5), clamp(15, 0, 10), gcd(12, -18), pow(3, 4), hypot(3.0, 4.0), (2.0).sqrt().floor());
+    println(format("{} + {} = {}", [1, 2, 3]), pad_left("7", 3), pad_right("ab", 4) + "|");
+    println(max_of("b", "a"), min_of(3, 4), 1.compare(2), "b".compare("a"));
+
+    let d = ["a" : 1, "b" : 2];
+    println(get(d, "a"), get(d, "z"), get_or(d, "z", 0), entries(d), d.values());
+    println(collect(take(3, skip(2, count_from(10)))), collect(enumerate("hé")));
+    println(collect(filtered(mapped([1, 2, 3, 4], |x| x * x), |x| x % 2 == 0)));
+}
+"#);
+        assert_eq!( output, "[10, 6, 16, 2] [5, 3, 8] 17 Option::Some(8) Option::None\n\
+                             8 true false [(5, \"a\"), (3, \"b\")]\n\
+                             [\"fig\", \"pear\", \"apple\"] true\n\
+                             true 7 Option::Some(3) Option::Some(7) Option::None\n\
+                             [3, 1, 0] 2 false 2\n\
+                             the-quick-fox ababab [\"a\", \"b\", \"\", \"c\"] X\n\
+                             9 1.5 10 6 81 5.0 1.0\n\
+                             1 + 2 = 3   7 ab  |\n\
+                             b 3 Ordering::Less Ordering::Greater\n\
+                             Option::Some(1) Option::None 0 [(\"a\", 1), (\"b\", 2)] [1, 2]\n\
+                             [12, 13, 14] [(0, \"h\"), (1, \"é\")]\n\
+                             [4, 16]\n" );
+    }
+
+    #[test]
+    fn should_step_through_types_implementing_iterator() {
+        let output = run(r#"
+use std::{iter};
+
+struct Countdown { n : Int }
+
+impl Iterator for Countdown {
+    type Item = Int;
+    fun next(mut self : Self) -> Option<Int> {
+        if self.n == 0 { return Option::None }
+        self.n = self.n - 1;
+        Option::Some(self.n + 1)
+    }
+}
+
+fun main() {
+    let mut seen = [];
+    foreach x in Countdown { n : 3 } { seen.push(x); }
+    println(seen);
+
+    let tens = mapped(Countdown { n : 5 }, |x| x * 10);
+    println(collect(take(2, skip(1, tens))), collect(enumerate(filtered(Countdown { n : 4 }, |x| x % 2 == 0))));
+    let mut evens = filtered(count_from(1), |x| x % 2 == 0);
+    println(evens.next(), evens.next(), take(0, evens).next());
+}
+"#);
+        assert_eq!( output, "[3, 2, 1]\n[40, 30] [(0, 4), (1, 2)]\nOption::Some(2) Option::Some(4) Option::None\n" );
+
+        let module = linked("use std::{iter};\nstruct Point { x : Int }\nfun main() { foreach x in Point { x : 1 } { } }");
+        let errors = check(&module).unwrap_err();
+        assert!( matches!( &errors[..], [CheckError::NotIterable { .. }] ), "{:?}", errors );
+    }
+
+    #[test]
+    fn should_fail_like_the_native_methods_do() {
+        let module = linked("use std::{option};\nfun main() { Option::None.unwrap() }");
+        let mut out = vec![];
+        let error = interpreter::run(&module, &mut out).unwrap_err();
+        assert!( error.to_string().starts_with("panic: unwrap of Option::None"), "{}", error );
+
+        let module = linked("fun main() { [1, \"a\"].sort() }");
+        assert!( interpreter::run(&module, &mut out).is_err() );
+    }
+}